@@ -0,0 +1,347 @@
+// headless_sender.rs
+// Joins a room as a sender without a browser: publishes a VP8 video track to every viewer over
+// webrtc-rs and pushes synthetic inference results, so the server can be tested end to end.
+//
+//   cargo run --example headless_sender -- ws://127.0.0.1:8080 my-room [--ivf test.ivf] [--ice stun:host:3478]
+//       [--sender-token <token>]
+//
+// The room must exist; pass the sender_token returned when it was created.
+//
+// Video frames come from an IVF file, which is looped, or from stdin with `--ivf -`. To make a
+// test pattern or to stream a V4L2 camera:
+//
+//   ffmpeg -f lavfi -i testsrc=size=640x480:rate=30 -t 10 -c:v libvpx -b:v 1M test.ivf
+//   ffmpeg -f v4l2 -i /dev/video0 -c:v libvpx -deadline realtime -f ivf - | \
+//       cargo run --example headless_sender -- ws://127.0.0.1:8080 my-room --ivf -
+//
+// Without --ivf the track is negotiated but carries no frames.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use tokio::sync::{mpsc, Mutex};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_VP8};
+use webrtc::api::{APIBuilder, API};
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::interceptor::registry::Registry;
+use webrtc::media::io::ivf_reader::IVFReader;
+use webrtc::media::Sample;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::TrackLocal;
+use ws2infer_protocol::{SignalingClient, SignalingMessage, SignalingMessageType};
+
+/// How often a synthetic inference result is pushed
+const INFERENCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Frame duration used when the IVF header has no usable timebase
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(33);
+
+struct Options {
+    server_url: String,
+    room_id: String,
+    ivf: Option<String>,
+    ice: String,
+    sender_token: Option<String>,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut positional = Vec::new();
+    let mut ivf = None;
+    let mut ice = None;
+    let mut sender_token = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ivf" => ivf = Some(args.next().ok_or_else(|| anyhow!("--ivf needs a path"))?),
+            "--ice" => ice = Some(args.next().ok_or_else(|| anyhow!("--ice needs a URL"))?),
+            "--sender-token" => sender_token = Some(args.next().ok_or_else(|| anyhow!("--sender-token needs a token"))?),
+            _ => positional.push(arg),
+        }
+    }
+
+    let server_url = positional.first().cloned().unwrap_or_else(|| "ws://127.0.0.1:8080".to_string());
+    let room_id = positional.get(1).cloned().unwrap_or_else(|| "headless".to_string());
+    // The server's built-in STUN listener shares its host
+    let ice = ice.unwrap_or_else(|| format!("stun:{}:3478", host_of(&server_url)));
+    Ok(Options { server_url, room_id, ivf, ice, sender_token })
+}
+
+fn host_of(url: &str) -> &str {
+    let rest = url.split("://").nth(1).unwrap_or(url);
+    rest.split(['/', ':']).next().unwrap_or("127.0.0.1")
+}
+
+fn new_api() -> Result<API> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    Ok(APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build())
+}
+
+/// Writes IVF frames into the shared track at the file's frame rate, looping files until exit
+fn spawn_video(track: Arc<TrackLocalStaticSample>, path: String) {
+    tokio::task::spawn_blocking(move || {
+        let handle = tokio::runtime::Handle::current();
+        loop {
+            let reader: Box<dyn Read + Send> = if path == "-" {
+                Box::new(io::stdin())
+            } else {
+                match File::open(&path) {
+                    Ok(file) => Box::new(BufReader::new(file)),
+                    Err(e) => {
+                        eprintln!("Failed to open {}: {}", path, e);
+                        return;
+                    }
+                }
+            };
+
+            let (mut ivf, header) = match IVFReader::new(reader) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("{} is not an IVF stream: {}", path, e);
+                    return;
+                }
+            };
+            let frame_duration = if header.timebase_denominator > 0 && header.timebase_numerator > 0 {
+                Duration::from_millis(1000 * header.timebase_numerator as u64 / header.timebase_denominator as u64)
+            } else {
+                DEFAULT_FRAME_DURATION
+            };
+
+            while let Ok((frame, _)) = ivf.parse_next_frame() {
+                let sample = Sample {
+                    data: frame.freeze(),
+                    duration: frame_duration,
+                    ..Default::default()
+                };
+                if let Err(e) = handle.block_on(track.write_sample(&sample)) {
+                    eprintln!("Failed to write video sample: {}", e);
+                }
+                std::thread::sleep(frame_duration);
+            }
+
+            if path == "-" {
+                println!("Video input ended");
+                return;
+            }
+        }
+    });
+}
+
+/// Creates a peer connection to one viewer carrying the shared track and sends it an offer.
+/// Local ICE candidates are queued on `outgoing` because the signaling client is owned by the
+/// main loop.
+async fn offer_to(
+    api: &API,
+    config: RTCConfiguration,
+    track: Arc<TrackLocalStaticSample>,
+    viewer_id: String,
+    outgoing: mpsc::UnboundedSender<(String, SignalingMessageType, Value)>,
+) -> Result<Arc<RTCPeerConnection>> {
+    let peer_connection = Arc::new(api.new_peer_connection(config).await?);
+
+    let rtp_sender = peer_connection
+        .add_track(track as Arc<dyn TrackLocal + Send + Sync>)
+        .await?;
+    // RTCP has to be read for the interceptors (NACK, reports) to work
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 1500];
+        while rtp_sender.read(&mut buffer).await.is_ok() {}
+    });
+
+    let candidate_target = viewer_id.clone();
+    let candidate_outgoing = outgoing.clone();
+    peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+        let target = candidate_target.clone();
+        let outgoing = candidate_outgoing.clone();
+        Box::pin(async move {
+            let init = match candidate.map(|c| c.to_json()) {
+                Some(Ok(init)) => init,
+                _ => return,
+            };
+            if let Ok(value) = serde_json::to_value(init) {
+                let _ = outgoing.send((target, SignalingMessageType::IceCandidate, value));
+            }
+        })
+    }));
+
+    let state_viewer = viewer_id.clone();
+    peer_connection.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+        println!("Viewer {}: {}", state_viewer, state);
+        Box::pin(async {})
+    }));
+
+    let offer = peer_connection.create_offer(None).await?;
+    peer_connection.set_local_description(offer.clone()).await?;
+    let _ = outgoing.send((viewer_id, SignalingMessageType::Offer, serde_json::to_value(offer)?));
+
+    Ok(peer_connection)
+}
+
+/// Detections that drift across the frame so viewers have something to draw
+fn synthetic_inference(tick: u64) -> Value {
+    let x = (tick * 20 % 560) as f64;
+    serde_json::json!({
+        "timestamp": chrono::Utc::now().timestamp_millis(),
+        "model": "synthetic",
+        "frame_size": [640, 480],
+        "predictions": [
+            { "class": "person", "score": 0.9, "bbox": [x, 120.0, 80.0, 200.0] },
+            { "class": "cup", "score": 0.6, "bbox": [400.0, 300.0, 40.0, 50.0] }
+        ]
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let options = parse_args()?;
+    let api = new_api()?;
+    let rtc_config = RTCConfiguration {
+        ice_servers: vec![RTCIceServer {
+            urls: vec![options.ice.clone()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_VP8.to_owned(),
+            ..Default::default()
+        },
+        "video".to_owned(),
+        "ws2infer-headless".to_owned(),
+    ));
+    match options.ivf.clone() {
+        Some(path) => spawn_video(track.clone(), path),
+        None => println!("No --ivf given; the video track will stay empty"),
+    }
+
+    let mut client = SignalingClient::connect(&options.server_url, &options.room_id).await?;
+    let own_id = client.connection_id().to_string();
+    client.join(true, Some(serde_json::json!({
+        "tracks": [{ "kind": "video", "label": "ws2infer-headless (VP8)" }],
+        "sender_token": options.sender_token
+    }))).await?;
+    println!("Joined room {} as sender {}", options.room_id, own_id);
+
+    let peers: Arc<Mutex<HashMap<String, Arc<RTCPeerConnection>>>> = Arc::new(Mutex::new(HashMap::new()));
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<(String, SignalingMessageType, Value)>();
+    let mut inference_tick = tokio::time::interval(INFERENCE_INTERVAL);
+    let mut tick = 0u64;
+
+    loop {
+        tokio::select! {
+            message = client.next_message() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => break,
+                };
+                handle_message(&api, &rtc_config, &track, &peers, &outgoing, message).await;
+            }
+            Some((target, message_type, data)) = outgoing_rx.recv() => {
+                let result = match message_type {
+                    SignalingMessageType::Offer => client.offer(&target, data).await,
+                    _ => client.ice_candidate(&target, data).await,
+                };
+                if let Err(e) = result {
+                    eprintln!("Failed to send to {}: {}", target, e);
+                }
+            }
+            _ = inference_tick.tick() => {
+                tick += 1;
+                client.inference_result(&own_id, synthetic_inference(tick)).await?;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    for (_, peer_connection) in peers.lock().await.drain() {
+        let _ = peer_connection.close().await;
+    }
+    client.close();
+    println!("Left room {}", options.room_id);
+    Ok(())
+}
+
+async fn handle_message(
+    api: &API,
+    rtc_config: &RTCConfiguration,
+    track: &Arc<TrackLocalStaticSample>,
+    peers: &Arc<Mutex<HashMap<String, Arc<RTCPeerConnection>>>>,
+    outgoing: &mpsc::UnboundedSender<(String, SignalingMessageType, Value)>,
+    message: SignalingMessage,
+) {
+    let data = message.data.clone().unwrap_or(Value::Null);
+
+    // Same triggers as sender.html: viewers already in the room, then each one that joins later
+    let new_viewers: Vec<String> = match message.message_type {
+        SignalingMessageType::RoomInfo => data.get("peers")
+            .and_then(Value::as_array)
+            .map(|peers| peers.iter()
+                .filter(|peer| !peer.get("is_sender").and_then(Value::as_bool).unwrap_or(false))
+                .filter_map(|peer| peer.get("id").and_then(Value::as_str).map(str::to_string))
+                .collect())
+            .unwrap_or_default(),
+        SignalingMessageType::NewPeer if !data.get("is_sender").and_then(Value::as_bool).unwrap_or(false) => {
+            data.get("connection_id").and_then(Value::as_str).map(str::to_string).into_iter().collect()
+        }
+        _ => Vec::new(),
+    };
+
+    for viewer_id in new_viewers {
+        if peers.lock().await.contains_key(&viewer_id) {
+            continue;
+        }
+        match offer_to(api, rtc_config.clone(), track.clone(), viewer_id.clone(), outgoing.clone()).await {
+            Ok(peer_connection) => {
+                peers.lock().await.insert(viewer_id, peer_connection);
+            }
+            Err(e) => eprintln!("Failed to offer to {}: {}", viewer_id, e),
+        }
+    }
+
+    let from = match message.sender_id.as_deref() {
+        Some(from) => from,
+        None => {
+            if matches!(message.message_type, SignalingMessageType::Error) {
+                eprintln!("Server error: {}", data);
+            }
+            return;
+        }
+    };
+    let peer_connection = match peers.lock().await.get(from) {
+        Some(peer_connection) => peer_connection.clone(),
+        None => return,
+    };
+
+    let result = match message.message_type {
+        SignalingMessageType::Answer => match serde_json::from_value::<RTCSessionDescription>(data) {
+            Ok(answer) => peer_connection.set_remote_description(answer).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        },
+        SignalingMessageType::IceCandidate => match serde_json::from_value::<RTCIceCandidateInit>(data) {
+            Ok(candidate) => peer_connection.add_ice_candidate(candidate).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        },
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to apply {:?} from {}: {}", message.message_type, from, e);
+    }
+}
@@ -0,0 +1,34 @@
+// stun_packet.rs
+// Feeds arbitrary datagrams to StunServer::handle_stun_packet. It must never panic, and whatever
+// it answers with must be a well-formed STUN message.
+//
+//     cargo +nightly fuzz run stun_packet
+
+#![no_main]
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+use cam2webrtc_fuzz::check_response;
+use cam2webrtc_fuzz::stun::{StunServer, StunStats};
+
+fn server() -> &'static (Runtime, StunServer) {
+    static SERVER: OnceLock<(Runtime, StunServer)> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let runtime = Runtime::new().expect("tokio runtime");
+        let server = runtime.block_on(async {
+            StunServer::new("127.0.0.1:0".parse().unwrap(), StunStats::default()).expect("bind STUN socket")
+        });
+        (runtime, server)
+    })
+}
+
+fuzz_target!(|packet: &[u8]| {
+    let (_, server) = server();
+    let src_addr: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+    if let Some(response) = server.handle_stun_packet(packet, src_addr) {
+        check_response(&response);
+    }
+});
@@ -0,0 +1,52 @@
+// turn_packet.rs
+// Feeds arbitrary datagrams to TurnServer::handle_turn_packet, which walks attributes for
+// long-term credentials and send indications. The first byte picks a server with or without
+// authentication and the client port, so both the credential check and allocated clients'
+// send indications get exercised. It must never panic, and responses must be well-formed.
+//
+//     cargo +nightly fuzz run turn_packet
+
+#![no_main]
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+use cam2webrtc_fuzz::check_response;
+use cam2webrtc_fuzz::turn::{TurnAuth, TurnServer};
+
+struct Servers {
+    runtime: Runtime,
+    open: TurnServer,
+    authenticated: TurnServer,
+}
+
+fn servers() -> &'static Servers {
+    static SERVERS: OnceLock<Servers> = OnceLock::new();
+    SERVERS.get_or_init(|| {
+        let runtime = Runtime::new().expect("tokio runtime");
+        let bind: [SocketAddr; 1] = ["127.0.0.1:0".parse().unwrap()];
+        let (open, authenticated) = runtime.block_on(async {
+            let open = TurnServer::new(&bind, None, 49152..=49407).expect("bind TURN socket");
+            let auth = TurnAuth::new("fuzz-secret".to_string(), "fuzz".to_string());
+            let authenticated = TurnServer::new(&bind, Some(auth), 49152..=49407).expect("bind TURN socket");
+            (open, authenticated)
+        });
+        Servers { runtime, open, authenticated }
+    })
+}
+
+fuzz_target!(|input: &[u8]| {
+    let (selector, packet) = match input.split_first() {
+        Some((selector, packet)) => (*selector, packet),
+        None => return,
+    };
+    let servers = servers();
+    let server = if selector & 0x80 == 0 { &servers.open } else { &servers.authenticated };
+    // A handful of client ports, so send indications often come from a client with an allocation
+    let src_addr = SocketAddr::new([192, 0, 2, 1].into(), 50000 + u16::from(selector & 0x07));
+    if let Some(response) = servers.runtime.block_on(server.handle_turn_packet(packet, src_addr)) {
+        check_response(&response);
+    }
+});
@@ -0,0 +1,30 @@
+// lib.rs
+// The server is a binary crate, so the fuzz targets compile its STUN/TURN modules straight from
+// src/ under this crate root. Only the modules they need are pulled in; ip_filter is replaced by
+// a stand-in that admits every address, since the targets exercise parsing, not filtering.
+
+#[path = "../../src/stun.rs"]
+pub mod stun;
+#[path = "../../src/stun_proto.rs"]
+pub mod stun_proto;
+#[path = "../../src/turn.rs"]
+pub mod turn;
+#[allow(dead_code)]
+#[path = "../../src/network.rs"]
+mod network;
+
+mod ip_filter {
+    use std::net::IpAddr;
+
+    pub fn permits(_ip: IpAddr) -> bool {
+        true
+    }
+}
+
+/// A response must be a complete STUN message that decodes again: header length covering the
+/// padded attributes, and every attribute readable
+pub fn check_response(response: &[u8]) {
+    if let Err(e) = stun_proto::decode(response) {
+        panic!("malformed response ({}): {:02x?}", e, response);
+    }
+}
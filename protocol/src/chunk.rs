@@ -0,0 +1,141 @@
+//! Splitting signaling messages that exceed a size limit.
+//!
+//! Some proxies cut WebSocket messages above a fixed size, which an SDP with many candidates
+//! can reach. Connections that declare [`CAP_CHUNKING`](crate::CAP_CHUNKING) learn the server's
+//! limit from `welcome.max_message_bytes`; a larger message is sent as `chunk` messages holding
+//! consecutive slices of its JSON text and a closing `chunk_end`, and the receiver handles the
+//! joined text as if it had arrived whole. Either side may chunk.
+//!
+//! ```json
+//! {"type": "chunk", "data": {"id": "c1", "index": 0, "part": "{\"type\":\"offer\",..."}}
+//! {"type": "chunk_end", "data": {"id": "c1", "count": 3}}
+//! ```
+//!
+//! Chunks of one message must arrive in order; chunks of different messages may interleave.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::{SignalingMessage, SignalingMessageType};
+
+/// Smallest limit chunking works with; room for the envelope plus a useful slice
+pub const MIN_MESSAGE_BYTES: usize = 1024;
+/// JSON around the slice of a chunk: type, connection_id, id and index, with headroom
+const ENVELOPE_BYTES: usize = 256;
+/// Messages one connection may be reassembling at once
+const MAX_PENDING: usize = 4;
+/// An incomplete message is forgotten after this long
+const PENDING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `json` as chunk messages of at most `max_message_bytes` each, followed by the chunk_end.
+/// `connection_id` addresses every piece, like the message it came from.
+pub fn split(json: &str, max_message_bytes: usize, id: &str, connection_id: Option<String>) -> Vec<SignalingMessage> {
+    let envelope = ENVELOPE_BYTES + id.len() + connection_id.as_ref().map_or(0, String::len);
+    let budget = max_message_bytes.max(MIN_MESSAGE_BYTES).saturating_sub(envelope).max(64);
+    let mut parts = Vec::new();
+    let (mut start, mut escaped) = (0, 0);
+    for (offset, c) in json.char_indices() {
+        // Slices are measured as they will appear inside a JSON string
+        let size = escaped_len(c);
+        if escaped + size > budget && offset > start {
+            parts.push(&json[start..offset]);
+            (start, escaped) = (offset, 0);
+        }
+        escaped += size;
+    }
+    parts.push(&json[start..]);
+
+    let count = parts.len();
+    let mut messages: Vec<SignalingMessage> = parts.into_iter()
+        .enumerate()
+        .map(|(index, part)| piece(SignalingMessageType::Chunk, connection_id.clone(), serde_json::json!({"id": id, "index": index, "part": part})))
+        .collect();
+    messages.push(piece(SignalingMessageType::ChunkEnd, connection_id, serde_json::json!({"id": id, "count": count})));
+    messages
+}
+
+fn piece(message_type: SignalingMessageType, connection_id: Option<String>, data: Value) -> SignalingMessage {
+    SignalingMessage {
+        message_type,
+        connection_id,
+        source_sender_id: None,
+        sender_id: None,
+        offer_id: None,
+        data: Some(data),
+        is_sender: None,
+        seq: None,
+        msg_id: None,
+    }
+}
+
+/// Bytes `c` takes inside a JSON string as serde_json writes it
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        c if (c as u32) < 0x20 => 6,
+        c => c.len_utf8(),
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    text: String,
+    parts: usize,
+    started: Instant,
+}
+
+/// Joins the chunks received on one connection
+#[derive(Debug)]
+pub struct Reassembler {
+    max_bytes: usize,
+    pending: HashMap<String, Pending>,
+}
+
+impl Reassembler {
+    /// Reassembled messages may be at most `max_bytes` long
+    pub fn new(max_bytes: usize) -> Self {
+        Self { max_bytes, pending: HashMap::new() }
+    }
+
+    /// Takes a chunk or chunk_end; returns the joined JSON text once a chunk_end completes a
+    /// message. A malformed, out-of-order or oversized piece discards its message.
+    pub fn push(&mut self, message: &SignalingMessage) -> Result<Option<String>, String> {
+        let now = Instant::now();
+        self.pending.retain(|_, pending| now.duration_since(pending.started) < PENDING_TIMEOUT);
+
+        let data = message.data.as_ref().ok_or("chunk without data")?;
+        let id = data.get("id").and_then(Value::as_str).ok_or("chunk without an id")?;
+        match message.message_type {
+            SignalingMessageType::Chunk => {
+                let index = data.get("index").and_then(Value::as_u64).ok_or("chunk without an index")?;
+                let part = data.get("part").and_then(Value::as_str).ok_or("chunk without a part")?;
+                if !self.pending.contains_key(id) && self.pending.len() >= MAX_PENDING {
+                    return Err(format!("at most {} chunked messages may be in flight", MAX_PENDING));
+                }
+                let pending = self.pending.entry(id.to_string()).or_insert_with(|| Pending { text: String::new(), parts: 0, started: now });
+                if index != pending.parts as u64 {
+                    self.pending.remove(id);
+                    return Err(format!("chunk {} of {} arrived out of order", index, id));
+                }
+                if pending.text.len() + part.len() > self.max_bytes {
+                    self.pending.remove(id);
+                    return Err(format!("chunked message exceeds {} bytes", self.max_bytes));
+                }
+                pending.text.push_str(part);
+                pending.parts += 1;
+                Ok(None)
+            }
+            SignalingMessageType::ChunkEnd => {
+                let count = data.get("count").and_then(Value::as_u64).ok_or("chunk_end without a count")?;
+                let pending = self.pending.remove(id).ok_or_else(|| format!("chunk_end for unknown message {}", id))?;
+                if count != pending.parts as u64 {
+                    return Err(format!("message {} has {} of {} chunks", id, pending.parts, count));
+                }
+                Ok(Some(pending.text))
+            }
+            _ => Err("not a chunk".to_string()),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Async signaling client built on tokio-tungstenite.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::chunk::{self, Reassembler};
+use crate::{deflate, SignalingMessage, SignalingMessageType, CAP_CHUNKING, CAP_DEFLATE, PROTOCOL_VERSION};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Boxed, since tungstenite::Error is large
+    WebSocket(Box<tungstenite::Error>),
+    Json(serde_json::Error),
+    /// The connection to the server has been closed
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::WebSocket(e) => write!(f, "websocket error: {}", e),
+            ClientError::Json(e) => write!(f, "invalid message: {}", e),
+            ClientError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<tungstenite::Error> for ClientError {
+    fn from(e: tungstenite::Error) -> Self {
+        ClientError::WebSocket(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+/// A connection to one room of a ws2infer signaling server.
+///
+/// Reading and writing happen on background tasks; outgoing messages are queued, and incoming
+/// ones are delivered through [`next_message`](Self::next_message) or an
+/// [`on_message`](Self::on_message) handler.
+pub struct SignalingClient {
+    connection_id: String,
+    /// Limit from the server's welcome above which messages are sent as chunks; 0 until then
+    max_message_bytes: Arc<AtomicUsize>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    incoming: Option<mpsc::UnboundedReceiver<SignalingMessage>>,
+}
+
+impl SignalingClient {
+    /// Connects to `{server_url}/ws/{room_id}` (e.g. `ws://192.168.1.10:8080`) with a random
+    /// connection ID
+    pub async fn connect(server_url: &str, room_id: &str) -> Result<Self, ClientError> {
+        Self::connect_as(server_url, room_id, uuid::Uuid::new_v4().to_string()).await
+    }
+
+    /// Connects with a caller-chosen connection ID, e.g. a stable device name
+    pub async fn connect_as(server_url: &str, room_id: &str, connection_id: String) -> Result<Self, ClientError> {
+        let url = format!("{}/ws/{}", server_url.trim_end_matches('/'), room_id);
+        let (stream, _) = tokio_tungstenite::connect_async(url).await?;
+        let (mut write, mut read) = stream.split();
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_rx.recv().await {
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+            let _ = write.close().await;
+        });
+
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let max_message_bytes = Arc::new(AtomicUsize::new(0));
+        let announced_limit = max_message_bytes.clone();
+        tokio::spawn(async move {
+            let mut chunks = Reassembler::new(deflate::MAX_INFLATED_BYTES as usize);
+            while let Some(Ok(message)) = read.next().await {
+                match message {
+                    // Binary frames carry the same JSON for clients that declared CAP_BINARY,
+                    // or compressed JSON under CAP_DEFLATE
+                    Message::Text(_) | Message::Binary(_) => {
+                        let mut data = message.into_data();
+                        if deflate::is_compressed(&data) {
+                            match deflate::decompress(&data) {
+                                Ok(json) => data = json,
+                                Err(_) => continue,
+                            }
+                        }
+                        // Message types this version doesn't know are skipped
+                        let Ok(mut message) = serde_json::from_slice::<SignalingMessage>(&data) else {
+                            continue;
+                        };
+                        if matches!(message.message_type, SignalingMessageType::Chunk | SignalingMessageType::ChunkEnd) {
+                            // Broken chunk sets are dropped like undecodable frames
+                            match chunks.push(&message).ok().flatten().and_then(|text| serde_json::from_str(&text).ok()) {
+                                Some(whole) => message = whole,
+                                None => continue,
+                            }
+                        }
+                        if matches!(message.message_type, SignalingMessageType::Welcome) {
+                            let limit = message.data.as_ref()
+                                .and_then(|data| data.get("max_message_bytes"))
+                                .and_then(serde_json::Value::as_u64)
+                                .unwrap_or(0);
+                            announced_limit.store(limit as usize, Ordering::Relaxed);
+                        }
+                        if incoming_tx.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            connection_id,
+            max_message_bytes,
+            outgoing,
+            incoming: Some(incoming),
+        })
+    }
+
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
+
+    /// Queues any message as-is, as chunks when it exceeds the limit the server announced
+    pub fn send(&self, message: &SignalingMessage) -> Result<(), ClientError> {
+        let text = serde_json::to_string(message)?;
+        let max_message_bytes = self.max_message_bytes.load(Ordering::Relaxed);
+        if max_message_bytes == 0 || text.len() <= max_message_bytes {
+            return self.outgoing.send(Message::Text(text)).map_err(|_| ClientError::Closed);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        for piece in chunk::split(&text, max_message_bytes, &id, message.connection_id.clone()) {
+            let piece = serde_json::to_string(&piece)?;
+            self.outgoing.send(Message::Text(piece)).map_err(|_| ClientError::Closed)?;
+        }
+        Ok(())
+    }
+
+    /// Joins the room; must be the first message so the server learns this connection's ID.
+    /// `data` carries join options such as `tracks`, `display_name`, `metadata`, `guest`,
+    /// `admin_token`, `sender_token` or `capabilities`; `protocol_version` defaults to
+    /// [`PROTOCOL_VERSION`], so the server answers with a `welcome` before `room_info`, and
+    /// `capabilities` to [`CAP_DEFLATE`] and [`CAP_CHUNKING`].
+    pub async fn join(&self, is_sender: bool, data: Option<Value>) -> Result<(), ClientError> {
+        let mut data = match data {
+            Some(Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        data.entry("protocol_version").or_insert(Value::from(PROTOCOL_VERSION));
+        data.entry("capabilities").or_insert(serde_json::json!([CAP_DEFLATE, CAP_CHUNKING]));
+
+        let mut message = SignalingMessage::new_join(self.connection_id.clone(), is_sender);
+        message.data = Some(Value::Object(data));
+        self.send(&message)
+    }
+
+    /// Sends an SDP offer to one peer
+    pub async fn offer(&self, target_connection_id: &str, sdp: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_offer(target_connection_id.to_string(), self.connection_id.clone(), sdp))
+    }
+
+    /// Sends an SDP answer to the peer that made the offer
+    pub async fn answer(&self, target_connection_id: &str, sdp: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_answer(target_connection_id.to_string(), self.connection_id.clone(), sdp))
+    }
+
+    pub async fn ice_candidate(&self, target_connection_id: &str, candidate: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_ice_candidate(
+            target_connection_id.to_string(),
+            self.connection_id.clone(),
+            candidate,
+        ))
+    }
+
+    /// Reports inference results about the stream of `source_sender_id`
+    pub async fn inference_result(&self, source_sender_id: &str, data: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_inference_result(
+            self.connection_id.clone(),
+            source_sender_id.to_string(),
+            data,
+        ))
+    }
+
+    /// Forwards opaque `data` to another connection through the server (rate limited); for
+    /// control data when no direct data channel is available
+    pub async fn relay(&self, target_connection_id: &str, data: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_relay(target_connection_id.to_string(), self.connection_id.clone(), data))
+    }
+
+    /// Sends end-to-end encryption key material to one peer of an `e2ee` room; the server only
+    /// routes it to that peer
+    pub async fn key_exchange(&self, target_connection_id: &str, data: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_key_exchange(target_connection_id.to_string(), self.connection_id.clone(), data))
+    }
+
+    /// Asks the peer sending us video for its simulcast layer `rid`
+    pub async fn subscribe_layer(&self, target_connection_id: &str, rid: &str) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_subscribe_layer(target_connection_id.to_string(), self.connection_id.clone(), rid))
+    }
+
+    /// Receives only InferenceUpdates with detections of `classes` (any when empty) scoring at
+    /// least `min_score`
+    pub async fn subscribe_inference(&self, classes: &[String], min_score: Option<f64>) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_subscribe_inference(self.connection_id.clone(), classes, min_score))
+    }
+
+    /// Replaces the room's zones (admins and controllers only); ZoneEvents report detections in them
+    pub async fn set_zones(&self, zones: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_set_zones(self.connection_id.clone(), zones))
+    }
+
+    /// Replays stored results (`since`, `until`, `speed`, `source_id`) to this connection as
+    /// InferenceUpdates with `replay: true`; `Value::Null` stops a running replay
+    pub async fn replay(&self, request: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_replay(self.connection_id.clone(), request))
+    }
+
+    /// Sends application-defined `data` to every other peer in the room (chat, annotations, ...)
+    pub async fn broadcast(&self, data: Value) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_broadcast(self.connection_id.clone(), data))
+    }
+
+    /// Asks the server for a `sync_state` with the full room state, e.g. after reconnecting
+    pub async fn sync(&self) -> Result<(), ClientError> {
+        self.send(&SignalingMessage::new_sync_request(self.connection_id.clone()))
+    }
+
+    /// Next message from the server; None once the connection is closed or after
+    /// [`on_message`](Self::on_message) took over delivery
+    pub async fn next_message(&mut self) -> Option<SignalingMessage> {
+        self.incoming.as_mut()?.recv().await
+    }
+
+    /// Hands every incoming message to `handler` on a background task instead of
+    /// [`next_message`](Self::next_message). Returns None if a handler was already installed.
+    pub fn on_message<F, Fut>(&mut self, mut handler: F) -> Option<JoinHandle<()>>
+    where
+        F: FnMut(SignalingMessage) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut incoming = self.incoming.take()?;
+        Some(tokio::spawn(async move {
+            while let Some(message) = incoming.recv().await {
+                handler(message).await;
+            }
+        }))
+    }
+
+    /// Closes the WebSocket; the server treats this as leaving the room
+    pub fn close(&self) {
+        let _ = self.outgoing.send(Message::Close(None));
+    }
+}
@@ -0,0 +1,43 @@
+//! Application-level compression of signaling messages.
+//!
+//! The server's WebSocket stack has no permessage-deflate, so connections that declare
+//! [`CAP_DEFLATE`](crate::CAP_DEFLATE) get messages of at least [`DEFLATE_MIN_BYTES`] as binary
+//! frames holding zlib-compressed JSON (`DecompressionStream("deflate")` in browsers). Either
+//! side may send such frames; they are told apart from plain JSON and JPEG frames by the zlib
+//! header.
+
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// Smaller messages are sent uncompressed; below this the zlib overhead eats the gain
+pub const DEFLATE_MIN_BYTES: usize = 512;
+/// Largest accepted decompressed message, so a small frame can't expand without bound
+pub const MAX_INFLATED_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Whether a binary frame starts with a zlib header
+pub fn is_compressed(frame: &[u8]) -> bool {
+    match frame {
+        [cmf, flg, ..] => cmf & 0x0F == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+pub fn compress(json: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(json.len() / 4), Compression::fast());
+    // Writing to a Vec can't fail
+    let _ = encoder.write_all(json);
+    encoder.finish().unwrap_or_default()
+}
+
+/// The JSON in a compressed frame; fails on corrupt data or beyond [`MAX_INFLATED_BYTES`]
+pub fn decompress(frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut json = Vec::new();
+    ZlibDecoder::new(frame).take(MAX_INFLATED_BYTES + 1).read_to_end(&mut json)?;
+    if json.len() as u64 > MAX_INFLATED_BYTES {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "decompressed message too large"));
+    }
+    Ok(json)
+}
@@ -0,0 +1,555 @@
+//! Signaling message types shared by the ws2infer server and its Rust clients.
+//!
+//! Every message is a JSON object with a snake_case `type`. Clients identify themselves with the
+//! `connection_id` of their first message (normally `join`); afterwards `connection_id` names the
+//! peer a message is routed to.
+
+mod version;
+pub mod chunk;
+pub mod deflate;
+
+#[cfg(feature = "client")]
+mod client;
+
+#[cfg(feature = "client")]
+pub use client::{ClientError, SignalingClient};
+
+pub use version::{
+    Negotiated, CAP_BINARY, CAP_CHUNKING, CAP_CONFIG_DELTA, CAP_DEFLATE, CAP_INFERENCE_V2, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION, SERVER_CAPABILITIES,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalingMessage {
+    #[serde(rename = "type")]
+    pub message_type: SignalingMessageType,
+    pub connection_id: Option<String>,
+    pub source_sender_id: Option<String>,
+    /// Author of the message; for client messages the server sets it to the connection_id the
+    /// client joined with, whatever the client sent
+    pub sender_id: Option<String>,
+    pub offer_id: Option<String>,
+    pub data: Option<Value>,
+    pub is_sender: Option<bool>,
+    /// Stamped by the server on each message it queues for a connection, starting at 1 on each
+    /// WebSocket connection; a gap means messages were lost, a reset means a reconnect.
+    /// InferenceUpdate, InferenceStats and Analytics, which may be dropped under backpressure,
+    /// carry none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Chosen by the client; a message repeating an ID the same connection sent recently is
+    /// dropped, so clients can retransmit safely
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalingMessageType {
+    Join,
+    Leave,
+    Offer,
+    Answer,
+    IceCandidate,
+    RoomInfo,
+    Error,
+    InferenceResult,
+    InferenceUpdate,
+    NewPeer,
+    /// The whole client config after a reload; sent to clients without supports_config_delta
+    ConfigUpdated,
+    /// What a reload changed (`changed`, `removed`, `etag`); sent instead of ConfigUpdated to
+    /// clients with supports_config_delta
+    ConfigChanged,
+    GuestExpiring,
+    SessionExpired,
+    Anomaly,
+    InferenceStats,
+    /// Reply to a join that declared `protocol_version`; never sent to legacy clients
+    Welcome,
+    /// Asks for the full current room state, e.g. after reconnecting
+    SyncRequest,
+    /// Reply to SyncRequest: room_info, stored offers and the latest inference per source
+    SyncState,
+    /// Opaque application data the server forwards to `connection_id`, rate limited; the
+    /// fallback when a direct data channel can't be established
+    Relay,
+    /// Application-defined data the server copies to every other peer in the room
+    Broadcast,
+    /// Sent instead of anything else when the room lives on another instance of a cluster;
+    /// `data.url` is the WebSocket URL to reconnect to. The server closes the connection after it.
+    Redirect,
+    /// Connection quality from the client's getStats(), `data.peers` holding one entry per peer
+    /// connection; stored by the server and never forwarded
+    StatsReport,
+    /// Server to sender: viewers report loss (or have recovered), so capture at `data.width`,
+    /// `data.height` and `data.frame_rate` (any may be null when unbounded)
+    QualityHint,
+    /// The room was deleted through the API; `data.reason` says why. The server closes the
+    /// connection after it.
+    RoomClosed,
+    /// Asks `connection_id` to restart ICE with `sender_id` (a new offer with iceRestart) after
+    /// a network change. Clients send it to each other; the server sends it when a sender
+    /// rejoins shortly after dropping, to the sender once per viewer and to each viewer.
+    IceRestart,
+    /// End-to-end encryption key material (e.g. an ECDH public key or a wrapped frame key for
+    /// insertable streams) from `sender_id` to `connection_id`, in rooms whose room_info has
+    /// `e2ee: true`. The server forwards it to that one connection only: it is never broadcast,
+    /// stored, included in sync_state or logged.
+    KeyExchange,
+    /// Viewer to the peer sending it video (the sender, or the SFU peer in SFU rooms): receive
+    /// the simulcast layer `data.rid` (e.g. "h", "m", "l"), lower ones on a poor connection.
+    /// The server remembers the choice per viewer and sender.
+    SubscribeLayer,
+    /// Sender or SFU peer to viewers: the simulcast layers on offer (`data.layers`, a list of
+    /// rids) and the one being sent to this viewer (`data.active`). Without `connection_id` it
+    /// goes to every viewer.
+    LayerInfo,
+    /// Viewer to server: forward only InferenceUpdates with a detection whose class is in
+    /// `data.classes` (any class when absent or empty) and whose score is at least
+    /// `data.min_score`; other detections are left out of the update. `data: null` subscribes
+    /// to everything again.
+    SubscribeInference,
+    /// Admin or controller to server: replace the room's zones with `data.zones`, a list of
+    /// polygons (see /api/rooms/{id}/zones). An empty list removes them all.
+    SetZones,
+    /// Server to every member: a detection entered or left a zone, or stayed in it for the
+    /// zone's dwell_secs. `data` has zone_id, event, source_sender_id, count, classes and
+    /// timestamp.
+    ZoneEvent,
+    /// Server to viewers and admins: crossing counts of the room's counting lines
+    /// (`data.lines`), sent periodically while they change. Newer ones supersede older ones.
+    Analytics,
+    /// Server to every member: a room policy script raised an alert for an inference result.
+    /// `data` has script, source_sender_id and the script's alert.
+    Alert,
+    /// Member to server: stream the stored results between `data.since` and `data.until`
+    /// (RFC 3339) at `data.speed` times real time to this connection, as InferenceUpdates with
+    /// `replay: true`; `data: null` stops. Server to that member: `data.status` is started
+    /// (with count), finished or stopped.
+    Replay,
+    /// One slice (`data.part`, number `data.index`) of the JSON text of a message too large to
+    /// send whole, for connections with `supports_chunking`; see [`chunk`]
+    Chunk,
+    /// Closes the chunks of `data.id` after `data.count` slices; the joined text is handled as
+    /// the original message
+    ChunkEnd,
+    /// Server to every member of a room with an idle timeout: nothing but stats_report was
+    /// signaled or inferred for a while, and the server closes every connection with a
+    /// session_expired (`data.idle: true`) in `data.seconds_left` unless traffic resumes
+    IdleWarning,
+    /// Server to every member: the sender left and its standby `data.sender_id` took over from
+    /// `data.previous_sender_id`. Viewers get the new sender's offers next; the new sender gets
+    /// a fresh room_info listing the peers to offer to.
+    SenderChanged,
+}
+
+impl SignalingMessage {
+    pub fn new_join(connection_id: String, is_sender: bool) -> Self {
+        Self {
+            message_type: SignalingMessageType::Join,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: None,
+            is_sender: Some(is_sender),
+            seq: None,
+            msg_id: None,
+        }
+    }
+    
+    pub fn new_offer(
+        connection_id: String,
+        sender_id: String,
+        sdp: Value,
+    ) -> Self {
+        Self {
+            message_type: SignalingMessageType::Offer,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(sdp),
+            is_sender: Some(true),
+            seq: None,
+            msg_id: None,
+        }
+    }
+    
+    pub fn new_answer(
+        connection_id: String,
+        sender_id: String,
+        sdp: Value,
+    ) -> Self {
+        Self {
+            message_type: SignalingMessageType::Answer,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(sdp),
+            is_sender: Some(false),
+            seq: None,
+            msg_id: None,
+        }
+    }
+    
+    pub fn new_ice_candidate(
+        connection_id: String,
+        sender_id: String,
+        candidate: Value,
+    ) -> Self {
+        Self {
+            message_type: SignalingMessageType::IceCandidate,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(candidate),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+    
+    pub fn new_config_updated(connection_id: String, config: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::ConfigUpdated,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(config),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_config_changed(connection_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::ConfigChanged,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_error(connection_id: String, error: String) -> Self {
+        Self {
+            message_type: SignalingMessageType::Error,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({
+                "error": error
+            })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_sync_request(sender_id: String) -> Self {
+        Self {
+            message_type: SignalingMessageType::SyncRequest,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: None,
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_sync_state(connection_id: String, state: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::SyncState,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(state),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_relay(target_connection_id: String, sender_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Relay,
+            connection_id: Some(target_connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_broadcast(sender_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Broadcast,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_redirect(url: String) -> Self {
+        Self {
+            message_type: SignalingMessageType::Redirect,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({ "url": url })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_stats_report(sender_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::StatsReport,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_quality_hint(connection_id: String, hint: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::QualityHint,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(hint),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_room_closed(connection_id: String, reason: &str) -> Self {
+        Self {
+            message_type: SignalingMessageType::RoomClosed,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({ "reason": reason })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_sender_changed(connection_id: String, previous_sender_id: &str, sender_id: &str) -> Self {
+        Self {
+            message_type: SignalingMessageType::SenderChanged,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({ "previous_sender_id": previous_sender_id, "sender_id": sender_id })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_ice_restart(connection_id: String, sender_id: String, reason: &str) -> Self {
+        Self {
+            message_type: SignalingMessageType::IceRestart,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(serde_json::json!({ "reason": reason })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_key_exchange(target_connection_id: String, sender_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::KeyExchange,
+            connection_id: Some(target_connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_subscribe_layer(target_connection_id: String, sender_id: String, rid: &str) -> Self {
+        Self {
+            message_type: SignalingMessageType::SubscribeLayer,
+            connection_id: Some(target_connection_id),
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(serde_json::json!({ "rid": rid })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_subscribe_inference(sender_id: String, classes: &[String], min_score: Option<f64>) -> Self {
+        Self {
+            message_type: SignalingMessageType::SubscribeInference,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(serde_json::json!({ "classes": classes, "min_score": min_score })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_set_zones(sender_id: String, zones: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::SetZones,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(serde_json::json!({ "zones": zones })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_zone_event(source_sender_id: String, event: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::ZoneEvent,
+            connection_id: None,
+            source_sender_id: Some(source_sender_id),
+            sender_id: None,
+            offer_id: None,
+            data: Some(event),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_replay(sender_id: String, request: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Replay,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(request),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_replay_status(target_connection_id: String, status: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Replay,
+            connection_id: Some(target_connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(status),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_alert(source_sender_id: String, alert: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Alert,
+            connection_id: None,
+            source_sender_id: Some(source_sender_id),
+            sender_id: None,
+            offer_id: None,
+            data: Some(alert),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_analytics(target_connection_id: String, data: serde_json::Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::Analytics,
+            connection_id: Some(target_connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_layer_info(target_connection_id: Option<String>, sender_id: String, layers: &[String], active: Option<&str>) -> Self {
+        Self {
+            message_type: SignalingMessageType::LayerInfo,
+            connection_id: target_connection_id,
+            source_sender_id: None,
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(serde_json::json!({ "layers": layers, "active": active })),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    pub fn new_inference_result(sender_id: String, source_sender_id: String, data: Value) -> Self {
+        Self {
+            message_type: SignalingMessageType::InferenceResult,
+            connection_id: None,
+            source_sender_id: Some(source_sender_id),
+            sender_id: Some(sender_id),
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+}
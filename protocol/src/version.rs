@@ -0,0 +1,151 @@
+//! Protocol versions and capability negotiation.
+//!
+//! A client declares `protocol_version` and `capabilities` in its `join` data. The server
+//! answers with a `welcome` carrying the version both sides speak and the capabilities it
+//! accepted, then adapts every message to that connection through
+//! [`SignalingMessage::adapt_for`]. Clients that send neither field are treated as
+//! [`LEGACY_PROTOCOL_VERSION`] and receive exactly what the original JS clients expect.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{SignalingMessage, SignalingMessageType};
+
+/// Newest protocol version this crate speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Version of clients that predate negotiation
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// The client also accepts messages as binary WebSocket frames (UTF-8 JSON)
+pub const CAP_BINARY: &str = "supports_binary";
+/// `inference_update` data is the result itself (`detections`, `timestamp`, ...) next to
+/// `source_sender_id` instead of being wrapped in `latest`
+pub const CAP_INFERENCE_V2: &str = "supports_inference_v2";
+
+/// Messages of at least [`DEFLATE_MIN_BYTES`](crate::deflate::DEFLATE_MIN_BYTES) arrive as binary
+/// frames of zlib-compressed JSON; see [`deflate`](crate::deflate)
+pub const CAP_DEFLATE: &str = "supports_deflate";
+
+/// Messages above `welcome.max_message_bytes` travel as `chunk`/`chunk_end` in both
+/// directions; see [`chunk`](crate::chunk)
+pub const CAP_CHUNKING: &str = "supports_chunking";
+
+/// Config reloads arrive as `config_changed` with only the changed keys instead of
+/// `config_updated` with the whole config
+pub const CAP_CONFIG_DELTA: &str = "supports_config_delta";
+
+/// Capabilities the server can honour
+pub const SERVER_CAPABILITIES: &[&str] = &[CAP_BINARY, CAP_INFERENCE_V2, CAP_DEFLATE, CAP_CHUNKING, CAP_CONFIG_DELTA];
+
+/// What was agreed with one connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Negotiated {
+    pub protocol_version: u32,
+    pub capabilities: Vec<String>,
+    /// Largest message the server takes or sends whole, set when chunking was agreed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_message_bytes: Option<usize>,
+}
+
+impl Default for Negotiated {
+    fn default() -> Self {
+        Self {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+            max_message_bytes: None,
+        }
+    }
+}
+
+impl Negotiated {
+    /// Settles on the lower of both versions and the capabilities both sides support;
+    /// unknown capabilities are ignored
+    pub fn from_join(data: Option<&Value>) -> Self {
+        let requested = data
+            .and_then(|d| d.get("protocol_version"))
+            .and_then(Value::as_u64)
+            .map(|v| v.clamp(LEGACY_PROTOCOL_VERSION as u64, PROTOCOL_VERSION as u64) as u32);
+        let protocol_version = match requested {
+            Some(version) => version,
+            None => return Self::default(),
+        };
+
+        let capabilities = data
+            .and_then(|d| d.get("capabilities"))
+            .and_then(Value::as_array)
+            .map(|caps| caps.iter()
+                .filter_map(Value::as_str)
+                .filter(|cap| SERVER_CAPABILITIES.contains(cap))
+                .map(str::to_string)
+                .collect())
+            .unwrap_or_default();
+
+        Self { protocol_version, capabilities, max_message_bytes: None }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.protocol_version <= LEGACY_PROTOCOL_VERSION
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl SignalingMessageType {
+    /// Oldest protocol version that knows this message type
+    pub fn min_protocol_version(&self) -> u32 {
+        match self {
+            SignalingMessageType::Welcome | SignalingMessageType::Chunk | SignalingMessageType::ChunkEnd => 2,
+            _ => LEGACY_PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl SignalingMessage {
+    pub fn new_welcome(connection_id: String, negotiated: &Negotiated) -> Self {
+        Self {
+            message_type: SignalingMessageType::Welcome,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: serde_json::to_value(negotiated).ok(),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    /// The message as the recipient understands it, or None when its version has no such
+    /// message. Messages are built in the legacy shape and upgraded here.
+    pub fn adapt_for(mut self, negotiated: &Negotiated) -> Option<Self> {
+        if self.message_type.min_protocol_version() > negotiated.protocol_version {
+            return None;
+        }
+
+        if matches!(self.message_type, SignalingMessageType::InferenceUpdate)
+            && negotiated.supports(CAP_INFERENCE_V2)
+        {
+            if let Some(data) = self.data.take() {
+                self.data = Some(inference_update_v2(data));
+            }
+        }
+        Some(self)
+    }
+}
+
+/// `{source_sender_id, latest: {predictions, ...}}` -> `{source_sender_id, detections, ...}`
+fn inference_update_v2(data: Value) -> Value {
+    let mut result = match data.get("latest") {
+        Some(Value::Object(latest)) => latest.clone(),
+        _ => return data,
+    };
+    if let Some(predictions) = result.remove("predictions") {
+        result.insert("detections".to_string(), predictions);
+    }
+    if let Some(source) = data.get("source_sender_id") {
+        result.insert("source_sender_id".to_string(), source.clone());
+    }
+    Value::Object(result)
+}
@@ -0,0 +1,121 @@
+// aggregation.rs
+// Rolling per-room, per-source inference statistics: detections per class, mean confidence and
+// the rate at which results arrive. Served by GET /api/rooms/{id}/inference/stats and pushed to
+// viewers as InferenceStats for dashboard overlays.
+
+use std::collections::{HashMap, VecDeque};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+
+/// Span of the rolling window statistics are computed over
+pub const STATS_WINDOW_SECS: i64 = 60;
+
+#[derive(Debug, Clone)]
+struct Sample {
+    at: DateTime<Utc>,
+    /// (class, score) of every detection in the result
+    detections: Vec<(String, f64)>,
+}
+
+#[derive(Debug, Default)]
+pub struct InferenceAggregator {
+    /// room_id -> source_sender_id -> samples inside the window, oldest first
+    windows: HashMap<String, HashMap<String, VecDeque<Sample>>>,
+}
+
+impl InferenceAggregator {
+    /// Records one normalized (and calibrated) inference payload
+    pub fn record(&mut self, room_id: &str, source_id: &str, payload: &Value, now: DateTime<Utc>) {
+        let detections = payload.get("predictions")
+            .and_then(Value::as_array)
+            .map(|items| {
+                items.iter()
+                    .filter_map(|item| {
+                        let class = item.get("class")?.as_str()?;
+                        let score = item.get("score")?.as_f64()?;
+                        Some((class.to_string(), score))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let samples = self.windows
+            .entry(room_id.to_string())
+            .or_default()
+            .entry(source_id.to_string())
+            .or_default();
+        samples.push_back(Sample { at: now, detections });
+        prune_samples(samples, now);
+    }
+
+    /// Drops samples that left the window, and sources and rooms that went quiet
+    pub fn prune(&mut self, now: DateTime<Utc>) {
+        for sources in self.windows.values_mut() {
+            for samples in sources.values_mut() {
+                prune_samples(samples, now);
+            }
+            sources.retain(|_, samples| !samples.is_empty());
+        }
+        self.windows.retain(|_, sources| !sources.is_empty());
+    }
+
+    pub fn forget_room(&mut self, room_id: &str) {
+        self.windows.remove(room_id);
+    }
+
+    /// Rooms that currently have samples in the window
+    pub fn active_rooms(&self) -> Vec<String> {
+        self.windows.keys().cloned().collect()
+    }
+
+    /// Window statistics for every source in the room
+    pub fn stats(&self, room_id: &str, now: DateTime<Utc>) -> Value {
+        let cutoff = now - Duration::seconds(STATS_WINDOW_SECS);
+        let mut sources = serde_json::Map::new();
+
+        for (source_id, samples) in self.windows.get(room_id).into_iter().flatten() {
+            let live: Vec<&Sample> = samples.iter().filter(|s| s.at > cutoff).collect();
+            if live.is_empty() {
+                continue;
+            }
+
+            // Rate over the time the source has actually been reporting, at least one second
+            let observed_ms = (now - live[0].at).num_milliseconds().clamp(1000, STATS_WINDOW_SECS * 1000);
+            let fps = live.len() as f64 * 1000.0 / observed_ms as f64;
+
+            let mut per_class: HashMap<&str, (u64, f64)> = HashMap::new();
+            for (class, score) in live.iter().flat_map(|s| &s.detections) {
+                let entry = per_class.entry(class.as_str()).or_insert((0, 0.0));
+                entry.0 += 1;
+                entry.1 += score;
+            }
+            let classes: serde_json::Map<String, Value> = per_class.into_iter()
+                .map(|(class, (count, total))| {
+                    (class.to_string(), serde_json::json!({
+                        "count": count,
+                        "avg_score": total / count as f64
+                    }))
+                })
+                .collect();
+
+            sources.insert(source_id.clone(), serde_json::json!({
+                "results": live.len(),
+                "fps": fps,
+                "classes": classes
+            }));
+        }
+
+        serde_json::json!({
+            "room_id": room_id,
+            "window_secs": STATS_WINDOW_SECS,
+            "sources": sources
+        })
+    }
+}
+
+fn prune_samples(samples: &mut VecDeque<Sample>, now: DateTime<Utc>) {
+    let cutoff = now - Duration::seconds(STATS_WINDOW_SECS);
+    while samples.front().is_some_and(|s| s.at <= cutoff) {
+        samples.pop_front();
+    }
+}
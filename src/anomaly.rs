@@ -0,0 +1,196 @@
+// anomaly.rs
+// Optional anomaly detection over inference traffic. A per-room baseline of typical detection
+// counts per class and hour of day is learned from stored history; live results are compared
+// against it over a rolling window and Anomaly events are emitted when they deviate by more than
+// the configured factor.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+    /// Live mean must exceed expected * factor (spike) or fall below expected / factor (drop)
+    #[serde(default = "default_factor")]
+    pub factor: f64,
+    /// Expected means below this are treated as this value, so rare classes don't fire on noise
+    #[serde(default = "default_min_expected")]
+    pub min_expected: f64,
+    /// Rolling window of live results compared against the baseline
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Minimum live results in the window before anything is evaluated
+    #[serde(default = "default_min_window_results")]
+    pub min_window_results: usize,
+    /// How much stored history the baseline is learned from
+    #[serde(default = "default_history_days")]
+    pub history_days: u32,
+    /// Minimum stored results for an hour of day before it is trusted
+    #[serde(default = "default_min_history_samples")]
+    pub min_history_samples: u64,
+    /// How often the baseline is re-learned from the database
+    #[serde(default = "default_relearn_secs")]
+    pub relearn_secs: u64,
+}
+
+fn default_factor() -> f64 { 3.0 }
+fn default_min_expected() -> f64 { 0.5 }
+fn default_window_secs() -> u64 { 300 }
+fn default_min_window_results() -> usize { 10 }
+fn default_history_days() -> u32 { 14 }
+fn default_min_history_samples() -> u64 { 50 }
+fn default_relearn_secs() -> u64 { 3600 }
+
+impl AnomalyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.factor <= 1.0 {
+            return Err("factor must be greater than 1".to_string());
+        }
+        if self.window_secs == 0 || self.relearn_secs == 0 || self.history_days == 0 {
+            return Err("window_secs, relearn_secs and history_days must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Counts detections per class in an InferenceResult payload
+pub fn class_counts(payload: &Value) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for key in ["predictions", "detections"] {
+        if let Some(items) = payload.get(key).and_then(Value::as_array) {
+            for class in items.iter().filter_map(|item| item.get("class").and_then(Value::as_str)) {
+                *counts.entry(class.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Mean detections per result, per room, hour of day (UTC) and class
+#[derive(Debug, Default, Clone)]
+pub struct Baseline {
+    /// (room_id, hour) -> number of stored results
+    samples: HashMap<(String, u32), u64>,
+    /// (room_id, hour, class) -> mean detections per result
+    means: HashMap<(String, u32, String), f64>,
+}
+
+impl Baseline {
+    pub fn learn(history: &[(String, DateTime<Utc>, Value)]) -> Self {
+        let mut samples: HashMap<(String, u32), u64> = HashMap::new();
+        let mut totals: HashMap<(String, u32, String), u64> = HashMap::new();
+
+        for (room_id, ts, payload) in history {
+            let hour = ts.hour();
+            *samples.entry((room_id.clone(), hour)).or_insert(0) += 1;
+            for (class, count) in class_counts(payload) {
+                *totals.entry((room_id.clone(), hour, class)).or_insert(0) += count as u64;
+            }
+        }
+
+        let means = totals.into_iter()
+            .map(|((room_id, hour, class), total)| {
+                let n = samples.get(&(room_id.clone(), hour)).copied().unwrap_or(1).max(1);
+                ((room_id, hour, class), total as f64 / n as f64)
+            })
+            .collect();
+
+        Self { samples, means }
+    }
+}
+
+/// Receive time and class counts of one live result
+type Observation = (DateTime<Utc>, HashMap<String, usize>);
+
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    baseline: Baseline,
+    /// room_id -> recent (receive time, class counts)
+    recent: HashMap<String, VecDeque<Observation>>,
+    /// (room_id, class) -> when the last anomaly for it was emitted
+    last_emitted: HashMap<(String, String), DateTime<Utc>>,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            baseline: Baseline::default(),
+            recent: HashMap::new(),
+            last_emitted: HashMap::new(),
+        }
+    }
+
+    pub fn set_baseline(&mut self, baseline: Baseline) {
+        self.baseline = baseline;
+    }
+
+    /// Records a live result and returns an event for every class that now deviates from the
+    /// baseline. Each (room, class) fires at most once per window.
+    pub fn observe(&mut self, room_id: &str, payload: &Value, now: DateTime<Utc>) -> Vec<Value> {
+        let window = chrono::Duration::seconds(self.config.window_secs as i64);
+        let recent = self.recent.entry(room_id.to_string()).or_default();
+        recent.push_back((now, class_counts(payload)));
+        while recent.front().is_some_and(|(ts, _)| now - *ts > window) {
+            recent.pop_front();
+        }
+        if recent.len() < self.config.min_window_results {
+            return Vec::new();
+        }
+
+        let hour = now.hour();
+        let history_samples = self.baseline.samples.get(&(room_id.to_string(), hour)).copied().unwrap_or(0);
+        if history_samples < self.config.min_history_samples {
+            return Vec::new();
+        }
+
+        let mut classes: HashSet<String> = recent.iter()
+            .flat_map(|(_, counts)| counts.keys().cloned())
+            .collect();
+        classes.extend(
+            self.baseline.means.keys()
+                .filter(|(r, h, _)| r == room_id && *h == hour)
+                .map(|(_, _, class)| class.clone()),
+        );
+
+        let results = recent.len() as f64;
+        let mut events = Vec::new();
+        for class in classes {
+            let observed = recent.iter()
+                .map(|(_, counts)| counts.get(&class).copied().unwrap_or(0))
+                .sum::<usize>() as f64 / results;
+            let expected = self.baseline.means
+                .get(&(room_id.to_string(), hour, class.clone()))
+                .copied()
+                .unwrap_or(0.0);
+
+            let kind = if observed > expected.max(self.config.min_expected) * self.config.factor {
+                "spike"
+            } else if expected >= self.config.min_expected && observed < expected / self.config.factor {
+                "drop"
+            } else {
+                continue;
+            };
+
+            let key = (room_id.to_string(), class.clone());
+            if matches!(self.last_emitted.get(&key), Some(last) if now - *last < window) {
+                continue;
+            }
+            self.last_emitted.insert(key, now);
+
+            events.push(serde_json::json!({
+                "class": class,
+                "kind": kind,
+                "observed": observed,
+                "expected": expected,
+                "factor": self.config.factor,
+                "hour": hour,
+                "window_secs": self.config.window_secs,
+                "ts": now.to_rfc3339()
+            }));
+        }
+        events
+    }
+}
@@ -0,0 +1,188 @@
+// api.rs
+// Request and response bodies of the REST API. They derive utoipa's ToSchema so openapi.rs can
+// describe them in /api/openapi.json; handlers build these instead of ad-hoc JSON so the
+// document and the responses can't drift apart.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::presence::Profile;
+use crate::quota::QuotaExceeded;
+use crate::room::{Role, RoomMode};
+use crate::room_template::RoomTemplate;
+use crate::schedule::Schedule;
+
+/// Largest JSON request body accepted, like a long-polling request's
+pub const MAX_BODY_BYTES: u64 = crate::poll::MAX_BODY_BYTES;
+
+/// Body of every error response
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    /// Where to send the request instead, when the room belongs to another cluster instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Which quota was exceeded, on 429 responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaExceeded>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self { error: error.into(), url: None, quota: None }
+    }
+
+    pub fn elsewhere(url: String) -> Self {
+        Self { error: "Room belongs to another instance".to_string(), url: Some(url), quota: None }
+    }
+
+    pub fn quota(exceeded: QuotaExceeded) -> Self {
+        Self { error: exceeded.to_string(), url: None, quota: Some(exceeded) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateRoomRequest {
+    /// Deterministic ID for fixed installations; the existing room is returned if it exists
+    #[serde(default)]
+    pub room_id: Option<String>,
+    /// Name of a room_templates entry in config.json to start from
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Inline settings ("mode", "guest_session_secs", "idle_timeout_secs", "video_constraints",
+    /// "max_viewers", "recording", "e2ee", "ice_policy"); each one given overrides the template's
+    #[serde(flatten)]
+    pub settings: RoomTemplate,
+    /// Accept joins from starts_at and close the room at ends_at (both optional)
+    #[serde(flatten)]
+    pub schedule: Schedule,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomResponse {
+    pub room_id: String,
+    /// False when a client-supplied room_id named a room that already existed
+    pub created: bool,
+    /// Senders must join with this; only returned when the room was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_token: Option<String>,
+    /// Viewers joining as controllers must present this; only returned when the room was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_token: Option<String>,
+}
+
+/// Reply to PUT /api/rooms/{id}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomMetadata {
+    pub room_id: String,
+    /// RFC 3339
+    pub created_at: String,
+    pub connection_count: usize,
+    /// Only returned when the room was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_token: Option<String>,
+    /// Only returned when the room was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerSummary {
+    pub connection_id: String,
+    pub role: Role,
+    /// RFC 3339
+    pub connected_at: String,
+    /// display_name and metadata from the peer's Join
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// Simulcast layer a viewer asked for from one peer sending it video
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LayerSubscription {
+    pub viewer: String,
+    pub sender: String,
+    pub rid: String,
+}
+
+/// Reply to GET /api/rooms/{id}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomStatus {
+    pub exists: bool,
+    pub room_id: String,
+    /// RFC 3339
+    pub created_at: String,
+    pub mode: RoomMode,
+    pub template: Option<String>,
+    pub connection_count: usize,
+    pub senders: usize,
+    pub viewers: usize,
+    pub admins: usize,
+    pub controllers: usize,
+    pub standbys: usize,
+    /// Only for room admins: connection IDs route signaling, so they aren't published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peers: Option<Vec<PeerSummary>>,
+    pub active_offers: usize,
+    /// Only for room admins, like `peers`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_subscriptions: Option<Vec<LayerSubscription>>,
+    /// Capture time of the newest inference result from any source (RFC 3339)
+    pub latest_inference_at: Option<String>,
+    /// Players that fetched the room's HLS playlist recently
+    pub hls_viewers: usize,
+}
+
+/// Reply to GET /api/rooms/{id}/capabilities
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomCapabilities {
+    pub room_id: String,
+    pub mode: RoomMode,
+    pub sender_present: bool,
+    /// Viewers and controllers counted against max_viewers
+    pub viewer_count: usize,
+    pub max_viewers: Option<usize>,
+    /// A server-side recording is running
+    pub recording: bool,
+    /// The SFU's forwarding peer is in the room
+    pub sfu_active: bool,
+    pub e2ee: bool,
+    /// Viewers need a guest pass (POST /api/rooms/{id}/guest-passes) lasting this long
+    pub guest_session_secs: Option<u64>,
+    /// Activation window, when the room has one
+    #[serde(flatten)]
+    pub schedule: Schedule,
+}
+
+/// Body of POST /api/rooms/{id}/guest-passes
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct GuestPassRequest {
+    /// Pass length; only room admins may choose it
+    #[serde(default)]
+    pub session_secs: Option<u64>,
+}
+
+/// Reply to POST /api/rooms/{id}/guest-passes; viewers join with `data.guest_token`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct GuestPass {
+    pub token: String,
+    /// When the session ends, however often the viewer reconnects (RFC 3339)
+    pub expires_at: String,
+}
+
+/// Reply to DELETE /api/rooms/{id}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomDeleted {
+    pub room_id: String,
+    pub closed_connections: usize,
+}
+
+/// Reply to GET /api/turn-credentials
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TurnCredentials {
+    pub username: String,
+    pub password: String,
+    /// Seconds the credentials stay valid
+    pub ttl: u64,
+    pub uris: Vec<String>,
+}
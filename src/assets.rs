@@ -0,0 +1,153 @@
+// assets.rs
+// The web pages, served from the static/ directory on disk or, when built with the
+// `embed-assets` feature and configured to, from a copy compiled into the binary so the server
+// ships as a single file. With the SPA fallback on, GET requests for unknown extension-less
+// paths outside /api and /ws get index.html, so client-side routes survive a reload. Every page
+// carries an ETag and is compressed for clients that accept it (see http_cache.rs).
+
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use warp::path::Tail;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::http_cache;
+
+/// Path prefixes that belong to the server, never to the client-side router
+const SERVER_PREFIXES: &[&str] = &["api/", "ws/", "metrics"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaticConfig {
+    /// Directory served when the embedded copy isn't used
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Serve the copy of static/ compiled into the binary (needs the `embed-assets` build feature)
+    #[serde(default)]
+    pub embedded: bool,
+    /// Answer unknown page paths with index.html
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// How long browsers may reuse files other than HTML without revalidating
+    #[serde(default = "default_cache_max_age_secs")]
+    pub cache_max_age_secs: u64,
+}
+
+fn default_directory() -> String { "static".to_string() }
+fn default_cache_max_age_secs() -> u64 { 300 }
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_directory(),
+            embedded: false,
+            spa_fallback: false,
+            cache_max_age_secs: default_cache_max_age_secs(),
+        }
+    }
+}
+
+impl StaticConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.embedded && !cfg!(feature = "embed-assets") {
+            return Err("embedded needs a build with the embed-assets feature".to_string());
+        }
+        if !self.embedded && self.directory.is_empty() {
+            return Err("directory must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// GET routes for the pages, to be tried after every API route
+pub fn filter(config: &StaticConfig) -> BoxedFilter<(Response,)> {
+    http_cache::revalidated(http_cache::compressed(pages(config)), config.cache_max_age_secs)
+}
+
+fn pages(config: &StaticConfig) -> BoxedFilter<(Response,)> {
+    let files = if config.embedded {
+        warp::get()
+            .and(warp::path::tail())
+            .and_then(|tail: Tail| async move {
+                let path = match tail.as_str() {
+                    "" => "index.html".to_string(),
+                    path if path.ends_with('/') => format!("{}index.html", path),
+                    path => path.to_string(),
+                };
+                embedded::get(&path)
+                    .map(|(body, content_type)| with_content_type(body, &content_type))
+                    .ok_or_else(warp::reject::not_found)
+            })
+            .boxed()
+    } else {
+        warp::fs::dir(config.directory.clone()).map(|file: warp::fs::File| with_file_etag(file.into_response())).boxed()
+    };
+    if !config.spa_fallback {
+        return files;
+    }
+
+    let config = config.clone();
+    let fallback = warp::get()
+        .and(warp::path::tail())
+        .and_then(move |tail: Tail| spa_fallback(tail, config.clone()));
+    files.or(fallback).unify().boxed()
+}
+
+async fn spa_fallback(tail: Tail, config: StaticConfig) -> Result<Response, Rejection> {
+    let path = tail.as_str();
+    let last_segment = path.rsplit('/').next().unwrap_or_default();
+    if SERVER_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) || last_segment.contains('.') {
+        return Err(warp::reject::not_found());
+    }
+    let index = if config.embedded {
+        embedded::get("index.html").map(|(body, _)| body)
+    } else {
+        tokio::fs::read(Path::new(&config.directory).join("index.html")).await.ok()
+    };
+    index
+        .map(|body| with_content_type(body, "text/html; charset=utf-8"))
+        .ok_or_else(warp::reject::not_found)
+}
+
+fn with_content_type(body: Vec<u8>, content_type: &str) -> Response {
+    let etag = http_cache::etag_for(&body);
+    let response = warp::reply::with_header(body, CONTENT_TYPE, content_type);
+    warp::reply::with_header(response, ETAG, etag).into_response()
+}
+
+/// Files on disk are streamed, so their ETag comes from modification time and size
+fn with_file_etag(mut response: Response) -> Response {
+    let headers = response.headers();
+    let identity = [LAST_MODIFIED, CONTENT_LENGTH]
+        .iter()
+        .map(|name| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("/");
+    if let Ok(etag) = HeaderValue::from_str(&http_cache::etag_for(identity.as_bytes())) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    #[derive(rust_embed::RustEmbed)]
+    #[folder = "static/"]
+    struct Assets;
+
+    /// File contents and content type
+    pub fn get(path: &str) -> Option<(Vec<u8>, String)> {
+        let file = Assets::get(path)?;
+        let content_type = mime_guess::from_path(path).first_or_octet_stream().to_string();
+        Some((file.data.into_owned(), content_type))
+    }
+}
+
+#[cfg(not(feature = "embed-assets"))]
+mod embedded {
+    /// Never called: config validation rejects `embedded` in builds without the feature
+    pub fn get(_path: &str) -> Option<(Vec<u8>, String)> {
+        None
+    }
+}
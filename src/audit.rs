@@ -0,0 +1,66 @@
+// audit.rs
+// Audit trail of administrative and security-relevant actions: rooms created and deleted,
+// connections the server closes, rejected admin tokens and room credentials, config reloads
+// and WASM plugin changes. Each entry records who acted (admin, a connection ID, the server),
+// from which IP and when; entries are written to the audit table off the async runtime and
+// listed by GET /api/admin/audit.
+
+use std::net::IpAddr;
+use serde_json::Value;
+use tracing::{info, warn};
+use warp::http::StatusCode;
+
+use crate::persistence;
+use crate::state::{SharedState, State};
+
+/// Largest page GET /api/admin/audit returns
+pub const MAX_LIST_LIMIT: u32 = 1000;
+
+pub const ROOM_CREATED: &str = "room_created";
+pub const ROOM_DELETED: &str = "room_deleted";
+pub const CONNECTION_CLOSED: &str = "connection_closed";
+pub const AUTH_FAILED: &str = "auth_failed";
+pub const CONFIG_RELOADED: &str = "config_reloaded";
+pub const CONFIG_RELOAD_REJECTED: &str = "config_reload_rejected";
+pub const PLUGIN_INSTALLED: &str = "plugin_installed";
+pub const PLUGIN_REMOVED: &str = "plugin_removed";
+
+/// Actor for actions the server takes on its own
+pub const SERVER: &str = "server";
+
+pub fn record(state: &State, action: &str, actor: &str, ip: Option<IpAddr>, room_id: Option<&str>, detail: Value) {
+    info!(action, actor, ip = ?ip, room_id, %detail, "audit");
+    let db_path = state.db_path();
+    let (action, actor, room_id) = (action.to_string(), actor.to_string(), room_id.map(str::to_string));
+    let ip = ip.map(|ip| ip.to_string());
+    tokio::task::spawn_blocking(move || {
+        let saved = persistence::save_audit(
+            &db_path,
+            &action,
+            &actor,
+            ip.as_deref(),
+            room_id.as_deref(),
+            &detail,
+        );
+        if let Err(e) = saved {
+            warn!("Failed to save audit entry {}: {}", action, e);
+        }
+    });
+}
+
+/// Access log hook for the HTTP API: every 401/403 is recorded as a failed authentication
+pub fn http_auth_failures(state: SharedState) -> impl Fn(warp::log::Info) + Clone + Send + Sync + 'static {
+    move |log| {
+        if log.status() != StatusCode::UNAUTHORIZED && log.status() != StatusCode::FORBIDDEN {
+            return;
+        }
+        record(
+            &state,
+            AUTH_FAILED,
+            "anonymous",
+            state.proxies.client_ip(log.remote_addr().map(|addr| addr.ip()), log.request_headers()),
+            None,
+            serde_json::json!({"method": log.method().as_str(), "path": log.path(), "status": log.status().as_u16()}),
+        );
+    }
+}
@@ -0,0 +1,114 @@
+// auth.rs
+// Optional authentication of room creators and senders by an identity provider. A provider turns
+// a bearer token into an Identity (subject and permissions mapped from its claims); when one is
+// configured, POST/PUT /api/rooms need an `Authorization: Bearer <token>` with create_rooms and
+// sender joins need an `id_token` with send in the join data, on top of the room's sender token.
+// Without a provider nothing changes. OpenID Connect (oidc.rs) is the only provider so far.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use warp::http::StatusCode;
+
+use crate::oidc::{OidcConfig, OidcProvider};
+use crate::state::State;
+
+/// What a token's roles allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// POST/PUT /api/rooms
+    CreateRooms,
+    /// Joining a room as its sender
+    Send,
+}
+
+impl Permission {
+    fn denied_message(self) -> &'static str {
+        match self {
+            Self::CreateRooms => "Not allowed to create rooms",
+            Self::Send => "Not allowed to join as sender",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// The token's subject; recorded as the actor in the audit log
+    pub subject: String,
+    pub permissions: HashSet<Permission>,
+    /// Tenant the caller's rooms are created in (tenant.rs); None for rooms outside any tenant
+    pub tenant: Option<String>,
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Verifies `token` and maps its claims, or explains why it was refused
+    async fn authenticate(&self, token: &str) -> Result<Identity, String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum AuthConfig {
+    Oidc(OidcConfig),
+}
+
+impl AuthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Oidc(config) => config.validate(),
+        }
+    }
+}
+
+/// Starts the configured provider
+pub async fn start(config: &AuthConfig) -> Arc<dyn AuthProvider> {
+    match config {
+        AuthConfig::Oidc(config) => Arc::new(OidcProvider::start(config.clone()).await),
+    }
+}
+
+/// Why a token was not accepted, with the HTTP status to answer with
+#[derive(Debug, Clone)]
+pub struct Refusal {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+/// Checks `token` for `permission` and returns the token's identity, or None when the server has
+/// no provider and every caller is let through
+pub async fn authorize(state: &State, token: Option<&str>, permission: Permission) -> Result<Option<Identity>, Refusal> {
+    let provider = match state.auth() {
+        Some(provider) => provider,
+        None => return Ok(None),
+    };
+    let token = token.ok_or_else(|| Refusal {
+        status: StatusCode::UNAUTHORIZED,
+        message: "ID token required".to_string(),
+    })?;
+    let identity = provider.authenticate(token).await.map_err(|e| Refusal {
+        status: StatusCode::UNAUTHORIZED,
+        message: format!("Invalid ID token: {}", e),
+    })?;
+    if !identity.permissions.contains(&permission) {
+        return Err(Refusal {
+            status: StatusCode::FORBIDDEN,
+            message: permission.denied_message().to_string(),
+        });
+    }
+    Ok(Some(identity))
+}
+
+/// The token of an `Authorization: Bearer <token>` header
+pub fn bearer(authorization: Option<&str>) -> Option<&str> {
+    authorization.and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Compares a presented secret (token, API key, MAC) with the expected one in constant time, so
+/// response timing doesn't tell a guesser how many leading bytes were right. Every secret check
+/// goes through here.
+pub fn secret_eq(given: impl AsRef<[u8]>, expected: impl AsRef<[u8]>) -> bool {
+    given.as_ref().ct_eq(expected.as_ref()).into()
+}
@@ -0,0 +1,370 @@
+// backplane.rs
+// Optional Redis backplane so one room can span several server instances behind a load balancer.
+// Rooms and their members are stored in Redis hashes and membership changes are published to
+// every instance, which mirrors remote members into its own RoomManager; everything else a client
+// sends is handled only by the instance it is connected to. Responses for connections held by
+// another instance are published to that instance's channel and delivered there. Instances
+// refresh a heartbeat key; members of an instance whose key expired are dropped by the others.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::StreamExt;
+use tracing::{info, warn, error};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::Clients;
+use crate::room::{RoomManager, RoomMode, RoomSettings};
+use crate::signaling::SignalingMessage;
+use crate::state::State;
+
+/// How often the heartbeat is refreshed and dead instances are looked for
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Pause before resubscribing after the pub/sub connection dropped
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackplaneConfig {
+    /// e.g. `redis://10.0.0.5:6379/0`
+    pub redis_url: String,
+    /// Prepended to every key and channel, so several deployments can share one Redis
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Must differ between instances; a random ID is used when unset
+    #[serde(default)]
+    pub instance_id: Option<String>,
+    /// An instance whose heartbeat is older than this is considered gone
+    #[serde(default = "default_instance_ttl_secs")]
+    pub instance_ttl_secs: u64,
+}
+
+fn default_prefix() -> String { "ws2infer".to_string() }
+fn default_instance_ttl_secs() -> u64 { 15 }
+
+impl BackplaneConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.redis_url.starts_with("redis://") && !self.redis_url.starts_with("rediss://") {
+            return Err("redis_url must start with redis:// or rediss://".to_string());
+        }
+        if self.prefix.is_empty() {
+            return Err("prefix must not be empty".to_string());
+        }
+        if matches!(&self.instance_id, Some(id) if id.is_empty()) {
+            return Err("instance_id must not be empty when set".to_string());
+        }
+        if self.instance_ttl_secs < 2 * HEARTBEAT_INTERVAL.as_secs() {
+            return Err(format!("instance_ttl_secs must be at least {}", 2 * HEARTBEAT_INTERVAL.as_secs()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Event {
+    /// A member joined on `instance`; `message` is its Join
+    Join { instance: String, room_id: String, message: SignalingMessage },
+    Leave { instance: String, room_id: String, connection_id: String },
+    /// A response for a connection of the receiving instance
+    Deliver { message: SignalingMessage },
+}
+
+pub struct Backplane {
+    instance_id: String,
+    prefix: String,
+    instance_ttl_secs: u64,
+    redis: ConnectionManager,
+    room_manager: Arc<RwLock<RoomManager>>,
+    clients: Clients,
+    /// Remote members mirrored locally: connection_id -> (instance, room_id)
+    remote: Mutex<HashMap<String, (String, String)>>,
+}
+
+impl Backplane {
+    /// Connects, registers the backplane in the server state for route_responses and starts the
+    /// subscriber and heartbeat tasks
+    pub async fn start(config: &BackplaneConfig, state: &State, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) -> Result<(), String> {
+        let client = redis::Client::open(config.redis_url.as_str()).map_err(|e| e.to_string())?;
+        let redis = ConnectionManager::new(client.clone()).await.map_err(|e| e.to_string())?;
+        let instance_id = config.instance_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+        let backplane = Arc::new(Self {
+            instance_id,
+            prefix: config.prefix.clone(),
+            instance_ttl_secs: config.instance_ttl_secs,
+            redis,
+            room_manager,
+            clients,
+            remote: Mutex::new(HashMap::new()),
+        });
+        backplane.heartbeat().await?;
+        if state.backplane.set(backplane.clone()).is_err() {
+            return Err("backplane already started".to_string());
+        }
+        info!("Joined Redis backplane at {} as instance {}", config.redis_url, backplane.instance_id);
+
+        let subscriber = backplane.clone();
+        tokio::task::spawn(async move {
+            loop {
+                if let Err(e) = subscriber.subscribe(&client).await {
+                    warn!("Redis backplane subscription failed: {}", e);
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        let heartbeat = backplane.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = heartbeat.heartbeat().await {
+                    warn!("Redis backplane heartbeat failed: {}", e);
+                }
+                heartbeat.drop_dead_instances().await;
+            }
+        });
+        Ok(())
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    fn events_channel(&self) -> String {
+        self.key("events")
+    }
+
+    fn instance_channel(&self, instance: &str) -> String {
+        self.key(&format!("instance:{}", instance))
+    }
+
+    fn alive_key(&self, instance: &str) -> String {
+        self.key(&format!("alive:{}", instance))
+    }
+
+    fn members_key(&self, room_id: &str) -> String {
+        self.key(&format!("room:{}", room_id))
+    }
+
+    async fn heartbeat(&self) -> Result<(), String> {
+        let mut redis = self.redis.clone();
+        redis.set_ex::<_, _, ()>(self.alive_key(&self.instance_id), 1, self.instance_ttl_secs as usize)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn publish(&self, channel: String, event: &Event) {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        let mut redis = self.redis.clone();
+        if let Err(e) = redis.publish::<_, _, ()>(&channel, payload).await {
+            warn!("Redis backplane publish to {} failed: {}", channel, e);
+        }
+    }
+
+    /// Makes a room created on this instance known to the others, with the settings every
+    /// instance needs to handle joins the same way
+    pub async fn announce_room(&self, room_id: &str, settings: &RoomSettings) {
+        let settings = serde_json::to_string(settings).unwrap_or_default();
+        let mut redis = self.redis.clone();
+        if let Err(e) = redis.hset::<_, _, _, ()>(self.key("rooms"), room_id, settings).await {
+            warn!("Redis backplane could not announce room {}: {}", room_id, e);
+        }
+    }
+
+    /// Withdraws a deleted room so other instances don't recreate it on the next join
+    pub async fn forget_room(&self, room_id: &str) {
+        let mut redis = self.redis.clone();
+        let _ = redis.hdel::<_, _, ()>(self.key("rooms"), room_id).await;
+        let _ = redis.del::<_, ()>(self.members_key(room_id)).await;
+    }
+
+    /// Before a local join or room creation: creates the room if another instance announced it,
+    /// and mirrors the members of live instances this one hasn't heard about (e.g. after it started)
+    pub async fn prepare_join(&self, room_id: &str) {
+        let mut redis = self.redis.clone();
+        if !self.room_manager.read().await.rooms.contains_key(room_id) {
+            let settings: Option<String> = redis.hget(self.key("rooms"), room_id).await.unwrap_or(None);
+            let settings = match settings.and_then(|s| serde_json::from_str::<RoomSettings>(&s).ok()) {
+                Some(settings) => settings,
+                None => return,
+            };
+            let mut manager = self.room_manager.write().await;
+            if manager.create_room_if_absent(room_id.to_string()) {
+                if let Some(room) = manager.rooms.get_mut(room_id) {
+                    room.apply_settings(&settings);
+                    if settings.mode == RoomMode::Sfu {
+                        room.sfu_connection_id = Some(format!("sfu-{}", room_id));
+                    }
+                }
+            }
+        }
+
+        let members: HashMap<String, String> = match redis.hgetall(self.members_key(room_id)).await {
+            Ok(members) => members,
+            Err(e) => {
+                warn!("Redis backplane could not load members of room {}: {}", room_id, e);
+                return;
+            }
+        };
+        let mut alive = HashMap::new();
+        for (connection_id, event) in members {
+            if let Ok(Event::Join { instance, room_id, message }) = serde_json::from_str::<Event>(&event) {
+                if instance == self.instance_id || self.remote.lock().unwrap().contains_key(&connection_id) {
+                    continue;
+                }
+                let instance_alive = match alive.get(&instance) {
+                    Some(instance_alive) => *instance_alive,
+                    None => {
+                        let instance_alive: bool = redis.exists(self.alive_key(&instance)).await.unwrap_or(false);
+                        alive.insert(instance.clone(), instance_alive);
+                        instance_alive
+                    }
+                };
+                if instance_alive {
+                    self.mirror_join(instance, room_id, message).await;
+                }
+            }
+        }
+    }
+
+    /// After a local member joined `room_id` with `message`
+    pub async fn joined(&self, room_id: &str, message: &SignalingMessage) {
+        let connection_id = match &message.connection_id {
+            Some(connection_id) => connection_id.clone(),
+            None => return,
+        };
+        // The admin token and ID token stay out of Redis; other instances only need the member itself
+        let mut message = message.clone();
+        if let Some(data) = message.data.as_mut().and_then(|data| data.as_object_mut()) {
+            data.remove("admin_token");
+            data.remove("id_token");
+        }
+        let event = Event::Join {
+            instance: self.instance_id.clone(),
+            room_id: room_id.to_string(),
+            message,
+        };
+        let mut redis = self.redis.clone();
+        let stored = serde_json::to_string(&event).unwrap_or_default();
+        if let Err(e) = redis.hset::<_, _, _, ()>(self.members_key(room_id), &connection_id, stored).await {
+            warn!("Redis backplane could not store member {}: {}", connection_id, e);
+        }
+        self.publish(self.events_channel(), &event).await;
+    }
+
+    /// After a local member left `room_id`
+    pub async fn left(&self, room_id: &str, connection_id: &str) {
+        let mut redis = self.redis.clone();
+        let _ = redis.hdel::<_, _, ()>(self.members_key(room_id), connection_id).await;
+        let event = Event::Leave {
+            instance: self.instance_id.clone(),
+            room_id: room_id.to_string(),
+            connection_id: connection_id.to_string(),
+        };
+        self.publish(self.events_channel(), &event).await;
+    }
+
+    /// Sends responses whose target isn't connected here to the instance holding it; targets
+    /// unknown to the backplane have disconnected
+    pub async fn forward(&self, responses: Vec<SignalingMessage>) {
+        for message in responses {
+            let instance = message.connection_id.as_ref()
+                .and_then(|id| self.remote.lock().unwrap().get(id).map(|(instance, _)| instance.clone()));
+            if let Some(instance) = instance {
+                self.publish(self.instance_channel(&instance), &Event::Deliver { message }).await;
+            }
+        }
+    }
+
+    async fn subscribe(&self, client: &redis::Client) -> redis::RedisResult<()> {
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(self.events_channel()).await?;
+        pubsub.subscribe(self.instance_channel(&self.instance_id)).await?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: String = message.get_payload()?;
+            match serde_json::from_str::<Event>(&payload) {
+                Ok(event) => self.handle(event).await,
+                Err(e) => warn!("Ignoring malformed backplane event: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, event: Event) {
+        match event {
+            Event::Join { instance, room_id, message } if instance != self.instance_id => {
+                self.mirror_join(instance, room_id, message).await;
+            }
+            Event::Leave { instance, room_id, connection_id } if instance != self.instance_id => {
+                self.remote.lock().unwrap().remove(&connection_id);
+                // The joining instance notified everyone already
+                self.room_manager.write().await.remove_connection(&room_id, &connection_id);
+            }
+            Event::Deliver { message } => {
+                crate::route_local(&self.clients, vec![message]).await;
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a remote member's Join locally. Responses are dropped: the member's own instance
+    /// sent room_info and new_peer to everyone, and checks its guest pass and handles its expiry.
+    async fn mirror_join(&self, instance: String, room_id: String, message: SignalingMessage) {
+        let connection_id = match &message.connection_id {
+            Some(connection_id) => connection_id.clone(),
+            None => return,
+        };
+        self.remote.lock().unwrap().insert(connection_id.clone(), (instance, room_id.clone()));
+
+        let mut manager = self.room_manager.write().await;
+        manager.handle_server_join(room_id.clone(), message);
+        if let Some(info) = manager.rooms.get_mut(&room_id).and_then(|room| room.connections.get_mut(&connection_id)) {
+            info.expires_at = None;
+        }
+    }
+
+    /// Drops the mirrored members of instances whose heartbeat expired; every instance tells
+    /// its own connections about them
+    async fn drop_dead_instances(&self) {
+        let instances: HashSet<String> = self.remote.lock().unwrap().values().map(|(instance, _)| instance.clone()).collect();
+        let mut redis = self.redis.clone();
+        for instance in instances {
+            match redis.exists::<_, bool>(self.alive_key(&instance)).await {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(e) => {
+                    warn!("Redis backplane could not check instance {}: {}", instance, e);
+                    return;
+                }
+            }
+            error!("Backplane instance {} stopped sending heartbeats; dropping its connections", instance);
+
+            let members: Vec<(String, String)> = {
+                let mut remote = self.remote.lock().unwrap();
+                let members: Vec<(String, String)> = remote.iter()
+                    .filter(|(_, (owner, _))| *owner == instance)
+                    .map(|(connection_id, (_, room_id))| (connection_id.clone(), room_id.clone()))
+                    .collect();
+                for (connection_id, _) in &members {
+                    remote.remove(connection_id);
+                }
+                members
+            };
+            for (connection_id, room_id) in members {
+                let _ = redis.hdel::<_, _, ()>(self.members_key(&room_id), &connection_id).await;
+                let responses = self.room_manager.write().await.remove_connection(&room_id, &connection_id);
+                if let Some(responses) = responses {
+                    crate::route_local(&self.clients, responses).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// loadtest.rs
+// Load generator for the signaling path. Creates rooms over the REST API, then joins one
+// simulated sender and many viewers to each over WebSocket: senders offer to every viewer that
+// joins and re-offer periodically, viewers answer and keep posting inference results. No media is
+// sent; only the server's room handling is exercised. At the end, latency percentiles are printed
+// for joins (join sent -> room_info), offers (offer sent -> answer back through the server) and
+// inference fan-out (result timestamp -> inference_update at each viewer, millisecond resolution).
+//
+//   cargo run --release --features loadtest --bin loadtest -- http://127.0.0.1:8080 \
+//       [--rooms 10] [--viewers 100] [--duration 30] [--offer-interval 1000] \
+//       [--inference-interval 200] [--ramp 5] [--bearer <ID token>]
+//
+// Every inference result is persisted by the server, so point it at a scratch data/ directory.
+// Thousands of connections need a raised open file limit (ulimit -n) on both ends.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use ws2infer_protocol::{SignalingClient, SignalingMessage, SignalingMessageType};
+
+/// Minimal description that passes the server's SDP checks; the load test never sends media
+const OFFER_SDP: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\na=sendonly\r\na=rtpmap:96 VP8/90000\r\n";
+const ANSWER_SDP: &str = "v=0\r\no=- 2 1 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\na=recvonly\r\na=rtpmap:96 VP8/90000\r\n";
+
+struct Options {
+    server_url: String,
+    rooms: usize,
+    viewers: usize,
+    duration: Duration,
+    offer_interval: Duration,
+    inference_interval: Duration,
+    /// Time over which each room's viewers are joined, so joins don't all land at once
+    ramp: Duration,
+    bearer: Option<String>,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut positional = Vec::new();
+    let mut rooms = 10;
+    let mut viewers = 100;
+    let mut duration = 30;
+    let mut offer_interval = 1000;
+    let mut inference_interval = 200;
+    let mut ramp = 5;
+    let mut bearer = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rooms" => rooms = number(&mut args, &arg)? as usize,
+            "--viewers" => viewers = number(&mut args, &arg)? as usize,
+            "--duration" => duration = number(&mut args, &arg)?,
+            "--offer-interval" => offer_interval = number(&mut args, &arg)?,
+            "--inference-interval" => inference_interval = number(&mut args, &arg)?,
+            "--ramp" => ramp = number(&mut args, &arg)?,
+            "--bearer" => bearer = Some(args.next().ok_or_else(|| anyhow!("--bearer needs a token"))?),
+            _ => positional.push(arg),
+        }
+    }
+
+    let server_url = positional.first().cloned().unwrap_or_else(|| "http://127.0.0.1:8080".to_string());
+    Ok(Options {
+        server_url: server_url.trim_end_matches('/').to_string(),
+        rooms,
+        viewers,
+        duration: Duration::from_secs(duration),
+        offer_interval: Duration::from_millis(offer_interval.max(1)),
+        inference_interval: Duration::from_millis(inference_interval.max(1)),
+        ramp: Duration::from_secs(ramp),
+        bearer,
+    })
+}
+
+fn number(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<u64> {
+    args.next()
+        .ok_or_else(|| anyhow!("{} needs a number", flag))?
+        .parse()
+        .with_context(|| format!("{} needs a number", flag))
+}
+
+/// ws:// or wss:// for the server's http:// or https:// URL
+fn websocket_url(server_url: &str) -> String {
+    match server_url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some((_, rest)) => format!("ws://{}", rest),
+        None => format!("ws://{}", server_url),
+    }
+}
+
+/// Latency samples of one kind, in microseconds
+#[derive(Default)]
+struct Samples(Mutex<Vec<u64>>);
+
+impl Samples {
+    fn record(&self, latency: Duration) {
+        self.0.lock().unwrap().push(latency.as_micros() as u64);
+    }
+
+    fn report(&self, name: &str) {
+        let mut samples = self.0.lock().unwrap().clone();
+        if samples.is_empty() {
+            println!("{:<10} no samples", name);
+            return;
+        }
+        samples.sort_unstable();
+        let percentile = |p: f64| {
+            let index = ((samples.len() as f64 * p).ceil() as usize).clamp(1, samples.len()) - 1;
+            samples[index] as f64 / 1000.0
+        };
+        println!(
+            "{:<10} n={:<8} p50={:>8.2}ms p90={:>8.2}ms p99={:>8.2}ms max={:>8.2}ms",
+            name,
+            samples.len(),
+            percentile(0.50),
+            percentile(0.90),
+            percentile(0.99),
+            *samples.last().unwrap() as f64 / 1000.0,
+        );
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    join: Samples,
+    offer: Samples,
+    inference: Samples,
+    failed_clients: Mutex<u64>,
+    server_errors: Mutex<HashMap<String, u64>>,
+}
+
+impl Stats {
+    fn server_error(&self, message: &SignalingMessage) {
+        let error = message.data.as_ref()
+            .and_then(|data| data.get("error"))
+            .and_then(Value::as_str)
+            .unwrap_or("unknown")
+            .to_string();
+        *self.server_errors.lock().unwrap().entry(error).or_default() += 1;
+    }
+}
+
+struct CreatedRoom {
+    room_id: String,
+    sender_token: Option<String>,
+}
+
+async fn create_room(http: &reqwest::Client, options: &Options) -> Result<CreatedRoom> {
+    let mut request = http.post(format!("{}/api/rooms", options.server_url)).json(&serde_json::json!({}));
+    if let Some(token) = &options.bearer {
+        request = request.bearer_auth(token);
+    }
+    let response: Value = request.send().await?.error_for_status()?.json().await?;
+    let room_id = response.get("room_id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("room creation returned no room_id: {}", response))?
+        .to_string();
+    let sender_token = response.get("sender_token").and_then(Value::as_str).map(str::to_string);
+    Ok(CreatedRoom { room_id, sender_token })
+}
+
+fn unix_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn offer_description() -> Value {
+    serde_json::json!({"type": "offer", "sdp": OFFER_SDP})
+}
+
+/// Joins as the room's sender, offers to each viewer as it arrives and again every
+/// offer_interval, and times each answer
+async fn run_sender(ws_url: String, room: Arc<CreatedRoom>, options: Arc<Options>, stats: Arc<Stats>, deadline: Instant) -> Result<()> {
+    let mut client = SignalingClient::connect(&ws_url, &room.room_id).await?;
+    let joined_at = Instant::now();
+    // With an identity provider configured, sender joins need the same token as room creation
+    let join_data = serde_json::json!({"sender_token": room.sender_token, "id_token": options.bearer});
+    client.join(true, Some(join_data)).await?;
+
+    let mut viewers: HashMap<String, Option<Instant>> = HashMap::new();
+    let mut reoffer = tokio::time::interval(options.offer_interval);
+    let mut joined = false;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline.into()) => break,
+            _ = reoffer.tick() => {
+                for (viewer, pending) in viewers.iter_mut() {
+                    // One offer in flight per viewer, like a real sender
+                    if pending.is_none() {
+                        *pending = Some(Instant::now());
+                        client.offer(viewer, offer_description()).await?;
+                    }
+                }
+            }
+            message = client.next_message() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => return Err(anyhow!("sender connection closed")),
+                };
+                match message.message_type {
+                    SignalingMessageType::RoomInfo if !joined => {
+                        joined = true;
+                        stats.join.record(joined_at.elapsed());
+                    }
+                    SignalingMessageType::NewPeer => {
+                        let data = message.data.unwrap_or_default();
+                        let is_sender = data.get("is_sender").and_then(Value::as_bool).unwrap_or(false);
+                        if let (Some(viewer), false) = (data.get("connection_id").and_then(Value::as_str), is_sender) {
+                            viewers.insert(viewer.to_string(), Some(Instant::now()));
+                            client.offer(viewer, offer_description()).await?;
+                        }
+                    }
+                    SignalingMessageType::Answer => {
+                        let sent_at = message.sender_id.as_ref().and_then(|viewer| viewers.get_mut(viewer)).and_then(Option::take);
+                        if let Some(sent_at) = sent_at {
+                            stats.offer.record(sent_at.elapsed());
+                        }
+                    }
+                    SignalingMessageType::Leave => {
+                        if let Some(viewer) = message.data.as_ref().and_then(|data| data.get("connection_id")).and_then(Value::as_str) {
+                            viewers.remove(viewer);
+                        }
+                    }
+                    SignalingMessageType::Error => stats.server_error(&message),
+                    _ => {}
+                }
+            }
+        }
+    }
+    client.close();
+    Ok(())
+}
+
+/// Joins as a viewer, answers every offer and posts an inference result about the sender every
+/// inference_interval, timing the inference_update fan-out
+async fn run_viewer(ws_url: String, room: Arc<CreatedRoom>, options: Arc<Options>, stats: Arc<Stats>, deadline: Instant) -> Result<()> {
+    let mut client = SignalingClient::connect(&ws_url, &room.room_id).await?;
+    let joined_at = Instant::now();
+    client.join(false, None).await?;
+
+    let mut sender_id: Option<String> = None;
+    let mut report = tokio::time::interval(options.inference_interval);
+    let mut joined = false;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline.into()) => break,
+            _ = report.tick() => {
+                if let Some(sender_id) = &sender_id {
+                    let data = serde_json::json!({
+                        "timestamp": unix_millis(),
+                        "model": "loadtest",
+                        "predictions": [{"class": "person", "score": 0.9, "bbox": [10.0, 20.0, 100.0, 200.0]}],
+                    });
+                    client.inference_result(sender_id, data).await?;
+                }
+            }
+            message = client.next_message() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => return Err(anyhow!("viewer connection closed")),
+                };
+                match message.message_type {
+                    SignalingMessageType::RoomInfo if !joined => {
+                        joined = true;
+                        stats.join.record(joined_at.elapsed());
+                    }
+                    SignalingMessageType::Offer => {
+                        if let Some(from) = message.sender_id {
+                            client.answer(&from, serde_json::json!({"type": "answer", "sdp": ANSWER_SDP})).await?;
+                            sender_id = Some(from);
+                        }
+                    }
+                    SignalingMessageType::InferenceUpdate => {
+                        let timestamp = message.data.as_ref()
+                            .and_then(|data| data.pointer("/latest/timestamp"))
+                            .and_then(Value::as_i64);
+                        if let Some(timestamp) = timestamp {
+                            let elapsed = (unix_millis() - timestamp).max(0) as u64;
+                            stats.inference.record(Duration::from_millis(elapsed));
+                        }
+                    }
+                    SignalingMessageType::Error => stats.server_error(&message),
+                    _ => {}
+                }
+            }
+        }
+    }
+    client.close();
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let options = Arc::new(parse_args()?);
+    let ws_url = websocket_url(&options.server_url);
+    let http = reqwest::Client::new();
+    let stats = Arc::new(Stats::default());
+
+    let mut rooms = Vec::with_capacity(options.rooms);
+    for _ in 0..options.rooms {
+        rooms.push(Arc::new(create_room(&http, &options).await.context("creating a room")?));
+    }
+    println!(
+        "{} rooms x (1 sender + {} viewers) against {} for {}s",
+        options.rooms,
+        options.viewers,
+        options.server_url,
+        options.duration.as_secs()
+    );
+
+    let started = Instant::now();
+    let deadline = started + options.ramp + options.duration;
+    let mut tasks = Vec::new();
+    for room in &rooms {
+        tasks.push(tokio::spawn(run_sender(ws_url.clone(), room.clone(), options.clone(), stats.clone(), deadline)));
+    }
+    for index in 0..options.viewers {
+        // Spread each room's viewers evenly over the ramp
+        let delay = options.ramp.mul_f64(index as f64 / options.viewers as f64);
+        for room in &rooms {
+            let (ws_url, room, options, stats) = (ws_url.clone(), room.clone(), options.clone(), stats.clone());
+            tasks.push(tokio::spawn(async move {
+                tokio::time::sleep_until((started + delay).into()).await;
+                run_viewer(ws_url, room, options, stats, deadline).await
+            }));
+        }
+    }
+
+    for task in tasks {
+        match task.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                *stats.failed_clients.lock().unwrap() += 1;
+                eprintln!("client failed: {}", e);
+            }
+            Err(e) => eprintln!("client task panicked: {}", e),
+        }
+    }
+
+    println!("finished in {:.1}s", started.elapsed().as_secs_f64());
+    stats.join.report("join");
+    stats.offer.report("offer");
+    stats.inference.report("inference");
+    println!("failed clients: {}", stats.failed_clients.lock().unwrap());
+    for (error, count) in stats.server_errors.lock().unwrap().iter() {
+        println!("server error x{}: {}", count, error);
+    }
+    Ok(())
+}
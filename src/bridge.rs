@@ -0,0 +1,705 @@
+// bridge.rs
+// Outbound federation: this server joins a room of another ws2infer instance as a client, so
+// the feed of an edge box can be watched on a central server (mode "pull") or a local feed be
+// shown on another instance (mode "push"). Only signaling is relayed. Every viewer on the far
+// side gets a connection of its own on the sender's side: a remote viewer connection per local
+// viewer when pulling, a LocalPeer viewer per remote viewer when pushing. The sender therefore
+// negotiates with each viewer as usual and media flows directly between them (through TURN
+// when they can't reach each other). Inference results about the bridged stream are relayed
+// both ways; results the bridge brought in are recognised by their timestamp and not sent
+// back. Bridges come from config.json `bridges`, start with the server and reconnect after
+// `reconnect_secs` when either side goes away.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+use ws2infer_protocol::SignalingClient;
+
+use crate::Clients;
+use crate::local_peer::LocalPeer;
+use crate::room::RoomManager;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::state::SharedState;
+
+/// How long connecting to the remote instance may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Timestamps of relayed inference results remembered per direction
+const RELAYED_KEPT: usize = 64;
+
+fn default_reconnect_secs() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeMode {
+    /// Watch the remote room's sender here: the bridge is a viewer there and the sender of
+    /// local_room
+    Pull,
+    /// Show local_room's sender in the remote room: the bridge is the sender there and a viewer
+    /// here
+    Push,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    /// Room on this server; created when it doesn't exist
+    pub local_room: String,
+    /// Base URL of the other instance, e.g. `wss://central.example.com`
+    pub remote_url: String,
+    pub remote_room: String,
+    pub mode: BridgeMode,
+    /// Extra Join data for the remote room, e.g. `{"sender_token": "..."}` to push into a room
+    /// that has one
+    #[serde(default)]
+    pub join_data: Option<Value>,
+    /// Wait before reconnecting after either side went away
+    #[serde(default = "default_reconnect_secs")]
+    pub reconnect_secs: u64,
+}
+
+impl BridgeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !crate::tenant::is_valid_room_key(&self.local_room) {
+            return Err(format!("invalid local_room {}", self.local_room));
+        }
+        if !crate::tenant::is_valid_room_key(&self.remote_room) {
+            return Err(format!("invalid remote_room {}", self.remote_room));
+        }
+        if !self.remote_url.starts_with("ws://") && !self.remote_url.starts_with("wss://") {
+            return Err(format!("'{}' is not a ws:// or wss:// URL", self.remote_url));
+        }
+        if self.join_data.as_ref().is_some_and(|data| !data.is_object()) {
+            return Err("join_data must be an object".to_string());
+        }
+        if self.reconnect_secs == 0 {
+            return Err("reconnect_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Starts a bridge; it reconnects for as long as the server runs
+pub fn spawn(bridge: BridgeConfig, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) {
+    tokio::task::spawn(async move {
+        loop {
+            let result = match bridge.mode {
+                BridgeMode::Pull => pull(&bridge, &state, &room_manager, &clients).await,
+                BridgeMode::Push => push(&bridge, &state, &room_manager, &clients).await,
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Bridge of room {} to {}/ws/{} stopped: {}; reconnecting in {}s",
+                    bridge.local_room, bridge.remote_url, bridge.remote_room, e, bridge.reconnect_secs
+                );
+            }
+            tokio::time::sleep(Duration::from_secs(bridge.reconnect_secs)).await;
+        }
+    });
+}
+
+/// What the session loop of a bridge reacts to. The anchor is the bridge's connection to the
+/// room it joins first; links are the connections opened for one peer of that room each, keyed
+/// by the peer's ID and told apart from earlier links of the same peer by their own connection
+/// ID.
+enum Event {
+    Anchor(SignalingMessage),
+    AnchorClosed,
+    Link(String, String, SignalingMessage),
+    LinkClosed(String, String),
+    /// Time to reopen the link of a peer that is still there
+    Reopen(String),
+}
+
+impl Event {
+    fn message(key: &Option<String>, link_id: &str, message: SignalingMessage) -> Self {
+        match key {
+            Some(key) => Event::Link(key.clone(), link_id.to_string(), message),
+            None => Event::Anchor(message),
+        }
+    }
+
+    fn closed(key: Option<String>, link_id: String) -> Self {
+        match key {
+            Some(key) => Event::LinkClosed(key, link_id),
+            None => Event::AnchorClosed,
+        }
+    }
+}
+
+type Events = mpsc::UnboundedSender<Event>;
+
+fn new_connection_id() -> String {
+    format!("bridge-{}", Uuid::new_v4())
+}
+
+async fn connect(bridge: &BridgeConfig) -> Result<SignalingClient, String> {
+    let connecting = SignalingClient::connect_as(&bridge.remote_url, &bridge.remote_room, new_connection_id());
+    match tokio::time::timeout(CONNECT_TIMEOUT, connecting).await {
+        Ok(Ok(client)) => Ok(client),
+        Ok(Err(e)) => Err(format!("could not connect to {}: {}", bridge.remote_url, e)),
+        Err(_) => Err(format!("connecting to {} timed out", bridge.remote_url)),
+    }
+}
+
+/// Feeds what a LocalPeer receives into the session loop until its queue is closed
+fn forward_local(peer: LocalPeer, events: Events, key: Option<String>) {
+    tokio::spawn(async move {
+        while let Some(message) = peer.recv().await {
+            if events.send(Event::message(&key, &peer.connection_id, message)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(Event::closed(key, peer.connection_id));
+    });
+}
+
+/// Feeds what a remote connection receives into the session loop until its socket is closed
+fn forward_remote(client: &mut SignalingClient, events: Events, key: Option<String>) {
+    let link_id = client.connection_id().to_string();
+    let closed = Event::closed(key.clone(), link_id.clone());
+    let messages = events.clone();
+    let Some(reader) = client.on_message(move |message| {
+        let _ = messages.send(Event::message(&key, &link_id, message));
+        async {}
+    }) else {
+        return;
+    };
+    tokio::spawn(async move {
+        let _ = reader.await;
+        let _ = events.send(closed);
+    });
+}
+
+fn reopen_later(events: Events, key: String, secs: u64) {
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+        let _ = events.send(Event::Reopen(key));
+    });
+}
+
+fn data_str<'a>(message: &'a SignalingMessage, field: &str) -> Option<&'a str> {
+    message.data.as_ref()?.get(field)?.as_str()
+}
+
+fn data_is_sender(message: &SignalingMessage) -> bool {
+    message.data.as_ref().and_then(|data| data.get("is_sender")).and_then(Value::as_bool).unwrap_or(false)
+}
+
+/// IDs of the peers in a RoomInfo, each with whether it is a sender
+fn room_info_peers(message: &SignalingMessage) -> Vec<(String, bool)> {
+    let Some(peers) = message.data.as_ref().and_then(|data| data.get("peers")).and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    peers.iter()
+        .filter_map(|peer| {
+            let id = peer.get("id")?.as_str()?.to_string();
+            Some((id, peer.get("is_sender").and_then(Value::as_bool).unwrap_or(false)))
+        })
+        .collect()
+}
+
+/// Source and latest result of an InferenceUpdate that carries one
+fn inference_update(message: &SignalingMessage) -> Option<(&str, &Value)> {
+    let data = message.data.as_ref()?;
+    let latest = data.get("latest").filter(|latest| !latest.is_null())?;
+    Some((data.get("source_sender_id")?.as_str()?, latest))
+}
+
+/// Timestamps of the inference results the bridge relayed, per direction, so a result it
+/// brought into one room isn't relayed back when that room reports it
+#[derive(Default)]
+struct Relayed {
+    to_remote: VecDeque<i64>,
+    to_local: VecDeque<i64>,
+}
+
+impl Relayed {
+    /// Whether a result reported in the local room should be relayed to the remote one
+    fn relay_to_remote(&mut self, latest: &Value) -> bool {
+        Self::take(latest, &self.to_local, &mut self.to_remote)
+    }
+
+    /// Whether a result reported in the remote room should be relayed to the local one
+    fn relay_to_local(&mut self, latest: &Value) -> bool {
+        Self::take(latest, &self.to_remote, &mut self.to_local)
+    }
+
+    fn take(latest: &Value, brought_in: &VecDeque<i64>, relayed: &mut VecDeque<i64>) -> bool {
+        let Some(timestamp) = latest.get("timestamp").and_then(Value::as_i64) else { return false };
+        if brought_in.contains(&timestamp) {
+            return false;
+        }
+        if relayed.len() == RELAYED_KEPT {
+            relayed.pop_front();
+        }
+        relayed.push_back(timestamp);
+        true
+    }
+}
+
+/// A remote viewer connection opened for one local viewer
+struct PullLink {
+    client: SignalingClient,
+    /// The remote peer whose offer this viewer took
+    remote_peer: Option<String>,
+}
+
+/// Mode pull: the bridge is the sender of the local room, and each local viewer watches the
+/// remote sender through a remote viewer connection of its own
+struct Pull<'a> {
+    bridge: &'a BridgeConfig,
+    anchor: LocalPeer,
+    events: Events,
+    joined: bool,
+    /// Local viewers, in ID order; the first with a link relays inference results
+    viewers: BTreeSet<String>,
+    links: HashMap<String, PullLink>,
+    remote_sender: Option<String>,
+    relayed: Relayed,
+}
+
+async fn pull(bridge: &BridgeConfig, state: &SharedState, room_manager: &Arc<RwLock<RoomManager>>, clients: &Clients) -> Result<(), String> {
+    // The bridge takes the sender slot of the local room, token and all
+    let sender_token = {
+        let mut manager = room_manager.write().await;
+        manager.create_room_if_absent(bridge.local_room.clone());
+        manager.rooms.get(&bridge.local_room).and_then(|room| room.sender_token.clone())
+    };
+    let (events, mut incoming) = mpsc::unbounded_channel();
+    let anchor = LocalPeer::join_as(
+        state.clone(),
+        bridge.local_room.clone(),
+        new_connection_id(),
+        room_manager.clone(),
+        clients.clone(),
+        true,
+        serde_json::json!({ "bridge": true, "sender_token": sender_token }),
+    )
+    .await;
+    forward_local(anchor.clone(), events.clone(), None);
+
+    let mut session = Pull {
+        bridge,
+        anchor,
+        events,
+        joined: false,
+        viewers: BTreeSet::new(),
+        links: HashMap::new(),
+        remote_sender: None,
+        relayed: Relayed::default(),
+    };
+    let result = session.run(&mut incoming).await;
+    for link in session.links.values() {
+        link.client.close();
+    }
+    session.anchor.leave().await;
+    result
+}
+
+impl Pull<'_> {
+    async fn run(&mut self, incoming: &mut mpsc::UnboundedReceiver<Event>) -> Result<(), String> {
+        while let Some(event) = incoming.recv().await {
+            match event {
+                Event::Anchor(message) => self.local(message).await?,
+                Event::AnchorClosed => return Err("left the local room".to_string()),
+                Event::Link(viewer_id, link_id, message) => {
+                    if self.is_current(&viewer_id, &link_id) {
+                        self.remote(viewer_id, message).await;
+                    }
+                }
+                Event::LinkClosed(viewer_id, link_id) => {
+                    if self.is_current(&viewer_id, &link_id) {
+                        self.links.remove(&viewer_id);
+                        warn!("Bridge of room {}: remote connection for {} closed", self.bridge.local_room, viewer_id);
+                        reopen_later(self.events.clone(), viewer_id, self.bridge.reconnect_secs);
+                    }
+                }
+                Event::Reopen(viewer_id) => {
+                    if self.viewers.contains(&viewer_id) && !self.links.contains_key(&viewer_id) {
+                        self.open(viewer_id).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_current(&self, viewer_id: &str, link_id: &str) -> bool {
+        self.links.get(viewer_id).is_some_and(|link| link.client.connection_id() == link_id)
+    }
+
+    /// The link that relays inference results
+    fn primary(&self) -> Option<(&String, &PullLink)> {
+        self.viewers.iter().find_map(|id| self.links.get_key_value(id))
+    }
+
+    async fn open(&mut self, viewer_id: String) {
+        let opened = match connect(self.bridge).await {
+            Ok(client) => match client.join(false, self.bridge.join_data.clone()).await {
+                Ok(()) => Ok(client),
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e),
+        };
+        match opened {
+            Ok(mut client) => {
+                forward_remote(&mut client, self.events.clone(), Some(viewer_id.clone()));
+                self.links.insert(viewer_id, PullLink { client, remote_peer: None });
+            }
+            Err(e) => {
+                warn!("Bridge of room {}: no remote connection for {}: {}", self.bridge.local_room, viewer_id, e);
+                reopen_later(self.events.clone(), viewer_id, self.bridge.reconnect_secs);
+            }
+        }
+    }
+
+    async fn viewer_joined(&mut self, viewer_id: String) {
+        if self.viewers.insert(viewer_id.clone()) && !self.links.contains_key(&viewer_id) {
+            self.open(viewer_id).await;
+        }
+    }
+
+    /// A message for the bridge's sender connection in the local room
+    async fn local(&mut self, message: SignalingMessage) -> Result<(), String> {
+        match message.message_type {
+            SignalingMessageType::RoomInfo => {
+                if !self.joined {
+                    self.joined = true;
+                    info!("Bridge of room {} pulling from {}/ws/{}", self.bridge.local_room, self.bridge.remote_url, self.bridge.remote_room);
+                }
+                for (peer_id, is_sender) in room_info_peers(&message) {
+                    if !is_sender {
+                        self.viewer_joined(peer_id).await;
+                    }
+                }
+            }
+            SignalingMessageType::NewPeer => {
+                if let (Some(peer_id), false) = (data_str(&message, "connection_id"), data_is_sender(&message)) {
+                    self.viewer_joined(peer_id.to_string()).await;
+                }
+            }
+            SignalingMessageType::Leave => {
+                if let Some(peer_id) = data_str(&message, "connection_id") {
+                    self.viewers.remove(peer_id);
+                    if let Some(link) = self.links.remove(peer_id) {
+                        link.client.close();
+                    }
+                }
+            }
+            SignalingMessageType::Answer | SignalingMessageType::IceCandidate => {
+                let (Some(viewer_id), Some(data)) = (&message.sender_id, message.data.clone()) else { return Ok(()) };
+                let Some(link) = self.links.get(viewer_id) else { return Ok(()) };
+                let Some(remote_peer) = &link.remote_peer else { return Ok(()) };
+                // A failed send shows up as the link closing
+                let _ = if matches!(message.message_type, SignalingMessageType::Answer) {
+                    link.client.answer(remote_peer, data).await
+                } else {
+                    link.client.ice_candidate(remote_peer, data).await
+                };
+            }
+            SignalingMessageType::InferenceUpdate => {
+                let Some((source_id, latest)) = inference_update(&message) else { return Ok(()) };
+                if source_id != self.anchor.connection_id {
+                    return Ok(());
+                }
+                let Some(remote_peer) = self.primary().and_then(|(_, link)| link.remote_peer.clone()) else { return Ok(()) };
+                if !self.relayed.relay_to_remote(latest) {
+                    return Ok(());
+                }
+                if let Some((_, link)) = self.primary() {
+                    let _ = link.client.inference_result(&remote_peer, latest.clone()).await;
+                }
+            }
+            SignalingMessageType::RoomClosed => return Err("the local room was closed".to_string()),
+            SignalingMessageType::Error => {
+                let error = data_str(&message, "error").unwrap_or("unknown error").to_string();
+                if !self.joined {
+                    return Err(format!("could not join the local room: {}", error));
+                }
+                warn!("Bridge of room {}: {}", self.bridge.local_room, error);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// A message for the remote connection of local viewer `viewer_id`
+    async fn remote(&mut self, viewer_id: String, message: SignalingMessage) {
+        let anchor_id = self.anchor.connection_id.clone();
+        match message.message_type {
+            SignalingMessageType::Offer => {
+                let (Some(from), Some(sdp)) = (message.sender_id, message.data) else { return };
+                if let Some(link) = self.links.get_mut(&viewer_id) {
+                    link.remote_peer = Some(from);
+                }
+                self.anchor.signal(SignalingMessage::new_offer(viewer_id, anchor_id, sdp)).await;
+            }
+            SignalingMessageType::IceCandidate => {
+                let from_offerer = self.links.get(&viewer_id)
+                    .is_some_and(|link| link.remote_peer.is_some() && link.remote_peer == message.sender_id);
+                if let (true, Some(data)) = (from_offerer, message.data) {
+                    self.anchor.signal(SignalingMessage::new_ice_candidate(viewer_id, anchor_id, data)).await;
+                }
+            }
+            SignalingMessageType::RoomInfo => {
+                self.remote_sender = room_info_peers(&message).into_iter().find(|(_, is_sender)| *is_sender).map(|(id, _)| id);
+            }
+            SignalingMessageType::NewPeer => {
+                if let (Some(peer_id), true) = (data_str(&message, "connection_id"), data_is_sender(&message)) {
+                    self.remote_sender = Some(peer_id.to_string());
+                }
+            }
+            SignalingMessageType::Leave
+                if data_str(&message, "connection_id").is_some_and(|id| self.remote_sender.as_deref() == Some(id)) =>
+            {
+                self.remote_sender = None;
+            }
+            SignalingMessageType::InferenceUpdate => {
+                if self.primary().map(|(id, _)| id) != Some(&viewer_id) {
+                    return;
+                }
+                let Some((source_id, latest)) = inference_update(&message) else { return };
+                if Some(source_id) != self.remote_sender.as_deref() || !self.relayed.relay_to_local(latest) {
+                    return;
+                }
+                let result = SignalingMessage::new_inference_result(anchor_id.clone(), anchor_id, latest.clone());
+                self.anchor.signal(result).await;
+            }
+            SignalingMessageType::Error => {
+                let error = data_str(&message, "error").unwrap_or("unknown error");
+                warn!("Bridge of room {}: remote connection for {}: {}", self.bridge.local_room, viewer_id, error);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A local viewer connection opened for one remote viewer
+struct PushLink {
+    peer: LocalPeer,
+    /// The local peer whose offer this link took
+    local_peer: Option<String>,
+}
+
+/// Mode push: the bridge is the sender of the remote room, and each remote viewer watches the
+/// local sender through a local viewer of its own
+struct Push<'a> {
+    bridge: &'a BridgeConfig,
+    state: &'a SharedState,
+    room_manager: &'a Arc<RwLock<RoomManager>>,
+    clients: &'a Clients,
+    anchor: SignalingClient,
+    events: Events,
+    joined: bool,
+    /// Remote viewers, in ID order; the first with a link relays inference results
+    viewers: BTreeSet<String>,
+    links: HashMap<String, PushLink>,
+    local_sender: Option<String>,
+    relayed: Relayed,
+}
+
+async fn push(bridge: &BridgeConfig, state: &SharedState, room_manager: &Arc<RwLock<RoomManager>>, clients: &Clients) -> Result<(), String> {
+    room_manager.write().await.create_room_if_absent(bridge.local_room.clone());
+    let (events, mut incoming) = mpsc::unbounded_channel();
+    let mut anchor = connect(bridge).await?;
+    forward_remote(&mut anchor, events.clone(), None);
+    anchor.join(true, bridge.join_data.clone()).await.map_err(|e| e.to_string())?;
+
+    let mut session = Push {
+        bridge,
+        state,
+        room_manager,
+        clients,
+        anchor,
+        events,
+        joined: false,
+        viewers: BTreeSet::new(),
+        links: HashMap::new(),
+        local_sender: None,
+        relayed: Relayed::default(),
+    };
+    let result = session.run(&mut incoming).await;
+    session.anchor.close();
+    for link in session.links.values() {
+        link.peer.leave().await;
+    }
+    result
+}
+
+impl Push<'_> {
+    async fn run(&mut self, incoming: &mut mpsc::UnboundedReceiver<Event>) -> Result<(), String> {
+        while let Some(event) = incoming.recv().await {
+            match event {
+                Event::Anchor(message) => self.remote(message).await?,
+                Event::AnchorClosed => return Err("connection to the remote instance closed".to_string()),
+                Event::Link(viewer_id, link_id, message) => {
+                    if self.is_current(&viewer_id, &link_id) {
+                        self.local(viewer_id, message).await;
+                    }
+                }
+                Event::LinkClosed(viewer_id, link_id) => {
+                    if self.is_current(&viewer_id, &link_id) {
+                        self.links.remove(&viewer_id);
+                        warn!("Bridge of room {}: local connection for {} closed", self.bridge.local_room, viewer_id);
+                        reopen_later(self.events.clone(), viewer_id, self.bridge.reconnect_secs);
+                    }
+                }
+                Event::Reopen(viewer_id) => {
+                    if self.viewers.contains(&viewer_id) && !self.links.contains_key(&viewer_id) {
+                        self.open(viewer_id).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_current(&self, viewer_id: &str, link_id: &str) -> bool {
+        self.links.get(viewer_id).is_some_and(|link| link.peer.connection_id == link_id)
+    }
+
+    /// The link that relays inference results
+    fn primary(&self) -> Option<(&String, &PushLink)> {
+        self.viewers.iter().find_map(|id| self.links.get_key_value(id))
+    }
+
+    async fn open(&mut self, viewer_id: String) {
+        // The room may have been deleted since the bridge started
+        self.room_manager.write().await.create_room_if_absent(self.bridge.local_room.clone());
+        let peer = LocalPeer::join(
+            self.state.clone(),
+            self.bridge.local_room.clone(),
+            new_connection_id(),
+            self.room_manager.clone(),
+            self.clients.clone(),
+            serde_json::json!({ "bridge": true }),
+        )
+        .await;
+        forward_local(peer.clone(), self.events.clone(), Some(viewer_id.clone()));
+        self.links.insert(viewer_id, PushLink { peer, local_peer: None });
+    }
+
+    async fn viewer_joined(&mut self, viewer_id: String) {
+        if self.viewers.insert(viewer_id.clone()) && !self.links.contains_key(&viewer_id) {
+            self.open(viewer_id).await;
+        }
+    }
+
+    /// A message for the bridge's sender connection in the remote room
+    async fn remote(&mut self, message: SignalingMessage) -> Result<(), String> {
+        match message.message_type {
+            SignalingMessageType::RoomInfo => {
+                if !self.joined {
+                    self.joined = true;
+                    info!("Bridge of room {} pushing to {}/ws/{}", self.bridge.local_room, self.bridge.remote_url, self.bridge.remote_room);
+                }
+                for (peer_id, is_sender) in room_info_peers(&message) {
+                    if !is_sender {
+                        self.viewer_joined(peer_id).await;
+                    }
+                }
+            }
+            SignalingMessageType::NewPeer => {
+                if let (Some(peer_id), false) = (data_str(&message, "connection_id"), data_is_sender(&message)) {
+                    self.viewer_joined(peer_id.to_string()).await;
+                }
+            }
+            SignalingMessageType::Leave => {
+                if let Some(peer_id) = data_str(&message, "connection_id") {
+                    self.viewers.remove(peer_id);
+                    if let Some(link) = self.links.remove(peer_id) {
+                        link.peer.leave().await;
+                    }
+                }
+            }
+            SignalingMessageType::Answer | SignalingMessageType::IceCandidate => {
+                let (Some(viewer_id), Some(data)) = (&message.sender_id, message.data.clone()) else { return Ok(()) };
+                let Some(link) = self.links.get(viewer_id) else { return Ok(()) };
+                let Some(local_peer) = link.local_peer.clone() else { return Ok(()) };
+                let link_id = link.peer.connection_id.clone();
+                let relayed = if matches!(message.message_type, SignalingMessageType::Answer) {
+                    SignalingMessage::new_answer(local_peer, link_id, data)
+                } else {
+                    SignalingMessage::new_ice_candidate(local_peer, link_id, data)
+                };
+                link.peer.signal(relayed).await;
+            }
+            SignalingMessageType::InferenceUpdate => {
+                let Some((source_id, latest)) = inference_update(&message) else { return Ok(()) };
+                if source_id != self.anchor.connection_id() {
+                    return Ok(());
+                }
+                let Some((peer, local_peer)) = self.primary().and_then(|(_, link)| Some((link.peer.clone(), link.local_peer.clone()?))) else {
+                    return Ok(());
+                };
+                if self.relayed.relay_to_local(latest) {
+                    peer.signal(SignalingMessage::new_inference_result(peer.connection_id.clone(), local_peer, latest.clone())).await;
+                }
+            }
+            SignalingMessageType::RoomClosed => return Err("the remote room was closed".to_string()),
+            SignalingMessageType::Error => {
+                let error = data_str(&message, "error").unwrap_or("unknown error").to_string();
+                if !self.joined {
+                    return Err(format!("could not join the remote room: {}", error));
+                }
+                warn!("Bridge of room {}: {}", self.bridge.local_room, error);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// A message for the local connection of remote viewer `viewer_id`
+    async fn local(&mut self, viewer_id: String, message: SignalingMessage) {
+        match message.message_type {
+            SignalingMessageType::Offer => {
+                let (Some(from), Some(sdp)) = (message.sender_id, message.data) else { return };
+                if let Some(link) = self.links.get_mut(&viewer_id) {
+                    link.local_peer = Some(from);
+                }
+                let _ = self.anchor.offer(&viewer_id, sdp).await;
+            }
+            SignalingMessageType::IceCandidate => {
+                let from_offerer = self.links.get(&viewer_id)
+                    .is_some_and(|link| link.local_peer.is_some() && link.local_peer == message.sender_id);
+                if let (true, Some(data)) = (from_offerer, message.data) {
+                    let _ = self.anchor.ice_candidate(&viewer_id, data).await;
+                }
+            }
+            SignalingMessageType::RoomInfo => {
+                self.local_sender = room_info_peers(&message).into_iter().find(|(_, is_sender)| *is_sender).map(|(id, _)| id);
+            }
+            SignalingMessageType::NewPeer => {
+                if let (Some(peer_id), true) = (data_str(&message, "connection_id"), data_is_sender(&message)) {
+                    self.local_sender = Some(peer_id.to_string());
+                }
+            }
+            SignalingMessageType::Leave
+                if data_str(&message, "connection_id").is_some_and(|id| self.local_sender.as_deref() == Some(id)) =>
+            {
+                self.local_sender = None;
+            }
+            SignalingMessageType::InferenceUpdate => {
+                if self.primary().map(|(id, _)| id) != Some(&viewer_id) {
+                    return;
+                }
+                let Some((source_id, latest)) = inference_update(&message) else { return };
+                if Some(source_id) != self.local_sender.as_deref() || !self.relayed.relay_to_remote(latest) {
+                    return;
+                }
+                let _ = self.anchor.inference_result(self.anchor.connection_id(), latest.clone()).await;
+            }
+            SignalingMessageType::Error => {
+                let error = data_str(&message, "error").unwrap_or("unknown error");
+                warn!("Bridge of room {}: local connection for {}: {}", self.bridge.local_room, viewer_id, error);
+            }
+            _ => {}
+        }
+    }
+}
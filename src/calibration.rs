@@ -0,0 +1,76 @@
+// calibration.rs
+// Per-model detection score calibration. Different model builds (lite_mobilenet_v2 vs mobilenet_v2,
+// quantized vs float) report differently distributed scores; remapping them server-side keeps
+// thresholds meaningful no matter which build a viewer runs.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Model name used when an InferenceResult does not say which model produced it
+pub const DEFAULT_MODEL: &str = "default";
+
+/// Piecewise-linear score remapping given as `[raw, calibrated]` points sorted by raw score.
+/// Scores outside the first/last point are clamped to the end values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationCurve(pub Vec<(f64, f64)>);
+
+impl CalibrationCurve {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.0.len() < 2 {
+            return Err("needs at least 2 points".to_string());
+        }
+        for (raw, calibrated) in &self.0 {
+            if !(0.0..=1.0).contains(raw) || !(0.0..=1.0).contains(calibrated) {
+                return Err(format!("point [{}, {}] is outside 0..1", raw, calibrated));
+            }
+        }
+        if self.0.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err("raw scores must be strictly increasing".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, score: f64) -> f64 {
+        let (first, last) = match (self.0.first(), self.0.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return score,
+        };
+        if score <= first.0 {
+            return first.1;
+        }
+        if score >= last.0 {
+            return last.1;
+        }
+        for w in self.0.windows(2) {
+            let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+            if score <= x1 {
+                return y0 + (score - x0) * (y1 - y0) / (x1 - x0);
+            }
+        }
+        last.1
+    }
+}
+
+/// Looks up the curve for the payload's `model`, preferring a room override over the global map.
+pub fn curve_for<'a>(
+    payload: &Value,
+    room_curves: &'a HashMap<String, CalibrationCurve>,
+    global_curves: &'a HashMap<String, CalibrationCurve>,
+) -> Option<&'a CalibrationCurve> {
+    let model = payload.get("model").and_then(Value::as_str).unwrap_or(DEFAULT_MODEL);
+    room_curves.get(model).or_else(|| global_curves.get(model))
+}
+
+/// Rewrites every `score` in the payload's `predictions` (or `detections`) array in place.
+pub fn apply_to_payload(payload: &mut Value, curve: &CalibrationCurve) {
+    for key in ["predictions", "detections"] {
+        if let Some(items) = payload.get_mut(key).and_then(Value::as_array_mut) {
+            for item in items {
+                if let Some(score) = item.get("score").and_then(Value::as_f64) {
+                    item["score"] = serde_json::json!(curve.apply(score));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// client_certs.rs
+// Optional TLS client certificates (mTLS) on the HTTPS signaling listeners. Fixed camera
+// installations get a certificate issued by a CA the operator controls; with `required` the TLS
+// handshake fails for any client that can't present one, before a room or sender token is even
+// looked at. `optional` verifies certificates that are presented but lets other clients in, which
+// is meant for rolling certificates out to devices before enforcing them. Unix socket and
+// systemd-activated listeners are plain HTTP and are not covered; the proxy in front of them has
+// to check certificates itself.
+
+use std::fs::File;
+use std::io::BufReader;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientCertMode {
+    /// Handshakes without a certificate from the CA fail
+    #[default]
+    Required,
+    /// Certificates are checked when presented, clients without one still connect
+    Optional,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientCertConfig {
+    /// PEM file with the CA certificate(s) client certificates must chain to
+    pub ca_path: String,
+    #[serde(default)]
+    pub mode: ClientCertMode,
+}
+
+impl ClientCertConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let file = File::open(&self.ca_path).map_err(|e| format!("ca_path '{}': {}", self.ca_path, e))?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+            .map_err(|e| format!("ca_path '{}': {}", self.ca_path, e))?;
+        if certs.is_empty() {
+            return Err(format!("ca_path '{}' contains no PEM certificates", self.ca_path));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+// client_queue.rs
+// Bounded outgoing queue per WebSocket connection. When a slow peer fills its queue the oldest
+// queued InferenceUpdate/InferenceStats/Analytics is dropped to make room, since a newer one
+// supersedes it; other signaling messages are never dropped in favour of those. A queue that
+// stays full is reported through saturated_for so the connection can be closed. The handle also
+// remembers the protocol version and capabilities negotiated at join, which decide how messages
+// are encoded, numbers the messages that must be delivered, and remembers the client's recent
+// msg_ids so retransmissions can be dropped.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use warp::ws::Message;
+use ws2infer_protocol::Negotiated;
+
+/// Messages a connection may have queued before drop-oldest kicks in
+pub const CLIENT_QUEUE_CAPACITY: usize = 256;
+/// Client msg_ids remembered per connection for duplicate detection
+const RECENT_MSG_IDS: usize = 128;
+
+#[derive(Debug)]
+struct QueueState {
+    /// (message, droppable)
+    messages: VecDeque<(Message, bool)>,
+    closed: bool,
+    /// When the queue last filled up; cleared once it drains below half capacity
+    saturated_since: Option<Instant>,
+    dropped: u64,
+    /// Last seq stamped on a message for this connection
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    protocol: Mutex<Negotiated>,
+    recent_msg_ids: Mutex<VecDeque<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    inner: Arc<Inner>,
+}
+
+impl Default for ClientHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientHandle {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(QueueState {
+                    messages: VecDeque::new(),
+                    closed: false,
+                    saturated_since: None,
+                    dropped: 0,
+                    seq: 0,
+                }),
+                notify: Notify::new(),
+                protocol: Mutex::new(Negotiated::default()),
+                recent_msg_ids: Mutex::new(VecDeque::new()),
+            }),
+        }
+    }
+
+    /// Queues a message a newer one will supersede (InferenceUpdate, InferenceStats, Analytics).
+    /// False when the connection is gone, or when the queue is full of undroppable messages.
+    pub fn send_droppable(&self, message: Message) -> bool {
+        self.push(message, true)
+    }
+
+    /// Queues the frames of one chunked message back to back; none of them is droppable, since
+    /// losing one loses the message
+    pub fn send_chunks(&self, frames: Vec<Message>) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        let queued = frames.into_iter().all(|frame| Self::push_locked(&mut state, frame, false));
+        drop(state);
+        self.inner.notify.notify_one();
+        queued
+    }
+
+    /// Queues a message that must be delivered, built by `encode` from the next sequence number
+    /// (more than one frame when it is chunked). The number is taken under the queue lock, so
+    /// messages are queued in seq order; it is used up even when the message is discarded,
+    /// leaving the gap the client looks for.
+    pub fn send_sequenced(&self, encode: impl FnOnce(u64) -> Vec<Message>) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        let frames = encode(state.seq + 1);
+        if frames.is_empty() {
+            return false;
+        }
+        state.seq += 1;
+        let queued = frames.into_iter().all(|frame| Self::push_locked(&mut state, frame, false));
+        drop(state);
+        if queued {
+            self.inner.notify.notify_one();
+        }
+        queued
+    }
+
+    fn push(&self, message: Message, droppable: bool) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        let queued = Self::push_locked(&mut state, message, droppable);
+        drop(state);
+        if queued {
+            self.inner.notify.notify_one();
+        }
+        queued
+    }
+
+    fn push_locked(state: &mut QueueState, message: Message, droppable: bool) -> bool {
+        if state.messages.len() >= CLIENT_QUEUE_CAPACITY {
+            state.saturated_since.get_or_insert_with(Instant::now);
+            state.dropped += 1;
+            match state.messages.iter().position(|(_, droppable)| *droppable) {
+                Some(oldest) => {
+                    state.messages.remove(oldest);
+                }
+                None => return false,
+            }
+        }
+
+        state.messages.push_back((message, droppable));
+        true
+    }
+
+    /// Remembers a msg_id this connection sent; true when it was already seen recently
+    pub fn is_duplicate(&self, msg_id: &str) -> bool {
+        let mut recent = self.inner.recent_msg_ids.lock().unwrap();
+        if recent.iter().any(|id| id == msg_id) {
+            return true;
+        }
+        if recent.len() >= RECENT_MSG_IDS {
+            recent.pop_front();
+        }
+        recent.push_back(msg_id.to_string());
+        false
+    }
+
+    /// Next message for the socket; None once the queue was shut down or closed and drained
+    pub async fn recv(&self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Some(message) = Self::pop_locked(&mut state) {
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.inner.notify.notified().await;
+        }
+    }
+
+    /// Next queued message without waiting, for transports that send messages in batches
+    pub fn try_recv(&self) -> Option<Message> {
+        Self::pop_locked(&mut self.inner.state.lock().unwrap())
+    }
+
+    fn pop_locked(state: &mut QueueState) -> Option<Message> {
+        let (message, _) = state.messages.pop_front()?;
+        if state.messages.len() < CLIENT_QUEUE_CAPACITY / 2 {
+            state.saturated_since = None;
+        }
+        Some(message)
+    }
+
+    /// Replaces anything still queued with a close frame; nothing is accepted afterwards
+    pub fn close(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.messages.clear();
+        state.messages.push_back((Message::close(), false));
+        state.closed = true;
+        drop(state);
+        self.inner.notify.notify_one();
+    }
+
+    /// Called when the socket writer stops; further sends report failure
+    pub fn shutdown(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.messages.clear();
+        state.closed = true;
+    }
+
+    /// Legacy until the connection joins with a protocol_version
+    pub fn protocol(&self) -> Negotiated {
+        self.inner.protocol.lock().unwrap().clone()
+    }
+
+    pub fn set_protocol(&self, negotiated: Negotiated) {
+        *self.inner.protocol.lock().unwrap() = negotiated;
+    }
+
+    pub fn depth(&self) -> usize {
+        self.inner.state.lock().unwrap().messages.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.inner.state.lock().unwrap().dropped
+    }
+
+    /// How long the queue has been continuously (near) full
+    pub fn saturated_for(&self, now: Instant) -> Option<Duration> {
+        self.inner.state.lock().unwrap().saturated_since.map(|since| now.duration_since(since))
+    }
+}
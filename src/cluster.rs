@@ -0,0 +1,85 @@
+// cluster.rs
+// Cluster mode without shared state: every room belongs to exactly one instance of a fixed list,
+// chosen by rendezvous hashing of the room ID, so all instances (and anything else holding the
+// same list) agree on the owner without talking to each other. WebSockets that reach the wrong
+// instance get a Redirect to the owner, and rooms created here get IDs this instance owns.
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    /// This instance's public base URL as clients reach it, e.g. `wss://sig-1.example.com`
+    pub self_url: String,
+    /// Base URLs of all instances, including this one; every instance needs the same list
+    pub instances: Vec<String>,
+}
+
+impl ClusterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let is_ws_url = |url: &str| url.starts_with("ws://") || url.starts_with("wss://");
+        if let Some(url) = self.instances.iter().find(|url| !is_ws_url(url)) {
+            return Err(format!("'{}' is not a ws:// or wss:// URL", url));
+        }
+        if !self.instances.contains(&self.self_url) {
+            return Err("self_url must be one of instances".to_string());
+        }
+        Ok(())
+    }
+
+    /// Base URL of the instance that owns `room_id`
+    pub fn owner(&self, room_id: &str) -> &str {
+        self.instances
+            .iter()
+            .max_by_key(|instance| score(instance, room_id))
+            .map(String::as_str)
+            .unwrap_or(&self.self_url)
+    }
+
+    pub fn is_local(&self, room_id: &str) -> bool {
+        self.owner(room_id) == self.self_url
+    }
+
+    /// WebSocket URL of the room on its owner
+    pub fn room_url(&self, room_id: &str) -> String {
+        format!("{}/ws/{}", self.owner(room_id).trim_end_matches('/'), room_id)
+    }
+
+    /// REST URL of the room on its owner, for redirecting room-creating requests
+    pub fn room_api_url(&self, room_id: &str) -> String {
+        format!("{}/api/rooms/{}", self.http_owner(room_id), room_id)
+    }
+
+    /// Long-polling URL of the room on its owner
+    pub fn room_poll_url(&self, room_id: &str) -> String {
+        format!("{}/poll/{}", self.http_owner(room_id), room_id)
+    }
+
+    /// The owner's URL with ws:// or wss:// turned into http:// or https://
+    fn http_owner(&self, room_id: &str) -> String {
+        let owner = self.owner(room_id).trim_end_matches('/');
+        owner.strip_prefix("ws").map(|rest| format!("http{}", rest)).unwrap_or_else(|| owner.to_string())
+    }
+
+    /// A random room key in `tenant` owned by this instance; takes about as many tries as there
+    /// are instances
+    pub fn new_room_id(&self, tenant: Option<&str>) -> String {
+        loop {
+            let room_id = crate::tenant::room_key(tenant, &Uuid::new_v4().to_string());
+            if self.is_local(&room_id) {
+                return room_id;
+            }
+        }
+    }
+}
+
+/// Stable across processes and builds, unlike std's hasher
+fn score(instance: &str, room_id: &str) -> u64 {
+    let digest = Sha1::new()
+        .chain_update(instance.trim_end_matches('/').as_bytes())
+        .chain_update(b"\n")
+        .chain_update(room_id.as_bytes())
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap_or_default())
+}
@@ -1,28 +1,835 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::fs;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use sha1::{Digest, Sha1};
+use serde_json::Value;
+use crate::calibration::CalibrationCurve;
+use crate::anomaly::AnomalyConfig;
+use crate::tracker::TrackingConfig;
+use crate::mqtt::MqttConfig;
+use crate::webhooks::WebhookConfig;
+use crate::tenant::{self, TenantConfig};
+use crate::quota::Quotas;
+use crate::webtransport::WebTransportConfig;
+use crate::server_inference::ServerInferenceConfig;
+use crate::snapshot::SnapshotConfig;
+use crate::backplane::BackplaneConfig;
+use crate::cluster::ClusterConfig;
+use crate::bridge::BridgeConfig;
+use crate::ingest::RtspIngestConfig;
+use crate::hls::HlsConfig;
+use crate::telemetry::TelemetryConfig;
+use crate::mdns::MdnsConfig;
+use crate::logging::LoggingConfig;
+use crate::retention::RetentionConfig;
+use crate::downsampling::DownsamplingConfig;
+use crate::uploader::UploadConfig;
+use crate::event_bus::EventBusConfig;
+use crate::wasm_plugins::WasmPluginsConfig;
+use crate::scripting::ScriptingConfig;
+use crate::inference::InferenceLimits;
+use crate::sdp::SdpPolicy;
+use crate::ip_filter::IpFilterConfig;
+use crate::message_size::MessageSizeConfig;
+use crate::client_certs::ClientCertConfig;
+use crate::auth::AuthConfig;
+use crate::proxy::{Origin, ProxyConfig};
+use crate::relay::RelayConfig;
+use crate::quality::QualityConfig;
+use crate::room_template::RoomTemplate;
+use crate::assets::StaticConfig;
+use crate::recorder::RecordingConfig;
+use crate::external_address::ExternalAddressConfig;
+
+/// Live config shared with request handlers; updated in place by hot reload
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+/// How many client config versions `GET /api/config?since=` can diff against
+const CONFIG_VERSIONS_KEPT: usize = 16;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub signaling_addr: String,
     pub stun_addr: String,
     pub turn_addr: String,
+    /// Further addresses the HTTP server listens on besides signaling_addr, e.g. "[::]:8080"
+    #[serde(default)]
+    pub extra_signaling_addrs: Vec<String>,
+    /// Path of a Unix domain socket the HTTP server also listens on, for a reverse proxy on
+    /// the same host; served without TLS
+    #[serde(default)]
+    pub signaling_unix_socket: Option<String>,
+    /// Further UDP addresses for STUN; IPv6 sockets are IPv6-only, so "[::]:3478" can sit next to
+    /// "0.0.0.0:3478"
+    #[serde(default)]
+    pub extra_stun_addrs: Vec<String>,
+    /// Further UDP addresses for TURN, like extra_stun_addrs
+    #[serde(default)]
+    pub extra_turn_addrs: Vec<String>,
+    /// Public IP/hostname advertised behind NAT or in containers, instead of the LAN address
+    #[serde(default)]
+    pub external_address: ExternalAddressConfig,
+    /// Optional STUN over TCP listener
+    #[serde(default)]
+    pub stun_tcp_addr: Option<String>,
+    /// Optional TURN over TCP listener (turn:host:port?transport=tcp)
+    #[serde(default)]
+    pub turn_tcp_addr: Option<String>,
+    /// Optional TURN over TLS listener (turns:), using tls_cert_path/tls_key_path
+    #[serde(default)]
+    pub turns_addr: Option<String>,
     pub ice_servers: Vec<IceServerConfig>,
     pub video_constraints: serde_json::Value,
     pub tls_enabled: bool,
     pub tls_cert_path: String,
     pub tls_key_path: String,
+    /// CA that signaling clients must present certificates from (mTLS); needs tls_enabled
+    #[serde(default)]
+    pub tls_client_certs: Option<ClientCertConfig>,
+    /// Identity provider that room creators and senders must authenticate with
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// Score calibration curves keyed by model name (the `model` field of InferenceResult data)
+    #[serde(default)]
+    pub score_calibration: HashMap<String, CalibrationCurve>,
+    /// Shared secret for TURN REST API credentials; when unset the TURN server accepts any client
+    #[serde(default)]
+    pub turn_secret: Option<String>,
+    #[serde(default = "default_turn_realm")]
+    pub turn_realm: String,
+    #[serde(default = "default_turn_credential_ttl_secs")]
+    pub turn_credential_ttl_secs: u64,
+    /// Inclusive range of ports handed out as TURN relayed addresses
+    #[serde(default = "default_relay_port_min")]
+    pub relay_port_min: u16,
+    #[serde(default = "default_relay_port_max")]
+    pub relay_port_max: u16,
+    /// Total bytes a single TURN allocation may relay before it is torn down
+    #[serde(default)]
+    pub turn_quota_bytes: Option<u64>,
+    /// Bytes per second a single TURN allocation may relay before it is torn down
+    #[serde(default)]
+    pub turn_max_bytes_per_sec: Option<u64>,
+    /// Token for administrative access (admin RoomInfo on Join, admin REST endpoints)
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Independent applications sharing this server, each with its own rooms and keys
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    /// Limits every room gets: connections and stored inference results per day
+    #[serde(default)]
+    pub room_quotas: Quotas,
+    /// Length of guest passes room admins issue for rooms without their own guest_session_secs
+    #[serde(default = "default_guest_session_secs")]
+    pub guest_session_secs: u64,
+    /// How long before a guest session ends the GuestExpiring warning is sent
+    #[serde(default = "default_guest_warning_secs")]
+    pub guest_warning_secs: u64,
+    /// How long before a room's idle timeout runs out the IdleWarning is sent
+    #[serde(default = "default_idle_warning_secs")]
+    pub idle_warning_secs: u64,
+    /// Enables anomaly detection against a baseline learned from stored history
+    #[serde(default)]
+    pub anomaly: Option<AnomalyConfig>,
+    /// Assigns track IDs to detections across frames when set
+    #[serde(default)]
+    pub tracking: Option<TrackingConfig>,
+    /// Publishes every inference result to an MQTT broker when set
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    /// Endpoints notified when rooms are created, get their first sender, empty or are deleted
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Bounds the size and age of stored inference data when set
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+    /// Rolls stored detections up into per-minute and per-hour summaries when set
+    #[serde(default)]
+    pub downsampling: Option<DownsamplingConfig>,
+    /// Ships rotated JSONL files, snapshots and recordings to an S3-compatible bucket when set
+    #[serde(default)]
+    pub upload: Option<UploadConfig>,
+    /// Publishes lifecycle events and inference results to Kafka or NATS when set
+    #[serde(default)]
+    pub event_bus: Option<EventBusConfig>,
+    /// Runs sandboxed WebAssembly hook plugins, managed with /api/admin/plugins, when set
+    #[serde(default)]
+    pub wasm_plugins: Option<WasmPluginsConfig>,
+    /// Runs Rhai room policy scripts on joins and inference results when set
+    #[serde(default)]
+    pub scripting: Option<ScriptingConfig>,
+    /// InferenceUpdate broadcasts are coalesced to the latest result per source at this interval;
+    /// 0 sends one update per result
+    #[serde(default = "default_inference_broadcast_ms")]
+    pub inference_broadcast_ms: u64,
+    /// Size and rate limits for Relay messages forwarded between connections
+    #[serde(default)]
+    pub relay: RelayConfig,
+    /// Size and nesting limits for InferenceResult payloads
+    #[serde(default)]
+    pub inference_limits: InferenceLimits,
+    /// Checks and stripping applied to Offer/Answer SDP
+    #[serde(default)]
+    pub sdp: SdpPolicy,
+    /// Largest signaling message accepted or sent whole, and the chunking limit
+    #[serde(default)]
+    pub message_size: MessageSizeConfig,
+    /// CIDR allow and deny lists for HTTP/WebSocket and STUN/TURN clients
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+    /// Reverse proxies whose X-Forwarded-* headers identify the client
+    #[serde(default)]
+    pub reverse_proxy: ProxyConfig,
+    /// Named room profiles POST /api/rooms can start from via `template`
+    #[serde(default)]
+    pub room_templates: HashMap<String, RoomTemplate>,
+    /// When viewers' reported loss makes the sender step down within video_constraints
+    #[serde(default)]
+    pub quality: QualityConfig,
+    /// Where and in which segment length POST /api/rooms/{id}/record writes recordings
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    /// Runs an ONNX detection model on JPEG frames uploaded by senders (needs the
+    /// `server-inference` build feature)
+    #[serde(default)]
+    pub server_inference: Option<ServerInferenceConfig>,
+    /// Where POST /api/rooms/{id}/snapshot stores images and how many each room keeps
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
+    /// Shares rooms with other instances through Redis
+    #[serde(default)]
+    pub backplane: Option<BackplaneConfig>,
+    /// Assigns each room to one instance of a fixed list and redirects clients to it; an
+    /// alternative to the backplane
+    #[serde(default)]
+    pub cluster: Option<ClusterConfig>,
+    /// Rooms of other instances this server joins as a client, relaying their feeds here or
+    /// the feeds of local rooms there
+    #[serde(default)]
+    pub bridges: Vec<BridgeConfig>,
+    /// IP cameras pulled over RTSP into rooms as their senders (needs the `rtsp-ingest` build
+    /// feature)
+    #[serde(default)]
+    pub rtsp_ingest: Vec<RtspIngestConfig>,
+    /// Packages the video of SFU rooms as LL-HLS under /hls/{room_id}/ for players without
+    /// WebRTC; SFU rooms then accept H.264 only
+    #[serde(default)]
+    pub hls: Option<HlsConfig>,
+    /// Where the web pages are served from
+    #[serde(default)]
+    pub static_files: StaticConfig,
+    /// Log format and levels
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Exports tracing spans for signaling, STUN and TURN to an OTLP collector
+    #[serde(default)]
+    pub telemetry: Option<TelemetryConfig>,
+    /// Advertise the server on the LAN via mDNS (_ws2infer._tcp)
+    #[serde(default)]
+    pub mdns: Option<MdnsConfig>,
+    /// Experimental WebTransport signaling listener; needs the `webtransport` build feature
+    #[serde(default)]
+    pub webtransport: Option<WebTransportConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_inference_broadcast_ms() -> u64 {
+    250
+}
+
+fn default_guest_session_secs() -> u64 {
+    900
+}
+
+fn default_guest_warning_secs() -> u64 {
+    60
+}
+
+fn default_idle_warning_secs() -> u64 {
+    60
+}
+
+fn default_turn_realm() -> String {
+    "ws2infer".to_string()
+}
+
+fn default_turn_credential_ttl_secs() -> u64 {
+    86400
+}
+
+fn default_relay_port_min() -> u16 {
+    49152
+}
+
+fn default_relay_port_max() -> u16 {
+    65535
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IceServerConfig {
     pub urls: Vec<String>,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            signaling_addr: "0.0.0.0:8080".to_string(),
+            stun_addr: "0.0.0.0:3478".to_string(),
+            turn_addr: "0.0.0.0:3479".to_string(),
+            extra_signaling_addrs: Vec::new(),
+            signaling_unix_socket: None,
+            extra_stun_addrs: Vec::new(),
+            extra_turn_addrs: Vec::new(),
+            external_address: ExternalAddressConfig::default(),
+            stun_tcp_addr: None,
+            turn_tcp_addr: None,
+            turns_addr: None,
+            ice_servers: vec![IceServerConfig { urls: vec!["stun:localhost:3478".to_string()] }],
+            video_constraints: serde_json::json!({
+                "width": { "ideal": 1280 },
+                "height": { "ideal": 720 }
+            }),
+            tls_enabled: true,
+            tls_cert_path: "cert.pem".to_string(),
+            tls_key_path: "key.pem".to_string(),
+            tls_client_certs: None,
+            auth: None,
+            score_calibration: HashMap::new(),
+            turn_secret: None,
+            turn_realm: default_turn_realm(),
+            turn_credential_ttl_secs: default_turn_credential_ttl_secs(),
+            relay_port_min: default_relay_port_min(),
+            relay_port_max: default_relay_port_max(),
+            turn_quota_bytes: None,
+            turn_max_bytes_per_sec: None,
+            admin_token: None,
+            tenants: Vec::new(),
+            room_quotas: Quotas::default(),
+            guest_session_secs: default_guest_session_secs(),
+            guest_warning_secs: default_guest_warning_secs(),
+            idle_warning_secs: default_idle_warning_secs(),
+            anomaly: None,
+            tracking: None,
+            mqtt: None,
+            webhooks: Vec::new(),
+            retention: None,
+            downsampling: None,
+            upload: None,
+            event_bus: None,
+            wasm_plugins: None,
+            scripting: None,
+            inference_broadcast_ms: default_inference_broadcast_ms(),
+            relay: RelayConfig::default(),
+            inference_limits: InferenceLimits::default(),
+            sdp: SdpPolicy::default(),
+            message_size: MessageSizeConfig::default(),
+            ip_filter: IpFilterConfig::default(),
+            reverse_proxy: ProxyConfig::default(),
+            room_templates: HashMap::new(),
+            quality: QualityConfig::default(),
+            recording: RecordingConfig::default(),
+            server_inference: None,
+            snapshots: SnapshotConfig::default(),
+            backplane: None,
+            cluster: None,
+            bridges: Vec::new(),
+            rtsp_ingest: Vec::new(),
+            hls: None,
+            static_files: StaticConfig::default(),
+            logging: LoggingConfig::default(),
+            telemetry: None,
+            mdns: None,
+            webtransport: None,
+        }
+    }
+}
+
 impl Config {
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         let content = fs::read_to_string(path)?;
         let config: Config = serde_json::from_str(&content)?;
         Ok(config)
     }
+
+    /// Copy of the config as served to clients: localhost in ice_servers is replaced by the
+    /// external address (or this machine's IP) so phones on the LAN or outside the NAT get
+    /// reachable STUN/TURN URLs.
+    /// The subset of settings served to browsers; secrets and listener details stay server-side
+    pub fn for_clients(&self) -> Value {
+        self.for_clients_via(None)
+    }
+
+    /// for_clients for a request that came through a reverse proxy: without a configured
+    /// external address, the host the client asked the proxy for is the one it can reach
+    pub fn for_clients_via(&self, origin: Option<&Origin>) -> Value {
+        let mut ice_servers = self.ice_servers.clone();
+
+        if let Some(host) = self.client_host(origin) {
+            for ice_server in &mut ice_servers {
+                ice_server.urls = ice_server.urls.iter().map(|url| {
+                    url.replace("localhost", &host)
+                       .replace("127.0.0.1", &host)
+                }).collect();
+            }
+        }
+
+        serde_json::json!({
+            "ice_servers": ice_servers,
+            "video_constraints": self.video_constraints,
+            "tls_enabled": self.tls_enabled,
+            // null when senders should not upload frames
+            "server_inference_interval_ms": self.server_inference.as_ref().map(|c| c.min_interval_ms),
+            // null without a WebTransport listener; sessions open at https://{host}:{port}/wt/{room_id}
+            "webtransport_port": self.webtransport.as_ref().and_then(|c| c.port())
+        })
+    }
+
+    /// signaling_addr followed by extra_signaling_addrs; call after `validate`
+    pub fn signaling_addrs(&self) -> Vec<SocketAddr> {
+        bind_addrs(&self.signaling_addr, &self.extra_signaling_addrs)
+    }
+
+    pub fn stun_addrs(&self) -> Vec<SocketAddr> {
+        bind_addrs(&self.stun_addr, &self.extra_stun_addrs)
+    }
+
+    pub fn turn_addrs(&self) -> Vec<SocketAddr> {
+        bind_addrs(&self.turn_addr, &self.extra_turn_addrs)
+    }
+
+    /// Host for STUN/TURN URLs: the external address, else the proxied request's host, else
+    /// this machine's LAN address
+    fn client_host(&self, origin: Option<&Origin>) -> Option<String> {
+        self.external_address.configured_host()
+            .or_else(|| origin.map(|origin| origin.hostname().to_string()))
+            .or_else(|| self.external_address.advertised_host())
+    }
+
+    /// turn: URLs clients should pair with REST API credentials for the built-in TURN server
+    pub fn turn_uris(&self, origin: Option<&Origin>) -> Vec<String> {
+        let port = self.turn_addr.parse::<SocketAddr>().map(|addr| addr.port()).unwrap_or(3479);
+        let host = self.client_host(origin).unwrap_or_else(|| "localhost".to_string());
+        let stream_port = |addr: &Option<String>| {
+            addr.as_ref()
+                .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                .map(|addr| addr.port())
+        };
+
+        let mut uris = vec![format!("turn:{}:{}?transport=udp", host, port)];
+        if let Some(tcp_port) = stream_port(&self.turn_tcp_addr) {
+            uris.push(format!("turn:{}:{}?transport=tcp", host, tcp_port));
+        }
+        if let Some(tls_port) = stream_port(&self.turns_addr) {
+            uris.push(format!("turns:{}:{}?transport=tcp", host, tls_port));
+        }
+        uris
+    }
+
+    /// Checks the whole config before any socket is bound and returns every problem found,
+    /// so an operator can fix config.json in one pass.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if let Some(path) = &self.signaling_unix_socket {
+            if path.is_empty() {
+                errors.push("signaling_unix_socket: path must not be empty".to_string());
+            } else if cfg!(not(unix)) {
+                errors.push("signaling_unix_socket: Unix sockets need a unix platform".to_string());
+            }
+        }
+
+        // Addresses must parse, and listeners of the same protocol must not share a port; the
+        // addresses of one listener may (e.g. "0.0.0.0:3478" and "[::]:3478")
+        let mut ports: Vec<(String, &str, &str, u16)> = Vec::new();
+        let extra = |name: &'static str, values: &'static str, addrs: &Vec<String>| {
+            addrs.iter()
+                .enumerate()
+                .map(move |(i, addr)| (format!("{}[{}]", values, i), name, Some(addr.clone())))
+                .collect::<Vec<_>>()
+        };
+        let listeners = [
+            ("signaling_addr".to_string(), "signaling_addr", Some(self.signaling_addr.clone())),
+            ("stun_addr".to_string(), "stun_addr", Some(self.stun_addr.clone())),
+            ("turn_addr".to_string(), "turn_addr", Some(self.turn_addr.clone())),
+            ("stun_tcp_addr".to_string(), "stun_tcp_addr", self.stun_tcp_addr.clone()),
+            ("turn_tcp_addr".to_string(), "turn_tcp_addr", self.turn_tcp_addr.clone()),
+            ("turns_addr".to_string(), "turns_addr", self.turns_addr.clone()),
+        ]
+            .into_iter()
+            .chain(extra("signaling_addr", "extra_signaling_addrs", &self.extra_signaling_addrs))
+            .chain(extra("stun_addr", "extra_stun_addrs", &self.extra_stun_addrs))
+            .chain(extra("turn_addr", "extra_turn_addrs", &self.extra_turn_addrs));
+        for (name, listener, value) in listeners {
+            let protocol = match listener {
+                "stun_addr" | "turn_addr" => "udp",
+                _ => "tcp",
+            };
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match value.parse::<SocketAddr>() {
+                Ok(addr) => ports.push((name, listener, protocol, addr.port())),
+                Err(e) => errors.push(format!("{}: '{}' is not a valid socket address ({})", name, value, e)),
+            }
+        }
+        for (i, (name_a, listener_a, protocol_a, port_a)) in ports.iter().enumerate() {
+            for (name_b, listener_b, protocol_b, port_b) in &ports[i + 1..] {
+                if listener_a != listener_b && protocol_a == protocol_b && port_a == port_b && *port_a != 0 {
+                    errors.push(format!("{} and {} both use {} port {}", name_a, name_b, protocol_a, port_a));
+                }
+            }
+        }
+
+        for (i, server) in self.ice_servers.iter().enumerate() {
+            if server.urls.is_empty() {
+                errors.push(format!("ice_servers[{}]: urls must not be empty", i));
+            }
+            for url in &server.urls {
+                if let Err(e) = validate_ice_url(url) {
+                    errors.push(format!("ice_servers[{}]: '{}' {}", i, url, e));
+                }
+            }
+        }
+
+        for (model, curve) in &self.score_calibration {
+            if let Err(e) = curve.validate() {
+                errors.push(format!("score_calibration.{}: {}", model, e));
+            }
+        }
+
+        if let Some(anomaly) = &self.anomaly {
+            if let Err(e) = anomaly.validate() {
+                errors.push(format!("anomaly: {}", e));
+            }
+        }
+
+        if let Some(tracking) = &self.tracking {
+            if let Err(e) = tracking.validate() {
+                errors.push(format!("tracking: {}", e));
+            }
+        }
+
+        if let Some(mqtt) = &self.mqtt {
+            if let Err(e) = mqtt.validate() {
+                errors.push(format!("mqtt: {}", e));
+            }
+        }
+
+        for (i, webhook) in self.webhooks.iter().enumerate() {
+            if let Err(e) = webhook.validate() {
+                errors.push(format!("webhooks[{}]: {}", i, e));
+            }
+        }
+
+        if let Some(retention) = &self.retention {
+            if let Err(e) = retention.validate() {
+                errors.push(format!("retention: {}", e));
+            }
+        }
+
+        if let Some(downsampling) = &self.downsampling {
+            if let Err(e) = downsampling.validate() {
+                errors.push(format!("downsampling: {}", e));
+            }
+        }
+
+        if let Some(upload) = &self.upload {
+            if let Err(e) = upload.validate() {
+                errors.push(format!("upload: {}", e));
+            }
+        }
+
+        if let Some(event_bus) = &self.event_bus {
+            if let Err(e) = event_bus.validate() {
+                errors.push(format!("event_bus: {}", e));
+            }
+        }
+        if let Some(wasm_plugins) = &self.wasm_plugins {
+            if let Err(e) = wasm_plugins.validate() {
+                errors.push(format!("wasm_plugins: {}", e));
+            }
+        }
+        if let Some(scripting) = &self.scripting {
+            if let Err(e) = scripting.validate() {
+                errors.push(format!("scripting: {}", e));
+            }
+        }
+
+        if let Err(e) = self.relay.validate() {
+            errors.push(format!("relay: {}", e));
+        }
+        if let Err(e) = self.inference_limits.validate() {
+            errors.push(format!("inference_limits: {}", e));
+        }
+        if let Err(e) = self.sdp.validate() {
+            errors.push(format!("sdp: {}", e));
+        }
+        if let Err(e) = self.message_size.validate() {
+            errors.push(format!("message_size: {}", e));
+        }
+        for (name, template) in &self.room_templates {
+            if let Err(e) = template.validate() {
+                errors.push(format!("room_templates.{}: {}", name, e));
+            }
+        }
+        if let Err(e) = self.quality.validate() {
+            errors.push(format!("quality: {}", e));
+        }
+        if let Err(e) = self.recording.validate() {
+            errors.push(format!("recording: {}", e));
+        }
+        if let Err(e) = self.snapshots.validate() {
+            errors.push(format!("snapshots: {}", e));
+        }
+        if let Some(backplane) = &self.backplane {
+            if let Err(e) = backplane.validate() {
+                errors.push(format!("backplane: {}", e));
+            }
+        }
+        if let Some(cluster) = &self.cluster {
+            if let Err(e) = cluster.validate() {
+                errors.push(format!("cluster: {}", e));
+            }
+            if self.backplane.is_some() {
+                errors.push("cluster: cannot be combined with backplane".to_string());
+            }
+        }
+        for (i, bridge) in self.bridges.iter().enumerate() {
+            if let Err(e) = bridge.validate() {
+                errors.push(format!("bridges[{}]: {}", i, e));
+            }
+        }
+        for (i, ingest) in self.rtsp_ingest.iter().enumerate() {
+            if let Err(e) = ingest.validate() {
+                errors.push(format!("rtsp_ingest[{}]: {}", i, e));
+            }
+        }
+        if let Some(hls) = &self.hls {
+            if let Err(e) = hls.validate() {
+                errors.push(format!("hls: {}", e));
+            }
+        }
+        if let Err(e) = self.external_address.validate() {
+            errors.push(format!("external_address: {}", e));
+        }
+
+        if let Err(e) = self.static_files.validate() {
+            errors.push(format!("static_files: {}", e));
+        }
+        if let Err(e) = self.logging.validate() {
+            errors.push(format!("logging: {}", e));
+        }
+        if let Some(telemetry) = &self.telemetry {
+            if let Err(e) = telemetry.validate() {
+                errors.push(format!("telemetry: {}", e));
+            }
+        }
+
+        if let Some(mdns) = &self.mdns {
+            if let Err(e) = mdns.validate() {
+                errors.push(format!("mdns: {}", e));
+            }
+        }
+
+        if let Some(webtransport) = &self.webtransport {
+            if let Err(e) = webtransport.validate() {
+                errors.push(format!("webtransport: {}", e));
+            }
+        }
+
+        if let Some(server_inference) = &self.server_inference {
+            if let Err(e) = server_inference.validate() {
+                errors.push(format!("server_inference: {}", e));
+            }
+        }
+
+        if matches!(&self.turn_secret, Some(secret) if secret.is_empty()) {
+            errors.push("turn_secret: must not be empty when set".to_string());
+        }
+        if matches!(&self.admin_token, Some(token) if token.is_empty()) {
+            errors.push("admin_token: must not be empty when set".to_string());
+        }
+        errors.extend(tenant::validate_all(&self.tenants));
+        if let Err(e) = self.room_quotas.validate() {
+            errors.push(format!("room_quotas: {}", e));
+        }
+        if self.room_quotas.max_rooms.is_some() {
+            errors.push("room_quotas: max_rooms only applies to tenants".to_string());
+        }
+        if self.turn_credential_ttl_secs == 0 {
+            errors.push("turn_credential_ttl_secs: must be greater than 0".to_string());
+        }
+        if self.relay_port_min == 0 || self.relay_port_min > self.relay_port_max {
+            errors.push(format!(
+                "relay_port_min/relay_port_max: {}-{} is not a valid port range",
+                self.relay_port_min, self.relay_port_max
+            ));
+        }
+        if self.turn_quota_bytes == Some(0) {
+            errors.push("turn_quota_bytes: must be greater than 0 when set".to_string());
+        }
+        if self.turn_max_bytes_per_sec == Some(0) {
+            errors.push("turn_max_bytes_per_sec: must be greater than 0 when set".to_string());
+        }
+
+        // Certificates are generated on first start, so their directory has to be writable
+        if self.tls_enabled || self.turns_addr.is_some() {
+            for (name, path) in [
+                ("tls_cert_path", &self.tls_cert_path),
+                ("tls_key_path", &self.tls_key_path),
+            ] {
+                if let Err(e) = check_path_usable(path) {
+                    errors.push(format!("{}: '{}' {}", name, path, e));
+                }
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            if let Err(e) = auth.validate() {
+                errors.push(format!("auth: {}", e));
+            }
+        }
+        if let Some(client_certs) = &self.tls_client_certs {
+            if !self.tls_enabled {
+                errors.push("tls_client_certs: client certificates need tls_enabled".to_string());
+            } else if let Err(e) = client_certs.validate() {
+                errors.push(format!("tls_client_certs: {}", e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Validates `stun:`/`stuns:`/`turn:`/`turns:` URLs (RFC 7064 / RFC 7065).
+/// Parsed listener addresses, skipping any `validate` would have rejected
+fn bind_addrs(primary: &str, extra: &[String]) -> Vec<SocketAddr> {
+    std::iter::once(primary)
+        .chain(extra.iter().map(String::as_str))
+        .filter_map(|addr| addr.parse().ok())
+        .collect()
+}
+
+fn validate_ice_url(url: &str) -> Result<(), String> {
+    let (scheme, rest) = url
+        .split_once(':')
+        .ok_or_else(|| "is missing a scheme (expected stun:, stuns:, turn: or turns:)".to_string())?;
+    if !matches!(scheme, "stun" | "stuns" | "turn" | "turns") {
+        return Err(format!("has unsupported scheme '{}'", scheme));
+    }
+
+    let (host_port, query) = match rest.split_once('?') {
+        Some((host_port, query)) => (host_port, Some(query)),
+        None => (rest, None),
+    };
+    if let Some(query) = query {
+        if scheme.starts_with("stun") {
+            return Err("must not have a query part".to_string());
+        }
+        if !matches!(query, "transport=udp" | "transport=tcp") {
+            return Err(format!("has unsupported query '{}'", query));
+        }
+    }
+
+    let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+        let (host, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| "has an unterminated IPv6 literal".to_string())?;
+        if after.is_empty() {
+            (host, None)
+        } else {
+            let port = after
+                .strip_prefix(':')
+                .ok_or_else(|| "has unexpected characters after the IPv6 literal".to_string())?;
+            (host, Some(port))
+        }
+    } else {
+        match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err("has an empty host".to_string());
+    }
+    if let Some(port) = port {
+        port.parse::<u16>()
+            .map_err(|_| format!("has an invalid port '{}'", port))?;
+    }
+    Ok(())
+}
+
+/// An existing file only needs to be readable; a missing one must be creatable.
+fn check_path_usable(path: &str) -> Result<(), String> {
+    let path = Path::new(path);
+    if path.exists() {
+        return fs::File::open(path)
+            .map(|_| ())
+            .map_err(|e| format!("is not readable ({})", e));
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    tempfile::NamedTempFile::new_in(dir)
+        .map(|_| ())
+        .map_err(|e| format!("cannot be created in '{}' ({})", dir.display(), e))
+}
+
+/// Content tag of a client config payload, used as its ETag
+pub fn config_etag(client_config: &Value) -> String {
+    Sha1::digest(client_config.to_string().as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Top-level fields of `new` that differ from `old`, and fields `old` had that `new` lacks
+pub fn config_delta(old: &Value, new: &Value) -> Value {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+
+    let changed: serde_json::Map<String, Value> = new.iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let removed: Vec<&String> = old.keys().filter(|key| !new.contains_key(*key)).collect();
+
+    serde_json::json!({ "changed": changed, "removed": removed })
+}
+
+/// Recently served client config payloads keyed by ETag, so a poll can be answered with a delta
+#[derive(Debug, Default)]
+pub struct ConfigVersions {
+    versions: VecDeque<(String, Value)>,
+}
+
+impl ConfigVersions {
+    /// Remembers a payload handed to clients and returns its ETag
+    pub fn record(&mut self, client_config: &Value) -> String {
+        let etag = config_etag(client_config);
+        if !self.versions.iter().any(|(tag, _)| *tag == etag) {
+            if self.versions.len() == CONFIG_VERSIONS_KEPT {
+                self.versions.pop_front();
+            }
+            self.versions.push_back((etag.clone(), client_config.clone()));
+        }
+        etag
+    }
+
+    pub fn get(&self, etag: &str) -> Option<&Value> {
+        self.versions.iter().find(|(tag, _)| tag == etag).map(|(_, config)| config)
+    }
 }
@@ -0,0 +1,62 @@
+// connection_log.rs
+// Connection registry for diagnosing clients across restarts: every successful join is stored
+// in the connections table with the connection ID, room, role, client IP and the User-Agent
+// of the WebSocket, long-polling or WebTransport handshake, and the row is closed when the
+// connection leaves. Rows still open when the server starts belonged to a process that
+// stopped without closing them and are marked interrupted. Listed by GET /api/admin/connections.
+
+use std::net::IpAddr;
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::persistence;
+use crate::room::Role;
+
+/// Largest page GET /api/admin/connections returns
+pub const MAX_LIST_LIMIT: u32 = 1000;
+
+/// Closes the rows a previous run left open; called once at startup after the DB is migrated
+pub fn close_interrupted(db_path: &str) {
+    match persistence::close_interrupted_connections(db_path, Utc::now()) {
+        Ok(0) => {}
+        Ok(closed) => info!("Marked {} connections left open by the previous run as interrupted", closed),
+        Err(e) => warn!("Failed to close interrupted connections: {}", e),
+    }
+}
+
+/// Records a join. Awaited, so the leave of the same connection can't be written first.
+pub async fn joined(db_path: &str, connection_id: &str, room_id: &str, role: Role, ip: Option<IpAddr>, user_agent: Option<&str>) {
+    let role = serde_json::to_value(role).ok().and_then(|role| role.as_str().map(str::to_string)).unwrap_or_default();
+    let db_path = db_path.to_string();
+    let (connection_id, room_id) = (connection_id.to_string(), room_id.to_string());
+    let ip = ip.map(|ip| ip.to_string());
+    let user_agent = user_agent.map(str::to_string);
+    let now = Utc::now();
+    let saved = tokio::task::spawn_blocking(move || {
+        persistence::save_connection_join(&db_path, &connection_id, &room_id, &role, ip.as_deref(), user_agent.as_deref(), now)
+            .map_err(|e| format!("{} in {}: {}", connection_id, room_id, e))
+    })
+    .await;
+    match saved {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("Failed to record the join of {}", e),
+        Err(e) => warn!("Failed to record a join: {}", e),
+    }
+}
+
+/// Records that the connection left the room
+pub async fn left(db_path: &str, connection_id: &str, room_id: &str) {
+    let db_path = db_path.to_string();
+    let (connection_id, room_id) = (connection_id.to_string(), room_id.to_string());
+    let now = Utc::now();
+    let saved = tokio::task::spawn_blocking(move || {
+        persistence::save_connection_leave(&db_path, &connection_id, &room_id, now)
+            .map_err(|e| format!("{} in {}: {}", connection_id, room_id, e))
+    })
+    .await;
+    match saved {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!("Failed to record the leave of {}", e),
+        Err(e) => warn!("Failed to record a leave: {}", e),
+    }
+}
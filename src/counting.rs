@@ -0,0 +1,229 @@
+// counting.rs
+// Virtual counting lines per room for footfall counting, set with PUT /api/rooms/{id}/lines.
+// Crossings are computed from tracked detections (tracker.rs, so `tracking` must be enabled):
+// when the bottom centre of a track's bbox moves from one side of a line segment to the other
+// between two results, the line counts one crossing in that direction, per class. Counts are
+// returned by GET /api/rooms/{id}/analytics and pushed to viewers as Analytics messages while
+// they change. They live in memory and start from zero when the lines are replaced.
+
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::inference::InferencePayload;
+use crate::room;
+
+/// Lines one room may have at most
+const MAX_LINES: usize = 16;
+/// A track's last position is forgotten after this long without a result (capture time)
+const POSITION_TTL_MS: i64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountingLine {
+    /// Letters, digits, '-' and '_'; unique within the room
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Segment ends, [x, y] in pixels of the analysed frame like bboxes. Crossing from the left
+    /// of from→to to its right (seen with y pointing down) counts as `forward`.
+    pub from: [f64; 2],
+    pub to: [f64; 2],
+    /// The ends are fractions (0-1) of the frame instead; results without frame_size are skipped
+    #[serde(default)]
+    pub normalized: bool,
+    /// Classes counted; any when empty
+    #[serde(default)]
+    pub classes: Vec<String>,
+}
+
+impl CountingLine {
+    pub fn validate(&self) -> Result<(), String> {
+        if !room::is_valid_room_id(&self.id) {
+            return Err(format!("line id '{}' may only contain letters, digits, '-' and '_'", self.id));
+        }
+        if self.from.iter().chain(&self.to).any(|v| !v.is_finite()) {
+            return Err(format!("line {} has a non-finite point", self.id));
+        }
+        if self.from == self.to {
+            return Err(format!("line {} needs two different points", self.id));
+        }
+        if self.normalized && self.from.iter().chain(&self.to).any(|v| !(0.0..=1.0).contains(v)) {
+            return Err(format!("line {} is normalized, so its points must be between 0 and 1", self.id));
+        }
+        Ok(())
+    }
+
+    /// The track's anchor in the line's coordinates
+    fn point(&self, anchor: [f64; 2], frame_size: Option<[u32; 2]>) -> Option<[f64; 2]> {
+        match (self.normalized, frame_size) {
+            (false, _) => Some(anchor),
+            (true, Some([w, h])) => Some([anchor[0] / w as f64, anchor[1] / h as f64]),
+            (true, None) => None,
+        }
+    }
+
+    /// Which side of the line `p` is on: > 0 right, < 0 left (y down), 0 on it
+    fn side(&self, p: [f64; 2]) -> f64 {
+        let ([ax, ay], [bx, by]) = (self.from, self.to);
+        (bx - ax) * (p[1] - ay) - (by - ay) * (p[0] - ax)
+    }
+
+    /// Direction in which the move from `a` to `b` crosses the segment, if it does
+    fn crossing(&self, a: [f64; 2], b: [f64; 2]) -> Option<Direction> {
+        let (before, after) = (self.side(a), self.side(b));
+        if before == 0.0 || after == 0.0 || (before > 0.0) == (after > 0.0) {
+            return None;
+        }
+        // The move must also pass between the segment's ends
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let cross = |p: [f64; 2]| dx * (p[1] - a[1]) - dy * (p[0] - a[0]);
+        if (cross(self.from) > 0.0) == (cross(self.to) > 0.0) {
+            return None;
+        }
+        Some(if before < 0.0 { Direction::Forward } else { Direction::Backward })
+    }
+}
+
+/// Checks a PUT body: the list and each line
+pub fn validate_all(lines: &[CountingLine]) -> Result<(), String> {
+    if lines.len() > MAX_LINES {
+        return Err(format!("at most {} lines", MAX_LINES));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        line.validate()?;
+        if lines[..i].iter().any(|other| other.id == line.id) {
+            return Err(format!("duplicate line id {}", line.id));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Counts {
+    pub forward: u64,
+    pub backward: u64,
+}
+
+impl Counts {
+    fn add(&mut self, direction: Direction) {
+        match direction {
+            Direction::Forward => self.forward += 1,
+            Direction::Backward => self.backward += 1,
+        }
+    }
+}
+
+/// One line's totals in GET /api/rooms/{id}/analytics and Analytics messages
+#[derive(Debug, Clone, Serialize)]
+pub struct LineCounts {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub forward: u64,
+    pub backward: u64,
+    pub classes: BTreeMap<String, Counts>,
+}
+
+#[derive(Debug, Clone)]
+struct Position {
+    anchor: [f64; 2],
+    frame_size: Option<[u32; 2]>,
+    seen_ms: i64,
+}
+
+/// A room's lines, their counts and the last position of every track
+#[derive(Debug, Clone, Default)]
+pub struct LineCounter {
+    lines: Vec<CountingLine>,
+    /// line_id -> class -> counts
+    counts: HashMap<String, BTreeMap<String, Counts>>,
+    /// (source_id, track_id) -> last position
+    positions: HashMap<(String, u64), Position>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Counts changed since the last Analytics message
+    changed: bool,
+}
+
+impl LineCounter {
+    pub fn lines(&self) -> &[CountingLine] {
+        &self.lines
+    }
+
+    /// Replaces the lines and starts counting from zero
+    pub fn set(&mut self, lines: Vec<CountingLine>) {
+        self.lines = lines;
+        self.counts.clear();
+        self.positions.clear();
+        self.since = Some(chrono::Utc::now());
+        self.changed = true;
+    }
+
+    /// Counts the crossings of a tracked result of `source_id`
+    pub fn observe(&mut self, source_id: &str, payload: &Value) {
+        if self.lines.is_empty() {
+            return;
+        }
+        let Ok(payload) = serde_json::from_value::<InferencePayload>(payload.clone()) else {
+            return;
+        };
+        let now = payload.timestamp;
+        self.positions.retain(|_, position| now - position.seen_ms <= POSITION_TTL_MS);
+        for detection in &payload.predictions {
+            let Some(track_id) = detection.track_id else { continue };
+            let [x, y, width, height] = detection.bbox;
+            let position = Position { anchor: [x + width / 2.0, y + height], frame_size: payload.frame_size, seen_ms: now };
+            let previous = self.positions.insert((source_id.to_string(), track_id), position.clone());
+            let Some(previous) = previous else { continue };
+            for line in &self.lines {
+                if !line.classes.is_empty() && !line.classes.contains(&detection.class) {
+                    continue;
+                }
+                let (Some(a), Some(b)) = (line.point(previous.anchor, previous.frame_size), line.point(position.anchor, position.frame_size)) else {
+                    continue;
+                };
+                if let Some(direction) = line.crossing(a, b) {
+                    self.counts.entry(line.id.clone()).or_default().entry(detection.class.clone()).or_default().add(direction);
+                    self.changed = true;
+                }
+            }
+        }
+    }
+
+    pub fn forget_source(&mut self, source_id: &str) {
+        self.positions.retain(|(source, _), _| source != source_id);
+    }
+
+    /// Totals per line, as served by the analytics endpoint
+    pub fn snapshot(&self) -> Value {
+        let lines: Vec<LineCounts> = self.lines.iter()
+            .map(|line| {
+                let classes = self.counts.get(&line.id).cloned().unwrap_or_default();
+                LineCounts {
+                    id: line.id.clone(),
+                    name: line.name.clone(),
+                    forward: classes.values().map(|counts| counts.forward).sum(),
+                    backward: classes.values().map(|counts| counts.backward).sum(),
+                    classes,
+                }
+            })
+            .collect();
+        serde_json::json!({
+            "since": self.since.map(|since| since.to_rfc3339()),
+            "lines": lines,
+        })
+    }
+
+    /// The snapshot if counts changed since the previous call, for Analytics messages
+    pub fn take_changed(&mut self) -> Option<Value> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        Some(self.snapshot())
+    }
+}
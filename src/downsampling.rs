@@ -0,0 +1,72 @@
+// downsampling.rs
+// Keeps long-running deployments queryable: every interval_secs the detections of finished
+// minutes are rolled up into per-minute summaries (detections per class and the highest score,
+// per room and source), and finished hours into per-hour ones, in their own SQLite tables. Raw
+// rows are still pruned by the retention policy; the summaries have their own, usually much
+// longer, maximum ages. GET /api/rooms/{id}/summary reads them.
+
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::persistence::{self, Resolution};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownsamplingConfig {
+    /// How often finished minutes and hours are rolled up
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Per-minute summaries older than this are deleted
+    #[serde(default)]
+    pub minute_max_age_days: Option<u32>,
+    /// Per-hour summaries older than this are deleted
+    #[serde(default)]
+    pub hour_max_age_days: Option<u32>,
+}
+
+fn default_interval_secs() -> u64 { 60 }
+
+impl DownsamplingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_secs == 0 {
+            return Err("interval_secs must be greater than 0".to_string());
+        }
+        if self.minute_max_age_days == Some(0) || self.hour_max_age_days == Some(0) {
+            return Err("minute_max_age_days and hour_max_age_days must be greater than 0 when set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Rolls up every `interval_secs`; database work runs on the blocking pool
+pub fn spawn(config: DownsamplingConfig, db_path: String) {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            let (config, db_path) = (config.clone(), db_path.clone());
+            let result = tokio::task::spawn_blocking(move || apply(&config, &db_path, Utc::now())).await;
+            if let Err(e) = result {
+                error!("Downsampling task failed: {}", e);
+            }
+        }
+    });
+}
+
+fn apply(config: &DownsamplingConfig, db_path: &str, now: DateTime<Utc>) {
+    match persistence::rollup_detections(db_path, now) {
+        Ok((minutes, hours)) => debug!("Rolled up {} minute and {} hour summaries", minutes, hours),
+        Err(e) => error!("Failed to roll up detections: {}", e),
+    }
+
+    let limits = [(Resolution::Minute, config.minute_max_age_days), (Resolution::Hour, config.hour_max_age_days)];
+    for (resolution, max_age_days) in limits {
+        let Some(days) = max_age_days else { continue };
+        match persistence::prune_summaries(db_path, resolution, now - chrono::Duration::days(days as i64)) {
+            Ok(0) => {}
+            Ok(deleted) => info!("Removed {} expired {} summaries", deleted, resolution.name()),
+            Err(e) => error!("Failed to prune {} summaries: {}", resolution.name(), e),
+        }
+    }
+}
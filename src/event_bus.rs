@@ -0,0 +1,233 @@
+// event_bus.rs
+// Optional event-bus publisher for stream-processing pipelines: room lifecycle events (the same
+// ones webhooks get) and every inference result are published to Kafka topics or NATS subjects.
+// Each backend sits behind its cargo feature (`kafka` links librdkafka, `nats` is pure Rust).
+// Messages go through a bounded queue to one publishing task, so a slow broker never holds up
+// signaling; when the queue is full new messages are dropped with a warning. With a backplane,
+// each instance publishes what happens locally.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+/// Messages waiting for the broker before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusKind {
+    Kafka,
+    Nats,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventBusConfig {
+    pub kind: BusKind,
+    /// Kafka bootstrap servers (host:port) or NATS server URLs
+    pub servers: Vec<String>,
+    /// Topic or subject of inference results; {room_id} and {source_id} are replaced. Defaults
+    /// to `ws2infer.inference` on Kafka (keyed by room) and `ws2infer.inference.{room_id}` on NATS
+    #[serde(default)]
+    pub inference_subject: Option<String>,
+    /// Topic or subject of lifecycle events; {event} and {room_id} are replaced. Defaults to
+    /// `ws2infer.events` on Kafka and `ws2infer.events.{event}` on NATS
+    #[serde(default)]
+    pub events_subject: Option<String>,
+    #[serde(default = "default_true")]
+    pub inference: bool,
+    #[serde(default = "default_true")]
+    pub events: bool,
+    /// NATS user and password
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// NATS token
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Extra librdkafka producer settings, e.g. "security.protocol" or "compression.type"
+    #[serde(default)]
+    pub kafka_properties: BTreeMap<String, String>,
+}
+
+fn default_true() -> bool { true }
+
+impl EventBusConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.servers.is_empty() || self.servers.iter().any(String::is_empty) {
+            return Err("servers must list at least one non-empty address".to_string());
+        }
+        if [&self.inference_subject, &self.events_subject].iter().any(|subject| matches!(subject, Some(s) if s.is_empty())) {
+            return Err("inference_subject and events_subject must not be empty when set".to_string());
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err("password requires username".to_string());
+        }
+        match self.kind {
+            BusKind::Kafka if !cfg!(feature = "kafka") => Err("this build does not include the kafka feature".to_string()),
+            BusKind::Nats if !cfg!(feature = "nats") => Err("this build does not include the nats feature".to_string()),
+            BusKind::Kafka if self.username.is_some() || self.token.is_some() => {
+                Err("username and token are for NATS; use kafka_properties (sasl.username, ...) on Kafka".to_string())
+            }
+            BusKind::Nats if !self.kafka_properties.is_empty() => Err("kafka_properties are for Kafka only".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn inference_subject(&self) -> &str {
+        match (&self.inference_subject, self.kind) {
+            (Some(subject), _) => subject,
+            (None, BusKind::Kafka) => "ws2infer.inference",
+            (None, BusKind::Nats) => "ws2infer.inference.{room_id}",
+        }
+    }
+
+    fn events_subject(&self) -> &str {
+        match (&self.events_subject, self.kind) {
+            (Some(subject), _) => subject,
+            (None, BusKind::Kafka) => "ws2infer.events",
+            (None, BusKind::Nats) => "ws2infer.events.{event}",
+        }
+    }
+
+    /// Makes a client-supplied ID one valid topic name part or subject token
+    fn token(&self, id: &str) -> String {
+        match self.kind {
+            BusKind::Kafka => id.replace(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')), "_"),
+            BusKind::Nats => id.replace(|c: char| c == '.' || c == '*' || c == '>' || c.is_whitespace(), "_"),
+        }
+    }
+}
+
+/// One message for the broker; `key` partitions Kafka topics and is unused on NATS
+#[cfg_attr(not(any(feature = "kafka", feature = "nats")), allow(dead_code))]
+struct Outgoing {
+    subject: String,
+    key: String,
+    body: String,
+}
+
+/// Publisher of one server; clones share the queue
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    config: EventBusConfig,
+    queue: mpsc::Sender<Outgoing>,
+}
+
+impl EventBus {
+    /// Connects in the background and starts the publishing task
+    pub fn start(config: EventBusConfig) -> Self {
+        let (queue, outgoing) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::task::spawn(backend::run(config.clone(), outgoing));
+        Self { config, queue }
+    }
+
+    /// Drops the message when the queue is full, or silently once the publisher has stopped (it
+    /// logged why)
+    fn enqueue(&self, message: Outgoing) {
+        if let Err(mpsc::error::TrySendError::Full(message)) = self.queue.try_send(message) {
+            warn!("Event bus queue is full; dropping a message for {}", message.subject);
+        }
+    }
+
+    /// Publishes a room lifecycle event (see webhooks.rs for the events)
+    pub fn publish_event(&self, event: &str, room_id: &str, detail: &Value) {
+        if !self.config.events {
+            return;
+        }
+        let subject = self.config.events_subject()
+            .replace("{event}", &self.config.token(event))
+            .replace("{room_id}", &self.config.token(room_id));
+        let body = serde_json::json!({
+            "event": event,
+            "room_id": room_id,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "data": detail,
+        });
+        self.enqueue(Outgoing { subject, key: room_id.to_string(), body: body.to_string() });
+    }
+
+    /// Publishes one normalized inference result
+    pub fn publish_inference(&self, room_id: &str, source_id: &str, payload: &Value) {
+        if !self.config.inference {
+            return;
+        }
+        let subject = self.config.inference_subject()
+            .replace("{room_id}", &self.config.token(room_id))
+            .replace("{source_id}", &self.config.token(source_id));
+        let body = serde_json::json!({
+            "room_id": room_id,
+            "source_id": source_id,
+            "data": payload,
+        });
+        self.enqueue(Outgoing { subject, key: room_id.to_string(), body: body.to_string() });
+    }
+}
+
+mod backend {
+    use super::*;
+
+    pub async fn run(config: EventBusConfig, outgoing: mpsc::Receiver<Outgoing>) {
+        let result = match config.kind {
+            BusKind::Kafka => kafka(&config, outgoing).await,
+            BusKind::Nats => nats(&config, outgoing).await,
+        };
+        if let Err(e) = result {
+            error!("Event bus publisher stopped: {}", e);
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    async fn kafka(config: &EventBusConfig, mut outgoing: mpsc::Receiver<Outgoing>) -> Result<(), String> {
+        use rdkafka::config::ClientConfig;
+        use rdkafka::producer::{FutureProducer, FutureRecord};
+
+        let mut client_config = ClientConfig::new();
+        client_config.set("bootstrap.servers", config.servers.join(","));
+        for (key, value) in &config.kafka_properties {
+            client_config.set(key, value);
+        }
+        let producer: FutureProducer = client_config.create().map_err(|e| e.to_string())?;
+        tracing::info!("Publishing to Kafka at {}", config.servers.join(","));
+        while let Some(message) = outgoing.recv().await {
+            let record = FutureRecord::to(&message.subject).key(&message.key).payload(&message.body);
+            if let Err((e, _)) = producer.send(record, std::time::Duration::from_secs(5)).await {
+                warn!("Failed to publish to Kafka topic {}: {}", message.subject, e);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    async fn kafka(_config: &EventBusConfig, _outgoing: mpsc::Receiver<Outgoing>) -> Result<(), String> {
+        Err("this build does not include the kafka feature".to_string())
+    }
+
+    #[cfg(feature = "nats")]
+    async fn nats(config: &EventBusConfig, mut outgoing: mpsc::Receiver<Outgoing>) -> Result<(), String> {
+        let mut options = async_nats::ConnectOptions::new();
+        if let Some(username) = &config.username {
+            options = options.user_and_password(username.clone(), config.password.clone().unwrap_or_default());
+        }
+        if let Some(token) = &config.token {
+            options = options.token(token.clone());
+        }
+        // Keeps reconnecting in the background once the first connection succeeds
+        let servers = config.servers.join(",");
+        let client = options.connect(servers.as_str()).await.map_err(|e| e.to_string())?;
+        tracing::info!("Publishing to NATS at {}", servers);
+        while let Some(message) = outgoing.recv().await {
+            if let Err(e) = client.publish(message.subject.clone(), message.body.into()).await {
+                warn!("Failed to publish to NATS subject {}: {}", message.subject, e);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "nats"))]
+    async fn nats(_config: &EventBusConfig, _outgoing: mpsc::Receiver<Outgoing>) -> Result<(), String> {
+        Err("this build does not include the nats feature".to_string())
+    }
+}
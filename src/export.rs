@@ -0,0 +1,260 @@
+// export.rs
+// Stored inference data in analyst-friendly formats for GET /api/rooms/{id}/inference/export.
+// CSV and Parquet are flattened to one row per detection with the bbox split into columns;
+// JSONL keeps the stored records as they are. CSV and JSONL are streamed straight from SQLite,
+// Parquet needs its footer written last and is assembled in memory before it is sent.
+
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use tracing::error;
+use parquet::arrow::ArrowWriter;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use warp::hyper::Body;
+
+use crate::persistence;
+
+/// Rows per streamed chunk (CSV/JSONL) and per record batch (Parquet)
+const ROWS_PER_CHUNK: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "csv" => Some(ExportFormat::Csv),
+            "parquet" => Some(ExportFormat::Parquet),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// One detection of one stored result; results without detections become a row with empty
+/// detection columns so empty frames stay visible in the export
+#[derive(Debug, Clone, Default)]
+struct ExportRow {
+    source_id: String,
+    stored_at: String,
+    timestamp: Option<i64>,
+    model: Option<String>,
+    class: Option<String>,
+    score: Option<f64>,
+    bbox: [Option<f64>; 4],
+}
+
+const CSV_HEADER: &str = "room_id,source_id,stored_at,timestamp,model,class,score,bbox_x,bbox_y,bbox_width,bbox_height\n";
+
+fn flatten(source_id: &str, stored_at: &str, payload: &Value) -> Vec<ExportRow> {
+    let base = ExportRow {
+        source_id: source_id.to_string(),
+        stored_at: stored_at.to_string(),
+        timestamp: payload.get("timestamp").and_then(Value::as_i64),
+        model: payload.get("model").and_then(Value::as_str).map(str::to_string),
+        ..Default::default()
+    };
+    let detections = payload.get("predictions")
+        .or_else(|| payload.get("detections"))
+        .and_then(Value::as_array)
+        .filter(|items| !items.is_empty());
+
+    match detections {
+        Some(items) => items.iter().map(|item| {
+            let mut bbox = [None; 4];
+            if let Some(values) = item.get("bbox").and_then(Value::as_array) {
+                for (slot, value) in bbox.iter_mut().zip(values) {
+                    *slot = value.as_f64();
+                }
+            }
+            ExportRow {
+                class: item.get("class").and_then(Value::as_str).map(str::to_string),
+                score: item.get("score").and_then(Value::as_f64),
+                bbox,
+                ..base.clone()
+            }
+        }).collect(),
+        None => vec![base],
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line(room_id: &str, row: &ExportRow) -> String {
+    let number = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    let mut fields = vec![
+        csv_field(room_id),
+        csv_field(&row.source_id),
+        csv_field(&row.stored_at),
+        row.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+        csv_field(row.model.as_deref().unwrap_or("")),
+        csv_field(row.class.as_deref().unwrap_or("")),
+        number(row.score),
+    ];
+    fields.extend(row.bbox.iter().map(|v| number(*v)));
+    let mut line = fields.join(",");
+    line.push('\n');
+    line
+}
+
+/// Response body for the room's stored results in the requested format
+pub fn body(db_path: String, room_id: String, format: ExportFormat) -> Body {
+    let (tx, mut rx) = mpsc::channel::<Result<Vec<u8>, std::io::Error>>(4);
+
+    tokio::task::spawn_blocking(move || {
+        let result = match format {
+            ExportFormat::Parquet => write_parquet(&db_path, &room_id).map(|bytes| {
+                let _ = tx.blocking_send(Ok(bytes));
+            }),
+            _ => stream_text(&db_path, &room_id, format, &tx),
+        };
+        if let Err(e) = result {
+            // Already-sent chunks can't be retracted; failing the body aborts the download
+            error!("Inference export for room {} failed: {}", room_id, e);
+            let _ = tx.blocking_send(Err(std::io::Error::other(e)));
+        }
+    });
+
+    let stream = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+    Body::wrap_stream(stream)
+}
+
+fn stream_text(
+    db_path: &str,
+    room_id: &str,
+    format: ExportFormat,
+    tx: &mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+) -> Result<(), String> {
+    let mut chunk = String::new();
+    let mut rows = 0;
+    if format == ExportFormat::Csv {
+        chunk.push_str(CSV_HEADER);
+    }
+
+    let mut receiver_open = true;
+    persistence::for_each_room_inference(db_path, room_id, |source_id, ts, payload| {
+        if format == ExportFormat::Csv {
+            for row in flatten(source_id, ts, &payload) {
+                chunk.push_str(&csv_line(room_id, &row));
+                rows += 1;
+            }
+        } else {
+            let record = serde_json::json!({
+                "room_id": room_id,
+                "source_id": source_id,
+                "payload": payload,
+                "ts": ts
+            });
+            chunk.push_str(&record.to_string());
+            chunk.push('\n');
+            rows += 1;
+        }
+
+        if rows >= ROWS_PER_CHUNK {
+            rows = 0;
+            // The client went away: stop reading the database
+            receiver_open = tx.blocking_send(Ok(std::mem::take(&mut chunk).into_bytes())).is_ok();
+        }
+        receiver_open
+    }).map_err(|e| e.to_string())?;
+
+    if receiver_open && !chunk.is_empty() {
+        let _ = tx.blocking_send(Ok(chunk.into_bytes()));
+    }
+    Ok(())
+}
+
+fn write_parquet(db_path: &str, room_id: &str) -> Result<Vec<u8>, String> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("room_id", DataType::Utf8, false),
+        Field::new("source_id", DataType::Utf8, false),
+        Field::new("stored_at", DataType::Utf8, false),
+        Field::new("timestamp", DataType::Int64, true),
+        Field::new("model", DataType::Utf8, true),
+        Field::new("class", DataType::Utf8, true),
+        Field::new("score", DataType::Float64, true),
+        Field::new("bbox_x", DataType::Float64, true),
+        Field::new("bbox_y", DataType::Float64, true),
+        Field::new("bbox_width", DataType::Float64, true),
+        Field::new("bbox_height", DataType::Float64, true),
+    ]));
+
+    let mut buffer = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buffer, schema.clone(), None).map_err(|e| e.to_string())?;
+    let mut pending: Vec<ExportRow> = Vec::new();
+    let mut write_error = None;
+
+    let flush = |rows: &mut Vec<ExportRow>, writer: &mut ArrowWriter<&mut Vec<u8>>| -> Result<(), String> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let batch = record_batch(schema.clone(), room_id, rows)?;
+        rows.clear();
+        writer.write(&batch).map_err(|e| e.to_string())
+    };
+
+    persistence::for_each_room_inference(db_path, room_id, |source_id, ts, payload| {
+        pending.extend(flatten(source_id, ts, &payload));
+        if pending.len() >= ROWS_PER_CHUNK {
+            if let Err(e) = flush(&mut pending, &mut writer) {
+                write_error = Some(e);
+                return false;
+            }
+        }
+        true
+    }).map_err(|e| e.to_string())?;
+
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+    flush(&mut pending, &mut writer)?;
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(buffer)
+}
+
+fn record_batch(schema: Arc<Schema>, room_id: &str, rows: &[ExportRow]) -> Result<RecordBatch, String> {
+    let bbox = |i: usize| -> ArrayRef {
+        Arc::new(rows.iter().map(|r| r.bbox[i]).collect::<Float64Array>())
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![room_id; rows.len()])),
+        Arc::new(rows.iter().map(|r| Some(r.source_id.as_str())).collect::<StringArray>()),
+        Arc::new(rows.iter().map(|r| Some(r.stored_at.as_str())).collect::<StringArray>()),
+        Arc::new(rows.iter().map(|r| r.timestamp).collect::<Int64Array>()),
+        Arc::new(rows.iter().map(|r| r.model.as_deref()).collect::<StringArray>()),
+        Arc::new(rows.iter().map(|r| r.class.as_deref()).collect::<StringArray>()),
+        Arc::new(rows.iter().map(|r| r.score).collect::<Float64Array>()),
+        bbox(0),
+        bbox(1),
+        bbox(2),
+        bbox(3),
+    ];
+    RecordBatch::try_new(schema, columns).map_err(|e| e.to_string())
+}
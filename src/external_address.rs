@@ -0,0 +1,143 @@
+// external_address.rs
+// The address clients should use to reach this server from outside its NAT or container.
+// get_local_ip() only sees the host's own interface, which is a private or Docker bridge address
+// in those setups; a configured IP or hostname (or one learned at startup by asking a public STUN
+// server for our mapped address) is used instead to rewrite localhost in ice_servers and as the
+// IP in TURN's XOR-RELAYED-ADDRESS.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::network;
+use crate::stun_proto::{self, Attribute, Message, MessageType, MAGIC_COOKIE};
+
+const DETECT_TIMEOUT: Duration = Duration::from_secs(3);
+const DETECT_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExternalAddressConfig {
+    /// Public IP of this server, used for TURN relayed addresses and ICE URLs
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    /// Public hostname for ICE URLs; takes precedence over the IP there
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Public STUN server ("host:port") asked for our mapped address at startup when `ip` is unset
+    #[serde(default)]
+    pub detect_via: Option<String>,
+    /// Learned through `detect_via` at startup; kept across config reloads
+    #[serde(skip)]
+    pub detected: Option<IpAddr>,
+}
+
+impl ExternalAddressConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(hostname) = &self.hostname {
+            if hostname.is_empty() || hostname.contains(['/', ':', ' ']) {
+                return Err(format!("hostname '{}' must be a bare host name", hostname));
+            }
+        }
+        if let Some(server) = &self.detect_via {
+            match server.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+                _ => return Err(format!("detect_via '{}' must be host:port", server)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured IP, or the one detected at startup
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip.or(self.detected)
+    }
+
+    /// The public hostname or IP when one is configured or was detected
+    pub fn configured_host(&self) -> Option<String> {
+        self.hostname.clone().or_else(|| self.ip().map(url_host))
+    }
+
+    /// Host clients should put in STUN/TURN URLs: the public hostname or IP, falling back to
+    /// this machine's LAN address
+    pub fn advertised_host(&self) -> Option<String> {
+        self.configured_host().or_else(|| network::get_local_ip().map(url_host))
+    }
+}
+
+/// An IP as it appears in a URL; IPv6 needs brackets
+fn url_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// Learns the public IP through `detect_via` when no IP is configured. Failures are logged and
+/// leave the LAN address in use.
+pub async fn init(config: &mut ExternalAddressConfig) {
+    if let Some(ip) = config.ip {
+        info!("Advertising external IP {}", ip);
+        return;
+    }
+    let server = match config.detect_via.clone() {
+        Some(server) => server,
+        None => return,
+    };
+    match detect(&server).await {
+        Ok(ip) => {
+            info!("Detected external IP {} via {}", ip, server);
+            config.detected = Some(ip);
+        }
+        Err(e) => warn!("External IP detection via {} failed: {}", server, e),
+    }
+}
+
+/// Sends a STUN Binding request to `server` and returns the mapped IP from its response
+pub async fn detect(server: &str) -> std::io::Result<IpAddr> {
+    let server_addr = tokio::net::lookup_host(server)
+        .await?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no IPv4 address for STUN server"))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+
+    let mut transaction = [0u8; 16];
+    transaction[..4].copy_from_slice(&MAGIC_COOKIE);
+    transaction[4..].copy_from_slice(&uuid::Uuid::new_v4().as_bytes()[..12]);
+    let request = Message::new(MessageType::BindingRequest, transaction).encode();
+
+    let mut buf = [0u8; 512];
+    for _ in 0..DETECT_ATTEMPTS {
+        socket.send_to(&request, server_addr).await?;
+        match tokio::time::timeout(DETECT_TIMEOUT, socket.recv_from(&mut buf)).await {
+            Ok(Ok((len, _))) => {
+                if let Some(ip) = mapped_ip(&buf[..len], &transaction) {
+                    return Ok(ip);
+                }
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => continue,
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no usable response from STUN server"))
+}
+
+/// The IP in a Binding response's XOR-MAPPED-ADDRESS (or legacy MAPPED-ADDRESS)
+fn mapped_ip(response: &[u8], transaction: &[u8; 16]) -> Option<IpAddr> {
+    let header = stun_proto::decode_header(response).ok()?;
+    if header.message_type != MessageType::BindingResponse || header.transaction != *transaction {
+        return None;
+    }
+
+    let mut mapped = None;
+    // Attributes we can't read (e.g. vendor extensions) are skipped rather than failing the response
+    for raw in stun_proto::raw_attributes(response) {
+        match raw.ok()?.decode() {
+            Ok(Attribute::XorMappedAddress(addr)) => return Some(addr.ip()),
+            Ok(Attribute::MappedAddress(addr)) => mapped = Some(addr.ip()),
+            _ => {}
+        }
+    }
+    mapped
+}
@@ -0,0 +1,310 @@
+// fmp4.rs
+// Minimal fragmented MP4 (CMAF) muxer for a single H.264 video track, used by the HLS egress.
+// The init segment carries the SPS and PPS in an avcC box; each fragment is one moof+mdat pair
+// whose samples are length-prefixed NAL units, so fragments can be served as LL-HLS parts and
+// concatenated into segments. Times are in the 90 kHz RTP clock.
+
+/// Timescale of the track, the H.264 RTP clock rate
+pub const TIMESCALE: u32 = 90_000;
+
+/// sample_depends_on = 2 (does not depend on others)
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// sample_depends_on = 1, sample_is_non_sync_sample = 1
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+/// tfhd: offsets are relative to the start of the moof
+const DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+/// trun: data-offset, sample-duration, sample-size and sample-flags present
+const TRUN_FLAGS: u32 = 0x0001 | 0x0100 | 0x0200 | 0x0400;
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub struct Sample {
+    /// Ticks until the next sample
+    pub duration: u32,
+    pub keyframe: bool,
+    /// Access unit as 4-byte length-prefixed NAL units
+    pub data: Vec<u8>,
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+}
+
+fn full_box(out: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) {
+    let mut body = Vec::with_capacity(payload.len() + 4);
+    body.extend_from_slice(&(((version as u32) << 24) | flags).to_be_bytes());
+    body.extend_from_slice(payload);
+    write_box(out, kind, &body);
+}
+
+fn u16s(out: &mut Vec<u8>, values: &[u16]) {
+    for value in values {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn u32s(out: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// ftyp and moov for one H.264 track; `sps` and `pps` are NAL units without start codes
+pub fn init_segment(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let (width, height) = sps_size(sps).unwrap_or((0, 0));
+    let mut out = Vec::new();
+
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"iso6");
+    u32s(&mut ftyp, &[0]);
+    ftyp.extend_from_slice(b"iso6isomavc1mp41");
+    write_box(&mut out, b"ftyp", &ftyp);
+
+    let mut mvhd = Vec::new();
+    u32s(&mut mvhd, &[0, 0, 1000, 0, 0x0001_0000]);
+    u16s(&mut mvhd, &[0x0100, 0]);
+    u32s(&mut mvhd, &[0, 0]);
+    u32s(&mut mvhd, &MATRIX);
+    u32s(&mut mvhd, &[0; 6]);
+    u32s(&mut mvhd, &[2]); // next_track_ID
+
+    let mut tkhd = Vec::new();
+    u32s(&mut tkhd, &[0, 0, 1, 0, 0, 0, 0]);
+    u16s(&mut tkhd, &[0, 0, 0, 0]);
+    u32s(&mut tkhd, &MATRIX);
+    u32s(&mut tkhd, &[(width as u32) << 16, (height as u32) << 16]);
+
+    let mut mdhd = Vec::new();
+    u32s(&mut mdhd, &[0, 0, TIMESCALE, 0]);
+    u16s(&mut mdhd, &[0x55C4, 0]); // "und"
+
+    let mut hdlr = Vec::new();
+    u32s(&mut hdlr, &[0]);
+    hdlr.extend_from_slice(b"vide");
+    u32s(&mut hdlr, &[0, 0, 0]);
+    hdlr.extend_from_slice(b"VideoHandler\0");
+
+    let mut vmhd = Vec::new();
+    u16s(&mut vmhd, &[0, 0, 0, 0]);
+
+    let mut url = Vec::new();
+    full_box(&mut url, b"url ", 0, 1, &[]);
+    let mut dref = Vec::new();
+    u32s(&mut dref, &[1]);
+    dref.extend_from_slice(&url);
+    let mut dinf = Vec::new();
+    full_box(&mut dinf, b"dref", 0, 0, &dref);
+
+    let mut avcc = vec![1, sps.get(1).copied().unwrap_or(0x42), sps.get(2).copied().unwrap_or(0), sps.get(3).copied().unwrap_or(0x1F), 0xFF, 0xE1];
+    u16s(&mut avcc, &[sps.len() as u16]);
+    avcc.extend_from_slice(sps);
+    avcc.push(1);
+    u16s(&mut avcc, &[pps.len() as u16]);
+    avcc.extend_from_slice(pps);
+
+    let mut avc1 = vec![0; 6];
+    u16s(&mut avc1, &[1, 0, 0]); // data_reference_index
+    u32s(&mut avc1, &[0, 0, 0]);
+    u16s(&mut avc1, &[width, height]);
+    u32s(&mut avc1, &[0x0048_0000, 0x0048_0000, 0]);
+    u16s(&mut avc1, &[1]); // frame_count
+    avc1.extend_from_slice(&[0; 32]); // compressorname
+    u16s(&mut avc1, &[0x0018, 0xFFFF]);
+    write_box(&mut avc1, b"avcC", &avcc);
+
+    let mut stsd = Vec::new();
+    u32s(&mut stsd, &[1]);
+    write_box(&mut stsd, b"avc1", &avc1);
+
+    let mut stbl = Vec::new();
+    full_box(&mut stbl, b"stsd", 0, 0, &stsd);
+    full_box(&mut stbl, b"stts", 0, 0, &[0; 4]);
+    full_box(&mut stbl, b"stsc", 0, 0, &[0; 4]);
+    full_box(&mut stbl, b"stsz", 0, 0, &[0; 8]);
+    full_box(&mut stbl, b"stco", 0, 0, &[0; 4]);
+
+    let mut minf = Vec::new();
+    full_box(&mut minf, b"vmhd", 0, 1, &vmhd);
+    write_box(&mut minf, b"dinf", &dinf);
+    write_box(&mut minf, b"stbl", &stbl);
+
+    let mut mdia = Vec::new();
+    full_box(&mut mdia, b"mdhd", 0, 0, &mdhd);
+    full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+    write_box(&mut mdia, b"minf", &minf);
+
+    let mut trak = Vec::new();
+    full_box(&mut trak, b"tkhd", 0, 3, &tkhd);
+    write_box(&mut trak, b"mdia", &mdia);
+
+    let mut trex = Vec::new();
+    u32s(&mut trex, &[1, 1, 0, 0, 0]);
+    let mut mvex = Vec::new();
+    full_box(&mut mvex, b"trex", 0, 0, &trex);
+
+    let mut moov = Vec::new();
+    full_box(&mut moov, b"mvhd", 0, 0, &mvhd);
+    write_box(&mut moov, b"trak", &trak);
+    write_box(&mut moov, b"mvex", &mvex);
+    write_box(&mut out, b"moov", &moov);
+    out
+}
+
+fn moof(sequence: u32, base_decode_time: u64, samples: &[Sample], data_offset: u32) -> Vec<u8> {
+    let mut mfhd = Vec::new();
+    u32s(&mut mfhd, &[sequence]);
+
+    let mut trun = Vec::new();
+    u32s(&mut trun, &[samples.len() as u32, data_offset]);
+    for sample in samples {
+        let flags = if sample.keyframe { SYNC_SAMPLE_FLAGS } else { NON_SYNC_SAMPLE_FLAGS };
+        u32s(&mut trun, &[sample.duration, sample.data.len() as u32, flags]);
+    }
+
+    let mut traf = Vec::new();
+    full_box(&mut traf, b"tfhd", 0, DEFAULT_BASE_IS_MOOF, &1u32.to_be_bytes());
+    full_box(&mut traf, b"tfdt", 1, 0, &base_decode_time.to_be_bytes());
+    full_box(&mut traf, b"trun", 0, TRUN_FLAGS, &trun);
+
+    let mut moof = Vec::new();
+    full_box(&mut moof, b"mfhd", 0, 0, &mfhd);
+    write_box(&mut moof, b"traf", &traf);
+    let mut out = Vec::new();
+    write_box(&mut out, b"moof", &moof);
+    out
+}
+
+/// One moof+mdat fragment of consecutive samples starting at `base_decode_time`
+pub fn fragment(sequence: u32, base_decode_time: u64, samples: &[Sample]) -> Vec<u8> {
+    // The moof's size doesn't depend on the offset it carries
+    let moof_size = moof(sequence, base_decode_time, samples, 0).len() as u32;
+    let mut out = moof(sequence, base_decode_time, samples, moof_size + 8);
+    let mdat: Vec<u8> = samples.iter().flat_map(|sample| sample.data.iter().copied()).collect();
+    write_box(&mut out, b"mdat", &mdat);
+    out
+}
+
+/// Exp-Golomb reader over an RBSP (emulation prevention bytes already removed)
+struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitReader {
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some((value << 1) | self.bit()?))
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.bits(zeros)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let value = self.ue()?;
+        Some(if value % 2 == 1 { (value / 2 + 1) as i32 } else { -((value / 2) as i32) })
+    }
+}
+
+/// Width and height from an SPS NAL unit; None when it is truncated or malformed
+pub fn sps_size(sps: &[u8]) -> Option<(u16, u16)> {
+    let mut rbsp = Vec::with_capacity(sps.len());
+    let mut zeros = 0;
+    for &byte in sps.get(1..)? {
+        if byte == 3 && zeros >= 2 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    let mut reader = BitReader { data: rbsp, pos: 0 };
+
+    let profile_idc = reader.bits(8)?;
+    reader.bits(16)?; // constraint flags, level_idc
+    reader.ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = reader.ue()?;
+        if chroma_format_idc == 3 {
+            reader.bit()?; // separate_colour_plane_flag
+        }
+        reader.ue()?; // bit_depth_luma_minus8
+        reader.ue()?; // bit_depth_chroma_minus8
+        reader.bit()?; // qpprime_y_zero_transform_bypass_flag
+        if reader.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if reader.bit()? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let (mut last, mut next) = (8i32, 8i32);
+                    for _ in 0..size {
+                        if next != 0 {
+                            next = (last + reader.se()? + 256) % 256;
+                        }
+                        if next != 0 {
+                            last = next;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    reader.ue()?; // log2_max_frame_num_minus4
+    match reader.ue()? {
+        0 => {
+            reader.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            reader.bit()?;
+            reader.se()?;
+            reader.se()?;
+            for _ in 0..reader.ue()? {
+                reader.se()?;
+            }
+        }
+        _ => {}
+    }
+    reader.ue()?; // max_num_ref_frames
+    reader.bit()?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = reader.ue()? as u64 + 1;
+    let height_in_map_units = reader.ue()? as u64 + 1;
+    let frame_mbs_only = reader.bit()?;
+    if frame_mbs_only == 0 {
+        reader.bit()?; // mb_adaptive_frame_field_flag
+    }
+    reader.bit()?; // direct_8x8_inference_flag
+    let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+    if reader.bit()? == 1 {
+        left = reader.ue()?;
+        right = reader.ue()?;
+        top = reader.ue()?;
+        bottom = reader.ue()?;
+    }
+
+    let (sub_width, sub_height) = match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    let fields = 2 - frame_mbs_only as u64;
+    let crop_x = sub_width * (left as u64 + right as u64);
+    let crop_y = sub_height * fields * (top as u64 + bottom as u64);
+    let width = (width_in_mbs * 16).checked_sub(crop_x)?;
+    let height = (fields * height_in_map_units * 16).checked_sub(crop_y)?;
+    Some((u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+}
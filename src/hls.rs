@@ -0,0 +1,624 @@
+// hls.rs
+// LL-HLS egress for viewers that can't do WebRTC (smart TVs, embeds). When config.json `hls` is
+// set, the forwarding peer of every SFU room without E2EE also hands the sender's first H.264
+// video track to a packager here, which cuts it into fMP4 parts and segments (fmp4.rs) kept in
+// memory and served under /hls/{room_id}/. Nothing is decoded: segments start at keyframes,
+// which are requested from the sender once one is overdue, and a new upstream continues the
+// timeline after a discontinuity. Players fetching the playlist are counted as the room's HLS
+// viewers by address and user agent. Audio is not packaged.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify};
+use webrtc::media::io::sample_builder::SampleBuilder;
+use webrtc::rtp::codecs::h264::H264Packet;
+use webrtc::rtp::packet::Packet;
+
+use crate::fmp4::{self, Sample, TIMESCALE};
+
+/// Packets the sample builder holds back to reorder late ones
+const MAX_LATE_PACKETS: u16 = 256;
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Completed segments, from the live edge back, whose parts are still listed
+const SEGMENTS_WITH_PARTS: usize = 2;
+/// Length given to the last frame of an upstream, whose successor never arrives (30 fps)
+const LAST_FRAME_TICKS: u64 = 3000;
+
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+
+fn default_part_ms() -> u64 {
+    500
+}
+
+fn default_segment_secs() -> u64 {
+    2
+}
+
+fn default_segments_kept() -> usize {
+    6
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HlsConfig {
+    /// Target duration of a partial segment
+    #[serde(default = "default_part_ms")]
+    pub part_ms: u64,
+    /// Segments are cut at the first keyframe after this long, and without one at twice that
+    #[serde(default = "default_segment_secs")]
+    pub segment_secs: u64,
+    /// Completed segments listed in the playlist
+    #[serde(default = "default_segments_kept")]
+    pub segments_kept: usize,
+}
+
+impl HlsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.segment_secs == 0 || self.segment_secs > 10 {
+            return Err("segment_secs must be between 1 and 10".to_string());
+        }
+        if self.part_ms < 100 || self.part_ms >= self.segment_secs * 1000 {
+            return Err("part_ms must be at least 100 and shorter than segment_secs".to_string());
+        }
+        if self.segments_kept < 3 {
+            return Err("segments_kept must be at least 3".to_string());
+        }
+        Ok(())
+    }
+
+    fn part_ticks(&self) -> u64 {
+        self.part_ms * TIMESCALE as u64 / 1000
+    }
+
+    fn segment_ticks(&self) -> u64 {
+        self.segment_secs * TIMESCALE as u64
+    }
+
+    /// EXT-X-TARGETDURATION: segments cut without a keyframe reach twice segment_secs
+    fn target_duration(&self) -> u64 {
+        self.segment_secs * 2
+    }
+}
+
+/// The egress of one server: its config, None when HLS is off, and the stream of every room
+/// being packaged. Clones share the streams.
+#[derive(Clone, Default)]
+pub struct Hls {
+    config: Option<HlsConfig>,
+    streams: Arc<Mutex<HashMap<String, Arc<HlsStream>>>>,
+}
+
+impl fmt::Debug for Hls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hls").field("config", &self.config).finish_non_exhaustive()
+    }
+}
+
+impl Hls {
+    pub fn new(config: Option<HlsConfig>) -> Self {
+        Self { config, streams: Default::default() }
+    }
+
+    /// Whether SFU rooms package their stream; they then accept H.264 only
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Registers the stream of a room's forwarding peer; None when HLS isn't configured.
+    /// `keyframe_tx` asks the forwarding peer for a keyframe from the sender
+    pub fn start(&self, room_id: &str, keyframe_tx: mpsc::UnboundedSender<()>) -> Option<Arc<HlsStream>> {
+        let config = self.config.clone()?;
+        let stream = Arc::new(HlsStream::new(config, keyframe_tx));
+        self.streams.lock().unwrap().insert(room_id.to_string(), stream.clone());
+        Some(stream)
+    }
+
+    /// Unregisters `stream`, unless a newer forwarding peer of the room replaced it already
+    pub fn stop(&self, room_id: &str, stream: &Arc<HlsStream>) {
+        let mut streams = self.streams.lock().unwrap();
+        if streams.get(room_id).is_some_and(|current| Arc::ptr_eq(current, stream)) {
+            streams.remove(room_id);
+        }
+        stream.changes.notify_waiters();
+    }
+
+    pub fn get(&self, room_id: &str) -> Option<Arc<HlsStream>> {
+        self.streams.lock().unwrap().get(room_id).cloned()
+    }
+
+    /// Players that fetched the room's playlist recently
+    pub fn viewer_count(&self, room_id: &str) -> usize {
+        self.get(room_id).map_or(0, |stream| stream.viewer_count())
+    }
+}
+
+/// Why a blocking playlist request got no playlist
+pub enum PlaylistError {
+    /// _HLS_msn is more than two segments ahead of the live edge
+    TooFarAhead,
+    /// The requested segment or part didn't arrive in time
+    Timeout,
+}
+
+/// What a file name under /hls/{room_id}/ refers to
+pub enum File {
+    /// index.m3u8
+    Playlist,
+    /// init{id}.mp4
+    Init(u64),
+    /// seg{msn}.m4s
+    Segment(u64),
+    /// part{msn}.{index}.m4s
+    Part(u64, usize),
+}
+
+impl File {
+    pub fn parse(name: &str) -> Option<Self> {
+        if name == "index.m3u8" {
+            return Some(Self::Playlist);
+        }
+        if let Some(id) = name.strip_prefix("init").and_then(|rest| rest.strip_suffix(".mp4")) {
+            return id.parse().ok().map(Self::Init);
+        }
+        if let Some(msn) = name.strip_prefix("seg").and_then(|rest| rest.strip_suffix(".m4s")) {
+            return msn.parse().ok().map(Self::Segment);
+        }
+        let (msn, index) = name.strip_prefix("part")?.strip_suffix(".m4s")?.split_once('.')?;
+        Some(Self::Part(msn.parse().ok()?, index.parse().ok()?))
+    }
+}
+
+struct Part {
+    data: Bytes,
+    duration: u64,
+    /// Starts with a keyframe
+    independent: bool,
+}
+
+struct Segment {
+    msn: u64,
+    /// ID of the init segment its fragments need
+    init: u64,
+    /// First segment after a new upstream
+    discontinuity: bool,
+    parts: Vec<Part>,
+}
+
+impl Segment {
+    fn duration(&self) -> u64 {
+        self.parts.iter().map(|part| part.duration).sum()
+    }
+}
+
+struct Init {
+    id: u64,
+    data: Bytes,
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+}
+
+struct Frame {
+    time: u64,
+    keyframe: bool,
+    /// Length-prefixed NAL units without parameter sets
+    data: Vec<u8>,
+}
+
+struct Packager {
+    builder: SampleBuilder<H264Packet>,
+    /// RTP timestamps wrap; (last timestamp, ticks since the upstream started)
+    clock: Option<(u32, u64)>,
+    /// Timeline position of the current upstream's first frame, so it continues the previous one
+    offset: u64,
+    /// End of the last packaged frame
+    end: u64,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    inits: Vec<Init>,
+    /// Waits for its successor, which gives its duration
+    held: Option<Frame>,
+    pending: Vec<Sample>,
+    part_start: u64,
+    segment_start: u64,
+    segments: VecDeque<Segment>,
+    current: Option<Segment>,
+    next_msn: u64,
+    discontinuity_sequence: u64,
+    /// The next segment follows a new upstream
+    discontinuity: bool,
+    fragment_sequence: u32,
+    last_keyframe_request: Option<Instant>,
+}
+
+fn new_builder() -> SampleBuilder<H264Packet> {
+    // Length-prefixed output, as the fMP4 samples want it
+    let mut depacketizer = H264Packet::default();
+    depacketizer.is_avc = true;
+    SampleBuilder::new(MAX_LATE_PACKETS, depacketizer, TIMESCALE)
+}
+
+impl Packager {
+    fn new() -> Self {
+        Self {
+            builder: new_builder(),
+            clock: None,
+            offset: 0,
+            end: 0,
+            sps: None,
+            pps: None,
+            inits: Vec::new(),
+            held: None,
+            pending: Vec::new(),
+            part_start: 0,
+            segment_start: 0,
+            segments: VecDeque::new(),
+            current: None,
+            next_msn: 0,
+            discontinuity_sequence: 0,
+            discontinuity: false,
+            fragment_sequence: 1,
+            last_keyframe_request: None,
+        }
+    }
+
+    /// Splits an access unit into the parameter sets, kept aside, and the rest
+    fn frame(&mut self, time: u64, access_unit: &[u8]) -> Frame {
+        let mut frame = Frame { time, keyframe: false, data: Vec::with_capacity(access_unit.len()) };
+        let mut pos = 0;
+        while pos + 4 <= access_unit.len() {
+            let len = u32::from_be_bytes([access_unit[pos], access_unit[pos + 1], access_unit[pos + 2], access_unit[pos + 3]]) as usize;
+            let Some(nal) = access_unit.get(pos + 4..pos + 4 + len) else { break };
+            pos += 4 + len;
+            match nal.first().map(|header| header & 0x1F) {
+                None | Some(NAL_AUD) => {}
+                Some(NAL_SPS) => self.sps = Some(nal.to_vec()),
+                Some(NAL_PPS) => self.pps = Some(nal.to_vec()),
+                Some(nal_type) => {
+                    frame.keyframe |= nal_type == NAL_IDR;
+                    frame.data.extend_from_slice(&access_unit[pos - 4 - len..pos]);
+                }
+            }
+        }
+        frame
+    }
+
+    /// Packages one access unit; true when a keyframe should be requested
+    fn push_frame(&mut self, config: &HlsConfig, timestamp: u32, access_unit: &[u8]) -> bool {
+        let ticks = match &mut self.clock {
+            Some((last, elapsed)) => {
+                *elapsed += timestamp.wrapping_sub(*last) as u64;
+                *last = timestamp;
+                *elapsed
+            }
+            None => {
+                self.clock = Some((timestamp, 0));
+                0
+            }
+        };
+        let frame = self.frame(self.offset + ticks, access_unit);
+        if frame.data.is_empty() {
+            return false;
+        }
+
+        let params = match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) => Some((sps.clone(), pps.clone())),
+            _ => None,
+        };
+        let new_params = match (&params, self.inits.last()) {
+            (Some((sps, pps)), Some(init)) => *sps != init.sps || *pps != init.pps,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+
+        if let Some(held) = self.held.take() {
+            self.finish_frame(config, held, frame.time);
+        }
+
+        let elapsed = self.current.as_ref().map(|_| frame.time.saturating_sub(self.segment_start));
+        let cut = match elapsed {
+            None => frame.keyframe && params.is_some(),
+            Some(elapsed) => (frame.keyframe && (elapsed >= config.segment_ticks() || new_params)) || elapsed >= 2 * config.segment_ticks(),
+        };
+        if cut {
+            self.close_segment(config);
+            if new_params {
+                if let Some((sps, pps)) = params {
+                    let id = self.inits.last().map_or(0, |init| init.id + 1);
+                    let data = Bytes::from(fmp4::init_segment(&sps, &pps));
+                    self.inits.push(Init { id, data, sps, pps });
+                }
+            }
+            self.open_segment(frame.time);
+        }
+        if self.current.is_none() {
+            // Nothing is decodable before the first keyframe with its parameter sets
+            return true;
+        }
+
+        let wants_keyframe = !frame.keyframe && elapsed.is_some_and(|elapsed| elapsed >= config.segment_ticks());
+        self.held = Some(frame);
+        wants_keyframe
+    }
+
+    /// Adds a frame whose successor starts at `end` to the current part
+    fn finish_frame(&mut self, config: &HlsConfig, frame: Frame, end: u64) {
+        let end = end.max(frame.time + 1);
+        if !self.pending.is_empty() && end - self.part_start > config.part_ticks() {
+            self.close_part();
+        }
+        if self.pending.is_empty() {
+            self.part_start = frame.time;
+        }
+        let duration = u32::try_from(end - frame.time).unwrap_or(u32::MAX);
+        self.pending.push(Sample { duration, keyframe: frame.keyframe, data: frame.data });
+        self.end = frame.time + duration as u64;
+        if self.end - self.part_start >= config.part_ticks() {
+            self.close_part();
+        }
+    }
+
+    fn close_part(&mut self) {
+        let Some(segment) = self.current.as_mut() else {
+            self.pending.clear();
+            return;
+        };
+        if self.pending.is_empty() {
+            return;
+        }
+        let samples = std::mem::take(&mut self.pending);
+        let data = fmp4::fragment(self.fragment_sequence, self.part_start, &samples);
+        self.fragment_sequence = self.fragment_sequence.wrapping_add(1);
+        segment.parts.push(Part {
+            data: Bytes::from(data),
+            duration: samples.iter().map(|sample| sample.duration as u64).sum(),
+            independent: samples[0].keyframe,
+        });
+    }
+
+    fn open_segment(&mut self, time: u64) {
+        let init = self.inits.last().map_or(0, |init| init.id);
+        self.current = Some(Segment {
+            msn: self.next_msn,
+            init,
+            discontinuity: std::mem::take(&mut self.discontinuity),
+            parts: Vec::new(),
+        });
+        self.next_msn += 1;
+        self.part_start = time;
+        self.segment_start = time;
+    }
+
+    fn close_segment(&mut self, config: &HlsConfig) {
+        self.close_part();
+        let Some(segment) = self.current.take() else { return };
+        if segment.parts.is_empty() {
+            // Never listed; its number and discontinuity go to the next one
+            self.next_msn = segment.msn;
+            self.discontinuity |= segment.discontinuity;
+            return;
+        }
+        self.segments.push_back(segment);
+        while self.segments.len() > config.segments_kept {
+            if let Some(dropped) = self.segments.pop_front() {
+                if dropped.discontinuity {
+                    self.discontinuity_sequence += 1;
+                }
+            }
+        }
+        if let Some(oldest) = self.segments.front().map(|segment| segment.init) {
+            self.inits.retain(|init| init.id >= oldest);
+        }
+    }
+
+    /// A new upstream: ends the current segment and continues the timeline after a
+    /// discontinuity
+    fn restart(&mut self, config: &HlsConfig) {
+        if let Some(held) = self.held.take() {
+            let end = held.time + LAST_FRAME_TICKS;
+            self.finish_frame(config, held, end);
+        }
+        self.close_segment(config);
+        self.discontinuity |= !self.segments.is_empty();
+        self.offset = self.end;
+        self.clock = None;
+        self.builder = new_builder();
+        self.sps = None;
+        self.pps = None;
+    }
+
+    /// Whether a playlist asked for with _HLS_msn and _HLS_part would have it
+    fn has(&self, msn: u64, part: Option<usize>) -> bool {
+        if msn < self.next_msn.saturating_sub(1) || self.segments.back().is_some_and(|segment| segment.msn >= msn) {
+            return true;
+        }
+        match (&self.current, part) {
+            (Some(current), Some(part)) => current.msn == msn && current.parts.len() > part,
+            _ => false,
+        }
+    }
+
+    fn find(&self, msn: u64) -> Option<&Segment> {
+        self.segments.iter().chain(self.current.as_ref()).find(|segment| segment.msn == msn)
+    }
+
+    fn playlist(&self, config: &HlsConfig) -> String {
+        let part_target = config.part_ms as f64 / 1000.0;
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:9\n#EXT-X-TARGETDURATION:{}\n#EXT-X-PART-INF:PART-TARGET={:.3}\n#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}\n",
+            config.target_duration(),
+            part_target,
+            part_target * 3.0,
+        );
+        let first_msn = self.segments.front().or(self.current.as_ref()).map_or(self.next_msn, |segment| segment.msn);
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", first_msn));
+        playlist.push_str(&format!("#EXT-X-DISCONTINUITY-SEQUENCE:{}\n", self.discontinuity_sequence));
+
+        let parted_from = self.segments.len().saturating_sub(SEGMENTS_WITH_PARTS);
+        let mut map = None;
+        for (i, segment) in self.segments.iter().chain(self.current.as_ref()).enumerate() {
+            if segment.discontinuity && i > 0 {
+                playlist.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            if map != Some(segment.init) || (segment.discontinuity && i > 0) {
+                playlist.push_str(&format!("#EXT-X-MAP:URI=\"init{}.mp4\"\n", segment.init));
+                map = Some(segment.init);
+            }
+            if i >= parted_from {
+                for (index, part) in segment.parts.iter().enumerate() {
+                    playlist.push_str(&format!(
+                        "#EXT-X-PART:DURATION={:.5},URI=\"part{}.{}.m4s\"{}\n",
+                        part.duration as f64 / TIMESCALE as f64,
+                        segment.msn,
+                        index,
+                        if part.independent { ",INDEPENDENT=YES" } else { "" },
+                    ));
+                }
+            }
+            if i < self.segments.len() {
+                playlist.push_str(&format!("#EXTINF:{:.5},\nseg{}.m4s\n", segment.duration() as f64 / TIMESCALE as f64, segment.msn));
+            }
+        }
+        if let Some(current) = &self.current {
+            playlist.push_str(&format!("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part{}.{}.m4s\"\n", current.msn, current.parts.len()));
+        }
+        playlist
+    }
+}
+
+/// The packaged stream of one room
+pub struct HlsStream {
+    config: HlsConfig,
+    keyframe_tx: mpsc::UnboundedSender<()>,
+    packager: Mutex<Packager>,
+    /// Woken whenever a part is published, for blocking requests
+    changes: Notify,
+    /// Players by address and user agent, with their last playlist fetch
+    viewers: Mutex<HashMap<(Option<IpAddr>, String), Instant>>,
+}
+
+impl HlsStream {
+    fn new(config: HlsConfig, keyframe_tx: mpsc::UnboundedSender<()>) -> Self {
+        Self {
+            config,
+            keyframe_tx,
+            packager: Mutex::new(Packager::new()),
+            changes: Notify::new(),
+            viewers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Feeds one RTP packet of the sender's H.264 track
+    pub fn push_rtp(&self, packet: &Packet) {
+        let (published, wants_keyframe) = {
+            let mut packager = self.packager.lock().unwrap();
+            let published_before = packager.fragment_sequence;
+            packager.builder.push(packet.clone());
+            let mut wants_keyframe = false;
+            while let Some(sample) = packager.builder.pop() {
+                wants_keyframe |= packager.push_frame(&self.config, sample.packet_timestamp, &sample.data);
+            }
+            let due = !matches!(packager.last_keyframe_request, Some(at) if at.elapsed() < KEYFRAME_REQUEST_INTERVAL);
+            if wants_keyframe && due {
+                packager.last_keyframe_request = Some(Instant::now());
+            }
+            (packager.fragment_sequence != published_before, wants_keyframe && due)
+        };
+        if published {
+            self.changes.notify_waiters();
+        }
+        if wants_keyframe {
+            let _ = self.keyframe_tx.send(());
+        }
+    }
+
+    /// Called for every new upstream connection of the room's sender
+    pub fn restart(&self) {
+        self.packager.lock().unwrap().restart(&self.config);
+        self.changes.notify_waiters();
+    }
+
+    /// Runs `check` until it returns something or the blocking request times out
+    async fn wait_for<T>(&self, check: impl Fn(&Packager) -> Option<T>) -> Option<T> {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(self.config.target_duration() * 3);
+        loop {
+            let changed = self.changes.notified();
+            let found = check(&self.packager.lock().unwrap());
+            if found.is_some() {
+                return found;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    /// The media playlist, once it has segment `msn` (part `part` of it when given) if asked
+    /// to block; counts the caller as a viewer
+    pub async fn playlist(&self, msn: Option<u64>, part: Option<usize>, client: Option<IpAddr>, user_agent: String) -> Result<String, PlaylistError> {
+        self.viewers.lock().unwrap().insert((client, user_agent), Instant::now());
+        let Some(msn) = msn else {
+            return Ok(self.packager.lock().unwrap().playlist(&self.config));
+        };
+        if msn > self.packager.lock().unwrap().next_msn + 1 {
+            return Err(PlaylistError::TooFarAhead);
+        }
+        self.wait_for(|packager| packager.has(msn, part).then(|| packager.playlist(&self.config)))
+            .await
+            .ok_or(PlaylistError::Timeout)
+    }
+
+    pub fn init_segment(&self, id: u64) -> Option<Bytes> {
+        let packager = self.packager.lock().unwrap();
+        packager.inits.iter().find(|init| init.id == id).map(|init| init.data.clone())
+    }
+
+    /// A completed segment: its parts back to back
+    pub fn segment(&self, msn: u64) -> Option<Bytes> {
+        let packager = self.packager.lock().unwrap();
+        let segment = packager.segments.iter().find(|segment| segment.msn == msn)?;
+        let data: Vec<u8> = segment.parts.iter().flat_map(|part| part.data.iter().copied()).collect();
+        Some(Bytes::from(data))
+    }
+
+    /// A part, waiting for it when it is the next one of the current segment (the preload hint)
+    pub async fn part(&self, msn: u64, index: usize) -> Option<Bytes> {
+        self.wait_for(|packager| match packager.find(msn) {
+            Some(segment) => match segment.parts.get(index) {
+                Some(part) => Some(Some(part.data.clone())),
+                None if packager.current.as_ref().is_some_and(|current| current.msn == msn) && index == segment.parts.len() => None,
+                None => Some(None),
+            },
+            // The segment after the current one, while waiting for its first keyframe
+            None if msn == packager.next_msn && index == 0 => None,
+            None => Some(None),
+        })
+        .await
+        .flatten()
+    }
+
+    /// A player stops counting as a viewer this long after its last playlist fetch
+    fn viewer_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.target_duration() * 3)
+    }
+
+    fn viewer_count(&self) -> usize {
+        let timeout = self.viewer_timeout();
+        let mut viewers = self.viewers.lock().unwrap();
+        viewers.retain(|_, seen| seen.elapsed() < timeout);
+        viewers.len()
+    }
+
+    /// Whether this player fetched the playlist recently; only those get segments and parts
+    pub fn is_viewer(&self, client: Option<IpAddr>, user_agent: &str) -> bool {
+        self.viewers.lock().unwrap()
+            .get(&(client, user_agent.to_string()))
+            .is_some_and(|seen| seen.elapsed() < self.viewer_timeout())
+    }
+}
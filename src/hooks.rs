@@ -0,0 +1,88 @@
+// hooks.rs
+// Plugin hooks around the room manager. A SignalingHook sees every Join before it is processed,
+// every other message a client sends into a room, and every connection leaving a room; it can
+// change the message, reject it with an error for the client, or just observe. Hooks are
+// registered with ServerBuilder::with_hook and run in registration order; the first rejection
+// wins and later hooks don't see the message. They run while the room manager is locked, so
+// they must not block.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::room::Role;
+use crate::signaling::SignalingMessage;
+
+/// Who a hook call is about
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    pub room_id: &'a str,
+    /// The joining, sending or leaving connection, when known
+    pub connection_id: Option<&'a str>,
+    /// The connection's role in the room; None for a Join
+    pub role: Option<Role>,
+}
+
+/// What happens to an intercepted message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookDecision {
+    /// Process the (possibly modified) message
+    Continue,
+    /// Drop it and send this error to the client
+    Reject(String),
+}
+
+pub trait SignalingHook: Send + Sync {
+    /// Shown in logs when the hook rejects a message
+    fn name(&self) -> &str;
+
+    /// A Join, before the server checks tokens and capacity
+    fn on_join(&self, _context: &HookContext, _message: &mut SignalingMessage) -> HookDecision {
+        HookDecision::Continue
+    }
+
+    /// Any other message from a client in the room
+    fn on_message(&self, _context: &HookContext, _message: &mut SignalingMessage) -> HookDecision {
+        HookDecision::Continue
+    }
+
+    /// A connection left the room or was removed with it
+    fn on_leave(&self, _context: &HookContext) {}
+}
+
+/// The registered hooks, in order
+#[derive(Clone, Default)]
+pub struct Hooks(Vec<Arc<dyn SignalingHook>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|hook| hook.name())).finish()
+    }
+}
+
+impl Hooks {
+    pub fn push(&mut self, hook: Arc<dyn SignalingHook>) {
+        self.0.push(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Runs on_join or on_message on every hook until one rejects; returns that hook's name
+    /// and reason
+    pub fn intercept(&self, context: &HookContext, message: &mut SignalingMessage, join: bool) -> Result<(), (String, String)> {
+        for hook in &self.0 {
+            let decision = if join { hook.on_join(context, message) } else { hook.on_message(context, message) };
+            if let HookDecision::Reject(reason) = decision {
+                return Err((hook.name().to_string(), reason));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn leave(&self, context: &HookContext) {
+        for hook in &self.0 {
+            hook.on_leave(context);
+        }
+    }
+}
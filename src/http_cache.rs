@@ -0,0 +1,109 @@
+// http_cache.rs
+// Response compression and revalidation for mobile viewers on slow links. `compressed` answers
+// with Brotli or gzip when the client accepts it; `revalidated` adds Cache-Control and turns a
+// request whose If-None-Match matches the response's ETag into a bodyless 304.
+
+use sha1::{Digest, Sha1};
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, ETAG, VARY};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// Passes only requests whose Accept-Encoding lists `encoding`
+fn accepts(encoding: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::<String>("accept-encoding")
+        .and_then(move |accepted: String| async move {
+            let listed = accepted
+                .split(',')
+                .filter_map(|item| item.split(';').next())
+                .any(|item| item.trim().eq_ignore_ascii_case(encoding));
+            if listed {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// Compresses the responses of `filter` with the best encoding the client accepts
+pub fn compressed<F>(filter: F) -> BoxedFilter<(Response,)>
+where
+    F: Filter<Extract = (Response,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    let brotli = accepts("br")
+        .and(filter.clone())
+        .with(warp::compression::brotli())
+        .map(Reply::into_response);
+    let gzip = accepts("gzip")
+        .and(filter.clone())
+        .with(warp::compression::gzip())
+        .map(Reply::into_response);
+    brotli
+        .or(gzip)
+        .unify()
+        .or(filter)
+        .unify()
+        .map(|mut response: Response| {
+            response.headers_mut().insert(VARY, HeaderValue::from_static("accept-encoding"));
+            response
+        })
+        .boxed()
+}
+
+/// Weak ETag of a body; weak so it holds for every content encoding of the same bytes
+pub fn etag_for(body: &[u8]) -> String {
+    let digest = Sha1::digest(body);
+    let hex: String = digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// Adds Cache-Control to successful responses of `filter` (HTML is always revalidated, other
+/// files may be reused for `max_age_secs`) and answers matching If-None-Match with 304
+pub fn revalidated(filter: BoxedFilter<(Response,)>, max_age_secs: u64) -> BoxedFilter<(Response,)> {
+    warp::header::optional::<String>("if-none-match")
+        .and(filter)
+        .map(move |if_none_match: Option<String>, response: Response| revalidate(response, if_none_match, max_age_secs))
+        .boxed()
+}
+
+fn revalidate(mut response: Response, if_none_match: Option<String>, max_age_secs: u64) -> Response {
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    let cache_control = if is_html {
+        HeaderValue::from_static("no-cache")
+    } else {
+        HeaderValue::from_str(&format!("public, max-age={}", max_age_secs)).unwrap_or(HeaderValue::from_static("no-cache"))
+    };
+
+    let etag = response.headers().get(ETAG).cloned();
+    let matches = match (&etag, &if_none_match) {
+        (Some(etag), Some(candidates)) => candidates
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || etag.to_str().is_ok_and(|etag| weak_eq(candidate, etag))),
+        _ => false,
+    };
+    if let (true, Some(etag)) = (matches, etag) {
+        let mut not_modified = Response::default();
+        *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+        not_modified.headers_mut().insert(ETAG, etag);
+        not_modified.headers_mut().insert(CACHE_CONTROL, cache_control);
+        return not_modified;
+    }
+
+    response.headers_mut().insert(CACHE_CONTROL, cache_control);
+    response
+}
+
+/// If-None-Match uses weak comparison: `W/"x"` and `"x"` match
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
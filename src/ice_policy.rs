@@ -0,0 +1,74 @@
+// ice_policy.rs
+// Per-room control over which ICE candidates peers get to see, and so over the paths media may
+// take. The server drops IceCandidate messages and a=candidate lines of Offer/Answer SDP whose
+// candidate type the room's policy refuses: without host candidates media has to go through
+// STUN-mapped addresses or TURN, with relay candidates only it always goes through TURN, and with
+// host candidates only it never leaves the local network. RoomInfo carries the policy so clients
+// can also set iceTransportPolicy themselves.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::sdp;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IcePolicy {
+    /// Every candidate is forwarded
+    #[default]
+    All,
+    /// Host candidates are dropped, so local addresses aren't revealed to peers
+    NoHost,
+    /// Only TURN relay candidates are forwarded
+    RelayOnly,
+    /// Only host candidates are forwarded, keeping media on the LAN
+    LanOnly,
+}
+
+impl IcePolicy {
+    /// Whether a candidate of this type (`host`, `srflx`, `prflx`, `relay`) may be forwarded
+    fn permits_type(self, candidate_type: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::NoHost => candidate_type != "host",
+            Self::RelayOnly => candidate_type == "relay",
+            Self::LanOnly => candidate_type == "host",
+        }
+    }
+
+    /// Whether an IceCandidate message may be forwarded; the empty end-of-candidates marker
+    /// always is, unparseable candidates only without a policy
+    pub fn permits(self, message: &SignalingMessage) -> bool {
+        let candidate = message.data.as_ref()
+            .and_then(|data| data.get("candidate"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if self == Self::All || candidate.is_empty() {
+            return true;
+        }
+        sdp::candidate_type(candidate).is_some_and(|candidate_type| self.permits_type(candidate_type))
+    }
+
+    /// Removes the a=candidate lines the policy refuses from an Offer's or Answer's SDP
+    pub fn filter_description(self, message: &mut SignalingMessage) {
+        if self == Self::All || !matches!(message.message_type, SignalingMessageType::Offer | SignalingMessageType::Answer) {
+            return;
+        }
+        let description = match message.data.as_mut().and_then(Value::as_object_mut) {
+            Some(description) => description,
+            None => return,
+        };
+        let filtered = match description.get("sdp").and_then(Value::as_str) {
+            Some(sdp) => sdp.split_inclusive('\n')
+                .filter(|line| {
+                    !line.starts_with("a=candidate:")
+                        || sdp::candidate_type(line.trim_end()).is_some_and(|candidate_type| self.permits_type(candidate_type))
+                })
+                .collect::<String>(),
+            None => return,
+        };
+        description.insert("sdp".to_string(), Value::String(filtered));
+    }
+}
@@ -0,0 +1,242 @@
+// inference.rs
+// Typed schema for InferenceResult payloads. Clients send free-form JSON; it is checked here and
+// re-serialized in one canonical shape before it is calibrated, stored or broadcast. Oversized
+// or deeply nested data is rejected before it is deserialized, so one buggy sender can't bloat
+// the database or hold up broadcasts. InferenceFilter narrows the broadcast copies down to what
+// a viewer subscribed to with SubscribeInference.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InferenceLimits {
+    /// Largest accepted InferenceResult `data`, in bytes of serialized JSON
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// Deepest accepted nesting of arrays and objects in `data`
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    /// Most detections accepted in one result
+    #[serde(default = "default_max_predictions")]
+    pub max_predictions: usize,
+}
+
+fn default_max_bytes() -> usize { 65536 }
+fn default_max_depth() -> usize { 8 }
+fn default_max_predictions() -> usize { 500 }
+
+/// Longest class or model name kept; longer ones are cut
+const MAX_LABEL_CHARS: usize = 128;
+
+impl Default for InferenceLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: default_max_bytes(),
+            max_depth: default_max_depth(),
+            max_predictions: default_max_predictions(),
+        }
+    }
+}
+
+impl InferenceLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_bytes == 0 {
+            return Err("max_bytes must be greater than 0".to_string());
+        }
+        // A valid payload nests data -> predictions -> detection -> bbox
+        if self.max_depth < 4 {
+            return Err("max_depth must be at least 4".to_string());
+        }
+        Ok(())
+    }
+
+    /// Checks raw data against the size and nesting limits, without recursing
+    fn check(&self, data: &Value) -> Result<(), String> {
+        let mut stack = vec![(data, 1)];
+        while let Some((value, depth)) = stack.pop() {
+            let children: Box<dyn Iterator<Item = &Value>> = match value {
+                Value::Array(items) => Box::new(items.iter()),
+                Value::Object(fields) => Box::new(fields.values()),
+                _ => continue,
+            };
+            if depth > self.max_depth {
+                return Err(format!("data is nested deeper than {} levels", self.max_depth));
+            }
+            stack.extend(children.map(|child| (child, depth + 1)));
+        }
+
+        let bytes = serde_json::to_vec(data).map(|json| json.len()).unwrap_or(usize::MAX);
+        if bytes > self.max_bytes {
+            return Err(format!("data is {} bytes, more than the {} allowed", bytes, self.max_bytes));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detection {
+    pub class: String,
+    pub score: f64,
+    /// [x, y, width, height] in pixels of the analysed frame
+    pub bbox: [f64; 4],
+    /// Same object across frames; set by the server when tracking is enabled (tracker.rs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InferencePayload {
+    /// Capture time in unix milliseconds
+    pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Older clients name this array `detections`
+    #[serde(alias = "detections")]
+    pub predictions: Vec<Detection>,
+    /// [width, height] of the analysed frame
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_size: Option<[u32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inference_time_ms: Option<f64>,
+}
+
+impl InferencePayload {
+    /// Parses and validates raw InferenceResult data within `limits`. Unknown fields are
+    /// dropped and labels are sanitized.
+    pub fn parse(data: &Value, limits: &InferenceLimits) -> Result<Self, String> {
+        limits.check(data)?;
+        let mut payload: InferencePayload = serde_json::from_value(data.clone()).map_err(|e| e.to_string())?;
+        if payload.predictions.len() > limits.max_predictions {
+            return Err(format!(
+                "{} predictions, more than the {} allowed",
+                payload.predictions.len(),
+                limits.max_predictions
+            ));
+        }
+        payload.sanitize();
+        payload.validate()?;
+        Ok(payload)
+    }
+
+    /// Strips control characters and surrounding whitespace from labels, and caps their length
+    fn sanitize(&mut self) {
+        let clean = |label: &str| -> String {
+            label
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+                .trim()
+                .chars()
+                .take(MAX_LABEL_CHARS)
+                .collect()
+        };
+        for detection in &mut self.predictions {
+            detection.class = clean(&detection.class);
+        }
+        if let Some(model) = &self.model {
+            self.model = Some(clean(model));
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.timestamp <= 0 {
+            return Err("timestamp must be a positive unix time in milliseconds".to_string());
+        }
+        if matches!(&self.model, Some(model) if model.is_empty()) {
+            return Err("model must not be empty when set".to_string());
+        }
+        if matches!(self.frame_size, Some([w, h]) if w == 0 || h == 0) {
+            return Err("frame_size must be non-zero".to_string());
+        }
+        if matches!(self.inference_time_ms, Some(ms) if !ms.is_finite() || ms < 0.0) {
+            return Err("inference_time_ms must be a non-negative number".to_string());
+        }
+
+        for (i, detection) in self.predictions.iter().enumerate() {
+            if detection.class.is_empty() {
+                return Err(format!("predictions[{}].class must not be empty", i));
+            }
+            if !(0.0..=1.0).contains(&detection.score) {
+                return Err(format!("predictions[{}].score must be between 0 and 1", i));
+            }
+            let [_, _, width, height] = detection.bbox;
+            if detection.bbox.iter().any(|v| !v.is_finite()) || width < 0.0 || height < 0.0 {
+                return Err(format!("predictions[{}].bbox must be finite with non-negative size", i));
+            }
+        }
+        Ok(())
+    }
+
+    /// The normalized record that is stored and broadcast
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// Most classes one SubscribeInference may name
+const MAX_FILTER_CLASSES: usize = 64;
+
+/// A viewer's SubscribeInference: the detections it wants in its InferenceUpdates
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferenceFilter {
+    /// Empty for any class
+    pub classes: HashSet<String>,
+    pub min_score: Option<f64>,
+}
+
+impl InferenceFilter {
+    /// Parses SubscribeInference data; None (subscribe to everything) for null or a filter that
+    /// lets everything through
+    pub fn parse(data: Option<&Value>) -> Result<Option<Self>, String> {
+        let data = match data {
+            None | Some(Value::Null) => return Ok(None),
+            Some(data) => data,
+        };
+        let classes: HashSet<String> = match data.get("classes") {
+            None | Some(Value::Null) => HashSet::new(),
+            Some(Value::Array(classes)) if classes.len() <= MAX_FILTER_CLASSES => classes.iter()
+                .map(|class| class.as_str().map(str::to_string).ok_or_else(|| "classes must be strings".to_string()))
+                .collect::<Result<_, _>>()?,
+            Some(Value::Array(_)) => return Err(format!("at most {} classes", MAX_FILTER_CLASSES)),
+            Some(_) => return Err("classes must be a list".to_string()),
+        };
+        let min_score = match data.get("min_score") {
+            None | Some(Value::Null) => None,
+            Some(score) => match score.as_f64() {
+                Some(score) if (0.0..=1.0).contains(&score) => Some(score),
+                _ => return Err("min_score must be between 0 and 1".to_string()),
+            },
+        };
+        if classes.is_empty() && min_score.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self { classes, min_score }))
+    }
+
+    fn matches(&self, detection: &Value) -> bool {
+        let class_matches = self.classes.is_empty()
+            || detection.get("class").and_then(Value::as_str).is_some_and(|class| self.classes.contains(class));
+        let score_matches = match self.min_score {
+            Some(min) => detection.get("score").and_then(Value::as_f64).is_some_and(|score| score >= min),
+            None => true,
+        };
+        class_matches && score_matches
+    }
+
+    /// The stored payload with only matching detections, or None when none match
+    pub fn apply(&self, payload: &Value) -> Option<Value> {
+        let predictions: Vec<Value> = payload.get("predictions")?
+            .as_array()?
+            .iter()
+            .filter(|detection| self.matches(detection))
+            .cloned()
+            .collect();
+        if predictions.is_empty() {
+            return None;
+        }
+        let mut filtered = payload.clone();
+        filtered["predictions"] = Value::Array(predictions);
+        Some(filtered)
+    }
+}
@@ -0,0 +1,391 @@
+// ingest.rs
+// RTSP ingest for IP cameras that can't open the sender page, behind the `rtsp-ingest` cargo
+// feature. Each entry of config.json `rtsp_ingest` pulls a camera's H.264 video with retina and
+// joins the room as its sender (a LocalPeer). Frames are repackaged, never decoded: retina's
+// length-prefixed NAL units become Annex B samples on one local track, led by the parameter
+// sets on keyframes, and the ingest offers that track the way the sender page would. A room the
+// ingest creates is an SFU room, so the camera is uploaded once and the forwarding peer fans it
+// out; in an existing mesh room each viewer gets its own connection to the same track. Audio is
+// not ingested, and viewers joining mid-stream see video from the camera's next keyframe.
+
+use serde::{Deserialize, Serialize};
+
+fn default_reconnect_secs() -> u64 {
+    5
+}
+
+/// How RTP gets from the camera
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestTransport {
+    /// Interleaved in the RTSP connection; works through NAT and firewalls
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RtspIngestConfig {
+    /// Room the camera sends to; created as an SFU room when it doesn't exist
+    pub room_id: String,
+    /// rtsp:// URL of the camera; user and password in the URL are sent as credentials
+    pub url: String,
+    #[serde(default)]
+    pub transport: IngestTransport,
+    /// Wait before reconnecting after the camera or the room went away
+    #[serde(default = "default_reconnect_secs")]
+    pub reconnect_secs: u64,
+}
+
+impl RtspIngestConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !crate::tenant::is_valid_room_key(&self.room_id) {
+            return Err(format!("invalid room_id {}", self.room_id));
+        }
+        if !self.url.starts_with("rtsp://") {
+            return Err("url must be an rtsp:// URL".to_string());
+        }
+        if self.reconnect_secs == 0 {
+            return Err("reconnect_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rtsp-ingest")]
+pub use camera::spawn;
+
+#[cfg(feature = "rtsp-ingest")]
+mod camera {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use retina::client::{Credentials, PlayOptions, Session, SessionOptions, SetupOptions, TcpTransportOptions, Transport, UdpTransportOptions};
+    use retina::codec::{CodecItem, ParametersRef, VideoFrame};
+    use serde_json::Value;
+    use tokio::sync::RwLock;
+    use tracing::{info, warn};
+    use webrtc::api::interceptor_registry::register_default_interceptors;
+    use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
+    use webrtc::api::{APIBuilder, API};
+    use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+    use webrtc::interceptor::registry::Registry;
+    use webrtc::media::Sample;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::peer_connection::RTCPeerConnection;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+    use webrtc::track::track_local::TrackLocal;
+
+    use super::{IngestTransport, RtspIngestConfig};
+    use crate::Clients;
+    use crate::local_peer::LocalPeer;
+    use crate::room::{RoomManager, RoomMode, RoomSettings};
+    use crate::signaling::{SignalingMessage, SignalingMessageType};
+    use crate::state::SharedState;
+
+    /// Sample duration until two frames give the camera's frame interval
+    const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(33);
+    const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+    /// Starts pulling the camera into its room; reconnects for as long as the server runs
+    pub fn spawn(config: RtspIngestConfig, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) {
+        tokio::task::spawn(async move {
+            loop {
+                if let Err(e) = run(&config, &state, &room_manager, &clients).await {
+                    warn!("RTSP ingest into room {} stopped: {}; reconnecting in {}s", config.room_id, e, config.reconnect_secs);
+                }
+                tokio::time::sleep(Duration::from_secs(config.reconnect_secs)).await;
+            }
+        });
+    }
+
+    async fn run(config: &RtspIngestConfig, state: &SharedState, room_manager: &Arc<RwLock<RoomManager>>, clients: &Clients) -> Result<(), String> {
+        // The camera is reached before the sender slot is taken
+        let mut url = url::Url::parse(&config.url).map_err(|e| format!("invalid url: {}", e))?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let creds = (!url.username().is_empty()).then(|| Credentials {
+            username: url.username().to_string(),
+            password: url.password().unwrap_or_default().to_string(),
+        });
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        let mut session = Session::describe(url, SessionOptions::default().creds(creds).user_agent("ws2infer".to_string()))
+            .await
+            .map_err(|e| format!("DESCRIBE failed: {}", e))?;
+        let stream_index = session.streams()
+            .iter()
+            .position(|stream| stream.media() == "video" && stream.encoding_name() == "h264")
+            .ok_or_else(|| "the camera offers no H.264 video".to_string())?;
+        let mut parameters = match session.streams()[stream_index].parameters() {
+            Some(ParametersRef::Video(video)) => parameter_sets(video.extra_data()),
+            _ => Vec::new(),
+        };
+        let transport = match config.transport {
+            IngestTransport::Tcp => Transport::Tcp(TcpTransportOptions::default()),
+            IngestTransport::Udp => Transport::Udp(UdpTransportOptions::default()),
+        };
+        session.setup(stream_index, SetupOptions::default().transport(transport))
+            .await
+            .map_err(|e| format!("SETUP failed: {}", e))?;
+        let mut frames = Box::pin(
+            session.play(PlayOptions::default())
+                .await
+                .map_err(|e| format!("PLAY failed: {}", e))?
+                .demuxed()
+                .map_err(|e| e.to_string())?,
+        );
+
+        let created = {
+            let mut manager = room_manager.write().await;
+            let created = manager.create_room_if_absent(config.room_id.clone());
+            if let (true, Some(room)) = (created, manager.rooms.get_mut(&config.room_id)) {
+                room.apply_settings(&RoomSettings { mode: RoomMode::Sfu, ..Default::default() });
+            }
+            created
+        };
+        if created {
+            crate::sfu::spawn(state.clone(), config.room_id.clone(), room_manager.clone(), clients.clone());
+        }
+        let sender_token = room_manager.read().await.rooms.get(&config.room_id).and_then(|room| room.sender_token.clone());
+
+        let api = new_api().map_err(|e| e.to_string())?;
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_H264.to_string(),
+                clock_rate: 90_000,
+                ..Default::default()
+            },
+            "video".to_string(),
+            "rtsp".to_string(),
+        ));
+        let peer = LocalPeer::join_as(
+            state.clone(),
+            config.room_id.clone(),
+            format!("rtsp-{}", uuid::Uuid::new_v4()),
+            room_manager.clone(),
+            clients.clone(),
+            true,
+            serde_json::json!({ "ingest": "rtsp", "sender_token": sender_token }),
+        )
+        .await;
+        info!("RTSP ingest from {} started in room {}", host, config.room_id);
+
+        let mut ingest = Ingest { peer, api, track, viewers: HashMap::new(), joined: false, last_pts: None };
+        let result = loop {
+            tokio::select! {
+                message = ingest.peer.recv() => match message {
+                    Some(message) => {
+                        if let Err(e) = ingest.handle(message).await {
+                            break Err(e);
+                        }
+                    }
+                    None => break Err("left the room".to_string()),
+                },
+                item = frames.next() => match item {
+                    Some(Ok(CodecItem::VideoFrame(frame))) => {
+                        if frame.has_new_parameters() {
+                            if let Some(ParametersRef::Video(video)) = frames.streams()[frame.stream_id()].parameters() {
+                                parameters = parameter_sets(video.extra_data());
+                            }
+                        }
+                        ingest.write_frame(&frame, &parameters).await;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => break Err(format!("stream failed: {}", e)),
+                    None => break Err("the camera ended the stream".to_string()),
+                },
+            }
+        };
+        ingest.close().await;
+        info!("RTSP ingest from {} stopped in room {}", host, config.room_id);
+        result
+    }
+
+    /// All default codecs, which include the H.264 profiles browsers decode
+    fn new_api() -> Result<API, webrtc::Error> {
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs()?;
+        let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+        Ok(APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build())
+    }
+
+    /// SPS and PPS NAL units of an AVCDecoderConfigurationRecord (retina's extra_data)
+    fn parameter_sets(record: &[u8]) -> Vec<Vec<u8>> {
+        let mut sets = Vec::new();
+        let mut pos = 5;
+        // The SPS count shares its byte with reserved bits; the PPS count has one to itself
+        for mask in [0x1f, 0xff] {
+            let Some(&count) = record.get(pos) else { break };
+            pos += 1;
+            for _ in 0..(count & mask) {
+                let Some(length) = record.get(pos..pos + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize) else {
+                    return sets;
+                };
+                let Some(set) = record.get(pos + 2..pos + 2 + length) else { return sets };
+                sets.push(set.to_vec());
+                pos += 2 + length;
+            }
+        }
+        sets
+    }
+
+    /// An Annex B access unit from a frame's 4-byte length-prefixed NAL units
+    fn annex_b(frame: &[u8], parameter_sets: &[Vec<u8>]) -> Vec<u8> {
+        let mut access_unit = Vec::with_capacity(frame.len() + parameter_sets.iter().map(|set| set.len() + 4).sum::<usize>());
+        for set in parameter_sets {
+            access_unit.extend_from_slice(&START_CODE);
+            access_unit.extend_from_slice(set);
+        }
+        let mut rest = frame;
+        while rest.len() >= 4 {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let Some(nal) = rest.get(4..4 + length) else { break };
+            access_unit.extend_from_slice(&START_CODE);
+            access_unit.extend_from_slice(nal);
+            rest = &rest[4 + length..];
+        }
+        access_unit
+    }
+
+    struct Ingest {
+        peer: LocalPeer,
+        api: API,
+        track: Arc<TrackLocalStaticSample>,
+        /// One peer connection per viewer the ingest was told about (only the SFU's peer in SFU rooms)
+        viewers: HashMap<String, Arc<RTCPeerConnection>>,
+        joined: bool,
+        /// Presentation time of the previous frame, in 90 kHz ticks
+        last_pts: Option<i64>,
+    }
+
+    impl Ingest {
+        async fn handle(&mut self, message: SignalingMessage) -> Result<(), String> {
+            let data = message.data.clone().unwrap_or(Value::Null);
+            let is_sender = |peer: &Value| peer.get("is_sender").and_then(Value::as_bool).unwrap_or(false);
+
+            match message.message_type {
+                SignalingMessageType::RoomInfo => {
+                    self.joined = true;
+                    let viewers: Vec<String> = data.get("peers")
+                        .and_then(Value::as_array)
+                        .map(|peers| peers.iter()
+                            .filter(|peer| !is_sender(peer))
+                            .filter_map(|peer| peer.get("id").and_then(Value::as_str).map(str::to_string))
+                            .collect())
+                        .unwrap_or_default();
+                    for viewer_id in viewers {
+                        self.offer_to(viewer_id).await;
+                    }
+                }
+                SignalingMessageType::NewPeer if !is_sender(&data) => {
+                    if let Some(viewer_id) = data.get("connection_id").and_then(Value::as_str) {
+                        self.offer_to(viewer_id.to_string()).await;
+                    }
+                }
+                SignalingMessageType::Answer => {
+                    let answer = message.data.and_then(|data| serde_json::from_value::<RTCSessionDescription>(data).ok());
+                    let viewer = message.sender_id.as_ref().and_then(|id| self.viewers.get(id));
+                    if let (Some(viewer), Some(answer)) = (viewer, answer) {
+                        if let Err(e) = viewer.set_remote_description(answer).await {
+                            warn!("RTSP ingest in room {} rejected an answer: {}", self.peer.room_id, e);
+                        }
+                    }
+                }
+                SignalingMessageType::IceCandidate => {
+                    let candidate = message.data.and_then(|data| serde_json::from_value::<RTCIceCandidateInit>(data).ok());
+                    let viewer = message.sender_id.as_ref().and_then(|id| self.viewers.get(id));
+                    if let (Some(viewer), Some(candidate)) = (viewer, candidate) {
+                        let _ = viewer.add_ice_candidate(candidate).await;
+                    }
+                }
+                SignalingMessageType::Leave => {
+                    if let Some(viewer) = data.get("connection_id").and_then(Value::as_str).and_then(|id| self.viewers.remove(id)) {
+                        let _ = viewer.close().await;
+                    }
+                }
+                SignalingMessageType::RoomClosed => return Err("the room was closed".to_string()),
+                SignalingMessageType::Error => {
+                    let error = data.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+                    if !self.joined {
+                        return Err(format!("could not join the room: {}", error));
+                    }
+                    warn!("RTSP ingest in room {}: {}", self.peer.room_id, error);
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+
+        /// (Re)creates the viewer's connection to the camera track and offers it
+        async fn offer_to(&mut self, viewer_id: String) {
+            if let Some(previous) = self.viewers.remove(&viewer_id) {
+                let _ = previous.close().await;
+            }
+            match self.create_viewer_connection(&viewer_id).await {
+                Ok(peer_connection) => {
+                    self.viewers.insert(viewer_id, peer_connection);
+                }
+                Err(e) => warn!("RTSP ingest in room {} could not offer to {}: {}", self.peer.room_id, viewer_id, e),
+            }
+        }
+
+        async fn create_viewer_connection(&self, viewer_id: &str) -> Result<Arc<RTCPeerConnection>, String> {
+            let peer_connection = Arc::new(
+                self.api.new_peer_connection(RTCConfiguration::default()).await.map_err(|e| e.to_string())?,
+            );
+            self.peer.forward_ice_candidates(&peer_connection, viewer_id.to_string());
+
+            let rtp_sender = peer_connection
+                .add_track(self.track.clone() as Arc<dyn TrackLocal + Send + Sync>)
+                .await
+                .map_err(|e| e.to_string())?;
+            // RTCP has to be read for the interceptors to run; the camera can't be asked for a
+            // keyframe, so PLIs are dropped
+            tokio::task::spawn(async move {
+                while rtp_sender.read_rtcp().await.is_ok() {}
+            });
+
+            let offer = peer_connection.create_offer(None).await.map_err(|e| e.to_string())?;
+            peer_connection.set_local_description(offer.clone()).await.map_err(|e| e.to_string())?;
+            let offer = serde_json::to_value(offer).map_err(|e| e.to_string())?;
+            self.peer.signal(SignalingMessage::new_offer(viewer_id.to_string(), self.peer.connection_id.clone(), offer)).await;
+            Ok(peer_connection)
+        }
+
+        async fn write_frame(&mut self, frame: &VideoFrame, parameter_sets: &[Vec<u8>]) {
+            let pts = frame.timestamp().elapsed();
+            let duration = match self.last_pts.replace(pts) {
+                Some(last) if pts > last => Duration::from_micros(((pts - last) as u64).saturating_mul(1_000_000) / 90_000),
+                _ => DEFAULT_FRAME_DURATION,
+            };
+            let leading_sets: &[Vec<u8>] = if frame.is_random_access_point() { parameter_sets } else { &[] };
+            let sample = Sample {
+                data: Bytes::from(annex_b(frame.data(), leading_sets)),
+                duration,
+                ..Default::default()
+            };
+            // Fails only while no viewer is connected
+            let _ = self.track.write_sample(&sample).await;
+        }
+
+        async fn close(&mut self) {
+            for (_, viewer) in self.viewers.drain() {
+                let _ = viewer.close().await;
+            }
+            self.peer.leave().await;
+        }
+    }
+}
+
+#[cfg(not(feature = "rtsp-ingest"))]
+pub fn spawn(config: RtspIngestConfig, _state: crate::state::SharedState, _room_manager: std::sync::Arc<tokio::sync::RwLock<crate::room::RoomManager>>, _clients: crate::Clients) {
+    tracing::error!("RTSP ingest into room {} needs a build with the rtsp-ingest feature", config.room_id);
+}
@@ -0,0 +1,73 @@
+// ip_filter.rs
+// CIDR allow and deny lists for clients of an internet-exposed instance. They apply to HTTP and
+// WebSocket requests (answered with 403; behind a trusted proxy the forwarded client IP counts)
+// and to STUN/TURN messages over UDP, TCP and TLS (dropped without a response). A deny entry wins
+// over an allow entry; an empty allow list admits every address not denied. The lists are
+// reloaded with the config.
+
+use std::net::IpAddr;
+use std::sync::RwLock;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+use warp::Filter;
+
+use crate::proxy;
+use crate::state::SharedState;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IpFilterConfig {
+    /// Networks allowed to connect, e.g. "192.168.0.0/16"; empty allows everyone not denied
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    /// Networks always refused
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilterConfig {
+    fn permits(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// The lists in effect for one server
+#[derive(Debug, Default)]
+pub struct IpFilter {
+    active: RwLock<IpFilterConfig>,
+}
+
+impl IpFilter {
+    pub fn new(config: &IpFilterConfig) -> Self {
+        Self { active: RwLock::new(config.clone()) }
+    }
+
+    /// Replaces the lists in effect; called on config reload
+    pub fn set(&self, config: &IpFilterConfig) {
+        *self.active.write().unwrap() = config.clone();
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        self.active.read().unwrap().permits(ip)
+    }
+}
+
+/// Answers requests from refused addresses with 403 and rejects the rest, so it goes in front
+/// of the other routes with `or`
+pub fn refuse(state: &SharedState) -> impl Filter<Extract = (warp::reply::WithStatus<&'static str>,), Error = warp::Rejection> + Clone {
+    let filter_state = state.clone();
+    proxy::client(state).and_then(move |client_ip: Option<IpAddr>| {
+        let state = filter_state.clone();
+        async move {
+            match client_ip {
+                Some(ip) if !state.ip_filter.permits(ip) => Ok(warp::reply::with_status("Forbidden", StatusCode::FORBIDDEN)),
+                _ => Err(warp::reject::not_found()),
+            }
+        }
+    })
+}
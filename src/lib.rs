@@ -0,0 +1,992 @@
+// lib.rs
+// The signaling, STUN and TURN server as a library. Server::builder() takes the config (and
+// optionally the config file to watch for reloads, and where inference results are stored) and
+// start() runs every listener until they stop, so other Rust applications can embed the stack
+// in their own runtime instead of running the binary; src/main.rs is a thin wrapper around it.
+
+use tracing::{info, warn, error};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{RwLock, oneshot};
+use warp::ws::{WebSocket, Message};
+use futures_util::{SinkExt, StreamExt};
+
+mod room;
+mod api;
+mod openapi;
+mod persistence;
+mod stun;
+mod stun_proto;
+mod turn;
+mod signaling;
+pub mod config;
+mod network;
+mod calibration;
+mod reload;
+mod transport;
+mod anomaly;
+mod tracker;
+mod inference;
+mod aggregation;
+mod mqtt;
+pub mod store;
+pub mod hooks;
+mod webhooks;
+mod event_bus;
+mod wasm_plugins;
+mod scripting;
+mod retention;
+mod downsampling;
+mod uploader;
+mod export;
+mod client_queue;
+mod relay;
+mod peer_stats;
+mod quality;
+mod room_template;
+mod room_groups;
+mod assets;
+mod http_cache;
+mod webm;
+mod fmp4;
+mod hls;
+mod recorder;
+mod local_peer;
+mod sfu;
+mod server_inference;
+mod snapshot;
+mod backplane;
+mod cluster;
+mod bridge;
+mod ingest;
+mod telemetry;
+mod logging;
+mod external_address;
+mod mdns;
+mod qr;
+mod audit;
+mod connection_log;
+mod ip_filter;
+mod proxy;
+mod message_size;
+mod listeners;
+mod client_certs;
+mod auth;
+mod oidc;
+mod sdp;
+mod ice_policy;
+pub mod tenant;
+pub mod quota;
+mod presence;
+mod schedule;
+mod zones;
+mod counting;
+mod replay;
+mod session;
+mod poll;
+mod webtransport;
+mod state;
+mod routes;
+
+use room::RoomManager;
+use stun::{StunServer, StunStats};
+use turn::{TurnServer, TurnAuth, TurnQuota};
+use config::SharedConfig;
+use client_certs::ClientCertMode;
+use std::net::SocketAddr;
+use anyhow::Context as _;
+use std::fs;
+use rcgen::generate_simple_self_signed;
+use network::get_all_local_ips;
+use client_queue::ClientHandle;
+use ws2infer_protocol::{chunk, deflate, Negotiated, CAP_BINARY, CAP_DEFLATE};
+use server_inference::ServerInference;
+use state::{SharedState, State};
+
+// How often viewers receive InferenceStats for rooms with recent results
+const INFERENCE_STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// How often viewers receive Analytics for rooms whose line counts changed
+const ANALYTICS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// A client whose queue stays full this long is disconnected
+const QUEUE_SATURATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// How long a single WebSocket send may block before the connection is considered wedged
+const SEND_STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Peers with more queued messages than this are skipped for InferenceUpdate, InferenceStats and
+// Analytics; the next update supersedes the dropped one anyway
+const DROPPABLE_BACKLOG: usize = 32;
+
+// Type alias for Clients map: connection_id -> outgoing queue
+type Clients = Arc<RwLock<HashMap<String, ClientHandle>>>;
+
+pub use config::Config;
+pub use store::{InferenceStore, SqliteStore};
+pub use hooks::{HookContext, HookDecision, SignalingHook};
+pub use room::Role;
+pub use signaling::{SignalingMessage, SignalingMessageType};
+
+/// Installs the tracing subscriber described by the config's `logging` and `telemetry`.
+/// Embedders with their own subscriber skip this.
+pub fn init_logging(config: &Config) {
+    logging::init(&config.logging, config.telemetry.as_ref());
+}
+
+/// An embeddable server; see Server::builder
+pub struct Server {
+    config: Config,
+    config_path: Option<std::path::PathBuf>,
+    data_dir: std::path::PathBuf,
+    store: Option<Arc<dyn InferenceStore>>,
+    hooks: hooks::Hooks,
+}
+
+/// Collects what the server runs with; anything not set falls back to the binary's behaviour
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Option<Config>,
+    config_path: Option<std::path::PathBuf>,
+    data_dir: Option<std::path::PathBuf>,
+    store: Option<Arc<dyn InferenceStore>>,
+    hooks: hooks::Hooks,
+}
+
+impl ServerBuilder {
+    /// Runs with this config instead of loading the config file
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Config file watched for reloadable settings, and loaded at start unless with_config was
+    /// given. Without it the config never changes after start.
+    pub fn with_config_path(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Directory for inference.db, inference.jsonl and the rest of the server's own data;
+    /// `data` when unset. Servers embedded in one process need one each.
+    pub fn with_data_dir(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.data_dir = Some(path.into());
+        self
+    }
+
+    /// Stores inference results here instead of inference.db and inference.jsonl in the data
+    /// directory
+    pub fn with_store(mut self, store: Arc<dyn InferenceStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Adds a hook that sees messages after the hooks added before it
+    pub fn with_hook(mut self, hook: Arc<dyn SignalingHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        let config = match (self.config, &self.config_path) {
+            (Some(config), _) => config,
+            (None, Some(path)) => Config::load(path).unwrap_or_else(|e| {
+                error!("Failed to load {}: {}. Using defaults.", path.display(), e);
+                Config::default()
+            }),
+            (None, None) => Config::default(),
+        };
+        let data_dir = self.data_dir.unwrap_or_else(|| state::DEFAULT_DATA_DIR.into());
+        Server { config, config_path: self.config_path, data_dir, store: self.store, hooks: self.hooks }
+    }
+
+    /// Builds the server and runs it until its HTTP listeners stop
+    pub async fn start(self) -> anyhow::Result<()> {
+        self.build().run().await
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Starts the STUN, TURN and background tasks, then serves HTTP and WebSocket signaling
+    /// until the listeners stop
+    pub async fn run(self) -> anyhow::Result<()> {
+        run(self).await
+    }
+}
+
+async fn run(server: Server) -> anyhow::Result<()> {
+    let Server { mut config, config_path, data_dir, store, mut hooks } = server;
+    info!("Starting Cam2WebRTC Signaling Server...");
+
+    if let Err(problems) = config.validate() {
+        for problem in &problems {
+            error!("Invalid config: {}", problem);
+        }
+        anyhow::bail!("config has {} problem(s); fix them and restart", problems.len());
+    }
+    let stun_tcp_addr = parse_stream_addr(config.stun_tcp_addr.as_deref(), "stun_tcp_addr")?;
+    let turn_tcp_addr = parse_stream_addr(config.turn_tcp_addr.as_deref(), "turn_tcp_addr")?;
+    let turns_addr = parse_stream_addr(config.turns_addr.as_deref(), "turns_addr")?;
+
+    let state: SharedState = Arc::new(State::new(&config, data_dir));
+
+    // Ensure data directory exists and initialize persistence DB
+    if let Err(e) = std::fs::create_dir_all(state.data_dir()) {
+        error!("Failed to create data directory {}: {}", state.data_dir().display(), e);
+    }
+
+    match persistence::init_db(&state.db_path()) {
+        Ok(()) => connection_log::close_interrupted(&state.db_path()),
+        Err(e) => error!("Failed to initialize inference DB: {}", e),
+    }
+
+    // Learn the public IP before anything advertises addresses to clients
+    external_address::init(&mut config.external_address).await;
+
+    // Startup snapshot for listener settings; reloadable settings are read from shared_config
+    let shared_config: SharedConfig = Arc::new(RwLock::new(config.clone()));
+    let config_arc = Arc::new(config);
+
+    // Generate certificates if they don't exist (HTTPS, turns: and WebTransport share them)
+    let tls_used = config_arc.tls_enabled || config_arc.turns_addr.is_some() || config_arc.webtransport.is_some();
+    if tls_used && (!std::path::Path::new(&config_arc.tls_cert_path).exists() || !std::path::Path::new(&config_arc.tls_key_path).exists()) {
+        info!("Generating self-signed certificate...");
+        let subject_alt_names = get_all_local_ips();
+        info!("Certificate will be valid for: {:?}", subject_alt_names);
+        let cert = generate_simple_self_signed(subject_alt_names)?;
+        fs::write(&config_arc.tls_cert_path, cert.serialize_pem()?)?;
+        fs::write(&config_arc.tls_key_path, cert.serialize_private_key_pem())?;
+        info!("Certificate generated: {} and {}", config_arc.tls_cert_path, config_arc.tls_key_path);
+    }
+
+    // Start one STUN server per configured address; STUN/TCP shares the first one's handler
+    // and all of them count into the same stats for /metrics
+    let stun_stats = StunStats::default();
+    for (i, stun_addr) in config_arc.stun_addrs().into_iter().enumerate() {
+        let stats = stun_stats.clone();
+        let stun_state = state.clone();
+        tokio::task::spawn(async move {
+            match StunServer::new(stun_addr, stats, stun_state.clone()) {
+                Ok(server) => {
+                    let server = Arc::new(server);
+
+                    if let (0, Some(tcp_addr)) = (i, stun_tcp_addr) {
+                        let tcp_server = server.clone();
+                        tokio::task::spawn(async move {
+                            let handler = move |packet: Vec<u8>, peer: SocketAddr| {
+                                let server = tcp_server.clone();
+                                async move { server.handle_stun_packet(&packet, peer) }
+                            };
+                            if let Err(e) = transport::listen(tcp_addr, None, stun_state, handler, "STUN/TCP").await {
+                                error!("STUN/TCP listener failed: {}", e);
+                            }
+                        });
+                    }
+
+                    info!("Starting STUN server on {}", stun_addr);
+                    if let Err(e) = server.run().await {
+                        error!("STUN server failed: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to create STUN server on {}: {}", stun_addr, e);
+                }
+            }
+        });
+    }
+
+    // Start TURN server; the handle is kept for the usage/metrics endpoints
+    let turn_addrs = config_arc.turn_addrs();
+    let turn_auth = config_arc.turn_secret.clone()
+        .map(|secret| TurnAuth::new(secret, config_arc.turn_realm.clone()));
+    let relay_ports = config_arc.relay_port_min..=config_arc.relay_port_max;
+    let turn_quota = TurnQuota {
+        max_bytes: config_arc.turn_quota_bytes,
+        max_bytes_per_sec: config_arc.turn_max_bytes_per_sec,
+    };
+    let turn_server = match TurnServer::new(&turn_addrs, turn_auth, relay_ports, state.clone()) {
+        Ok(server) => Some(Arc::new(server.with_quota(turn_quota).with_relay_ip(config_arc.external_address.ip()))),
+        Err(e) => {
+            error!("Failed to create TURN server: {}", e);
+            None
+        }
+    };
+
+    if let Some(server) = turn_server.clone() {
+        let turn_config = config_arc.clone();
+        let turn_state = state.clone();
+        tokio::task::spawn(async move {
+            // TURN over TCP (turn:...?transport=tcp) and TLS (turns:)
+            let tls_acceptor = match turns_addr {
+                Some(_) => match transport::load_tls_acceptor(&turn_config.tls_cert_path, &turn_config.tls_key_path) {
+                    Ok(acceptor) => Some(acceptor),
+                    Err(e) => {
+                        error!("Failed to load TLS certificate for TURN/TLS: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let stream_listeners = [
+                (turn_tcp_addr, None, "TURN/TCP"),
+                (turns_addr.filter(|_| tls_acceptor.is_some()), tls_acceptor, "TURN/TLS"),
+            ];
+            for (addr, tls, label) in stream_listeners {
+                let Some(addr) = addr else {
+                    continue;
+                };
+                let tcp_server = server.clone();
+                let listen_state = turn_state.clone();
+                tokio::task::spawn(async move {
+                    let handler = move |packet: Vec<u8>, peer: SocketAddr| {
+                        let server = tcp_server.clone();
+                        async move { server.handle_turn_packet(&packet, peer).await }
+                    };
+                    if let Err(e) = transport::listen(addr, tls, listen_state, handler, label).await {
+                        error!("{} listener failed: {}", label, e);
+                    }
+                });
+            }
+
+            info!("Starting TURN server on {:?}", turn_addrs);
+            if let Err(e) = server.run().await {
+                error!("TURN server failed: {}", e);
+            }
+        });
+    }
+    
+    // Initialize room manager
+    let mut manager = RoomManager::new();
+    manager.calibration = config_arc.score_calibration.clone();
+    manager.admin_token = config_arc.admin_token.clone();
+    manager.tenant_admin_tokens = tenant::admin_tokens(&config_arc.tenants);
+    manager.quotas.set_config(config_arc.room_quotas.clone(), &config_arc.tenants);
+    manager.guest_session_secs = config_arc.guest_session_secs;
+    manager.groups = room_groups::RoomGroups::load(&state.db_path());
+    manager.anomaly = config_arc.anomaly.clone().map(anomaly::AnomalyDetector::new);
+    manager.tracker = config_arc.tracking.clone().map(tracker::Tracker::new);
+    manager.mqtt = config_arc.mqtt.as_ref().map(mqtt::MqttPublisher::start);
+    manager.store = store.unwrap_or_else(|| Arc::new(SqliteStore::in_dir(state.data_dir())));
+    // WASM plugins run after the embedder's hooks
+    let wasm_plugins = config_arc.wasm_plugins.clone().and_then(|plugins_config| match wasm_plugins::WasmPlugins::start(plugins_config) {
+        Ok(plugins) => Some(Arc::new(plugins)),
+        Err(e) => {
+            error!("WASM plugins disabled: {}", e);
+            None
+        }
+    });
+    if let Some(plugins) = &wasm_plugins {
+        hooks.push(plugins.clone());
+    }
+    manager.hooks = hooks;
+    manager.scripts = config_arc.scripting.as_ref().and_then(|scripting_config| match scripting::Scripts::load(scripting_config) {
+        Ok(scripts) => Some(scripts),
+        Err(e) => {
+            error!("Room policy scripts disabled: {}", e);
+            None
+        }
+    });
+    manager.event_bus = state.event_bus.clone();
+    manager.webhooks = state.webhooks.clone();
+    manager.hls = state.hls.clone();
+    manager.coalesce_inference_updates = config_arc.inference_broadcast_ms > 0;
+    manager.relay = relay::RelayLimiter::new(config_arc.relay.clone());
+    manager.inference_limits = config_arc.inference_limits.clone();
+    manager.sdp = config_arc.sdp.clone();
+    manager.quality = quality::QualityController::new(config_arc.quality.clone(), config_arc.video_constraints.clone());
+    let room_manager = Arc::new(RwLock::new(manager));
+    
+    // Initialize clients map
+    let clients = Clients::default();
+
+    if let Some(auth_config) = &config_arc.auth {
+        let _ = state.auth.set(auth::start(auth_config).await);
+    }
+
+    // Without Redis the instance still serves its own rooms
+    if let Some(backplane_config) = &config_arc.backplane {
+        if let Err(e) = backplane::Backplane::start(backplane_config, &state, room_manager.clone(), clients.clone()).await {
+            error!("Redis backplane disabled: {}", e);
+        }
+    }
+
+    // A model that fails to load disables server-side inference rather than the server
+    let server_inference = config_arc.server_inference.as_ref().and_then(|inference_config| {
+        match ServerInference::start(inference_config, state.clone(), room_manager.clone(), clients.clone()) {
+            Ok(server_inference) => Some(server_inference),
+            Err(e) => {
+                error!("Server-side inference disabled: {}", e);
+                None
+            }
+        }
+    });
+
+    // Periodically re-learn the anomaly baseline from stored history
+    if let Some(anomaly_config) = config_arc.anomaly.clone() {
+        let anomaly_room_manager = room_manager.clone();
+        let anomaly_db_path = state.db_path();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(anomaly_config.relearn_secs));
+            loop {
+                interval.tick().await;
+                let since = chrono::Utc::now() - chrono::Duration::days(anomaly_config.history_days as i64);
+                let db_path = anomaly_db_path.clone();
+                let history = tokio::task::spawn_blocking(move || {
+                    persistence::load_inference_since(&db_path, since)
+                }).await;
+
+                match history {
+                    Ok(Ok(history)) => {
+                        let baseline = anomaly::Baseline::learn(&history);
+                        if let Some(detector) = anomaly_room_manager.write().await.anomaly.as_mut() {
+                            detector.set_baseline(baseline);
+                        }
+                        info!("Anomaly baseline learned from {} stored results", history.len());
+                    }
+                    Ok(Err(e)) => error!("Failed to load inference history for anomaly baseline: {}", e),
+                    Err(e) => error!("Anomaly baseline task failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Prune old inference rows and rotate the JSONL export
+    if let Some(retention_config) = config_arc.retention.clone() {
+        retention::spawn(retention_config, state.db_path(), state.jsonl_path());
+    }
+
+    // Roll detections up into per-minute and per-hour summaries
+    if let Some(downsampling_config) = config_arc.downsampling.clone() {
+        downsampling::spawn(downsampling_config, state.db_path());
+    }
+
+    // Offload rotated exports, snapshots and recordings to object storage
+    if let Some(upload_config) = config_arc.upload.clone() {
+        uploader::spawn(
+            upload_config,
+            state.db_path(),
+            state.jsonl_path(),
+            config_arc.snapshots.directory.clone(),
+            config_arc.recording.directory.clone(),
+        );
+    }
+
+    // End guest sessions that ran out of time and connections to rooms left idle
+    let guest_state = state.clone();
+    let guest_room_manager = room_manager.clone();
+    let guest_clients = clients.clone();
+    let guest_warning_secs = config_arc.guest_warning_secs;
+    let idle_warning_secs = config_arc.idle_warning_secs;
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let mut manager = guest_room_manager.write().await;
+            let now = chrono::Utc::now();
+            let (mut responses, expired) = manager.expire_guests(now, guest_warning_secs);
+            let (idle_responses, idle) = manager.expire_idle(now, idle_warning_secs);
+            responses.extend(idle_responses);
+            let expired: Vec<_> = expired.into_iter().map(|closed| (closed, "guest_session_expired"))
+                .chain(idle.into_iter().map(|closed| (closed, "idle_timeout")))
+                .collect();
+            let mut emptied = HashSet::new();
+            for ((room_id, connection_id), _) in expired.iter().rev() {
+                // The last connection closed in a room is the one that emptied it
+                if manager.rooms.get(room_id).is_some_and(|room| room.connections.is_empty()) && emptied.insert(room_id) {
+                    guest_state.webhooks.emit(webhooks::ROOM_EMPTIED, room_id, serde_json::json!({"connection_id": connection_id}));
+                }
+            }
+            drop(manager);
+            route_responses(&guest_state, &guest_clients, responses).await;
+            for ((room_id, connection_id), reason) in expired {
+                info!("Closing {} in {}: {}", connection_id, room_id, reason);
+                audit::record(
+                    &guest_state,
+                    audit::CONNECTION_CLOSED,
+                    audit::SERVER,
+                    None,
+                    Some(&room_id),
+                    serde_json::json!({"connection_id": connection_id, "reason": reason}),
+                );
+                close_connection(&guest_clients, &connection_id).await;
+                if let Some(backplane) = guest_state.backplane() {
+                    backplane.left(&room_id, &connection_id).await;
+                }
+            }
+        }
+    });
+
+    // Close scheduled rooms whose window ended
+    let schedule_state = state.clone();
+    let schedule_room_manager = room_manager.clone();
+    let schedule_clients = clients.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let mut manager = schedule_room_manager.write().await;
+            let closed: Vec<_> = manager.ended_rooms(chrono::Utc::now())
+                .into_iter()
+                .filter_map(|room_id| manager.close_room(&room_id, schedule::ENDED_REASON).map(|closed| (room_id, closed)))
+                .collect();
+            drop(manager);
+            for (room_id, (responses, connection_ids)) in closed {
+                route_responses(&schedule_state, &schedule_clients, responses).await;
+                for connection_id in &connection_ids {
+                    close_connection(&schedule_clients, connection_id).await;
+                }
+                if let Some(backplane) = schedule_state.backplane() {
+                    backplane.forget_room(&room_id).await;
+                }
+                info!("Room {} reached the end of its schedule, {} connection(s) closed", room_id, connection_ids.len());
+                audit::record(
+                    &schedule_state,
+                    audit::ROOM_DELETED,
+                    audit::SERVER,
+                    None,
+                    Some(&room_id),
+                    serde_json::json!({"closed_connections": connection_ids.len(), "reason": "schedule_ended"}),
+                );
+                schedule_state.webhooks.emit(webhooks::ROOM_DELETED, &room_id, serde_json::json!({"closed_connections": connection_ids.len(), "reason": "schedule_ended"}));
+            }
+        }
+    });
+
+    // Coalesce InferenceUpdate broadcasts: latest result per source once per interval
+    if config_arc.inference_broadcast_ms > 0 {
+        let updates_state = state.clone();
+        let updates_room_manager = room_manager.clone();
+        let updates_clients = clients.clone();
+        let broadcast_interval = std::time::Duration::from_millis(config_arc.inference_broadcast_ms);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(broadcast_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let responses = updates_room_manager.write().await.take_inference_updates();
+                route_responses(&updates_state, &updates_clients, responses).await;
+            }
+        });
+    }
+
+    // Push rolling inference statistics to viewers
+    let stats_state = state.clone();
+    let stats_room_manager = room_manager.clone();
+    let stats_clients = clients.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(INFERENCE_STATS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let responses = stats_room_manager.write().await.inference_stats_messages(chrono::Utc::now());
+            route_responses(&stats_state, &stats_clients, responses).await;
+        }
+    });
+
+    // Push counting line totals to viewers
+    let analytics_state = state.clone();
+    let analytics_room_manager = room_manager.clone();
+    let analytics_clients = clients.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(ANALYTICS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let responses = analytics_room_manager.write().await.analytics_messages();
+            route_responses(&analytics_state, &analytics_clients, responses).await;
+        }
+    });
+
+    // Disconnect clients whose send queue stays full; they can't keep up with the room
+    let saturation_state = state.clone();
+    let saturation_clients = clients.clone();
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let now = std::time::Instant::now();
+            let saturated: Vec<String> = saturation_clients.read().await.iter()
+                .filter(|(_, client)| client.saturated_for(now).is_some_and(|d| d >= QUEUE_SATURATION_TIMEOUT))
+                .map(|(connection_id, _)| connection_id.clone())
+                .collect();
+            for connection_id in saturated {
+                warn!("Send queue of {} stayed full for {:?}; disconnecting", connection_id, QUEUE_SATURATION_TIMEOUT);
+                audit::record(
+                    &saturation_state,
+                    audit::CONNECTION_CLOSED,
+                    audit::SERVER,
+                    None,
+                    None,
+                    serde_json::json!({"connection_id": connection_id, "reason": "send_queue_saturated"}),
+                );
+                close_connection(&saturation_clients, &connection_id).await;
+            }
+        }
+    });
+
+    // Join the rooms of other instances configured as bridges
+    for bridge_config in config_arc.bridges.clone() {
+        bridge::spawn(bridge_config, state.clone(), room_manager.clone(), clients.clone());
+    }
+
+    // Pull RTSP cameras into their rooms
+    for ingest_config in config_arc.rtsp_ingest.clone() {
+        ingest::spawn(ingest_config, state.clone(), room_manager.clone(), clients.clone());
+    }
+
+    // Watch the config file and apply reloadable settings without a restart
+    if let Some(config_path) = config_path {
+        reload::spawn(
+            config_path,
+            state.clone(),
+            shared_config.clone(),
+            room_manager.clone(),
+            clients.clone(),
+        );
+    }
+    
+    let cluster = config_arc.cluster.clone().map(Arc::new);
+
+    // Experimental WebTransport signaling, served by the same sessions as the WebSocket route
+    if let Some(webtransport_config) = config_arc.webtransport.clone() {
+        let context = webtransport::Context {
+            state: state.clone(),
+            room_manager: room_manager.clone(),
+            clients: clients.clone(),
+            server_inference: server_inference.clone(),
+            cluster: cluster.clone(),
+        };
+        let (cert_path, key_path) = (config_arc.tls_cert_path.clone(), config_arc.tls_key_path.clone());
+        tokio::task::spawn(async move {
+            if let Err(e) = webtransport::run(webtransport_config, cert_path, key_path, context).await {
+                error!("WebTransport listener failed: {}", e);
+            }
+        });
+    }
+
+    // Long-polling fallback for clients whose proxies block WebSocket upgrades
+    let poll_sessions = poll::PollSessions::default();
+    poll_sessions.spawn_reaper();
+
+    // HTTP and WebSocket routes (routes/)
+    let context = routes::Context {
+        state: state.clone(),
+        config: shared_config.clone(),
+        room_manager: room_manager.clone(),
+        clients: clients.clone(),
+        server_inference,
+        cluster,
+        poll_sessions,
+        recordings: recorder::SharedRecordings::default(),
+        turn_server,
+        wasm_plugins,
+        stun_stats,
+    };
+    let routes = routes::all(&context, assets::filter(&config_arc.static_files));
+    
+    // Sockets from systemd replace the configured TCP addresses; the Unix socket comes on top
+    let mut local_listeners = listeners::inherited();
+    let addrs = if local_listeners.is_empty() {
+        config_arc.signaling_addrs()
+    } else {
+        info!("Socket-activated: not binding signaling_addr");
+        Vec::new()
+    };
+    if let Some(path) = &config_arc.signaling_unix_socket {
+        match listeners::bind_unix(path) {
+            Ok(listener) => local_listeners.push(listener),
+            Err(e) => error!("Failed to listen on unix:{}: {}", path, e),
+        }
+    }
+    let local_servers = futures_util::future::join_all(local_listeners.into_iter().map(|listener| {
+        let routes = routes.clone();
+        async move {
+            match listener {
+                listeners::Listener::Tcp(listener) => warp::serve(routes).run_incoming(listeners::accept_tcp(listener)).await,
+                #[cfg(unix)]
+                listeners::Listener::Unix(listener) => warp::serve(routes).run_incoming(listeners::accept_unix(listener)).await,
+            }
+        }
+    }));
+
+    let mdns_daemon = config_arc.mdns.as_ref()
+        .zip(addrs.first())
+        .and_then(|(mdns_config, addr)| mdns::advertise(mdns_config, *addr, config_arc.tls_enabled));
+    
+    if config_arc.tls_enabled {
+        for addr in &addrs {
+            info!("Server listening on https://{}", addr);
+        }
+        
+        if let Some(local_ip) = network::get_local_ip() {
+            info!("Access from mobile devices: https://{}:8080/sender.html or viewer.html", local_ip);
+            info!("Note: You may need to accept the self-signed certificate warning on your mobile device.");
+        }
+        if let Some(client_certs) = &config_arc.tls_client_certs {
+            info!("TLS client certificates from {} are {:?}", client_certs.ca_path, client_certs.mode);
+        }
+        
+        let servers = addrs.into_iter().map(|addr| {
+            let server = warp::serve(routes.clone())
+                .tls()
+                .cert_path(&config_arc.tls_cert_path)
+                .key_path(&config_arc.tls_key_path);
+            let server = match &config_arc.tls_client_certs {
+                Some(client_certs) if client_certs.mode == ClientCertMode::Required => server.client_auth_required_path(&client_certs.ca_path),
+                Some(client_certs) => server.client_auth_optional_path(&client_certs.ca_path),
+                None => server,
+            };
+            server.run(addr)
+        });
+        tokio::join!(futures_util::future::join_all(servers), local_servers);
+    } else {
+        for addr in &addrs {
+            info!("Server listening on http://{}", addr);
+        }
+        let servers = addrs.into_iter().map(|addr| warp::serve(routes.clone()).run(addr));
+        tokio::join!(futures_util::future::join_all(servers), local_servers);
+    }
+
+    if let Some(daemon) = mdns_daemon {
+        let _ = daemon.shutdown();
+    }
+    telemetry::shutdown();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "websocket", skip_all, fields(room_id = %room_id, client_ip = ?client_ip, connection_id = tracing::field::Empty))]
+async fn handle_websocket(
+    socket: WebSocket,
+    state: SharedState,
+    room_id: String,
+    room_manager: Arc<RwLock<RoomManager>>,
+    clients: Clients,
+    server_inference: Option<Arc<ServerInference>>,
+    client_ip: Option<std::net::IpAddr>,
+    user_agent: Option<String>,
+) {
+    info!("New WebSocket connection for room: {}", room_id);
+    
+    let (mut user_ws_tx, mut user_ws_rx) = socket.split();
+    
+    let mut session = session::Session::new(state, room_id.clone(), room_manager, clients, server_inference, client_ip)
+        .with_user_agent(user_agent);
+    let forward_client = session.client().clone();
+    
+    // Resolves when the forwarding task stops (stalled or failed socket), so the read loop
+    // below also stops and the connection is cleaned up
+    let (stalled_tx, mut stalled_rx) = oneshot::channel::<()>();
+    
+    // Spawn task to forward messages from channel to WebSocket.
+    // Each send is bounded by SEND_STALL_TIMEOUT so a peer that stops reading cannot keep
+    // this task (and its queue) alive forever.
+    let forward_room_id = room_id.clone();
+    tokio::task::spawn(async move {
+        let mut sent: u64 = 0;
+        while let Some(message) = forward_client.recv().await {
+            // A close frame queued by the server ends the connection once it is sent
+            let is_close = message.is_close();
+            match tokio::time::timeout(SEND_STALL_TIMEOUT, user_ws_tx.send(message)).await {
+                Ok(Ok(())) => {
+                    sent += 1;
+                    if is_close {
+                        break;
+                    }
+                }
+                Ok(Err(e)) => {
+                    error!("Websocket send error: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    error!(
+                        "WebSocket send stalled for {:?} in room {} after {} messages; closing connection",
+                        SEND_STALL_TIMEOUT, forward_room_id, sent
+                    );
+                    let _ = stalled_tx.send(());
+                    break;
+                }
+            }
+        }
+        forward_client.shutdown();
+    });
+
+    // Handle incoming messages
+    loop {
+        let result = tokio::select! {
+            next = user_ws_rx.next() => match next {
+                Some(result) => result,
+                None => break,
+            },
+            _ = &mut stalled_rx => break,
+        };
+        
+        match result {
+            Ok(msg) => session.receive(&msg).await,
+            Err(e) => {
+                error!("WebSocket error: {}", e);
+                break;
+            }
+        }
+    }
+    
+    session.end("WebSocket").await;
+}
+
+/// Forwards a room group's events to a dashboard until it disconnects. Events are dropped for
+/// a subscriber that falls behind rather than held for it.
+async fn handle_group_websocket(socket: WebSocket, group_id: String, mut events: tokio::sync::broadcast::Receiver<room_groups::GroupEvent>) {
+    info!("Group subscriber connected to {}", group_id);
+    let (mut tx, mut rx) = socket.split();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if event.group_id == group_id => {
+                    let Ok(text) = serde_json::to_string(&*event.message) else { continue };
+                    if tx.send(Message::text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Group subscriber of {} missed {} events", group_id, missed);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = rx.next() => match incoming {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => break,
+            },
+        }
+    }
+    let _ = tx.close().await;
+    info!("Group subscriber disconnected from {}", group_id);
+}
+
+/// Tells a client which instance owns its room, then closes the connection
+async fn redirect_websocket(socket: WebSocket, url: String) {
+    info!("Redirecting WebSocket to {}", url);
+    let (mut tx, _) = socket.split();
+    if let Ok(text) = serde_json::to_string(&SignalingMessage::new_redirect(url)) {
+        let _ = tx.send(Message::text(text)).await;
+    }
+    let _ = tx.close().await;
+}
+
+/// Sends each response to the client named by its connection_id, adapted to the protocol
+/// version and capabilities it negotiated. Targets connected to another instance are reached
+/// through the backplane; targets that are no longer registered anywhere have disconnected and
+/// are skipped, as are messages their protocol version doesn't know.
+async fn route_responses(state: &State, clients: &Clients, responses: Vec<SignalingMessage>) {
+    let elsewhere = route_local(clients, responses).await;
+    if let (Some(backplane), false) = (state.backplane(), elsewhere.is_empty()) {
+        backplane.forward(elsewhere).await;
+    }
+}
+
+/// Sends the responses for clients connected to this instance and returns the others
+async fn route_local(clients: &Clients, responses: Vec<SignalingMessage>) -> Vec<SignalingMessage> {
+    let clients_guard = clients.read().await;
+    let mut elsewhere = Vec::new();
+    for response in responses {
+        let target = match response.connection_id.as_ref().and_then(|id| clients_guard.get(id)) {
+            Some(target) => target,
+            None => {
+                elsewhere.push(response);
+                continue;
+            }
+        };
+        let droppable = matches!(
+            response.message_type,
+            SignalingMessageType::InferenceUpdate | SignalingMessageType::InferenceStats | SignalingMessageType::Analytics
+        );
+        if droppable && target.depth() > DROPPABLE_BACKLOG {
+            continue;
+        }
+        let protocol = target.protocol();
+        let mut response = match response.adapt_for(&protocol) {
+            Some(response) => response,
+            None => continue,
+        };
+        if droppable {
+            let mut frames = encode_for(&response, &protocol);
+            if frames.len() == 1 {
+                target.send_droppable(frames.remove(0));
+            } else if !frames.is_empty() {
+                target.send_chunks(frames);
+            }
+        } else {
+            target.send_sequenced(|seq| {
+                response.seq = Some(seq);
+                encode_for(&response, &protocol)
+            });
+        }
+    }
+    elsewhere
+}
+
+/// JSON for the client, as a binary frame (compressed when large) if it negotiated that. A
+/// frame above the max_message_bytes of a chunking client is replaced by chunk frames; no
+/// frames means the message couldn't be encoded.
+fn encode_for(response: &SignalingMessage, protocol: &Negotiated) -> Vec<Message> {
+    let Ok(response_text) = serde_json::to_string(response) else {
+        return Vec::new();
+    };
+    let frame = encode_text(&response_text, protocol);
+    match protocol.max_message_bytes {
+        Some(max_message_bytes) if frame.as_bytes().len() > max_message_bytes => {
+            let id = uuid::Uuid::new_v4().to_string();
+            chunk::split(&response_text, max_message_bytes, &id, response.connection_id.clone())
+                .iter()
+                .filter_map(|piece| serde_json::to_string(piece).ok())
+                .map(|piece| if protocol.supports(CAP_BINARY) { Message::binary(piece) } else { Message::text(piece) })
+                .collect()
+        }
+        _ => vec![frame],
+    }
+}
+
+fn encode_text(text: &str, protocol: &Negotiated) -> Message {
+    if protocol.supports(CAP_DEFLATE) && text.len() >= deflate::DEFLATE_MIN_BYTES {
+        Message::binary(deflate::compress(text.as_bytes()))
+    } else if protocol.supports(CAP_BINARY) {
+        Message::binary(text)
+    } else {
+        Message::text(text)
+    }
+}
+
+/// The address of an optional STUN/TURN stream listener; `field` names it in the error
+fn parse_stream_addr(addr: Option<&str>, field: &str) -> anyhow::Result<Option<SocketAddr>> {
+    addr.map(|addr| addr.parse::<SocketAddr>().with_context(|| format!("Invalid {} {}", field, addr)))
+        .transpose()
+}
+
+/// Unregisters a client and queues a close frame; its forwarding task stops after sending it,
+/// which ends the connection's read loop as well.
+async fn close_connection(clients: &Clients, connection_id: &str) {
+    if let Some(client) = clients.write().await.remove(connection_id) {
+        client.close();
+    }
+}
+
+/// Prometheus exposition of per-connection send queue depth and drops
+async fn render_client_metrics(clients: &Clients) -> String {
+    let clients = clients.read().await;
+    // Connection IDs are chosen by clients, so escape them for the label value
+    let label = |id: &str| id.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let mut out = String::new();
+    out.push_str("# HELP ws_client_queue_depth Messages queued for a WebSocket connection\n");
+    out.push_str("# TYPE ws_client_queue_depth gauge\n");
+    for (connection_id, client) in clients.iter() {
+        out.push_str(&format!("ws_client_queue_depth{{connection_id=\"{}\"}} {}\n", label(connection_id), client.depth()));
+    }
+    out.push_str("# HELP ws_client_queue_dropped_total Messages dropped because a connection's queue was full\n");
+    out.push_str("# TYPE ws_client_queue_dropped_total counter\n");
+    for (connection_id, client) in clients.iter() {
+        out.push_str(&format!("ws_client_queue_dropped_total{{connection_id=\"{}\"}} {}\n", label(connection_id), client.dropped()));
+    }
+    out
+}
@@ -0,0 +1,130 @@
+// listeners.rs
+// Listeners for the signaling HTTP server that aren't bound from signaling_addr: a Unix domain
+// socket for a reverse proxy on the same host, and sockets passed in by systemd socket
+// activation (the sd_listen_fds protocol: LISTEN_PID, LISTEN_FDS, descriptors from 3), which lets
+// systemd hold the port across restarts. Both are served as plain HTTP, TLS being the proxy's
+// job, and neither tells warp the client's address, so they are treated as proxy connections.
+
+use std::io;
+use std::time::Duration;
+use futures_util::Stream;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::{info, warn};
+
+/// Pause after a failed accept (e.g. out of file descriptors) before trying again
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Sockets systemd passed to this process; empty when not socket-activated
+#[cfg(unix)]
+pub fn inherited() -> Vec<Listener> {
+    use std::os::unix::io::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let for_us = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+    if !for_us || count <= 0 {
+        return Vec::new();
+    }
+    // Not meant for child processes
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        .filter_map(|fd| {
+            // SAFETY: systemd hands these descriptors to this process, and nothing else owns them
+            let socket = unsafe { socket2::Socket::from_raw_fd(fd) };
+            match adopt(socket) {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    warn!("Ignoring socket {} from systemd: {}", fd, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn inherited() -> Vec<Listener> {
+    Vec::new()
+}
+
+#[cfg(unix)]
+fn adopt(socket: socket2::Socket) -> io::Result<Listener> {
+    if socket.r#type()? != socket2::Type::STREAM {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a stream socket"));
+    }
+    socket.set_nonblocking(true)?;
+    let local = socket.local_addr()?;
+    if local.domain() == socket2::Domain::UNIX {
+        info!("Server listening on a Unix socket from systemd");
+        Ok(Listener::Unix(UnixListener::from_std(socket.into())?))
+    } else {
+        info!("Server listening on {:?} (socket from systemd)", local.as_socket());
+        Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+    }
+}
+
+/// Binds `path`, replacing a socket file left by a previous run; the proxy needs to be in the
+/// file's group to connect
+#[cfg(unix)]
+pub fn bind_unix(path: &str) -> io::Result<Listener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    info!("Server listening on unix:{}", path);
+    Ok(Listener::Unix(listener))
+}
+
+#[cfg(not(unix))]
+pub fn bind_unix(_path: &str) -> io::Result<Listener> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets need a unix platform"))
+}
+
+/// Accepted connections for warp's run_incoming. Accept errors are logged and retried, since
+/// an error item would stop the server.
+pub fn accept_tcp(listener: TcpListener) -> impl Stream<Item = io::Result<tokio::net::TcpStream>> {
+    futures_util::stream::unfold(listener, |listener| async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => return Some((Ok(stream), listener)),
+                Err(e) => {
+                    warn!("HTTP accept error: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(unix)]
+pub fn accept_unix(listener: UnixListener) -> impl Stream<Item = io::Result<tokio::net::UnixStream>> {
+    futures_util::stream::unfold(listener, |listener| async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => return Some((Ok(stream), listener)),
+                Err(e) => {
+                    warn!("HTTP accept error on Unix socket: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            }
+        }
+    })
+}
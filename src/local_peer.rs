@@ -0,0 +1,123 @@
+// local_peer.rs
+// Room participants that live inside the server (the recorder, SFU forwarders, bridges). They
+// speak the same signaling protocol as browsers, just in-process: their ClientHandle is
+// registered in Clients like a WebSocket's, what they send goes through
+// RoomManager::handle_message and route_responses, and messages addressed to them are read back
+// from their queue.
+
+use std::sync::Arc;
+use serde_json::Value;
+use tokio::sync::RwLock;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::Clients;
+use crate::client_queue::ClientHandle;
+use crate::room::RoomManager;
+use crate::signaling::SignalingMessage;
+use crate::state::SharedState;
+
+#[derive(Clone)]
+pub struct LocalPeer {
+    state: SharedState,
+    pub room_id: String,
+    pub connection_id: String,
+    room_manager: Arc<RwLock<RoomManager>>,
+    clients: Clients,
+    client: ClientHandle,
+}
+
+impl LocalPeer {
+    /// Registers the peer and joins the room as a viewer; `data` is the Join data
+    pub async fn join(
+        state: SharedState,
+        room_id: String,
+        connection_id: String,
+        room_manager: Arc<RwLock<RoomManager>>,
+        clients: Clients,
+        data: Value,
+    ) -> Self {
+        Self::join_as(state, room_id, connection_id, room_manager, clients, false, data).await
+    }
+
+    /// Like `join`, but as the room's sender when `is_sender`
+    pub async fn join_as(
+        state: SharedState,
+        room_id: String,
+        connection_id: String,
+        room_manager: Arc<RwLock<RoomManager>>,
+        clients: Clients,
+        is_sender: bool,
+        data: Value,
+    ) -> Self {
+        let client = ClientHandle::new();
+        clients.write().await.insert(connection_id.clone(), client.clone());
+        let peer = Self { state, room_id, connection_id, room_manager, clients, client };
+
+        let mut join = SignalingMessage::new_join(peer.connection_id.clone(), is_sender);
+        join.data = Some(data);
+        let responses = peer.room_manager.write().await.handle_server_join(peer.room_id.clone(), join.clone());
+        if let Some(responses) = responses {
+            crate::route_responses(&peer.state, &peer.clients, responses).await;
+        }
+        if let Some(backplane) = peer.state.backplane() {
+            backplane.joined(&peer.room_id, &join).await;
+        }
+        peer
+    }
+
+    /// Handles a message as if the peer had sent it over a WebSocket
+    /// The server the peer runs in
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
+    pub async fn signal(&self, message: SignalingMessage) {
+        let responses = self.room_manager.write().await.handle_message(self.room_id.clone(), message);
+        if let Some(responses) = responses {
+            crate::route_responses(&self.state, &self.clients, responses).await;
+        }
+    }
+
+    /// Next message addressed to this peer; None once the server closed its queue
+    pub async fn recv(&self) -> Option<SignalingMessage> {
+        loop {
+            let message = self.client.recv().await?;
+            if let Ok(message) = serde_json::from_slice::<SignalingMessage>(message.as_bytes()) {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Sends the peer connection's local ICE candidates to `target` through the room
+    pub fn forward_ice_candidates(&self, peer_connection: &RTCPeerConnection, target: String) {
+        let peer = self.clone();
+        peer_connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let peer = peer.clone();
+            let target = target.clone();
+            Box::pin(async move {
+                let init = match candidate.map(|c| c.to_json()) {
+                    Some(Ok(init)) => init,
+                    _ => return,
+                };
+                if let Ok(data) = serde_json::to_value(init) {
+                    let message = SignalingMessage::new_ice_candidate(target, peer.connection_id.clone(), data);
+                    peer.signal(message).await;
+                }
+            })
+        }));
+    }
+
+    /// Leaves the room and unregisters the peer; a pending `recv` returns None
+    pub async fn leave(&self) {
+        let responses = self.room_manager.write().await.remove_connection(&self.room_id, &self.connection_id);
+        if let Some(responses) = responses {
+            crate::route_responses(&self.state, &self.clients, responses).await;
+            if let Some(backplane) = self.state.backplane() {
+                backplane.left(&self.room_id, &self.connection_id).await;
+            }
+        }
+        self.clients.write().await.remove(&self.connection_id);
+        self.client.close();
+    }
+}
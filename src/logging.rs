@@ -0,0 +1,111 @@
+// logging.rs
+// Log output through tracing: human-readable text or one JSON object per line for log
+// aggregation. Lines written inside a span carry its fields, so everything logged while
+// handling a WebSocket has room_id and connection_id attached. Levels come from config.json
+// (a default plus per-module overrides) unless RUST_LOG is set, and are reloaded with the
+// config; the format needs a restart. `log` records from dependencies are bridged in.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::telemetry::{self, TelemetryConfig};
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Level for everything not listed in `modules`
+    #[serde(default = "default_level")]
+    pub level: String,
+    /// Per-module levels, e.g. `{"cam2webrtc::turn": "debug", "webrtc": "warn"}`
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+}
+
+fn default_level() -> String { "info".to_string() }
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            level: default_level(),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        EnvFilter::try_new(self.directives()).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    /// The levels in RUST_LOG syntax
+    fn directives(&self) -> String {
+        let mut directives = vec![self.level.clone()];
+        directives.extend(self.modules.iter().map(|(module, level)| format!("{}={}", module, level)));
+        directives.join(",")
+    }
+
+    /// RUST_LOG, when set, takes precedence over the configured levels
+    fn filter(&self) -> EnvFilter {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(self.directives()))
+    }
+}
+
+/// Installs the global subscriber, with OTLP export when telemetry is configured; must run
+/// inside the runtime
+pub fn init(config: &LoggingConfig, telemetry: Option<&TelemetryConfig>) {
+    let (filter, handle) = reload::Layer::new(config.filter());
+    let _ = FILTER.set(handle);
+
+    let (otel, telemetry_result) = match telemetry.map(telemetry::layer) {
+        Some(Ok(layer)) => (Some(layer), telemetry.map(|config| Ok(config.otlp_endpoint.clone()))),
+        Some(Err(e)) => (None, Some(Err(e))),
+        None => (None, None),
+    };
+    let json = config.format == LogFormat::Json;
+
+    let installed = tracing_subscriber::registry()
+        .with(filter)
+        .with(json.then(|| fmt::layer().json().with_span_list(true)))
+        .with((!json).then(fmt::layer))
+        .with(otel)
+        .try_init();
+    if let Err(e) = installed {
+        eprintln!("Failed to install logger: {}", e);
+        return;
+    }
+
+    match telemetry_result {
+        Some(Ok(endpoint)) => info!("Exporting traces to {}", endpoint),
+        Some(Err(e)) => error!("Telemetry disabled: {}", e),
+        None => {}
+    }
+}
+
+/// Applies changed levels from a reloaded config; a no-op while RUST_LOG is set
+pub fn set_levels(config: &LoggingConfig) -> Result<(), String> {
+    if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return Ok(());
+    }
+    match FILTER.get() {
+        Some(handle) => handle.reload(config.filter()).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
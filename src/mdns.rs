@@ -0,0 +1,81 @@
+// mdns.rs
+// Optional mDNS/DNS-SD advertisement of the signaling server as _ws2infer._tcp on the LAN, so
+// mobile apps can find it without the user typing an IP address. TXT records carry the base URL
+// and the room-creation URL, plus whether TLS is on.
+
+use std::net::{IpAddr, SocketAddr};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::network;
+
+const SERVICE_TYPE: &str = "_ws2infer._tcp.local.";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MdnsConfig {
+    /// Name shown to users browsing for the service
+    #[serde(default = "default_instance_name")]
+    pub instance_name: String,
+    /// Host name announced alongside the service, without ".local."
+    #[serde(default = "default_host_name")]
+    pub host_name: String,
+}
+
+fn default_instance_name() -> String { "ws2infer".to_string() }
+fn default_host_name() -> String { "ws2infer".to_string() }
+
+impl MdnsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.instance_name.is_empty() || self.instance_name.len() > 63 {
+            return Err("instance_name must be 1-63 bytes".to_string());
+        }
+        if self.host_name.is_empty() || !self.host_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err("host_name must be a single label of letters, digits and '-'".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Registers the service for the HTTP listener at `addr`; the daemon keeps answering queries
+/// until `shutdown` is called on the returned handle
+pub fn advertise(config: &MdnsConfig, addr: SocketAddr, tls: bool) -> Option<ServiceDaemon> {
+    let ip = match addr.ip() {
+        ip if ip.is_unspecified() => network::get_local_ip()?,
+        ip => ip,
+    };
+    let scheme = if tls { "https" } else { "http" };
+    let base_url = match ip {
+        IpAddr::V4(ip) => format!("{}://{}:{}", scheme, ip, addr.port()),
+        IpAddr::V6(ip) => format!("{}://[{}]:{}", scheme, ip, addr.port()),
+    };
+    let rooms_url = format!("{}/api/rooms", base_url);
+    let properties = [
+        ("url", base_url.as_str()),
+        ("rooms", rooms_url.as_str()),
+        ("tls", if tls { "1" } else { "0" }),
+    ];
+
+    let advertised = ServiceDaemon::new().and_then(|daemon| {
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &config.instance_name,
+            &format!("{}.local.", config.host_name),
+            ip,
+            addr.port(),
+            &properties[..],
+        )?;
+        daemon.register(service)?;
+        Ok(daemon)
+    });
+    match advertised {
+        Ok(daemon) => {
+            info!("Advertising {} as {} via mDNS", base_url, SERVICE_TYPE);
+            Some(daemon)
+        }
+        Err(e) => {
+            warn!("mDNS advertisement failed: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// message_size.rs
+// Size limit for signaling messages, for deployments behind proxies that cut large WebSocket
+// messages. A frame above max_message_bytes is refused with an Error instead of being handled.
+// Clients that negotiate supports_chunking are told the limit in their welcome and send larger
+// messages as chunk/chunk_end, which the session joins (up to max_chunked_bytes) and handles
+// like a whole message; messages the server sends them above the limit are split the same way.
+// The limit is reloaded with the config; connections keep the one announced in their welcome
+// for what the server sends them.
+
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use ws2infer_protocol::chunk;
+
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 256 * 1024;
+const DEFAULT_MAX_CHUNKED_BYTES: usize = 1024 * 1024;
+/// Reassembled messages may not exceed what a compressed frame may inflate to
+const MAX_CHUNKED_BYTES: usize = ws2infer_protocol::deflate::MAX_INFLATED_BYTES as usize;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageSizeConfig {
+    /// Largest frame accepted, and sent whole to clients that support chunking
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+    /// Largest message a client may send as chunks
+    #[serde(default = "default_max_chunked_bytes")]
+    pub max_chunked_bytes: usize,
+}
+
+fn default_max_message_bytes() -> usize { DEFAULT_MAX_MESSAGE_BYTES }
+fn default_max_chunked_bytes() -> usize { DEFAULT_MAX_CHUNKED_BYTES }
+
+impl Default for MessageSizeConfig {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: default_max_message_bytes(),
+            max_chunked_bytes: default_max_chunked_bytes(),
+        }
+    }
+}
+
+impl MessageSizeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_message_bytes < chunk::MIN_MESSAGE_BYTES {
+            return Err(format!("max_message_bytes must be at least {}", chunk::MIN_MESSAGE_BYTES));
+        }
+        if self.max_chunked_bytes < self.max_message_bytes || self.max_chunked_bytes > MAX_CHUNKED_BYTES {
+            return Err(format!("max_chunked_bytes must be between max_message_bytes and {}", MAX_CHUNKED_BYTES));
+        }
+        Ok(())
+    }
+}
+
+/// The limits in effect for one server
+#[derive(Debug, Default)]
+pub struct MessageSizeLimits {
+    active: RwLock<MessageSizeConfig>,
+}
+
+impl MessageSizeLimits {
+    pub fn new(config: &MessageSizeConfig) -> Self {
+        Self { active: RwLock::new(config.clone()) }
+    }
+
+    /// Replaces the limits in effect; called on config reload
+    pub fn set(&self, config: &MessageSizeConfig) {
+        *self.active.write().unwrap() = config.clone();
+    }
+
+    pub fn get(&self) -> MessageSizeConfig {
+        self.active.read().unwrap().clone()
+    }
+}
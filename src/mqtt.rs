@@ -0,0 +1,124 @@
+// mqtt.rs
+// Optional MQTT publisher for inference results, so home-automation and IoT consumers can
+// subscribe to detections without speaking the signaling WebSocket protocol.
+
+use std::time::Duration;
+use tracing::{info, warn};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Pending publishes buffered while the broker is unreachable
+const REQUEST_CAPACITY: usize = 256;
+/// Pause between reconnect attempts after the event loop reports an error
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub broker_host: String,
+    #[serde(default = "default_broker_port")]
+    pub broker_port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// `{room_id}` and `{source_id}` are replaced per result
+    #[serde(default = "default_topic_template")]
+    pub topic_template: String,
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+fn default_broker_port() -> u16 { 1883 }
+fn default_client_id() -> String { "ws2infer".to_string() }
+fn default_topic_template() -> String { "ws2infer/{room_id}/{source_id}".to_string() }
+
+impl MqttConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.broker_host.is_empty() {
+            return Err("broker_host must not be empty".to_string());
+        }
+        if self.client_id.is_empty() {
+            return Err("client_id must not be empty".to_string());
+        }
+        if self.topic_template.is_empty() || self.topic_template.contains(['+', '#']) {
+            return Err("topic_template must be non-empty and must not contain wildcards".to_string());
+        }
+        if self.qos > 2 {
+            return Err("qos must be 0, 1 or 2".to_string());
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err("password requires username".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Fills the topic template; IDs are client-supplied, so topic separators and wildcards in
+/// them are replaced to keep each ID a single topic level
+pub fn topic_for(template: &str, room_id: &str, source_id: &str) -> String {
+    let level = |id: &str| id.replace(['/', '+', '#'], "_");
+    template
+        .replace("{room_id}", &level(room_id))
+        .replace("{source_id}", &level(source_id))
+}
+
+#[derive(Debug)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic_template: String,
+    qos: QoS,
+    retain: bool,
+}
+
+impl MqttPublisher {
+    /// Connects in the background; the event loop keeps reconnecting until the server exits
+    pub fn start(config: &MqttConfig) -> Self {
+        let mut options = MqttOptions::new(config.client_id.clone(), config.broker_host.clone(), config.broker_port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let broker = format!("{}:{}", config.broker_host, config.broker_port);
+        tokio::task::spawn(async move {
+            info!("Publishing inference results to MQTT broker {}", broker);
+            loop {
+                if let Err(e) = event_loop.poll().await {
+                    warn!("MQTT connection to {} failed: {}", broker, e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        });
+
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        };
+        Self {
+            client,
+            topic_template: config.topic_template.clone(),
+            qos,
+            retain: config.retain,
+        }
+    }
+
+    /// Queues one normalized inference result; drops it with a warning when the queue is full
+    pub fn publish(&self, room_id: &str, source_id: &str, payload: &Value) {
+        let topic = topic_for(&self.topic_template, room_id, source_id);
+        let message = serde_json::json!({
+            "room_id": room_id,
+            "source_id": source_id,
+            "data": payload
+        });
+        if let Err(e) = self.client.try_publish(topic.as_str(), self.qos, self.retain, message.to_string()) {
+            warn!("Dropped MQTT publish to {}: {}", topic, e);
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::net::{IpAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
 
 /// Get the local IP address of this machine
 pub fn get_local_ip() -> Option<IpAddr> {
@@ -20,3 +21,14 @@ pub fn get_all_local_ips() -> Vec<String> {
     
     ips
 }
+
+/// Binds a UDP socket. IPv6 sockets are IPv6-only, so "0.0.0.0:3478" and "[::]:3478" can be
+/// listed side by side for dual-stack hosts.
+pub fn bind_udp(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
@@ -0,0 +1,215 @@
+// oidc.rs
+// OpenID Connect provider for auth.rs. ID tokens are JWTs signed by the issuer; their keys come
+// from the issuer's JWKS (found through /.well-known/openid-configuration unless jwks_uri is set)
+// and are refetched when a token names a key ID we haven't seen, so key rotation needs no restart.
+// Issuer, audience and expiry are checked, and only asymmetric algorithms are accepted. Values of
+// the configured roles claim (e.g. "groups", or "realm_access.roles" for Keycloak) are mapped to
+// permissions through role_mapping. With tenant_claim set, that claim's value puts the caller in
+// a tenant (tenant.rs).
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::auth::{AuthProvider, Identity, Permission};
+use crate::room;
+
+/// Unknown key IDs trigger at most one JWKS fetch per interval, so junk tokens can't hammer the
+/// issuer
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Asymmetric algorithms only: an HS256 token "signed" with a public key must not verify
+const ALLOWED_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// Must equal the `iss` claim, e.g. "https://accounts.example.com/realms/cameras"
+    pub issuer: String,
+    /// Must appear in the `aud` claim; usually the client ID registered for this server
+    pub audience: String,
+    /// Overrides the jwks_uri from the issuer's discovery document
+    #[serde(default)]
+    pub jwks_uri: Option<String>,
+    /// Claim holding the caller's roles or groups; dots descend into nested objects
+    #[serde(default = "default_roles_claim")]
+    pub roles_claim: String,
+    /// Permissions granted by each role; roles not listed grant nothing
+    #[serde(default)]
+    pub role_mapping: HashMap<String, Vec<Permission>>,
+    /// Claim naming the caller's tenant (e.g. "org_id"); dots descend into nested objects.
+    /// Tokens without it create rooms outside any tenant.
+    #[serde(default)]
+    pub tenant_claim: Option<String>,
+}
+
+fn default_roles_claim() -> String { "roles".to_string() }
+
+impl OidcConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_http_url(&self.issuer) {
+            return Err(format!("issuer '{}' must be an http(s) URL", self.issuer));
+        }
+        if let Some(uri) = self.jwks_uri.as_ref().filter(|uri| !is_http_url(uri)) {
+            return Err(format!("jwks_uri '{}' must be an http(s) URL", uri));
+        }
+        if self.audience.is_empty() {
+            return Err("audience must not be empty".to_string());
+        }
+        if self.roles_claim.is_empty() {
+            return Err("roles_claim must not be empty".to_string());
+        }
+        if matches!(&self.tenant_claim, Some(claim) if claim.is_empty()) {
+            return Err("tenant_claim must not be empty when set".to_string());
+        }
+        if self.role_mapping.is_empty() {
+            return Err("role_mapping must grant at least one role a permission".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn is_http_url(value: &str) -> bool {
+    value.starts_with("https://") || value.starts_with("http://")
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Option<JwkSet>,
+    fetched_at: Option<Instant>,
+}
+
+pub struct OidcProvider {
+    config: OidcConfig,
+    http: reqwest::Client,
+    cache: RwLock<KeyCache>,
+}
+
+impl OidcProvider {
+    /// Fetches the issuer's keys once up front; when that fails the first token retries it
+    pub async fn start(config: OidcConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        let provider = Self { config, http, cache: RwLock::new(KeyCache::default()) };
+        match provider.refresh().await {
+            Ok(count) => info!("Loaded {} signing keys for OIDC issuer {}", count, provider.config.issuer),
+            Err(e) => warn!("Could not load signing keys for OIDC issuer {}: {}", provider.config.issuer, e),
+        }
+        provider
+    }
+
+    async fn fetch_keys(&self) -> Result<JwkSet, reqwest::Error> {
+        let jwks_uri = match &self.config.jwks_uri {
+            Some(uri) => uri.clone(),
+            None => {
+                let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+                self.http.get(url).send().await?.error_for_status()?.json::<Discovery>().await?.jwks_uri
+            }
+        };
+        self.http.get(jwks_uri).send().await?.error_for_status()?.json::<JwkSet>().await
+    }
+
+    /// Replaces the cached keys and returns how many there are
+    async fn refresh(&self) -> Result<usize, String> {
+        self.cache.write().await.fetched_at = Some(Instant::now());
+        let keys = self.fetch_keys().await.map_err(|e| e.to_string())?;
+        let count = keys.keys.len();
+        self.cache.write().await.keys = Some(keys);
+        Ok(count)
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, String> {
+        for attempt in 0..2 {
+            {
+                let cache = self.cache.read().await;
+                let jwk = cache.keys.as_ref().and_then(|keys| match kid {
+                    Some(kid) => keys.find(kid),
+                    // Issuers with a single key may leave kid out
+                    None if keys.keys.len() == 1 => keys.keys.first(),
+                    None => None,
+                });
+                if let Some(jwk) = jwk {
+                    return DecodingKey::from_jwk(jwk).map_err(|e| e.to_string());
+                }
+                let recently_fetched = cache.fetched_at.is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL);
+                if attempt > 0 || recently_fetched {
+                    break;
+                }
+            }
+            self.refresh().await?;
+        }
+        Err(format!("unknown signing key {:?}", kid))
+    }
+
+    fn permissions(&self, claims: &Value) -> HashSet<Permission> {
+        let roles = self.config.roles_claim
+            .split('.')
+            .try_fold(claims, |value, key| value.get(key));
+        let roles: Vec<&str> = match roles {
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(value)) => value.split_whitespace().collect(),
+            _ => Vec::new(),
+        };
+        roles.iter()
+            .filter_map(|role| self.config.role_mapping.get(*role))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// The tenant_claim's value; one that couldn't prefix a room key is refused
+    fn tenant(&self, claims: &Value) -> Result<Option<String>, String> {
+        let Some(claim) = &self.config.tenant_claim else {
+            return Ok(None);
+        };
+        match claim.split('.').try_fold(claims, |value, key| value.get(key)).and_then(Value::as_str) {
+            Some(tenant) if room::is_valid_room_id(tenant) => Ok(Some(tenant.to_string())),
+            Some(tenant) => Err(format!("tenant '{}' is not a valid tenant ID", tenant)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    async fn authenticate(&self, token: &str) -> Result<Identity, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| e.to_string())?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(format!("algorithm {:?} is not accepted", header.alg));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<Value>(token, &key, &validation)
+            .map_err(|e| e.to_string())?
+            .claims;
+
+        let subject = claims.get("sub").and_then(Value::as_str).unwrap_or_default().to_string();
+        Ok(Identity { subject, permissions: self.permissions(&claims), tenant: self.tenant(&claims)? })
+    }
+}
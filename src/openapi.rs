@@ -0,0 +1,168 @@
+// openapi.rs
+// OpenAPI 3 description of the REST API, served at /api/openapi.json, with a Swagger UI page at
+// /api/docs, so Python and Node clients can be generated instead of hand-written. Schemas come
+// from the typed bodies in api.rs. The routes themselves are warp filter closures in main.rs,
+// which utoipa can't annotate, so each operation is declared here on an empty function named
+// after its route; keep them in step when a route changes.
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::api::{
+    CreateRoomRequest, ErrorResponse, LayerSubscription, PeerSummary, RoomCapabilities,
+    RoomDeleted, RoomMetadata, RoomResponse, RoomStatus, TurnCredentials,
+};
+use crate::ice_policy::IcePolicy;
+use crate::presence::Profile;
+use crate::quota::{Quota, QuotaExceeded, QuotaScope};
+use crate::room::{Role, RoomMode};
+use crate::room_template::RoomTemplate;
+use crate::schedule::Schedule;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "ws2infer signaling server", description = "Rooms, TURN credentials and room state for ws2infer clients"),
+    paths(create_room, get_room, room_capabilities, put_room, delete_room, turn_credentials),
+    components(schemas(
+        CreateRoomRequest, RoomTemplate, RoomMode, IcePolicy, Role, RoomResponse, RoomMetadata,
+        RoomStatus, PeerSummary, Profile, Schedule, LayerSubscription, RoomCapabilities, RoomDeleted, TurnCredentials,
+        ErrorResponse, QuotaExceeded, Quota, QuotaScope,
+    )),
+    modifiers(&BearerAuth),
+    tags((name = "rooms"), (name = "turn")),
+)]
+pub struct ApiDoc;
+
+/// `Authorization: Bearer <token>`: a tenant API key or an identity provider's ID token for room
+/// creation, the admin_token (or a tenant's) for admin routes
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+    }
+}
+
+/// Swagger UI for /api/openapi.json; the UI's scripts and styles come from the unpkg CDN
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>ws2infer API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// Creates a room; with a `room_id` that already exists, returns it with `created: false`
+#[utoipa::path(
+    post,
+    path = "/api/rooms",
+    tag = "rooms",
+    request_body = CreateRoomRequest,
+    responses(
+        (status = 200, description = "Room created, or the existing room with that room_id", body = RoomResponse),
+        (status = 400, description = "Invalid room_id, unknown template or invalid settings", body = ErrorResponse),
+        (status = 401, description = "An identity provider is configured and the token is missing or invalid, or the API key is unknown", body = ErrorResponse),
+        (status = 403, description = "The room_id belongs to another tenant", body = ErrorResponse),
+        (status = 421, description = "The room_id belongs to another cluster instance", body = ErrorResponse),
+        (status = 429, description = "The tenant's max_rooms quota is reached; `quota` says which", body = ErrorResponse),
+    ),
+    security((), ("bearer" = [])),
+)]
+#[allow(dead_code)]
+fn create_room() {}
+
+/// Connection counts per role, offers and inference activity for dashboards; peers only with
+/// the admin token
+#[utoipa::path(
+    get,
+    path = "/api/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room ID")),
+    responses(
+        (status = 200, description = "Room status", body = RoomStatus),
+        (status = 404, description = "No such room"),
+    ),
+)]
+#[allow(dead_code)]
+fn get_room() {}
+
+/// What a viewer page needs before joining, e.g. whether the camera is online
+#[utoipa::path(
+    get,
+    path = "/api/rooms/{room_id}/capabilities",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room ID")),
+    responses(
+        (status = 200, description = "Room capabilities", body = RoomCapabilities),
+        (status = 404, description = "No such room"),
+    ),
+)]
+#[allow(dead_code)]
+fn room_capabilities() {}
+
+/// Creates a room with a caller-chosen ID
+#[utoipa::path(
+    put,
+    path = "/api/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room ID")),
+    responses(
+        (status = 201, description = "Room created; the tokens are included", body = RoomMetadata),
+        (status = 409, description = "The room already exists", body = RoomMetadata),
+        (status = 400, description = "Invalid room_id", body = ErrorResponse),
+        (status = 401, description = "An identity provider is configured and the token is missing or invalid, or the API key is unknown", body = ErrorResponse),
+        (status = 403, description = "The room_id belongs to another tenant", body = ErrorResponse),
+        (status = 421, description = "The room belongs to another cluster instance", body = ErrorResponse),
+        (status = 429, description = "The tenant's max_rooms quota is reached; `quota` says which", body = ErrorResponse),
+    ),
+    security((), ("bearer" = [])),
+)]
+#[allow(dead_code)]
+fn put_room() {}
+
+/// Closes a room: members get room_closed and are disconnected (admin only)
+#[utoipa::path(
+    delete,
+    path = "/api/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room ID")),
+    responses(
+        (status = 200, description = "Room closed", body = RoomDeleted),
+        (status = 401, description = "Wrong or missing admin token", body = ErrorResponse),
+        (status = 403, description = "No admin_token is configured, or a tenant's admin_token was used on another tenant's room", body = ErrorResponse),
+        (status = 404, description = "No such room"),
+    ),
+    security(("bearer" = [])),
+)]
+#[allow(dead_code)]
+fn delete_room() {}
+
+/// Ephemeral TURN REST API credentials derived from turn_secret. The bearer token is an admin
+/// token, a token the room handed out (sender, control or guest pass) or an ID token.
+#[utoipa::path(
+    get,
+    path = "/api/turn-credentials",
+    tag = "turn",
+    params(
+        ("room_id" = Option<String>, Query, description = "Room whose sender, control or guest pass token is the bearer"),
+        ("user" = Option<String>, Query, description = "Included in the username after its expiry"),
+    ),
+    responses(
+        (status = 200, description = "Credentials and TURN URIs", body = TurnCredentials),
+        (status = 401, description = "No token, or one the server doesn't accept", body = ErrorResponse),
+        (status = 404, description = "turn_secret is not configured"),
+    ),
+    security(("bearer" = [])),
+)]
+#[allow(dead_code)]
+fn turn_credentials() {}
@@ -0,0 +1,129 @@
+// peer_stats.rs
+// Connection quality as seen by the clients: each connection periodically sends a StatsReport
+// with figures derived from RTCPeerConnection.getStats() for each of its peer connections, and
+// the recent reports are kept per connection so operators can check bitrate, RTT and packet
+// loss per viewer through GET /api/rooms/{id}/stats. Nothing is persisted.
+
+use std::collections::{HashMap, VecDeque};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Reports kept per connection, newest last
+pub const MAX_REPORTS_PER_CONNECTION: usize = 12;
+/// A report may describe at most this many peer connections
+pub const MAX_PEERS_PER_REPORT: usize = 64;
+
+/// Figures for one peer connection; the client computes rates from successive getStats() calls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// The other end of the peer connection
+    pub peer_id: String,
+    #[serde(default)]
+    pub outbound_bitrate_kbps: Option<f64>,
+    #[serde(default)]
+    pub inbound_bitrate_kbps: Option<f64>,
+    #[serde(default)]
+    pub rtt_ms: Option<f64>,
+    /// Lost packets over the last interval, from 0 to 1
+    #[serde(default)]
+    pub packet_loss: Option<f64>,
+    #[serde(default)]
+    pub jitter_ms: Option<f64>,
+    #[serde(default)]
+    pub frames_per_second: Option<f64>,
+}
+
+impl PeerStats {
+    fn validate(&self) -> Result<(), String> {
+        if self.peer_id.is_empty() {
+            return Err("peer_id is required".to_string());
+        }
+        let figures = [
+            self.outbound_bitrate_kbps,
+            self.inbound_bitrate_kbps,
+            self.rtt_ms,
+            self.packet_loss,
+            self.jitter_ms,
+            self.frames_per_second,
+        ];
+        if figures.iter().flatten().any(|value| !value.is_finite() || *value < 0.0) {
+            return Err("stats must be non-negative numbers".to_string());
+        }
+        if matches!(self.packet_loss, Some(loss) if loss > 1.0) {
+            return Err("packet_loss must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    pub received_at: DateTime<Utc>,
+    pub peers: Vec<PeerStats>,
+}
+
+impl StatsReport {
+    /// Parses StatsReport data, `{"peers": [...]}`
+    pub fn from_data(data: Option<&Value>, now: DateTime<Utc>) -> Result<Self, String> {
+        let peers = data
+            .and_then(|data| data.get("peers"))
+            .ok_or_else(|| "stats_report data needs a peers array".to_string())?;
+        let peers: Vec<PeerStats> = serde_json::from_value(peers.clone()).map_err(|e| format!("Invalid stats_report: {}", e))?;
+        if peers.len() > MAX_PEERS_PER_REPORT {
+            return Err(format!("stats_report may describe at most {} peers", MAX_PEERS_PER_REPORT));
+        }
+        for peer in &peers {
+            peer.validate()?;
+        }
+        Ok(Self { received_at: now, peers })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PeerStatsStore {
+    /// room_id -> connection_id -> recent reports, oldest first
+    reports: HashMap<String, HashMap<String, VecDeque<StatsReport>>>,
+}
+
+impl PeerStatsStore {
+    pub fn record(&mut self, room_id: &str, connection_id: &str, report: StatsReport) {
+        let reports = self.reports
+            .entry(room_id.to_string())
+            .or_default()
+            .entry(connection_id.to_string())
+            .or_default();
+        reports.push_back(report);
+        while reports.len() > MAX_REPORTS_PER_CONNECTION {
+            reports.pop_front();
+        }
+    }
+
+    pub fn forget(&mut self, room_id: &str, connection_id: &str) {
+        if let Some(connections) = self.reports.get_mut(room_id) {
+            connections.remove(connection_id);
+            if connections.is_empty() {
+                self.reports.remove(room_id);
+            }
+        }
+    }
+
+    pub fn forget_room(&mut self, room_id: &str) {
+        self.reports.remove(room_id);
+    }
+
+    pub fn latest(&self, room_id: &str, connection_id: &str) -> Option<&StatsReport> {
+        self.reports.get(room_id)?.get(connection_id)?.back()
+    }
+
+    /// Recent reports of every connection in the room that has sent any
+    pub fn room(&self, room_id: &str) -> HashMap<String, Vec<StatsReport>> {
+        self.reports
+            .get(room_id)
+            .map(|connections| connections
+                .iter()
+                .map(|(connection_id, reports)| (connection_id.clone(), reports.iter().cloned().collect()))
+                .collect())
+            .unwrap_or_default()
+    }
+}
@@ -3,17 +3,198 @@
 // 目的:
 // - 恒久的に保存したいデータは SQLite に入れる（検索や集約が容易）。
 // - 他の AI や人が編集・利用しやすい形でエクスポートするために JSONL も併用する。
+// SQLite は WAL モードで開き、DB ファイルごとの小さなコネクションプールを使い回す。
+// 推論結果の書き込みと履歴の読み出しが同時に走っても "database is locked" にならないように、
+// 書き込みのロック待ちは busy_timeout まで待つ。
+// スキーマの変更は MIGRATIONS に追記する。init_db が PRAGMA user_version を見て未適用のものだけを
+// 順にトランザクション内で適用するので、既存の DB もそのまま新しいバージョンに上がる。
 
-use chrono::Utc;
-use rusqlite::{params, Connection};
+use chrono::{DateTime, Utc};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+use serde::Serialize;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-/// 初期化: DB ファイルを作成しテーブルを準備する
+use crate::inference::Detection;
+
+/// DB ファイルごとのコネクション数の上限
+const POOL_SIZE: u32 = 4;
+/// 他のコネクションが書き込み中のときにロック解放を待つ時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+static POOLS: OnceLock<Mutex<HashMap<String, Pool<SqliteConnectionManager>>>> = OnceLock::new();
+
+/// `db_path` のプールからコネクションを借りる（初回はプールを作る）
+fn connect(db_path: &str) -> rusqlite::Result<PooledConnection<SqliteConnectionManager>> {
+    let pool = {
+        let mut pools = POOLS.get_or_init(Default::default).lock().unwrap();
+        match pools.get(db_path) {
+            Some(pool) => pool.clone(),
+            None => {
+                let manager = SqliteConnectionManager::file(db_path).with_init(|conn| {
+                    conn.busy_timeout(BUSY_TIMEOUT)?;
+                    // foreign_keys: 推論結果を消すと detections の行も消える（ON DELETE CASCADE）
+                    conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;")
+                });
+                let pool = Pool::builder().max_size(POOL_SIZE).build(manager).map_err(pool_error)?;
+                pools.insert(db_path.to_string(), pool.clone());
+                pool
+            }
+        }
+    };
+    pool.get().map_err(pool_error)
+}
+
+fn pool_error(e: r2d2::Error) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN), Some(e.to_string()))
+}
+
+/// 新しく保存する推論結果の行の形式
+/// - 1: `payload` の JSON のみ
+/// - 2: 検出ごとの行を `detections` にも書く
+/// - 3: 検出に `track_id` が付く（tracking 有効時）
+pub const INFERENCE_SCHEMA_VERSION: i64 = 3;
+
+/// DB スキーマの変更 1 回分。`version` は適用後の PRAGMA user_version
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// 古い順。適用済みのものは書き換えず、変更は新しいバージョンとして末尾に足す
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "inference.schema_version and the normalized detections table",
+        sql: "ALTER TABLE inference ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;
+              CREATE TABLE detections (
+                  id INTEGER PRIMARY KEY AUTOINCREMENT,
+                  inference_id INTEGER NOT NULL REFERENCES inference (id) ON DELETE CASCADE,
+                  room_id TEXT NOT NULL,
+                  source_id TEXT NOT NULL,
+                  ts TEXT NOT NULL,
+                  class TEXT NOT NULL,
+                  score REAL NOT NULL,
+                  x REAL NOT NULL,
+                  y REAL NOT NULL,
+                  width REAL NOT NULL,
+                  height REAL NOT NULL
+              );
+              CREATE INDEX detections_inference ON detections (inference_id);
+              CREATE INDEX detections_room_class_score ON detections (room_id, class, score);
+              CREATE INDEX detections_room_time ON detections (room_id, ts);
+              INSERT INTO detections (inference_id, room_id, source_id, ts, class, score, x, y, width, height)
+                  SELECT i.id, i.room_id, i.source_id, i.ts,
+                         json_extract(p.value, '$.class'), json_extract(p.value, '$.score'),
+                         json_extract(p.value, '$.bbox[0]'), json_extract(p.value, '$.bbox[1]'),
+                         json_extract(p.value, '$.bbox[2]'), json_extract(p.value, '$.bbox[3]')
+                  FROM inference i, json_each(i.payload, '$.predictions') p
+                  WHERE json_valid(i.payload) AND json_extract(p.value, '$.class') IS NOT NULL
+                      AND json_extract(p.value, '$.score') IS NOT NULL AND json_array_length(p.value, '$.bbox') = 4;",
+    },
+    Migration {
+        version: 2,
+        description: "detections.track_id",
+        sql: "ALTER TABLE detections ADD COLUMN track_id INTEGER;
+              CREATE INDEX detections_room_track ON detections (room_id, track_id) WHERE track_id IS NOT NULL;",
+    },
+    Migration {
+        version: 3,
+        description: "per-minute and per-hour detection summaries",
+        sql: "CREATE TABLE detections_minute (
+                  room_id TEXT NOT NULL,
+                  source_id TEXT NOT NULL,
+                  bucket TEXT NOT NULL,
+                  class TEXT NOT NULL,
+                  count INTEGER NOT NULL,
+                  max_score REAL NOT NULL,
+                  PRIMARY KEY (room_id, bucket, source_id, class)
+              );
+              CREATE TABLE detections_hour (
+                  room_id TEXT NOT NULL,
+                  source_id TEXT NOT NULL,
+                  bucket TEXT NOT NULL,
+                  class TEXT NOT NULL,
+                  count INTEGER NOT NULL,
+                  max_score REAL NOT NULL,
+                  PRIMARY KEY (room_id, bucket, source_id, class)
+              );
+              CREATE TABLE rollup_state (
+                  resolution TEXT PRIMARY KEY,
+                  done_until TEXT NOT NULL
+              );",
+    },
+    Migration {
+        version: 4,
+        description: "files shipped to object storage",
+        sql: "CREATE TABLE uploads (
+                  path TEXT PRIMARY KEY,
+                  bytes INTEGER NOT NULL,
+                  object_key TEXT NOT NULL,
+                  uploaded_at TEXT NOT NULL
+              );",
+    },
+    Migration {
+        version: 5,
+        description: "inference index for time-range replays",
+        sql: "CREATE INDEX inference_room_time ON inference (room_id, ts);",
+    },
+    Migration {
+        version: 6,
+        description: "connection registry",
+        sql: "CREATE TABLE connections (
+                  id INTEGER PRIMARY KEY AUTOINCREMENT,
+                  connection_id TEXT NOT NULL,
+                  room_id TEXT NOT NULL,
+                  role TEXT NOT NULL,
+                  ip TEXT,
+                  user_agent TEXT,
+                  joined_at TEXT NOT NULL,
+                  left_at TEXT,
+                  interrupted INTEGER NOT NULL DEFAULT 0
+              );
+              CREATE INDEX connections_room_time ON connections (room_id, joined_at);
+              CREATE INDEX connections_connection ON connections (connection_id, joined_at);
+              CREATE INDEX connections_open ON connections (left_at) WHERE left_at IS NULL;",
+    },
+    Migration {
+        version: 7,
+        description: "room groups",
+        sql: "CREATE TABLE room_groups (
+                  group_id TEXT PRIMARY KEY,
+                  rooms TEXT NOT NULL,
+                  updated_at TEXT NOT NULL
+              );",
+    },
+];
+
+/// 未適用のマイグレーションを順に適用し、適用後の user_version を返す
+fn migrate(conn: &mut rusqlite::Connection) -> rusqlite::Result<i64> {
+    let applied: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let mut current = applied;
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        // PRAGMA はパラメータを取れないので値を埋め込む（整数のみ）
+        tx.execute_batch(&format!("PRAGMA user_version = {}", migration.version))?;
+        tx.commit()?;
+        tracing::info!("Migrated inference DB to version {}: {}", migration.version, migration.description);
+        current = migration.version;
+    }
+    Ok(current)
+}
+
+/// 初期化: DB ファイルを作成しテーブルを準備し、マイグレーションを適用する
 /// `db_path` は例えば "data/inference.db" のようなパス
 pub fn init_db(db_path: &str) -> rusqlite::Result<()> {
-    let conn = Connection::open(db_path)?;
+    let mut conn = connect(db_path)?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS inference (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -24,6 +205,34 @@ pub fn init_db(db_path: &str) -> rusqlite::Result<()> {
         )",
         [],
     )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS snapshots (
+            snapshot_id TEXT PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            source_id TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            bytes INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            stored_at TEXT NOT NULL,
+            path TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute("CREATE INDEX IF NOT EXISTS snapshots_room_time ON snapshots (room_id, timestamp)", [])?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at TEXT NOT NULL,
+            action TEXT NOT NULL,
+            actor TEXT NOT NULL,
+            ip TEXT,
+            room_id TEXT,
+            detail TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute("CREATE INDEX IF NOT EXISTS audit_action_time ON audit (action, at)", [])?;
+    migrate(&mut conn)?;
     Ok(())
 }
 
@@ -31,15 +240,69 @@ pub fn init_db(db_path: &str) -> rusqlite::Result<()> {
 /// - `db_path`: DB ファイルパス
 /// - `room_id`, `source_id`: メタデータ
 /// - `payload`: JSON 値（シリアライズして保存）
+/// - 検出（`predictions`）は同じトランザクションで `detections` にも 1 件 1 行で書く
 pub fn save_inference_sqlite(db_path: &str, room_id: &str, source_id: &str, payload: &Value) -> rusqlite::Result<()> {
-    let conn = Connection::open(db_path)?;
+    let mut conn = connect(db_path)?;
     let payload_text = serde_json::to_string(payload).unwrap_or_else(|_| "null".to_string());
     let ts = Utc::now().to_rfc3339();
-    conn.execute(
-        "INSERT INTO inference (room_id, source_id, payload, ts) VALUES (?1, ?2, ?3, ?4)",
-        params![room_id, source_id, payload_text, ts],
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO inference (room_id, source_id, payload, ts, schema_version) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![room_id, source_id, payload_text, ts, INFERENCE_SCHEMA_VERSION],
     )?;
-    Ok(())
+    let inference_id = tx.last_insert_rowid();
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO detections (inference_id, room_id, source_id, ts, class, score, x, y, width, height, track_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        let predictions = payload.get("predictions").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+        for detection in predictions.iter().filter_map(|value| serde_json::from_value::<Detection>(value.clone()).ok()) {
+            let [x, y, width, height] = detection.bbox;
+            let track_id = detection.track_id.map(|id| id as i64);
+            insert.execute(params![inference_id, room_id, source_id, ts, detection.class, detection.score, x, y, width, height, track_id])?;
+        }
+    }
+    tx.commit()
+}
+
+/// `detections` の 1 行
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectionRecord {
+    pub inference_id: i64,
+    pub source_id: String,
+    pub ts: String,
+    pub class: String,
+    pub score: f64,
+    /// [x, y, 幅, 高さ]
+    pub bbox: [f64; 4],
+    /// tracking 有効時に振られた追跡 ID
+    pub track_id: Option<i64>,
+}
+
+/// ルームの検出を新しい順に返す（detections_room_class_score / detections_room_time の索引を使う）
+/// - `class`: このクラスのものだけ
+/// - `min_score`: スコアがこれ以上のものだけ
+/// - `since`: この時刻以降に保存されたものだけ
+pub fn list_detections(db_path: &str, room_id: &str, class: Option<&str>, min_score: Option<f64>, since: Option<DateTime<Utc>>, limit: u32) -> rusqlite::Result<Vec<DetectionRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT inference_id, source_id, ts, class, score, x, y, width, height, track_id FROM detections
+         WHERE room_id = ?1 AND (?2 IS NULL OR class = ?2) AND (?3 IS NULL OR score >= ?3) AND (?4 IS NULL OR ts >= ?4)
+         ORDER BY id DESC LIMIT ?5",
+    )?;
+    let rows = stmt.query_map(params![room_id, class, min_score, since.map(|t| t.to_rfc3339()), limit], |row| {
+        Ok(DetectionRecord {
+            inference_id: row.get(0)?,
+            source_id: row.get(1)?,
+            ts: row.get(2)?,
+            class: row.get(3)?,
+            score: row.get(4)?,
+            bbox: [row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?],
+            track_id: row.get(9)?,
+        })
+    })?;
+    rows.collect()
 }
 
 /// 人や他のAIが読みやすく編集しやすい JSON Lines 形式で追記する
@@ -60,3 +323,591 @@ pub fn append_jsonl(jsonl_path: &str, room_id: &str, source_id: &str, payload: &
     writeln!(file, "{}", serde_json::to_string(&record).unwrap_or_else(|_| "null".to_string()))?;
     Ok(())
 }
+
+/// 指定時刻以降の推論結果を (room_id, ts, payload) で読み出す
+/// 異常検知のベースライン学習など、履歴をまとめて集計する用途向け
+pub fn load_inference_since(db_path: &str, since: DateTime<Utc>) -> rusqlite::Result<Vec<(String, DateTime<Utc>, Value)>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare("SELECT room_id, ts, payload FROM inference WHERE ts >= ?1 ORDER BY id")?;
+    let rows = stmt.query_map(params![since.to_rfc3339()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        let (room_id, ts, payload) = row?;
+        // ts は to_rfc3339() で保存しているので、読めない行は壊れたものとして飛ばす
+        let ts = match DateTime::parse_from_rfc3339(&ts) {
+            Ok(ts) => ts.with_timezone(&Utc),
+            Err(_) => continue,
+        };
+        let payload = serde_json::from_str(&payload).unwrap_or(Value::Null);
+        records.push((room_id, ts, payload));
+    }
+    Ok(records)
+}
+
+/// ルームの推論結果を `since` 以上 `until` 未満の範囲で古い順に (source_id, ts, payload) で読み出す
+/// リプレイ用。`source_id` を指定するとその送信者の結果だけ、最大 `limit` 件
+pub fn load_room_inference_range(
+    db_path: &str,
+    room_id: &str,
+    source_id: Option<&str>,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    limit: u32,
+) -> rusqlite::Result<Vec<(String, DateTime<Utc>, Value)>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT source_id, ts, payload FROM inference
+         WHERE room_id = ?1 AND ts >= ?2 AND ts < ?3 AND (?4 IS NULL OR source_id = ?4)
+         ORDER BY ts, id LIMIT ?5",
+    )?;
+    let rows = stmt.query_map(params![room_id, since.to_rfc3339(), until.to_rfc3339(), source_id, limit], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        let (source_id, ts, payload) = row?;
+        let ts = match DateTime::parse_from_rfc3339(&ts) {
+            Ok(ts) => ts.with_timezone(&Utc),
+            Err(_) => continue,
+        };
+        let payload = serde_json::from_str(&payload).unwrap_or(Value::Null);
+        records.push((source_id, ts, payload));
+    }
+    Ok(records)
+}
+
+/// 保持ポリシーに従って古い推論結果を削除し、削除した行数を返す
+/// - `older_than`: この時刻より前の行を削除
+/// - `max_rows`: 新しい順にこの行数だけ残し、それより古い行を削除
+pub fn prune_inference(db_path: &str, older_than: Option<DateTime<Utc>>, max_rows: Option<u64>) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    let mut deleted = 0;
+    if let Some(cutoff) = older_than {
+        deleted += conn.execute("DELETE FROM inference WHERE ts < ?1", params![cutoff.to_rfc3339()])?;
+    }
+    if let Some(max_rows) = max_rows {
+        deleted += conn.execute(
+            "DELETE FROM inference WHERE id <= (SELECT id FROM inference ORDER BY id DESC LIMIT 1 OFFSET ?1)",
+            params![max_rows as i64],
+        )?;
+    }
+    Ok(deleted)
+}
+
+/// 集計の粒度。`detections_minute` / `detections_hour` の行は区間の開始時刻（`bucket`）ごと
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "minute" => Some(Self::Minute),
+            "hour" => Some(Self::Hour),
+            _ => None,
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            Self::Minute => "detections_minute",
+            Self::Hour => "detections_hour",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+        }
+    }
+
+    /// `t` を含む区間の開始時刻
+    pub fn floor(self, t: DateTime<Utc>) -> DateTime<Utc> {
+        use chrono::DurationRound;
+        let step = match self {
+            Self::Minute => chrono::Duration::minutes(1),
+            Self::Hour => chrono::Duration::hours(1),
+        };
+        t.duration_trunc(step).unwrap_or(t)
+    }
+}
+
+/// `resolution` の集計が済んでいる時刻（これより前の区間は確定済み）
+fn rollup_done_until(conn: &rusqlite::Connection, resolution: Resolution) -> rusqlite::Result<String> {
+    let done = conn.query_row(
+        "SELECT done_until FROM rollup_state WHERE resolution = ?1",
+        params![resolution.name()],
+        |row| row.get::<_, String>(0),
+    );
+    match done {
+        Ok(done) => Ok(done),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 終わった区間の検出を分単位・時間単位の集計（クラスごとの件数と最大スコア）にまとめる
+/// - 分単位は `detections` から、時間単位は確定済みの分単位の集計から作る
+/// - 前回の続きから `now` を含む区間の手前までを 1 回ずつ集計するので、何度呼んでも二重に数えない
+/// - 戻り値は書き込んだ（分, 時間）の行数
+pub fn rollup_detections(db_path: &str, now: DateTime<Utc>) -> rusqlite::Result<(usize, usize)> {
+    let mut conn = connect(db_path)?;
+    // ts は to_rfc3339 の UTC 表記なので、文字列の先頭を切ればその分・時の開始時刻になる
+    let mut written = (0, 0);
+    for resolution in [Resolution::Minute, Resolution::Hour] {
+        let tx = conn.transaction()?;
+        let from = rollup_done_until(&tx, resolution)?;
+        let mut until = resolution.floor(now).to_rfc3339();
+        let rows = match resolution {
+            Resolution::Minute => tx.execute(
+                "INSERT INTO detections_minute (room_id, source_id, bucket, class, count, max_score)
+                 SELECT room_id, source_id, substr(ts, 1, 16) || ':00+00:00', class, COUNT(*), MAX(score)
+                 FROM detections WHERE ts >= ?1 AND ts < ?2
+                 GROUP BY room_id, source_id, substr(ts, 1, 16), class
+                 ON CONFLICT (room_id, bucket, source_id, class)
+                 DO UPDATE SET count = count + excluded.count, max_score = MAX(max_score, excluded.max_score)",
+                params![from, until],
+            )?,
+            Resolution::Hour => {
+                // 分単位の集計が済んだところまでしか時間単位にはまとめない
+                let minutes_done = rollup_done_until(&tx, Resolution::Minute)?;
+                if minutes_done < until {
+                    until = match DateTime::parse_from_rfc3339(&minutes_done) {
+                        Ok(done) => Resolution::Hour.floor(done.with_timezone(&Utc)).to_rfc3339(),
+                        Err(_) => continue,
+                    };
+                }
+                tx.execute(
+                    "INSERT INTO detections_hour (room_id, source_id, bucket, class, count, max_score)
+                     SELECT room_id, source_id, substr(bucket, 1, 13) || ':00:00+00:00', class, SUM(count), MAX(max_score)
+                     FROM detections_minute WHERE bucket >= ?1 AND bucket < ?2
+                     GROUP BY room_id, source_id, substr(bucket, 1, 13), class
+                     ON CONFLICT (room_id, bucket, source_id, class)
+                     DO UPDATE SET count = count + excluded.count, max_score = MAX(max_score, excluded.max_score)",
+                    params![from, until],
+                )?
+            }
+        };
+        if until > from {
+            tx.execute(
+                "INSERT INTO rollup_state (resolution, done_until) VALUES (?1, ?2)
+                 ON CONFLICT (resolution) DO UPDATE SET done_until = excluded.done_until",
+                params![resolution.name(), until],
+            )?;
+        }
+        tx.commit()?;
+        match resolution {
+            Resolution::Minute => written.0 = rows,
+            Resolution::Hour => written.1 = rows,
+        }
+    }
+    Ok(written)
+}
+
+/// `older_than` より前の区間の集計を削除し、削除した行数を返す
+pub fn prune_summaries(db_path: &str, resolution: Resolution, older_than: DateTime<Utc>) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        &format!("DELETE FROM {} WHERE bucket < ?1", resolution.table()),
+        params![older_than.to_rfc3339()],
+    )
+}
+
+/// 集計の 1 行
+#[derive(Debug, Clone, Serialize)]
+pub struct SummaryRecord {
+    /// 区間の開始時刻
+    pub bucket: String,
+    pub source_id: String,
+    pub class: String,
+    pub count: i64,
+    pub max_score: f64,
+}
+
+/// ルームの集計を区間の新しい順に返す（主キーの (room_id, bucket) で引く）
+/// - `class`: このクラスのものだけ
+/// - `since` / `until`: 区間の開始時刻がこの範囲 [since, until) のものだけ
+pub fn list_summaries(
+    db_path: &str,
+    room_id: &str,
+    resolution: Resolution,
+    class: Option<&str>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: u32,
+) -> rusqlite::Result<Vec<SummaryRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT bucket, source_id, class, count, max_score FROM {}
+         WHERE room_id = ?1 AND (?2 IS NULL OR class = ?2) AND (?3 IS NULL OR bucket >= ?3) AND (?4 IS NULL OR bucket < ?4)
+         ORDER BY bucket DESC, source_id, class LIMIT ?5",
+        resolution.table(),
+    ))?;
+    let rows = stmt.query_map(
+        params![room_id, class, since.map(|t| t.to_rfc3339()), until.map(|t| t.to_rfc3339()), limit],
+        |row| {
+            Ok(SummaryRecord {
+                bucket: row.get(0)?,
+                source_id: row.get(1)?,
+                class: row.get(2)?,
+                count: row.get(3)?,
+                max_score: row.get(4)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+/// `path` がこのサイズのままアップロード済みか
+pub fn is_uploaded(db_path: &str, path: &str, bytes: u64) -> rusqlite::Result<bool> {
+    let conn = connect(db_path)?;
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM uploads WHERE path = ?1 AND bytes = ?2)",
+        params![path, bytes as i64],
+        |row| row.get(0),
+    )
+}
+
+/// アップロードしたファイルを記録する（同じパスを上げ直したら上書き）
+pub fn record_upload(db_path: &str, path: &str, bytes: u64, object_key: &str) -> rusqlite::Result<()> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "INSERT INTO uploads (path, bytes, object_key, uploaded_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (path) DO UPDATE SET bytes = excluded.bytes, object_key = excluded.object_key, uploaded_at = excluded.uploaded_at",
+        params![path, bytes as i64, object_key, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+/// 削除で空いた領域をファイルから解放する（VACUUM はデータベース全体を書き直すので頻繁には呼ばない）
+pub fn vacuum(db_path: &str) -> rusqlite::Result<()> {
+    let conn = connect(db_path)?;
+    conn.execute_batch("VACUUM")
+}
+
+/// ルームの推論結果を古い順に 1 行ずつ `f(source_id, ts, payload)` に渡す
+/// 全件をメモリに載せずにエクスポートするためのもの。`f` が false を返したらそこで打ち切る
+pub fn for_each_room_inference<F>(db_path: &str, room_id: &str, mut f: F) -> rusqlite::Result<()>
+where
+    F: FnMut(&str, &str, Value) -> bool,
+{
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare("SELECT source_id, ts, payload FROM inference WHERE room_id = ?1 ORDER BY id")?;
+    let mut rows = stmt.query(params![room_id])?;
+    while let Some(row) = rows.next()? {
+        let source_id: String = row.get(0)?;
+        let ts: String = row.get(1)?;
+        let payload: String = row.get(2)?;
+        let payload = serde_json::from_str(&payload).unwrap_or(Value::Null);
+        if !f(&source_id, &ts, payload) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// スナップショット画像のメタデータ（画像本体は `path` のファイル）
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotRecord {
+    pub snapshot_id: String,
+    pub room_id: String,
+    pub source_id: String,
+    pub content_type: String,
+    pub bytes: u64,
+    /// 撮影時刻（unix ミリ秒）。推論結果の `timestamp` と突き合わせるためのもの
+    pub timestamp: i64,
+    pub stored_at: String,
+    #[serde(skip)]
+    pub path: String,
+}
+
+fn snapshot_from_row(row: &rusqlite::Row) -> rusqlite::Result<SnapshotRecord> {
+    Ok(SnapshotRecord {
+        snapshot_id: row.get(0)?,
+        room_id: row.get(1)?,
+        source_id: row.get(2)?,
+        content_type: row.get(3)?,
+        bytes: row.get::<_, i64>(4)? as u64,
+        timestamp: row.get(5)?,
+        stored_at: row.get(6)?,
+        path: row.get(7)?,
+    })
+}
+
+const SNAPSHOT_COLUMNS: &str = "snapshot_id, room_id, source_id, content_type, bytes, timestamp, stored_at, path";
+
+/// スナップショットのメタデータを 1 件保存する
+pub fn save_snapshot(db_path: &str, record: &SnapshotRecord) -> rusqlite::Result<()> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        &format!("INSERT INTO snapshots ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", SNAPSHOT_COLUMNS),
+        params![
+            record.snapshot_id,
+            record.room_id,
+            record.source_id,
+            record.content_type,
+            record.bytes as i64,
+            record.timestamp,
+            record.stored_at,
+            record.path
+        ],
+    )?;
+    Ok(())
+}
+
+/// ルームのスナップショットを撮影時刻の新しい順に返す
+/// - `source_id`: 指定した送信者のものだけに絞る
+/// - `before`: この撮影時刻（unix ミリ秒）以前のものだけに絞る。検出結果の直前のフレームを探す用途
+pub fn list_snapshots(db_path: &str, room_id: &str, source_id: Option<&str>, before: Option<i64>, limit: u32) -> rusqlite::Result<Vec<SnapshotRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM snapshots
+         WHERE room_id = ?1 AND (?2 IS NULL OR source_id = ?2) AND (?3 IS NULL OR timestamp <= ?3)
+         ORDER BY timestamp DESC, stored_at DESC LIMIT ?4",
+        SNAPSHOT_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![room_id, source_id, before, limit], snapshot_from_row)?;
+    rows.collect()
+}
+
+/// ID でスナップショットを 1 件取得する（別ルームの ID は見つからない扱い）
+pub fn get_snapshot(db_path: &str, room_id: &str, snapshot_id: &str) -> rusqlite::Result<Option<SnapshotRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM snapshots WHERE room_id = ?1 AND snapshot_id = ?2", SNAPSHOT_COLUMNS))?;
+    let mut rows = stmt.query_map(params![room_id, snapshot_id], snapshot_from_row)?;
+    rows.next().transpose()
+}
+
+/// ルームのスナップショットを新しい順に `keep` 件だけ残して削除し、削除した画像ファイルのパスを返す
+pub fn prune_snapshots(db_path: &str, room_id: &str, keep: u64) -> rusqlite::Result<Vec<String>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT snapshot_id, path FROM snapshots WHERE room_id = ?1
+         ORDER BY timestamp DESC, stored_at DESC LIMIT -1 OFFSET ?2",
+    )?;
+    let old = stmt
+        .query_map(params![room_id, keep as i64], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (snapshot_id, _) in &old {
+        conn.execute("DELETE FROM snapshots WHERE snapshot_id = ?1", params![snapshot_id])?;
+    }
+    Ok(old.into_iter().map(|(_, path)| path).collect())
+}
+
+/// 監査ログの 1 件
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub at: String,
+    pub action: String,
+    /// 操作した主体（"admin"、接続 ID、"server" など）
+    pub actor: String,
+    pub ip: Option<String>,
+    pub room_id: Option<String>,
+    pub detail: Value,
+}
+
+/// 監査ログを 1 件追記する（時刻は保存時の現在時刻）
+pub fn save_audit(db_path: &str, action: &str, actor: &str, ip: Option<&str>, room_id: Option<&str>, detail: &Value) -> rusqlite::Result<()> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "INSERT INTO audit (at, action, actor, ip, room_id, detail) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![Utc::now().to_rfc3339(), action, actor, ip, room_id, detail.to_string()],
+    )?;
+    Ok(())
+}
+
+/// 監査ログを新しい順に返す
+/// - `action`, `room_id`: 指定したものだけに絞る
+/// - `room_prefix`: room_id がこれで始まるもの（テナントのルーム）だけに絞る
+/// - `since`: この時刻以降のものだけに絞る
+pub fn list_audit(db_path: &str, action: Option<&str>, room_id: Option<&str>, room_prefix: Option<&str>, since: Option<DateTime<Utc>>, limit: u32) -> rusqlite::Result<Vec<AuditRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, at, action, actor, ip, room_id, detail FROM audit
+         WHERE (?1 IS NULL OR action = ?1) AND (?2 IS NULL OR room_id = ?2) AND (?3 IS NULL OR at >= ?3)
+           AND (?5 IS NULL OR substr(room_id, 1, length(?5)) = ?5)
+         ORDER BY id DESC LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(params![action, room_id, since.map(|t| t.to_rfc3339()), limit, room_prefix], |row| {
+        let detail: String = row.get(6)?;
+        Ok(AuditRecord {
+            id: row.get(0)?,
+            at: row.get(1)?,
+            action: row.get(2)?,
+            actor: row.get(3)?,
+            ip: row.get(4)?,
+            room_id: row.get(5)?,
+            detail: serde_json::from_str(&detail).unwrap_or(Value::Null),
+        })
+    })?;
+    rows.collect()
+}
+
+/// 接続レジストリの 1 件（ルームへの参加 1 回分）
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionRecord {
+    pub id: i64,
+    pub connection_id: String,
+    pub room_id: String,
+    pub role: String,
+    pub ip: Option<String>,
+    /// WebSocket などのハンドシェイクで送られた User-Agent
+    pub user_agent: Option<String>,
+    pub joined_at: String,
+    /// 接続中なら None
+    pub left_at: Option<String>,
+    /// サーバーの停止で切れた（left_at は次の起動時刻）
+    pub interrupted: bool,
+}
+
+/// 参加を記録する。同じ接続が同じルームに開いたままの行があれば先に閉じる（再 Join）
+pub fn save_connection_join(
+    db_path: &str,
+    connection_id: &str,
+    room_id: &str,
+    role: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    joined_at: DateTime<Utc>,
+) -> rusqlite::Result<()> {
+    let mut conn = connect(db_path)?;
+    let tx = conn.transaction()?;
+    let joined_at = joined_at.to_rfc3339();
+    tx.execute(
+        "UPDATE connections SET left_at = ?3 WHERE connection_id = ?1 AND room_id = ?2 AND left_at IS NULL",
+        params![connection_id, room_id, joined_at],
+    )?;
+    tx.execute(
+        "INSERT INTO connections (connection_id, room_id, role, ip, user_agent, joined_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![connection_id, room_id, role, ip, user_agent, joined_at],
+    )?;
+    tx.commit()
+}
+
+/// 退出時刻を記録する
+pub fn save_connection_leave(db_path: &str, connection_id: &str, room_id: &str, left_at: DateTime<Utc>) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "UPDATE connections SET left_at = ?3 WHERE connection_id = ?1 AND room_id = ?2 AND left_at IS NULL",
+        params![connection_id, room_id, left_at.to_rfc3339()],
+    )
+}
+
+/// 起動時に呼ぶ: 前回のプロセスで開いたまま残った行を中断として閉じ、その件数を返す
+pub fn close_interrupted_connections(db_path: &str, now: DateTime<Utc>) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "UPDATE connections SET left_at = ?1, interrupted = 1 WHERE left_at IS NULL",
+        params![now.to_rfc3339()],
+    )
+}
+
+/// 接続レジストリの絞り込み条件（None は絞らない）
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionQuery {
+    pub room_id: Option<String>,
+    /// room_id がこれで始まるもの（テナントのルーム）だけ
+    pub room_prefix: Option<String>,
+    pub connection_id: Option<String>,
+    pub ip: Option<String>,
+    /// この時刻以降に参加したもの
+    pub since: Option<DateTime<Utc>>,
+    /// この時刻より前に参加したもの
+    pub until: Option<DateTime<Utc>>,
+    /// true なら接続中のものだけ
+    pub open: bool,
+}
+
+/// 接続レジストリを参加の新しい順に返す
+pub fn list_connections(db_path: &str, query: &ConnectionQuery, limit: u32) -> rusqlite::Result<Vec<ConnectionRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, connection_id, room_id, role, ip, user_agent, joined_at, left_at, interrupted FROM connections
+         WHERE (?1 IS NULL OR room_id = ?1) AND (?2 IS NULL OR substr(room_id, 1, length(?2)) = ?2)
+           AND (?3 IS NULL OR connection_id = ?3) AND (?4 IS NULL OR ip = ?4)
+           AND (?5 IS NULL OR joined_at >= ?5) AND (?6 IS NULL OR joined_at < ?6)
+           AND (?7 = 0 OR left_at IS NULL)
+         ORDER BY joined_at DESC, id DESC LIMIT ?8",
+    )?;
+    let rows = stmt.query_map(
+        params![
+            query.room_id,
+            query.room_prefix,
+            query.connection_id,
+            query.ip,
+            query.since.map(|t| t.to_rfc3339()),
+            query.until.map(|t| t.to_rfc3339()),
+            query.open,
+            limit,
+        ],
+        |row| {
+            Ok(ConnectionRecord {
+                id: row.get(0)?,
+                connection_id: row.get(1)?,
+                room_id: row.get(2)?,
+                role: row.get(3)?,
+                ip: row.get(4)?,
+                user_agent: row.get(5)?,
+                joined_at: row.get(6)?,
+                left_at: row.get(7)?,
+                interrupted: row.get(8)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+/// `older_than` より前に退出した行を削除し、削除件数を返す（接続中のものは残す）
+pub fn prune_connections(db_path: &str, older_than: DateTime<Utc>) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    conn.execute("DELETE FROM connections WHERE left_at IS NOT NULL AND left_at < ?1", params![older_than.to_rfc3339()])
+}
+
+/// ルームグループの 1 件。rooms はルームキーの JSON 配列として保存する
+#[derive(Debug, Clone)]
+pub struct RoomGroupRecord {
+    pub group_id: String,
+    pub rooms: Vec<String>,
+    pub updated_at: String,
+}
+
+/// ルームグループを作成または置き換える
+pub fn save_room_group(db_path: &str, record: &RoomGroupRecord) -> rusqlite::Result<()> {
+    let conn = connect(db_path)?;
+    let rooms = serde_json::to_string(&record.rooms).unwrap_or_else(|_| "[]".to_string());
+    conn.execute(
+        "INSERT INTO room_groups (group_id, rooms, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(group_id) DO UPDATE SET rooms = excluded.rooms, updated_at = excluded.updated_at",
+        params![record.group_id, rooms, record.updated_at],
+    )?;
+    Ok(())
+}
+
+/// ルームグループを削除し、削除件数を返す
+pub fn delete_room_group(db_path: &str, group_id: &str) -> rusqlite::Result<usize> {
+    let conn = connect(db_path)?;
+    conn.execute("DELETE FROM room_groups WHERE group_id = ?1", params![group_id])
+}
+
+/// 起動時に全グループを読み込む（rooms が読めない行は空のグループになる）
+pub fn load_room_groups(db_path: &str) -> rusqlite::Result<Vec<RoomGroupRecord>> {
+    let conn = connect(db_path)?;
+    let mut stmt = conn.prepare("SELECT group_id, rooms, updated_at FROM room_groups ORDER BY group_id")?;
+    let rows = stmt.query_map([], |row| {
+        let rooms: String = row.get(1)?;
+        Ok(RoomGroupRecord {
+            group_id: row.get(0)?,
+            rooms: serde_json::from_str(&rooms).unwrap_or_default(),
+            updated_at: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
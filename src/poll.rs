@@ -0,0 +1,237 @@
+// poll.rs
+// Signaling over plain HTTP for networks whose proxies block WebSocket upgrades. A client POSTs
+// a JSON array of SignalingMessages to /poll/{room_id} and gets back the messages queued for it;
+// a session cookie ties its requests to one Session (session.rs), so joins, routing and
+// cleanup are those of a WebSocket connection. A request without messages waits up to
+// POLL_WAIT for something to arrive; one carrying messages returns right away with whatever is
+// queued, so ICE candidates and answers aren't held up. Sessions that stop polling are ended
+// like a closed socket.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use warp::ws::Message;
+use ws2infer_protocol::deflate;
+
+use crate::Clients;
+use crate::client_queue::ClientHandle;
+use crate::room::RoomManager;
+use crate::server_inference::ServerInference;
+use crate::session::Session;
+use crate::signaling::SignalingMessage;
+use crate::state::SharedState;
+
+pub const COOKIE: &str = "ws2infer_poll";
+/// How long a request without messages waits for the first queued message
+const POLL_WAIT: Duration = Duration::from_secs(25);
+/// A session not polled for this long is ended as if its socket had closed
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Messages returned by one request at most; the rest wait for the next
+const MAX_BATCH: usize = 64;
+/// Largest request body accepted
+pub const MAX_BODY_BYTES: u64 = 256 * 1024;
+/// Open sessions at most; starting another is answered with 429
+const MAX_SESSIONS: usize = 10_000;
+/// Open sessions from one client address at most
+const MAX_SESSIONS_PER_IP: usize = 32;
+
+struct PollSession {
+    /// None once the session has ended
+    session: tokio::sync::Mutex<Option<Session>>,
+    client: ClientHandle,
+    room_id: String,
+    client_ip: Option<IpAddr>,
+    last_poll: Mutex<Instant>,
+}
+
+impl PollSession {
+    fn touch(&self) {
+        *self.last_poll.lock().unwrap() = Instant::now();
+    }
+
+    async fn end(&self) {
+        if let Some(session) = self.session.lock().await.take() {
+            session.end("Long-polling").await;
+        }
+    }
+}
+
+/// What a poll request returns; `closed` means the session ended and the cookie is void
+pub struct PollReply {
+    pub session_id: String,
+    pub messages: Vec<Value>,
+    pub closed: bool,
+}
+
+impl PollReply {
+    /// Sent instead of a session when the room lives on another instance of a cluster; the
+    /// client polls `url` from then on
+    pub fn redirect(url: String) -> Self {
+        let redirect = serde_json::to_value(SignalingMessage::new_redirect(url)).unwrap_or_default();
+        Self { session_id: String::new(), messages: vec![redirect], closed: true }
+    }
+
+    pub fn body(&self) -> Value {
+        serde_json::json!({"messages": self.messages, "closed": self.closed})
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PollSessions {
+    sessions: Arc<Mutex<HashMap<String, Arc<PollSession>>>>,
+}
+
+impl PollSessions {
+    /// Ends sessions whose client stopped polling
+    pub fn spawn_reaper(&self) {
+        let sessions = self.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                interval.tick().await;
+                let now = Instant::now();
+                let idle: Vec<Arc<PollSession>> = {
+                    let mut sessions = sessions.sessions.lock().unwrap();
+                    let ids: Vec<String> = sessions.iter()
+                        .filter(|(_, polled)| now.duration_since(*polled.last_poll.lock().unwrap()) >= SESSION_IDLE_TIMEOUT)
+                        .map(|(id, _)| id.clone())
+                        .collect();
+                    ids.iter().filter_map(|id| sessions.remove(id)).collect()
+                };
+                for polled in idle {
+                    info!("Long-polling session for room {} stopped polling", polled.room_id);
+                    polled.end().await;
+                }
+            }
+        });
+    }
+
+    /// Handles one POST /poll/{room_id}: feeds `incoming` to the session named by the cookie
+    /// (starting one when there is none for this room) and collects the messages queued for it.
+    /// Err when a new session would exceed MAX_SESSIONS or MAX_SESSIONS_PER_IP.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn exchange(
+        &self,
+        session_id: Option<String>,
+        room_id: String,
+        incoming: Vec<Value>,
+        state: SharedState,
+        room_manager: Arc<RwLock<RoomManager>>,
+        clients: Clients,
+        server_inference: Option<Arc<ServerInference>>,
+        client_ip: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<PollReply, &'static str> {
+        let (session_id, polled) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let existing = session_id.and_then(|id| {
+                let polled = sessions.get(&id).filter(|polled| polled.room_id == room_id)?.clone();
+                Some((id, polled))
+            });
+            if existing.is_none() {
+                if sessions.len() >= MAX_SESSIONS {
+                    warn!("Refused a long-polling session for room {}: {} sessions open", room_id, sessions.len());
+                    return Err("Too many long-polling sessions");
+                }
+                let from_client = client_ip.map_or(0, |ip| sessions.values().filter(|polled| polled.client_ip == Some(ip)).count());
+                if from_client >= MAX_SESSIONS_PER_IP {
+                    return Err("Too many long-polling sessions from this address");
+                }
+            }
+            existing.unwrap_or_else(|| {
+                let id = uuid::Uuid::new_v4().to_string();
+                let session = Session::new(state, room_id.clone(), room_manager, clients, server_inference, client_ip)
+                    .with_user_agent(user_agent);
+                info!("New long-polling session for room: {}", room_id);
+                let polled = Arc::new(PollSession {
+                    client: session.client().clone(),
+                    session: tokio::sync::Mutex::new(Some(session)),
+                    room_id,
+                    client_ip,
+                    last_poll: Mutex::new(Instant::now()),
+                });
+                sessions.insert(id.clone(), polled.clone());
+                (id, polled)
+            })
+        };
+        polled.touch();
+
+        let sent_messages = !incoming.is_empty();
+        {
+            let mut session = polled.session.lock().await;
+            let Some(session) = session.as_mut() else {
+                return Ok(PollReply { session_id, messages: Vec::new(), closed: true });
+            };
+            for message in incoming {
+                session.receive(&Message::text(message.to_string())).await;
+            }
+        }
+
+        let mut queued = Vec::new();
+        if !sent_messages {
+            if let Ok(Some(message)) = tokio::time::timeout(POLL_WAIT, polled.client.recv()).await {
+                queued.push(message);
+            }
+        }
+        while queued.len() < MAX_BATCH {
+            match polled.client.try_recv() {
+                Some(message) => queued.push(message),
+                None => break,
+            }
+        }
+        polled.touch();
+
+        let mut messages = Vec::new();
+        let mut closed = false;
+        for message in queued {
+            // A close frame: the server disconnected this client (room deleted, guest expired, ...)
+            if message.is_close() {
+                closed = true;
+                break;
+            }
+            match decode(&message) {
+                Some(value) => messages.push(value),
+                None => warn!("Dropped a message for long-polling session {} that isn't JSON", session_id),
+            }
+        }
+        if closed {
+            self.end(&session_id).await;
+        }
+        Ok(PollReply { session_id, messages, closed })
+    }
+
+    /// Ends a session, e.g. on DELETE /poll/{room_id}; false when there was none
+    pub async fn end(&self, session_id: &str) -> bool {
+        let polled = self.sessions.lock().unwrap().remove(session_id);
+        match polled {
+            Some(polled) => {
+                polled.end().await;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The JSON of a queued frame; binary frames are compressed or plain JSON like over WebSocket
+fn decode(message: &Message) -> Option<Value> {
+    let bytes = message.as_bytes();
+    if message.is_binary() && deflate::is_compressed(bytes) {
+        serde_json::from_slice(&deflate::decompress(bytes).ok()?).ok()
+    } else {
+        serde_json::from_slice(bytes).ok()
+    }
+}
+
+/// Cookie naming the session, scoped to the room's poll URL; expired once the session ended
+pub fn cookie(reply: &PollReply, room_id: &str) -> String {
+    let max_age = if reply.closed { 0 } else { SESSION_IDLE_TIMEOUT.as_secs() };
+    format!(
+        "{}={}; Path=/poll/{}; Max-Age={}; HttpOnly; SameSite=Strict",
+        COOKIE, reply.session_id, room_id, max_age
+    )
+}
@@ -0,0 +1,78 @@
+// presence.rs
+// What a client says about itself when joining, so viewer UIs can label peers with more than a
+// connection ID: an optional display_name and flat metadata (device type, app version, ...) from
+// the Join data. The profile is validated once, kept on the connection's ConnectionInfo and
+// shown with the peer in RoomInfo, NewPeer and GET /api/rooms/{id}. It is self-declared and
+// not authenticated.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+pub const MAX_METADATA_KEYS: usize = 16;
+pub const MAX_METADATA_KEY_CHARS: usize = 32;
+pub const MAX_METADATA_VALUE_CHARS: usize = 256;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// String, number or boolean values, e.g. {"device_type": "phone", "app_version": "1.4.2"}
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schema(value_type = Object)]
+    pub metadata: BTreeMap<String, Value>,
+}
+
+impl Profile {
+    /// Reads `display_name` and `metadata` from Join data; both are optional
+    pub fn from_join(data: &Value) -> Result<Self, String> {
+        let display_name = match data.get("display_name") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(name)) => {
+                let name = name.trim();
+                if name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+                    return Err(format!("display_name must be at most {} characters", MAX_DISPLAY_NAME_CHARS));
+                }
+                if name.chars().any(char::is_control) {
+                    return Err("display_name must not contain control characters".to_string());
+                }
+                (!name.is_empty()).then(|| name.to_string())
+            }
+            Some(_) => return Err("display_name must be a string".to_string()),
+        };
+
+        let metadata = match data.get("metadata") {
+            None | Some(Value::Null) => BTreeMap::new(),
+            Some(Value::Object(entries)) => {
+                if entries.len() > MAX_METADATA_KEYS {
+                    return Err(format!("metadata may have at most {} keys", MAX_METADATA_KEYS));
+                }
+                for (key, value) in entries {
+                    if key.is_empty() || key.chars().count() > MAX_METADATA_KEY_CHARS {
+                        return Err(format!("metadata keys must be 1 to {} characters", MAX_METADATA_KEY_CHARS));
+                    }
+                    match value {
+                        Value::String(text) if text.chars().count() > MAX_METADATA_VALUE_CHARS => {
+                            return Err(format!("metadata.{} must be at most {} characters", key, MAX_METADATA_VALUE_CHARS));
+                        }
+                        Value::String(_) | Value::Number(_) | Value::Bool(_) => {}
+                        _ => return Err(format!("metadata.{} must be a string, number or boolean", key)),
+                    }
+                }
+                entries.iter().map(|(key, value)| (key.clone(), value.clone())).collect()
+            }
+            Some(_) => return Err("metadata must be an object".to_string()),
+        };
+
+        Ok(Self { display_name, metadata })
+    }
+
+    /// Adds the profile's fields to a peer entry (a JSON object)
+    pub fn annotate(&self, peer: &mut Value) {
+        if let (Some(peer), Value::Object(fields)) = (peer.as_object_mut(), serde_json::to_value(self).unwrap_or_default()) {
+            peer.extend(fields);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// proxy.rs
+// Reverse-proxy awareness. Behind nginx or Caddy every request comes from the proxy, so the
+// client's IP, the scheme it used and the host it asked for are taken from X-Forwarded-For,
+// X-Forwarded-Proto and X-Forwarded-Host, but only when the request arrived from one of the
+// configured trusted proxies; anyone else could forge those headers. The list is reloaded with
+// the config.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::RwLock;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use warp::http::HeaderMap;
+use warp::Filter;
+
+use crate::state::SharedState;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Addresses of proxies whose X-Forwarded-* headers are believed, e.g. "127.0.0.1/32"
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Where a proxied request was originally sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    /// "http" or "https"
+    pub scheme: String,
+    /// Host as the client wrote it, port included when it had one
+    pub host: String,
+}
+
+impl Origin {
+    /// The host without its port, as it goes into STUN/TURN URLs
+    pub fn hostname(&self) -> &str {
+        // "[::1]:443" keeps its brackets; a bare IPv6 address has no port to strip
+        if let Some(end) = self.host.find(']') {
+            return &self.host[..=end];
+        }
+        match self.host.split_once(':') {
+            Some((host, port)) if !port.contains(':') => host,
+            _ => &self.host,
+        }
+    }
+}
+
+/// The proxies one server trusts
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    trusted: RwLock<Vec<IpNet>>,
+}
+
+impl TrustedProxies {
+    pub fn new(config: &ProxyConfig) -> Self {
+        Self { trusted: RwLock::new(config.trusted_proxies.clone()) }
+    }
+
+    /// Replaces the trusted proxy list; called on config reload
+    pub fn set(&self, config: &ProxyConfig) {
+        *self.trusted.write().unwrap() = config.trusted_proxies.clone();
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted.read().unwrap().iter().any(|net| net.contains(&ip))
+    }
+
+    /// Requests without a peer address came in on the Unix socket or a socket from systemd, which
+    /// are only exposed through a local proxy
+    fn is_trusted_peer(&self, remote: Option<IpAddr>) -> bool {
+        remote.is_none_or(|ip| self.is_trusted(ip))
+    }
+
+    /// The client's IP: X-Forwarded-For is read from the right, skipping trusted proxies, as long
+    /// as the hop before it was trusted
+    pub fn client_ip(&self, remote: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        if !self.is_trusted_peer(remote) {
+            return remote;
+        }
+        let forwarded: Vec<IpAddr> = headers.get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        let mut client = remote;
+        for hop in forwarded.into_iter().rev() {
+            client = Some(hop);
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Scheme and host of a request that came through a trusted proxy; None for direct requests
+    pub fn origin(&self, remote: Option<IpAddr>, headers: &HeaderMap) -> Option<Origin> {
+        if !self.is_trusted_peer(remote) {
+            return None;
+        }
+        let host = first_value(headers, "x-forwarded-host").or_else(|| first_value(headers, "host"))?;
+        let scheme = match first_value(headers, "x-forwarded-proto") {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            _ => "http",
+        };
+        Some(Origin { scheme: scheme.to_string(), host: host.to_string() })
+    }
+}
+
+/// The first value of a header, for those a proxy chain may have sent as a comma list
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Extracts the client's IP, see `TrustedProxies::client_ip`
+pub fn client(state: &SharedState) -> impl Filter<Extract = (Option<IpAddr>,), Error = std::convert::Infallible> + Clone {
+    let state = state.clone();
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(move |remote: Option<SocketAddr>, headers: HeaderMap| state.proxies.client_ip(remote.map(|addr| addr.ip()), &headers))
+}
+
+/// Extracts the proxied request's origin, see `TrustedProxies::origin`
+pub fn request_origin(state: &SharedState) -> impl Filter<Extract = (Option<Origin>,), Error = std::convert::Infallible> + Clone {
+    let state = state.clone();
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(move |remote: Option<SocketAddr>, headers: HeaderMap| state.proxies.origin(remote.map(|addr| addr.ip()), &headers))
+}
@@ -0,0 +1,121 @@
+// qr.rs
+// QR codes for pairing a phone with a room: the code holds the sender or viewer page URL with the
+// room ID, on the address phones can reach (the external address, or this machine's LAN IP) and
+// with https when TLS is on, or on the public URL when requested through a reverse proxy.
+// Rendered as SVG, or as PNG for apps that can't display SVG.
+
+use qrcode::render::svg;
+use qrcode::{Color, QrCode};
+
+use crate::config::Config;
+use crate::proxy::Origin;
+
+/// Pixels per module in PNG output
+const PNG_SCALE: u32 = 8;
+/// Light modules around the code, as the spec requires
+const QUIET_ZONE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Sender,
+    Viewer,
+}
+
+impl Page {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sender" => Some(Self::Sender),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+
+    fn file(self) -> &'static str {
+        match self {
+            Self::Sender => "sender.html",
+            Self::Viewer => "viewer.html",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "svg" => Some(Self::Svg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Svg => "image/svg+xml",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+/// The page URL a phone should open to join `room_id`; sender pages carry the room's sender token.
+/// Behind a reverse proxy the URL is the one the proxy was reached on.
+pub fn page_url(config: &Config, origin: Option<&Origin>, page: Page, room_id: &str, sender_token: Option<&str>) -> String {
+    let base = match origin {
+        Some(origin) => format!("{}://{}", origin.scheme, origin.host),
+        None => {
+            let scheme = if config.tls_enabled { "https" } else { "http" };
+            let host = config.external_address.advertised_host().unwrap_or_else(|| "localhost".to_string());
+            let port = config.signaling_addrs().first().map(|addr| addr.port()).unwrap_or(8080);
+            format!("{}://{}:{}", scheme, host, port)
+        }
+    };
+    let mut url = format!("{}/{}?room={}", base, page.file(), room_id);
+    if let (Page::Sender, Some(token)) = (page, sender_token) {
+        url.push_str(&format!("&token={}", token));
+    }
+    url
+}
+
+pub fn render(data: &str, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| e.to_string())?;
+    match format {
+        ImageFormat::Svg => Ok(code
+            .render::<svg::Color>()
+            .min_dimensions(256, 256)
+            .quiet_zone(true)
+            .build()
+            .into_bytes()),
+        ImageFormat::Png => png_image(&code),
+    }
+}
+
+/// 8-bit grayscale PNG, each module scaled to PNG_SCALE pixels
+fn png_image(code: &QrCode) -> Result<Vec<u8>, String> {
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (modules + 2 * QUIET_ZONE) * PNG_SCALE;
+    let mut pixels = vec![255u8; (size * size) as usize];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let (x, y) = (i as u32 % modules + QUIET_ZONE, i as u32 / modules + QUIET_ZONE);
+        for row in y * PNG_SCALE..(y + 1) * PNG_SCALE {
+            let start = (row * size + x * PNG_SCALE) as usize;
+            pixels[start..start + PNG_SCALE as usize].fill(0);
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, size, size);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
@@ -0,0 +1,197 @@
+// quality.rs
+// Sender-side quality coordination. When several viewers' StatsReports show packet loss on the
+// stream from the sender (directly or through the SFU), the sender gets a QualityHint to step
+// down its resolution and frame rate; once the viewers report a clean stream again for a while
+// it is told to step back up. Every step stays within the bounds of the room's video
+// constraints (its template's, or config.video_constraints).
+
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::peer_stats::PeerStatsStore;
+
+/// (resolution scale, frame rate scale) per level; level 0 is the configured maximum
+const LEVELS: &[(f64, f64)] = &[(1.0, 1.0), (0.75, 1.0), (0.5, 0.75), (0.5, 0.5), (0.25, 0.5)];
+/// Frame rate assumed when video_constraints doesn't bound it
+const DEFAULT_FRAME_RATE: f64 = 30.0;
+/// Reports older than this no longer describe the stream
+const REPORT_MAX_AGE_SECS: i64 = 15;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// A viewer counts as lossy at or above this packet loss (0-1)
+    #[serde(default = "default_loss_threshold")]
+    pub loss_threshold: f64,
+    /// Lossy viewers needed before the sender is asked to step down
+    #[serde(default = "default_min_viewers")]
+    pub min_viewers: usize,
+    /// Every viewer must be at or below this loss before the sender may step up
+    #[serde(default = "default_recover_threshold")]
+    pub recover_threshold: f64,
+    /// Minimum time between two step-downs
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    /// How long the stream must stay clean after a change before stepping up
+    #[serde(default = "default_recover_secs")]
+    pub recover_secs: u64,
+}
+
+fn default_enabled() -> bool { true }
+fn default_loss_threshold() -> f64 { 0.05 }
+fn default_min_viewers() -> usize { 2 }
+fn default_recover_threshold() -> f64 { 0.01 }
+fn default_cooldown_secs() -> u64 { 10 }
+fn default_recover_secs() -> u64 { 30 }
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            loss_threshold: default_loss_threshold(),
+            min_viewers: default_min_viewers(),
+            recover_threshold: default_recover_threshold(),
+            cooldown_secs: default_cooldown_secs(),
+            recover_secs: default_recover_secs(),
+        }
+    }
+}
+
+impl QualityConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.loss_threshold) || !(0.0..=1.0).contains(&self.recover_threshold) {
+            return Err("loss_threshold and recover_threshold must be between 0 and 1".to_string());
+        }
+        if self.recover_threshold >= self.loss_threshold {
+            return Err("recover_threshold must be below loss_threshold".to_string());
+        }
+        if self.min_viewers == 0 {
+            return Err("min_viewers must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Upper and lower bound of one video constraint, e.g. `{"ideal": 1280, "min": 320}` or `1280`
+fn constraint_bounds(constraints: &Value, name: &str) -> Option<(f64, Option<f64>)> {
+    let constraint = constraints.get(name)?;
+    if let Some(value) = constraint.as_f64() {
+        return Some((value, None));
+    }
+    let upper = ["max", "ideal", "exact"].iter().find_map(|key| constraint.get(*key).and_then(Value::as_f64))?;
+    let lower = constraint.get("min").and_then(Value::as_f64);
+    Some((upper, lower))
+}
+
+fn scaled(bounds: Option<(f64, Option<f64>)>, scale: f64) -> Option<f64> {
+    let (upper, lower) = bounds?;
+    Some((upper * scale).max(lower.unwrap_or(0.0)).min(upper).round())
+}
+
+#[derive(Debug, Clone)]
+struct SenderQuality {
+    sender_id: String,
+    level: usize,
+    changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+pub struct QualityController {
+    config: QualityConfig,
+    video_constraints: Value,
+    /// room_id -> the current sender's level; reset when the sender changes
+    rooms: HashMap<String, SenderQuality>,
+}
+
+impl QualityController {
+    pub fn new(config: QualityConfig, video_constraints: Value) -> Self {
+        Self {
+            config,
+            video_constraints,
+            rooms: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: QualityConfig, video_constraints: Value) {
+        self.config = config;
+        self.video_constraints = video_constraints;
+    }
+
+    pub fn forget_room(&mut self, room_id: &str) {
+        self.rooms.remove(room_id);
+    }
+
+    /// Looks at the latest reports of `viewers` about their stream from `upstream` (the sender
+    /// or the SFU's forwarding peer) and returns QualityHint data for the sender when it should
+    /// change level. `room_constraints` replaces the configured video_constraints when set.
+    #[allow(clippy::too_many_arguments)]
+    pub fn evaluate(
+        &mut self,
+        room_id: &str,
+        room_constraints: Option<&Value>,
+        sender_id: &str,
+        upstream: &[&str],
+        viewers: &[String],
+        peer_stats: &PeerStatsStore,
+        now: DateTime<Utc>,
+    ) -> Option<Value> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let losses: Vec<f64> = viewers.iter()
+            .filter_map(|viewer| peer_stats.latest(room_id, viewer))
+            .filter(|report| now - report.received_at <= Duration::seconds(REPORT_MAX_AGE_SECS))
+            .filter_map(|report| report.peers.iter().find(|peer| upstream.contains(&peer.peer_id.as_str())))
+            .filter_map(|peer| peer.packet_loss)
+            .collect();
+        if losses.is_empty() {
+            return None;
+        }
+        let lossy = losses.iter().filter(|loss| **loss >= self.config.loss_threshold).count();
+
+        let state = self.rooms.entry(room_id.to_string()).or_insert_with(|| SenderQuality {
+            sender_id: sender_id.to_string(),
+            level: 0,
+            changed_at: now,
+        });
+        if state.sender_id != sender_id {
+            *state = SenderQuality { sender_id: sender_id.to_string(), level: 0, changed_at: now };
+        }
+        let since_change = now - state.changed_at;
+
+        let reason = if lossy >= self.config.min_viewers
+            && state.level + 1 < LEVELS.len()
+            && (state.level == 0 || since_change >= Duration::seconds(self.config.cooldown_secs as i64))
+        {
+            state.level += 1;
+            "packet_loss"
+        } else if state.level > 0
+            && losses.iter().all(|loss| *loss <= self.config.recover_threshold)
+            && since_change >= Duration::seconds(self.config.recover_secs as i64)
+        {
+            state.level -= 1;
+            "recovered"
+        } else {
+            return None;
+        };
+        state.changed_at = now;
+
+        let (resolution_scale, frame_rate_scale) = LEVELS[state.level];
+        let constraints = room_constraints.unwrap_or(&self.video_constraints);
+        let frame_rate = constraint_bounds(constraints, "frameRate").unwrap_or((DEFAULT_FRAME_RATE, None));
+        Some(serde_json::json!({
+            "reason": reason,
+            "level": state.level,
+            "max_level": LEVELS.len() - 1,
+            "width": scaled(constraint_bounds(constraints, "width"), resolution_scale),
+            "height": scaled(constraint_bounds(constraints, "height"), resolution_scale),
+            "frame_rate": scaled(Some(frame_rate), frame_rate_scale),
+            "viewers_with_loss": lossy,
+            "viewers_reporting": losses.len(),
+        }))
+    }
+}
@@ -0,0 +1,207 @@
+// quota.rs
+// Configurable quotas per tenant (tenants[].quotas) and per room (room_quotas): how many rooms a
+// tenant may have open, how many connections a tenant or a room may hold at once, and how many
+// inference results a tenant or a room may store per UTC day. Exceeding one is answered with a
+// structured error naming the quota, its scope and its limit: 429 with an ErrorResponse on the
+// REST API, an error message with a `quota` object over signaling. Results over the daily limit
+// are still forwarded live, just not stored. The daily counters live in memory and start from
+// zero on restart.
+
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::room::Room;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::tenant::{self, TenantConfig};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Quotas {
+    /// Rooms open at once; tenants only
+    #[serde(default)]
+    pub max_rooms: Option<usize>,
+    /// Connections at once, counting members mirrored from other instances
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Inference results stored per UTC day
+    #[serde(default)]
+    pub max_inference_rows_per_day: Option<u64>,
+}
+
+impl Quotas {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_rooms == Some(0) || self.max_connections == Some(0) || self.max_inference_rows_per_day == Some(0) {
+            return Err("limits must be greater than 0 when set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Which quota was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+    MaxRooms,
+    MaxConnections,
+    MaxInferenceRowsPerDay,
+}
+
+impl Quota {
+    /// The config field setting it
+    fn name(self) -> &'static str {
+        match self {
+            Self::MaxRooms => "max_rooms",
+            Self::MaxConnections => "max_connections",
+            Self::MaxInferenceRowsPerDay => "max_inference_rows_per_day",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaScope {
+    Tenant,
+    Room,
+}
+
+/// Body of a quota error, in ErrorResponse.quota and in signaling error messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    pub scope: QuotaScope,
+    /// The tenant or room key the quota belongs to
+    pub id: String,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = match self.scope {
+            QuotaScope::Tenant => "Tenant",
+            QuotaScope::Room => "Room",
+        };
+        write!(f, "{} {} reached its {} quota ({})", scope, self.id, self.quota.name(), self.limit)
+    }
+}
+
+impl QuotaExceeded {
+    fn new(quota: Quota, scope: QuotaScope, id: &str, limit: u64) -> Self {
+        Self { quota, scope, id: id.to_string(), limit }
+    }
+
+    /// Signaling error for `connection_id`; `data.quota` carries this
+    pub fn message(&self, connection_id: String) -> SignalingMessage {
+        SignalingMessage {
+            message_type: SignalingMessageType::Error,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({"error": self.to_string(), "quota": self})),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+}
+
+/// An inference result that may not be stored
+pub struct RowRefused {
+    pub exceeded: QuotaExceeded,
+    /// The first result refused today by this quota; the reporter is told once a day
+    pub first: bool,
+}
+
+/// Stored inference results per room key and per tenant for the current UTC day
+#[derive(Debug, Default)]
+struct DailyRows {
+    day: Option<NaiveDate>,
+    rooms: HashMap<String, u64>,
+    tenants: HashMap<String, u64>,
+}
+
+/// Configured quotas and the daily counters, held by the RoomManager
+#[derive(Debug, Default)]
+pub struct QuotaLimits {
+    room: Quotas,
+    tenants: HashMap<String, Quotas>,
+    rows: DailyRows,
+}
+
+impl QuotaLimits {
+    pub fn set_config(&mut self, room: Quotas, tenants: &[TenantConfig]) {
+        self.room = room;
+        self.tenants = tenants.iter().map(|tenant| (tenant.id.clone(), tenant.quotas.clone())).collect();
+    }
+
+    fn tenant_quotas(&self, room_key: &str) -> Option<(&str, &Quotas)> {
+        let (tenant, quotas) = self.tenants.get_key_value(tenant::tenant_of(room_key)?)?;
+        Some((tenant.as_str(), quotas))
+    }
+
+    /// Before a room with a new key is created
+    pub fn check_new_room(&self, room_key: &str, rooms: &HashMap<String, Room>) -> Result<(), QuotaExceeded> {
+        if let Some((tenant, Quotas { max_rooms: Some(max), .. })) = self.tenant_quotas(room_key) {
+            if rooms.keys().filter(|key| tenant::tenant_of(key) == Some(tenant)).count() >= *max {
+                return Err(QuotaExceeded::new(Quota::MaxRooms, QuotaScope::Tenant, tenant, *max as u64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Before `connection_id` joins; one rejoining with its ID is not counted twice
+    pub fn check_connection(&self, room_key: &str, connection_id: &str, rooms: &HashMap<String, Room>) -> Result<(), QuotaExceeded> {
+        if rooms.get(room_key).is_some_and(|room| room.connections.contains_key(connection_id)) {
+            return Ok(());
+        }
+        if let Some(max) = self.room.max_connections {
+            if rooms.get(room_key).map_or(0, |room| room.connections.len()) >= max {
+                return Err(QuotaExceeded::new(Quota::MaxConnections, QuotaScope::Room, room_key, max as u64));
+            }
+        }
+        if let Some((tenant, Quotas { max_connections: Some(max), .. })) = self.tenant_quotas(room_key) {
+            let connections: usize = rooms.iter()
+                .filter(|(key, _)| tenant::tenant_of(key) == Some(tenant))
+                .map(|(_, room)| room.connections.len())
+                .sum();
+            if connections >= *max {
+                return Err(QuotaExceeded::new(Quota::MaxConnections, QuotaScope::Tenant, tenant, *max as u64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts an inference result about to be stored, or refuses it
+    pub fn take_inference_row(&mut self, room_key: &str, now: DateTime<Utc>) -> Result<(), RowRefused> {
+        let today = now.date_naive();
+        if self.rows.day != Some(today) {
+            self.rows = DailyRows { day: Some(today), ..Default::default() };
+        }
+        let room_limit = self.room.max_inference_rows_per_day;
+        let tenant_limit = self.tenant_quotas(room_key)
+            .and_then(|(tenant, quotas)| Some((tenant.to_string(), quotas.max_inference_rows_per_day?)));
+
+        let room_rows = self.rows.rooms.entry(room_key.to_string()).or_default();
+        if let Some(max) = room_limit.filter(|max| *room_rows >= *max) {
+            *room_rows += 1;
+            return Err(RowRefused {
+                exceeded: QuotaExceeded::new(Quota::MaxInferenceRowsPerDay, QuotaScope::Room, room_key, max),
+                first: *room_rows == max + 1,
+            });
+        }
+        if let Some((tenant, max)) = tenant_limit {
+            let tenant_rows = self.rows.tenants.entry(tenant.clone()).or_default();
+            if *tenant_rows >= max {
+                *tenant_rows += 1;
+                return Err(RowRefused {
+                    first: *tenant_rows == max + 1,
+                    exceeded: QuotaExceeded::new(Quota::MaxInferenceRowsPerDay, QuotaScope::Tenant, &tenant, max),
+                });
+            }
+            *tenant_rows += 1;
+        }
+        *self.rows.rooms.entry(room_key.to_string()).or_default() += 1;
+        Ok(())
+    }
+}
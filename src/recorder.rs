@@ -0,0 +1,486 @@
+// recorder.rs
+// Server-side recording of a room's video. A recorder joins the room as an extra viewer (a
+// LocalPeer), so the sender negotiates with it exactly as it does with browsers. It only
+// accepts VP8, depacketizes the received RTP and writes it to WebM segments under
+// <directory>/<room_id>/<recording_id>/.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use tracing::{info, warn, error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{oneshot, RwLock};
+use uuid::Uuid;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_VP8};
+use webrtc::api::{APIBuilder, API};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::interceptor::registry::Registry;
+use webrtc::media::io::sample_builder::SampleBuilder;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp::codecs::vp8::Vp8Packet;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType};
+use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::rtp_transceiver::RTCRtpTransceiver;
+use webrtc::track::track_remote::TrackRemote;
+
+use crate::Clients;
+use crate::local_peer::LocalPeer;
+use crate::room::RoomManager;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::state::SharedState;
+use crate::tenant;
+use crate::webm::{self, WebmWriter};
+
+/// Finished recordings kept for GET /api/admin/recordings
+const FINISHED_KEPT: usize = 100;
+/// Packets the sample builder waits for before giving up on a lost one
+const MAX_LATE_PACKETS: u16 = 256;
+/// Keyframe requests (PLI) are not sent more often than this
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+const VP8_CLOCK_RATE: u32 = 90_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingConfig {
+    /// Recordings are written below this directory
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// A new segment file is started at the first keyframe after this many seconds
+    #[serde(default = "default_segment_secs")]
+    pub segment_secs: u64,
+}
+
+fn default_directory() -> String { "data/recordings".to_string() }
+fn default_segment_secs() -> u64 { 300 }
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_directory(),
+            segment_secs: default_segment_secs(),
+        }
+    }
+}
+
+impl RecordingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.directory.is_empty() {
+            return Err("directory must not be empty".to_string());
+        }
+        if self.segment_secs == 0 {
+            return Err("segment_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingState {
+    /// Joined the room, waiting for the sender's offer and the first keyframe
+    Waiting,
+    Recording,
+    Stopped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingInfo {
+    pub id: String,
+    pub room_id: String,
+    /// The recorder's connection ID in the room
+    pub connection_id: String,
+    pub state: RecordingState,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub directory: String,
+    pub segments: Vec<String>,
+    pub frames: u64,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum StartError {
+    RoomNotFound,
+    AlreadyRecording(RecordingInfo),
+    Failed(String),
+}
+
+#[derive(Debug)]
+struct ActiveRecording {
+    info: Arc<Mutex<RecordingInfo>>,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+/// Recordings of this process: running ones and the most recent finished ones
+#[derive(Debug, Default)]
+pub struct Recordings {
+    active: HashMap<String, ActiveRecording>,
+    finished: VecDeque<RecordingInfo>,
+}
+
+pub type SharedRecordings = Arc<RwLock<Recordings>>;
+
+impl Recordings {
+    /// Running recordings first, then finished ones, newest first
+    pub fn list(&self) -> Vec<RecordingInfo> {
+        let mut active: Vec<RecordingInfo> = self.active.values()
+            .map(|recording| recording.info.lock().unwrap().clone())
+            .collect();
+        active.sort_by_key(|recording| std::cmp::Reverse(recording.started_at));
+        active.extend(self.finished.iter().cloned());
+        active
+    }
+
+    pub fn get(&self, recording_id: &str) -> Option<RecordingInfo> {
+        match self.active.get(recording_id) {
+            Some(recording) => Some(recording.info.lock().unwrap().clone()),
+            None => self.finished.iter().find(|info| info.id == recording_id).cloned(),
+        }
+    }
+
+    pub fn active_for_room(&self, room_id: &str) -> Option<RecordingInfo> {
+        self.active.values()
+            .map(|recording| recording.info.lock().unwrap().clone())
+            .find(|info| info.room_id == room_id)
+    }
+
+    /// Asks a running recording to stop; false when it isn't running
+    pub fn stop(&mut self, recording_id: &str) -> bool {
+        match self.active.get_mut(recording_id).and_then(|recording| recording.stop.take()) {
+            Some(stop) => {
+                let _ = stop.send(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish(&mut self, recording_id: &str) {
+        if let Some(recording) = self.active.remove(recording_id) {
+            self.finished.push_front(recording.info.lock().unwrap().clone());
+            self.finished.truncate(FINISHED_KEPT);
+        }
+    }
+}
+
+/// Joins `room_id` with a recorder peer and returns its initial state; recording starts once
+/// the sender's first keyframe arrives
+pub async fn start(
+    recordings: &SharedRecordings,
+    config: &RecordingConfig,
+    state: SharedState,
+    room_id: String,
+    room_manager: Arc<RwLock<RoomManager>>,
+    clients: Clients,
+) -> Result<RecordingInfo, StartError> {
+    // Held until the recording is registered so two requests can't both start one
+    let mut registry = recordings.write().await;
+    if let Some(existing) = registry.active_for_room(&room_id) {
+        return Err(StartError::AlreadyRecording(existing));
+    }
+    if !room_manager.read().await.rooms.contains_key(&room_id) {
+        return Err(StartError::RoomNotFound);
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let directory = tenant::room_dir(Path::new(&config.directory), &room_id).join(&id);
+    fs::create_dir_all(&directory).map_err(|e| StartError::Failed(format!("{}: {}", directory.display(), e)))?;
+    let api = new_api().map_err(|e| StartError::Failed(e.to_string()))?;
+
+    let info = RecordingInfo {
+        id: id.clone(),
+        room_id: room_id.clone(),
+        connection_id: format!("recorder-{}", id),
+        state: RecordingState::Waiting,
+        started_at: Utc::now(),
+        stopped_at: None,
+        directory: directory.display().to_string(),
+        segments: Vec::new(),
+        frames: 0,
+        bytes: 0,
+        error: None,
+    };
+    let shared_info = Arc::new(Mutex::new(info.clone()));
+    let (stop_tx, stop_rx) = oneshot::channel();
+    registry.active.insert(id.clone(), ActiveRecording {
+        info: shared_info.clone(),
+        stop: Some(stop_tx),
+    });
+    drop(registry);
+
+    let connection_id = info.connection_id.clone();
+    let segment_duration = Duration::from_secs(config.segment_secs);
+    let recordings = recordings.clone();
+    tokio::task::spawn(async move {
+        info!("Recording {} started in room {}", id, room_id);
+        let local = LocalPeer::join(state, room_id, connection_id, room_manager, clients, serde_json::json!({ "recorder": true })).await;
+        let peer = RecorderPeer {
+            peer: local,
+            info: shared_info,
+            directory,
+            segment_duration,
+        };
+        let result = peer.run(api, stop_rx).await;
+        peer.peer.leave().await;
+        {
+            let mut info = peer.info.lock().unwrap();
+            info.stopped_at = Some(Utc::now());
+            match result {
+                // A segment write error already marked it failed
+                Ok(()) if info.state == RecordingState::Failed => {}
+                Ok(()) => info.state = RecordingState::Stopped,
+                Err(e) => {
+                    error!("Recording {} failed: {}", id, e);
+                    info.state = RecordingState::Failed;
+                    info.error = Some(e);
+                }
+            }
+        }
+        recordings.write().await.finish(&id);
+        info!("Recording {} stopped", id);
+    });
+
+    Ok(info)
+}
+
+/// VP8 only, so senders negotiate the one codec the WebM writer handles
+fn new_api() -> Result<API, webrtc::Error> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_codec(
+        RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_VP8.to_owned(),
+                clock_rate: VP8_CLOCK_RATE,
+                ..Default::default()
+            },
+            payload_type: 96,
+            ..Default::default()
+        },
+        RTPCodecType::Video,
+    )?;
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    Ok(APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build())
+}
+
+struct RecorderPeer {
+    peer: LocalPeer,
+    info: Arc<Mutex<RecordingInfo>>,
+    directory: PathBuf,
+    segment_duration: Duration,
+}
+
+impl RecorderPeer {
+    async fn run(&self, api: API, mut stop: oneshot::Receiver<()>) -> Result<(), String> {
+        // (sender connection ID, peer connection) of the stream being recorded
+        let mut current: Option<(String, Arc<RTCPeerConnection>)> = None;
+        loop {
+            let message = tokio::select! {
+                message = self.peer.recv() => match message {
+                    Some(message) => message,
+                    // Closed by the server, e.g. the room went away
+                    None => break,
+                },
+                _ = &mut stop => break,
+            };
+
+            match message.message_type {
+                SignalingMessageType::Offer => {
+                    let sender_id = match message.sender_id.clone() {
+                        Some(sender_id) => sender_id,
+                        None => continue,
+                    };
+                    if let Some((_, previous)) = current.take() {
+                        let _ = previous.close().await;
+                    }
+                    match self.answer(&api, &sender_id, message.data).await {
+                        Ok(peer_connection) => current = Some((sender_id, peer_connection)),
+                        Err(e) => warn!("Recorder {} could not answer {}: {}", self.peer.connection_id, sender_id, e),
+                    }
+                }
+                SignalingMessageType::IceCandidate => {
+                    let candidate = message.data.and_then(|data| serde_json::from_value::<RTCIceCandidateInit>(data).ok());
+                    if let (Some((sender_id, peer_connection)), Some(candidate)) = (&current, candidate) {
+                        if message.sender_id.as_ref() == Some(sender_id) {
+                            if let Err(e) = peer_connection.add_ice_candidate(candidate).await {
+                                warn!("Recorder {} rejected an ICE candidate: {}", self.peer.connection_id, e);
+                            }
+                        }
+                    }
+                }
+                SignalingMessageType::Leave => {
+                    let left = message.data.as_ref().and_then(|data| data.get("connection_id")).and_then(Value::as_str);
+                    if matches!((&current, left), (Some((sender_id, _)), Some(left)) if sender_id == left) {
+                        if let Some((_, peer_connection)) = current.take() {
+                            let _ = peer_connection.close().await;
+                        }
+                        info!("Recorder {} waiting for the sender to return", self.peer.connection_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((_, peer_connection)) = current {
+            let _ = peer_connection.close().await;
+        }
+        Ok(())
+    }
+
+    async fn answer(&self, api: &API, sender_id: &str, offer: Option<Value>) -> Result<Arc<RTCPeerConnection>, String> {
+        let offer: RTCSessionDescription = offer
+            .and_then(|offer| serde_json::from_value(offer).ok())
+            .ok_or_else(|| "offer without a session description".to_string())?;
+
+        let peer_connection = Arc::new(
+            api.new_peer_connection(RTCConfiguration::default()).await.map_err(|e| e.to_string())?,
+        );
+
+        self.peer.forward_ice_candidates(&peer_connection, sender_id.to_string());
+
+        let segments = SegmentWriter {
+            directory: self.directory.clone(),
+            segment_duration: self.segment_duration,
+            info: self.info.clone(),
+            current: None,
+            segment_start: Duration::ZERO,
+            // Continue the numbering when the sender reconnects
+            index: self.info.lock().unwrap().segments.len() as u32,
+        };
+        let segments = Arc::new(Mutex::new(Some(segments)));
+        let weak_peer_connection = Arc::downgrade(&peer_connection);
+        peer_connection.on_track(Box::new(move |track: Arc<TrackRemote>, _: Arc<RTCRtpReceiver>, _: Arc<RTCRtpTransceiver>| {
+            // One video track per sender; audio isn't negotiated
+            let writer = match track.kind() {
+                RTPCodecType::Video => segments.lock().unwrap().take(),
+                _ => None,
+            };
+            if let Some(writer) = writer {
+                tokio::task::spawn(record_track(track, weak_peer_connection.clone(), writer));
+            }
+            Box::pin(async {})
+        }));
+
+        peer_connection.set_remote_description(offer).await.map_err(|e| e.to_string())?;
+        let answer = peer_connection.create_answer(None).await.map_err(|e| e.to_string())?;
+        peer_connection.set_local_description(answer.clone()).await.map_err(|e| e.to_string())?;
+        let answer = serde_json::to_value(answer).map_err(|e| e.to_string())?;
+        self.peer.signal(SignalingMessage::new_answer(sender_id.to_string(), self.peer.connection_id.clone(), answer)).await;
+
+        Ok(peer_connection)
+    }
+}
+
+/// Writes one track until the peer connection closes
+async fn record_track(track: Arc<TrackRemote>, peer_connection: Weak<RTCPeerConnection>, mut segments: SegmentWriter) {
+    let mut builder = SampleBuilder::new(MAX_LATE_PACKETS, Vp8Packet::default(), VP8_CLOCK_RATE);
+    let mut last_keyframe_request: Option<Instant> = None;
+    // RTP timestamps wrap; accumulate the deltas instead
+    let mut clock: Option<(u32, u64)> = None;
+
+    while let Ok((packet, _)) = track.read_rtp().await {
+        builder.push(packet);
+        while let Some(sample) = builder.pop() {
+            let ticks = match &mut clock {
+                Some((last, elapsed)) => {
+                    *elapsed += sample.packet_timestamp.wrapping_sub(*last) as u64;
+                    *last = sample.packet_timestamp;
+                    *elapsed
+                }
+                None => {
+                    clock = Some((sample.packet_timestamp, 0));
+                    0
+                }
+            };
+            let timestamp = Duration::from_millis(ticks * 1000 / VP8_CLOCK_RATE as u64);
+
+            let needs_keyframe = match segments.write(timestamp, &sample.data) {
+                Ok(needs_keyframe) => needs_keyframe,
+                Err(e) => {
+                    segments.fail(format!("write failed: {}", e));
+                    return;
+                }
+            };
+            let due = !matches!(last_keyframe_request, Some(at) if at.elapsed() < KEYFRAME_REQUEST_INTERVAL);
+            if needs_keyframe && due {
+                last_keyframe_request = Some(Instant::now());
+                if let Some(peer_connection) = peer_connection.upgrade() {
+                    let pli = PictureLossIndication { sender_ssrc: 0, media_ssrc: track.ssrc() };
+                    let _ = peer_connection.write_rtcp(&[Box::new(pli)]).await;
+                }
+            }
+        }
+    }
+    segments.close();
+}
+
+/// Splits the stream into WebM files that each start with a keyframe
+struct SegmentWriter {
+    directory: PathBuf,
+    segment_duration: Duration,
+    info: Arc<Mutex<RecordingInfo>>,
+    current: Option<WebmWriter>,
+    /// Stream time at which the current segment started
+    segment_start: Duration,
+    index: u32,
+}
+
+impl SegmentWriter {
+    /// Writes a frame; true while a keyframe is wanted (before the first one, or once the
+    /// current segment is due to rotate)
+    fn write(&mut self, timestamp: Duration, frame: &[u8]) -> std::io::Result<bool> {
+        let keyframe = webm::is_vp8_keyframe(frame);
+        let rotate_due = self.current.is_some() && timestamp.saturating_sub(self.segment_start) >= self.segment_duration;
+
+        if keyframe && (self.current.is_none() || rotate_due) {
+            if let Some((width, height)) = webm::vp8_keyframe_size(frame) {
+                self.close();
+                self.index += 1;
+                let path = self.directory.join(format!("segment-{:05}.webm", self.index));
+                self.current = Some(WebmWriter::create(&path, width, height)?);
+                self.segment_start = timestamp;
+                let mut info = self.info.lock().unwrap();
+                info.state = RecordingState::Recording;
+                info.segments.push(path.display().to_string());
+            }
+        }
+
+        let writer = match self.current.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(true),
+        };
+        let offset = timestamp.saturating_sub(self.segment_start).as_millis() as u64;
+        writer.write_frame(offset, keyframe, frame)?;
+
+        let mut info = self.info.lock().unwrap();
+        info.frames += 1;
+        Ok(rotate_due && !keyframe)
+    }
+
+    fn close(&mut self) {
+        if let Some(writer) = self.current.take() {
+            match writer.finish() {
+                Ok(bytes) => self.info.lock().unwrap().bytes += bytes,
+                Err(e) => self.fail(format!("finishing segment failed: {}", e)),
+            }
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        self.current = None;
+        let mut info = self.info.lock().unwrap();
+        info.state = RecordingState::Failed;
+        info.error = Some(error);
+    }
+}
@@ -0,0 +1,122 @@
+// relay.rs
+// Rate limits for application messages the server forwards without interpreting them: Relay
+// goes to one other connection of the room (the fallback for low-bandwidth control data such
+// as PTZ commands when a direct data channel can't be established), Broadcast goes to every
+// other peer (chat, annotations, control panels). Each connection gets one token bucket shared
+// by both, plus a payload size cap per message type.
+
+use std::collections::HashMap;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayConfig {
+    /// Sustained Relay and Broadcast messages per second allowed per connection
+    #[serde(default = "default_messages_per_sec")]
+    pub messages_per_sec: f64,
+    /// Messages a connection may send at once before the sustained rate applies
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// Largest accepted Relay `data`, in bytes of serialized JSON
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// Largest accepted Broadcast `data`; smaller because it is copied to every peer
+    #[serde(default = "default_broadcast_max_bytes")]
+    pub broadcast_max_bytes: usize,
+}
+
+fn default_messages_per_sec() -> f64 { 10.0 }
+fn default_burst() -> u32 { 20 }
+fn default_max_bytes() -> usize { 8192 }
+fn default_broadcast_max_bytes() -> usize { 4096 }
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: default_messages_per_sec(),
+            burst: default_burst(),
+            max_bytes: default_max_bytes(),
+            broadcast_max_bytes: default_broadcast_max_bytes(),
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.messages_per_sec.is_finite() || self.messages_per_sec <= 0.0 {
+            return Err("messages_per_sec must be greater than 0".to_string());
+        }
+        if self.burst == 0 {
+            return Err("burst must be greater than 0".to_string());
+        }
+        if self.max_bytes == 0 || self.broadcast_max_bytes == 0 {
+            return Err("max_bytes and broadcast_max_bytes must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct RelayLimiter {
+    config: RelayConfig,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RelayLimiter {
+    pub fn new(config: RelayConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Applies new limits; existing buckets are capped at the new burst
+    pub fn set_config(&mut self, config: RelayConfig) {
+        let burst = config.burst as f64;
+        for bucket in self.buckets.values_mut() {
+            bucket.tokens = bucket.tokens.min(burst);
+        }
+        self.config = config;
+    }
+
+    /// Takes one token for a Relay of `payload_bytes` from `connection_id`, or says why it may
+    /// not be forwarded
+    pub fn check_relay(&mut self, connection_id: &str, payload_bytes: usize, now: Instant) -> Result<(), String> {
+        self.take("Relay", connection_id, payload_bytes, self.config.max_bytes, now)
+    }
+
+    pub fn check_broadcast(&mut self, connection_id: &str, payload_bytes: usize, now: Instant) -> Result<(), String> {
+        self.take("Broadcast", connection_id, payload_bytes, self.config.broadcast_max_bytes, now)
+    }
+
+    fn take(&mut self, kind: &str, connection_id: &str, payload_bytes: usize, max_bytes: usize, now: Instant) -> Result<(), String> {
+        if payload_bytes > max_bytes {
+            return Err(format!("{} payload of {} bytes exceeds the limit of {}", kind, payload_bytes, max_bytes));
+        }
+
+        let burst = self.config.burst as f64;
+        let bucket = self.buckets.entry(connection_id.to_string()).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.messages_per_sec).min(burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(format!("{} rate limit of {} messages/s exceeded", kind, self.config.messages_per_sec));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    pub fn forget(&mut self, connection_id: &str) {
+        self.buckets.remove(connection_id);
+    }
+}
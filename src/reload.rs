@@ -0,0 +1,202 @@
+// reload.rs
+// Hot reload of config.json. The file is polled for modification and, on unix, SIGHUP forces a
+// reload. Settings that need sockets to be rebound (addresses, TLS) still require a restart;
+// everything else is swapped into the shared config in place.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn, error};
+use tokio::sync::RwLock;
+
+use crate::Clients;
+use crate::audit;
+use crate::config::{self, Config, SharedConfig};
+use crate::logging;
+use crate::room::RoomManager;
+use crate::tenant;
+use crate::signaling::SignalingMessage;
+use crate::state::SharedState;
+use ws2infer_protocol::CAP_CONFIG_DELTA;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn spawn(path: PathBuf, state: SharedState, config: SharedConfig, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) {
+    {
+        let path = path.clone();
+        let state = state.clone();
+        let config = config.clone();
+        let room_manager = room_manager.clone();
+        let clients = clients.clone();
+        tokio::task::spawn(async move {
+            let mut last_modified = modified_time(&path);
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let modified = modified_time(&path);
+                if modified != last_modified {
+                    last_modified = modified;
+                    reload(&path, &state, &config, &room_manager, &clients).await;
+                }
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    tokio::task::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading {}", path.display());
+            reload(&path, &state, &config, &room_manager, &clients).await;
+        }
+    });
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+async fn reload(path: &Path, state: &SharedState, config: &SharedConfig, room_manager: &Arc<RwLock<RoomManager>>, clients: &Clients) {
+    let mut new_config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Config reload failed, keeping current settings: {}", e);
+            audit::record(state, audit::CONFIG_RELOAD_REJECTED, "config_file", None, None, serde_json::json!({"path": path.display().to_string(), "problems": [e.to_string()]}));
+            return;
+        }
+    };
+    if let Err(problems) = new_config.validate() {
+        for problem in &problems {
+            error!("Config reload rejected: {}", problem);
+        }
+        audit::record(state, audit::CONFIG_RELOAD_REJECTED, "config_file", None, None, serde_json::json!({"path": path.display().to_string(), "problems": problems}));
+        return;
+    }
+
+    let mut current = config.write().await;
+    // Not in the file: learned at startup
+    new_config.external_address.detected = current.external_address.detected;
+    if current.signaling_addr != new_config.signaling_addr
+        || current.stun_addr != new_config.stun_addr
+        || current.turn_addr != new_config.turn_addr
+        || current.stun_tcp_addr != new_config.stun_tcp_addr
+        || current.turn_tcp_addr != new_config.turn_tcp_addr
+        || current.turns_addr != new_config.turns_addr
+        || current.extra_signaling_addrs != new_config.extra_signaling_addrs
+        || current.signaling_unix_socket != new_config.signaling_unix_socket
+        || current.extra_stun_addrs != new_config.extra_stun_addrs
+        || current.extra_turn_addrs != new_config.extra_turn_addrs
+        || current.external_address != new_config.external_address
+        || current.tls_enabled != new_config.tls_enabled
+        || current.tls_cert_path != new_config.tls_cert_path
+        || current.tls_key_path != new_config.tls_key_path
+        || current.tls_client_certs != new_config.tls_client_certs
+        || current.auth != new_config.auth
+        || current.turn_secret != new_config.turn_secret
+        || current.turn_realm != new_config.turn_realm
+        || current.relay_port_min != new_config.relay_port_min
+        || current.relay_port_max != new_config.relay_port_max
+        || current.turn_quota_bytes != new_config.turn_quota_bytes
+        || current.turn_max_bytes_per_sec != new_config.turn_max_bytes_per_sec
+        || current.tracking != new_config.tracking
+        || current.mqtt != new_config.mqtt
+        || current.webhooks != new_config.webhooks
+        || current.retention != new_config.retention
+        || current.downsampling != new_config.downsampling
+        || current.upload != new_config.upload
+        || current.event_bus != new_config.event_bus
+        || current.wasm_plugins != new_config.wasm_plugins
+        || current.scripting != new_config.scripting
+        || current.inference_broadcast_ms != new_config.inference_broadcast_ms
+        || current.server_inference != new_config.server_inference
+        || current.backplane != new_config.backplane
+        || current.cluster != new_config.cluster
+        || current.bridges != new_config.bridges
+        || current.rtsp_ingest != new_config.rtsp_ingest
+        || current.hls != new_config.hls
+        || current.telemetry != new_config.telemetry
+        || current.mdns != new_config.mdns
+        || current.webtransport != new_config.webtransport
+        || current.logging.format != new_config.logging.format
+        || current.static_files != new_config.static_files
+    {
+        warn!("Listener, Unix socket and external address, TLS and client certificate, authentication provider, TURN auth, relay port, quota, tracking, MQTT, webhook, retention, downsampling, upload, event bus, WASM plugin, scripting, server inference, backplane, cluster, bridge, RTSP ingest, HLS, telemetry, mDNS, WebTransport, log format and static file changes in {} take effect after a restart", path.display());
+    }
+
+    let previous_public = current.for_clients();
+
+    current.ice_servers = new_config.ice_servers;
+    current.video_constraints = new_config.video_constraints;
+    current.score_calibration = new_config.score_calibration;
+    current.admin_token = new_config.admin_token;
+    current.tenants = new_config.tenants;
+    current.room_quotas = new_config.room_quotas;
+    current.guest_session_secs = new_config.guest_session_secs;
+    current.relay = new_config.relay;
+    current.inference_limits = new_config.inference_limits;
+    current.sdp = new_config.sdp;
+    if current.message_size != new_config.message_size {
+        state.message_size.set(&new_config.message_size);
+    }
+    current.message_size = new_config.message_size;
+    if current.ip_filter != new_config.ip_filter {
+        state.ip_filter.set(&new_config.ip_filter);
+    }
+    current.ip_filter = new_config.ip_filter;
+    if current.reverse_proxy != new_config.reverse_proxy {
+        state.proxies.set(&new_config.reverse_proxy);
+    }
+    current.reverse_proxy = new_config.reverse_proxy;
+    current.quality = new_config.quality;
+    current.room_templates = new_config.room_templates;
+    current.recording = new_config.recording;
+    current.snapshots = new_config.snapshots;
+    if current.logging != new_config.logging {
+        if let Err(e) = logging::set_levels(&new_config.logging) {
+            error!("Failed to apply log levels: {}", e);
+        }
+    }
+    current.logging = new_config.logging;
+    {
+        let mut manager = room_manager.write().await;
+        manager.calibration = current.score_calibration.clone();
+        manager.admin_token = current.admin_token.clone();
+        manager.tenant_admin_tokens = tenant::admin_tokens(&current.tenants);
+        manager.quotas.set_config(current.room_quotas.clone(), &current.tenants);
+        manager.guest_session_secs = current.guest_session_secs;
+        manager.relay.set_config(current.relay.clone());
+        manager.inference_limits = current.inference_limits.clone();
+        manager.sdp = current.sdp.clone();
+        manager.quality.set_config(current.quality.clone(), current.video_constraints.clone());
+    }
+    info!("Reloaded {}", path.display());
+    audit::record(state, audit::CONFIG_RELOADED, "config_file", None, None, serde_json::json!({"path": path.display().to_string()}));
+
+    let public = current.for_clients();
+    drop(current);
+    if public == previous_public {
+        return;
+    }
+
+    // Clients that asked for deltas apply them and can poll GET /api/config?since=<etag> from
+    // here on; the others get the whole config, as before deltas existed
+    let mut delta = config::config_delta(&previous_public, &public);
+    delta["etag"] = serde_json::json!(config::config_etag(&public));
+
+    let messages: Vec<SignalingMessage> = clients.read().await.iter()
+        .map(|(connection_id, client)| if client.protocol().supports(CAP_CONFIG_DELTA) {
+            SignalingMessage::new_config_changed(connection_id.clone(), delta.clone())
+        } else {
+            SignalingMessage::new_config_updated(connection_id.clone(), public.clone())
+        })
+        .collect();
+    crate::route_responses(state, clients, messages).await;
+}
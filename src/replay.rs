@@ -0,0 +1,165 @@
+// replay.rs
+// Replays stored inference results to one connection so a viewer can scrub back through what
+// the camera detected. The results of a time range are read from the database up front and
+// sent as InferenceUpdates marked `replay: true`, spaced like they were received divided by
+// the speed (long quiet stretches are shortened), framed by Replay status messages. A
+// connection has at most one replay; a new request or `data: null` stops the running one, and
+// it ends by itself when the connection goes away. Requested with a Replay message or
+// POST /api/rooms/{id}/replay.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::Clients;
+use crate::persistence;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::state::SharedState;
+
+/// Most results one replay sends
+pub const MAX_RESULTS: u32 = 10_000;
+/// Longest wait between two replayed results, however far apart they were stored
+const MAX_GAP: Duration = Duration::from_secs(5);
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 100.0;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayRequest {
+    pub since: DateTime<Utc>,
+    /// Defaults to now
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    /// Multiple of real time
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// Only this sender's results
+    #[serde(default)]
+    pub source_id: Option<String>,
+}
+
+fn default_speed() -> f64 { 1.0 }
+
+/// Body of POST /api/rooms/{id}/replay: the request plus the connection that gets the replay
+#[derive(Debug, Clone, Deserialize)]
+pub struct TargetedReplayRequest {
+    pub connection_id: String,
+    #[serde(flatten)]
+    pub request: ReplayRequest,
+}
+
+impl ReplayRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed) {
+            return Err(format!("speed must be between {} and {}", MIN_SPEED, MAX_SPEED));
+        }
+        if self.until.is_some_and(|until| until <= self.since) {
+            return Err("until must be after since".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// connection_id -> (replay number, task) of the running replays
+fn active() -> &'static Mutex<HashMap<String, (u64, tokio::task::AbortHandle)>> {
+    static ACTIVE: OnceLock<Mutex<HashMap<String, (u64, tokio::task::AbortHandle)>>> = OnceLock::new();
+    ACTIVE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+static NEXT_REPLAY: AtomicU64 = AtomicU64::new(1);
+
+/// Stops the connection's replay; false when none was running
+pub fn stop(connection_id: &str) -> bool {
+    match active().lock().unwrap().remove(connection_id) {
+        Some((_, task)) => {
+            task.abort();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Loads the requested results and starts sending them to `connection_id`, replacing its
+/// running replay. Returns how many results will be sent.
+pub async fn start(state: SharedState, clients: Clients, room_id: String, connection_id: String, request: ReplayRequest) -> Result<usize, String> {
+    request.validate()?;
+    let until = request.until.unwrap_or_else(Utc::now);
+    let (load_room, source_id, since) = (room_id.clone(), request.source_id.clone(), request.since);
+    let db_path = state.db_path();
+    let results = tokio::task::spawn_blocking(move || {
+        persistence::load_room_inference_range(&db_path, &load_room, source_id.as_deref(), since, until, MAX_RESULTS)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|loaded| loaded.map_err(|e| e.to_string()))
+    .map_err(|e| {
+        error!("Failed to load inference history for a replay in room {}: {}", room_id, e);
+        "Failed to load the stored results".to_string()
+    })?;
+
+    let count = results.len();
+    let started = serde_json::json!({
+        "status": "started",
+        "count": count,
+        "since": since.to_rfc3339(),
+        "until": until.to_rfc3339(),
+        "speed": request.speed,
+        "truncated": count == MAX_RESULTS as usize,
+    });
+    let replay = NEXT_REPLAY.fetch_add(1, Ordering::Relaxed);
+    let target = connection_id.clone();
+    // Held until the task is registered, so a replay that ends at once is still forgotten
+    let mut running = active().lock().unwrap();
+    let task = tokio::task::spawn(async move {
+        crate::route_responses(&state, &clients, vec![SignalingMessage::new_replay_status(target.clone(), started)]).await;
+        let mut previous: Option<DateTime<Utc>> = None;
+        for (source_id, ts, payload) in results {
+            if let Some(previous) = previous {
+                let gap = (ts - previous).to_std().unwrap_or_default().div_f64(request.speed);
+                tokio::time::sleep(gap.min(MAX_GAP)).await;
+            }
+            previous = Some(ts);
+            if !clients.read().await.contains_key(&target) {
+                debug!("Replay for {} stopped; the connection is gone", target);
+                finish(&target, replay);
+                return;
+            }
+            let update = SignalingMessage {
+                message_type: SignalingMessageType::InferenceUpdate,
+                connection_id: Some(target.clone()),
+                source_sender_id: None,
+                sender_id: None,
+                offer_id: None,
+                data: Some(serde_json::json!({
+                    "source_sender_id": source_id,
+                    "latest": payload,
+                    "replay": true,
+                    "stored_at": ts.to_rfc3339(),
+                })),
+                is_sender: None,
+                seq: None,
+                msg_id: None,
+            };
+            crate::route_responses(&state, &clients, vec![update]).await;
+        }
+        let finished = serde_json::json!({"status": "finished", "count": count});
+        crate::route_responses(&state, &clients, vec![SignalingMessage::new_replay_status(target.clone(), finished)]).await;
+        finish(&target, replay);
+    });
+
+    if let Some((_, previous)) = running.insert(connection_id, (replay, task.abort_handle())) {
+        previous.abort();
+    }
+    Ok(count)
+}
+
+/// Forgets a replay that ended unless a newer one has replaced it
+fn finish(connection_id: &str, replay: u64) {
+    let mut active = active().lock().unwrap();
+    if active.get(connection_id).is_some_and(|(running, _)| *running == replay) {
+        active.remove(connection_id);
+    }
+}
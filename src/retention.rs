@@ -0,0 +1,186 @@
+// retention.rs
+// Keeps stored inference data bounded: old or excess SQLite rows are deleted (followed by a
+// VACUUM), the JSONL export is rotated by size or date, rotated files can be gzipped, and rotated
+// files past the maximum age are removed.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing::{info, error};
+use serde::{Deserialize, Serialize};
+
+use crate::persistence;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Rows (including closed connection registry rows) and rotated JSONL files older than this
+    /// are deleted
+    #[serde(default)]
+    pub max_age_days: Option<u32>,
+    /// Only the newest this many rows are kept in SQLite
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    /// How often the policy is applied
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Rotate the JSONL export once it reaches this size
+    #[serde(default)]
+    pub jsonl_max_bytes: Option<u64>,
+    /// Rotate the JSONL export when its first record is from an earlier (UTC) day
+    #[serde(default)]
+    pub jsonl_rotate_daily: bool,
+    /// Compress rotated JSONL files to .jsonl.gz
+    #[serde(default)]
+    pub gzip_rotated: bool,
+}
+
+fn default_interval_secs() -> u64 { 3600 }
+
+impl RetentionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_secs == 0 {
+            return Err("interval_secs must be greater than 0".to_string());
+        }
+        if self.max_age_days == Some(0) || self.max_rows == Some(0) || self.jsonl_max_bytes == Some(0) {
+            return Err("max_age_days, max_rows and jsonl_max_bytes must be greater than 0 when set".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Applies the policy every `interval_secs`; file and database work runs on the blocking pool
+pub fn spawn(config: RetentionConfig, db_path: String, jsonl_path: String) {
+    tokio::task::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            let (config, db_path, jsonl_path) = (config.clone(), db_path.clone(), jsonl_path.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                apply(&config, &db_path, Path::new(&jsonl_path), Utc::now())
+            }).await;
+            if let Err(e) = result {
+                error!("Retention task failed: {}", e);
+            }
+        }
+    });
+}
+
+fn apply(config: &RetentionConfig, db_path: &str, jsonl_path: &Path, now: DateTime<Utc>) {
+    let cutoff = config.max_age_days.map(|days| now - chrono::Duration::days(days as i64));
+
+    match persistence::prune_inference(db_path, cutoff, config.max_rows) {
+        Ok(0) => {}
+        Ok(deleted) => {
+            info!("Retention removed {} inference rows", deleted);
+            if let Err(e) = persistence::vacuum(db_path) {
+                error!("Failed to VACUUM {}: {}", db_path, e);
+            }
+        }
+        Err(e) => error!("Failed to prune inference rows: {}", e),
+    }
+
+    if let Some(cutoff) = cutoff {
+        match persistence::prune_connections(db_path, cutoff) {
+            Ok(0) => {}
+            Ok(deleted) => info!("Retention removed {} connection registry rows", deleted),
+            Err(e) => error!("Failed to prune connection registry rows: {}", e),
+        }
+    }
+
+    match rotate_jsonl(jsonl_path, config, now) {
+        Ok(Some(rotated)) => info!("Rotated {} to {}", jsonl_path.display(), rotated.display()),
+        Ok(None) => {}
+        Err(e) => error!("Failed to rotate {}: {}", jsonl_path.display(), e),
+    }
+
+    if let Some(cutoff) = cutoff {
+        if let Err(e) = remove_rotated_before(jsonl_path, cutoff) {
+            error!("Failed to remove old rotated files of {}: {}", jsonl_path.display(), e);
+        }
+    }
+}
+
+/// Renames the live file to `<stem>-<timestamp>.jsonl` when it is due; appends after this
+/// start a fresh file because the writer reopens the path for every record
+fn rotate_jsonl(path: &Path, config: &RetentionConfig, now: DateTime<Utc>) -> io::Result<Option<PathBuf>> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if size == 0 {
+        return Ok(None);
+    }
+
+    let too_big = matches!(config.jsonl_max_bytes, Some(max) if size >= max);
+    let new_day = config.jsonl_rotate_daily
+        && matches!(first_record_date(path)?, Some(date) if date < now.date_naive());
+    if !too_big && !new_day {
+        return Ok(None);
+    }
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("inference");
+    let rotated = path.with_file_name(format!("{}-{}.jsonl", stem, now.format("%Y%m%d-%H%M%S")));
+    fs::rename(path, &rotated)?;
+
+    if config.gzip_rotated {
+        return gzip(&rotated).map(Some);
+    }
+    Ok(Some(rotated))
+}
+
+fn first_record_date(path: &Path) -> io::Result<Option<NaiveDate>> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+    let date = serde_json::from_str::<serde_json::Value>(&first_line).ok()
+        .and_then(|record| record.get("ts")?.as_str().map(str::to_string))
+        .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+        .map(|ts| ts.with_timezone(&Utc).date_naive());
+    Ok(date)
+}
+
+fn gzip(path: &Path) -> io::Result<PathBuf> {
+    let gz_path = PathBuf::from(format!("{}.gz", path.display()));
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)?;
+    Ok(gz_path)
+}
+
+/// Rotated siblings of the JSONL export (`<stem>-*.jsonl[.gz]`)
+pub fn rotated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("inference");
+    let prefix = format!("{}-", stem);
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(&prefix) && (name.ends_with(".jsonl") || name.ends_with(".jsonl.gz")) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// Deletes rotated siblings last modified before the cutoff
+fn remove_rotated_before(path: &Path, cutoff: DateTime<Utc>) -> io::Result<()> {
+    for rotated in rotated_files(path)? {
+        let modified: DateTime<Utc> = fs::metadata(&rotated)?.modified()?.into();
+        if modified < cutoff {
+            fs::remove_file(&rotated)?;
+            info!("Removed expired rotated file {}", rotated.display());
+        }
+    }
+    Ok(())
+}
@@ -1,25 +1,221 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
 use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use serde_json::Value;
 use crate::signaling::{SignalingMessage, SignalingMessageType};
-use log::error;
-use crate::persistence;
+use tracing::{debug, error, info, warn};
+use crate::calibration::{self, CalibrationCurve};
+use crate::anomaly::AnomalyDetector;
+use crate::tracker::Tracker;
+use crate::inference::{InferenceFilter, InferenceLimits, InferencePayload};
+use crate::sdp::SdpPolicy;
+use crate::ice_policy::IcePolicy;
+use crate::aggregation::InferenceAggregator;
+use crate::mqtt::MqttPublisher;
+use crate::store::{InferenceStore, SqliteStore};
+use crate::hooks::{HookContext, Hooks};
+use crate::scripting::Scripts;
+use crate::relay::RelayLimiter;
+use crate::peer_stats::{PeerStatsStore, StatsReport};
+use crate::quality::QualityController;
+use crate::quota::QuotaLimits;
+use crate::auth;
+use crate::tenant;
+use crate::webhooks::{self, Webhooks};
+use crate::event_bus::EventBus;
+use crate::zones::{self, ZoneSet};
+use crate::counting::LineCounter;
+use crate::presence::Profile;
+use crate::schedule::Schedule;
+use crate::room_groups::RoomGroups;
+use crate::hls::Hls;
+use crate::api::{LayerSubscription, PeerSummary, RoomCapabilities, RoomMetadata, RoomStatus};
 
 #[derive(Debug, Clone)]
 pub struct Room {
-    #[allow(dead_code)]
     pub id: String,
     pub connections: HashMap<String, ConnectionInfo>,
     pub offers: HashMap<String, SignalingMessage>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Per-room calibration overrides keyed by model name
+    pub calibration: HashMap<String, CalibrationCurve>,
+    /// Model viewers should run, from the creation request or template; None leaves the
+    /// choice to the client
+    pub model: Option<String>,
+    /// When set, every viewer joining this room needs a guest pass lasting this many seconds
+    pub guest_session_secs: Option<u64>,
+    /// Guest passes issued by POST /api/rooms/{id}/guest-passes, keyed by token
+    pub guest_passes: HashMap<String, GuestPass>,
+    /// When set, the room's connections are closed after this many seconds without traffic
+    pub idle_timeout_secs: Option<u64>,
+    /// Last signaling message or inference result other than a StatsReport
+    pub last_activity: chrono::DateTime<chrono::Utc>,
+    pub idle_warned: bool,
+    /// When joins are accepted; the room is closed at its end
+    pub schedule: Schedule,
+    pub mode: RoomMode,
+    /// Connection ID of the server's forwarding peer in SFU rooms
+    pub sfu_connection_id: Option<String>,
+    /// Template the room was created from, if any
+    pub template: Option<String>,
+    /// Overrides config.video_constraints for this room's sender
+    pub video_constraints: Option<Value>,
+    pub max_viewers: Option<usize>,
+    /// Whether a recording was started when the room was created
+    pub recording: bool,
+    /// Advertised in RoomInfo; clients may then encrypt media end to end and swap keys with KeyExchange
+    pub e2ee: bool,
+    /// Which ICE candidate types are forwarded between the room's peers
+    pub ice_policy: IcePolicy,
+    /// Issued when the room was created through the API; senders must present it when joining
+    pub sender_token: Option<String>,
+    /// Issued with sender_token; viewers joining with `"role": "controller"` must present it
+    pub control_token: Option<String>,
+    /// The last sender to leave and when, to recognise it rejoining after a network change
+    pub last_sender: Option<(String, chrono::DateTime<chrono::Utc>)>,
+    /// Simulcast layer (rid) each viewer asked for, keyed by (viewer, peer sending it video)
+    pub layer_subscriptions: HashMap<(String, String), String>,
+    /// Polygons checked against every inference result, and who is in them
+    pub zones: ZoneSet,
+    /// Counting lines and their crossing counts
+    pub counting: LineCounter,
+}
+
+/// Settings chosen when a room is created, from a template and the request; shared with other
+/// instances through the backplane
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoomSettings {
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub mode: RoomMode,
+    #[serde(default)]
+    pub guest_session_secs: Option<u64>,
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub video_constraints: Option<Value>,
+    #[serde(default)]
+    pub max_viewers: Option<usize>,
+    #[serde(default)]
+    pub recording: bool,
+    #[serde(default)]
+    pub e2ee: bool,
+    #[serde(default)]
+    pub ice_policy: IcePolicy,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub sender_token: Option<String>,
+    #[serde(default)]
+    pub control_token: Option<String>,
+}
+
+/// How video gets from the sender to viewers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum RoomMode {
+    /// The sender keeps one peer connection per viewer
+    #[default]
+    #[serde(rename = "1onN", alias = "mesh")]
+    Mesh,
+    /// The sender streams to the server once and the server forwards RTP to each viewer
+    #[serde(rename = "sfu")]
+    Sfu,
+}
+
+/// Room IDs supplied by callers must be 1-64 chars of ASCII alphanumerics, '-' or '_'
+/// so they are safe to embed in URLs, file names and log lines.
+pub fn is_valid_room_id(room_id: &str) -> bool {
+    !room_id.is_empty()
+        && room_id.len() <= 64
+        && room_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// RTP stream IDs (RFC 8851) naming simulcast layers: 1-16 alphanumerics, '-' or '_'
+fn is_valid_rid(rid: &str) -> bool {
+    !rid.is_empty() && rid.len() <= 16 && rid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Secret a room's sender (or controller) joins with, returned only to whoever created the room
+pub fn new_room_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Outstanding guest passes per room; POST /api/rooms/{id}/guest-passes answers 429 beyond this
+pub const MAX_GUEST_PASSES: usize = 10_000;
+
+/// A time-boxed viewer session issued by the server. The expiry belongs to the pass, not to
+/// the connection, so rejoining under a new connection ID doesn't restart it.
+#[derive(Debug, Clone)]
+pub struct GuestPass {
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Address an anonymous pass was issued to; asking again from it returns the same pass
+    pub issued_to: Option<IpAddr>,
+}
+
+const INVALID_SENDER_TOKEN: &str = "Invalid sender token";
+const INVALID_CONTROL_TOKEN: &str = "Invalid control token";
+const INVALID_GUEST_TOKEN: &str = "Invalid or expired guest token";
+
+/// The error of a join rejected for a wrong sender, control or guest token, for the audit log
+pub fn join_auth_error(response: &SignalingMessage) -> Option<&str> {
+    if !matches!(response.message_type, SignalingMessageType::Error) {
+        return None;
+    }
+    response.data.as_ref()
+        .and_then(|data| data.get("error"))
+        .and_then(Value::as_str)
+        .filter(|error| [INVALID_SENDER_TOKEN, INVALID_CONTROL_TOKEN, INVALID_GUEST_TOKEN].contains(error))
+}
+
+/// What a connection is allowed to see and do in a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Sender,
+    Viewer,
+    /// Viewer that presented the configured admin_token when joining
+    Admin,
+    /// Viewer that presented the room's control_token and may send control messages
+    /// (e.g. PTZ or torch commands) to the sender
+    Controller,
+    /// Second sender that joined with `"standby": true`; it waits unseen by viewers and becomes
+    /// the sender when the active one leaves
+    Standby,
+}
+
+/// A sender rejoining with its old connection ID within this window is told to restart ICE
+const SENDER_RECONNECT_SECS: i64 = 60;
+
+/// Relay/Broadcast data marked `"kind": "control"` commands the camera; plain viewers may not
+/// send it
+fn is_control_message(message: &SignalingMessage) -> bool {
+    message.data.as_ref()
+        .and_then(|data| data.get("kind"))
+        .and_then(Value::as_str)
+        == Some("control")
 }
 
 #[derive(Debug, Clone)]
 pub struct ConnectionInfo {
-    #[allow(dead_code)]
     pub id: String,
     pub is_sender: bool,
-    #[allow(dead_code)]
+    pub role: Role,
+    /// Track metadata announced by senders in their Join data (kind, label, settings)
+    pub tracks: Vec<Value>,
     pub connected_at: chrono::DateTime<chrono::Utc>,
+    /// Set for time-boxed guest sessions; the connection is closed at this time
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expiry_warned: bool,
+    /// What the connection subscribed to with SubscribeInference; None for every update
+    pub inference_filter: Option<InferenceFilter>,
+    /// display_name and metadata the client joined with
+    pub profile: Profile,
 }
 
 impl Room {
@@ -28,11 +224,98 @@ impl Room {
             id,
             connections: HashMap::new(),
             offers: HashMap::new(),
+            created_at: chrono::Utc::now(),
+            calibration: HashMap::new(),
+            model: None,
+            guest_session_secs: None,
+            guest_passes: HashMap::new(),
+            idle_timeout_secs: None,
+            last_activity: chrono::Utc::now(),
+            idle_warned: false,
+            schedule: Schedule::default(),
+            mode: RoomMode::Mesh,
+            sfu_connection_id: None,
+            template: None,
+            video_constraints: None,
+            max_viewers: None,
+            recording: false,
+            e2ee: false,
+            ice_policy: IcePolicy::All,
+            sender_token: None,
+            control_token: None,
+            last_sender: None,
+            layer_subscriptions: HashMap::new(),
+            zones: ZoneSet::default(),
+            counting: LineCounter::default(),
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &RoomSettings) {
+        self.template = settings.template.clone();
+        self.mode = settings.mode;
+        self.guest_session_secs = settings.guest_session_secs;
+        self.idle_timeout_secs = settings.idle_timeout_secs;
+        self.schedule = settings.schedule;
+        self.video_constraints = settings.video_constraints.clone();
+        self.max_viewers = settings.max_viewers;
+        self.recording = settings.recording;
+        self.e2ee = settings.e2ee;
+        self.ice_policy = settings.ice_policy;
+        self.model = settings.model.clone();
+        self.sender_token = settings.sender_token.clone();
+        self.control_token = settings.control_token.clone();
+    }
+
+    pub fn settings(&self) -> RoomSettings {
+        RoomSettings {
+            template: self.template.clone(),
+            mode: self.mode,
+            guest_session_secs: self.guest_session_secs,
+            idle_timeout_secs: self.idle_timeout_secs,
+            schedule: self.schedule,
+            video_constraints: self.video_constraints.clone(),
+            max_viewers: self.max_viewers,
+            recording: self.recording,
+            e2ee: self.e2ee,
+            ice_policy: self.ice_policy,
+            model: self.model.clone(),
+            sender_token: self.sender_token.clone(),
+            control_token: self.control_token.clone(),
         }
     }
+
+    /// Viewers counted against max_viewers: not admins, not the server's forwarding peer
+    fn limited_viewer_count(&self, except: &str) -> usize {
+        self.connections.values()
+            .filter(|c| matches!(c.role, Role::Viewer | Role::Controller) && c.id != except)
+            .filter(|c| self.sfu_connection_id.as_deref() != Some(c.id.as_str()))
+            .count()
+    }
+
+    /// Why `from` may not send this Relay/Broadcast: it isn't a member, or it is a plain viewer
+    /// sending a control message
+    fn control_rejection(&self, from: &str, message: &SignalingMessage) -> Option<String> {
+        let Some(role) = self.connections.get(from).map(|info| info.role) else {
+            return Some("Join the room before sending messages".to_string());
+        };
+        if role == Role::Viewer && is_control_message(message) {
+            debug!("Dropped control message from viewer {} in room {}", from, self.id);
+            return Some("Control messages need the controller role".to_string());
+        }
+        None
+    }
+
+    /// In SFU rooms the sender only ever sees the forwarding peer, so it never offers to
+    /// viewers directly
+    pub fn hidden_from_sender(&self, peer_id: &str) -> bool {
+        self.mode == RoomMode::Sfu
+            && self.sfu_connection_id.as_deref() != Some(peer_id)
+            && self.connections.get(peer_id).is_some_and(|c| !c.is_sender)
+    }
     
-    pub fn add_connection(&mut self, connection_id: String, is_sender: bool) -> Result<Vec<String>, String> {
+    pub fn add_connection(&mut self, connection_id: String, role: Role, tracks: Vec<Value>, profile: Profile) -> Result<Vec<String>, String> {
         let removed_ids = Vec::new();
+        let is_sender = role == Role::Sender;
         
         // If the new connection is a sender, we should check if one already exists
         // (Usually only 1 sender per room in this simple model)
@@ -43,19 +326,39 @@ impl Room {
                 return Err("Sender already exists in this room".to_string());
             }
         }
+        if role == Role::Standby && self.connections.values().any(|c| c.role == Role::Standby) {
+            return Err("Standby sender already exists in this room".to_string());
+        }
         
         let connection_info = ConnectionInfo {
             id: connection_id.clone(),
             is_sender,
+            role,
+            tracks,
             connected_at: chrono::Utc::now(),
+            expires_at: None,
+            expiry_warned: false,
+            inference_filter: None,
+            profile,
         };
         
         self.connections.insert(connection_id, connection_info);
         Ok(removed_ids)
     }
     
+    /// Makes the standby the room's sender; returns its ID
+    pub fn promote_standby(&mut self) -> Option<String> {
+        let standby = self.connections.values_mut().find(|c| c.role == Role::Standby)?;
+        standby.role = Role::Sender;
+        standby.is_sender = true;
+        Some(standby.id.clone())
+    }
+
     pub fn remove_connection(&mut self, connection_id: &str) {
-        self.connections.remove(connection_id);
+        if let Some(info) = self.connections.remove(connection_id).filter(|info| info.is_sender) {
+            self.last_sender = Some((info.id, chrono::Utc::now()));
+        }
+        self.layer_subscriptions.retain(|(viewer, sender), _| viewer != connection_id && sender != connection_id);
         // Clean up associated offers
         self.offers.retain(|_, offer| {
             if let Some(sender_id) = offer.sender_id.as_ref() {
@@ -82,6 +385,211 @@ impl Room {
     pub fn get_connection_count(&self) -> usize {
         self.connections.len()
     }
+
+    pub fn viewer_count(&self) -> usize {
+        self.connections.values().filter(|c| !c.is_sender && c.role != Role::Standby).count()
+    }
+
+    /// RoomInfo payload tailored to the recipient's role: senders get viewer counts and
+    /// pending offers, viewers get sender track metadata and the model to run, admins get
+    /// everything. The common fields are kept for every role so older clients keep working.
+    pub fn room_info(&self, connection_id: &str, role: Role, latest_inference: Option<&HashMap<String, Value>>) -> Value {
+        let mut info = serde_json::json!({
+            "room_id": self.id,
+            "mode": self.mode,
+            "template": self.template,
+            "video_constraints": self.video_constraints,
+            "max_viewers": self.max_viewers,
+            "idle_timeout_secs": self.idle_timeout_secs,
+            "starts_at": self.schedule.starts_at,
+            "ends_at": self.schedule.ends_at,
+            "recording": self.recording,
+            "e2ee": self.e2ee,
+            "ice_policy": self.ice_policy,
+            "role": role,
+            "connection_count": self.get_connection_count(),
+            "peers": self.connections.iter()
+                    .filter(|(id, _)| id.as_str() != connection_id)
+                    .filter(|(id, _)| role != Role::Sender || !self.hidden_from_sender(id))
+                    .filter(|(_, info)| info.role != Role::Standby || role == Role::Admin)
+                    .map(|(id, info)| {
+                        let mut peer = serde_json::json!({ "id": id, "is_sender": info.is_sender, "role": info.role });
+                        info.profile.annotate(&mut peer);
+                        peer
+                    })
+                    .collect::<Vec<_>>()
+        });
+
+        let role_specific = match role {
+            Role::Sender => serde_json::json!({
+                "viewer_count": self.viewer_count(),
+                "pending_offers": {
+                    "count": self.offers.len(),
+                    "offer_ids": self.offers.keys().collect::<Vec<_>>()
+                }
+            }),
+            Role::Viewer | Role::Controller => serde_json::json!({
+                "senders": self.connections.values()
+                    .filter(|c| c.is_sender)
+                    .map(|c| serde_json::json!({ "id": c.id, "display_name": c.profile.display_name, "tracks": c.tracks }))
+                    .collect::<Vec<_>>(),
+                "model": self.model
+            }),
+            Role::Standby => serde_json::json!({
+                "active_sender": self.connections.values().find(|c| c.is_sender).map(|c| &c.id)
+            }),
+            Role::Admin => serde_json::json!({
+                "created_at": self.created_at.to_rfc3339(),
+                "viewer_count": self.viewer_count(),
+                "connections": self.connections.values()
+                    .map(|c| {
+                        let mut connection = serde_json::json!({
+                            "id": c.id,
+                            "role": c.role,
+                            "tracks": c.tracks,
+                            "connected_at": c.connected_at.to_rfc3339()
+                        });
+                        c.profile.annotate(&mut connection);
+                        connection
+                    })
+                    .collect::<Vec<_>>(),
+                "pending_offers": { "count": self.offers.len() },
+                "model": self.model,
+                "calibration": self.calibration,
+                "latest_inference": latest_inference
+            }),
+        };
+
+        if let (Some(info), Value::Object(extra)) = (info.as_object_mut(), role_specific) {
+            info.extend(extra);
+        }
+        if let Some(expires_at) = self.connections.get(connection_id).and_then(|c| c.expires_at) {
+            info["session_expires_at"] = Value::String(expires_at.to_rfc3339());
+        }
+        info
+    }
+
+    /// Everything a (re)connected client needs to rebuild its view without waiting for the
+    /// next events: its room_info, the stored offers and the latest result of every source
+    pub fn sync_state(&self, connection_id: &str, role: Role, latest_inference: Option<&HashMap<String, Value>>) -> Value {
+        serde_json::json!({
+            "room_info": self.room_info(connection_id, role, latest_inference),
+            "offers": self.offers.values()
+                .map(|offer| serde_json::json!({
+                    "offer_id": offer.offer_id,
+                    "sender_id": offer.sender_id,
+                    "sdp": offer.data
+                }))
+                .collect::<Vec<_>>(),
+            "latest_inference": latest_inference,
+            "synced_at": chrono::Utc::now().to_rfc3339()
+        })
+    }
+
+    pub fn metadata(&self) -> RoomMetadata {
+        RoomMetadata {
+            room_id: self.id.clone(),
+            created_at: self.created_at.to_rfc3339(),
+            connection_count: self.get_connection_count(),
+            sender_token: None,
+            control_token: None,
+        }
+    }
+
+    /// Health summary polled by dashboards through GET /api/rooms/{id}; `latest_inference` is
+    /// the room's entry in RoomManager::inference_db and `hls_viewers` its HLS players. Peers and layer subscriptions, which name
+    /// connections, are left out unless `with_peers` (the caller is a room admin).
+    pub fn status(&self, latest_inference: Option<&HashMap<String, Value>>, hls_viewers: usize, with_peers: bool) -> RoomStatus {
+        let mut roles: HashMap<Role, usize> = HashMap::new();
+        for connection in self.connections.values() {
+            *roles.entry(connection.role).or_default() += 1;
+        }
+        let peers = with_peers.then(|| self.connections.values()
+            .map(|connection| PeerSummary {
+                connection_id: connection.id.clone(),
+                role: connection.role,
+                connected_at: connection.connected_at.to_rfc3339(),
+                profile: connection.profile.clone(),
+            })
+            .collect());
+        // Capture time of the newest result from any source
+        let latest_inference_at = latest_inference
+            .into_iter()
+            .flat_map(HashMap::values)
+            .filter_map(|payload| payload.get("timestamp").and_then(Value::as_i64))
+            .max()
+            .and_then(chrono::DateTime::<chrono::Utc>::from_timestamp_millis)
+            .map(|at| at.to_rfc3339());
+
+        RoomStatus {
+            exists: true,
+            room_id: self.id.clone(),
+            created_at: self.created_at.to_rfc3339(),
+            mode: self.mode,
+            template: self.template.clone(),
+            connection_count: self.get_connection_count(),
+            senders: roles.get(&Role::Sender).copied().unwrap_or(0),
+            viewers: roles.get(&Role::Viewer).copied().unwrap_or(0),
+            admins: roles.get(&Role::Admin).copied().unwrap_or(0),
+            controllers: roles.get(&Role::Controller).copied().unwrap_or(0),
+            standbys: roles.get(&Role::Standby).copied().unwrap_or(0),
+            peers,
+            active_offers: self.offers.len(),
+            layer_subscriptions: with_peers.then(|| self.layer_subscriptions.iter()
+                .map(|((viewer, sender), rid)| LayerSubscription { viewer: viewer.clone(), sender: sender.clone(), rid: rid.clone() })
+                .collect()),
+            latest_inference_at,
+            hls_viewers,
+        }
+    }
+
+    /// Issues a guest pass valid for `secs` from `now`. Anonymous callers (`client_ip` set) get
+    /// back the unexpired pass already issued to their address. None once MAX_GUEST_PASSES
+    /// are outstanding.
+    pub fn issue_guest_pass(&mut self, secs: u64, client_ip: Option<IpAddr>, now: chrono::DateTime<chrono::Utc>) -> Option<(String, GuestPass)> {
+        if let Some(ip) = client_ip {
+            let existing = self.guest_passes.iter()
+                .find(|(_, pass)| pass.issued_to == Some(ip) && pass.expires_at > now);
+            if let Some((token, pass)) = existing {
+                return Some((token.clone(), pass.clone()));
+            }
+        }
+        self.guest_passes.retain(|_, pass| pass.expires_at > now);
+        if self.guest_passes.len() >= MAX_GUEST_PASSES {
+            return None;
+        }
+        let pass = GuestPass {
+            expires_at: now + chrono::Duration::seconds(secs as i64),
+            issued_to: client_ip,
+        };
+        let token = new_room_token();
+        self.guest_passes.insert(token.clone(), pass.clone());
+        Some((token, pass))
+    }
+
+    /// Whether `token` is one the room handed out: its sender or control token, or a guest pass
+    /// unexpired at `now`
+    pub fn issued(&self, token: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+        [&self.sender_token, &self.control_token].into_iter().flatten().any(|issued| auth::secret_eq(token, issued))
+            || self.guest_passes.get(token).is_some_and(|pass| pass.expires_at > now)
+    }
+
+    /// What a viewer UI needs before opening a WebSocket, served by
+    /// GET /api/rooms/{id}/capabilities; `recording` comes from the recorder registry
+    pub fn capabilities(&self, recording: bool) -> RoomCapabilities {
+        RoomCapabilities {
+            room_id: self.id.clone(),
+            mode: self.mode,
+            sender_present: self.connections.values().any(|c| c.is_sender),
+            viewer_count: self.limited_viewer_count(""),
+            max_viewers: self.max_viewers,
+            recording,
+            sfu_active: self.sfu_connection_id.as_ref().is_some_and(|id| self.connections.contains_key(id)),
+            e2ee: self.e2ee,
+            guest_session_secs: self.guest_session_secs,
+            schedule: self.schedule,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -89,6 +597,53 @@ pub struct RoomManager {
     pub rooms: HashMap<String, Room>,
     // Simple in-memory inference DB: room_id -> (source_sender_id -> latest inference Value)
     pub inference_db: HashMap<String, HashMap<String, Value>>,
+    /// Global score calibration curves keyed by model name (from config)
+    pub calibration: HashMap<String, CalibrationCurve>,
+    /// Token that upgrades a joining viewer to Role::Admin (from config)
+    pub admin_token: Option<String>,
+    /// Tenant admin tokens mapped to their tenant; they upgrade viewers in that tenant's rooms only
+    pub tenant_admin_tokens: HashMap<String, String>,
+    /// Tenant and room quotas with today's stored result counts (from config)
+    pub quotas: QuotaLimits,
+    /// Length of guest passes a room admin issues without choosing one, in rooms without
+    /// their own guest_session_secs (from config)
+    pub guest_session_secs: u64,
+    /// Compares live detection counts against the learned baseline when enabled
+    pub anomaly: Option<AnomalyDetector>,
+    /// Assigns track IDs to detections when enabled
+    pub tracker: Option<Tracker>,
+    /// Rolling per-source statistics for InferenceStats and the stats endpoint
+    pub aggregator: InferenceAggregator,
+    /// Forwards stored inference results to MQTT when configured
+    pub mqtt: Option<MqttPublisher>,
+    /// Publishes stored inference results when configured (the server's, see state.rs)
+    pub event_bus: Option<EventBus>,
+    /// Zone events, alerts and sender promotions go out through these (the server's)
+    pub webhooks: Webhooks,
+    /// HLS players count as viewers (the server's)
+    pub hls: Hls,
+    /// Persists accepted inference results (SqliteStore unless an embedder set another)
+    pub store: Arc<dyn InferenceStore>,
+    /// Plugin hooks registered by an embedder
+    pub hooks: Hooks,
+    /// Room policy scripts run on joins and inference results when configured
+    pub scripts: Option<Scripts>,
+    /// Batch InferenceUpdate broadcasts into take_inference_updates instead of sending one per result
+    pub coalesce_inference_updates: bool,
+    /// Per-connection limits for Relay messages (from config)
+    pub relay: RelayLimiter,
+    /// Size and nesting limits for InferenceResult data (from config)
+    pub inference_limits: InferenceLimits,
+    /// What Offer and Answer SDP must look like and what is stripped from it (from config)
+    pub sdp: SdpPolicy,
+    /// Recent StatsReports per connection for GET /api/rooms/{id}/stats
+    pub peer_stats: PeerStatsStore,
+    /// Turns viewers' StatsReports into QualityHints for the sender (from config)
+    pub quality: QualityController,
+    /// Room groups and the events their subscribers get
+    pub groups: RoomGroups,
+    /// room_id -> sources with results not yet broadcast while coalescing
+    pending_updates: HashMap<String, HashSet<String>>,
 }
 
 impl RoomManager {
@@ -96,6 +651,29 @@ impl RoomManager {
         Self {
             rooms: HashMap::new(),
             inference_db: HashMap::new(),
+            calibration: HashMap::new(),
+            admin_token: None,
+            tenant_admin_tokens: HashMap::new(),
+            quotas: QuotaLimits::default(),
+            guest_session_secs: 900,
+            anomaly: None,
+            tracker: None,
+            aggregator: InferenceAggregator::default(),
+            mqtt: None,
+            event_bus: None,
+            webhooks: Webhooks::default(),
+            hls: Hls::default(),
+            store: Arc::new(SqliteStore::default()),
+            hooks: Hooks::default(),
+            scripts: None,
+            coalesce_inference_updates: false,
+            relay: RelayLimiter::default(),
+            inference_limits: InferenceLimits::default(),
+            sdp: SdpPolicy::default(),
+            peer_stats: PeerStatsStore::default(),
+            quality: QualityController::default(),
+            groups: RoomGroups::default(),
+            pending_updates: HashMap::new(),
         }
     }
     
@@ -103,16 +681,171 @@ impl RoomManager {
         let room = Room::new(room_id.clone());
         self.rooms.insert(room_id, room);
     }
+
+    /// Creates the room only if no room with this ID exists yet.
+    /// Returns true when a new room was created.
+    pub fn create_room_if_absent(&mut self, room_id: String) -> bool {
+        if self.rooms.contains_key(&room_id) {
+            return false;
+        }
+        self.create_room(room_id);
+        true
+    }
     
+    /// Handles a member's message; `sender_id` is its author, which Session sets to the
+    /// connection the client joined as
     pub fn handle_message(&mut self, room_id: String, message: SignalingMessage) -> Option<Vec<SignalingMessage>> {
+        self.handle(room_id, message, false)
+    }
+
+    /// Why an HLS player may not fetch `room_id`'s playlist, checked like a viewer's Join: the
+    /// schedule and, in guest rooms, a guest pass every time; max_viewers (counting HLS players)
+    /// and connection quotas only for players not `returning` within the viewer timeout
+    pub fn hls_refusal(&self, room_id: &str, guest_token: Option<&str>, returning: bool, now: chrono::DateTime<chrono::Utc>) -> Option<String> {
+        let Some(room) = self.rooms.get(room_id) else {
+            return Some("Room not found".to_string());
+        };
+        if let Some(status) = room.schedule.refusal(now) {
+            return Some(Schedule::refusal_error(status).to_string());
+        }
+        let guest_pass = guest_token.and_then(|token| room.guest_passes.get(token)).filter(|pass| pass.expires_at > now);
+        if room.guest_session_secs.is_some() && guest_pass.is_none() {
+            return Some(INVALID_GUEST_TOKEN.to_string());
+        }
+        if returning {
+            return None;
+        }
+        if room.max_viewers.is_some_and(|max| room.limited_viewer_count("") + self.hls.viewer_count(room_id) >= max) {
+            return Some("Room is full".to_string());
+        }
+        self.quotas.check_connection(room_id, "", &self.rooms).err().map(|exceeded| exceeded.to_string())
+    }
+
+    /// Like handle_message, for Joins the server makes itself: its local peers (SFU, recorder,
+    /// bridges) and members of other instances mirrored through the backplane, whose own
+    /// instance checked their guest pass. Neither needs one here.
+    pub fn handle_server_join(&mut self, room_id: String, message: SignalingMessage) -> Option<Vec<SignalingMessage>> {
+        self.handle(room_id, message, true)
+    }
+
+    #[tracing::instrument(skip_all, fields(room_id = %room_id, message_type = ?message.message_type, connection_id = ?message.connection_id, sender_id = ?message.sender_id, offer_id = ?message.offer_id))]
+    fn handle(&mut self, room_id: String, message: SignalingMessage, server_join: bool) -> Option<Vec<SignalingMessage>> {
+        // Tenant quotas count connections across rooms, so check before borrowing this one
+        let quota_refusal = match (&message.message_type, &message.connection_id) {
+            (SignalingMessageType::Join, Some(cid)) => self.quotas.check_connection(&room_id, cid, &self.rooms).err(),
+            _ => None,
+        };
         let room = self.rooms.get_mut(&room_id)?;
+        // StatsReports come on a timer from every open page, so they don't keep a room alive
+        if !matches!(message.message_type, SignalingMessageType::StatsReport) {
+            room.last_activity = chrono::Utc::now();
+            room.idle_warned = false;
+        }
+
+        let mut message = message;
+        if !self.hooks.is_empty() {
+            let join = matches!(message.message_type, SignalingMessageType::Join);
+            let from = if join { message.connection_id.clone() } else { message.sender_id.clone() };
+            let context = HookContext {
+                room_id: &room_id,
+                connection_id: from.as_deref(),
+                role: from.as_ref().filter(|_| !join).and_then(|id| room.connections.get(id)).map(|info| info.role),
+            };
+            if let Err((hook, reason)) = self.hooks.intercept(&context, &mut message, join) {
+                debug!("Hook {} rejected {:?} from {:?} in room {}: {}", hook, message.message_type, from, room_id, reason);
+                return from.map(|from| vec![SignalingMessage::new_error(from, reason)]);
+            }
+        }
+        if let Err(e) = self.sdp.sanitize(&mut message) {
+            debug!("Rejected {:?} from {:?} in room {}: {}", message.message_type, message.sender_id, room_id, e);
+            return message.sender_id.map(|from| vec![SignalingMessage::new_error(from, format!("Invalid SDP: {}", e))]);
+        }
+        room.ice_policy.filter_description(&mut message);
         
         match message.message_type {
             SignalingMessageType::Join => {
                 let is_sender = message.is_sender.unwrap_or(false);
                 let connection_id = message.connection_id.clone()?;
+                let join_data = message.data.clone().unwrap_or(Value::Null);
                 
-                let removed_ids = match room.add_connection(connection_id.clone(), is_sender) {
+                let presented_token = join_data.get("admin_token").and_then(Value::as_str);
+                let wants_control = join_data.get("role").and_then(Value::as_str) == Some("controller");
+                let control_token = join_data.get("control_token").and_then(Value::as_str);
+                let wants_standby = join_data.get("standby").and_then(Value::as_bool).unwrap_or(false);
+                let role = if is_sender {
+                    // A second sender may wait as a standby; with no sender to stand by for, it is the sender
+                    if wants_standby && room.connections.values().any(|c| c.is_sender && c.id != connection_id) {
+                        Role::Standby
+                    } else {
+                        Role::Sender
+                    }
+                } else if matches!((&self.admin_token, presented_token), (Some(expected), Some(given)) if auth::secret_eq(given, expected))
+                    || presented_token.and_then(|given| self.tenant_admin_tokens.iter().find(|(token, _)| auth::secret_eq(given, token.as_str())))
+                        .is_some_and(|(_, tenant)| tenant::tenant_of(&room_id) == Some(tenant.as_str()))
+                {
+                    Role::Admin
+                } else if wants_control {
+                    match (&room.control_token, control_token) {
+                        (Some(expected), Some(given)) if auth::secret_eq(given, expected) => Role::Controller,
+                        _ => return Some(vec![SignalingMessage::new_error(connection_id, INVALID_CONTROL_TOKEN.to_string())]),
+                    }
+                } else {
+                    Role::Viewer
+                };
+                let tracks = join_data.get("tracks")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                let profile = match Profile::from_join(&join_data) {
+                    Ok(profile) => profile,
+                    Err(e) => return Some(vec![SignalingMessage::new_error(connection_id, format!("Invalid join: {}", e))]),
+                };
+                
+                // Guest sessions are bound to a pass the server issued, never to what the client claims
+                let guest_expires_at = match (role, join_data.get("guest_token").and_then(Value::as_str)) {
+                    _ if server_join => None,
+                    (Role::Viewer, Some(token)) => match room.guest_passes.get(token).filter(|pass| pass.expires_at > chrono::Utc::now()) {
+                        Some(pass) => Some(pass.expires_at),
+                        None => return Some(vec![SignalingMessage::new_error(connection_id, INVALID_GUEST_TOKEN.to_string())]),
+                    },
+                    (Role::Viewer, None) if room.guest_session_secs.is_some() => {
+                        return Some(vec![SignalingMessage::new_error(connection_id, "Guest pass required".to_string())]);
+                    }
+                    _ => None,
+                };
+                
+                // Keeps visitors of a public room from taking the camera slot
+                if let (true, Some(expected)) = (is_sender, &room.sender_token) {
+                    if !join_data.get("sender_token").and_then(Value::as_str).is_some_and(|given| auth::secret_eq(given, expected)) {
+                        return Some(vec![SignalingMessage::new_error(connection_id, INVALID_SENDER_TOKEN.to_string())]);
+                    }
+                }
+
+                if let Some(status) = room.schedule.refusal(chrono::Utc::now())
+                    .filter(|_| role != Role::Admin && room.sfu_connection_id.as_deref() != Some(connection_id.as_str()))
+                {
+                    return Some(vec![room.schedule.refusal_message(connection_id, status)]);
+                }
+
+                let full = matches!(room.max_viewers, Some(max) if matches!(role, Role::Viewer | Role::Controller)
+                    && room.sfu_connection_id.as_deref() != Some(connection_id.as_str())
+                    && room.limited_viewer_count(&connection_id) + self.hls.viewer_count(&room_id) >= max);
+                if full {
+                    return Some(vec![SignalingMessage::new_error(connection_id, "Room is full".to_string())]);
+                }
+                if let Some(exceeded) = quota_refusal.filter(|_| room.sfu_connection_id.as_deref() != Some(connection_id.as_str())) {
+                    return Some(vec![exceeded.message(connection_id)]);
+                }
+                if let Some(Err(reason)) = self.scripts.as_ref().map(|scripts| scripts.on_join(&room_id, &connection_id, role, &join_data)) {
+                    debug!("Room policy denied join of {} in room {}: {}", connection_id, room_id, reason);
+                    return Some(vec![SignalingMessage::new_error(connection_id, reason)]);
+                }
+
+                let sender_reconnected = role == Role::Sender && room.last_sender.take().is_some_and(|(id, left_at)| {
+                    id == connection_id && chrono::Utc::now() - left_at <= chrono::Duration::seconds(SENDER_RECONNECT_SECS)
+                });
+
+                let removed_ids = match room.add_connection(connection_id.clone(), role, tracks, profile.clone()) {
                     Ok(ids) => ids,
                     Err(e) => {
                         return Some(vec![SignalingMessage {
@@ -125,10 +858,16 @@ impl RoomManager {
                                 "error": e
                             })),
                             is_sender: None,
+                            seq: None,
+                            msg_id: None,
                         }]);
                     }
                 };
                 
+                if let (Some(expires_at), Some(info)) = (guest_expires_at, room.connections.get_mut(&connection_id)) {
+                    info.expires_at = Some(expires_at);
+                }
+                
                 let connection_count = room.get_connection_count();
 
                 // Prepare RoomInfo for the joiner
@@ -138,21 +877,16 @@ impl RoomManager {
                     source_sender_id: None,
                     sender_id: None,
                     offer_id: None,
-                    data: Some(serde_json::json!({
-                        "room_id": room_id,
-                        "mode": "1onN",
-                        "connection_count": connection_count,
-                        "peers": room.connections.iter()
-                                .filter(|(id, _)| *id != &connection_id)
-                                .map(|(id, info)| serde_json::json!({ "id": id, "is_sender": info.is_sender }))
-                                .collect::<Vec<_>>()
-                    })),
+                    data: Some(room.room_info(&connection_id, role, self.inference_db.get(&room_id))),
                     is_sender: None,
+                    seq: None,
+                    msg_id: None,
                 }];
 
                 // Notify about replaced connections (Leave messages)
                 for rid in removed_ids {
-                    for (other_id, _) in &room.connections {
+                    self.groups.publish(&room_id, SignalingMessageType::Leave, &serde_json::json!({"connection_id": rid, "connection_count": connection_count}));
+                    for other_id in room.connections.keys() {
                         responses.push(SignalingMessage {
                             message_type: SignalingMessageType::Leave,
                             connection_id: Some(other_id.clone()),
@@ -164,29 +898,54 @@ impl RoomManager {
                                 "connection_count": connection_count
                             })),
                             is_sender: None,
+                            seq: None,
+                            msg_id: None,
                         });
                     }
                 }
 
                 // Notify other peers about the new user
-                for (other_id, _) in &room.connections {
-                    if *other_id != connection_id {
+                let hidden_from_sender = room.hidden_from_sender(&connection_id);
+                let mut new_peer = serde_json::json!({
+                    "connection_id": connection_id,
+                    "is_sender": is_sender,
+                    "role": role,
+                    "connection_count": connection_count
+                });
+                profile.annotate(&mut new_peer);
+                self.groups.publish(&room_id, SignalingMessageType::NewPeer, &new_peer);
+                for (other_id, other) in &room.connections {
+                    // Only admins see a standby; a standby sees no one join, having no one to offer to
+                    let hidden = (other.is_sender && hidden_from_sender)
+                        || (role == Role::Standby && other.role != Role::Admin)
+                        || other.role == Role::Standby;
+                    if *other_id != connection_id && !hidden {
                         responses.push(SignalingMessage {
                             message_type: SignalingMessageType::NewPeer,
                             connection_id: Some(other_id.clone()),
                             source_sender_id: None,
                             sender_id: None,
                             offer_id: None,
-                            data: Some(serde_json::json!({
-                                "connection_id": connection_id,
-                                "is_sender": is_sender,
-                                "connection_count": connection_count
-                            })),
+                            data: Some(new_peer.clone()),
                             is_sender: None,
+                            seq: None,
+                            msg_id: None,
                         });
                     }
                 }
 
+                // A sender back after a network change: its viewers may still hold their peer
+                // connections, so both sides are asked to restart ICE instead of starting over
+                if sender_reconnected {
+                    for (other_id, other) in &room.connections {
+                        if *other_id == connection_id || other.is_sender || room.hidden_from_sender(other_id) {
+                            continue;
+                        }
+                        responses.push(SignalingMessage::new_ice_restart(connection_id.clone(), other_id.clone(), "sender_reconnected"));
+                        responses.push(SignalingMessage::new_ice_restart(other_id.clone(), connection_id.clone(), "sender_reconnected"));
+                    }
+                }
+
                 // Legacy: If this is a viewer, send them existing stored offers
                 if !is_sender {
                     let offers = room.get_offers_for_viewer();
@@ -199,6 +958,8 @@ impl RoomManager {
                             offer_id: offer.offer_id.clone(),
                             data: offer.data.clone(),
                             is_sender: None,
+                            seq: None,
+                            msg_id: None,
                         });
                     }
                 }
@@ -207,6 +968,12 @@ impl RoomManager {
             }
             
             SignalingMessageType::Offer => {
+                let from = message.sender_id.clone()?;
+                match room.connections.get(&from).map(|info| info.role) {
+                    None => return Some(vec![SignalingMessage::new_error(from, "Join the room before offering".to_string())]),
+                    Some(Role::Standby) => return Some(vec![SignalingMessage::new_error(from, "A standby sender can't offer until it is promoted".to_string())]),
+                    Some(_) => {}
+                }
                 // In Mesh 1onN, we usually route directly if connection_id is set
                 if message.connection_id.is_some() {
                     return Some(vec![message]);
@@ -224,6 +991,8 @@ impl RoomManager {
                             "error": e
                         })),
                         is_sender: None,
+                        seq: None,
+                        msg_id: None,
                     }]);
                 }
                 
@@ -232,7 +1001,7 @@ impl RoomManager {
                 
                 for offer in offers {
                     for (conn_id, conn_info) in &room.connections {
-                        if !conn_info.is_sender {
+                        if !conn_info.is_sender && conn_info.role != Role::Standby {
                             responses.push(SignalingMessage {
                                 message_type: SignalingMessageType::Offer,
                                 connection_id: Some(conn_id.clone()),
@@ -241,6 +1010,8 @@ impl RoomManager {
                                 offer_id: offer.offer_id.clone(),
                                 data: offer.data.clone(),
                                 is_sender: None,
+                                seq: None,
+                                msg_id: None,
                             });
                         }
                     }
@@ -252,6 +1023,10 @@ impl RoomManager {
             SignalingMessageType::Answer => Some(vec![message]),
 
             SignalingMessageType::IceCandidate => {
+                if !room.ice_policy.permits(&message) {
+                    debug!("Dropped candidate from {:?} in room {} under ice_policy {:?}", message.sender_id, room_id, room.ice_policy);
+                    return None;
+                }
                 if message.connection_id.is_some() {
                     Some(vec![message])
                 } else {
@@ -267,52 +1042,305 @@ impl RoomManager {
                 }
             }
 
-            SignalingMessageType::InferenceResult => {
-                // Expect message.source_sender_id to indicate which original sender the predictions refer to
-                let source_id = message.source_sender_id.clone();
-                if source_id.is_none() {
+            SignalingMessageType::Relay => {
+                let from = message.sender_id.clone()?;
+                if let Some(error) = room.control_rejection(&from, &message) {
+                    return Some(vec![SignalingMessage::new_error(from, error)]);
+                }
+                let target = match &message.connection_id {
+                    Some(target) if room.connections.contains_key(target) => target.clone(),
+                    _ => return Some(vec![SignalingMessage::new_error(from, "Relay target is not in this room".to_string())]),
+                };
+                let payload_bytes = message.data.as_ref().map(|data| data.to_string().len()).unwrap_or(0);
+                if let Err(e) = self.relay.check_relay(&from, payload_bytes, std::time::Instant::now()) {
+                    debug!("Dropped relay from {} to {} in room {}: {}", from, target, room_id, e);
+                    return Some(vec![SignalingMessage::new_error(from, e)]);
+                }
+                Some(vec![message])
+            }
+
+            SignalingMessageType::IceRestart => {
+                let from = message.sender_id.clone()?;
+                if !room.connections.contains_key(&from) {
+                    return None;
+                }
+                match &message.connection_id {
+                    Some(target) if room.connections.contains_key(target) => Some(vec![message]),
+                    _ => Some(vec![SignalingMessage::new_error(from, "IceRestart target is not in this room".to_string())]),
+                }
+            }
+
+            // Key material goes to its one target and nowhere else; nothing of it is kept
+            SignalingMessageType::KeyExchange => {
+                let from = message.sender_id.clone()?;
+                if !room.connections.contains_key(&from) {
+                    return None;
+                }
+                if !room.e2ee {
+                    return Some(vec![SignalingMessage::new_error(from, "End-to-end encryption is not enabled in this room".to_string())]);
+                }
+                match &message.connection_id {
+                    Some(target) if *target != from && room.connections.contains_key(target) => Some(vec![message]),
+                    _ => Some(vec![SignalingMessage::new_error(from, "KeyExchange target is not in this room".to_string())]),
+                }
+            }
+
+            SignalingMessageType::SubscribeLayer => {
+                let from = message.sender_id.clone()?;
+                if !room.connections.contains_key(&from) {
+                    return None;
+                }
+                let target = match &message.connection_id {
+                    Some(target) if *target != from && room.connections.contains_key(target) => target.clone(),
+                    _ => return Some(vec![SignalingMessage::new_error(from, "SubscribeLayer target is not in this room".to_string())]),
+                };
+                let rid = match message.data.as_ref().and_then(|data| data.get("rid")).and_then(Value::as_str) {
+                    Some(rid) if is_valid_rid(rid) => rid.to_string(),
+                    _ => return Some(vec![SignalingMessage::new_error(from, "SubscribeLayer needs a valid rid".to_string())]),
+                };
+                room.layer_subscriptions.insert((from, target), rid);
+                Some(vec![message])
+            }
+
+            SignalingMessageType::SubscribeInference => {
+                let from = message.sender_id.clone()?;
+                let filter = match InferenceFilter::parse(message.data.as_ref()) {
+                    Ok(filter) => filter,
+                    Err(e) => return Some(vec![SignalingMessage::new_error(from, format!("Invalid SubscribeInference: {}", e))]),
+                };
+                let info = room.connections.get_mut(&from)?;
+                debug!("Connection {} in room {} subscribed to inference {:?}", from, room_id, filter);
+                info.inference_filter = filter;
+                None
+            }
+
+            SignalingMessageType::SetZones => {
+                let from = message.sender_id.clone()?;
+                if !matches!(room.connections.get(&from).map(|c| c.role), Some(Role::Admin | Role::Controller)) {
+                    return Some(vec![SignalingMessage::new_error(from, "Only admins and controllers may set zones".to_string())]);
+                }
+                let zones = match message.data.as_ref().and_then(|data| data.get("zones")).cloned().map(serde_json::from_value::<Vec<zones::Zone>>) {
+                    Some(Ok(zones)) => zones,
+                    Some(Err(e)) => return Some(vec![SignalingMessage::new_error(from, format!("Invalid SetZones: {}", e))]),
+                    None => return Some(vec![SignalingMessage::new_error(from, "SetZones needs data.zones".to_string())]),
+                };
+                if let Err(e) = zones::validate_all(&zones) {
+                    return Some(vec![SignalingMessage::new_error(from, format!("Invalid SetZones: {}", e))]);
+                }
+                debug!("Connection {} set {} zones in room {}", from, zones.len(), room_id);
+                room.zones.set(zones);
+                None
+            }
+
+            SignalingMessageType::LayerInfo => {
+                let from = message.sender_id.clone()?;
+                if !room.connections.contains_key(&from) {
+                    return None;
+                }
+                match &message.connection_id {
+                    Some(target) if room.connections.contains_key(target) => Some(vec![message]),
+                    Some(_) => Some(vec![SignalingMessage::new_error(from, "LayerInfo target is not in this room".to_string())]),
+                    None => Some(room.connections.values()
+                        .filter(|c| !c.is_sender && c.id != from)
+                        .map(|c| {
+                            let mut copy = message.clone();
+                            copy.connection_id = Some(c.id.clone());
+                            copy
+                        })
+                        .collect()),
+                }
+            }
+
+            SignalingMessageType::Broadcast => {
+                let from = message.sender_id.clone()?;
+                if let Some(error) = room.control_rejection(&from, &message) {
+                    return Some(vec![SignalingMessage::new_error(from, error)]);
+                }
+                let payload_bytes = message.data.as_ref().map(|data| data.to_string().len()).unwrap_or(0);
+                if let Err(e) = self.relay.check_broadcast(&from, payload_bytes, std::time::Instant::now()) {
+                    debug!("Dropped broadcast from {} in room {}: {}", from, room_id, e);
+                    return Some(vec![SignalingMessage::new_error(from, e)]);
+                }
+                let responses = room.connections.keys()
+                    .filter(|conn_id| **conn_id != from)
+                    .map(|conn_id| {
+                        let mut copy = message.clone();
+                        copy.connection_id = Some(conn_id.clone());
+                        copy
+                    })
+                    .collect();
+                Some(responses)
+            }
+
+            SignalingMessageType::StatsReport => {
+                let from = message.sender_id.clone()?;
+                if !room.connections.contains_key(&from) {
                     return None;
                 }
-                let source_id = source_id.unwrap();
+                let now = chrono::Utc::now();
+                let report = match StatsReport::from_data(message.data.as_ref(), now) {
+                    Ok(report) => report,
+                    Err(e) => return Some(vec![SignalingMessage::new_error(from, e)]),
+                };
+                self.peer_stats.record(&room_id, &from, report);
+
+                // Viewer reports may call for the sender to change its capture quality
+                let sender_id = room.connections.values().find(|c| c.is_sender).map(|c| c.id.clone())?;
+                let sfu_id = room.sfu_connection_id.clone();
+                if from == sender_id || sfu_id.as_deref() == Some(from.as_str()) {
+                    return None;
+                }
+                let viewers: Vec<String> = room.connections.values()
+                    .filter(|c| !c.is_sender && sfu_id.as_deref() != Some(c.id.as_str()))
+                    .map(|c| c.id.clone())
+                    .collect();
+                let upstream: Vec<&str> = std::iter::once(sender_id.as_str()).chain(sfu_id.as_deref()).collect();
+                let hint = self.quality.evaluate(&room_id, room.video_constraints.as_ref(), &sender_id, &upstream, &viewers, &self.peer_stats, now)?;
+                debug!("Quality hint for sender {} in room {}: {}", sender_id, room_id, hint);
+                Some(vec![SignalingMessage::new_quality_hint(sender_id, hint)])
+            }
+
+            SignalingMessageType::SyncRequest => {
+                let requester = message.sender_id.clone().or_else(|| message.connection_id.clone())?;
+                let role = match room.connections.get(&requester) {
+                    Some(info) => info.role,
+                    None => return Some(vec![SignalingMessage::new_error(requester, "Join the room before requesting a sync".to_string())]),
+                };
+                let state = room.sync_state(&requester, role, self.inference_db.get(&room_id));
+                Some(vec![SignalingMessage::new_sync_state(requester, state)])
+            }
+
+            SignalingMessageType::InferenceResult => {
+                // Expect message.source_sender_id to indicate which original sender the predictions refer to
+                let source_id = message.source_sender_id.clone()?;
+                // SFU viewers get the stream from the forwarding peer; credit the actual sender
+                let source_id = match (&room.sfu_connection_id, room.connections.values().find(|c| c.is_sender)) {
+                    (Some(sfu_id), Some(sender)) if *sfu_id == source_id => sender.id.clone(),
+                    _ => source_id,
+                };
+
+                // Reject malformed payloads back to the reporting viewer
+                let normalized = match message.data.as_ref().map(|data| InferencePayload::parse(data, &self.inference_limits)) {
+                    Some(Ok(payload)) => Some(payload.to_value()),
+                    Some(Err(e)) => {
+                        debug!("Rejected inference result for {} in room {}: {}", source_id, room_id, e);
+                        let reporter = message.sender_id.clone()?;
+                        return Some(vec![SignalingMessage::new_error(reporter, format!("Invalid inference payload: {}", e))]);
+                    }
+                    None => None,
+                };
+
+                let mut anomalies = Vec::new();
+                let mut quota_errors = Vec::new();
+                let mut zone_events = Vec::new();
+                let mut alerts = Vec::new();
 
                 // Store the latest data in inference_db (in-memory)
-                let room_entry = self.inference_db.entry(room_id.clone()).or_insert_with(HashMap::new);
-                if let Some(d) = message.data.clone() {
+                let room_entry = self.inference_db.entry(room_id.clone()).or_default();
+                if let Some(mut d) = normalized {
+                    // Calibrate scores before anything is stored or evaluated
+                    if let Some(curve) = calibration::curve_for(&d, &room.calibration, &self.calibration) {
+                        calibration::apply_to_payload(&mut d, curve);
+                    }
+                    // Policy scripts see calibrated scores and may rewrite the result
+                    if let Some(scripts) = &self.scripts {
+                        let outcome = scripts.on_inference(&room_id, &source_id, &mut d, &self.inference_limits);
+                        if outcome.dropped {
+                            return Some(Vec::new());
+                        }
+                        alerts = outcome.alerts;
+                    }
+                    if let Some(tracker) = self.tracker.as_mut() {
+                        tracker.assign(&room_id, &source_id, &mut d);
+                    }
+
                     // Update in-memory
                     room_entry.insert(source_id.clone(), d.clone());
 
-                    // Persist: attempt SQLite insert, log error on failure.
-                    // DB path and JSONL path are chosen as defaults under `data/`.
-                    // These files/folders may need to be created or adjusted in production.
-                    if let Err(e) = persistence::save_inference_sqlite("data/inference.db", &room_id, &source_id, &d) {
-                        error!("Failed to save inference to sqlite: {}", e);
+                    zone_events = room.zones.evaluate(&source_id, &d);
+                    room.counting.observe(&source_id, &d);
+
+                    // Over the daily stored-results quota the result is still forwarded live;
+                    // the reporter hears about it once a day
+                    let stored = match self.quotas.take_inference_row(&room_id, chrono::Utc::now()) {
+                        Ok(()) => true,
+                        Err(refused) => {
+                            if let (true, Some(reporter)) = (refused.first, &message.sender_id) {
+                                quota_errors.push(refused.exceeded.message(reporter.clone()));
+                            }
+                            false
+                        }
+                    };
+
+                    // Persist through the configured store (SQLite and JSONL under data/ by default)
+                    if stored {
+                        if let Err(e) = self.store.save_inference(&room_id, &source_id, &d) {
+                            error!("Failed to store inference: {}", e);
+                        }
+                    }
+
+                    if let Some(publisher) = &self.mqtt {
+                        publisher.publish(&room_id, &source_id, &d);
+                    }
+                    if let Some(event_bus) = &self.event_bus {
+                        event_bus.publish_inference(&room_id, &source_id, &d);
                     }
 
-                    // Also append a human/AI-friendly JSONL export for easy editing and transfer.
-                    if let Err(e) = persistence::append_jsonl("data/inference.jsonl", &room_id, &source_id, &d) {
-                        error!("Failed to append inference to jsonl: {}", e);
+                    let now = chrono::Utc::now();
+                    self.aggregator.record(&room_id, &source_id, &d, now);
+                    if let Some(detector) = self.anomaly.as_mut() {
+                        anomalies = detector.observe(&room_id, &d, now);
                     }
                 }
 
-                // Broadcast a lightweight InferenceUpdate to all peers in the room
-                let mut responses = Vec::new();
+                // Broadcast a lightweight InferenceUpdate to all peers in the room, or leave it to
+                // take_inference_updates when updates are coalesced per interval
+                let mut responses = quota_errors;
+                if self.coalesce_inference_updates {
+                    self.pending_updates.entry(room_id.clone()).or_default().insert(source_id.clone());
+                }
                 if let Some(room) = self.rooms.get(&room_id) {
-                    for (conn_id, _) in &room.connections {
-                        // Prepare aggregated payload: include latest for this source
-                        let payload = serde_json::json!({
-                            "source_sender_id": source_id,
-                            "latest": room_entry.get(&source_id)
-                        });
+                    if !self.coalesce_inference_updates {
+                        responses.extend(inference_update_messages(room, &source_id, room_entry.get(&source_id)));
+                        self.groups.publish(&room_id, SignalingMessageType::InferenceUpdate, &serde_json::json!({"source_sender_id": source_id, "latest": room_entry.get(&source_id)}));
+                    }
 
-                        responses.push(SignalingMessage {
-                            message_type: SignalingMessageType::InferenceUpdate,
-                            connection_id: Some(conn_id.clone()),
-                            source_sender_id: None,
-                            sender_id: None,
-                            offer_id: None,
-                            data: Some(payload),
-                            is_sender: None,
-                        });
+                    for event in &anomalies {
+                        warn!("Inference anomaly in room {}: {}", room_id, event);
+                        for conn_id in room.connections.keys() {
+                            responses.push(SignalingMessage {
+                                message_type: SignalingMessageType::Anomaly,
+                                connection_id: Some(conn_id.clone()),
+                                source_sender_id: Some(source_id.clone()),
+                                sender_id: None,
+                                offer_id: None,
+                                data: Some(event.clone()),
+                                is_sender: None,
+                                seq: None,
+                                msg_id: None,
+                            });
+                        }
+                    }
+
+                    for event in &zone_events {
+                        debug!("Zone {} in room {}: {:?} by {}", event.zone_id, room_id, event.event, source_id);
+                        let data = serde_json::to_value(event).unwrap_or(Value::Null);
+                        self.webhooks.emit(webhooks::ZONE_EVENT, &room_id, data.clone());
+                        for conn_id in room.connections.keys() {
+                            let mut message = SignalingMessage::new_zone_event(source_id.clone(), data.clone());
+                            message.connection_id = Some(conn_id.clone());
+                            responses.push(message);
+                        }
+                    }
+
+                    for alert in &alerts {
+                        debug!("Room policy alert in room {}: {}", room_id, alert);
+                        self.webhooks.emit(webhooks::ALERT, &room_id, alert.clone());
+                        for conn_id in room.connections.keys() {
+                            let mut message = SignalingMessage::new_alert(source_id.clone(), alert.clone());
+                            message.connection_id = Some(conn_id.clone());
+                            responses.push(message);
+                        }
                     }
                 }
 
@@ -325,12 +1353,25 @@ impl RoomManager {
     
     pub fn remove_connection(&mut self, room_id: &str, connection_id: &str) -> Option<Vec<SignalingMessage>> {
         let room = self.rooms.get_mut(room_id)?;
+        // Already removed by the server (e.g. an expired guest); peers were notified then
+        if !room.connections.contains_key(connection_id) {
+            return None;
+        }
+        let role = room.connections.get(connection_id).map(|info| info.role);
         room.remove_connection(connection_id);
+        room.counting.forget_source(connection_id);
+        self.hooks.leave(&HookContext { room_id, connection_id: Some(connection_id), role });
+        self.relay.forget(connection_id);
+        self.peer_stats.forget(room_id, connection_id);
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.forget_source(room_id, connection_id);
+        }
         
         let connection_count = room.get_connection_count();
         let mut responses = Vec::new();
+        self.groups.publish(room_id, SignalingMessageType::Leave, &serde_json::json!({"connection_id": connection_id, "connection_count": connection_count}));
         
-        for (other_id, _) in &room.connections {
+        for other_id in room.connections.keys() {
             responses.push(SignalingMessage {
                 message_type: SignalingMessageType::Leave,
                 connection_id: Some(other_id.clone()),
@@ -342,10 +1383,287 @@ impl RoomManager {
                     "connection_count": connection_count
                 })),
                 is_sender: None,
+                seq: None,
+                msg_id: None,
             });
         }
+
+        // The standby takes over from a sender that left: everyone is told, and the new sender
+        // offers to the viewers listed in a fresh RoomInfo
+        if role == Some(Role::Sender) {
+            if let Some(promoted) = room.promote_standby() {
+                info!("Standby {} took over from sender {} in room {}", promoted, connection_id, room_id);
+                for other_id in room.connections.keys() {
+                    responses.push(SignalingMessage::new_sender_changed(other_id.clone(), connection_id, &promoted));
+                }
+                responses.push(SignalingMessage {
+                    message_type: SignalingMessageType::RoomInfo,
+                    connection_id: Some(promoted.clone()),
+                    source_sender_id: None,
+                    sender_id: None,
+                    offer_id: None,
+                    data: Some(room.room_info(&promoted, Role::Sender, self.inference_db.get(room_id))),
+                    is_sender: None,
+                    seq: None,
+                    msg_id: None,
+                });
+                self.groups.publish(room_id, SignalingMessageType::SenderChanged, &serde_json::json!({"previous_sender_id": connection_id, "sender_id": promoted}));
+                self.webhooks.emit(webhooks::SENDER_JOINED, room_id, serde_json::json!({"connection_id": promoted, "promoted_from": connection_id}));
+            }
+        }
         
         Some(responses)
     }
+
+    /// Deletes a room with everything kept in memory for it. Returns a RoomClosed message for
+    /// every member and the connection IDs whose sockets should now be closed, including the
+    /// room's SFU and recorder peers, which stop once their queues close.
+    pub fn close_room(&mut self, room_id: &str, reason: &str) -> Option<(Vec<SignalingMessage>, Vec<String>)> {
+        let room = self.rooms.remove(room_id)?;
+        self.inference_db.remove(room_id);
+        self.aggregator.forget_room(room_id);
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.forget_room(room_id);
+        }
+        self.peer_stats.forget_room(room_id);
+        self.quality.forget_room(room_id);
+        self.pending_updates.remove(room_id);
+
+        for info in room.connections.values() {
+            self.hooks.leave(&HookContext { room_id, connection_id: Some(&info.id), role: Some(info.role) });
+        }
+        let connection_ids: Vec<String> = room.connections.into_keys().collect();
+        let responses = connection_ids.iter()
+            .map(|connection_id| {
+                self.relay.forget(connection_id);
+                SignalingMessage::new_room_closed(connection_id.clone(), reason)
+            })
+            .collect();
+        Some((responses, connection_ids))
+    }
+
+    /// Rooms whose schedule ended by `now`, to be closed with close_room
+    pub fn ended_rooms(&self, now: chrono::DateTime<chrono::Utc>) -> Vec<String> {
+        self.rooms.values()
+            .filter(|room| room.schedule.has_ended(now))
+            .map(|room| room.id.clone())
+            .collect()
+    }
+
+    /// Warns guests whose session ends within `warning_secs` and removes expired ones.
+    /// Returns the messages to route (warnings, SessionExpired, Leave for remaining peers)
+    /// and the (room ID, connection ID) pairs whose sockets should now be closed.
+    pub fn expire_guests(&mut self, now: chrono::DateTime<chrono::Utc>, warning_secs: u64) -> (Vec<SignalingMessage>, Vec<(String, String)>) {
+        let mut responses = Vec::new();
+        let mut expired = Vec::new();
+
+        for (room_id, room) in &mut self.rooms {
+            room.guest_passes.retain(|_, pass| pass.expires_at > now);
+            for info in room.connections.values_mut() {
+                let expires_at = match info.expires_at {
+                    Some(expires_at) => expires_at,
+                    None => continue,
+                };
+                let seconds_left = (expires_at - now).num_seconds();
+
+                if seconds_left <= 0 {
+                    expired.push((room_id.clone(), info.id.clone()));
+                } else if !info.expiry_warned && seconds_left <= warning_secs as i64 {
+                    info.expiry_warned = true;
+                    responses.push(SignalingMessage {
+                        message_type: SignalingMessageType::GuestExpiring,
+                        connection_id: Some(info.id.clone()),
+                        source_sender_id: None,
+                        sender_id: None,
+                        offer_id: None,
+                        data: Some(serde_json::json!({
+                            "seconds_left": seconds_left,
+                            "expires_at": expires_at.to_rfc3339()
+                        })),
+                        is_sender: None,
+                        seq: None,
+                        msg_id: None,
+                    });
+                }
+            }
+        }
+
+        let mut closed = Vec::new();
+        for (room_id, connection_id) in expired {
+            responses.push(SignalingMessage {
+                message_type: SignalingMessageType::SessionExpired,
+                connection_id: Some(connection_id.clone()),
+                source_sender_id: None,
+                sender_id: None,
+                offer_id: None,
+                data: Some(serde_json::json!({
+                    "reason": "guest session time limit reached"
+                })),
+                is_sender: None,
+                seq: None,
+                msg_id: None,
+            });
+            if let Some(leaves) = self.remove_connection(&room_id, &connection_id) {
+                responses.extend(leaves);
+            }
+            closed.push((room_id, connection_id));
+        }
+
+        (responses, closed)
+    }
+
+    /// Warns the members of rooms with an idle timeout `warning_secs` before it runs out and
+    /// closes every connection (except the server's SFU peer) once it has. Returns the messages
+    /// to route and the (room ID, connection ID) pairs whose sockets should now be closed.
+    pub fn expire_idle(&mut self, now: chrono::DateTime<chrono::Utc>, warning_secs: u64) -> (Vec<SignalingMessage>, Vec<(String, String)>) {
+        let mut responses = Vec::new();
+        let mut idle = Vec::new();
+
+        for (room_id, room) in &mut self.rooms {
+            let idle_timeout_secs = match room.idle_timeout_secs {
+                Some(secs) if !room.connections.is_empty() => secs,
+                _ => continue,
+            };
+            let closes_at = room.last_activity + chrono::Duration::seconds(idle_timeout_secs as i64);
+            let seconds_left = (closes_at - now).num_seconds();
+            let members = room.connections.keys()
+                .filter(|id| room.sfu_connection_id.as_deref() != Some(id.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if seconds_left <= 0 {
+                idle.extend(members.into_iter().map(|connection_id| (room_id.clone(), connection_id)));
+            } else if !room.idle_warned && seconds_left <= warning_secs as i64 {
+                room.idle_warned = true;
+                let data = serde_json::json!({
+                    "seconds_left": seconds_left,
+                    "idle_timeout_secs": idle_timeout_secs,
+                    "closes_at": closes_at.to_rfc3339()
+                });
+                responses.extend(members.into_iter().map(|connection_id| SignalingMessage {
+                    message_type: SignalingMessageType::IdleWarning,
+                    connection_id: Some(connection_id),
+                    source_sender_id: None,
+                    sender_id: None,
+                    offer_id: None,
+                    data: Some(data.clone()),
+                    is_sender: None,
+                    seq: None,
+                    msg_id: None,
+                }));
+            }
+        }
+
+        let mut closed = Vec::new();
+        for (room_id, connection_id) in idle {
+            responses.push(SignalingMessage {
+                message_type: SignalingMessageType::SessionExpired,
+                connection_id: Some(connection_id.clone()),
+                source_sender_id: None,
+                sender_id: None,
+                offer_id: None,
+                data: Some(serde_json::json!({
+                    "reason": "room idle timeout reached",
+                    "idle": true
+                })),
+                is_sender: None,
+                seq: None,
+                msg_id: None,
+            });
+            if let Some(leaves) = self.remove_connection(&room_id, &connection_id) {
+                responses.extend(leaves);
+            }
+            closed.push((room_id, connection_id));
+        }
+
+        (responses, closed)
+    }
+
+    /// InferenceStats for every viewer and admin in rooms with recent inference results
+    pub fn inference_stats_messages(&mut self, now: chrono::DateTime<chrono::Utc>) -> Vec<SignalingMessage> {
+        self.aggregator.prune(now);
+
+        let mut responses = Vec::new();
+        for room_id in self.aggregator.active_rooms() {
+            let room = match self.rooms.get(&room_id) {
+                Some(room) => room,
+                None => continue,
+            };
+            let stats = self.aggregator.stats(&room_id, now);
+            for info in room.connections.values().filter(|info| info.role != Role::Sender) {
+                responses.push(SignalingMessage {
+                    message_type: SignalingMessageType::InferenceStats,
+                    connection_id: Some(info.id.clone()),
+                    source_sender_id: None,
+                    sender_id: None,
+                    offer_id: None,
+                    data: Some(stats.clone()),
+                    is_sender: None,
+                    seq: None,
+                    msg_id: None,
+                });
+            }
+        }
+        responses
+    }
+    /// Analytics for every viewer and admin in rooms whose line counts changed since the
+    /// previous call
+    pub fn analytics_messages(&mut self) -> Vec<SignalingMessage> {
+        let mut responses = Vec::new();
+        for room in self.rooms.values_mut() {
+            let Some(analytics) = room.counting.take_changed() else { continue };
+            for info in room.connections.values().filter(|info| info.role != Role::Sender) {
+                responses.push(SignalingMessage::new_analytics(info.id.clone(), analytics.clone()));
+            }
+        }
+        responses
+    }
+    /// One InferenceUpdate per peer with the latest result of every source that reported since
+    /// the previous call; intermediate results are superseded and never sent
+    pub fn take_inference_updates(&mut self) -> Vec<SignalingMessage> {
+        let mut responses = Vec::new();
+        for (room_id, sources) in self.pending_updates.drain() {
+            let (room, latest) = match (self.rooms.get(&room_id), self.inference_db.get(&room_id)) {
+                (Some(room), Some(latest)) => (room, latest),
+                _ => continue,
+            };
+            for source_id in sources {
+                responses.extend(inference_update_messages(room, &source_id, latest.get(&source_id)));
+                self.groups.publish(&room_id, SignalingMessageType::InferenceUpdate, &serde_json::json!({"source_sender_id": source_id, "latest": latest.get(&source_id)}));
+            }
+        }
+        responses
+    }
 }
 
+/// InferenceUpdate carrying a source's latest result to every connection in the room
+/// Connections with a SubscribeInference filter get only the detections they asked for, and
+/// nothing when none match
+fn inference_update_messages(room: &Room, source_id: &str, latest: Option<&Value>) -> Vec<SignalingMessage> {
+    let payload = serde_json::json!({
+        "source_sender_id": source_id,
+        "latest": latest
+    });
+    room.connections.values()
+        .filter_map(|info| {
+            let data = match (&info.inference_filter, latest) {
+                (Some(filter), Some(latest)) => serde_json::json!({
+                    "source_sender_id": source_id,
+                    "latest": filter.apply(latest)?
+                }),
+                _ => payload.clone(),
+            };
+            Some(SignalingMessage {
+                message_type: SignalingMessageType::InferenceUpdate,
+                connection_id: Some(info.id.clone()),
+                source_sender_id: None,
+                sender_id: None,
+                offer_id: None,
+                data: Some(data),
+                is_sender: None,
+                seq: None,
+                msg_id: None,
+            })
+        })
+        .collect()
+}
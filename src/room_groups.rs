@@ -0,0 +1,196 @@
+// room_groups.rs
+// Named sets of rooms (e.g. "building-A") for site-wide monitoring: admins manage them through
+// /api/admin/groups and dashboards subscribe to one with a WebSocket on /ws/groups/{id}, which
+// receives the NewPeer, Leave, SenderChanged and InferenceUpdate events of every member room
+// with a room_id added to their data. Groups are stored in SQLite and loaded at startup; a room
+// may be in any number of groups and need not exist yet. Tenant admins manage groups within
+// their tenant.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use ws2infer_protocol::{SignalingMessage, SignalingMessageType};
+
+use crate::persistence::{self, RoomGroupRecord};
+
+pub const MAX_GROUPS: usize = 1024;
+pub const MAX_ROOMS_PER_GROUP: usize = 256;
+
+/// Events a slow subscriber may fall behind by before it misses some
+const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RoomGroup {
+    pub group_id: String,
+    /// Room IDs (keys, with the tenant prefix for tenants' rooms)
+    pub rooms: Vec<String>,
+    /// RFC 3339
+    pub updated_at: String,
+}
+
+/// Body of PUT /api/admin/groups/{id}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PutRoomGroupRequest {
+    pub rooms: Vec<String>,
+}
+
+/// An event of a member room for the subscribers of one group
+#[derive(Debug, Clone)]
+pub struct GroupEvent {
+    pub group_id: String,
+    pub message: Arc<SignalingMessage>,
+}
+
+#[derive(Debug)]
+pub struct RoomGroups {
+    groups: HashMap<String, RoomGroup>,
+    /// Room key -> groups it is in
+    by_room: HashMap<String, BTreeSet<String>>,
+    events: broadcast::Sender<GroupEvent>,
+}
+
+impl Default for RoomGroups {
+    fn default() -> Self {
+        Self {
+            groups: HashMap::new(),
+            by_room: HashMap::new(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl RoomGroups {
+    /// Groups stored by a previous run; empty when the DB can't be read
+    pub fn load(db_path: &str) -> Self {
+        let mut groups = Self::default();
+        match persistence::load_room_groups(db_path) {
+            Ok(records) => {
+                if !records.is_empty() {
+                    info!("Loaded {} room group(s)", records.len());
+                }
+                for record in records {
+                    groups.insert(RoomGroup { group_id: record.group_id, rooms: record.rooms, updated_at: record.updated_at });
+                }
+            }
+            Err(e) => warn!("Failed to load room groups: {}", e),
+        }
+        groups
+    }
+
+    /// Groups whose ID starts with `prefix` (a tenant's), sorted by ID
+    pub fn list(&self, prefix: Option<&str>) -> Vec<RoomGroup> {
+        let mut groups: Vec<RoomGroup> = self.groups.values()
+            .filter(|group| prefix.is_none_or(|prefix| group.group_id.starts_with(prefix)))
+            .cloned()
+            .collect();
+        groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        groups
+    }
+
+    pub fn get(&self, group_id: &str) -> Option<&RoomGroup> {
+        self.groups.get(group_id)
+    }
+
+    /// Whether another group can be created
+    pub fn has_room_for(&self, group_id: &str) -> bool {
+        self.groups.contains_key(group_id) || self.groups.len() < MAX_GROUPS
+    }
+
+    /// Creates or replaces a group
+    pub fn insert(&mut self, group: RoomGroup) {
+        self.remove(&group.group_id);
+        for room_id in &group.rooms {
+            self.by_room.entry(room_id.clone()).or_default().insert(group.group_id.clone());
+        }
+        self.groups.insert(group.group_id.clone(), group);
+    }
+
+    pub fn remove(&mut self, group_id: &str) -> bool {
+        let Some(group) = self.groups.remove(group_id) else { return false };
+        for room_id in &group.rooms {
+            if let Some(groups) = self.by_room.get_mut(room_id) {
+                groups.remove(group_id);
+                if groups.is_empty() {
+                    self.by_room.remove(room_id);
+                }
+            }
+        }
+        true
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GroupEvent> {
+        self.events.subscribe()
+    }
+
+    /// Hands an event of `room_id` to the subscribers of every group the room is in, with
+    /// `data.room_id` set
+    pub fn publish(&self, room_id: &str, message_type: SignalingMessageType, data: &Value) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let Some(group_ids) = self.by_room.get(room_id) else { return };
+        let mut data = data.clone();
+        if let Some(fields) = data.as_object_mut() {
+            fields.insert("room_id".to_string(), room_id.into());
+        }
+        let message = Arc::new(SignalingMessage {
+            message_type,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        });
+        for group_id in group_ids {
+            // Fails only when every subscriber has just gone
+            let _ = self.events.send(GroupEvent { group_id: group_id.clone(), message: message.clone() });
+        }
+    }
+}
+
+/// Room keys of a PUT request, deduplicated in their order; `resolve` maps an ID given by the
+/// caller to a room key, or None when it belongs to another tenant
+pub fn room_keys(rooms: &[String], resolve: impl Fn(&str) -> Option<String>) -> Result<Vec<String>, String> {
+    if rooms.len() > MAX_ROOMS_PER_GROUP {
+        return Err(format!("a group may have at most {} rooms", MAX_ROOMS_PER_GROUP));
+    }
+    let mut seen = BTreeSet::new();
+    let mut keys = Vec::new();
+    for room_id in rooms {
+        if !crate::tenant::is_valid_room_key(room_id) {
+            return Err(format!("invalid room_id {}", room_id));
+        }
+        let key = resolve(room_id).ok_or_else(|| format!("{} belongs to another tenant", room_id))?;
+        if seen.insert(key.clone()) {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+/// Stores a created or replaced group
+pub async fn save(db_path: &str, group: &RoomGroup) -> Result<(), String> {
+    let db_path = db_path.to_string();
+    let record = RoomGroupRecord { group_id: group.group_id.clone(), rooms: group.rooms.clone(), updated_at: group.updated_at.clone() };
+    tokio::task::spawn_blocking(move || persistence::save_room_group(&db_path, &record))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+pub async fn delete(db_path: &str, group_id: &str) -> Result<(), String> {
+    let db_path = db_path.to_string();
+    let group_id = group_id.to_string();
+    tokio::task::spawn_blocking(move || persistence::delete_room_group(&db_path, &group_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
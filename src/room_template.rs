@@ -0,0 +1,90 @@
+// room_template.rs
+// Named room profiles from config.json, so deployments with different cameras can share one
+// server: POST /api/rooms picks a template by name and may override any of its settings
+// inline. The resolved settings live on the room and are sent to clients in RoomInfo.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::ice_policy::IcePolicy;
+use crate::room::{RoomMode, RoomSettings};
+use crate::schedule::Schedule;
+
+/// Same limit as the `model` of inference results
+const MAX_MODEL_CHARS: usize = 128;
+
+/// Room settings that are all optional, so a template or a request only sets what it cares about
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RoomTemplate {
+    #[serde(default)]
+    pub mode: Option<RoomMode>,
+    #[serde(default)]
+    pub guest_session_secs: Option<u64>,
+    /// Closes the room's connections after this many seconds without signaling or inference
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    /// getUserMedia video constraints for the sender, replacing config.video_constraints
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    pub video_constraints: Option<Value>,
+    /// Viewers allowed at once; admins and the server's own peers don't count
+    #[serde(default)]
+    pub max_viewers: Option<usize>,
+    /// Starts a server-side recording as soon as the room is created
+    #[serde(default)]
+    pub recording: Option<bool>,
+    /// Tells clients in RoomInfo that they may encrypt media end to end
+    #[serde(default)]
+    pub e2ee: Option<bool>,
+    /// ICE candidate types forwarded between peers, e.g. "relay_only" to force TURN
+    #[serde(default)]
+    pub ice_policy: Option<IcePolicy>,
+    /// Model viewers should run, e.g. "coco-ssd"; sent to viewers in RoomInfo
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl RoomTemplate {
+    pub fn validate(&self) -> Result<(), String> {
+        if matches!(&self.video_constraints, Some(constraints) if !constraints.is_object()) {
+            return Err("video_constraints must be an object".to_string());
+        }
+        if self.max_viewers == Some(0) {
+            return Err("max_viewers must be greater than 0".to_string());
+        }
+        if self.guest_session_secs == Some(0) {
+            return Err("guest_session_secs must be greater than 0".to_string());
+        }
+        if self.idle_timeout_secs == Some(0) {
+            return Err("idle_timeout_secs must be greater than 0".to_string());
+        }
+        if let Some(model) = &self.model {
+            if model.is_empty() || model.chars().count() > MAX_MODEL_CHARS || model.chars().any(char::is_control) {
+                return Err(format!("model must be 1-{} characters without control characters", MAX_MODEL_CHARS));
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings of a new room: these values where set, otherwise the template's
+    pub fn resolve(&self, template_name: Option<String>, template: Option<&RoomTemplate>) -> RoomSettings {
+        let base = template.cloned().unwrap_or_default();
+        RoomSettings {
+            template: template_name,
+            mode: self.mode.or(base.mode).unwrap_or_default(),
+            guest_session_secs: self.guest_session_secs.or(base.guest_session_secs),
+            idle_timeout_secs: self.idle_timeout_secs.or(base.idle_timeout_secs),
+            // Given with the request, not by templates
+            schedule: Schedule::default(),
+            video_constraints: self.video_constraints.clone().or(base.video_constraints),
+            max_viewers: self.max_viewers.or(base.max_viewers),
+            recording: self.recording.or(base.recording).unwrap_or(false),
+            e2ee: self.e2ee.or(base.e2ee).unwrap_or(false),
+            ice_policy: self.ice_policy.or(base.ice_policy).unwrap_or_default(),
+            model: self.model.clone().or(base.model),
+            sender_token: None,
+            control_token: None,
+        }
+    }
+}
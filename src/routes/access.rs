@@ -0,0 +1,109 @@
+// routes/access.rs
+// Who may call the guarded routes. with_admin hands the handler the caller's bearer token, which
+// it checks against the room it acts on (or the whole server); a refusal is an AccessDenied
+// rejection, and recover turns every one of them into the same JSON error reply.
+
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::api;
+use crate::auth;
+use crate::config::SharedConfig;
+use crate::tenant;
+
+const ADMIN_REQUIRED: &str = "Admin token required";
+const ADMIN_OR_API_KEY_REQUIRED: &str = "Admin token or API key required";
+
+/// A request refused for lacking credentials
+#[derive(Debug)]
+pub struct AccessDenied {
+    status: StatusCode,
+    error: &'static str,
+}
+
+impl warp::reject::Reject for AccessDenied {}
+
+impl AccessDenied {
+    pub fn new(status: StatusCode, error: &'static str) -> Self {
+        Self { status, error }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// The caller's `Authorization` header with the config to check it against
+#[derive(Clone)]
+pub struct Admin {
+    authorization: Option<String>,
+    config: SharedConfig,
+}
+
+/// Extracts the caller's credentials; handlers decide what they must cover
+pub fn with_admin(config: SharedConfig) -> impl Filter<Extract = (Admin,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .map(move |authorization| Admin { authorization, config: config.clone() })
+}
+
+impl Admin {
+    /// Falls back to a `?token=` query parameter, for browsers opening WebSockets
+    pub fn or_token(mut self, token: Option<&String>) -> Self {
+        if self.authorization.is_none() {
+            self.authorization = token.map(|token| format!("Bearer {}", token));
+        }
+        self
+    }
+
+    pub fn authorization(&self) -> Option<&str> {
+        self.authorization.as_deref()
+    }
+
+    /// The global admin token. FORBIDDEN when none is configured.
+    pub async fn server(&self) -> Result<(), AccessDenied> {
+        let config = self.config.read().await;
+        let status = match config.admin_token.as_deref() {
+            Some(admin_token) if auth::bearer(self.authorization()).is_some_and(|token| auth::secret_eq(token, admin_token)) => return Ok(()),
+            Some(_) => StatusCode::UNAUTHORIZED,
+            None => StatusCode::FORBIDDEN,
+        };
+        Err(AccessDenied::new(status, ADMIN_REQUIRED))
+    }
+
+    /// The global admin token or one of a tenant's, see tenant::admin_scope
+    pub async fn scope(&self) -> Result<tenant::Scope, AccessDenied> {
+        let config = self.config.read().await;
+        tenant::admin_scope(self.authorization(), config.admin_token.as_deref(), &config.tenants)
+            .map_err(|status| AccessDenied::new(status, ADMIN_REQUIRED))
+    }
+
+    /// An admin token covering the room, see tenant::room_admin_rejection
+    pub async fn room(&self, room_key: &str) -> Result<(), AccessDenied> {
+        let config = self.config.read().await;
+        match tenant::room_admin_rejection(self.authorization(), config.admin_token.as_deref(), &config.tenants, room_key) {
+            Some(status) => Err(AccessDenied::new(status, ADMIN_REQUIRED)),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn is_room_admin(&self, room_key: &str) -> bool {
+        self.room(room_key).await.is_ok()
+    }
+
+    /// Access to the room's results and images, see tenant::stored_data_rejection
+    pub async fn stored_data(&self, room_key: &str) -> Result<(), AccessDenied> {
+        let config = self.config.read().await;
+        match tenant::stored_data_rejection(self.authorization(), config.admin_token.as_deref(), &config.tenants, room_key) {
+            Some(status) => Err(AccessDenied::new(status, ADMIN_OR_API_KEY_REQUIRED)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Replies to AccessDenied rejections; any other rejection passes on to the next route
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<AccessDenied>() {
+        Some(denied) => Ok(warp::reply::with_status(warp::reply::json(&api::ErrorResponse::new(denied.error)), denied.status)),
+        None => Err(rejection),
+    }
+}
@@ -0,0 +1,358 @@
+// routes/admin.rs
+// /api/admin: TURN usage, the audit log and connection registry, recordings, room groups and
+// WASM plugins. Tenant admin tokens see the audit log, connections and groups of their own
+// tenant; the rest needs the global admin token.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+use warp::http::{Method, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+use super::access::{with_admin, Admin};
+use super::{with, Context};
+use crate::room::RoomManager;
+use crate::state::SharedState;
+use crate::turn::{TurnServer, TurnStats};
+use crate::wasm_plugins::{self, WasmPlugins};
+use crate::{api, audit, connection_log, persistence, proxy, recorder, room_groups, tenant, Clients};
+
+pub fn routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    turn_usage(context)
+        .or(turn_stats(context))
+        .or(audit_log(context))
+        .or(connections(context))
+        .or(recordings(context))
+        .or(group_put(context))
+        .or(groups(context))
+        .or(plugin_upload(context))
+        .or(plugins(context))
+}
+
+fn admin_base() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path("api").and(warp::path("admin"))
+}
+
+/// GET /api/admin/turn/usage: bytes relayed per allocation and per client IP
+fn turn_usage(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("turn"))
+        .and(warp::path("usage"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.turn_server.clone()))
+        .and_then(|admin: Admin, turn_server: Option<Arc<TurnServer>>| async move {
+            admin.server().await?;
+            match turn_server {
+                Some(server) => Ok::<_, Rejection>(warp::reply::json(&server.usage())),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// GET /api/admin/turn: relay allocations and bytes, per client IP, against connected peers
+fn turn_stats(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("turn"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.turn_server.as_ref().map(|server| server.stats())))
+        .and(with(context.clients.clone()))
+        .and_then(|admin: Admin, stats: Option<TurnStats>, clients: Clients| async move {
+            admin.server().await?;
+            let stats = stats.ok_or_else(warp::reject::not_found)?;
+            let mut body = stats.snapshot();
+            // Peers beyond the ones holding an allocation reach each other directly (or over STUN)
+            body["peers_connected"] = serde_json::json!(clients.read().await.len());
+            Ok::<_, Rejection>(warp::reply::json(&body))
+        })
+}
+
+/// GET /api/admin/audit?action=&room_id=&since=<RFC 3339>&limit=: audit log, newest first
+fn audit_log(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("audit"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with(context.state.db_path()))
+        .and_then(|admin: Admin, query: HashMap<String, String>, db_path: String| async move {
+            let scope = admin.scope().await?;
+            if query.get("room_id").is_some_and(|room_id| !scope.covers(room_id)) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new("room_id belongs to another tenant")),
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+            let since = match query.get("since").map(|v| chrono::DateTime::parse_from_rfc3339(v)) {
+                Some(Ok(since)) => Some(since.with_timezone(&chrono::Utc)),
+                Some(Err(_)) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "since must be an RFC 3339 time"})),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                None => None,
+            };
+            let limit = query.get("limit")
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(100)
+                .clamp(1, audit::MAX_LIST_LIMIT);
+            let action = query.get("action").cloned();
+            let room_id = query.get("room_id").cloned();
+            let room_prefix = scope.room_prefix();
+            let listed = tokio::task::spawn_blocking(move || {
+                persistence::list_audit(&db_path, action.as_deref(), room_id.as_deref(), room_prefix.as_deref(), since, limit)
+            }).await;
+            match listed {
+                Ok(Ok(records)) => Ok(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK)),
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        })
+}
+
+/// GET /api/admin/connections?room_id=&connection_id=&ip=&since=&until=&open=&limit=: connection
+/// registry, newest join first
+fn connections(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("connections"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with(context.state.db_path()))
+        .and_then(|admin: Admin, query: HashMap<String, String>, db_path: String| async move {
+            let scope = admin.scope().await?;
+            if query.get("room_id").is_some_and(|room_id| !scope.covers(room_id)) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new("room_id belongs to another tenant")),
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+            let mut times = [None, None];
+            for (name, time) in ["since", "until"].into_iter().zip(times.iter_mut()) {
+                match query.get(name).map(|v| chrono::DateTime::parse_from_rfc3339(v)) {
+                    Some(Ok(parsed)) => *time = Some(parsed.with_timezone(&chrono::Utc)),
+                    Some(Err(_)) => {
+                        return Ok(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": format!("{} must be an RFC 3339 time", name)})),
+                            StatusCode::BAD_REQUEST,
+                        ));
+                    }
+                    None => {}
+                }
+            }
+            let limit = query.get("limit")
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(100)
+                .clamp(1, connection_log::MAX_LIST_LIMIT);
+            let filter = persistence::ConnectionQuery {
+                room_id: query.get("room_id").cloned(),
+                room_prefix: scope.room_prefix(),
+                connection_id: query.get("connection_id").cloned(),
+                ip: query.get("ip").cloned(),
+                since: times[0],
+                until: times[1],
+                open: query.get("open").is_some_and(|v| v == "true"),
+            };
+            let listed = tokio::task::spawn_blocking(move || {
+                persistence::list_connections(&db_path, &filter, limit)
+            }).await;
+            match listed {
+                Ok(Ok(records)) => Ok(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK)),
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        })
+}
+
+/// GET /api/admin/recordings[/{id}]: running and recently finished recordings;
+/// DELETE /api/admin/recordings/{id} stops one
+fn recordings(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("recordings"))
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.recordings.clone()))
+        .and_then(|tail: warp::path::Tail, method: Method, admin: Admin, recordings: recorder::SharedRecordings| async move {
+            admin.server().await?;
+            let recording_id = tail.as_str().trim_end_matches('/');
+            let mut recordings = recordings.write().await;
+            match (method, recording_id) {
+                (Method::GET, "") => {
+                    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&recordings.list()), StatusCode::OK))
+                }
+                (Method::GET, id) if !id.contains('/') => match recordings.get(id) {
+                    Some(info) => Ok(warp::reply::with_status(warp::reply::json(&info), StatusCode::OK)),
+                    None => Err(warp::reject::not_found()),
+                },
+                (Method::DELETE, id) if !id.is_empty() && !id.contains('/') => {
+                    match (recordings.get(id), recordings.stop(id)) {
+                        (Some(info), true) => Ok(warp::reply::with_status(warp::reply::json(&info), StatusCode::ACCEPTED)),
+                        (Some(info), false) => Ok(warp::reply::with_status(warp::reply::json(&info), StatusCode::CONFLICT)),
+                        (None, _) => Err(warp::reject::not_found()),
+                    }
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// GET /api/admin/groups[/{id}]: room groups; DELETE /api/admin/groups/{id} removes one
+fn groups(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("groups"))
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|tail: warp::path::Tail, method: Method, admin: Admin, db_path: String, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let scope = admin.scope().await?;
+            match (method, tail.as_str().trim_end_matches('/')) {
+                (Method::GET, "") => {
+                    let groups = room_manager.read().await.groups.list(scope.room_prefix().as_deref());
+                    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&groups), StatusCode::OK))
+                }
+                (Method::GET, id) if !id.contains('/') => {
+                    let group_id = scope.resolve(id).ok_or_else(warp::reject::not_found)?;
+                    match room_manager.read().await.groups.get(&group_id) {
+                        Some(group) => Ok(warp::reply::with_status(warp::reply::json(group), StatusCode::OK)),
+                        None => Err(warp::reject::not_found()),
+                    }
+                }
+                (Method::DELETE, id) if !id.is_empty() && !id.contains('/') => {
+                    let group_id = scope.resolve(id).ok_or_else(warp::reject::not_found)?;
+                    if !room_manager.write().await.groups.remove(&group_id) {
+                        return Err(warp::reject::not_found());
+                    }
+                    if let Err(e) = room_groups::delete(&db_path, &group_id).await {
+                        error!("Failed to delete room group {}: {}", group_id, e);
+                    }
+                    Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"removed": group_id})), StatusCode::OK))
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// PUT /api/admin/groups/{id}: creates a room group or replaces its rooms
+fn group_put(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("groups"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|id: String, req: room_groups::PutRoomGroupRequest, admin: Admin, db_path: String, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let scope = admin.scope().await?;
+            let group_id = match scope.resolve(&id).filter(|_| tenant::is_valid_room_key(&id)) {
+                Some(group_id) => group_id,
+                None => {
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "Invalid group ID"})),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+            };
+            let rooms = match room_groups::room_keys(&req.rooms, |room_id| scope.resolve(room_id)) {
+                Ok(rooms) => rooms,
+                Err(e) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e})),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+            };
+            let group = room_groups::RoomGroup { group_id, rooms, updated_at: chrono::Utc::now().to_rfc3339() };
+            {
+                let mut manager = room_manager.write().await;
+                if !manager.groups.has_room_for(&group.group_id) {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": format!("At most {} groups", room_groups::MAX_GROUPS)})),
+                        StatusCode::TOO_MANY_REQUESTS,
+                    ));
+                }
+                manager.groups.insert(group.clone());
+            }
+            if let Err(e) = room_groups::save(&db_path, &group).await {
+                error!("Failed to store room group {}: {}", group.group_id, e);
+            }
+            Ok(warp::reply::with_status(warp::reply::json(&group), StatusCode::OK))
+        })
+}
+
+/// GET /api/admin/plugins: installed WASM plugins; DELETE /api/admin/plugins/{name} unloads one
+fn plugins(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("plugins"))
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(proxy::client(&context.state))
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.wasm_plugins.clone()))
+        .and_then(|tail: warp::path::Tail, method: Method, client_ip: Option<IpAddr>, admin: Admin, state: SharedState, plugins: Option<Arc<WasmPlugins>>| async move {
+            admin.server().await?;
+            let Some(plugins) = plugins else { return Err(warp::reject::not_found()) };
+            match (method, tail.as_str().trim_end_matches('/')) {
+                (Method::GET, "") => Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&plugins.list()), StatusCode::OK)),
+                (Method::DELETE, name) if !name.is_empty() && !name.contains('/') => {
+                    if !plugins.remove(name) {
+                        return Err(warp::reject::not_found());
+                    }
+                    audit::record(&state, audit::PLUGIN_REMOVED, "admin", client_ip, None, serde_json::json!({"name": name}));
+                    Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"removed": name})), StatusCode::OK))
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// PUT /api/admin/plugins/{name}: installs or replaces a plugin from the WASM module in the body
+fn plugin_upload(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    admin_base()
+        .and(warp::path("plugins"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::content_length_limit(wasm_plugins::MAX_MODULE_BYTES))
+        .and(warp::body::bytes())
+        .and(proxy::client(&context.state))
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.wasm_plugins.clone()))
+        .and_then(|name: String, body: bytes::Bytes, client_ip: Option<IpAddr>, admin: Admin, state: SharedState, plugins: Option<Arc<WasmPlugins>>| async move {
+            admin.server().await?;
+            let Some(plugins) = plugins else { return Err(warp::reject::not_found()) };
+            // Compiling takes a while for large modules
+            let installing_name = name.clone();
+            let installed = tokio::task::spawn_blocking(move || plugins.install(&installing_name, &body)).await;
+            match installed {
+                Ok(Ok(info)) => {
+                    info!("WASM plugin {} installed ({})", info.name, info.hooks.join(", "));
+                    audit::record(&state, audit::PLUGIN_INSTALLED, "admin", client_ip, None, serde_json::json!({"name": info.name, "hooks": info.hooks, "size_bytes": info.size_bytes}));
+                    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&info), StatusCode::OK))
+                }
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e})), StatusCode::BAD_REQUEST)),
+                Err(e) => {
+                    error!("Installing WASM plugin {} failed: {}", name, e);
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "Failed to install the plugin"})),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ))
+                }
+            }
+        })
+}
@@ -0,0 +1,348 @@
+// routes/data.rs
+// What rooms produced: live inference statistics and line counts from memory, and stored
+// results, detections, summaries and snapshots from the database. Stored data outlives the
+// in-memory room, so those routes don't need the room to exist any more.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use super::access::{with_admin, AccessDenied, Admin};
+use super::rooms::rooms_base;
+use super::{with, Context};
+use crate::config::SharedConfig;
+use crate::room::RoomManager;
+use crate::{auth, export, http_cache, persistence, snapshot, tenant};
+
+pub fn routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    analytics(context)
+        .or(inference_stats(context))
+        .or(inference_latest(context))
+        .or(inference_export(context))
+        .or(detections(context))
+        .or(summary(context))
+        .or(snapshot_upload(context))
+        .or(snapshot_list(context))
+        .or(snapshot_get(context))
+}
+
+/// GET /api/rooms/{id}/analytics: crossing counts per counting line and class
+fn analytics(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("analytics"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.stored_data(&room_id).await?;
+            let manager = room_manager.read().await;
+            let room = manager.rooms.get(&room_id).ok_or_else(warp::reject::not_found)?;
+            Ok::<_, Rejection>(warp::reply::json(&room.counting.snapshot()))
+        })
+}
+
+/// GET /api/rooms/{id}/inference/stats: rolling per-source statistics
+fn inference_stats(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("inference"))
+        .and(warp::path("stats"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.stored_data(&room_id).await?;
+            let manager = room_manager.read().await;
+            if !manager.rooms.contains_key(&room_id) {
+                return Err(warp::reject::not_found());
+            }
+            Ok::<_, Rejection>(warp::reply::json(&manager.aggregator.stats(&room_id, chrono::Utc::now())))
+        })
+}
+
+/// GET /api/rooms/{id}/inference/latest?source_id=: the newest result per source from memory,
+/// for HTTP clients (e.g. dashboards) that poll instead of holding a WebSocket
+fn inference_latest(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("inference"))
+        .and(warp::path("latest"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.stored_data(&room_id).await?;
+            let manager = room_manager.read().await;
+            if !manager.rooms.contains_key(&room_id) {
+                return Err(warp::reject::not_found());
+            }
+            let latest = manager.inference_db.get(&room_id);
+            let reply = match query.get("source_id") {
+                Some(source_id) => match latest.and_then(|sources| sources.get(source_id)) {
+                    Some(result) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"room_id": room_id, "source_id": source_id, "result": result})),
+                        StatusCode::OK,
+                    ),
+                    None => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "No inference result from this source yet"})),
+                        StatusCode::NOT_FOUND,
+                    ),
+                },
+                None => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"room_id": room_id, "sources": latest.cloned().unwrap_or_default()})),
+                    StatusCode::OK,
+                ),
+            };
+            Ok::<_, Rejection>(reply)
+        })
+}
+
+/// GET /api/rooms/{id}/inference/export?format=csv|parquet|jsonl: stored results for analysis
+fn inference_export(context: &Context) -> BoxedFilter<(Response,)> {
+    let route = rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("inference"))
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, db_path: String| async move {
+            admin.stored_data(&room_id).await?;
+            let format = match export::ExportFormat::parse(query.get("format").map(String::as_str).unwrap_or("csv")) {
+                Some(format) => format,
+                None => {
+                    let response = warp::http::Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .header("content-type", "application/json")
+                        .body(warp::hyper::Body::from(r#"{"error":"format must be csv, parquet or jsonl"}"#));
+                    return Ok::<_, Rejection>(response.unwrap());
+                }
+            };
+            // Path params aren't restricted to valid room IDs; keep the header well-formed
+            let stem = if tenant::is_valid_room_key(&room_id) { room_id.replace(tenant::SEPARATOR, "_") } else { "inference".to_string() };
+            let filename = format!("{}.{}", stem, format.extension());
+            let response = warp::http::Response::builder()
+                .header("content-type", format.content_type())
+                .header("content-disposition", format!("attachment; filename=\"{}\"", filename))
+                .body(export::body(db_path, room_id, format));
+            Ok(response.unwrap())
+        });
+    // Exports are large and compress well (apart from Parquet, which is compressed already)
+    http_cache::compressed(route)
+}
+
+/// GET /api/rooms/{id}/detections?class=&min_score=&since=<RFC 3339>&limit=: stored detections,
+/// newest first, from the indexed detections table
+fn detections(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("detections"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, db_path: String| async move {
+            let bad_request = |error: &str| {
+                Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": error})), StatusCode::BAD_REQUEST))
+            };
+            admin.stored_data(&room_id).await?;
+            let since = match query.get("since").map(|v| chrono::DateTime::parse_from_rfc3339(v)) {
+                Some(Ok(since)) => Some(since.with_timezone(&chrono::Utc)),
+                Some(Err(_)) => return bad_request("since must be an RFC 3339 time"),
+                None => None,
+            };
+            let min_score = match query.get("min_score").map(|v| v.parse::<f64>()) {
+                Some(Ok(score)) if (0.0..=1.0).contains(&score) => Some(score),
+                Some(_) => return bad_request("min_score must be between 0 and 1"),
+                None => None,
+            };
+            let limit = query.get("limit")
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(100)
+                .clamp(1, 1000);
+            let class = query.get("class").cloned();
+            let listed = tokio::task::spawn_blocking(move || {
+                persistence::list_detections(&db_path, &room_id, class.as_deref(), min_score, since, limit)
+            }).await;
+            match listed {
+                Ok(Ok(records)) => Ok(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK)),
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        })
+}
+
+/// GET /api/rooms/{id}/summary?resolution=minute|hour&class=&since=&until=&limit=: per-minute
+/// or per-hour detection summaries (downsampling.rs)
+fn summary(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("summary"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, db_path: String| async move {
+            let bad_request = |error: &str| {
+                Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": error})), StatusCode::BAD_REQUEST))
+            };
+            admin.stored_data(&room_id).await?;
+            let resolution = match persistence::Resolution::parse(query.get("resolution").map(String::as_str).unwrap_or("hour")) {
+                Some(resolution) => resolution,
+                None => return bad_request("resolution must be minute or hour"),
+            };
+            let mut range = [None, None];
+            for (bound, name) in range.iter_mut().zip(["since", "until"]) {
+                *bound = match query.get(name).map(|v| chrono::DateTime::parse_from_rfc3339(v)) {
+                    Some(Ok(time)) => Some(time.with_timezone(&chrono::Utc)),
+                    Some(Err(_)) => return bad_request(&format!("{} must be an RFC 3339 time", name)),
+                    None => None,
+                };
+            }
+            let limit = query.get("limit")
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(100)
+                .clamp(1, 1000);
+            let class = query.get("class").cloned();
+            let [since, until] = range;
+            let listed = tokio::task::spawn_blocking(move || {
+                persistence::list_summaries(&db_path, &room_id, resolution, class.as_deref(), since, until, limit)
+            }).await;
+            match listed {
+                Ok(Ok(records)) => Ok(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK)),
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        })
+}
+
+/// POST /api/rooms/{id}/snapshot: a still frame from one of the room's senders, as
+/// multipart/form-data or JSON with a base64 image. Connection IDs are public, so the upload
+/// must carry the room's sender_token (or an admin token) as a bearer token.
+fn snapshot_upload(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let upload = warp::multipart::form()
+        .max_length(snapshot::MAX_IMAGE_BYTES + 64 * 1024)
+        .then(snapshot::SnapshotUpload::from_multipart)
+        .or(warp::body::content_length_limit(snapshot::MAX_IMAGE_BYTES * 4 / 3 + 64 * 1024)
+            .and(warp::body::json())
+            .map(snapshot::SnapshotUpload::from_json))
+        .unify();
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("snapshot"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_admin(context.config.clone()))
+        .and(upload)
+        .and(with(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, admin: Admin, upload: Result<snapshot::SnapshotUpload, String>, config: SharedConfig, room_manager: Arc<RwLock<RoomManager>>, db_path: String| async move {
+            let error = |message: String, status: StatusCode| {
+                Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": message})), status))
+            };
+            let upload = match upload {
+                Ok(upload) => upload,
+                Err(e) => return error(e, StatusCode::BAD_REQUEST),
+            };
+            if !tenant::is_valid_room_key(&room_id) {
+                return error("Invalid room_id".to_string(), StatusCode::BAD_REQUEST);
+            }
+            let admin_check = admin.room(&room_id).await;
+            {
+                let manager = room_manager.read().await;
+                let room = manager.rooms.get(&room_id).ok_or_else(warp::reject::not_found)?;
+                let sender = matches!(
+                    (room.sender_token.as_deref(), auth::bearer(admin.authorization())),
+                    (Some(expected), Some(token)) if auth::secret_eq(token, expected)
+                );
+                if let (false, Err(denied)) = (sender, admin_check) {
+                    return Err(AccessDenied::new(denied.status(), "Sender token required").into());
+                }
+                if !room.connections.get(&upload.source_id).is_some_and(|connection| connection.is_sender) {
+                    return error("source_id is not a sender in this room".to_string(), StatusCode::FORBIDDEN);
+                }
+            }
+
+            let snapshot_config = config.read().await.snapshots.clone();
+            let stored = tokio::task::spawn_blocking(move || {
+                snapshot::store(&snapshot_config, &db_path, &room_id, upload)
+            }).await;
+            match stored {
+                Ok(Ok(record)) => Ok(warp::reply::with_status(warp::reply::json(&record), StatusCode::CREATED)),
+                Ok(Err(e)) => error(e, StatusCode::INTERNAL_SERVER_ERROR),
+                Err(e) => error(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
+
+/// GET /api/rooms/{id}/snapshots?source_id=&before=<ms>&limit=: newest first; `before` set to
+/// a detection's timestamp with limit=1 finds the frame it was made on
+fn snapshot_list(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("snapshots"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, db_path: String| async move {
+            admin.stored_data(&room_id).await?;
+            let before = query.get("before").and_then(|v| v.parse::<i64>().ok());
+            let limit = query.get("limit")
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(20)
+                .clamp(1, snapshot::MAX_LIST_LIMIT);
+            let source_id = query.get("source_id").cloned();
+            let listed = tokio::task::spawn_blocking(move || {
+                persistence::list_snapshots(&db_path, &room_id, source_id.as_deref(), before, limit)
+            }).await;
+            match listed {
+                Ok(Ok(records)) => Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&records), StatusCode::OK)),
+                Ok(Err(e)) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), StatusCode::INTERNAL_SERVER_ERROR)),
+            }
+        })
+}
+
+/// GET /api/rooms/{id}/snapshots/{snapshot_id}: the image itself
+fn snapshot_get(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("snapshots"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.db_path()))
+        .and_then(|room_id: String, snapshot_id: String, admin: Admin, db_path: String| async move {
+            admin.stored_data(&room_id).await?;
+            let record = tokio::task::spawn_blocking(move || {
+                persistence::get_snapshot(&db_path, &room_id, &snapshot_id)
+            }).await;
+            let record = match record {
+                Ok(Ok(Some(record))) => record,
+                _ => return Err(warp::reject::not_found()),
+            };
+            let image = tokio::fs::read(&record.path).await.map_err(|_| warp::reject::not_found())?;
+            let response = warp::http::Response::builder()
+                .header("content-type", record.content_type)
+                .header("cache-control", "private, max-age=86400")
+                .body(warp::hyper::Body::from(image));
+            Ok::<_, Rejection>(response.unwrap())
+        })
+}
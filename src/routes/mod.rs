@@ -0,0 +1,69 @@
+// routes/mod.rs
+// The HTTP side of the server, grouped by what the routes serve: signaling sessions over
+// WebSocket and long-polling (sessions.rs), rooms and their settings (rooms.rs), live and stored
+// room data (data.rs), the admin API (admin.rs) and server-wide endpoints such as the client
+// config, metrics and HLS (server.rs). access.rs decides who may call the guarded ones.
+
+mod access;
+mod admin;
+mod data;
+mod rooms;
+mod server;
+mod sessions;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::cluster::ClusterConfig;
+use crate::config::SharedConfig;
+use crate::room::RoomManager;
+use crate::server_inference::ServerInference;
+use crate::state::SharedState;
+use crate::stun::StunStats;
+use crate::turn::TurnServer;
+use crate::wasm_plugins::WasmPlugins;
+use crate::{audit, ip_filter, poll, recorder, Clients};
+
+/// What the routes need from the rest of the server
+#[derive(Clone)]
+pub struct Context {
+    pub state: SharedState,
+    pub config: SharedConfig,
+    pub room_manager: Arc<RwLock<RoomManager>>,
+    pub clients: Clients,
+    pub server_inference: Option<Arc<ServerInference>>,
+    pub cluster: Option<Arc<ClusterConfig>>,
+    pub poll_sessions: poll::PollSessions,
+    /// Server-side recordings, started by the record endpoints or by rooms created with recording on
+    pub recordings: recorder::SharedRecordings,
+    pub turn_server: Option<Arc<TurnServer>>,
+    pub wasm_plugins: Option<Arc<WasmPlugins>>,
+    pub stun_stats: StunStats,
+}
+
+/// Hands each request its own clone of `value`
+fn with<T: Clone + Send>(value: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
+    warp::any().map(move || value.clone())
+}
+
+/// Every route behind the IP filter, with CORS; requests none of them take go to `static_files`
+pub fn all(context: &Context, static_files: BoxedFilter<(Response,)>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // Boxed in groups so the combined filter's type stays within the compiler's query depth
+    let api_routes = rooms::routes(context).boxed()
+        .or(data::routes(context).boxed())
+        .or(admin::routes(context).boxed())
+        .or(server::api_routes(context).boxed())
+        .recover(access::recover)
+        .with(warp::log::custom(audit::http_auth_failures(context.state.clone())));
+
+    ip_filter::refuse(&context.state)
+        .or(sessions::routes(context).boxed())
+        .or(api_routes)
+        .or(server::routes(context).boxed())
+        .or(static_files)
+        .with(warp::cors().allow_any_origin().allow_methods(vec!["GET", "POST", "PUT", "DELETE"]))
+}
@@ -0,0 +1,724 @@
+// routes/rooms.rs
+// /api/rooms: creating, inspecting and closing rooms, and the settings room admins change while
+// a room runs (calibration, zones, counting lines, replays, recordings, guest passes).
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use super::access::{with_admin, Admin};
+use super::{with, Context};
+use crate::calibration::CalibrationCurve;
+use crate::cluster::ClusterConfig;
+use crate::config::SharedConfig;
+use crate::room::{self, RoomManager};
+use crate::signaling::SignalingMessage;
+use crate::state::SharedState;
+use crate::{api, audit, counting, proxy, qr, recorder, replay, sfu, tenant, webhooks, zones, Clients};
+
+pub fn routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    create_room(context)
+        .or(get_room(context))
+        .or(capabilities(context))
+        .or(guest_pass(context))
+        .or(put_room(context))
+        .or(delete_room(context))
+        .or(calibration(context))
+        .or(zones_get(context))
+        .or(zones_put(context))
+        .or(lines_get(context))
+        .or(lines_put(context))
+        .or(replay_start(context))
+        .or(replay_stop(context))
+        .or(peer_stats(context))
+        .or(qr(context))
+        .or(start_recording(context))
+        .or(stop_recording(context))
+}
+
+/// /api/rooms
+pub fn rooms_base() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path("api").and(warp::path("rooms"))
+}
+
+/// POST /api/rooms
+fn create_room(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and(with(context.cluster.clone()))
+        .and(with(context.config.clone()))
+        .and(with(context.recordings.clone()))
+        .and(proxy::client(&context.state))
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(|req: api::CreateRoomRequest, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients, cluster: Option<Arc<ClusterConfig>>, config: SharedConfig, recordings: recorder::SharedRecordings, client_ip: Option<IpAddr>, authorization: Option<String>| async move {
+            let tenants = config.read().await.tenants.clone();
+            let creator = match tenant::authorize_creator(&state, authorization.as_deref(), &tenants).await {
+                Ok(creator) => creator,
+                Err(refusal) => {
+                    audit::record(&state, audit::AUTH_FAILED, "api", client_ip, req.room_id.as_deref(), serde_json::json!({"error": refusal.message}));
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new(refusal.message)),
+                        refusal.status,
+                    ));
+                }
+            };
+            let actor = creator.actor;
+            let (template, recording_config) = {
+                let config = config.read().await;
+                (req.template.as_ref().map(|name| config.room_templates.get(name).cloned()), config.recording.clone())
+            };
+            let template = match template {
+                Some(None) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new("Unknown template")),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                Some(Some(template)) => Some(template),
+                None => None,
+            };
+            if let Err(e) = req.settings.validate().and_then(|_| req.schedule.validate(chrono::Utc::now())) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new(e)),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            let mut settings = req.settings.resolve(req.template, template.as_ref());
+            settings.sender_token = Some(room::new_room_token());
+            settings.control_token = Some(room::new_room_token());
+            settings.schedule = req.schedule;
+
+            let requested = match req.room_id {
+                Some(room_id) if !tenant::is_valid_room_key(&room_id) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new("Invalid room_id")),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                Some(room_id) => match tenant::resolve(creator.tenant.as_deref(), &room_id) {
+                    Some(room_key) => Some(room_key),
+                    None => {
+                        return Ok(warp::reply::with_status(
+                            warp::reply::json(&api::ErrorResponse::new("room_id belongs to another tenant")),
+                            StatusCode::FORBIDDEN,
+                        ));
+                    }
+                },
+                None => None,
+            };
+            let room_id = match (requested, &cluster) {
+                (Some(room_id), Some(cluster)) if !cluster.is_local(&room_id) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::elsewhere(cluster.room_api_url(&room_id))),
+                        StatusCode::MISDIRECTED_REQUEST,
+                    ));
+                }
+                (Some(room_id), _) => {
+                    // A room announced by another instance counts as existing
+                    if let Some(backplane) = state.backplane() {
+                        backplane.prepare_join(&room_id).await;
+                    }
+                    room_id
+                }
+                (None, Some(cluster)) => cluster.new_room_id(creator.tenant.as_deref()),
+                (None, None) => tenant::room_key(creator.tenant.as_deref(), &Uuid::new_v4().to_string()),
+            };
+
+            // Check and insert under one write lock so concurrent requests for the same ID
+            // create the room once; the existing room keeps its settings
+            let mut manager = room_manager.write().await;
+            if !manager.rooms.contains_key(&room_id) {
+                if let Err(exceeded) = manager.quotas.check_new_room(&room_id, &manager.rooms) {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::quota(exceeded)),
+                        StatusCode::TOO_MANY_REQUESTS,
+                    ));
+                }
+            }
+            if !manager.create_room_if_absent(room_id.clone()) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&api::RoomResponse { room_id, created: false, sender_token: None, control_token: None }),
+                    StatusCode::OK,
+                ));
+            }
+            if let Some(room) = manager.rooms.get_mut(&room_id) {
+                room.apply_settings(&settings);
+            }
+            drop(manager);
+            audit::record(
+                &state,
+                audit::ROOM_CREATED,
+                &actor,
+                client_ip,
+                Some(&room_id),
+                serde_json::json!({"template": settings.template, "mode": settings.mode, "recording": settings.recording, "e2ee": settings.e2ee, "ice_policy": settings.ice_policy, "schedule": settings.schedule}),
+            );
+            state.webhooks.emit(
+                webhooks::ROOM_CREATED,
+                &room_id,
+                serde_json::json!({"template": settings.template, "mode": settings.mode, "recording": settings.recording}),
+            );
+
+            if let Some(backplane) = state.backplane() {
+                backplane.announce_room(&room_id, &settings).await;
+            }
+
+            if settings.mode == room::RoomMode::Sfu {
+                sfu::spawn(state.clone(), room_id.clone(), room_manager.clone(), clients.clone());
+            }
+            if settings.recording {
+                if let Err(recorder::StartError::Failed(e)) = recorder::start(&recordings, &recording_config, state.clone(), room_id.clone(), room_manager.clone(), clients).await {
+                    error!("Recording of room {} could not start: {}", room_id, e);
+                }
+            }
+
+            let response = api::RoomResponse {
+                room_id,
+                created: true,
+                sender_token: settings.sender_token.clone(),
+                control_token: settings.control_token.clone(),
+            };
+
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK))
+        })
+}
+
+/// GET /api/rooms/{id}: connection counts per role, offers and inference activity for
+/// dashboards; the peers themselves only for room admins
+fn get_room(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let with_peers = admin.is_room_admin(&room_id).await;
+            let manager = room_manager.read().await;
+            match manager.rooms.get(&room_id) {
+                Some(room) => Ok::<_, Rejection>(warp::reply::json(&room.status(manager.inference_db.get(&room_id), manager.hls.viewer_count(&room_id), with_peers))),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// GET /api/rooms/{id}/capabilities: mode, sender presence, viewer count and whether
+/// recording or the SFU are running, so viewer pages can show "camera offline" before joining
+fn capabilities(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("capabilities"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with(context.room_manager.clone()))
+        .and(with(context.recordings.clone()))
+        .and_then(|room_id: String, room_manager: Arc<RwLock<RoomManager>>, recordings: recorder::SharedRecordings| async move {
+            let recording = recordings.read().await.active_for_room(&room_id).is_some();
+            let manager = room_manager.read().await;
+            match manager.rooms.get(&room_id) {
+                Some(room) => Ok::<_, Rejection>(warp::reply::json(&room.capabilities(recording))),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// POST /api/rooms/{id}/guest-passes: time-boxed viewer sessions. Room admins may issue them for
+/// any room and choose their length; anyone may get one for a room with guest_session_secs.
+fn guest_pass(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("guest-passes"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_admin(context.config.clone()))
+        .and(proxy::client(&context.state))
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, client_ip: Option<IpAddr>, request: api::GuestPassRequest, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let admin = admin.is_room_admin(&room_id).await;
+            let mut manager = room_manager.write().await;
+            let default_secs = manager.guest_session_secs;
+            let room = manager.rooms.get_mut(&room_id).ok_or_else(warp::reject::not_found)?;
+            let issued = match (admin, room.guest_session_secs, request.session_secs) {
+                (true, _, Some(0)) => Err((StatusCode::BAD_REQUEST, "session_secs must be greater than 0")),
+                (true, room_secs, secs) => Ok((secs.or(room_secs).unwrap_or(default_secs), None)),
+                (false, Some(secs), None) => Ok((secs, client_ip)),
+                (false, Some(_), Some(_)) => Err((StatusCode::FORBIDDEN, "Only room admins choose session_secs")),
+                (false, None, _) => Err((StatusCode::FORBIDDEN, "Room has no guest sessions")),
+            };
+            let (secs, issued_to) = match issued {
+                Ok(issued) => issued,
+                Err((status, error)) => {
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new(error)),
+                        status,
+                    ));
+                }
+            };
+            match room.issue_guest_pass(secs, issued_to, chrono::Utc::now()) {
+                Some((token, pass)) => Ok(warp::reply::with_status(
+                    warp::reply::json(&api::GuestPass { token, expires_at: pass.expires_at.to_rfc3339() }),
+                    StatusCode::CREATED,
+                )),
+                None => Ok(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new("Too many guest passes")),
+                    StatusCode::TOO_MANY_REQUESTS,
+                )),
+            }
+        })
+}
+
+/// PUT /api/rooms/{id}: create a room with a caller-chosen ID.
+/// Returns 201 when created, 409 with the existing room's metadata when it already exists.
+fn put_room(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.cluster.clone()))
+        .and(with(context.config.clone()))
+        .and(proxy::client(&context.state))
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(|room_id: String, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, cluster: Option<Arc<ClusterConfig>>, config: SharedConfig, client_ip: Option<IpAddr>, authorization: Option<String>| async move {
+            let tenants = config.read().await.tenants.clone();
+            let creator = match tenant::authorize_creator(&state, authorization.as_deref(), &tenants).await {
+                Ok(creator) => creator,
+                Err(refusal) => {
+                    audit::record(&state, audit::AUTH_FAILED, "api", client_ip, Some(&room_id), serde_json::json!({"error": refusal.message}));
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new(refusal.message)),
+                        refusal.status,
+                    ));
+                }
+            };
+            let actor = creator.actor;
+            if !tenant::is_valid_room_key(&room_id) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new("Invalid room_id")),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            let room_id = match tenant::resolve(creator.tenant.as_deref(), &room_id) {
+                Some(room_key) => room_key,
+                None => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new("room_id belongs to another tenant")),
+                        StatusCode::FORBIDDEN,
+                    ));
+                }
+            };
+            if let Some(cluster) = cluster.filter(|cluster| !cluster.is_local(&room_id)) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::elsewhere(cluster.room_api_url(&room_id))),
+                    StatusCode::MISDIRECTED_REQUEST,
+                ));
+            }
+
+            // A room announced by another instance counts as existing
+            if let Some(backplane) = state.backplane() {
+                backplane.prepare_join(&room_id).await;
+            }
+
+            // Check and insert under a single write lock so concurrent PUTs for the same ID
+            // cannot both create the room.
+            let mut manager = room_manager.write().await;
+            if !manager.rooms.contains_key(&room_id) {
+                if let Err(exceeded) = manager.quotas.check_new_room(&room_id, &manager.rooms) {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::quota(exceeded)),
+                        StatusCode::TOO_MANY_REQUESTS,
+                    ));
+                }
+            }
+            // Tokens are only issued with a room this request created
+            let settings = manager.create_room_if_absent(room_id.clone()).then(|| room::RoomSettings {
+                sender_token: Some(room::new_room_token()),
+                control_token: Some(room::new_room_token()),
+                ..Default::default()
+            });
+            let room = manager.rooms.get_mut(&room_id).ok_or_else(warp::reject::not_found)?;
+            if let Some(settings) = &settings {
+                room.apply_settings(settings);
+            }
+            let mut metadata = room.metadata();
+            let status = if settings.is_some() { StatusCode::CREATED } else { StatusCode::CONFLICT };
+            drop(manager);
+            if let Some(settings) = settings {
+                audit::record(&state, audit::ROOM_CREATED, &actor, client_ip, Some(&room_id), serde_json::json!({}));
+                state.webhooks.emit(webhooks::ROOM_CREATED, &room_id, serde_json::json!({}));
+                metadata.sender_token = settings.sender_token.clone();
+                metadata.control_token = settings.control_token.clone();
+                if let Some(backplane) = state.backplane() {
+                    backplane.announce_room(&room_id, &settings).await;
+                }
+            }
+
+            Ok(warp::reply::with_status(warp::reply::json(&metadata), status))
+        })
+}
+
+/// DELETE /api/rooms/{id}: closes a room (admin only). Members get RoomClosed and are
+/// disconnected; offers and in-memory inference data go with the room.
+fn delete_room(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and(proxy::client(&context.state))
+        .and_then(|room_id: String, admin: Admin, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients, client_ip: Option<IpAddr>| async move {
+            admin.room(&room_id).await?;
+            let closed = room_manager.write().await.close_room(&room_id, "room deleted");
+            let (responses, connection_ids) = closed.ok_or_else(warp::reject::not_found)?;
+            crate::route_responses(&state, &clients, responses).await;
+            for connection_id in &connection_ids {
+                crate::close_connection(&clients, connection_id).await;
+            }
+            if let Some(backplane) = state.backplane() {
+                backplane.forget_room(&room_id).await;
+            }
+            info!("Room {} deleted, {} connection(s) closed", room_id, connection_ids.len());
+            audit::record(
+                &state,
+                audit::ROOM_DELETED,
+                "admin",
+                client_ip,
+                Some(&room_id),
+                serde_json::json!({"closed_connections": connection_ids.len()}),
+            );
+            state.webhooks.emit(webhooks::ROOM_DELETED, &room_id, serde_json::json!({"closed_connections": connection_ids.len()}));
+            Ok::<_, Rejection>(warp::reply::with_status(
+                warp::reply::json(&api::RoomDeleted { room_id, closed_connections: connection_ids.len() }),
+                StatusCode::OK,
+            ))
+        })
+}
+
+/// PUT /api/rooms/{id}/calibration: per-room score calibration overrides keyed by model name
+/// (admin only; the curves rewrite every score stored for the room)
+fn calibration(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("calibration"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_admin(context.config.clone()))
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, curves: HashMap<String, CalibrationCurve>, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.room(&room_id).await?;
+            for (model, curve) in &curves {
+                if let Err(e) = curve.validate() {
+                    return Ok::<_, Rejection>(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new(format!("calibration for '{}' {}", model, e))),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+            }
+
+            let mut manager = room_manager.write().await;
+            match manager.rooms.get_mut(&room_id) {
+                Some(room) => {
+                    room.calibration = curves;
+                    Ok(warp::reply::with_status(warp::reply::json(&room.calibration), StatusCode::OK))
+                }
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// GET /api/rooms/{id}/zones: polygons alerted on with ZoneEvents (zones.rs)
+fn zones_get(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("zones"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.stored_data(&room_id).await?;
+            let manager = room_manager.read().await;
+            let room = manager.rooms.get(&room_id).ok_or_else(warp::reject::not_found)?;
+            Ok::<_, Rejection>(warp::reply::json(&room.zones.zones()))
+        })
+}
+
+/// PUT /api/rooms/{id}/zones: replaces the zones (admin only)
+fn zones_put(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("zones"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_admin(context.config.clone()))
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, zones: Vec<zones::Zone>, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.room(&room_id).await?;
+            if let Err(e) = zones::validate_all(&zones) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new(e)),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            let mut manager = room_manager.write().await;
+            let room = manager.rooms.get_mut(&room_id).ok_or_else(warp::reject::not_found)?;
+            room.zones.set(zones);
+            Ok(warp::reply::with_status(warp::reply::json(&room.zones.zones()), StatusCode::OK))
+        })
+}
+
+/// GET /api/rooms/{id}/lines: counting lines (counting.rs)
+fn lines_get(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("lines"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.stored_data(&room_id).await?;
+            let manager = room_manager.read().await;
+            let room = manager.rooms.get(&room_id).ok_or_else(warp::reject::not_found)?;
+            Ok::<_, Rejection>(warp::reply::json(&room.counting.lines()))
+        })
+}
+
+/// PUT /api/rooms/{id}/lines: replaces the counting lines and resets the counts (admin only)
+fn lines_put(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("lines"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_admin(context.config.clone()))
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, lines: Vec<counting::CountingLine>, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.room(&room_id).await?;
+            if let Err(e) = counting::validate_all(&lines) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&api::ErrorResponse::new(e)),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            let mut manager = room_manager.write().await;
+            let room = manager.rooms.get_mut(&room_id).ok_or_else(warp::reject::not_found)?;
+            room.counting.set(lines);
+            Ok(warp::reply::with_status(warp::reply::json(&room.counting.lines()), StatusCode::OK))
+        })
+}
+
+/// POST /api/rooms/{id}/replay: replays stored results to a connection in the room (admin only)
+fn replay_start(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("replay"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_admin(context.config.clone()))
+        .and(warp::body::content_length_limit(api::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and_then(|room_id: String, admin: Admin, body: replay::TargetedReplayRequest, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients| async move {
+            admin.room(&room_id).await?;
+            let member = room_manager.read().await.rooms.get(&room_id).map(|room| room.connections.contains_key(&body.connection_id));
+            match member {
+                None => return Err(warp::reject::not_found()),
+                Some(false) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&api::ErrorResponse::new("connection_id is not in this room")),
+                        StatusCode::NOT_FOUND,
+                    ));
+                }
+                Some(true) => {}
+            }
+            let connection_id = body.connection_id.clone();
+            match replay::start(state, clients, room_id, body.connection_id, body.request).await {
+                Ok(count) => Ok(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"connection_id": connection_id, "count": count})),
+                    StatusCode::ACCEPTED,
+                )),
+                Err(e) => Ok(warp::reply::with_status(warp::reply::json(&api::ErrorResponse::new(e)), StatusCode::BAD_REQUEST)),
+            }
+        })
+}
+
+/// DELETE /api/rooms/{id}/replay/{connection_id}: stops a replay (admin only)
+fn replay_stop(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("replay"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.clients.clone()))
+        .and_then(|room_id: String, connection_id: String, admin: Admin, state: SharedState, clients: Clients| async move {
+            admin.room(&room_id).await?;
+            if !replay::stop(&connection_id) {
+                return Err(warp::reject::not_found());
+            }
+            let stopped = SignalingMessage::new_replay_status(connection_id.clone(), serde_json::json!({"status": "stopped"}));
+            crate::route_responses(&state, &clients, vec![stopped]).await;
+            Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&serde_json::json!({"stopped": connection_id})), StatusCode::OK))
+        })
+}
+
+/// GET /api/rooms/{id}/stats: recent client-reported connection stats per connection (admin only)
+fn peer_stats(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("stats"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, admin: Admin, room_manager: Arc<RwLock<RoomManager>>| async move {
+            admin.room(&room_id).await?;
+            let manager = room_manager.read().await;
+            let room = manager.rooms.get(&room_id).ok_or_else(warp::reject::not_found)?;
+            let mut reports = manager.peer_stats.room(&room_id);
+            let connections: Vec<_> = room.connections.values()
+                .map(|info| {
+                    let reports = reports.remove(&info.id).unwrap_or_default();
+                    serde_json::json!({
+                        "connection_id": info.id,
+                        "role": info.role,
+                        "latest": reports.last(),
+                        "reports": reports,
+                    })
+                })
+                .collect();
+            Ok::<_, Rejection>(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"room_id": room_id, "connections": connections})),
+                StatusCode::OK,
+            ))
+        })
+}
+
+/// GET /api/rooms/{id}/qr?role=sender|viewer&format=svg|png: QR code of the page URL for
+/// joining the room from a phone. Sender codes hold the sender token, so they are admin only.
+fn qr(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("qr"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.config.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(proxy::request_origin(&context.state))
+        .and_then(|room_id: String, query: HashMap<String, String>, admin: Admin, config: SharedConfig, room_manager: Arc<RwLock<RoomManager>>, origin: Option<proxy::Origin>| async move {
+            let bad_request = |error: &str| {
+                warp::http::Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("content-type", "application/json")
+                    .body(warp::hyper::Body::from(serde_json::json!({"error": error}).to_string()))
+                    .unwrap()
+            };
+            let page = match qr::Page::parse(query.get("role").map(String::as_str).unwrap_or("viewer")) {
+                Some(page) => page,
+                None => return Ok::<_, Rejection>(bad_request("role must be sender or viewer")),
+            };
+            let format = match qr::ImageFormat::parse(query.get("format").map(String::as_str).unwrap_or("svg")) {
+                Some(format) => format,
+                None => return Ok(bad_request("format must be svg or png")),
+            };
+            if let qr::Page::Sender = page {
+                admin.room(&room_id).await?;
+            }
+            let sender_token = match room_manager.read().await.rooms.get(&room_id) {
+                Some(room) => room.sender_token.clone(),
+                None => return Err(warp::reject::not_found()),
+            };
+            let url = qr::page_url(&*config.read().await, origin.as_ref(), page, &room_id, sender_token.as_deref());
+            let image = qr::render(&url, format).map_err(|e| {
+                error!("Failed to render QR code for {}: {}", url, e);
+                warp::reject::reject()
+            })?;
+            Ok(warp::http::Response::builder()
+                .header("content-type", format.content_type())
+                .body(warp::hyper::Body::from(image))
+                .unwrap())
+        })
+}
+
+/// POST /api/rooms/{id}/record starts a server-side recording of the room (admin only)
+fn start_recording(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("record"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and(with(context.recordings.clone()))
+        .and_then(|room_id: String, admin: Admin, config: SharedConfig, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients, recordings: recorder::SharedRecordings| async move {
+            admin.room(&room_id).await?;
+            let recording_config = config.read().await.recording.clone();
+            let reply = match recorder::start(&recordings, &recording_config, state, room_id, room_manager, clients).await {
+                Ok(info) => warp::reply::with_status(warp::reply::json(&info), StatusCode::CREATED),
+                Err(recorder::StartError::RoomNotFound) => return Err(warp::reject::not_found()),
+                Err(recorder::StartError::AlreadyRecording(info)) => {
+                    warp::reply::with_status(warp::reply::json(&info), StatusCode::CONFLICT)
+                }
+                Err(recorder::StartError::Failed(e)) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e})),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            };
+            Ok::<_, Rejection>(reply)
+        })
+}
+
+/// DELETE /api/rooms/{id}/record stops the room's recording (admin only)
+fn stop_recording(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rooms_base()
+        .and(warp::path::param::<String>())
+        .and(warp::path("record"))
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.recordings.clone()))
+        .and_then(|room_id: String, admin: Admin, recordings: recorder::SharedRecordings| async move {
+            admin.room(&room_id).await?;
+            let mut recordings = recordings.write().await;
+            match recordings.active_for_room(&room_id) {
+                Some(info) => {
+                    recordings.stop(&info.id);
+                    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&info), StatusCode::ACCEPTED))
+                }
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
@@ -0,0 +1,242 @@
+// routes/server.rs
+// Server-wide endpoints: the client config, TURN credentials, the cluster directory and the API
+// description under /api, and Prometheus metrics and LL-HLS outside it.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use super::access::{with_admin, AccessDenied, Admin};
+use super::{with, Context};
+use crate::cluster::ClusterConfig;
+use crate::config::{self, ConfigVersions, SharedConfig};
+use crate::room::RoomManager;
+use crate::state::{SharedState, State};
+use crate::stun::StunStats;
+use crate::turn::{self, TurnServer};
+use crate::{api, auth, hls, openapi, proxy, tenant, Clients};
+
+const TURN_TOKEN_REQUIRED: &str = "Room token, admin token or ID token required";
+
+pub fn api_routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    client_config(context)
+        .or(turn_credentials(context))
+        .or(cluster(context))
+        .or(openapi())
+        .or(api_docs())
+}
+
+pub fn routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    metrics(context).or(hls(context))
+}
+
+/// GET /api/config[?since=<etag>]: 304 when the client's version is current, a delta against
+/// a recently served version, or the full client config otherwise
+fn client_config(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let versions = Arc::new(Mutex::new(ConfigVersions::default()));
+    warp::path("api")
+        .and(warp::path("config"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with(context.config.clone()))
+        .and(with(versions))
+        .and(proxy::request_origin(&context.state))
+        .and_then(|query: HashMap<String, String>, if_none_match: Option<String>, config: SharedConfig, versions: Arc<Mutex<ConfigVersions>>, origin: Option<proxy::Origin>| async move {
+            let client_config = config.read().await.for_clients_via(origin.as_ref());
+            let since = query.get("since").cloned().or_else(|| {
+                if_none_match.map(|tag| tag.trim_start_matches("W/").trim_matches('"').to_string())
+            });
+            let (etag, previous) = {
+                let mut versions = versions.lock().unwrap();
+                let etag = versions.record(&client_config);
+                let previous = since.as_deref().and_then(|since| versions.get(since).cloned());
+                (etag, previous)
+            };
+
+            let response = warp::http::Response::builder().header("etag", format!("\"{}\"", etag));
+            let response = if since.as_deref() == Some(etag.as_str()) {
+                response.status(StatusCode::NOT_MODIFIED).body(String::new())
+            } else {
+                let body = match previous {
+                    Some(previous) => {
+                        let mut delta = config::config_delta(&previous, &client_config);
+                        delta["etag"] = serde_json::json!(etag);
+                        delta
+                    }
+                    None => client_config,
+                };
+                response.header("content-type", "application/json").body(body.to_string())
+            };
+            Ok::<_, Rejection>(response.unwrap())
+        })
+}
+
+/// GET /api/turn-credentials?room_id=&user=: ephemeral TURN REST API credentials derived from
+/// turn_secret, for callers turn_access lets through
+fn turn_credentials(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("turn-credentials"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_admin(context.config.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.config.clone()))
+        .and(proxy::request_origin(&context.state))
+        .and_then(|query: HashMap<String, String>, admin: Admin, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, config: SharedConfig, origin: Option<proxy::Origin>| async move {
+            let secret = config.read().await.turn_secret.clone().ok_or_else(warp::reject::not_found)?;
+            turn_access(&admin, &state, &room_manager, query.get("room_id")).await?;
+            let config = config.read().await;
+            let user = query.get("user").map(String::as_str).unwrap_or("");
+            let (username, password) = turn::rest_credentials(&secret, user, config.turn_credential_ttl_secs);
+
+            Ok::<_, Rejection>(warp::reply::json(&api::TurnCredentials {
+                username,
+                password,
+                ttl: config.turn_credential_ttl_secs,
+                uris: config.turn_uris(origin.as_ref()),
+            }))
+        })
+}
+
+/// Relaying costs bandwidth, so credentials go to admins, to holders of a token `room_id` handed
+/// out (sender, control or guest pass) and to callers whose bearer the identity provider accepts
+async fn turn_access(admin: &Admin, state: &State, room_manager: &RwLock<RoomManager>, room_id: Option<&String>) -> Result<(), AccessDenied> {
+    if admin.scope().await.is_ok() {
+        return Ok(());
+    }
+    let token = auth::bearer(admin.authorization()).ok_or_else(|| AccessDenied::new(StatusCode::UNAUTHORIZED, TURN_TOKEN_REQUIRED))?;
+    let issued = match room_id {
+        Some(room_id) => room_manager.read().await.rooms.get(room_id).is_some_and(|room| room.issued(token, chrono::Utc::now())),
+        None => false,
+    };
+    if issued {
+        return Ok(());
+    }
+    match state.auth() {
+        Some(provider) if provider.authenticate(token).await.is_ok() => Ok(()),
+        _ => Err(AccessDenied::new(StatusCode::UNAUTHORIZED, TURN_TOKEN_REQUIRED)),
+    }
+}
+
+/// GET /api/cluster/rooms/{id}: which instance owns a room (cluster mode only)
+fn cluster(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("cluster"))
+        .and(warp::path("rooms"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with(context.cluster.clone()))
+        .and_then(|room_id: String, cluster: Option<Arc<ClusterConfig>>| async move {
+            let cluster = cluster.ok_or_else(warp::reject::not_found)?;
+            Ok::<_, Rejection>(warp::reply::json(&serde_json::json!({
+                "room_id": room_id,
+                "instance": cluster.owner(&room_id),
+                "url": cluster.room_url(&room_id)
+            })))
+        })
+}
+
+/// GET /api/openapi.json: OpenAPI description of the REST API
+fn openapi() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("openapi.json"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::json(&<openapi::ApiDoc as utoipa::OpenApi>::openapi()))
+}
+
+/// GET /api/docs: Swagger UI for the OpenAPI description
+fn api_docs() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("api")
+        .and(warp::path("docs"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| warp::reply::html(openapi::SWAGGER_UI))
+}
+
+/// GET /metrics: Prometheus exposition of STUN request and TURN relay counters, client send
+/// queues and rooms and connections per tenant
+fn metrics(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with(context.stun_stats.clone()))
+        .and(with(context.turn_server.clone()))
+        .and(with(context.clients.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|stun_stats: StunStats, turn_server: Option<Arc<TurnServer>>, clients: Clients, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let mut body = stun_stats.render_metrics();
+            body.push_str(&turn_server.as_ref().map(|server| server.render_metrics()).unwrap_or_default());
+            body.push_str(&crate::render_client_metrics(&clients).await);
+            body.push_str(&tenant::render_metrics(&*room_manager.read().await));
+            Ok::<_, Rejection>(warp::reply::with_header(body, "content-type", "text/plain; version=0.0.4"))
+        })
+}
+
+/// LL-HLS of SFU rooms for players without WebRTC: GET /hls/{room_id}/index.m3u8 (blocking
+/// with _HLS_msn and _HLS_part), init{n}.mp4, seg{msn}.m4s and part{msn}.{i}.m4s
+fn hls(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("hls")
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(proxy::client(&context.state))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and_then(|room_id: String, name: String, query: HashMap<String, String>, client_ip: Option<IpAddr>, user_agent: Option<String>, state: SharedState, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let stream = state.hls.get(&room_id).ok_or_else(warp::reject::not_found)?;
+            let file = hls::File::parse(&name).ok_or_else(warp::reject::not_found)?;
+            let user_agent = user_agent.unwrap_or_default();
+            let returning = stream.is_viewer(client_ip, &user_agent);
+            // Players are admitted like joining viewers when they fetch the playlist; segments
+            // and parts go only to players admitted recently
+            let refusal = match file {
+                hls::File::Playlist => room_manager.read().await
+                    .hls_refusal(&room_id, query.get("guest_token").map(String::as_str), returning, chrono::Utc::now()),
+                _ if !returning => Some("Fetch the playlist first".to_string()),
+                _ => None,
+            };
+            if let Some(refusal) = refusal {
+                return Ok::<_, Rejection>(warp::http::Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(warp::hyper::Body::from(refusal))
+                    .unwrap());
+            }
+            let (body, content_type, cache_control) = match file {
+                hls::File::Playlist => {
+                    let msn = query.get("_HLS_msn").and_then(|msn| msn.parse().ok());
+                    let part = query.get("_HLS_part").and_then(|part| part.parse().ok());
+                    let status = match stream.playlist(msn, part, client_ip, user_agent).await {
+                        Ok(playlist) => return Ok(warp::http::Response::builder()
+                            .header("content-type", "application/vnd.apple.mpegurl")
+                            .header("cache-control", "no-cache")
+                            .body(warp::hyper::Body::from(playlist))
+                            .unwrap()),
+                        Err(hls::PlaylistError::TooFarAhead) => StatusCode::BAD_REQUEST,
+                        Err(hls::PlaylistError::Timeout) => StatusCode::SERVICE_UNAVAILABLE,
+                    };
+                    return Ok(warp::http::Response::builder().status(status).body(warp::hyper::Body::empty()).unwrap());
+                }
+                hls::File::Init(id) => (stream.init_segment(id), "video/mp4", "max-age=3600"),
+                hls::File::Segment(msn) => (stream.segment(msn), "video/iso.segment", "max-age=60"),
+                hls::File::Part(msn, index) => (stream.part(msn, index).await, "video/iso.segment", "max-age=60"),
+            };
+            let body = body.ok_or_else(warp::reject::not_found)?;
+            Ok(warp::http::Response::builder()
+                .header("content-type", content_type)
+                .header("cache-control", cache_control)
+                .body(warp::hyper::Body::from(body))
+                .unwrap())
+        })
+}
@@ -0,0 +1,131 @@
+// routes/sessions.rs
+// Signaling over WebSocket (/ws/{room_id}) and its long-polling fallback (/poll/{room_id}); both
+// feed a Session, as the WebTransport listener does. Also the read-only event stream of a room
+// group for dashboards.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use super::access::{self, with_admin, Admin};
+use super::{with, Context};
+use crate::cluster::ClusterConfig;
+use crate::room::RoomManager;
+use crate::server_inference::ServerInference;
+use crate::state::SharedState;
+use crate::{api, poll, proxy, Clients};
+
+pub fn routes(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    group_ws(context).recover(access::recover)
+        .or(ws(context))
+        .or(poll(context))
+        .or(poll_leave(context))
+}
+
+/// GET /ws/{room_id}
+fn ws(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("ws")
+        .and(warp::path::param::<String>())
+        .and(warp::ws())
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and(with(context.server_inference.clone()))
+        .and(with(context.cluster.clone()))
+        .and(proxy::client(&context.state))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and_then(|room_id: String, ws: warp::ws::Ws, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients, server_inference: Option<Arc<ServerInference>>, cluster: Option<Arc<ClusterConfig>>, client_ip: Option<IpAddr>, user_agent: Option<String>| async move {
+            // In cluster mode, rooms owned by another instance are redirected there
+            let redirect = cluster.filter(|cluster| !cluster.is_local(&room_id)).map(|cluster| cluster.room_url(&room_id));
+            Ok::<_, Rejection>(ws.on_upgrade(move |socket| async move {
+                match redirect {
+                    Some(url) => crate::redirect_websocket(socket, url).await,
+                    None => crate::handle_websocket(socket, state, room_id, room_manager, clients, server_inference, client_ip, user_agent).await,
+                }
+            }))
+        })
+}
+
+/// GET /ws/groups/{id}: NewPeer, Leave and InferenceUpdate events of every room in a group,
+/// for site-wide dashboards (admin only; browsers pass the token as ?token=)
+fn group_ws(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("ws")
+        .and(warp::path("groups"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(with_admin(context.config.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with(context.room_manager.clone()))
+        .and_then(|id: String, ws: warp::ws::Ws, admin: Admin, query: HashMap<String, String>, room_manager: Arc<RwLock<RoomManager>>| async move {
+            let scope = admin.or_token(query.get("token")).scope().await?;
+            let group_id = scope.resolve(&id).ok_or_else(warp::reject::not_found)?;
+            let events = {
+                let manager = room_manager.read().await;
+                if manager.groups.get(&group_id).is_none() {
+                    return Err(warp::reject::not_found());
+                }
+                manager.groups.subscribe()
+            };
+            Ok(ws.on_upgrade(move |socket| crate::handle_group_websocket(socket, group_id, events)))
+        })
+}
+
+/// POST /poll/{room_id}: long-polling fallback for clients whose proxies block WebSocket upgrades
+fn poll(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("poll")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::cookie::optional::<String>(poll::COOKIE))
+        .and(warp::body::content_length_limit(poll::MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with(context.poll_sessions.clone()))
+        .and(with(context.state.clone()))
+        .and(with(context.room_manager.clone()))
+        .and(with(context.clients.clone()))
+        .and(with(context.server_inference.clone()))
+        .and(with(context.cluster.clone()))
+        .and(proxy::client(&context.state))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and_then(|room_id: String, session_id: Option<String>, messages: Vec<serde_json::Value>, sessions: poll::PollSessions, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients, server_inference: Option<Arc<ServerInference>>, cluster: Option<Arc<ClusterConfig>>, client_ip: Option<IpAddr>, user_agent: Option<String>| async move {
+            // In cluster mode, rooms owned by another instance are redirected there
+            let reply = match cluster.filter(|cluster| !cluster.is_local(&room_id)) {
+                Some(cluster) => Ok(poll::PollReply::redirect(cluster.room_poll_url(&room_id))),
+                None => sessions.exchange(session_id, room_id.clone(), messages, state, room_manager, clients, server_inference, client_ip, user_agent).await,
+            };
+            let (body, status, reply) = match reply {
+                Ok(reply) => (reply.body(), StatusCode::OK, reply),
+                Err(refusal) => (
+                    serde_json::to_value(api::ErrorResponse::new(refusal)).unwrap_or_default(),
+                    StatusCode::TOO_MANY_REQUESTS,
+                    poll::PollReply { session_id: String::new(), messages: Vec::new(), closed: true },
+                ),
+            };
+            Ok::<_, Rejection>(warp::reply::with_status(
+                warp::reply::with_header(warp::reply::json(&body), "set-cookie", poll::cookie(&reply, &room_id)),
+                status,
+            ))
+        })
+}
+
+/// DELETE /poll/{room_id}: leaves the room and ends the long-polling session
+fn poll_leave(context: &Context) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("poll")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::cookie::optional::<String>(poll::COOKIE))
+        .and(with(context.poll_sessions.clone()))
+        .and_then(|_room_id: String, session_id: Option<String>, sessions: poll::PollSessions| async move {
+            let ended = match session_id {
+                Some(session_id) => sessions.end(&session_id).await,
+                None => false,
+            };
+            let status = if ended { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND };
+            Ok::<_, Rejection>(warp::reply::with_status(warp::reply(), status))
+        })
+}
@@ -0,0 +1,81 @@
+// schedule.rs
+// Activation windows for rooms used by classes and events: POST /api/rooms may give starts_at
+// and ends_at, joins before the start or after the end are refused with an Error whose
+// data.schedule says which, and the room is closed for everyone at its end time. The SFU's own
+// peer and admins may join before the start, so a stream can be set up ahead of time.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use ws2infer_protocol::{SignalingMessage, SignalingMessageType};
+
+/// RoomClosed reason sent to the members of a room whose window ended
+pub const ENDED_REASON: &str = "room schedule ended";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Schedule {
+    /// Joins before this time are refused (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub starts_at: Option<DateTime<Utc>>,
+    /// The room is closed at this time (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    /// A new room's window must end after it starts, and in the future
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if let (Some(starts_at), Some(ends_at)) = (self.starts_at, self.ends_at) {
+            if ends_at <= starts_at {
+                return Err("ends_at must be after starts_at".to_string());
+            }
+        }
+        if self.ends_at.is_some_and(|ends_at| ends_at <= now) {
+            return Err("ends_at must be in the future".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn has_ended(&self, now: DateTime<Utc>) -> bool {
+        self.ends_at.is_some_and(|ends_at| ends_at <= now)
+    }
+
+    /// Why a join at `now` is refused, if it is
+    pub fn refusal(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        if self.starts_at.is_some_and(|starts_at| now < starts_at) {
+            Some("not_started")
+        } else if self.has_ended(now) {
+            Some("ended")
+        } else {
+            None
+        }
+    }
+
+    /// The error text for a refusal status
+    pub fn refusal_error(status: &str) -> &'static str {
+        if status == "not_started" { "Room has not opened yet" } else { "Room has closed" }
+    }
+
+    /// Error for `connection_id` joining outside the window; `data.schedule` has the status
+    /// (not_started or ended) with starts_at and ends_at
+    pub fn refusal_message(&self, connection_id: String, status: &str) -> SignalingMessage {
+        let error = Self::refusal_error(status);
+        let mut schedule = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = schedule.as_object_mut() {
+            fields.insert("status".to_string(), status.into());
+        }
+        SignalingMessage {
+            message_type: SignalingMessageType::Error,
+            connection_id: Some(connection_id),
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(serde_json::json!({"error": error, "schedule": schedule})),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+}
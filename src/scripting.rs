@@ -0,0 +1,298 @@
+// scripting.rs
+// Room policies as Rhai scripts (behind the `scripting` feature), configured in config.json: a
+// global script for every room and per-room scripts, which run after the global one. A script
+// may define `on_join(join)` to deny joins and `on_inference(result)` to rewrite a normalized
+// result (rename classes, drop detections) or raise alerts, which are sent to the room as Alert
+// messages and to webhooks as `alert`. Scripts get no file, network or module access; each call
+// is limited in operations and wall time, and a failing script lets the event through unless
+// fail_closed is set.
+
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::inference::{InferenceLimits, InferencePayload};
+use crate::room::{self, Role};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptingConfig {
+    /// Script file run for every room
+    #[serde(default)]
+    pub global: Option<String>,
+    /// Room ID -> script file run for that room after the global one
+    #[serde(default)]
+    pub rooms: BTreeMap<String, String>,
+    /// Rhai operations one call may take
+    #[serde(default = "default_max_operations")]
+    pub max_operations: u64,
+    /// Wall time one call may take
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Deny the join or drop the result when a script fails instead of letting it through
+    #[serde(default)]
+    pub fail_closed: bool,
+}
+
+fn default_max_operations() -> u64 { 100_000 }
+fn default_timeout_ms() -> u64 { 20 }
+
+impl ScriptingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.global.is_none() && self.rooms.is_empty() {
+            return Err("set global or at least one room script".to_string());
+        }
+        if self.global.as_deref() == Some("") || self.rooms.values().any(String::is_empty) {
+            return Err("script paths must not be empty".to_string());
+        }
+        if let Some(room_id) = self.rooms.keys().find(|room_id| !room::is_valid_room_id(room_id)) {
+            return Err(format!("invalid room ID {}", room_id));
+        }
+        if self.max_operations == 0 {
+            return Err("max_operations must be greater than 0".to_string());
+        }
+        if self.timeout_ms == 0 || self.timeout_ms > 1000 {
+            return Err("timeout_ms must be between 1 and 1000".to_string());
+        }
+        if !cfg!(feature = "scripting") {
+            return Err("this build does not include the scripting feature".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What on_inference decided for one result
+#[derive(Debug, Default)]
+pub struct InferenceOutcome {
+    /// The result is dropped (fail_closed and a script failed)
+    pub dropped: bool,
+    /// Alerts raised by the scripts, each tagged with the script's file
+    pub alerts: Vec<Value>,
+}
+
+struct Script {
+    path: String,
+    compiled: runtime::Script,
+}
+
+/// The compiled scripts with their sandboxed engine
+pub struct Scripts {
+    runtime: runtime::Runtime,
+    global: Option<Script>,
+    rooms: HashMap<String, Script>,
+    fail_closed: bool,
+}
+
+impl std::fmt::Debug for Scripts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scripts")
+            .field("global", &self.global.as_ref().map(|script| &script.path))
+            .field("rooms", &self.rooms.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Scripts {
+    /// Reads and compiles the configured scripts; ones that fail are logged and skipped
+    pub fn load(config: &ScriptingConfig) -> Result<Self, String> {
+        let runtime = runtime::Runtime::new(config)?;
+        let compile = |path: &String| {
+            let compiled = std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| runtime.compile(&source));
+            match compiled {
+                Ok(compiled) => {
+                    info!("Loaded room policy script {}", path);
+                    Some(Script { path: path.clone(), compiled })
+                }
+                Err(e) => {
+                    error!("Failed to load room policy script {}: {}", path, e);
+                    None
+                }
+            }
+        };
+        let global = config.global.as_ref().and_then(&compile);
+        let rooms = config.rooms.iter()
+            .filter_map(|(room_id, path)| compile(path).map(|script| (room_id.clone(), script)))
+            .collect();
+        Ok(Self { runtime, global, rooms, fail_closed: config.fail_closed })
+    }
+
+    fn scripts_for<'a>(&'a self, room_id: &str) -> impl Iterator<Item = &'a Script> {
+        self.global.iter().chain(self.rooms.get(room_id))
+    }
+
+    /// Err with the reason when a script denies the join
+    pub fn on_join(&self, room_id: &str, connection_id: &str, role: Role, data: &Value) -> Result<(), String> {
+        let input = serde_json::json!({
+            "room_id": room_id,
+            "connection_id": connection_id,
+            "role": role,
+            "data": data,
+        });
+        for script in self.scripts_for(room_id) {
+            match self.runtime.call(&script.compiled, "on_join", &input) {
+                Ok(None) | Ok(Some(Value::Null)) | Ok(Some(Value::Bool(true))) => {}
+                Ok(Some(Value::Bool(false))) => return Err("Join denied by room policy".to_string()),
+                Ok(Some(Value::String(reason))) => return Err(reason),
+                Ok(Some(other)) => {
+                    warn!("Room policy script {} returned {} from on_join; expected a bool or string", script.path, other);
+                }
+                Err(e) => {
+                    warn!("Room policy script {} failed in on_join: {}", script.path, e);
+                    if self.fail_closed {
+                        return Err("Join denied by room policy".to_string());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets the scripts rewrite a normalized payload; rewritten payloads that no longer parse
+    /// are ignored
+    pub fn on_inference(&self, room_id: &str, source_id: &str, payload: &mut Value, limits: &InferenceLimits) -> InferenceOutcome {
+        let mut outcome = InferenceOutcome::default();
+        for script in self.scripts_for(room_id) {
+            let input = serde_json::json!({
+                "room_id": room_id,
+                "source_id": source_id,
+                "payload": payload,
+            });
+            let answer = match self.runtime.call(&script.compiled, "on_inference", &input) {
+                Ok(Some(Value::Object(answer))) => answer,
+                Ok(None) | Ok(Some(Value::Null)) => continue,
+                Ok(Some(other)) => {
+                    warn!("Room policy script {} returned {} from on_inference; expected a map", script.path, other);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Room policy script {} failed in on_inference: {}", script.path, e);
+                    if self.fail_closed {
+                        outcome.dropped = true;
+                        return outcome;
+                    }
+                    continue;
+                }
+            };
+            if let Some(replacement) = answer.get("payload") {
+                match InferencePayload::parse(replacement, limits) {
+                    Ok(parsed) => *payload = parsed.to_value(),
+                    Err(e) => warn!("Room policy script {} returned an invalid payload: {}", script.path, e),
+                }
+            }
+            let alerts = match answer.get("alerts") {
+                Some(Value::Array(alerts)) => alerts.clone(),
+                Some(alert) => vec![alert.clone()],
+                None => Vec::new(),
+            };
+            outcome.alerts.extend(alerts.into_iter().map(|alert| {
+                let alert = match alert {
+                    Value::String(message) => serde_json::json!({"message": message}),
+                    other => other,
+                };
+                serde_json::json!({"script": script.path, "source_sender_id": source_id, "alert": alert})
+            }));
+        }
+        outcome
+    }
+}
+
+#[cfg(feature = "scripting")]
+mod runtime {
+    use std::cell::Cell;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use rhai::{CallFnOptions, Dynamic, Engine, Scope, AST};
+    use serde_json::Value;
+
+    use super::ScriptingConfig;
+
+    thread_local! {
+        /// When the running call must stop; checked by the engine's progress callback
+        static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    }
+
+    pub struct Runtime {
+        engine: Engine,
+        timeout: Duration,
+    }
+
+    pub struct Script {
+        ast: AST,
+        functions: HashSet<String>,
+    }
+
+    impl Runtime {
+        pub fn new(config: &ScriptingConfig) -> Result<Self, String> {
+            let mut engine = Engine::new();
+            // No `import` from files and no eval; print and debug go to the log
+            engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+            engine.disable_symbol("eval");
+            engine.set_max_operations(config.max_operations);
+            engine.set_max_call_levels(32);
+            engine.set_max_expr_depths(64, 32);
+            engine.set_max_string_size(64 * 1024);
+            engine.set_max_array_size(10_000);
+            engine.set_max_map_size(10_000);
+            engine.on_print(|text| tracing::info!(target: "script", "{}", text));
+            engine.on_debug(|text, _, position| tracing::debug!(target: "script", "{} at {}", text, position));
+            engine.on_progress(|_| {
+                let expired = DEADLINE.with(|deadline| deadline.get().is_some_and(|deadline| Instant::now() > deadline));
+                expired.then(|| Dynamic::from("time limit exceeded"))
+            });
+            Ok(Self { engine, timeout: Duration::from_millis(config.timeout_ms) })
+        }
+
+        pub fn compile(&self, source: &str) -> Result<Script, String> {
+            let ast = self.engine.compile(source).map_err(|e| e.to_string())?;
+            let functions = ast.iter_functions().map(|function| function.name.to_string()).collect();
+            Ok(Script { ast, functions })
+        }
+
+        /// None when the script doesn't define `function`; unit comes back as null
+        pub fn call(&self, script: &Script, function: &str, input: &Value) -> Result<Option<Value>, String> {
+            if !script.functions.contains(function) {
+                return Ok(None);
+            }
+            let argument = rhai::serde::to_dynamic(input).map_err(|e| e.to_string())?;
+            // Top-level statements ran once at compile time and are not repeated per call
+            let options = CallFnOptions::new().eval_ast(false);
+            DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+            let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, function, (argument,));
+            DEADLINE.with(|deadline| deadline.set(None));
+            let result = result.map_err(|e| e.to_string())?;
+            if result.is_unit() {
+                return Ok(Some(Value::Null));
+            }
+            rhai::serde::from_dynamic::<Value>(&result).map(Some).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod runtime {
+    use serde_json::Value;
+
+    use super::ScriptingConfig;
+
+    const UNAVAILABLE: &str = "this build does not include the scripting feature";
+
+    pub struct Runtime;
+
+    pub struct Script;
+
+    impl Runtime {
+        pub fn new(_config: &ScriptingConfig) -> Result<Self, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+
+        pub fn compile(&self, _source: &str) -> Result<Script, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+
+        pub fn call(&self, _script: &Script, _function: &str, _input: &Value) -> Result<Option<Value>, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// sdp.rs
+// Checks the session descriptions in Offer and Answer messages before they are forwarded, so a
+// buggy or hostile client can't hand its peers junk. The SDP is read line by line (RFC 8866
+// `<type>=<value>` lines, session part before the media sections) without interpreting codecs;
+// clearly malformed descriptions are rejected with an Error to the sender, and the configured
+// attributes, plus candidates with private addresses when asked to, are removed from the rest.
+
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SdpPolicy {
+    /// Largest accepted SDP text, in bytes
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// Most m= sections accepted in one description
+    #[serde(default = "default_max_media_sections")]
+    pub max_media_sections: usize,
+    /// Removes a=candidate lines with private, loopback or link-local addresses, for servers
+    /// whose clients should only ever connect over public addresses or TURN
+    #[serde(default)]
+    pub strip_private_candidates: bool,
+    /// Attribute names (e.g. "extmap-allow-mixed") whose a= lines are removed
+    #[serde(default)]
+    pub strip_attributes: Vec<String>,
+}
+
+fn default_max_bytes() -> usize { 65536 }
+fn default_max_media_sections() -> usize { 32 }
+
+impl Default for SdpPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: default_max_bytes(),
+            max_media_sections: default_max_media_sections(),
+            strip_private_candidates: false,
+            strip_attributes: Vec::new(),
+        }
+    }
+}
+
+impl SdpPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_bytes == 0 {
+            return Err("max_bytes must be greater than 0".to_string());
+        }
+        if self.max_media_sections == 0 {
+            return Err("max_media_sections must be greater than 0".to_string());
+        }
+        if let Some(name) = self.strip_attributes.iter().find(|name| name.is_empty() || name.contains([':', ' ', '='])) {
+            return Err(format!("strip_attributes entry '{}' must be a bare attribute name", name));
+        }
+        Ok(())
+    }
+
+    /// Replaces the SDP of an Offer or Answer with its sanitized form; other messages pass
+    /// untouched
+    pub fn sanitize(&self, message: &mut SignalingMessage) -> Result<(), String> {
+        let (expected, message_kind): (&[&str], &str) = match message.message_type {
+            SignalingMessageType::Offer => (&["offer"], "an offer"),
+            SignalingMessageType::Answer => (&["answer", "pranswer"], "an answer"),
+            _ => return Ok(()),
+        };
+        let description = message.data.as_mut()
+            .and_then(Value::as_object_mut)
+            .ok_or("data must be a session description object")?;
+        match description.get("type").and_then(Value::as_str) {
+            Some(kind) if expected.contains(&kind) => {}
+            Some(kind) => return Err(format!("type '{}' does not fit {}", kind, message_kind)),
+            None => return Err("type is missing".to_string()),
+        }
+        let sdp = description.get("sdp")
+            .and_then(Value::as_str)
+            .ok_or("sdp is missing")?;
+        let sanitized = self.sanitize_sdp(sdp)?;
+        description.insert("sdp".to_string(), Value::String(sanitized));
+        Ok(())
+    }
+
+    fn sanitize_sdp(&self, sdp: &str) -> Result<String, String> {
+        if sdp.len() > self.max_bytes {
+            return Err(format!("SDP is {} bytes, more than the {} allowed", sdp.len(), self.max_bytes));
+        }
+
+        let mut kept = Vec::new();
+        let mut session_types = Vec::new();
+        let mut media_sections = 0;
+        for (index, line) in sdp.lines().enumerate() {
+            let number = index + 1;
+            if line.is_empty() {
+                continue;
+            }
+            let (kind, value) = match line.split_once('=') {
+                Some((kind, value)) if kind.len() == 1 && kind.as_bytes()[0].is_ascii_lowercase() => (kind.as_bytes()[0], value),
+                _ => return Err(format!("line {} is not <type>=<value>", number)),
+            };
+            if line.chars().any(|c| c.is_control() && c != '\t') {
+                return Err(format!("line {} contains control characters", number));
+            }
+            if kept.is_empty() && line != "v=0" {
+                return Err("SDP must start with v=0".to_string());
+            }
+
+            match kind {
+                b'm' => {
+                    if media_sections == 0 {
+                        if let Some(missing) = [b'o', b's', b't'].iter().find(|kind| !session_types.contains(*kind)) {
+                            return Err(format!("session description has no {}= line", *missing as char));
+                        }
+                    }
+                    media_sections += 1;
+                    if media_sections > self.max_media_sections {
+                        return Err(format!("more than {} media sections", self.max_media_sections));
+                    }
+                    check_media_line(value).map_err(|e| format!("line {}: {}", number, e))?;
+                }
+                b'a' if self.strips(value) => continue,
+                _ if media_sections == 0 => session_types.push(kind),
+                _ => {}
+            }
+            kept.push(line);
+        }
+        if media_sections == 0 {
+            return Err("SDP has no media sections".to_string());
+        }
+
+        let mut sanitized = kept.join("\r\n");
+        sanitized.push_str("\r\n");
+        Ok(sanitized)
+    }
+
+    /// Whether the a= line with this value is removed
+    fn strips(&self, attribute: &str) -> bool {
+        let name = attribute.split(':').next().unwrap_or_default();
+        if self.strip_attributes.iter().any(|stripped| stripped == name) {
+            return true;
+        }
+        self.strip_private_candidates && name == "candidate" && candidate_ip(attribute).is_some_and(is_private)
+    }
+}
+
+/// `<media> <port>[/<count>] <proto> <fmt> ...`
+fn check_media_line(value: &str) -> Result<(), String> {
+    let fields: Vec<&str> = value.split(' ').collect();
+    if fields.len() < 4 || fields.iter().any(|field| field.is_empty()) {
+        return Err("m= line needs media, port, protocol and formats".to_string());
+    }
+    let port = fields[1].split('/').next().unwrap_or_default();
+    if port.parse::<u16>().is_err() {
+        return Err(format!("m= port '{}' is not a number", fields[1]));
+    }
+    Ok(())
+}
+
+/// The connection address of an ICE candidate (RFC 8839 `candidate:<foundation> <component>
+/// <transport> <priority> <address> <port> typ ...`), with or without the `a=` and `candidate:`
+/// prefixes; None for mDNS (.local) names
+pub fn candidate_ip(candidate: &str) -> Option<IpAddr> {
+    let candidate = candidate.strip_prefix("a=").unwrap_or(candidate);
+    let candidate = candidate.strip_prefix("candidate:").unwrap_or(candidate);
+    candidate.split_whitespace().nth(4)?.parse().ok()
+}
+
+/// The type after `typ` in an ICE candidate: `host`, `srflx`, `prflx` or `relay`
+pub fn candidate_type(candidate: &str) -> Option<&str> {
+    let mut fields = candidate.split_whitespace().skip_while(|field| *field != "typ");
+    fields.next()?;
+    fields.next()
+}
+
+/// Private (RFC 1918, unique local), loopback, link-local or unspecified
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback() || ip.is_unspecified() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
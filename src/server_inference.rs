@@ -0,0 +1,308 @@
+// server_inference.rs
+// Optional object detection on the server, for senders too weak to run models in the browser.
+// Senders upload JPEG frames as binary WebSocket messages; a single worker decodes them, runs the
+// configured ONNX model (tract, behind the `server-inference` cargo feature) and feeds the result
+// back through RoomManager as an InferenceResult credited to that sender, so it is calibrated,
+// stored and broadcast exactly like a viewer's. Frames arriving while the worker is busy, or
+// sooner than `min_interval_ms` after the previous one from the same sender, are dropped.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+
+use crate::Clients;
+use crate::inference::{Detection, InferencePayload};
+use crate::room::RoomManager;
+use crate::signaling::SignalingMessage;
+use crate::state::SharedState;
+
+/// Binary WebSocket messages starting with this are frame uploads rather than JSON
+pub const JPEG_MAGIC: [u8; 3] = [0xFF, 0xD8, 0xFF];
+/// Frames waiting for the worker; more are dropped, the next one is as good
+const QUEUE_CAPACITY: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInferenceConfig {
+    /// ONNX detection model with a [1, 3, input_size, input_size] RGB input in 0..1 and a
+    /// YOLOv8-style [1, 4 + classes, boxes] output (cx, cy, w, h in input pixels, then scores)
+    pub model_path: String,
+    /// Reported as `model` in the results, which also selects score calibration
+    #[serde(default = "default_model_name")]
+    pub model_name: String,
+    #[serde(default = "default_input_size")]
+    pub input_size: u32,
+    /// Class names by output index; missing names are reported as `class_<index>`
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default = "default_score_threshold")]
+    pub score_threshold: f32,
+    #[serde(default = "default_iou_threshold")]
+    pub iou_threshold: f32,
+    /// Frames a sender uploads sooner than this after its previous one are dropped
+    #[serde(default = "default_min_interval_ms")]
+    pub min_interval_ms: u64,
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: usize,
+}
+
+fn default_model_name() -> String { "server".to_string() }
+fn default_input_size() -> u32 { 640 }
+fn default_score_threshold() -> f32 { 0.25 }
+fn default_iou_threshold() -> f32 { 0.45 }
+fn default_min_interval_ms() -> u64 { 500 }
+fn default_max_frame_bytes() -> usize { 2 * 1024 * 1024 }
+
+impl ServerInferenceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.model_path.is_empty() {
+            return Err("model_path must not be empty".to_string());
+        }
+        if self.model_name.is_empty() {
+            return Err("model_name must not be empty".to_string());
+        }
+        if self.input_size == 0 || !self.input_size.is_multiple_of(32) {
+            return Err("input_size must be a positive multiple of 32".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.score_threshold) || !(0.0..=1.0).contains(&self.iou_threshold) {
+            return Err("score_threshold and iou_threshold must be between 0 and 1".to_string());
+        }
+        if self.max_frame_bytes == 0 {
+            return Err("max_frame_bytes must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+struct Frame {
+    room_id: String,
+    source_id: String,
+    jpeg: Vec<u8>,
+    received_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct ServerInference {
+    config: ServerInferenceConfig,
+    frames: mpsc::Sender<Frame>,
+    last_frame: Mutex<HashMap<String, Instant>>,
+}
+
+impl ServerInference {
+    /// Loads the model and starts the worker; fails when the model can't be loaded or the
+    /// server was built without the `server-inference` feature
+    pub fn start(config: &ServerInferenceConfig, state: SharedState, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) -> Result<Arc<Self>, String> {
+        let model = Arc::new(model::Model::load(config)?);
+        info!("Server-side inference enabled with {} ({})", config.model_path, config.model_name);
+
+        let (frames, mut queue) = mpsc::channel::<Frame>(QUEUE_CAPACITY);
+        let model_name = config.model_name.clone();
+        tokio::task::spawn(async move {
+            while let Some(frame) = queue.recv().await {
+                let model = model.clone();
+                let started = Instant::now();
+                let detected = tokio::task::spawn_blocking(move || model.detect(&frame.jpeg).map(|result| (frame, result))).await;
+                let (frame, (frame_size, predictions)) = match detected {
+                    Ok(Ok(detected)) => detected,
+                    Ok(Err(e)) => {
+                        warn!("Server-side inference failed: {}", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Server-side inference worker panicked: {}", e);
+                        continue;
+                    }
+                };
+
+                let payload = InferencePayload {
+                    timestamp: frame.received_at.timestamp_millis(),
+                    model: Some(model_name.clone()),
+                    predictions,
+                    frame_size: Some(frame_size),
+                    inference_time_ms: Some(started.elapsed().as_secs_f64() * 1000.0),
+                };
+                let message = SignalingMessage::new_inference_result(frame.source_id.clone(), frame.source_id, payload.to_value());
+
+                let responses = room_manager.write().await.handle_message(frame.room_id, message);
+                if let Some(responses) = responses {
+                    crate::route_responses(&state, &clients, responses).await;
+                }
+            }
+        });
+
+        Ok(Arc::new(Self {
+            config: config.clone(),
+            frames,
+            last_frame: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Queues a frame uploaded by `connection_id`. Errors are for the uploader; frames dropped
+    /// for rate or load are not errors.
+    pub async fn submit(&self, room_manager: &RwLock<RoomManager>, room_id: &str, connection_id: &str, jpeg: &[u8]) -> Result<(), String> {
+        if jpeg.len() > self.config.max_frame_bytes {
+            return Err(format!("Frame of {} bytes exceeds the limit of {}", jpeg.len(), self.config.max_frame_bytes));
+        }
+        let is_sender = room_manager.read().await.rooms.get(room_id)
+            .and_then(|room| room.connections.get(connection_id))
+            .is_some_and(|connection| connection.is_sender);
+        if !is_sender {
+            return Err("Only the room's sender can upload frames for inference".to_string());
+        }
+
+        let now = Instant::now();
+        {
+            let mut last_frame = self.last_frame.lock().unwrap();
+            let min_interval = Duration::from_millis(self.config.min_interval_ms);
+            if matches!(last_frame.get(connection_id), Some(last) if now.duration_since(*last) < min_interval) {
+                return Ok(());
+            }
+            last_frame.insert(connection_id.to_string(), now);
+        }
+
+        let frame = Frame {
+            room_id: room_id.to_string(),
+            source_id: connection_id.to_string(),
+            jpeg: jpeg.to_vec(),
+            received_at: chrono::Utc::now(),
+        };
+        if self.frames.try_send(frame).is_err() {
+            debug!("Server-side inference busy; dropped a frame from {}", connection_id);
+        }
+        Ok(())
+    }
+
+    pub fn forget(&self, connection_id: &str) {
+        self.last_frame.lock().unwrap().remove(connection_id);
+    }
+}
+
+#[cfg(feature = "server-inference")]
+mod model {
+    use image::imageops::FilterType;
+    use tract_onnx::prelude::*;
+
+    use super::{Detection, ServerInferenceConfig};
+
+    /// Detections kept per frame after non-maximum suppression
+    const MAX_DETECTIONS: usize = 100;
+
+    pub struct Model {
+        plan: TypedRunnableModel<TypedModel>,
+        input_size: u32,
+        labels: Vec<String>,
+        score_threshold: f32,
+        iou_threshold: f32,
+    }
+
+    impl Model {
+        pub fn load(config: &ServerInferenceConfig) -> Result<Self, String> {
+            let size = config.input_size as usize;
+            let plan = tract_onnx::onnx()
+                .model_for_path(&config.model_path)
+                .and_then(|model| model.with_input_fact(0, f32::fact([1, 3, size, size]).into()))
+                .and_then(|model| model.into_optimized())
+                .and_then(|model| model.into_runnable())
+                .map_err(|e| format!("Failed to load {}: {}", config.model_path, e))?;
+            Ok(Self {
+                plan,
+                input_size: config.input_size,
+                labels: config.labels.clone(),
+                score_threshold: config.score_threshold,
+                iou_threshold: config.iou_threshold,
+            })
+        }
+
+        /// Frame size and detections in pixels of the uploaded frame
+        pub fn detect(&self, jpeg: &[u8]) -> Result<([u32; 2], Vec<Detection>), String> {
+            let image = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)
+                .map_err(|e| format!("Invalid JPEG: {}", e))?
+                .to_rgb8();
+            let (width, height) = image.dimensions();
+            let size = self.input_size;
+            let resized = image::imageops::resize(&image, size, size, FilterType::Triangle);
+            let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, size as usize, size as usize), |(_, c, y, x)| {
+                resized[(x as u32, y as u32)][c] as f32 / 255.0
+            })
+            .into();
+
+            let outputs = self.plan.run(tvec!(input.into())).map_err(|e| e.to_string())?;
+            let output = outputs[0].to_array_view::<f32>().map_err(|e| e.to_string())?;
+            let shape = output.shape();
+            if shape.len() != 3 || shape[1] <= 4 {
+                return Err(format!("Unexpected model output shape {:?}", shape));
+            }
+
+            // Boxes were predicted on the stretched input; scale each axis back separately
+            let scale_x = width as f32 / size as f32;
+            let scale_y = height as f32 / size as f32;
+            let mut candidates = Vec::new();
+            for i in 0..shape[2] {
+                let (class, score) = (4..shape[1])
+                    .map(|row| (row - 4, output[[0, row, i]]))
+                    .fold((0, f32::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+                if score < self.score_threshold {
+                    continue;
+                }
+                let (cx, cy, w, h) = (output[[0, 0, i]], output[[0, 1, i]], output[[0, 2, i]], output[[0, 3, i]]);
+                let bbox = [(cx - w / 2.0) * scale_x, (cy - h / 2.0) * scale_y, w * scale_x, h * scale_y];
+                candidates.push((class, score, bbox));
+            }
+
+            let detections = non_max_suppression(candidates, self.iou_threshold)
+                .into_iter()
+                .map(|(class, score, bbox)| Detection {
+                    class: self.labels.get(class).cloned().unwrap_or_else(|| format!("class_{}", class)),
+                    score: score.clamp(0.0, 1.0) as f64,
+                    bbox: bbox.map(|v| v.max(0.0) as f64),
+                    track_id: None,
+                })
+                .collect();
+            Ok(([width, height], detections))
+        }
+    }
+
+    /// Greedy per-class suppression, highest score first
+    fn non_max_suppression(mut candidates: Vec<(usize, f32, [f32; 4])>, iou_threshold: f32) -> Vec<(usize, f32, [f32; 4])> {
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut kept: Vec<(usize, f32, [f32; 4])> = Vec::new();
+        for candidate in candidates {
+            let overlaps = kept.iter().any(|k| k.0 == candidate.0 && iou(&k.2, &candidate.2) > iou_threshold);
+            if !overlaps {
+                kept.push(candidate);
+                if kept.len() == MAX_DETECTIONS {
+                    break;
+                }
+            }
+        }
+        kept
+    }
+
+    fn iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+        let x1 = a[0].max(b[0]);
+        let y1 = a[1].max(b[1]);
+        let x2 = (a[0] + a[2]).min(b[0] + b[2]);
+        let y2 = (a[1] + a[3]).min(b[1] + b[3]);
+        let intersection = (x2 - x1).max(0.0) * (y2 - y1).max(0.0);
+        let union = a[2] * a[3] + b[2] * b[3] - intersection;
+        if union <= 0.0 { 0.0 } else { intersection / union }
+    }
+}
+
+#[cfg(not(feature = "server-inference"))]
+mod model {
+    use super::{Detection, ServerInferenceConfig};
+
+    pub struct Model;
+
+    impl Model {
+        pub fn load(_config: &ServerInferenceConfig) -> Result<Self, String> {
+            Err("this build does not include the server-inference feature".to_string())
+        }
+
+        pub fn detect(&self, _jpeg: &[u8]) -> Result<([u32; 2], Vec<Detection>), String> {
+            Err("this build does not include the server-inference feature".to_string())
+        }
+    }
+}
@@ -0,0 +1,327 @@
+// session.rs
+// One client's signaling session, independent of the transport carrying it. The WebSocket
+// handler and the long-polling endpoint (poll.rs) both feed the frames they receive to
+// Session::receive and end the session the same way, so joins, authentication, protocol
+// negotiation, duplicate detection and routing behave identically over either.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn, Instrument};
+use warp::ws::Message;
+use ws2infer_protocol::chunk::Reassembler;
+use ws2infer_protocol::{deflate, Negotiated, CAP_CHUNKING};
+
+use crate::Clients;
+use crate::client_queue::ClientHandle;
+use crate::room::{self, RoomManager};
+use crate::server_inference::{self, ServerInference};
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::replay::{self, ReplayRequest};
+use crate::state::SharedState;
+use crate::{audit, auth, connection_log, tenant, webhooks};
+
+pub struct Session {
+    state: SharedState,
+    room_id: String,
+    room_manager: Arc<RwLock<RoomManager>>,
+    clients: Clients,
+    server_inference: Option<Arc<ServerInference>>,
+    client_ip: Option<IpAddr>,
+    /// User-Agent of the handshake, kept for the connection registry
+    user_agent: Option<String>,
+    /// Outgoing queue; registered in Clients once the first join names a connection_id
+    client: ClientHandle,
+    connection_id: Option<String>,
+    /// A join of this connection is open in the connection registry
+    registered: bool,
+    /// Messages the client is sending in chunks
+    chunks: Reassembler,
+}
+
+impl Session {
+    pub fn new(
+        state: SharedState,
+        room_id: String,
+        room_manager: Arc<RwLock<RoomManager>>,
+        clients: Clients,
+        server_inference: Option<Arc<ServerInference>>,
+        client_ip: Option<IpAddr>,
+    ) -> Self {
+        let max_chunked_bytes = state.message_size.get().max_chunked_bytes;
+        Self {
+            state,
+            room_id,
+            room_manager,
+            clients,
+            server_inference,
+            client_ip,
+            user_agent: None,
+            client: ClientHandle::new(),
+            connection_id: None,
+            registered: false,
+            chunks: Reassembler::new(max_chunked_bytes),
+        }
+    }
+
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn client(&self) -> &ClientHandle {
+        &self.client
+    }
+
+    /// Handles one frame from the client: a JPEG upload, or a (possibly compressed) JSON
+    /// SignalingMessage, which may be a chunk of a larger one
+    pub async fn receive(&mut self, msg: &Message) {
+        // Binary JPEG frames are uploads for server-side inference
+        if msg.is_binary() && msg.as_bytes().starts_with(&server_inference::JPEG_MAGIC) {
+            let result = match (&self.server_inference, &self.connection_id) {
+                (Some(inference), Some(cid)) => inference.submit(&self.room_manager, &self.room_id, cid, msg.as_bytes()).await,
+                (None, _) => Err("Server-side inference is not enabled".to_string()),
+                (_, None) => Err("Join the room before uploading frames".to_string()),
+            };
+            if let (Err(e), Some(cid)) = (result, &self.connection_id) {
+                crate::route_responses(&self.state, &self.clients, vec![SignalingMessage::new_error(cid.clone(), e)]).await;
+            }
+            return;
+        }
+
+        if !msg.is_text() && !msg.is_binary() {
+            return;
+        }
+        let max_message_bytes = self.state.message_size.get().max_message_bytes;
+        if msg.as_bytes().len() > max_message_bytes {
+            let reason = format!("Message of {} bytes exceeds the limit of {} bytes; send it in chunks", msg.as_bytes().len(), max_message_bytes);
+            self.refuse(reason).await;
+            return;
+        }
+
+        // Other binary frames carry the same UTF-8 JSON as text frames, possibly compressed
+        let inflated = if msg.is_binary() && deflate::is_compressed(msg.as_bytes()) {
+            match deflate::decompress(msg.as_bytes()) {
+                Ok(json) => Some(json),
+                Err(e) => {
+                    warn!("Dropped undecodable compressed frame: {}", e);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let payload = inflated.as_deref().unwrap_or(msg.as_bytes());
+        let Ok(signaling_msg) = serde_json::from_slice::<SignalingMessage>(payload) else {
+            return;
+        };
+
+        // Larger messages come as chunks, which are joined here before anything else
+        let signaling_msg = match signaling_msg.message_type {
+            SignalingMessageType::Chunk | SignalingMessageType::ChunkEnd => {
+                let joined = self.chunks.push(&signaling_msg).and_then(|text| match text {
+                    Some(text) => serde_json::from_str::<SignalingMessage>(&text)
+                        .map(Some)
+                        .map_err(|e| format!("invalid message: {}", e)),
+                    None => Ok(None),
+                });
+                match joined {
+                    Ok(Some(whole)) if !matches!(whole.message_type, SignalingMessageType::Chunk | SignalingMessageType::ChunkEnd) => whole,
+                    Ok(Some(_)) => {
+                        self.refuse("Chunks must not contain chunks".to_string()).await;
+                        return;
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        self.refuse(format!("Dropped chunked message: {}", e)).await;
+                        return;
+                    }
+                }
+            }
+            _ => signaling_msg,
+        };
+        self.handle(signaling_msg).await;
+    }
+
+    /// Tells the client why its message was dropped, once it has a connection_id
+    async fn refuse(&self, reason: String) {
+        warn!("Dropped message in room {}: {}", self.room_id, reason);
+        if let Some(cid) = &self.connection_id {
+            crate::route_responses(&self.state, &self.clients, vec![SignalingMessage::new_error(cid.clone(), reason)]).await;
+        }
+    }
+
+    /// Handles one whole SignalingMessage from the client
+    async fn handle(&mut self, mut signaling_msg: SignalingMessage) {
+        // A retransmission of something this connection already sent
+        if signaling_msg.msg_id.as_deref().is_some_and(|msg_id| self.client.is_duplicate(msg_id)) {
+            debug!("Dropped duplicate message {:?}", signaling_msg.msg_id);
+            return;
+        }
+
+        // The first join binds the session to its connection_id. Outside a join connection_id
+        // names the target, and the room takes sender_id as the author, so sender_id is always
+        // overwritten with the bound connection
+        let is_join = matches!(signaling_msg.message_type, SignalingMessageType::Join);
+        match (&self.connection_id, is_join) {
+            (None, true) => {
+                if let Some(ref cid) = signaling_msg.connection_id {
+                    self.connection_id = Some(cid.clone());
+                    // Register client
+                    self.clients.write().await.insert(cid.clone(), self.client.clone());
+                    tracing::Span::current().record("connection_id", cid.as_str());
+                    info!("Registered client: {}", cid);
+                }
+            }
+            (None, false) => {
+                debug!("Dropped {:?} sent before joining room {}", signaling_msg.message_type, self.room_id);
+                return;
+            }
+            (Some(bound), true) if signaling_msg.connection_id.as_ref() != Some(bound) => {
+                self.refuse(format!("This connection joined as {}", bound)).await;
+                return;
+            }
+            (Some(_), true) => {}
+            (Some(bound), false) => signaling_msg.sender_id = Some(bound.clone()),
+        }
+
+        // With an identity provider, a sender's ID token is checked before the room sees its join
+        if let (SignalingMessageType::Join, Some(true), Some(cid)) = (&signaling_msg.message_type, signaling_msg.is_sender, &self.connection_id) {
+            let id_token = signaling_msg.data.as_ref()
+                .and_then(|data| data.get("id_token"))
+                .and_then(|token| token.as_str());
+            match auth::authorize(&self.state, id_token, auth::Permission::Send).await {
+                // A token tied to a tenant only sends into that tenant's rooms
+                Ok(Some(identity)) if identity.tenant.is_some() && identity.tenant.as_deref() != tenant::tenant_of(&self.room_id) => {
+                    let message = "Not allowed to join rooms of another tenant".to_string();
+                    audit::record(&self.state, audit::AUTH_FAILED, cid, self.client_ip, Some(&self.room_id), serde_json::json!({"error": message}));
+                    crate::route_responses(&self.state, &self.clients, vec![SignalingMessage::new_error(cid.clone(), message)]).await;
+                    return;
+                }
+                Ok(Some(identity)) => info!("Sender {} authenticated as {}", cid, identity.subject),
+                Ok(None) => {}
+                Err(refusal) => {
+                    audit::record(&self.state, audit::AUTH_FAILED, cid, self.client_ip, Some(&self.room_id), serde_json::json!({"error": refusal.message}));
+                    crate::route_responses(&self.state, &self.clients, vec![SignalingMessage::new_error(cid.clone(), refusal.message)]).await;
+                    return;
+                }
+            }
+        }
+
+        // Clients that declare a protocol_version are welcomed before room_info
+        if let (SignalingMessageType::Join, Some(cid)) = (&signaling_msg.message_type, &self.connection_id) {
+            let mut negotiated = Negotiated::from_join(signaling_msg.data.as_ref());
+            // Chunking clients split what they send above this, and so does the server
+            negotiated.max_message_bytes = negotiated.supports(CAP_CHUNKING).then(|| self.state.message_size.get().max_message_bytes);
+            self.client.set_protocol(negotiated.clone());
+            if !negotiated.is_legacy() {
+                crate::route_responses(&self.state, &self.clients, vec![SignalingMessage::new_welcome(cid.clone(), &negotiated)]).await;
+            }
+        }
+
+        // Replays are served to this connection straight from the database
+        if let (SignalingMessageType::Replay, Some(cid)) = (&signaling_msg.message_type, &self.connection_id) {
+            let responses = self.replay(cid.clone(), signaling_msg.data).await;
+            crate::route_responses(&self.state, &self.clients, responses).await;
+            return;
+        }
+
+        // With a backplane, joins may be for rooms and members of other instances
+        let join = matches!(signaling_msg.message_type, SignalingMessageType::Join).then(|| signaling_msg.clone());
+        if let (Some(_), Some(backplane)) = (&join, self.state.backplane()) {
+            backplane.prepare_join(&self.room_id).await;
+        }
+
+        // Lets one offer/answer exchange be followed across both connections
+        let span = tracing::info_span!(
+            "signaling_message",
+            connection_id = ?self.connection_id,
+            message_type = ?signaling_msg.message_type,
+            offer_id = ?signaling_msg.offer_id,
+        );
+        let joined = async {
+            let mut manager = self.room_manager.write().await;
+            if let Some(responses) = manager.handle_message(self.room_id.clone(), signaling_msg) {
+                if let (Some(error), Some(cid)) = (responses.iter().find_map(room::join_auth_error), &self.connection_id) {
+                    audit::record(&self.state, audit::AUTH_FAILED, cid, self.client_ip, Some(&self.room_id), serde_json::json!({"error": error}));
+                }
+                crate::route_responses(&self.state, &self.clients, responses).await;
+            }
+            let joined = join.and_then(|join| {
+                let room = manager.rooms.get(&self.room_id)?;
+                let role = room.connections.get(join.connection_id.as_ref()?)?.role;
+                Some((join, role))
+            });
+            // Only this sender is connected, counting members mirrored from other instances
+            if let (Some((join, role)), Some(room)) = (&joined, manager.rooms.get(&self.room_id)) {
+                if *role == room::Role::Sender && room.connections.values().filter(|info| info.is_sender).count() == 1 {
+                    self.state.webhooks.emit(webhooks::SENDER_JOINED, &self.room_id, serde_json::json!({"connection_id": join.connection_id}));
+                }
+            }
+            joined
+        }
+        .instrument(span)
+        .await;
+        if let (Some((_, role)), Some(cid)) = (&joined, &self.connection_id) {
+            connection_log::joined(&self.state.db_path(), cid, &self.room_id, *role, self.client_ip, self.user_agent.as_deref()).await;
+            self.registered = true;
+        }
+        if let (Some((join, _)), Some(backplane)) = (joined, self.state.backplane()) {
+            backplane.joined(&self.room_id, &join).await;
+        }
+    }
+
+    /// Starts or (with no data) stops a replay for this connection; the replay reports its
+    /// progress itself, so only errors and stops are returned
+    async fn replay(&self, cid: String, data: Option<serde_json::Value>) -> Vec<SignalingMessage> {
+        let member = self.room_manager.read().await.rooms.get(&self.room_id).is_some_and(|room| room.connections.contains_key(&cid));
+        if !member {
+            return vec![SignalingMessage::new_error(cid, "Join the room before requesting a replay".to_string())];
+        }
+        let Some(data) = data.filter(|data| !data.is_null()) else {
+            if !replay::stop(&cid) {
+                return Vec::new();
+            }
+            return vec![SignalingMessage::new_replay_status(cid, serde_json::json!({"status": "stopped"}))];
+        };
+        let request = match serde_json::from_value::<ReplayRequest>(data) {
+            Ok(request) => request,
+            Err(e) => return vec![SignalingMessage::new_error(cid, format!("Invalid replay request: {}", e))],
+        };
+        match replay::start(self.state.clone(), self.clients.clone(), self.room_id.clone(), cid.clone(), request).await {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![SignalingMessage::new_error(cid, e)],
+        }
+    }
+
+    /// Removes the connection from its room and from Clients, as when its socket closes
+    pub async fn end(self, transport: &str) {
+        let Some(cid) = self.connection_id else {
+            info!("{} connection closed for room: {} (no connection_id established)", transport, self.room_id);
+            return;
+        };
+        let mut manager = self.room_manager.write().await;
+        if let Some(responses) = manager.remove_connection(&self.room_id, &cid) {
+            if manager.rooms.get(&self.room_id).is_some_and(|room| room.connections.is_empty()) {
+                self.state.webhooks.emit(webhooks::ROOM_EMPTIED, &self.room_id, serde_json::json!({"connection_id": cid}));
+            }
+            crate::route_responses(&self.state, &self.clients, responses).await;
+            if let Some(backplane) = self.state.backplane() {
+                backplane.left(&self.room_id, &cid).await;
+            }
+        }
+        drop(manager);
+
+        let mut clients_guard = self.clients.write().await;
+        clients_guard.remove(&cid);
+        if let Some(inference) = &self.server_inference {
+            inference.forget(&cid);
+        }
+        replay::stop(&cid);
+        if self.registered {
+            connection_log::left(&self.state.db_path(), &cid, &self.room_id).await;
+        }
+
+        info!("{} connection closed for room: {}, connection: {}", transport, self.room_id, cid);
+    }
+}
@@ -0,0 +1,500 @@
+// sfu.rs
+// Selective forwarding for rooms created with mode "sfu". A forwarding peer joins the room as a
+// LocalPeer; the sender only ever sees this peer, so it uploads its stream once, and the peer
+// re-sends every received RTP packet to one peer connection per viewer. Nothing is decoded, so
+// viewers receive whatever codec the sender negotiated. Keyframe requests from viewers are
+// passed on to the sender. When the sender uses simulcast, each viewer gets one layer of each
+// video track, the one it picked with SubscribeLayer (else the first to arrive), and is told the
+// layers on offer with LayerInfo. With HLS egress configured, rooms without E2EE negotiate
+// H.264 only and the first H.264 video track of the sender is also packaged for HLS players.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn, error};
+use serde_json::Value;
+use tokio::sync::{mpsc, Notify, RwLock};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
+use webrtc::api::{APIBuilder, API};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType};
+use webrtc::rtp_transceiver::RTCPFeedback;
+use webrtc::rtp_transceiver::rtp_receiver::RTCRtpReceiver;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::rtp_transceiver::RTCRtpTransceiver;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+use webrtc::track::track_remote::TrackRemote;
+
+use crate::Clients;
+use crate::hls::HlsStream;
+use crate::local_peer::LocalPeer;
+use crate::room::RoomManager;
+use crate::signaling::{SignalingMessage, SignalingMessageType};
+use crate::state::SharedState;
+
+/// Tracks of one upstream offer arrive one by one; viewers are re-offered once they settle
+const RENEGOTIATE_DELAY: Duration = Duration::from_millis(300);
+
+/// Starts the forwarding peer of an SFU room; it runs until its queue is closed
+pub fn spawn(state: SharedState, room_id: String, room_manager: Arc<RwLock<RoomManager>>, clients: Clients) {
+    tokio::task::spawn(async move {
+        // Registered before joining so the sender is told about this peer, and only this peer
+        let connection_id = format!("sfu-{}", room_id);
+        let packaged = match room_manager.write().await.rooms.get_mut(&room_id) {
+            Some(room) => {
+                room.sfu_connection_id = Some(connection_id.clone());
+                // Encrypted frames can't be packaged
+                state.hls.is_enabled() && !room.e2ee
+            }
+            None => return,
+        };
+        let api = match new_api(packaged) {
+            Ok(api) => api,
+            Err(e) => {
+                error!("SFU for room {} could not start: {}", room_id, e);
+                if let Some(room) = room_manager.write().await.rooms.get_mut(&room_id) {
+                    room.sfu_connection_id = None;
+                }
+                return;
+            }
+        };
+        let peer = LocalPeer::join(state, room_id.clone(), connection_id, room_manager, clients, serde_json::json!({ "sfu": true })).await;
+        info!("SFU forwarding started in room {}", room_id);
+
+        let mut forwarder = Forwarder::new(peer.clone(), api, packaged);
+        forwarder.run().await;
+        forwarder.close().await;
+        peer.leave().await;
+        info!("SFU forwarding stopped in room {}", room_id);
+    });
+}
+
+/// All default codecs, so whatever the sender picks is forwarded as-is; H.264 and Opus only
+/// when the stream is `packaged` for HLS
+fn new_api(packaged: bool) -> Result<API, webrtc::Error> {
+    let mut media_engine = MediaEngine::default();
+    if packaged {
+        register_hls_codecs(&mut media_engine)?;
+    } else {
+        media_engine.register_default_codecs()?;
+    }
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    Ok(APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build())
+}
+
+/// Constrained baseline and baseline H.264 in packetization mode 1, as browsers offer them
+fn register_hls_codecs(media_engine: &mut MediaEngine) -> Result<(), webrtc::Error> {
+    let feedback = vec![
+        RTCPFeedback { typ: "goog-remb".to_owned(), parameter: String::new() },
+        RTCPFeedback { typ: "ccm".to_owned(), parameter: "fir".to_owned() },
+        RTCPFeedback { typ: "nack".to_owned(), parameter: String::new() },
+        RTCPFeedback { typ: "nack".to_owned(), parameter: "pli".to_owned() },
+    ];
+    for (payload_type, profile) in [(102, "42001f"), (125, "42e01f")] {
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_H264.to_owned(),
+                    clock_rate: 90_000,
+                    channels: 0,
+                    sdp_fmtp_line: format!("level-asymmetry-allowed=1;packetization-mode=1;profile-level-id={}", profile),
+                    rtcp_feedback: feedback.clone(),
+                },
+                payload_type,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )?;
+    }
+    media_engine.register_codec(
+        RTCRtpCodecParameters {
+            capability: RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_OPUS.to_owned(),
+                clock_rate: 48_000,
+                channels: 2,
+                sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                rtcp_feedback: Vec::new(),
+            },
+            payload_type: 111,
+            ..Default::default()
+        },
+        RTPCodecType::Audio,
+    )
+}
+
+struct Upstream {
+    sender_id: String,
+    peer_connection: Arc<RTCPeerConnection>,
+}
+
+/// Local copy of one of the sender's tracks; a simulcast track arrives once per layer
+#[derive(Clone)]
+struct ForwardedTrack {
+    /// Simulcast layer, empty without simulcast
+    rid: String,
+    local: Arc<TrackLocalStaticRTP>,
+}
+
+struct Viewer {
+    peer_connection: Arc<RTCPeerConnection>,
+    /// RTP senders of simulcast tracks by track ID, whose layer SubscribeLayer switches
+    layer_senders: HashMap<String, Arc<RTCRtpSender>>,
+}
+
+/// What a viewer is sent: every plain track, and of each simulcast track the layer `rid` when it
+/// exists, else the first layer that arrived
+fn select_tracks<'a>(tracks: &'a [ForwardedTrack], rid: Option<&str>) -> Vec<&'a ForwardedTrack> {
+    let mut selected: Vec<&ForwardedTrack> = Vec::new();
+    for track in tracks {
+        match selected.iter().position(|chosen| chosen.local.id() == track.local.id()) {
+            None => selected.push(track),
+            Some(index) if !track.rid.is_empty() && Some(track.rid.as_str()) == rid => selected[index] = track,
+            Some(_) => {}
+        }
+    }
+    selected
+}
+
+/// Simulcast layers the sender is sending, in arrival order
+fn layer_rids(tracks: &[ForwardedTrack]) -> Vec<String> {
+    let mut rids: Vec<String> = Vec::new();
+    for track in tracks.iter().filter(|track| !track.rid.is_empty()) {
+        if !rids.contains(&track.rid) {
+            rids.push(track.rid.clone());
+        }
+    }
+    rids
+}
+
+struct Forwarder {
+    peer: LocalPeer,
+    api: API,
+    upstream: Option<Upstream>,
+    /// Local copies of the sender's tracks, shared by every viewer connection
+    tracks: Arc<Mutex<Vec<ForwardedTrack>>>,
+    /// SSRCs of the sender's video tracks, for keyframe requests
+    video_ssrcs: Arc<Mutex<Vec<u32>>>,
+    tracks_changed: Arc<Notify>,
+    keyframe_tx: mpsc::UnboundedSender<()>,
+    keyframe_rx: mpsc::UnboundedReceiver<()>,
+    viewer_ids: HashSet<String>,
+    viewers: HashMap<String, Viewer>,
+    /// Simulcast layer each viewer asked for
+    layers: HashMap<String, String>,
+    /// HLS packaging of the sender's video, when configured
+    hls: Option<Arc<HlsStream>>,
+}
+
+impl Forwarder {
+    fn new(peer: LocalPeer, api: API, packaged: bool) -> Self {
+        let (keyframe_tx, keyframe_rx) = mpsc::unbounded_channel();
+        let hls = if packaged { peer.state().hls.start(&peer.room_id, keyframe_tx.clone()) } else { None };
+        Self {
+            peer,
+            api,
+            upstream: None,
+            tracks: Arc::default(),
+            video_ssrcs: Arc::default(),
+            tracks_changed: Arc::new(Notify::new()),
+            keyframe_tx,
+            keyframe_rx,
+            viewer_ids: HashSet::new(),
+            viewers: HashMap::new(),
+            layers: HashMap::new(),
+            hls,
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                message = self.peer.recv() => match message {
+                    Some(message) => self.handle(message).await,
+                    None => break,
+                },
+                _ = self.tracks_changed.notified() => {
+                    tokio::time::sleep(RENEGOTIATE_DELAY).await;
+                    let viewer_ids: Vec<String> = self.viewer_ids.iter().cloned().collect();
+                    for viewer_id in viewer_ids {
+                        self.offer_to(viewer_id).await;
+                    }
+                }
+                Some(()) = self.keyframe_rx.recv() => self.request_keyframe().await,
+            }
+        }
+    }
+
+    async fn handle(&mut self, message: SignalingMessage) {
+        let data = message.data.clone().unwrap_or(Value::Null);
+        let is_sender = |peer: &Value| peer.get("is_sender").and_then(Value::as_bool).unwrap_or(false);
+
+        match message.message_type {
+            SignalingMessageType::RoomInfo => {
+                let viewers: Vec<String> = data.get("peers")
+                    .and_then(Value::as_array)
+                    .map(|peers| peers.iter()
+                        .filter(|peer| !is_sender(peer))
+                        .filter_map(|peer| peer.get("id").and_then(Value::as_str).map(str::to_string))
+                        .collect())
+                    .unwrap_or_default();
+                for viewer_id in viewers {
+                    self.viewer_ids.insert(viewer_id.clone());
+                    self.offer_to(viewer_id).await;
+                }
+            }
+            SignalingMessageType::NewPeer if !is_sender(&data) => {
+                if let Some(viewer_id) = data.get("connection_id").and_then(Value::as_str) {
+                    self.viewer_ids.insert(viewer_id.to_string());
+                    self.offer_to(viewer_id.to_string()).await;
+                }
+            }
+            SignalingMessageType::Offer => {
+                let sender_id = match message.sender_id {
+                    Some(sender_id) => sender_id,
+                    None => return,
+                };
+                if let Err(e) = self.answer_upstream(sender_id.clone(), message.data).await {
+                    warn!("SFU in room {} could not answer {}: {}", self.peer.room_id, sender_id, e);
+                }
+            }
+            SignalingMessageType::Answer => {
+                let answer = message.data.and_then(|data| serde_json::from_value::<RTCSessionDescription>(data).ok());
+                let viewer = message.sender_id.as_ref().and_then(|id| self.viewers.get(id));
+                if let (Some(viewer), Some(answer)) = (viewer, answer) {
+                    if let Err(e) = viewer.peer_connection.set_remote_description(answer).await {
+                        warn!("SFU in room {} rejected an answer: {}", self.peer.room_id, e);
+                    }
+                }
+            }
+            SignalingMessageType::IceCandidate => {
+                let candidate = match message.data.and_then(|data| serde_json::from_value::<RTCIceCandidateInit>(data).ok()) {
+                    Some(candidate) => candidate,
+                    None => return,
+                };
+                let from = message.sender_id.unwrap_or_default();
+                let peer_connection = match &self.upstream {
+                    Some(upstream) if upstream.sender_id == from => Some(upstream.peer_connection.clone()),
+                    _ => self.viewers.get(&from).map(|viewer| viewer.peer_connection.clone()),
+                };
+                if let Some(peer_connection) = peer_connection {
+                    let _ = peer_connection.add_ice_candidate(candidate).await;
+                }
+            }
+            SignalingMessageType::Leave => {
+                let left = match data.get("connection_id").and_then(Value::as_str) {
+                    Some(left) => left.to_string(),
+                    None => return,
+                };
+                if matches!(&self.upstream, Some(upstream) if upstream.sender_id == left) {
+                    // Viewers keep their connections and are re-offered when the sender returns
+                    self.close_upstream().await;
+                } else {
+                    self.viewer_ids.remove(&left);
+                    self.layers.remove(&left);
+                    if let Some(viewer) = self.viewers.remove(&left) {
+                        let _ = viewer.peer_connection.close().await;
+                    }
+                }
+            }
+            SignalingMessageType::SubscribeLayer => {
+                let (viewer_id, rid) = match (message.sender_id, data.get("rid").and_then(Value::as_str)) {
+                    (Some(viewer_id), Some(rid)) => (viewer_id, rid.to_string()),
+                    _ => return,
+                };
+                self.layers.insert(viewer_id.clone(), rid.clone());
+                self.switch_layer(&viewer_id, &rid).await;
+            }
+            _ => {}
+        }
+    }
+
+    async fn close_upstream(&mut self) {
+        if let Some(upstream) = self.upstream.take() {
+            let _ = upstream.peer_connection.close().await;
+        }
+        self.tracks.lock().unwrap().clear();
+        self.video_ssrcs.lock().unwrap().clear();
+    }
+
+    async fn answer_upstream(&mut self, sender_id: String, offer: Option<Value>) -> Result<(), String> {
+        let offer: RTCSessionDescription = offer
+            .and_then(|offer| serde_json::from_value(offer).ok())
+            .ok_or_else(|| "offer without a session description".to_string())?;
+        self.close_upstream().await;
+
+        let peer_connection = Arc::new(
+            self.api.new_peer_connection(RTCConfiguration::default()).await.map_err(|e| e.to_string())?,
+        );
+        self.peer.forward_ice_candidates(&peer_connection, sender_id.clone());
+
+        let tracks = self.tracks.clone();
+        let video_ssrcs = self.video_ssrcs.clone();
+        let tracks_changed = self.tracks_changed.clone();
+        if let Some(hls) = &self.hls {
+            hls.restart();
+        }
+        // Simulcast sends each layer as a track of its own; one of them is packaged
+        let hls = self.hls.clone();
+        let packaging = Arc::new(AtomicBool::new(false));
+        peer_connection.on_track(Box::new(move |remote: Arc<TrackRemote>, _: Arc<RTCRtpReceiver>, _: Arc<RTCRtpTransceiver>| {
+            let local = Arc::new(TrackLocalStaticRTP::new(
+                remote.codec().capability,
+                remote.id(),
+                remote.stream_id(),
+            ));
+            tracks.lock().unwrap().push(ForwardedTrack { rid: remote.rid().to_string(), local: local.clone() });
+            if remote.kind() == RTPCodecType::Video {
+                video_ssrcs.lock().unwrap().push(remote.ssrc());
+            }
+            tracks_changed.notify_one();
+
+            let is_h264 = remote.kind() == RTPCodecType::Video && remote.codec().capability.mime_type.eq_ignore_ascii_case(MIME_TYPE_H264);
+            let hls = hls.clone().filter(|_| is_h264 && !packaging.swap(true, Ordering::SeqCst));
+            tokio::task::spawn(async move {
+                while let Ok((packet, _)) = remote.read_rtp().await {
+                    if let Some(hls) = &hls {
+                        hls.push_rtp(&packet);
+                    }
+                    // Fails only for viewers whose connection is closing; the others still get it
+                    let _ = local.write_rtp(&packet).await;
+                }
+            });
+            Box::pin(async {})
+        }));
+
+        peer_connection.set_remote_description(offer).await.map_err(|e| e.to_string())?;
+        let answer = peer_connection.create_answer(None).await.map_err(|e| e.to_string())?;
+        peer_connection.set_local_description(answer.clone()).await.map_err(|e| e.to_string())?;
+        let answer = serde_json::to_value(answer).map_err(|e| e.to_string())?;
+        self.peer.signal(SignalingMessage::new_answer(sender_id.clone(), self.peer.connection_id.clone(), answer)).await;
+
+        self.upstream = Some(Upstream { sender_id, peer_connection });
+        Ok(())
+    }
+
+    /// (Re)creates the viewer's connection with the current tracks and offers it; viewers
+    /// replace an existing connection from the same sender_id when a new offer arrives
+    async fn offer_to(&mut self, viewer_id: String) {
+        let tracks = self.tracks.lock().unwrap().clone();
+        if tracks.is_empty() {
+            // Offered once the sender's tracks arrive
+            return;
+        }
+        if let Some(previous) = self.viewers.remove(&viewer_id) {
+            let _ = previous.peer_connection.close().await;
+        }
+
+        match self.create_viewer_connection(&viewer_id, &tracks).await {
+            Ok(viewer) => {
+                self.viewers.insert(viewer_id, viewer);
+                // A new viewer can't decode anything until the next keyframe
+                self.request_keyframe().await;
+            }
+            Err(e) => warn!("SFU in room {} could not offer to {}: {}", self.peer.room_id, viewer_id, e),
+        }
+    }
+
+    async fn create_viewer_connection(&self, viewer_id: &str, tracks: &[ForwardedTrack]) -> Result<Viewer, String> {
+        let peer_connection = Arc::new(
+            self.api.new_peer_connection(RTCConfiguration::default()).await.map_err(|e| e.to_string())?,
+        );
+        self.peer.forward_ice_candidates(&peer_connection, viewer_id.to_string());
+
+        let selected = select_tracks(tracks, self.layers.get(viewer_id).map(String::as_str));
+        let mut layer_senders = HashMap::new();
+        let mut active_layer = None;
+        for track in selected {
+            let rtp_sender = peer_connection
+                .add_track(track.local.clone() as Arc<dyn TrackLocal + Send + Sync>)
+                .await
+                .map_err(|e| e.to_string())?;
+            if !track.rid.is_empty() {
+                layer_senders.insert(track.local.id().to_string(), rtp_sender.clone());
+                active_layer = Some(track.rid.clone());
+            }
+            // Viewers' PLI/FIR become keyframe requests to the sender
+            let keyframe_tx = self.keyframe_tx.clone();
+            tokio::task::spawn(async move {
+                while let Ok((packets, _)) = rtp_sender.read_rtcp().await {
+                    let wants_keyframe = packets.iter().any(|packet| {
+                        packet.as_any().downcast_ref::<PictureLossIndication>().is_some()
+                            || packet.as_any().downcast_ref::<FullIntraRequest>().is_some()
+                    });
+                    if wants_keyframe && keyframe_tx.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let offer = peer_connection.create_offer(None).await.map_err(|e| e.to_string())?;
+        peer_connection.set_local_description(offer.clone()).await.map_err(|e| e.to_string())?;
+        let offer = serde_json::to_value(offer).map_err(|e| e.to_string())?;
+        self.peer.signal(SignalingMessage::new_offer(viewer_id.to_string(), self.peer.connection_id.clone(), offer)).await;
+        if let Some(active) = &active_layer {
+            let layers = layer_rids(tracks);
+            self.peer.signal(SignalingMessage::new_layer_info(Some(viewer_id.to_string()), self.peer.connection_id.clone(), &layers, Some(active))).await;
+        }
+        Ok(Viewer { peer_connection, layer_senders })
+    }
+
+    /// Sends the viewer layer `rid` of its simulcast tracks from now on; unknown layers are
+    /// remembered for when the sender starts sending them
+    async fn switch_layer(&self, viewer_id: &str, rid: &str) {
+        let viewer = match self.viewers.get(viewer_id) {
+            Some(viewer) => viewer,
+            None => return,
+        };
+        let tracks = self.tracks.lock().unwrap().clone();
+        let mut switched = false;
+        for (track_id, rtp_sender) in &viewer.layer_senders {
+            let layer = tracks.iter().find(|track| track.rid == rid && track.local.id() == track_id.as_str());
+            if let Some(layer) = layer {
+                match rtp_sender.replace_track(Some(layer.local.clone() as Arc<dyn TrackLocal + Send + Sync>)).await {
+                    Ok(()) => switched = true,
+                    Err(e) => warn!("SFU in room {} could not switch {} to layer {}: {}", self.peer.room_id, viewer_id, rid, e),
+                }
+            }
+        }
+        if switched {
+            // The new layer is only decodable from its next keyframe
+            self.request_keyframe().await;
+            let layers = layer_rids(&tracks);
+            self.peer.signal(SignalingMessage::new_layer_info(Some(viewer_id.to_string()), self.peer.connection_id.clone(), &layers, Some(rid))).await;
+        }
+    }
+
+    async fn request_keyframe(&self) {
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream,
+            None => return,
+        };
+        let ssrcs = self.video_ssrcs.lock().unwrap().clone();
+        for media_ssrc in ssrcs {
+            let pli = PictureLossIndication { sender_ssrc: 0, media_ssrc };
+            let _ = upstream.peer_connection.write_rtcp(&[Box::new(pli)]).await;
+        }
+    }
+
+    async fn close(&mut self) {
+        if let Some(hls) = self.hls.take() {
+            self.peer.state().hls.stop(&self.peer.room_id, &hls);
+        }
+        self.close_upstream().await;
+        for (_, viewer) in self.viewers.drain() {
+            let _ = viewer.peer_connection.close().await;
+        }
+    }
+}
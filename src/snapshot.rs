@@ -0,0 +1,207 @@
+// snapshot.rs
+// Still frames uploaded by senders, so viewers can look at the frame a detection was made on
+// after the live stream has moved on. Uploads come as multipart/form-data or as JSON with a
+// base64 image; the image is written under the snapshot directory and its metadata (with the
+// capture timestamp that matches the inference result's) goes to SQLite.
+
+use std::fs;
+use std::path::Path;
+use base64::Engine;
+use bytes::Buf;
+use futures_util::TryStreamExt;
+use tracing::warn;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::persistence::{self, SnapshotRecord};
+use crate::tenant;
+
+/// Largest accepted image; JSON uploads may be a third larger for the base64 encoding
+pub const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+/// Listing returns at most this many snapshots
+pub const MAX_LIST_LIMIT: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    /// Images are written below this directory, one subdirectory per room
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Older snapshots of a room are deleted once it has more than this many
+    #[serde(default = "default_max_per_room")]
+    pub max_per_room: u64,
+}
+
+fn default_directory() -> String { "data/snapshots".to_string() }
+fn default_max_per_room() -> u64 { 500 }
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_directory(),
+            max_per_room: default_max_per_room(),
+        }
+    }
+}
+
+impl SnapshotConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.directory.is_empty() {
+            return Err("directory must not be empty".to_string());
+        }
+        if self.max_per_room == 0 {
+            return Err("max_per_room must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    /// Sniffed from the data; the declared content type isn't trusted
+    fn detect(image: &[u8]) -> Option<Self> {
+        if image.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if image.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(Self::Png)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// JSON form of an upload; `image` is base64, optionally as a `data:` URL
+#[derive(Debug, Deserialize)]
+pub struct SnapshotJson {
+    pub source_id: String,
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    pub image: String,
+}
+
+#[derive(Debug)]
+pub struct SnapshotUpload {
+    pub source_id: String,
+    /// Capture time in unix milliseconds; the upload time when the sender didn't say
+    pub timestamp: i64,
+    image: Vec<u8>,
+    format: ImageFormat,
+}
+
+impl SnapshotUpload {
+    fn new(source_id: String, timestamp: Option<i64>, image: Vec<u8>) -> Result<Self, String> {
+        if source_id.is_empty() {
+            return Err("source_id is required".to_string());
+        }
+        if matches!(timestamp, Some(ts) if ts <= 0) {
+            return Err("timestamp must be a positive unix time in milliseconds".to_string());
+        }
+        if image.len() as u64 > MAX_IMAGE_BYTES {
+            return Err(format!("Image of {} bytes exceeds the limit of {}", image.len(), MAX_IMAGE_BYTES));
+        }
+        let format = ImageFormat::detect(&image).ok_or_else(|| "image must be a JPEG or PNG".to_string())?;
+        Ok(Self {
+            source_id,
+            timestamp: timestamp.unwrap_or_else(|| chrono::Utc::now().timestamp_millis()),
+            image,
+            format,
+        })
+    }
+
+    pub fn from_json(body: SnapshotJson) -> Result<Self, String> {
+        let encoded = match body.image.split_once(";base64,") {
+            Some((prefix, data)) if prefix.starts_with("data:") => data,
+            _ => body.image.as_str(),
+        };
+        let image = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("image is not valid base64: {}", e))?;
+        Self::new(body.source_id, body.timestamp, image)
+    }
+
+    /// Reads the `image` file part and the `source_id` and `timestamp` fields of a form
+    pub async fn from_multipart(form: warp::multipart::FormData) -> Result<Self, String> {
+        let mut source_id = None;
+        let mut timestamp = None;
+        let mut image = None;
+
+        let mut parts = form;
+        while let Some(part) = parts.try_next().await.map_err(|e| e.to_string())? {
+            let name = part.name().to_string();
+            let data = part
+                .stream()
+                .try_fold(Vec::new(), |mut data, chunk| async move {
+                    data.extend_from_slice(chunk.chunk());
+                    Ok(data)
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+            match name.as_str() {
+                "image" => image = Some(data),
+                "source_id" => source_id = Some(String::from_utf8_lossy(&data).into_owned()),
+                "timestamp" => {
+                    let value = String::from_utf8_lossy(&data);
+                    timestamp = Some(value.trim().parse::<i64>().map_err(|_| "timestamp must be an integer".to_string())?);
+                }
+                _ => {}
+            }
+        }
+
+        let image = image.ok_or_else(|| "image part is required".to_string())?;
+        Self::new(source_id.unwrap_or_default(), timestamp, image)
+    }
+}
+
+/// Writes the image and its metadata, then drops the room's oldest snapshots beyond
+/// `max_per_room`. Blocking; run it off the async runtime.
+pub fn store(config: &SnapshotConfig, db_path: &str, room_id: &str, upload: SnapshotUpload) -> Result<SnapshotRecord, String> {
+    let directory = tenant::room_dir(Path::new(&config.directory), room_id);
+    fs::create_dir_all(&directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+
+    let snapshot_id = Uuid::new_v4().to_string();
+    let path = directory.join(format!("{}.{}", snapshot_id, upload.format.extension()));
+    fs::write(&path, &upload.image).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let record = SnapshotRecord {
+        snapshot_id,
+        room_id: room_id.to_string(),
+        source_id: upload.source_id,
+        content_type: upload.format.content_type().to_string(),
+        bytes: upload.image.len() as u64,
+        timestamp: upload.timestamp,
+        stored_at: chrono::Utc::now().to_rfc3339(),
+        path: path.to_string_lossy().into_owned(),
+    };
+    if let Err(e) = persistence::save_snapshot(db_path, &record) {
+        let _ = fs::remove_file(&path);
+        return Err(e.to_string());
+    }
+
+    match persistence::prune_snapshots(db_path, room_id, config.max_per_room) {
+        Ok(old) => {
+            for old_path in old {
+                let _ = fs::remove_file(old_path);
+            }
+        }
+        Err(e) => warn!("Failed to prune snapshots of room {}: {}", room_id, e),
+    }
+    Ok(record)
+}
@@ -0,0 +1,81 @@
+// state.rs
+// What one running Server shares with its listeners, sessions and tasks besides the room
+// manager and the clients map: the data directory, the reloadable IP filter, message size limits
+// and trusted proxies, webhooks and the event bus, HLS egress, and the identity provider and
+// backplane once they started. Each Server builds its own, so several servers embedded in one
+// process don't see each other's settings, data or streams.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::auth::AuthProvider;
+use crate::backplane::Backplane;
+use crate::config::Config;
+use crate::event_bus::EventBus;
+use crate::hls::Hls;
+use crate::ip_filter::IpFilter;
+use crate::message_size::MessageSizeLimits;
+use crate::proxy::TrustedProxies;
+use crate::store::{DB_FILE, JSONL_FILE};
+use crate::webhooks::Webhooks;
+
+/// Data directory unless ServerBuilder::with_data_dir names another
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+pub type SharedState = Arc<State>;
+
+pub struct State {
+    data_dir: PathBuf,
+    pub ip_filter: IpFilter,
+    pub message_size: MessageSizeLimits,
+    pub proxies: TrustedProxies,
+    /// Room lifecycle events go out through these, and to the event bus
+    pub webhooks: Webhooks,
+    /// Also gets every inference result
+    pub event_bus: Option<EventBus>,
+    pub hls: Hls,
+    /// Set once the identity provider started
+    pub auth: OnceLock<Arc<dyn AuthProvider>>,
+    /// Set once the backplane connected at startup
+    pub backplane: OnceLock<Arc<Backplane>>,
+}
+
+impl State {
+    /// Starts the webhook and event bus tasks, so it runs inside the server's runtime
+    pub fn new(config: &Config, data_dir: PathBuf) -> Self {
+        let event_bus = config.event_bus.clone().map(EventBus::start);
+        Self {
+            data_dir,
+            ip_filter: IpFilter::new(&config.ip_filter),
+            message_size: MessageSizeLimits::new(&config.message_size),
+            proxies: TrustedProxies::new(&config.reverse_proxy),
+            webhooks: Webhooks::start(&config.webhooks, event_bus.clone()),
+            event_bus,
+            hls: Hls::new(config.hls.clone()),
+            auth: OnceLock::new(),
+            backplane: OnceLock::new(),
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// The SQLite file with stored results, snapshots, the audit log, connections and room groups
+    pub fn db_path(&self) -> String {
+        self.data_dir.join(DB_FILE).to_string_lossy().into_owned()
+    }
+
+    /// The JSONL copy of stored results SqliteStore appends to
+    pub fn jsonl_path(&self) -> String {
+        self.data_dir.join(JSONL_FILE).to_string_lossy().into_owned()
+    }
+
+    pub fn auth(&self) -> Option<&dyn AuthProvider> {
+        self.auth.get().map(|provider| provider.as_ref())
+    }
+
+    pub fn backplane(&self) -> Option<&Arc<Backplane>> {
+        self.backplane.get()
+    }
+}
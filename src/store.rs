@@ -0,0 +1,62 @@
+// store.rs
+// Where accepted inference results are written. The server uses SqliteStore, which inserts
+// into inference.db and appends to inference.jsonl in its data directory; applications
+// embedding the server can pass their own store to ServerBuilder::with_store instead. The store is called
+// while the room manager is locked, so slow backends should queue the write and return. The
+// history endpoints, exports, retention and the anomaly baseline keep reading the SQLite file.
+
+use std::fmt;
+use std::path::Path;
+use serde_json::Value;
+
+use crate::persistence;
+
+/// SQLite file in the data directory
+pub const DB_FILE: &str = "inference.db";
+/// JSONL copy of stored results in the data directory
+pub const JSONL_FILE: &str = "inference.jsonl";
+
+pub trait InferenceStore: Send + Sync {
+    /// Stores one normalized result of `source_id` in `room_id`
+    fn save_inference(&self, room_id: &str, source_id: &str, payload: &Value) -> anyhow::Result<()>;
+}
+
+impl fmt::Debug for dyn InferenceStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InferenceStore")
+    }
+}
+
+/// The built-in store: a SQLite row per result plus a JSONL line for easy editing and transfer
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    pub db_path: String,
+    pub jsonl_path: String,
+}
+
+impl SqliteStore {
+    /// The store of a server whose data directory is `data_dir`
+    pub fn in_dir(data_dir: &Path) -> Self {
+        Self {
+            db_path: data_dir.join(DB_FILE).to_string_lossy().into_owned(),
+            jsonl_path: data_dir.join(JSONL_FILE).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+impl Default for SqliteStore {
+    fn default() -> Self {
+        Self::in_dir(Path::new(crate::state::DEFAULT_DATA_DIR))
+    }
+}
+
+impl InferenceStore for SqliteStore {
+    fn save_inference(&self, room_id: &str, source_id: &str, payload: &Value) -> anyhow::Result<()> {
+        // The JSONL line is still written when the insert fails
+        let sqlite = persistence::save_inference_sqlite(&self.db_path, room_id, source_id, payload);
+        let jsonl = persistence::append_jsonl(&self.jsonl_path, room_id, source_id, payload);
+        sqlite.map_err(|e| anyhow::anyhow!("sqlite: {}", e))?;
+        jsonl.map_err(|e| anyhow::anyhow!("jsonl: {}", e))?;
+        Ok(())
+    }
+}
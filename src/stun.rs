@@ -1,30 +1,122 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::collections::HashMap;
-use log::{info, error, debug};
-use byteorder::{BigEndian, ByteOrder};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+use tracing::{info, error, debug};
 use tokio::net::UdpSocket;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-// STUN message types
-const BINDING_REQUEST: u16 = 0x0001;
-const BINDING_RESPONSE: u16 = 0x0101;
-const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+use crate::network;
+use crate::state::SharedState;
+use crate::stun_proto::{self, Attribute, Message, MessageType};
 
-// STUN attribute types
-#[allow(dead_code)]
-const MAPPED_ADDRESS: u16 = 0x0001;
-const XOR_MAPPED_ADDRESS: u16 = 0x0020;
-const ERROR_CODE: u16 = 0x0009;
+/// Upper bounds of the UDP response latency histogram, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05];
+/// Upper bounds of the binding-requests-per-source-IP histogram
+const SOURCE_BUCKETS: [u64; 6] = [1, 2, 5, 10, 50, 100];
+/// Source IPs remembered for the unique count; beyond this new sources are only counted in
+/// stun_untracked_sources_total, so a scan can't grow the map without bound
+const MAX_TRACKED_SOURCES: usize = 65536;
+
+#[derive(Debug, Default)]
+struct Counters {
+    binding_requests: u64,
+    error_responses: u64,
+    filtered: u64,
+    malformed: u64,
+    requests_by_source: HashMap<IpAddr, u64>,
+    untracked_sources: u64,
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_count: u64,
+    latency_sum: Duration,
+}
+
+/// Request counters shared by every STUN server (UDP addresses and STUN/TCP) and GET /metrics
+#[derive(Debug, Clone, Default)]
+pub struct StunStats {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl StunStats {
+    fn binding_request(&self, ip: IpAddr) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.binding_requests += 1;
+        let tracked = counters.requests_by_source.len();
+        match counters.requests_by_source.get_mut(&ip) {
+            Some(count) => *count += 1,
+            None if tracked < MAX_TRACKED_SOURCES => {
+                counters.requests_by_source.insert(ip, 1);
+            }
+            None => counters.untracked_sources += 1,
+        }
+    }
+
+    fn responded(&self, elapsed: Duration) {
+        let mut counters = self.counters.lock().unwrap();
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in counters.latency_buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        counters.latency_count += 1;
+        counters.latency_sum += elapsed;
+    }
+
+    /// Prometheus text exposition of the request counters
+    pub fn render_metrics(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP stun_binding_requests_total STUN binding requests answered");
+        let _ = writeln!(out, "# TYPE stun_binding_requests_total counter");
+        let _ = writeln!(out, "stun_binding_requests_total {}", counters.binding_requests);
+        let _ = writeln!(out, "# HELP stun_error_responses_total Non-binding STUN messages answered with 400");
+        let _ = writeln!(out, "# TYPE stun_error_responses_total counter");
+        let _ = writeln!(out, "stun_error_responses_total {}", counters.error_responses);
+        let _ = writeln!(out, "# HELP stun_dropped_packets_total Packets dropped without an answer");
+        let _ = writeln!(out, "# TYPE stun_dropped_packets_total counter");
+        let _ = writeln!(out, "stun_dropped_packets_total{{reason=\"filtered\"}} {}", counters.filtered);
+        let _ = writeln!(out, "stun_dropped_packets_total{{reason=\"malformed\"}} {}", counters.malformed);
+        let _ = writeln!(out, "# HELP stun_unique_sources Distinct source IPs that sent a binding request");
+        let _ = writeln!(out, "# TYPE stun_unique_sources gauge");
+        let _ = writeln!(out, "stun_unique_sources {}", counters.requests_by_source.len());
+        let _ = writeln!(out, "# HELP stun_untracked_sources_total Binding requests from new sources after the tracking limit");
+        let _ = writeln!(out, "# TYPE stun_untracked_sources_total counter");
+        let _ = writeln!(out, "stun_untracked_sources_total {}", counters.untracked_sources);
+
+        let _ = writeln!(out, "# HELP stun_source_requests Binding requests per source IP");
+        let _ = writeln!(out, "# TYPE stun_source_requests histogram");
+        for bound in SOURCE_BUCKETS {
+            let sources = counters.requests_by_source.values().filter(|count| **count <= bound).count();
+            let _ = writeln!(out, "stun_source_requests_bucket{{le=\"{}\"}} {}", bound, sources);
+        }
+        let _ = writeln!(out, "stun_source_requests_bucket{{le=\"+Inf\"}} {}", counters.requests_by_source.len());
+        let _ = writeln!(out, "stun_source_requests_sum {}", counters.requests_by_source.values().sum::<u64>());
+        let _ = writeln!(out, "stun_source_requests_count {}", counters.requests_by_source.len());
+
+        let _ = writeln!(out, "# HELP stun_response_seconds Time from receiving a UDP request to sending its response");
+        let _ = writeln!(out, "# TYPE stun_response_seconds histogram");
+        for (count, bound) in counters.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(out, "stun_response_seconds_bucket{{le=\"{}\"}} {}", bound, count);
+        }
+        let _ = writeln!(out, "stun_response_seconds_bucket{{le=\"+Inf\"}} {}", counters.latency_count);
+        let _ = writeln!(out, "stun_response_seconds_sum {}", counters.latency_sum.as_secs_f64());
+        let _ = writeln!(out, "stun_response_seconds_count {}", counters.latency_count);
+        out
+    }
+}
 
 pub struct StunServer {
     socket: Arc<UdpSocket>,
     #[allow(dead_code)]
     local_addrs: HashMap<SocketAddr, SocketAddr>,
+    stats: StunStats,
+    state: SharedState,
 }
 
 impl StunServer {
-    pub fn new(bind_addr: SocketAddr) -> std::io::Result<Self> {
-        let socket = std::net::UdpSocket::bind(bind_addr)?;
+    pub fn new(bind_addr: SocketAddr, stats: StunStats, state: SharedState) -> std::io::Result<Self> {
+        let socket = network::bind_udp(bind_addr)?;
         socket.set_nonblocking(true)?;
         let tokio_socket = UdpSocket::from_std(socket)?;
         info!("STUN server listening on {}", bind_addr);
@@ -32,20 +124,24 @@ impl StunServer {
         Ok(Self {
             socket: Arc::new(tokio_socket),
             local_addrs: HashMap::new(),
+            stats,
+            state,
         })
     }
     
-    pub async fn run(&mut self) -> std::io::Result<()> {
+    pub async fn run(&self) -> std::io::Result<()> {
         let mut buf = [0u8; 1024];
         
         loop {
             match self.socket.recv_from(&mut buf).await {
                 Ok((len, src_addr)) => {
+                    let received = Instant::now();
                     let packet = &buf[..len];
                     
                     if let Some(response) = self.handle_stun_packet(packet, src_addr) {
-                        if let Err(e) = self.socket.send_to(&response, src_addr).await {
-                            error!("Failed to send STUN response: {}", e);
+                        match self.socket.send_to(&response, src_addr).await {
+                            Ok(_) => self.stats.responded(received.elapsed()),
+                            Err(e) => error!("Failed to send STUN response: {}", e),
                         }
                     }
                 }
@@ -56,101 +152,37 @@ impl StunServer {
         }
     }
     
-    fn handle_stun_packet(&mut self, packet: &[u8], src_addr: SocketAddr) -> Option<Vec<u8>> {
-        if packet.len() < 20 {
-            debug!("Packet too short for STUN message");
-            return None;
-        }
-        
-        let msg_type = BigEndian::read_u16(&packet[0..2]);
-        let msg_len = BigEndian::read_u16(&packet[2..4]);
-        
-        // Verify packet length
-        if packet.len() != 20 + msg_len as usize {
-            debug!("STUN packet length mismatch");
+    /// Handles one STUN message; shared by the UDP loop and the TCP/TLS transport
+    #[tracing::instrument(name = "stun_packet", skip_all, fields(peer = %src_addr, bytes = packet.len()))]
+    pub fn handle_stun_packet(&self, packet: &[u8], src_addr: SocketAddr) -> Option<Vec<u8>> {
+        if !self.state.ip_filter.permits(src_addr.ip()) {
+            debug!("Dropped STUN packet from filtered address");
+            self.stats.counters.lock().unwrap().filtered += 1;
             return None;
         }
-        
-        match msg_type {
-            BINDING_REQUEST => {
-                debug!("STUN binding request from {}", src_addr);
-                Some(self.create_binding_response(packet, src_addr))
-            }
-            _ => {
-                debug!("Unsupported STUN message type: 0x{:04x}", msg_type);
-                Some(self.create_error_response(packet, 400, "Bad Request"))
+        let header = match stun_proto::decode_header(packet) {
+            Ok(header) => header,
+            Err(e) => {
+                debug!("Dropped STUN packet: {}", e);
+                self.stats.counters.lock().unwrap().malformed += 1;
+                return None;
             }
-        }
-    }
-    
-    fn create_binding_response(&self, request: &[u8], src_addr: SocketAddr) -> Vec<u8> {
-        let mut response = Vec::new();
-        
-        // Message header
-        response.extend_from_slice(&BINDING_RESPONSE.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes()); // Length (placeholder)
-        response.extend_from_slice(&request[4..20]); // Copy magic cookie and transaction ID
-        
-        // XOR-MAPPED-ADDRESS attribute
-        let attr_type = XOR_MAPPED_ADDRESS;
-        let attr_len = 8u16;
-        
-        response.extend_from_slice(&attr_type.to_be_bytes());
-        response.extend_from_slice(&attr_len.to_be_bytes());
-        response.push(0x00); // Reserved
-        response.push(0x01); // IPv4 family
-        
-        let ip = src_addr.ip();
-        let port = src_addr.port() ^ 0x2112; // XOR with magic cookie
-        
-        response.extend_from_slice(&port.to_be_bytes());
+        };
         
-        match ip {
-            std::net::IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                for octet in octets {
-                    response.push(octet ^ 0x21); // XOR with magic cookie bytes
-                }
+        match header.message_type {
+            MessageType::BindingRequest => {
+                debug!("STUN binding request from {}", src_addr);
+                self.stats.binding_request(src_addr.ip());
+                let attributes = vec![Attribute::XorMappedAddress(src_addr)];
+                Some(Message::reply(&header, MessageType::BindingResponse, attributes).encode())
             }
-            std::net::IpAddr::V6(_) => {
-                // IPv6 support would go here
-                response.extend_from_slice(&[0; 16]);
+            other => {
+                debug!("Unsupported STUN message type: 0x{:04x}", other.to_u16());
+                self.stats.counters.lock().unwrap().error_responses += 1;
+                let attributes = vec![Attribute::ErrorCode { code: 400, reason: "Bad Request".to_string() }];
+                Some(Message::reply(&header, MessageType::BindingErrorResponse, attributes).encode())
             }
         }
-        
-        // Update message length
-        let total_len = response.len() - 20;
-        response[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
-        
-        response
-    }
-    
-    fn create_error_response(&self, request: &[u8], code: u16, reason: &str) -> Vec<u8> {
-        let mut response = Vec::new();
-        
-        // Message header
-        response.extend_from_slice(&BINDING_ERROR_RESPONSE.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes()); // Length (placeholder)
-        response.extend_from_slice(&request[4..20]); // Copy magic cookie and transaction ID
-        
-        // ERROR-CODE attribute
-        let error_class = code / 100;
-        let _error_number = code % 100;
-        let reason_bytes = reason.as_bytes();
-        let attr_len = 4 + reason_bytes.len() as u16;
-        
-        response.extend_from_slice(&ERROR_CODE.to_be_bytes());
-        response.extend_from_slice(&attr_len.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes());
-        response.push((error_class / 100) as u8);
-        response.push((error_class % 100) as u8);
-        response.extend_from_slice(reason_bytes);
-        
-        // Update message length
-        let total_len = response.len() - 20;
-        response[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
-        
-        response
     }
     
     #[allow(dead_code)]
@@ -0,0 +1,341 @@
+// stun_proto.rs
+// STUN message encoding and decoding (RFC 5389, plus the TURN additions of RFC 5766) shared by
+// the STUN and TURN servers and external address detection. Decoding checks the header and walks the attributes with bounds
+// checks, so crafted lengths end in a DecodeError rather than a panic; attributes this server
+// doesn't interpret come back as Attribute::Unknown. Encoding pads every attribute to 4 bytes and
+// keeps the header's length field in step. This module has no dependencies on the rest of the
+// server, so tests/stun_proto_tests.rs and the fuzz targets can compile it on its own.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use byteorder::{BigEndian, ByteOrder};
+
+pub const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
+pub const HEADER_LEN: usize = 20;
+
+// Attribute types
+const MAPPED_ADDRESS: u16 = 0x0001;
+const USERNAME: u16 = 0x0006;
+const MESSAGE_INTEGRITY: u16 = 0x0008;
+const ERROR_CODE: u16 = 0x0009;
+const LIFETIME: u16 = 0x000d;
+const XOR_PEER_ADDRESS: u16 = 0x0012;
+const DATA: u16 = 0x0013;
+const REALM: u16 = 0x0014;
+const NONCE: u16 = 0x0015;
+const XOR_RELAYED_ADDRESS: u16 = 0x0016;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    BindingRequest,
+    BindingResponse,
+    BindingErrorResponse,
+    AllocateRequest,
+    AllocateResponse,
+    AllocateErrorResponse,
+    SendIndication,
+    DataIndication,
+    /// Any other method or class, kept as its wire value
+    Other(u16),
+}
+
+impl MessageType {
+    pub fn from_u16(value: u16) -> Self {
+        match value {
+            0x0001 => Self::BindingRequest,
+            0x0101 => Self::BindingResponse,
+            0x0111 => Self::BindingErrorResponse,
+            0x0003 => Self::AllocateRequest,
+            0x0103 => Self::AllocateResponse,
+            0x0113 => Self::AllocateErrorResponse,
+            0x0016 => Self::SendIndication,
+            0x0017 => Self::DataIndication,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn to_u16(self) -> u16 {
+        match self {
+            Self::BindingRequest => 0x0001,
+            Self::BindingResponse => 0x0101,
+            Self::BindingErrorResponse => 0x0111,
+            Self::AllocateRequest => 0x0003,
+            Self::AllocateResponse => 0x0103,
+            Self::AllocateErrorResponse => 0x0113,
+            Self::SendIndication => 0x0016,
+            Self::DataIndication => 0x0017,
+            Self::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attribute {
+    MappedAddress(SocketAddr),
+    XorMappedAddress(SocketAddr),
+    XorRelayedAddress(SocketAddr),
+    XorPeerAddress(SocketAddr),
+    Username(String),
+    Realm(String),
+    Nonce(String),
+    /// HMAC-SHA1 over the message up to this attribute
+    MessageIntegrity([u8; 20]),
+    /// `code` is 300 to 699
+    ErrorCode { code: u16, reason: String },
+    /// Allocation lifetime in seconds
+    Lifetime(u32),
+    Data(Vec<u8>),
+    Unknown { attr_type: u16, value: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Fewer than the 20 header bytes
+    TooShort,
+    /// The header's length field doesn't match the bytes that follow
+    LengthMismatch,
+    /// The first two bits aren't zero or the length isn't a multiple of 4, so it isn't STUN
+    NotStun,
+    /// An attribute's length runs past the end of the message
+    Truncated,
+    /// A known attribute whose value can't be read
+    InvalidAttribute(u16),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort => write!(f, "shorter than a STUN header"),
+            Self::LengthMismatch => write!(f, "length field does not match the message"),
+            Self::NotStun => write!(f, "not a STUN message"),
+            Self::Truncated => write!(f, "attribute runs past the end of the message"),
+            Self::InvalidAttribute(attr_type) => write!(f, "invalid attribute 0x{:04x}", attr_type),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub message_type: MessageType,
+    /// Magic cookie and transaction ID, echoed verbatim in responses
+    pub transaction: [u8; 16],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub attributes: Vec<Attribute>,
+}
+
+/// An attribute as it appears in the message, with its byte offset for integrity checks
+#[derive(Debug, Clone, Copy)]
+pub struct RawAttribute<'a> {
+    pub offset: usize,
+    pub attr_type: u16,
+    pub value: &'a [u8],
+    /// The message's cookie and transaction ID, which XOR-*-ADDRESS values are masked with
+    transaction: &'a [u8; 16],
+}
+
+impl RawAttribute<'_> {
+    pub fn decode(&self) -> Result<Attribute, DecodeError> {
+        Attribute::decode(self.attr_type, self.value, self.transaction)
+    }
+}
+
+/// Checks the header of a complete message
+pub fn decode_header(packet: &[u8]) -> Result<Header, DecodeError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DecodeError::TooShort);
+    }
+    let message_type = BigEndian::read_u16(&packet[0..2]);
+    let length = BigEndian::read_u16(&packet[2..4]) as usize;
+    if packet.len() != HEADER_LEN + length {
+        return Err(DecodeError::LengthMismatch);
+    }
+    if message_type & 0xc000 != 0 || !length.is_multiple_of(4) {
+        return Err(DecodeError::NotStun);
+    }
+    let mut transaction = [0u8; 16];
+    transaction.copy_from_slice(&packet[4..HEADER_LEN]);
+    Ok(Header { message_type: MessageType::from_u16(message_type), transaction })
+}
+
+/// The attributes of a message whose header decode_header accepted, in order
+pub fn raw_attributes(packet: &[u8]) -> impl Iterator<Item = Result<RawAttribute<'_>, DecodeError>> {
+    let transaction: Option<&[u8; 16]> = packet.get(4..HEADER_LEN).and_then(|bytes| bytes.try_into().ok());
+    let mut pos = HEADER_LEN;
+    std::iter::from_fn(move || {
+        let transaction = transaction?;
+        if pos >= packet.len() {
+            return None;
+        }
+        let attribute = read_attribute(packet, pos, transaction);
+        pos = match &attribute {
+            Ok((_, next)) => *next,
+            Err(_) => packet.len(),
+        };
+        Some(attribute.map(|(attribute, _)| attribute))
+    })
+}
+
+/// The attribute at `pos` and where the next one starts (values are padded to 4 bytes)
+fn read_attribute<'a>(packet: &'a [u8], pos: usize, transaction: &'a [u8; 16]) -> Result<(RawAttribute<'a>, usize), DecodeError> {
+    let value_start = pos.checked_add(4).ok_or(DecodeError::Truncated)?;
+    let header = packet.get(pos..value_start).ok_or(DecodeError::Truncated)?;
+    let attr_type = BigEndian::read_u16(&header[0..2]);
+    let attr_len = BigEndian::read_u16(&header[2..4]) as usize;
+    let value = packet.get(value_start..value_start + attr_len).ok_or(DecodeError::Truncated)?;
+    Ok((RawAttribute { offset: pos, attr_type, value, transaction }, value_start + ((attr_len + 3) & !3)))
+}
+
+pub fn decode(packet: &[u8]) -> Result<Message, DecodeError> {
+    let header = decode_header(packet)?;
+    let attributes = raw_attributes(packet)
+        .map(|raw| raw.and_then(|raw| raw.decode()))
+        .collect::<Result<_, _>>()?;
+    Ok(Message { header, attributes })
+}
+
+impl Message {
+    pub fn new(message_type: MessageType, transaction: [u8; 16]) -> Self {
+        Self { header: Header { message_type, transaction }, attributes: Vec::new() }
+    }
+
+    /// A response to `request` (same cookie and transaction ID) carrying `attributes`
+    pub fn reply(request: &Header, message_type: MessageType, attributes: Vec<Attribute>) -> Self {
+        Self { header: Header { message_type, transaction: request.transaction }, attributes }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HEADER_LEN);
+        message.extend_from_slice(&self.header.message_type.to_u16().to_be_bytes());
+        message.extend_from_slice(&0u16.to_be_bytes()); // Length, set by append_attribute
+        message.extend_from_slice(&self.header.transaction);
+        for attribute in &self.attributes {
+            append_attribute(&mut message, attribute);
+        }
+        message
+    }
+}
+
+/// Adds an attribute to an encoded message and updates the header's length, e.g. for
+/// MESSAGE-INTEGRITY, which covers the bytes before it
+pub fn append_attribute(message: &mut Vec<u8>, attribute: &Attribute) {
+    let mut transaction = [0u8; 16];
+    transaction.copy_from_slice(&message[4..HEADER_LEN]);
+    let (attr_type, value) = attribute.encode(&transaction);
+    message.extend_from_slice(&attr_type.to_be_bytes());
+    message.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    message.extend_from_slice(&value);
+    while !message.len().is_multiple_of(4) {
+        message.push(0);
+    }
+    let length = (message.len() - HEADER_LEN) as u16;
+    message[2..4].copy_from_slice(&length.to_be_bytes());
+}
+
+impl Attribute {
+    /// `transaction` is the message's cookie and transaction ID (header bytes 4 to 20)
+    pub fn decode(attr_type: u16, value: &[u8], transaction: &[u8; 16]) -> Result<Self, DecodeError> {
+        let invalid = || DecodeError::InvalidAttribute(attr_type);
+        let text = || std::str::from_utf8(value).map(str::to_string).map_err(|_| invalid());
+        let xor = Some(transaction);
+        Ok(match attr_type {
+            MAPPED_ADDRESS => Self::MappedAddress(decode_address(value, None).ok_or_else(invalid)?),
+            XOR_MAPPED_ADDRESS => Self::XorMappedAddress(decode_address(value, xor).ok_or_else(invalid)?),
+            XOR_RELAYED_ADDRESS => Self::XorRelayedAddress(decode_address(value, xor).ok_or_else(invalid)?),
+            XOR_PEER_ADDRESS => Self::XorPeerAddress(decode_address(value, xor).ok_or_else(invalid)?),
+            USERNAME => Self::Username(text()?),
+            REALM => Self::Realm(text()?),
+            NONCE => Self::Nonce(text()?),
+            MESSAGE_INTEGRITY => Self::MessageIntegrity(value.try_into().map_err(|_| invalid())?),
+            ERROR_CODE => {
+                if value.len() < 4 || value[3] > 99 || !(3..=6).contains(&(value[2] & 0x07)) {
+                    return Err(invalid());
+                }
+                let code = u16::from(value[2] & 0x07) * 100 + u16::from(value[3]);
+                let reason = std::str::from_utf8(&value[4..]).map_err(|_| invalid())?.to_string();
+                Self::ErrorCode { code, reason }
+            }
+            LIFETIME => Self::Lifetime(u32::from_be_bytes(value.try_into().map_err(|_| invalid())?)),
+            DATA => Self::Data(value.to_vec()),
+            _ => Self::Unknown { attr_type, value: value.to_vec() },
+        })
+    }
+
+    /// The attribute's type and unpadded value, for a message with this cookie and transaction ID
+    pub fn encode(&self, transaction: &[u8; 16]) -> (u16, Vec<u8>) {
+        let xor = Some(transaction);
+        match self {
+            Self::MappedAddress(addr) => (MAPPED_ADDRESS, encode_address(*addr, None)),
+            Self::XorMappedAddress(addr) => (XOR_MAPPED_ADDRESS, encode_address(*addr, xor)),
+            Self::XorRelayedAddress(addr) => (XOR_RELAYED_ADDRESS, encode_address(*addr, xor)),
+            Self::XorPeerAddress(addr) => (XOR_PEER_ADDRESS, encode_address(*addr, xor)),
+            Self::Username(value) => (USERNAME, value.as_bytes().to_vec()),
+            Self::Realm(value) => (REALM, value.as_bytes().to_vec()),
+            Self::Nonce(value) => (NONCE, value.as_bytes().to_vec()),
+            Self::MessageIntegrity(mac) => (MESSAGE_INTEGRITY, mac.to_vec()),
+            Self::ErrorCode { code, reason } => {
+                let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+                value.extend_from_slice(reason.as_bytes());
+                (ERROR_CODE, value)
+            }
+            Self::Lifetime(secs) => (LIFETIME, secs.to_be_bytes().to_vec()),
+            Self::Data(data) => (DATA, data.clone()),
+            Self::Unknown { attr_type, value } => (*attr_type, value.clone()),
+        }
+    }
+}
+
+/// The bytes an address is XORed with (RFC 5389 §15.2): the magic cookie for IPv4, the cookie
+/// followed by the 96-bit transaction ID for IPv6. The port uses the cookie's high 16 bits, which
+/// are the mask's first two bytes either way. Messages from RFC 3489 clients carry no cookie in
+/// their header, but the mask always starts with the constant cookie.
+fn xor_mask(transaction: &[u8; 16]) -> [u8; 16] {
+    let mut mask = *transaction;
+    mask[..4].copy_from_slice(&MAGIC_COOKIE);
+    mask
+}
+
+/// `0, family, port, address`, XORed with the mask of `xor` for XOR-*-ADDRESS attributes
+fn encode_address(addr: SocketAddr, xor: Option<&[u8; 16]>) -> Vec<u8> {
+    let mask = xor.map(xor_mask).unwrap_or_default();
+    let (family, octets) = match addr.ip() {
+        IpAddr::V4(ip) => (FAMILY_IPV4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (FAMILY_IPV6, ip.octets().to_vec()),
+    };
+    let mut value = vec![0, family];
+    value.extend_from_slice(&(addr.port() ^ BigEndian::read_u16(&mask[..2])).to_be_bytes());
+    value.extend(octets.iter().zip(mask).map(|(octet, mask)| octet ^ mask));
+    value
+}
+
+fn decode_address(value: &[u8], xor: Option<&[u8; 16]>) -> Option<SocketAddr> {
+    let mask = xor.map(xor_mask).unwrap_or_default();
+    let port = BigEndian::read_u16(value.get(2..4)?) ^ BigEndian::read_u16(&mask[..2]);
+    let ip = match (value[1], &value[4..]) {
+        (FAMILY_IPV4, octets) if octets.len() == 4 => {
+            let mut ip = [0u8; 4];
+            for ((byte, octet), mask) in ip.iter_mut().zip(octets).zip(mask) {
+                *byte = octet ^ mask;
+            }
+            IpAddr::V4(Ipv4Addr::from(ip))
+        }
+        (FAMILY_IPV6, octets) if octets.len() == 16 => {
+            let mut ip = [0u8; 16];
+            for ((byte, octet), mask) in ip.iter_mut().zip(octets).zip(mask) {
+                *byte = octet ^ mask;
+            }
+            IpAddr::V6(Ipv6Addr::from(ip))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
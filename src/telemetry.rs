@@ -0,0 +1,69 @@
+// telemetry.rs
+// Optional OpenTelemetry export of tracing spans. Signaling messages, RoomManager handling and
+// STUN/TURN packets are wrapped in spans carrying the room, connection and peer IDs, so one
+// offer/answer exchange can be followed across connections in any OTLP backend. The exporter is
+// a layer of the logging subscriber (see logging.rs).
+
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::Resource;
+use serde::{Deserialize, Serialize};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// OTLP/gRPC collector endpoint
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Share of traces kept, from 0 to 1
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+fn default_otlp_endpoint() -> String { "http://localhost:4317".to_string() }
+fn default_service_name() -> String { "ws2infer".to_string() }
+fn default_sample_ratio() -> f64 { 1.0 }
+
+impl TelemetryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.otlp_endpoint.starts_with("http://") && !self.otlp_endpoint.starts_with("https://") {
+            return Err("otlp_endpoint must be an http:// or https:// URL".to_string());
+        }
+        if self.service_name.is_empty() {
+            return Err("service_name must not be empty".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            return Err("sample_ratio must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Layer exporting spans over OTLP, added to the subscriber by `logging::init`; must be
+/// built inside the runtime
+pub fn layer<S>(config: &TelemetryConfig) -> Result<OpenTelemetryLayer<S, Tracer>, String>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&config.otlp_endpoint))
+        .with_trace_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+                .with_resource(Resource::new(vec![KeyValue::new("service.name", config.service_name.clone())])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| e.to_string())?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Sends the spans still buffered; call before the process exits
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
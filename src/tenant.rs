@@ -0,0 +1,260 @@
+// tenant.rs
+// Tenants let one server host several independent applications. Each tenant has API keys that
+// create rooms in it and optionally its own admin token; an identity provider can put callers in
+// a tenant through a claim instead (oidc.rs tenant_claim). A tenant's rooms are keyed
+// "{tenant}:{room_id}" everywhere: in the RoomManager, in URLs (/ws/acme:lobby), in stored
+// inference results, audit entries and webhooks, and as nested directories for recordings and
+// snapshots. Rooms created without a tenant keep bare IDs, so servers without tenants see no
+// change. Tenant admin tokens only reach their own tenant's rooms; server-wide admin endpoints
+// still need the global admin_token.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+
+use crate::auth::{self, Permission, Refusal};
+use crate::quota::Quotas;
+use crate::room::{self, RoomManager};
+use crate::state::State;
+
+/// Between the tenant and the room ID in a room key; room IDs themselves can't contain it
+pub const SEPARATOR: char = ':';
+/// Label of rooms without a tenant in per-tenant metrics, hence not a valid tenant ID
+const DEFAULT_LABEL: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TenantConfig {
+    /// Prefix of the tenant's room keys; same characters as a room ID
+    pub id: String,
+    /// Bearer tokens that create rooms in this tenant through POST/PUT /api/rooms
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Admin token limited to this tenant's rooms (room admin endpoints, admin joins, audit log)
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Limits on the tenant's rooms, connections and stored results (quota.rs)
+    #[serde(default)]
+    pub quotas: Quotas,
+}
+
+impl TenantConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !room::is_valid_room_id(&self.id) {
+            return Err(format!("id '{}' may only contain letters, digits, '-' and '_'", self.id));
+        }
+        if self.id == DEFAULT_LABEL {
+            return Err(format!("id '{}' is reserved", DEFAULT_LABEL));
+        }
+        if self.api_keys.iter().any(String::is_empty) {
+            return Err("api_keys must not be empty strings".to_string());
+        }
+        if matches!(&self.admin_token, Some(token) if token.is_empty()) {
+            return Err("admin_token must not be empty when set".to_string());
+        }
+        self.quotas.validate().map_err(|e| format!("quotas: {}", e))
+    }
+}
+
+/// Checks the tenants list as a whole: IDs and tokens must be unique
+pub fn validate_all(tenants: &[TenantConfig]) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut ids = HashSet::new();
+    let mut tokens = HashSet::new();
+    for (i, tenant) in tenants.iter().enumerate() {
+        if let Err(e) = tenant.validate() {
+            errors.push(format!("tenants[{}]: {}", i, e));
+        }
+        if !ids.insert(tenant.id.as_str()) {
+            errors.push(format!("tenants[{}]: duplicate id '{}'", i, tenant.id));
+        }
+        for token in tenant.api_keys.iter().chain(&tenant.admin_token) {
+            if !tokens.insert(token.as_str()) {
+                errors.push(format!("tenants[{}]: a token is used more than once", i));
+            }
+        }
+    }
+    errors
+}
+
+/// Key of a tenant's room; rooms without a tenant keep their bare ID
+pub fn room_key(tenant: Option<&str>, room_id: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{}{}{}", tenant, SEPARATOR, room_id),
+        None => room_id.to_string(),
+    }
+}
+
+/// Tenant a room key belongs to
+pub fn tenant_of(room_key: &str) -> Option<&str> {
+    room_key.split_once(SEPARATOR).map(|(tenant, _)| tenant)
+}
+
+/// Room key for an ID given by a caller of `tenant`: a bare ID is put in the caller's tenant, a
+/// key naming another tenant is refused (None)
+pub fn resolve(tenant: Option<&str>, room_id: &str) -> Option<String> {
+    match (tenant, tenant_of(room_id)) {
+        (_, None) => Some(room_key(tenant, room_id)),
+        (Some(tenant), Some(owner)) if tenant == owner => Some(room_id.to_string()),
+        _ => None,
+    }
+}
+
+/// A room ID with an optional tenant prefix
+pub fn is_valid_room_key(room_key: &str) -> bool {
+    match room_key.split_once(SEPARATOR) {
+        Some((tenant, room_id)) => room::is_valid_room_id(tenant) && room::is_valid_room_id(room_id),
+        None => room::is_valid_room_id(room_key),
+    }
+}
+
+/// Directory of a room's files under `base`: tenants get a directory each
+pub fn room_dir(base: &Path, room_key: &str) -> PathBuf {
+    match room_key.split_once(SEPARATOR) {
+        Some((tenant, room_id)) => base.join(tenant).join(room_id),
+        None => base.join(room_key),
+    }
+}
+
+/// Tenant whose API key `token` is
+pub fn for_api_key<'a>(tenants: &'a [TenantConfig], token: &str) -> Option<&'a TenantConfig> {
+    tenants.iter().find(|tenant| tenant.api_keys.iter().any(|key| auth::secret_eq(token, key)))
+}
+
+/// Tenant whose admin token `token` is
+pub fn for_admin_token<'a>(tenants: &'a [TenantConfig], token: &str) -> Option<&'a TenantConfig> {
+    tenants.iter().find(|tenant| tenant.admin_token.as_deref().is_some_and(|expected| auth::secret_eq(token, expected)))
+}
+
+/// Tenant admin tokens mapped to their tenant, for RoomManager::tenant_admin_tokens
+pub fn admin_tokens(tenants: &[TenantConfig]) -> HashMap<String, String> {
+    tenants.iter()
+        .filter_map(|tenant| Some((tenant.admin_token.clone()?, tenant.id.clone())))
+        .collect()
+}
+
+/// Who is creating a room, and the tenant it goes in
+pub struct Creator {
+    /// Recorded as the actor in the audit log
+    pub actor: String,
+    pub tenant: Option<String>,
+}
+
+/// Identifies the caller of POST/PUT /api/rooms: a tenant API key puts the room in that tenant,
+/// otherwise the identity provider (if any) decides, through its tenant claim. A bearer token
+/// that is neither an API key nor accepted by a provider is refused once tenants have API keys,
+/// so a mistyped key can't silently create rooms outside the tenant.
+pub async fn authorize_creator(state: &State, authorization: Option<&str>, tenants: &[TenantConfig]) -> Result<Creator, Refusal> {
+    let token = auth::bearer(authorization);
+    if let Some(tenant) = token.and_then(|token| for_api_key(tenants, token)) {
+        return Ok(Creator { actor: format!("api_key:{}", tenant.id), tenant: Some(tenant.id.clone()) });
+    }
+    match auth::authorize(state, token, Permission::CreateRooms).await? {
+        Some(identity) => Ok(Creator { actor: identity.subject, tenant: identity.tenant }),
+        None if token.is_some() && tenants.iter().any(|tenant| !tenant.api_keys.is_empty()) => Err(Refusal {
+            status: StatusCode::UNAUTHORIZED,
+            message: "Unknown API key".to_string(),
+        }),
+        None => Ok(Creator { actor: "api".to_string(), tenant: None }),
+    }
+}
+
+/// What an admin token gives access to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// The global admin_token: everything
+    Global,
+    /// A tenant's admin_token: that tenant's rooms
+    Tenant(String),
+}
+
+impl Scope {
+    pub fn covers(&self, room_key: &str) -> bool {
+        match self {
+            Self::Global => true,
+            Self::Tenant(tenant) => tenant_of(room_key) == Some(tenant.as_str()),
+        }
+    }
+
+    /// Key for a room (or group) ID given by an admin of this scope: a tenant admin's bare ID is
+    /// put in the tenant, one naming another tenant is refused (None)
+    pub fn resolve(&self, id: &str) -> Option<String> {
+        match self {
+            Self::Global => Some(id.to_string()),
+            Self::Tenant(tenant) => resolve(Some(tenant), id),
+        }
+    }
+
+    /// Audit log entries and other room-keyed records are filtered by this prefix
+    pub fn room_prefix(&self) -> Option<String> {
+        match self {
+            Self::Global => None,
+            Self::Tenant(tenant) => Some(format!("{}{}", tenant, SEPARATOR)),
+        }
+    }
+}
+
+/// Checks an `Authorization: Bearer <token>` header against the global and the tenants' admin
+/// tokens. FORBIDDEN when no admin token is configured at all, UNAUTHORIZED when none matches.
+pub fn admin_scope(authorization: Option<&str>, admin_token: Option<&str>, tenants: &[TenantConfig]) -> Result<Scope, StatusCode> {
+    if admin_token.is_none() && tenants.iter().all(|tenant| tenant.admin_token.is_none()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let token = crate::auth::bearer(authorization).ok_or(StatusCode::UNAUTHORIZED)?;
+    if admin_token.is_some_and(|expected| auth::secret_eq(token, expected)) {
+        return Ok(Scope::Global);
+    }
+    for_admin_token(tenants, token)
+        .map(|tenant| Scope::Tenant(tenant.id.clone()))
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+/// Like admin_scope, for an endpoint acting on one room: a tenant admin asking about another
+/// tenant's room gets FORBIDDEN. Returns the status to reject with, or None for an admin.
+pub fn room_admin_rejection(authorization: Option<&str>, admin_token: Option<&str>, tenants: &[TenantConfig], room_key: &str) -> Option<StatusCode> {
+    match admin_scope(authorization, admin_token, tenants) {
+        Ok(scope) if scope.covers(room_key) => None,
+        Ok(_) => Some(StatusCode::FORBIDDEN),
+        Err(status) => Some(status),
+    }
+}
+
+/// For endpoints serving a room's results, images and what they are checked against (latest
+/// results, stats, line counts, zones, lines, export, detections, summaries, snapshots): a
+/// tenant's room is readable with the tenant's API keys or an admin token covering it; a room
+/// without a tenant needs the global admin token once one is configured.
+pub fn stored_data_rejection(authorization: Option<&str>, admin_token: Option<&str>, tenants: &[TenantConfig], room_key: &str) -> Option<StatusCode> {
+    match tenant_of(room_key) {
+        Some(owner) => {
+            let api_key = auth::bearer(authorization).and_then(|token| for_api_key(tenants, token));
+            if api_key.is_some_and(|tenant| tenant.id == owner) {
+                return None;
+            }
+            room_admin_rejection(authorization, admin_token, tenants, room_key)
+        }
+        None if admin_token.is_none() => None,
+        None => room_admin_rejection(authorization, admin_token, tenants, room_key),
+    }
+}
+
+/// Prometheus exposition of rooms and connections per tenant
+pub fn render_metrics(manager: &RoomManager) -> String {
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for (room_key, room) in &manager.rooms {
+        let entry = counts.entry(tenant_of(room_key).unwrap_or(DEFAULT_LABEL)).or_default();
+        entry.0 += 1;
+        entry.1 += room.connections.len();
+    }
+    let mut out = String::new();
+    out.push_str("# HELP ws2infer_tenant_rooms Open rooms per tenant\n");
+    out.push_str("# TYPE ws2infer_tenant_rooms gauge\n");
+    for (tenant, (rooms, _)) in &counts {
+        out.push_str(&format!("ws2infer_tenant_rooms{{tenant=\"{}\"}} {}\n", tenant, rooms));
+    }
+    out.push_str("# HELP ws2infer_tenant_connections Connections in each tenant's rooms\n");
+    out.push_str("# TYPE ws2infer_tenant_connections gauge\n");
+    for (tenant, (_, connections)) in &counts {
+        out.push_str(&format!("ws2infer_tenant_connections{{tenant=\"{}\"}} {}\n", tenant, connections));
+    }
+    out
+}
@@ -0,0 +1,132 @@
+// tracker.rs
+// Optional server-side object tracking. Each source's detections are matched to the previous
+// frames' tracks of the same class by bbox overlap (IoU, greedy from the best pair down), so an
+// object keeps one track_id while it is visible and clients can draw stable labels. Detections
+// that match no track start a new one; tracks not matched for max_age_ms of capture time are
+// dropped. The IDs are written into the payload before it is stored, evaluated against zones and
+// broadcast, and are unique across the server until it restarts.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::inference::InferencePayload;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackingConfig {
+    /// Lowest overlap at which a detection continues a track
+    #[serde(default = "default_iou_threshold")]
+    pub iou_threshold: f64,
+    /// How long a track survives without a matching detection
+    #[serde(default = "default_max_age_ms")]
+    pub max_age_ms: u64,
+}
+
+fn default_iou_threshold() -> f64 { 0.3 }
+fn default_max_age_ms() -> u64 { 1000 }
+
+impl TrackingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.iou_threshold > 0.0 && self.iou_threshold <= 1.0) {
+            return Err("iou_threshold must be greater than 0 and at most 1".to_string());
+        }
+        if self.max_age_ms == 0 {
+            return Err("max_age_ms must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    id: u64,
+    class: String,
+    bbox: [f64; 4],
+    /// Capture time of the last matching detection (unix ms)
+    last_seen_ms: i64,
+}
+
+/// Intersection over union of two [x, y, width, height] boxes
+fn iou(a: [f64; 4], b: [f64; 4]) -> f64 {
+    let width = (a[0] + a[2]).min(b[0] + b[2]) - a[0].max(b[0]);
+    let height = (a[1] + a[3]).min(b[1] + b[3]) - a[1].max(b[1]);
+    if width <= 0.0 || height <= 0.0 {
+        return 0.0;
+    }
+    let intersection = width * height;
+    let union = a[2] * a[3] + b[2] * b[3] - intersection;
+    if union > 0.0 { intersection / union } else { 0.0 }
+}
+
+/// Live tracks per room and source
+#[derive(Debug)]
+pub struct Tracker {
+    config: TrackingConfig,
+    next_id: u64,
+    /// (room_id, source_id) -> tracks
+    tracks: HashMap<(String, String), Vec<Track>>,
+}
+
+impl Tracker {
+    pub fn new(config: TrackingConfig) -> Self {
+        Self { config, next_id: 1, tracks: HashMap::new() }
+    }
+
+    /// Sets `track_id` on every detection of a normalized payload
+    pub fn assign(&mut self, room_id: &str, source_id: &str, payload: &mut Value) {
+        let Ok(mut parsed) = serde_json::from_value::<InferencePayload>(payload.clone()) else {
+            return;
+        };
+        let now = parsed.timestamp;
+        let tracks = self.tracks.entry((room_id.to_string(), source_id.to_string())).or_default();
+        tracks.retain(|track| now - track.last_seen_ms <= self.config.max_age_ms as i64);
+
+        let mut pairs: Vec<(f64, usize, usize)> = Vec::new();
+        for (t, track) in tracks.iter().enumerate() {
+            for (d, detection) in parsed.predictions.iter().enumerate() {
+                if detection.class != track.class {
+                    continue;
+                }
+                let overlap = iou(track.bbox, detection.bbox);
+                if overlap >= self.config.iou_threshold {
+                    pairs.push((overlap, t, d));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut track_taken = vec![false; tracks.len()];
+        let mut assigned: Vec<Option<u64>> = vec![None; parsed.predictions.len()];
+        for (_, t, d) in pairs {
+            if track_taken[t] || assigned[d].is_some() {
+                continue;
+            }
+            track_taken[t] = true;
+            assigned[d] = Some(tracks[t].id);
+            tracks[t].bbox = parsed.predictions[d].bbox;
+            tracks[t].last_seen_ms = now;
+        }
+
+        for (detection, assigned) in parsed.predictions.iter_mut().zip(assigned) {
+            let id = match assigned {
+                Some(id) => id,
+                None => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    tracks.push(Track { id, class: detection.class.clone(), bbox: detection.bbox, last_seen_ms: now });
+                    id
+                }
+            };
+            detection.track_id = Some(id);
+        }
+        *payload = parsed.to_value();
+    }
+
+    pub fn forget_source(&mut self, room_id: &str, source_id: &str) {
+        self.tracks.remove(&(room_id.to_string(), source_id.to_string()));
+    }
+
+    pub fn forget_room(&mut self, room_id: &str) {
+        self.tracks.retain(|(room, _), _| room != room_id);
+    }
+}
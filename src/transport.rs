@@ -0,0 +1,176 @@
+// transport.rs
+// STUN/TURN over TCP and TLS (turns:) for networks that block UDP entirely.
+// Both self-delimiting STUN framing (RFC 5389 §7.2.2) and RFC 4571 length-prefixed framing are
+// accepted; the framing is detected from the first message and used for the responses too.
+
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, error, debug};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+
+use crate::state::SharedState;
+use crate::stun_proto::MAGIC_COOKIE;
+
+const MAX_MESSAGE_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// Plain STUN messages back to back; the header carries the length
+    Stun,
+    /// Each message prefixed with a 2-byte big-endian length (RFC 4571)
+    Rfc4571,
+}
+
+/// Reads the PEM certificate chain and PKCS#8 key used for HTTPS
+pub fn load_certificate(cert_path: &str, key_path: &str) -> anyhow::Result<(Vec<rustls::Certificate>, rustls::PrivateKey)> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no PKCS#8 private key found in {}", key_path))?;
+    Ok((certs, rustls::PrivateKey(key)))
+}
+
+/// Builds a TLS acceptor from the PEM certificate and PKCS#8 key used for HTTPS
+pub fn load_tls_acceptor(cert_path: &str, key_path: &str) -> anyhow::Result<TlsAcceptor> {
+    let (certs, key) = load_certificate(cert_path, key_path)?;
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts TCP (optionally TLS) connections and feeds every framed message to `handler`,
+/// writing back whatever response it returns.
+pub async fn listen<F, Fut>(addr: SocketAddr, tls: Option<TlsAcceptor>, state: SharedState, handler: F, label: &'static str) -> std::io::Result<()>
+where
+    F: Fn(Vec<u8>, SocketAddr) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    info!("{} listening on {}", label, addr);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("{} accept error: {}", label, e);
+                continue;
+            }
+        };
+        if !state.ip_filter.permits(peer.ip()) {
+            debug!("{} refused connection from filtered address {}", label, peer);
+            continue;
+        }
+
+        let handler = handler.clone();
+        let tls = tls.clone();
+        tokio::task::spawn(async move {
+            match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(tls_stream) => serve_stream(tls_stream, peer, handler, label).await,
+                    Err(e) => debug!("{} TLS handshake with {} failed: {}", label, peer, e),
+                },
+                None => serve_stream(stream, peer, handler, label).await,
+            }
+        });
+    }
+}
+
+async fn serve_stream<S, F, Fut>(mut stream: S, peer: SocketAddr, handler: F, label: &'static str)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(Vec<u8>, SocketAddr) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    let mut framing = None;
+
+    loop {
+        let message = match read_message(&mut stream, &mut framing).await {
+            Ok(message) => message,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                    debug!("{} connection from {} closed: {}", label, peer, e);
+                }
+                return;
+            }
+        };
+
+        if let Some(response) = handler(message, peer).await {
+            let result = if framing == Some(Framing::Rfc4571) {
+                let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+                framed.extend_from_slice(&response);
+                stream.write_all(&framed).await
+            } else {
+                stream.write_all(&response).await
+            };
+            if let Err(e) = result {
+                debug!("{} write to {} failed: {}", label, peer, e);
+                return;
+            }
+        }
+    }
+}
+
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S, framing: &mut Option<Framing>) -> std::io::Result<Vec<u8>> {
+    match *framing {
+        Some(Framing::Stun) => read_stun_message(stream, Vec::new()).await,
+        Some(Framing::Rfc4571) => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await?;
+            let len = check_len(u16::from_be_bytes(len) as usize)?;
+            let mut message = vec![0u8; len];
+            stream.read_exact(&mut message).await?;
+            Ok(message)
+        }
+        None => {
+            // The magic cookie sits at offset 4 of a STUN header, or offset 6 behind an RFC 4571 prefix
+            let mut start = [0u8; 10];
+            stream.read_exact(&mut start).await?;
+
+            if start[4..8] == MAGIC_COOKIE {
+                *framing = Some(Framing::Stun);
+                read_stun_message(stream, start.to_vec()).await
+            } else if start[6..10] == MAGIC_COOKIE {
+                *framing = Some(Framing::Rfc4571);
+                let len = check_len(u16::from_be_bytes([start[0], start[1]]) as usize)?;
+                let mut message = start[2..].to_vec();
+                message.resize(len, 0);
+                stream.read_exact(&mut message[8..]).await?;
+                Ok(message)
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a STUN stream"))
+            }
+        }
+    }
+}
+
+/// Completes a STUN message of which `message` holds the first few bytes
+async fn read_stun_message<S: AsyncRead + Unpin>(stream: &mut S, mut message: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let have = message.len();
+    message.resize(20, 0);
+    stream.read_exact(&mut message[have..]).await?;
+
+    let len = check_len(20 + u16::from_be_bytes([message[2], message[3]]) as usize)?;
+    message.resize(len, 0);
+    stream.read_exact(&mut message[20..]).await?;
+    Ok(message)
+}
+
+fn check_len(len: usize) -> std::io::Result<usize> {
+    if (20..=MAX_MESSAGE_LEN).contains(&len) {
+        Ok(len)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid STUN message length {}", len)))
+    }
+}
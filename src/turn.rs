@@ -1,74 +1,366 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket as TokioUdpSocket;
-use log::{info, error, debug};
-use byteorder::{BigEndian, ByteOrder};
+use tracing::{info, warn, error, debug};
 use uuid::Uuid;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use md5::{Digest, Md5};
+use base64::Engine;
 
-// TURN message types
-const ALLOCATE_REQUEST: u16 = 0x0003;
-const ALLOCATE_RESPONSE: u16 = 0x0103;
-const ALLOCATE_ERROR_RESPONSE: u16 = 0x0113;
-const SEND_INDICATION: u16 = 0x0016;
-#[allow(dead_code)]
-const DATA_INDICATION: u16 = 0x0117;
+use crate::auth;
+use crate::network;
+use crate::state::SharedState;
+use crate::stun_proto::{self, Attribute, Header, Message, MessageType};
 
-// TURN attribute types
-const XOR_RELAYED_ADDRESS: u16 = 0x0016;
-const LIFETIME: u16 = 0x000d;
-const XOR_PEER_ADDRESS: u16 = 0x0012;
-const DATA: u16 = 0x0013;
+type HmacSha1 = Hmac<Sha1>;
+
+/// How long an allocation lives before its relay port is reclaimed
+const ALLOCATION_LIFETIME: Duration = Duration::from_secs(600);
+/// How often expired allocations are swept
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// Per-client usage is forgotten this long after the client's last allocation or relayed packet
+const CLIENT_USAGE_TTL: Duration = Duration::from_secs(3600);
+/// Client IPs tracked at most; the least recently seen is forgotten to make room
+const MAX_TRACKED_CLIENTS: usize = 4096;
+/// How long a NONCE handed out in a challenge is accepted; older ones get 438 Stale Nonce
+const NONCE_LIFETIME_SECS: i64 = 3600;
 
 #[derive(Debug, Clone)]
 pub struct TurnAllocation {
     #[allow(dead_code)]
     pub id: String,
-    #[allow(dead_code)]
     pub client_addr: SocketAddr,
-    #[allow(dead_code)]
     pub relayed_addr: SocketAddr,
     #[allow(dead_code)]
     pub peer_addr: Option<SocketAddr>,
-    #[allow(dead_code)]
-    pub lifetime: std::time::Instant,
+    pub lifetime: Instant,
     #[allow(dead_code)]
     pub permissions: HashMap<SocketAddr, std::time::Instant>,
+    pub bytes_relayed: u64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+/// Optional per-allocation limits; an allocation that exceeds either is torn down
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TurnQuota {
+    /// Total bytes an allocation may relay over its lifetime
+    pub max_bytes: Option<u64>,
+    /// Bytes an allocation may relay within any one-second window
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug)]
+struct ClientUsage {
+    allocations_created: u64,
+    bytes_relayed: u64,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    allocations_created: u64,
+    relayed_bytes: u64,
+    relayed_packets: u64,
+    quota_teardowns: u64,
+    /// Cumulative per client IP, outliving allocations up to CLIENT_USAGE_TTL
+    clients: HashMap<IpAddr, ClientUsage>,
+}
+
+impl Counters {
+    /// The usage of `client_ip`, marked as seen; the least recently seen client is forgotten
+    /// when MAX_TRACKED_CLIENTS are tracked already
+    fn client(&mut self, client_ip: IpAddr, now: Instant) -> &mut ClientUsage {
+        if !self.clients.contains_key(&client_ip) && self.clients.len() >= MAX_TRACKED_CLIENTS {
+            let oldest = self.clients.iter().min_by_key(|(_, usage)| usage.last_seen).map(|(ip, _)| *ip);
+            if let Some(oldest) = oldest {
+                self.clients.remove(&oldest);
+            }
+        }
+        let usage = self.clients.entry(client_ip)
+            .or_insert_with(|| ClientUsage { allocations_created: 0, bytes_relayed: 0, last_seen: now });
+        usage.last_seen = now;
+        usage
+    }
+}
+
+/// Usage counters of a TurnServer, shared with the admin API; clones see the same numbers
+#[derive(Debug, Clone)]
+pub struct TurnStats {
+    allocations: Arc<Mutex<HashMap<String, TurnAllocation>>>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl TurnStats {
+    fn allocated(&self, client_ip: IpAddr) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.allocations_created += 1;
+        counters.client(client_ip, Instant::now()).allocations_created += 1;
+    }
+
+    fn relayed(&self, client_ip: IpAddr, len: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.relayed_bytes += len;
+        counters.relayed_packets += 1;
+        counters.client(client_ip, Instant::now()).bytes_relayed += len;
+    }
+
+    /// Forgets clients without an active allocation that weren't seen for CLIENT_USAGE_TTL
+    fn prune_clients(&self, now: Instant) {
+        let active: std::collections::HashSet<IpAddr> = self.allocations.lock().unwrap()
+            .values()
+            .map(|allocation| allocation.client_addr.ip())
+            .collect();
+        self.counters.lock().unwrap().clients
+            .retain(|ip, usage| active.contains(ip) || now.duration_since(usage.last_seen) < CLIENT_USAGE_TTL);
+    }
+
+    fn torn_down(&self) {
+        self.counters.lock().unwrap().quota_teardowns += 1;
+    }
+
+    /// Totals, and per client IP seen recently the active and created allocations and bytes relayed
+    pub fn snapshot(&self) -> serde_json::Value {
+        let mut active_by_ip: HashMap<IpAddr, usize> = HashMap::new();
+        let active = {
+            let allocations = self.allocations.lock().unwrap();
+            for allocation in allocations.values() {
+                *active_by_ip.entry(allocation.client_addr.ip()).or_default() += 1;
+            }
+            allocations.len()
+        };
+        let counters = self.counters.lock().unwrap();
+        let clients: Vec<serde_json::Value> = counters.clients.iter()
+            .map(|(ip, usage)| serde_json::json!({
+                "ip": ip.to_string(),
+                "active_allocations": active_by_ip.get(ip).copied().unwrap_or(0),
+                "allocations_created": usage.allocations_created,
+                "bytes_relayed": usage.bytes_relayed,
+            }))
+            .collect();
+        serde_json::json!({
+            "active_allocations": active,
+            "active_clients": active_by_ip.len(),
+            "allocations_created": counters.allocations_created,
+            "relayed_bytes": counters.relayed_bytes,
+            "relayed_packets": counters.relayed_packets,
+            "quota_teardowns": counters.quota_teardowns,
+            "clients": clients,
+        })
+    }
+}
+
+/// Derives TURN REST API credentials (draft-uberti-behave-turn-rest): the username carries its
+/// own expiry as a unix timestamp and the password is base64(HMAC-SHA1(secret, username)).
+pub fn rest_credentials(secret: &str, user: &str, ttl_secs: u64) -> (String, String) {
+    let expiry = chrono::Utc::now().timestamp() + ttl_secs as i64;
+    let username = if user.is_empty() {
+        expiry.to_string()
+    } else {
+        format!("{}:{}", expiry, user)
+    };
+    let password = rest_password(secret, &username);
+    (username, password)
+}
+
+fn rest_password(secret: &str, username: &str) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(username.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Why an Allocate request was not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Challenge {
+    /// 401: credentials missing or wrong
+    Unauthorized,
+    /// 438: signed with a NONCE that expired; the client retries with the new one
+    StaleNonce,
+}
+
+/// Long-term credential check (RFC 5389 §10.2) against REST API credentials. Nonces carry the
+/// time they were issued, signed with a key of this server, so they expire without being stored.
+#[derive(Debug, Clone)]
+pub struct TurnAuth {
+    secret: String,
+    realm: String,
+    nonce_key: [u8; 16],
+}
+
+impl TurnAuth {
+    pub fn new(secret: String, realm: String) -> Self {
+        Self {
+            secret,
+            realm,
+            nonce_key: *Uuid::new_v4().as_bytes(),
+        }
+    }
+
+    /// A fresh NONCE: the issue time in hex followed by its signature
+    fn nonce(&self) -> String {
+        self.nonce_at(chrono::Utc::now().timestamp())
+    }
+
+    fn nonce_at(&self, issued_at: i64) -> String {
+        let mut mac = HmacSha1::new_from_slice(&self.nonce_key).expect("HMAC accepts keys of any length");
+        mac.update(&issued_at.to_be_bytes());
+        let mut nonce = format!("{:016x}", issued_at);
+        for byte in &mac.finalize().into_bytes()[..8] {
+            let _ = write!(nonce, "{:02x}", byte);
+        }
+        nonce
+    }
+
+    /// Whether this server issued `nonce` and when; None for anything else
+    fn nonce_issued_at(&self, nonce: &str) -> Option<i64> {
+        let issued_at = i64::from_str_radix(nonce.get(..16)?, 16).ok()?;
+        auth::secret_eq(nonce, self.nonce_at(issued_at)).then_some(issued_at)
+    }
+
+    /// Checks USERNAME/REALM/NONCE/MESSAGE-INTEGRITY and returns the HMAC key
+    /// to sign the response with, or why the request must be challenged.
+    fn verify(&self, packet: &[u8]) -> Result<Vec<u8>, Challenge> {
+        let (key, nonce) = self.signed_key(packet).ok_or(Challenge::Unauthorized)?;
+        match self.nonce_issued_at(&nonce) {
+            Some(issued_at) if chrono::Utc::now().timestamp() - issued_at <= NONCE_LIFETIME_SECS => Ok(key),
+            Some(_) => Err(Challenge::StaleNonce),
+            None => Err(Challenge::Unauthorized),
+        }
+    }
+
+    /// The HMAC key and NONCE of a request signed with valid, unexpired credentials
+    fn signed_key(&self, packet: &[u8]) -> Option<(Vec<u8>, String)> {
+        let mut username = None;
+        let mut realm = None;
+        let mut nonce = None;
+        let mut integrity = None;
+
+        for raw in stun_proto::raw_attributes(packet) {
+            let raw = raw.ok()?;
+            match raw.decode().ok()? {
+                Attribute::Username(value) => username = Some(value),
+                Attribute::Realm(value) => realm = Some(value),
+                Attribute::Nonce(value) => nonce = Some(value),
+                Attribute::MessageIntegrity(mac) => {
+                    // Anything after MESSAGE-INTEGRITY (e.g. FINGERPRINT) is not covered by it
+                    integrity = Some((raw.offset, mac));
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let (username, realm, nonce, (integrity_offset, received)) = (username?, realm?, nonce?, integrity?);
+        if realm != self.realm {
+            return None;
+        }
+
+        // The username starts with the unix time at which it stops being valid
+        let expiry: i64 = username.split(':').next()?.parse().ok()?;
+        if expiry < chrono::Utc::now().timestamp() {
+            debug!("TURN credentials for {} expired", username);
+            return None;
+        }
+
+        let password = rest_password(&self.secret, &username);
+        let key = Md5::digest(format!("{}:{}:{}", username, self.realm, password).as_bytes()).to_vec();
+        let expected = message_integrity(&packet[..integrity_offset], &key);
+        auth::secret_eq(received, expected).then_some((key, nonce))
+    }
+}
+
+/// HMAC-SHA1 over the message preceding MESSAGE-INTEGRITY, with the header length
+/// adjusted to end right after the MESSAGE-INTEGRITY attribute (RFC 5389 §15.4).
+fn message_integrity(preceding: &[u8], key: &[u8]) -> [u8; 20] {
+    // The header length field is 16 bits, so a valid message can't overflow it
+    let adjusted_len = u16::try_from(preceding.len() + 24 - 20).unwrap_or(u16::MAX);
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&preceding[0..2]);
+    mac.update(&adjusted_len.to_be_bytes());
+    mac.update(&preceding[4..]);
+    let mut integrity = [0u8; 20];
+    integrity.copy_from_slice(&mac.finalize().into_bytes());
+    integrity
 }
 
 pub struct TurnServer {
-    socket: Arc<TokioUdpSocket>,
+    /// One per configured UDP address; responses leave through the socket the request came in on
+    sockets: Vec<Arc<TokioUdpSocket>>,
+    auth: Option<TurnAuth>,
     allocations: Arc<Mutex<HashMap<String, TurnAllocation>>>,
     relay_ports: Arc<Mutex<HashMap<u16, String>>>, // port -> allocation_id
-    next_relay_port: u16,
+    relay_port_range: RangeInclusive<u16>,
+    next_relay_port: Mutex<u16>,
+    quota: TurnQuota,
+    /// Public IP put in XOR-RELAYED-ADDRESS; the client's own IP when unset
+    relay_ip: Option<IpAddr>,
+    stats: TurnStats,
+    state: SharedState,
 }
 
 impl TurnServer {
-    pub fn new(bind_addr: SocketAddr) -> std::io::Result<Self> {
-        let socket = std::net::UdpSocket::bind(bind_addr)?;
-        socket.set_nonblocking(true)?;
-        let tokio_socket = TokioUdpSocket::from_std(socket)?;
-        
-        info!("TURN server listening on {}", bind_addr);
+    pub fn new(bind_addrs: &[SocketAddr], auth: Option<TurnAuth>, relay_port_range: RangeInclusive<u16>, state: SharedState) -> std::io::Result<Self> {
+        let mut sockets = Vec::with_capacity(bind_addrs.len());
+        for bind_addr in bind_addrs {
+            let socket = network::bind_udp(*bind_addr)?;
+            socket.set_nonblocking(true)?;
+            sockets.push(Arc::new(TokioUdpSocket::from_std(socket)?));
+            info!("TURN server listening on {}", bind_addr);
+        }
         
+        let allocations = Arc::new(Mutex::new(HashMap::new()));
         Ok(Self {
-            socket: Arc::new(tokio_socket),
-            allocations: Arc::new(Mutex::new(HashMap::new())),
+            sockets,
+            auth,
+            stats: TurnStats { allocations: allocations.clone(), counters: Arc::default() },
+            allocations,
             relay_ports: Arc::new(Mutex::new(HashMap::new())),
-            next_relay_port: 49152, // Start of dynamic port range
+            next_relay_port: Mutex::new(*relay_port_range.start()),
+            relay_port_range,
+            quota: TurnQuota::default(),
+            relay_ip: None,
+            state,
         })
     }
     
-    pub async fn run(&mut self) -> std::io::Result<()> {
+    pub fn with_quota(mut self, quota: TurnQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+    
+    pub fn with_relay_ip(mut self, relay_ip: Option<IpAddr>) -> Self {
+        self.relay_ip = relay_ip;
+        self
+    }
+    
+    pub async fn run(&self) -> std::io::Result<()> {
+        let receivers = self.sockets.iter().map(|socket| self.receive(socket));
+        let sweeper = async {
+            let mut sweep = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                sweep.tick().await;
+                self.expire_allocations(Instant::now());
+            }
+        };
+        tokio::join!(futures_util::future::join_all(receivers), sweeper);
+        Ok(())
+    }
+    
+    async fn receive(&self, socket: &TokioUdpSocket) {
         let mut buf = [0u8; 2048];
         
         loop {
-            match self.socket.recv_from(&mut buf).await {
+            match socket.recv_from(&mut buf).await {
                 Ok((len, src_addr)) => {
                     let packet = &buf[..len];
                     
                     if let Some(response) = self.handle_turn_packet(packet, src_addr).await {
-                        if let Err(e) = self.socket.send_to(&response, src_addr).await {
+                        if let Err(e) = socket.send_to(&response, src_addr).await {
                             error!("Failed to send TURN response: {}", e);
                         }
                     }
@@ -80,152 +372,97 @@ impl TurnServer {
         }
     }
     
-    async fn handle_turn_packet(&mut self, packet: &[u8], src_addr: SocketAddr) -> Option<Vec<u8>> {
-        if packet.len() < 20 {
-            debug!("Packet too short for TURN message");
-            return None;
-        }
-        
-        let msg_type = BigEndian::read_u16(&packet[0..2]);
-        let msg_len = BigEndian::read_u16(&packet[2..4]);
-        
-        // Verify packet length
-        if packet.len() != 20 + msg_len as usize {
-            debug!("TURN packet length mismatch");
+    /// Handles one TURN message; shared by the UDP loop and the TCP/TLS transport
+    #[tracing::instrument(name = "turn_packet", skip_all, fields(peer = %src_addr, bytes = packet.len()))]
+    pub async fn handle_turn_packet(&self, packet: &[u8], src_addr: SocketAddr) -> Option<Vec<u8>> {
+        if !self.state.ip_filter.permits(src_addr.ip()) {
+            debug!("Dropped TURN packet from filtered address");
             return None;
         }
+        let header = match stun_proto::decode_header(packet) {
+            Ok(header) => header,
+            Err(e) => {
+                debug!("Dropped TURN packet: {}", e);
+                return None;
+            }
+        };
         
-        match msg_type {
-            ALLOCATE_REQUEST => {
+        match header.message_type {
+            MessageType::AllocateRequest => {
                 debug!("TURN allocate request from {}", src_addr);
-                Some(self.create_allocate_response(packet, src_addr).await)
+                let key = match &self.auth {
+                    None => None,
+                    Some(auth) => match auth.verify(packet) {
+                        Ok(key) => Some(key),
+                        Err(challenge) => {
+                            debug!("TURN allocate from {} challenged: {:?}", src_addr, challenge);
+                            return Some(self.create_challenge_response(&header, auth, challenge));
+                        }
+                    },
+                };
+                Some(self.create_allocate_response(&header, src_addr, key.as_deref()).await)
             }
-            SEND_INDICATION => {
+            MessageType::SendIndication => {
                 debug!("TURN send indication from {}", src_addr);
                 self.handle_send_indication(packet, src_addr).await;
                 None
             }
-            _ => {
-                debug!("Unsupported TURN message type: 0x{:04x}", msg_type);
-                Some(self.create_error_response(packet, 400, "Bad Request"))
+            other => {
+                debug!("Unsupported TURN message type: 0x{:04x}", other.to_u16());
+                Some(self.create_error_response(&header, 400, "Bad Request"))
             }
         }
     }
     
-    async fn create_allocate_response(&mut self, request: &[u8], client_addr: SocketAddr) -> Vec<u8> {
-        let allocation_id = Uuid::new_v4().to_string();
-        let relayed_port = self.get_next_relay_port();
-        let relayed_addr = SocketAddr::new(client_addr.ip(), relayed_port);
-        
-        // Create allocation
-        let allocation = TurnAllocation {
-            id: allocation_id.clone(),
-            client_addr,
-            relayed_addr,
-            peer_addr: None,
-            lifetime: std::time::Instant::now() + std::time::Duration::from_secs(600), // 10 minutes
-            permissions: HashMap::new(),
+    async fn create_allocate_response(&self, request: &Header, client_addr: SocketAddr, key: Option<&[u8]>) -> Vec<u8> {
+        let (allocation_id, relayed_addr) = match self.allocate(client_addr) {
+            Some(allocation) => allocation,
+            None => {
+                error!("No free TURN relay port for {} in {:?}", client_addr, self.relay_port_range);
+                return self.create_error_response(request, 508, "Insufficient Capacity");
+            }
         };
         
-        // Store allocation
-        {
-            let mut allocations = self.allocations.lock().unwrap();
-            allocations.insert(allocation_id.clone(), allocation);
-        }
-        
-        {
-            let mut relay_ports = self.relay_ports.lock().unwrap();
-            relay_ports.insert(relayed_port, allocation_id.clone());
-        }
-        
-        info!("Created TURN allocation {} for {} -> {}", allocation_id, client_addr, relayed_addr);
-        
-        // Build response
-        let mut response = Vec::new();
-        
-        // Message header
-        response.extend_from_slice(&ALLOCATE_RESPONSE.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes()); // Length (placeholder)
-        response.extend_from_slice(&request[4..20]); // Copy magic cookie and transaction ID
-        
-        // XOR-RELAYED-ADDRESS attribute
-        let attr_type = XOR_RELAYED_ADDRESS;
-        let attr_len = 8u16;
-        
-        response.extend_from_slice(&attr_type.to_be_bytes());
-        response.extend_from_slice(&attr_len.to_be_bytes());
-        response.push(0x00); // Reserved
-        response.push(0x01); // IPv4 family
-        
-        let ip = relayed_addr.ip();
-        let port = relayed_addr.port() ^ 0x2112; // XOR with magic cookie
+        info!("TURN allocation {} for {} -> {}", allocation_id, client_addr, relayed_addr);
         
-        response.extend_from_slice(&port.to_be_bytes());
+        let attributes = vec![
+            Attribute::XorRelayedAddress(relayed_addr),
+            Attribute::Lifetime(ALLOCATION_LIFETIME.as_secs() as u32),
+        ];
+        let mut response = Message::reply(request, MessageType::AllocateResponse, attributes).encode();
         
-        match ip {
-            std::net::IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                for octet in octets {
-                    response.push(octet ^ 0x21); // XOR with magic cookie bytes
-                }
-            }
-            std::net::IpAddr::V6(_) => {
-                response.extend_from_slice(&[0; 16]);
-            }
+        // Authenticated requests get an authenticated response
+        if let Some(key) = key {
+            let integrity = message_integrity(&response, key);
+            stun_proto::append_attribute(&mut response, &Attribute::MessageIntegrity(integrity));
         }
         
-        // LIFETIME attribute (600 seconds)
-        let lifetime_attr = LIFETIME;
-        let lifetime_len = 4u16;
-        response.extend_from_slice(&lifetime_attr.to_be_bytes());
-        response.extend_from_slice(&lifetime_len.to_be_bytes());
-        response.extend_from_slice(&600u32.to_be_bytes());
-        
-        // Update message length
-        let total_len = response.len() - 20;
-        response[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
-        
         response
     }
     
     async fn handle_send_indication(&self, packet: &[u8], src_addr: SocketAddr) {
         // Parse XOR-PEER-ADDRESS and DATA attributes
+        let message = match stun_proto::decode(packet) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Dropped TURN send indication from {}: {}", src_addr, e);
+                return;
+            }
+        };
         let mut peer_addr = None;
         let mut data = None;
-        
-        let mut pos = 20; // Skip header
-        while pos + 4 <= packet.len() {
-            let attr_type = BigEndian::read_u16(&packet[pos..pos+2]);
-            let attr_len = BigEndian::read_u16(&packet[pos+2..pos+4]);
-            pos += 4;
-            
-            if pos + attr_len as usize > packet.len() {
-                break;
-            }
-            
-            match attr_type {
-                XOR_PEER_ADDRESS => {
-                    if attr_len >= 8 {
-                        let port = BigEndian::read_u16(&packet[pos+2..pos+4]) ^ 0x2112;
-                        let ip_bytes = &packet[pos+4..pos+8];
-                        let mut octets = [0u8; 4];
-                        for (i, &byte) in ip_bytes.iter().enumerate() {
-                            octets[i] = byte ^ 0x21;
-                        }
-                        let ip = std::net::Ipv4Addr::from(octets);
-                        peer_addr = Some(SocketAddr::new(std::net::IpAddr::V4(ip), port));
-                    }
-                }
-                DATA => {
-                    data = Some(&packet[pos..pos+attr_len as usize]);
-                }
+        for attribute in &message.attributes {
+            match attribute {
+                Attribute::XorPeerAddress(addr) => peer_addr = Some(*addr),
+                Attribute::Data(bytes) => data = Some(bytes),
                 _ => {}
             }
-            
-            pos += (attr_len as usize + 3) & !3; // Round up to 4-byte boundary
         }
         
         if let (Some(peer), Some(data_bytes)) = (peer_addr, data) {
+            if !self.account_relayed(src_addr, data_bytes.len() as u64) {
+                return;
+            }
             debug!("Relaying data from {} to {}", src_addr, peer);
             
             // In a real implementation, you would forward this data to the peer
@@ -234,46 +471,192 @@ impl TurnServer {
         }
     }
     
-    fn create_error_response(&self, request: &[u8], code: u16, reason: &str) -> Vec<u8> {
-        let mut response = Vec::new();
+    fn create_error_response(&self, request: &Header, code: u16, reason: &str) -> Vec<u8> {
+        let attributes = vec![Attribute::ErrorCode { code, reason: reason.to_string() }];
+        Message::reply(request, MessageType::AllocateErrorResponse, attributes).encode()
+    }
+    
+    /// 401 or 438 challenge carrying the REALM and a fresh NONCE the client must sign its retry with
+    fn create_challenge_response(&self, request: &Header, auth: &TurnAuth, challenge: Challenge) -> Vec<u8> {
+        let (code, reason) = match challenge {
+            Challenge::Unauthorized => (401, "Unauthorized"),
+            Challenge::StaleNonce => (438, "Stale Nonce"),
+        };
+        let attributes = vec![
+            Attribute::ErrorCode { code, reason: reason.to_string() },
+            Attribute::Realm(auth.realm.clone()),
+            Attribute::Nonce(auth.nonce()),
+        ];
+        Message::reply(request, MessageType::AllocateErrorResponse, attributes).encode()
+    }
+    
+    /// Returns the client's live allocation with its lifetime refreshed, so retransmitted
+    /// Allocate requests don't leak ports, or creates one on a free relay port.
+    fn allocate(&self, client_addr: SocketAddr) -> Option<(String, SocketAddr)> {
+        let lifetime = Instant::now() + ALLOCATION_LIFETIME;
+        let mut allocations = self.allocations.lock().unwrap();
+        if let Some(existing) = allocations.values_mut().find(|a| a.client_addr == client_addr) {
+            existing.lifetime = lifetime;
+            return Some((existing.id.clone(), existing.relayed_addr));
+        }
         
-        // Message header
-        response.extend_from_slice(&ALLOCATE_ERROR_RESPONSE.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes()); // Length (placeholder)
-        response.extend_from_slice(&request[4..20]); // Copy magic cookie and transaction ID
+        let mut relay_ports = self.relay_ports.lock().unwrap();
+        let relayed_port = self.next_free_relay_port(&relay_ports)?;
+        let allocation_id = Uuid::new_v4().to_string();
+        let relayed_addr = SocketAddr::new(self.relay_ip.unwrap_or(client_addr.ip()), relayed_port);
         
-        // ERROR-CODE attribute
-        let error_class = code / 100;
-        let _error_number = code % 100;
-        let reason_bytes = reason.as_bytes();
-        let attr_len = 4 + reason_bytes.len() as u16;
+        allocations.insert(allocation_id.clone(), TurnAllocation {
+            id: allocation_id.clone(),
+            client_addr,
+            relayed_addr,
+            peer_addr: None,
+            lifetime,
+            permissions: HashMap::new(),
+            bytes_relayed: 0,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        });
+        relay_ports.insert(relayed_port, allocation_id.clone());
+        self.stats.allocated(client_addr.ip());
         
-        response.extend_from_slice(&0u16.to_be_bytes()); // ERROR-CODE attribute type
-        response.extend_from_slice(&attr_len.to_be_bytes());
-        response.extend_from_slice(&0u16.to_be_bytes());
-        response.push((error_class / 100) as u8);
-        response.push((error_class % 100) as u8);
-        response.extend_from_slice(reason_bytes);
+        Some((allocation_id, relayed_addr))
+    }
+    
+    /// Walks the configured range from where the last search stopped, skipping ports
+    /// still held by an allocation. None when every port in the range is in use.
+    fn next_free_relay_port(&self, in_use: &HashMap<u16, String>) -> Option<u16> {
+        let (min, max) = (*self.relay_port_range.start(), *self.relay_port_range.end());
+        let mut next_relay_port = self.next_relay_port.lock().unwrap();
+        for _ in 0..=(max - min) as u32 {
+            let port = *next_relay_port;
+            *next_relay_port = if port >= max { min } else { port + 1 };
+            if !in_use.contains_key(&port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+    
+    /// Charges relayed bytes to the sender's allocation and client IP. Returns false when
+    /// there is no allocation to relay through or the allocation was torn down for
+    /// exceeding its quota.
+    fn account_relayed(&self, src_addr: SocketAddr, len: u64) -> bool {
+        let now = Instant::now();
+        let mut allocations = self.allocations.lock().unwrap();
+        let (allocation_id, over_quota) = match allocations.values_mut().find(|a| a.client_addr == src_addr) {
+            Some(allocation) => {
+                if now.duration_since(allocation.window_start) >= Duration::from_secs(1) {
+                    allocation.window_start = now;
+                    allocation.window_bytes = 0;
+                }
+                allocation.bytes_relayed += len;
+                allocation.window_bytes += len;
+                
+                let over_total = matches!(self.quota.max_bytes, Some(max) if allocation.bytes_relayed > max);
+                let over_rate = matches!(self.quota.max_bytes_per_sec, Some(max) if allocation.window_bytes > max);
+                (allocation.id.clone(), over_total || over_rate)
+            }
+            None => {
+                debug!("TURN send indication from {} without an allocation", src_addr);
+                return false;
+            }
+        };
         
-        // Update message length
-        let total_len = response.len() - 20;
-        response[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+        self.stats.relayed(src_addr.ip(), len);
         
-        response
+        if over_quota {
+            if let Some(allocation) = allocations.remove(&allocation_id) {
+                self.relay_ports.lock().unwrap().remove(&allocation.relayed_addr.port());
+                warn!(
+                    "TURN allocation {} for {} exceeded its quota after {} bytes; torn down",
+                    allocation_id, src_addr, allocation.bytes_relayed
+                );
+            }
+            self.stats.torn_down();
+            return false;
+        }
+        true
+    }
+    
+    /// Per-allocation and per-client-IP relay totals for the admin API
+    pub fn usage(&self) -> serde_json::Value {
+        let now = Instant::now();
+        let allocations: Vec<serde_json::Value> = self.allocations.lock().unwrap()
+            .values()
+            .map(|a| serde_json::json!({
+                "id": a.id,
+                "client_addr": a.client_addr.to_string(),
+                "relayed_addr": a.relayed_addr.to_string(),
+                "bytes_relayed": a.bytes_relayed,
+                "expires_in_secs": a.lifetime.saturating_duration_since(now).as_secs(),
+            }))
+            .collect();
+        let counters = self.stats.counters.lock().unwrap();
+        let clients: serde_json::Map<String, serde_json::Value> = counters.clients
+            .iter()
+            .map(|(ip, usage)| (ip.to_string(), serde_json::json!(usage.bytes_relayed)))
+            .collect();
+        
+        serde_json::json!({
+            "allocations": allocations,
+            "bytes_by_client_ip": clients,
+            "quota_teardowns": counters.quota_teardowns,
+        })
+    }
+
+    /// Handle to the usage counters, for GET /api/admin/turn
+    pub fn stats(&self) -> TurnStats {
+        self.stats.clone()
+    }
+    
+    /// Prometheus text exposition of the relay counters
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let allocations = self.allocations.lock().unwrap();
+        let _ = writeln!(out, "# HELP turn_allocations Active TURN allocations");
+        let _ = writeln!(out, "# TYPE turn_allocations gauge");
+        let _ = writeln!(out, "turn_allocations {}", allocations.len());
+        let _ = writeln!(out, "# HELP turn_allocation_relayed_bytes Bytes relayed by each active allocation");
+        let _ = writeln!(out, "# TYPE turn_allocation_relayed_bytes gauge");
+        for allocation in allocations.values() {
+            let _ = writeln!(out, "turn_allocation_relayed_bytes{{allocation=\"{}\"}} {}", allocation.id, allocation.bytes_relayed);
+        }
+        drop(allocations);
+        
+        // Per-IP figures stay on the admin API: /metrics is unauthenticated
+        let _ = writeln!(out, "# HELP turn_relayed_bytes_total Bytes relayed");
+        let _ = writeln!(out, "# TYPE turn_relayed_bytes_total counter");
+        let counters = self.stats.counters.lock().unwrap();
+        let _ = writeln!(out, "turn_relayed_bytes_total {}", counters.relayed_bytes);
+        let _ = writeln!(out, "# HELP turn_allocations_created_total TURN allocations created");
+        let _ = writeln!(out, "# TYPE turn_allocations_created_total counter");
+        let _ = writeln!(out, "turn_allocations_created_total {}", counters.allocations_created);
+        let _ = writeln!(out, "# HELP turn_quota_teardowns_total Allocations torn down for exceeding their quota");
+        let _ = writeln!(out, "# TYPE turn_quota_teardowns_total counter");
+        let _ = writeln!(out, "turn_quota_teardowns_total {}", counters.quota_teardowns);
+        out
     }
     
-    fn get_next_relay_port(&mut self) -> u16 {
-        let port = self.next_relay_port;
-        if self.next_relay_port == 65535 {
-            self.next_relay_port = 49152; // Wrap around
-        } else {
-            self.next_relay_port += 1;
+    /// Drops allocations whose lifetime has passed and returns their ports to the pool, then
+    /// forgets the usage of clients gone for CLIENT_USAGE_TTL
+    pub fn expire_allocations(&self, now: Instant) {
+        {
+            let mut allocations = self.allocations.lock().unwrap();
+            let mut relay_ports = self.relay_ports.lock().unwrap();
+            allocations.retain(|id, allocation| {
+                if allocation.lifetime > now {
+                    return true;
+                }
+                relay_ports.remove(&allocation.relayed_addr.port());
+                info!("TURN allocation {} for {} expired", id, allocation.client_addr);
+                false
+            });
         }
-        port
+        self.stats.prune_clients(now);
     }
     
     #[allow(dead_code)]
     pub fn get_local_address(&self) -> std::io::Result<SocketAddr> {
-        self.socket.local_addr()
+        self.sockets[0].local_addr()
     }
 }
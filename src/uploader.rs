@@ -0,0 +1,379 @@
+// uploader.rs
+// Ships data off small edge disks: every interval_secs, rotated JSONL exports, snapshots and
+// recording segments that haven't changed for min_age_secs are PUT to an S3-compatible bucket
+// (AWS S3, MinIO, or GCS through its XML API with HMAC keys), signed with AWS Signature V4.
+// Object keys are a prefix template followed by the file's path below its directory. Uploaded
+// files are remembered in SQLite (or deleted, with delete_after_upload) so they go up once; a
+// failed PUT is retried with backoff and the file is tried again on the next run.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, warn};
+
+use crate::persistence;
+use crate::retention;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadConfig {
+    /// e.g. https://s3.eu-west-1.amazonaws.com, https://storage.googleapis.com, http://minio:9000
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Falls back to the AWS_ACCESS_KEY_ID environment variable
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    /// Falls back to the AWS_SECRET_ACCESS_KEY environment variable
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    /// Put the bucket in the path (endpoint/bucket/key) instead of the host name
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    /// Start of every object key; {kind} (jsonl, snapshots or recordings) and {date} (the file's
+    /// UTC modification date) are replaced
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Files modified more recently than this may still be written and are left for later
+    #[serde(default = "default_min_age_secs")]
+    pub min_age_secs: u64,
+    /// Attempts per file and run; 5xx responses and connection errors are retried
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_true")]
+    pub jsonl: bool,
+    #[serde(default = "default_true")]
+    pub snapshots: bool,
+    #[serde(default = "default_true")]
+    pub recordings: bool,
+    /// Delete local files once uploaded instead of remembering them
+    #[serde(default)]
+    pub delete_after_upload: bool,
+}
+
+fn default_region() -> String { "us-east-1".to_string() }
+fn default_path_style() -> bool { true }
+fn default_prefix() -> String { "{kind}/{date}".to_string() }
+fn default_interval_secs() -> u64 { 300 }
+fn default_min_age_secs() -> u64 { 60 }
+fn default_max_attempts() -> u32 { 5 }
+fn default_timeout_secs() -> u64 { 300 }
+fn default_true() -> bool { true }
+
+impl UploadConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match reqwest::Url::parse(&self.endpoint) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => {}
+            _ => return Err("endpoint must be an http(s) URL".to_string()),
+        }
+        if self.bucket.is_empty() || self.region.is_empty() {
+            return Err("bucket and region must not be empty".to_string());
+        }
+        if self.interval_secs == 0 || self.max_attempts == 0 || self.timeout_secs == 0 {
+            return Err("interval_secs, max_attempts and timeout_secs must be greater than 0".to_string());
+        }
+        if self.access_key_id.is_some() != self.secret_access_key.is_some() {
+            return Err("access_key_id and secret_access_key must be set together".to_string());
+        }
+        Ok(())
+    }
+
+    fn credentials(&self) -> Option<(String, String)> {
+        let access_key_id = self.access_key_id.clone().or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())?;
+        let secret_access_key = self.secret_access_key.clone().or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())?;
+        Some((access_key_id, secret_access_key))
+    }
+}
+
+/// Where files of one kind are found
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Jsonl,
+    Snapshots,
+    Recordings,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Snapshots => "snapshots",
+            Self::Recordings => "recordings",
+        }
+    }
+}
+
+/// A file ready to go up
+struct Candidate {
+    path: PathBuf,
+    key: String,
+    bytes: u64,
+}
+
+/// Runs an upload pass every `interval_secs`
+pub fn spawn(config: UploadConfig, db_path: String, jsonl_path: String, snapshot_dir: String, recording_dir: String) {
+    tokio::task::spawn(async move {
+        let Some(credentials) = config.credentials() else {
+            error!("Uploads to {} are disabled: no access key configured", config.bucket);
+            return;
+        };
+        let client = match reqwest::Client::builder().timeout(Duration::from_secs(config.timeout_secs)).build() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Upload client could not be built: {}", e);
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            let mut sources = Vec::new();
+            if config.jsonl {
+                sources.push((Kind::Jsonl, PathBuf::from(&jsonl_path)));
+            }
+            if config.snapshots {
+                sources.push((Kind::Snapshots, PathBuf::from(&snapshot_dir)));
+            }
+            if config.recordings {
+                sources.push((Kind::Recordings, PathBuf::from(&recording_dir)));
+            }
+            let (scan_config, scan_db_path) = (config.clone(), db_path.clone());
+            let candidates = tokio::task::spawn_blocking(move || scan(&scan_config, &scan_db_path, &sources, SystemTime::now())).await;
+            let candidates = match candidates {
+                Ok(candidates) => candidates,
+                Err(e) => {
+                    error!("Upload scan failed: {}", e);
+                    continue;
+                }
+            };
+            for candidate in candidates {
+                upload(&client, &config, &credentials, &db_path, candidate).await;
+            }
+        }
+    });
+}
+
+/// Settled files not uploaded yet, across all enabled kinds
+fn scan(config: &UploadConfig, db_path: &str, sources: &[(Kind, PathBuf)], now: SystemTime) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    for (kind, location) in sources {
+        let (root, files) = match kind {
+            Kind::Jsonl => {
+                let root = location.parent().map(Path::to_path_buf).unwrap_or_default();
+                (root, retention::rotated_files(location))
+            }
+            Kind::Snapshots | Kind::Recordings => (location.clone(), walk(location)),
+        };
+        let files = match files {
+            Ok(files) => files,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("Failed to list {} for upload: {}", location.display(), e);
+                continue;
+            }
+        };
+        for path in files {
+            let Ok(metadata) = std::fs::metadata(&path) else { continue };
+            let modified = metadata.modified().unwrap_or(now);
+            let settled = now.duration_since(modified).is_ok_and(|age| age.as_secs() >= config.min_age_secs);
+            if !settled || metadata.len() == 0 {
+                continue;
+            }
+            let local = path.display().to_string();
+            match persistence::is_uploaded(db_path, &local, metadata.len()) {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("Failed to look up upload state of {}: {}", local, e);
+                    continue;
+                }
+            }
+            let relative = path.strip_prefix(&root).unwrap_or(&path);
+            candidates.push(Candidate {
+                key: object_key(&config.prefix, *kind, modified.into(), relative),
+                path,
+                bytes: metadata.len(),
+            });
+        }
+    }
+    candidates
+}
+
+/// Every file below `dir`
+fn walk(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The expanded prefix and the file's path below its directory, joined with '/'
+fn object_key(prefix: &str, kind: Kind, modified: DateTime<Utc>, relative: &Path) -> String {
+    let prefix = prefix
+        .replace("{kind}", kind.name())
+        .replace("{date}", &modified.format("%Y-%m-%d").to_string());
+    let relative: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    [prefix.trim_matches('/').to_string(), relative.join("/")]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+async fn upload(client: &reqwest::Client, config: &UploadConfig, credentials: &(String, String), db_path: &str, candidate: Candidate) {
+    let body = match tokio::fs::read(&candidate.path).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to read {} for upload: {}", candidate.path.display(), e);
+            return;
+        }
+    };
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=config.max_attempts {
+        let request = match signed_put(client, config, credentials, &candidate.key, body.clone(), Utc::now()) {
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed to build upload of {}: {}", candidate.key, e);
+                return;
+            }
+        };
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("Uploaded {} as {}", candidate.path.display(), candidate.key);
+                finish(config, db_path, candidate).await;
+                return;
+            }
+            Ok(response) if !response.status().is_server_error() => {
+                let status = response.status();
+                let detail = response.text().await.unwrap_or_default();
+                warn!("Upload of {} was refused with {}: {}", candidate.key, status, detail);
+                return;
+            }
+            Ok(response) => warn!("Upload of {} failed with {} (attempt {})", candidate.key, response.status(), attempt),
+            Err(e) => warn!("Upload of {} failed: {} (attempt {})", candidate.key, e, attempt),
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+/// Deletes or remembers an uploaded file
+async fn finish(config: &UploadConfig, db_path: &str, candidate: Candidate) {
+    if config.delete_after_upload {
+        match tokio::fs::remove_file(&candidate.path).await {
+            Ok(()) => info!("Uploaded and removed {}", candidate.path.display()),
+            Err(e) => warn!("Uploaded {} but could not remove it: {}", candidate.path.display(), e),
+        }
+        return;
+    }
+    let (db_path, local) = (db_path.to_string(), candidate.path.display().to_string());
+    let recorded = tokio::task::spawn_blocking(move || persistence::record_upload(&db_path, &local, candidate.bytes, &candidate.key)).await;
+    match recorded {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Failed to record upload: {}", e),
+        Err(e) => error!("Failed to record upload: {}", e),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters, keeping '/' between segments
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// PUT of `body` as `key`, signed with AWS Signature V4
+fn signed_put(
+    client: &reqwest::Client,
+    config: &UploadConfig,
+    (access_key_id, secret_access_key): &(String, String),
+    key: &str,
+    body: Vec<u8>,
+    now: DateTime<Utc>,
+) -> Result<reqwest::RequestBuilder, String> {
+    let mut url = reqwest::Url::parse(&config.endpoint).map_err(|e| e.to_string())?;
+    let base = url.path().trim_end_matches('/').to_string();
+    let path = if config.path_style {
+        format!("{}/{}/{}", base, config.bucket, key)
+    } else {
+        let host = url.host_str().ok_or("endpoint has no host")?;
+        let host = format!("{}.{}", config.bucket, host);
+        url.set_host(Some(&host)).map_err(|e| e.to_string())?;
+        format!("{}/{}", base, key)
+    };
+    let canonical_uri = encode_path(&path);
+    url.set_path(&canonical_uri);
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err("endpoint has no host".to_string()),
+    };
+
+    let payload_hash = hex(&Sha256::digest(&body));
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        canonical_uri, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut signing_key = hmac(format!("AWS4{}", secret_access_key).as_bytes(), &date);
+    for part in [config.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac(&signing_key, part);
+    }
+    let signature = hex(&hmac(&signing_key, &string_to_sign));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key_id, scope, signed_headers, signature
+    );
+
+    Ok(client
+        .put(url)
+        .header("x-amz-date", amz_date)
+        .header("x-amz-content-sha256", payload_hash)
+        .header(reqwest::header::AUTHORIZATION, authorization)
+        .body(body))
+}
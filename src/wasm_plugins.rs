@@ -0,0 +1,335 @@
+// wasm_plugins.rs
+// Sandboxed user plugins: WebAssembly modules (run with wasmtime behind the `wasm-plugins`
+// feature) that implement the SignalingHook interface, so operators can deploy routing and
+// validation logic at runtime with PUT /api/admin/plugins/{name} instead of rebuilding the
+// server. Modules get no imports at all (no WASI, no host calls), a memory cap and a fuel budget
+// per call, and a fresh instance for every call, so a plugin can only look at the message it is
+// given and answer. Uploaded modules are kept in the plugin directory and loaded again on start;
+// they run in name order, after any native hooks.
+//
+// ABI: a module exports `memory`, `alloc(len: i32) -> i32` and any of `on_join`, `on_message`
+// and `on_leave`, each `(ptr: i32, len: i32) -> i64`. The input is a JSON object with hook,
+// room_id, connection_id, role and (except for on_leave) message. The result is 0 to continue
+// unchanged, or the pointer and length of a JSON answer packed as (ptr << 32) | len:
+// {"action": "continue", "message": {...}} to replace the message or
+// {"action": "reject", "reason": "..."} to refuse it.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::hooks::{HookContext, HookDecision, SignalingHook};
+use crate::room;
+use crate::signaling::SignalingMessage;
+
+/// Largest module PUT /api/admin/plugins/{name} accepts
+pub const MAX_MODULE_BYTES: u64 = 16 * 1024 * 1024;
+
+const HOOK_EXPORTS: [&str; 3] = ["on_join", "on_message", "on_leave"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WasmPluginsConfig {
+    /// Where uploaded modules are kept as {name}.wasm
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    /// Fuel (roughly, wasm instructions) one hook call may use before it is aborted
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    /// Linear memory one instance may grow to
+    #[serde(default = "default_max_memory_mb")]
+    pub max_memory_mb: u32,
+    /// Reject the message when a plugin fails (traps, runs out of fuel, answers garbage)
+    /// instead of letting it through
+    #[serde(default)]
+    pub fail_closed: bool,
+}
+
+fn default_directory() -> String { "data/plugins".to_string() }
+fn default_fuel() -> u64 { 10_000_000 }
+fn default_max_memory_mb() -> u32 { 16 }
+
+impl WasmPluginsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.directory.is_empty() {
+            return Err("directory must not be empty".to_string());
+        }
+        if self.fuel == 0 {
+            return Err("fuel must be greater than 0".to_string());
+        }
+        if self.max_memory_mb == 0 || self.max_memory_mb > 4096 {
+            return Err("max_memory_mb must be between 1 and 4096".to_string());
+        }
+        if !cfg!(feature = "wasm-plugins") {
+            return Err("this build does not include the wasm-plugins feature".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One installed plugin in GET /api/admin/plugins
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    /// The hook functions the module exports
+    pub hooks: Vec<String>,
+    pub size_bytes: usize,
+    pub installed_at: chrono::DateTime<chrono::Utc>,
+}
+
+struct Plugin {
+    info: PluginInfo,
+    module: runtime::Module,
+}
+
+/// What a plugin answers
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Answer {
+    Continue {
+        #[serde(default)]
+        message: Option<SignalingMessage>,
+    },
+    Reject {
+        reason: String,
+    },
+}
+
+/// The installed plugins, run as one SignalingHook
+pub struct WasmPlugins {
+    config: WasmPluginsConfig,
+    runtime: runtime::Runtime,
+    plugins: RwLock<BTreeMap<String, Plugin>>,
+}
+
+impl WasmPlugins {
+    /// Sets up the engine and loads every module in the plugin directory; modules that fail to
+    /// compile are skipped
+    pub fn start(config: WasmPluginsConfig) -> Result<Self, String> {
+        let runtime = runtime::Runtime::new(&config)?;
+        let plugins = Self { config, runtime, plugins: RwLock::new(BTreeMap::new()) };
+        let entries = match std::fs::read_dir(&plugins.config.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(plugins),
+            Err(e) => return Err(format!("failed to read {}: {}", plugins.config.directory, e)),
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()).filter(|_| path.extension().is_some_and(|ext| ext == "wasm")) else {
+                continue;
+            };
+            let loaded = std::fs::read(&path).map_err(|e| e.to_string()).and_then(|bytes| plugins.compile(name, &bytes));
+            match loaded {
+                Ok(plugin) => {
+                    info!("Loaded WASM plugin {} ({})", name, plugin.info.hooks.join(", "));
+                    plugins.plugins.write().unwrap().insert(name.to_string(), plugin);
+                }
+                Err(e) => error!("Failed to load WASM plugin {}: {}", path.display(), e),
+            }
+        }
+        Ok(plugins)
+    }
+
+    fn compile(&self, name: &str, bytes: &[u8]) -> Result<Plugin, String> {
+        let module = self.runtime.compile(bytes)?;
+        let hooks: Vec<String> = HOOK_EXPORTS.iter()
+            .filter(|export| module.exports().iter().any(|name| name == *export))
+            .map(|export| export.to_string())
+            .collect();
+        if hooks.is_empty() {
+            return Err(format!("the module exports none of {}", HOOK_EXPORTS.join(", ")));
+        }
+        let info = PluginInfo { name: name.to_string(), hooks, size_bytes: bytes.len(), installed_at: chrono::Utc::now() };
+        Ok(Plugin { info, module })
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        self.plugins.read().unwrap().values().map(|plugin| plugin.info.clone()).collect()
+    }
+
+    /// Compiles and stores a module, replacing the plugin of the same name. Blocks while
+    /// compiling.
+    pub fn install(&self, name: &str, bytes: &[u8]) -> Result<PluginInfo, String> {
+        if !room::is_valid_room_id(name) {
+            return Err("plugin names may only contain letters, digits, '-' and '_'".to_string());
+        }
+        let plugin = self.compile(name, bytes)?;
+        std::fs::create_dir_all(&self.config.directory)
+            .and_then(|_| std::fs::write(self.path(name), bytes))
+            .map_err(|e| format!("failed to store the module: {}", e))?;
+        let info = plugin.info.clone();
+        self.plugins.write().unwrap().insert(name.to_string(), plugin);
+        Ok(info)
+    }
+
+    /// Unloads a plugin and deletes its module; false when there was none
+    pub fn remove(&self, name: &str) -> bool {
+        if self.plugins.write().unwrap().remove(name).is_none() {
+            return false;
+        }
+        if let Err(e) = std::fs::remove_file(self.path(name)) {
+            warn!("Failed to delete WASM plugin module {}: {}", name, e);
+        }
+        true
+    }
+
+    fn path(&self, name: &str) -> std::path::PathBuf {
+        std::path::Path::new(&self.config.directory).join(format!("{}.wasm", name))
+    }
+
+    /// Calls `export` on every plugin that has it; None from a plugin means continue unchanged
+    fn run(&self, export: &str, context: &HookContext, mut message: Option<&mut SignalingMessage>) -> HookDecision {
+        let plugins = self.plugins.read().unwrap();
+        for plugin in plugins.values().filter(|plugin| plugin.info.hooks.iter().any(|hook| hook == export)) {
+            let input = serde_json::json!({
+                "hook": export,
+                "room_id": context.room_id,
+                "connection_id": context.connection_id,
+                "role": context.role,
+                "message": message.as_deref(),
+            });
+            let answer = self.runtime.call(&plugin.module, export, input.to_string().as_bytes())
+                .and_then(|output| match output {
+                    Some(output) => serde_json::from_slice::<Answer>(&output).map(Some).map_err(|e| format!("invalid answer: {}", e)),
+                    None => Ok(None),
+                });
+            match answer {
+                Ok(None) | Ok(Some(Answer::Continue { message: None })) => {}
+                Ok(Some(Answer::Continue { message: Some(replacement) })) => {
+                    if let Some(message) = message.as_deref_mut() {
+                        *message = replacement;
+                    }
+                }
+                Ok(Some(Answer::Reject { reason })) => {
+                    debug!("WASM plugin {} rejected a message in room {}: {}", plugin.info.name, context.room_id, reason);
+                    return HookDecision::Reject(reason);
+                }
+                Err(e) => {
+                    warn!("WASM plugin {} failed in {}: {}", plugin.info.name, export, e);
+                    if self.config.fail_closed && message.is_some() {
+                        return HookDecision::Reject(format!("Plugin {} failed", plugin.info.name));
+                    }
+                }
+            }
+        }
+        HookDecision::Continue
+    }
+}
+
+impl SignalingHook for WasmPlugins {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn on_join(&self, context: &HookContext, message: &mut SignalingMessage) -> HookDecision {
+        self.run("on_join", context, Some(message))
+    }
+
+    fn on_message(&self, context: &HookContext, message: &mut SignalingMessage) -> HookDecision {
+        self.run("on_message", context, Some(message))
+    }
+
+    fn on_leave(&self, context: &HookContext) {
+        self.run("on_leave", context, None);
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod runtime {
+    use wasmtime::{Engine, InstancePre, Linker, Store, StoreLimits, StoreLimitsBuilder};
+
+    use super::WasmPluginsConfig;
+
+    pub struct Runtime {
+        engine: Engine,
+        linker: Linker<StoreLimits>,
+        fuel: u64,
+        max_memory_bytes: usize,
+    }
+
+    pub struct Module {
+        pre: InstancePre<StoreLimits>,
+        exports: Vec<String>,
+    }
+
+    impl Module {
+        pub fn exports(&self) -> &[String] {
+            &self.exports
+        }
+    }
+
+    impl Runtime {
+        pub fn new(config: &WasmPluginsConfig) -> Result<Self, String> {
+            let mut engine_config = wasmtime::Config::new();
+            engine_config.consume_fuel(true);
+            let engine = Engine::new(&engine_config).map_err(|e| e.to_string())?;
+            // Nothing is linked in: modules that import anything fail to instantiate
+            let linker = Linker::new(&engine);
+            Ok(Self { engine, linker, fuel: config.fuel, max_memory_bytes: config.max_memory_mb as usize * 1024 * 1024 })
+        }
+
+        pub fn compile(&self, bytes: &[u8]) -> Result<Module, String> {
+            let module = wasmtime::Module::new(&self.engine, bytes).map_err(|e| e.to_string())?;
+            if module.imports().next().is_some() {
+                return Err("plugins may not import anything".to_string());
+            }
+            let exports = module.exports().map(|export| export.name().to_string()).collect();
+            let pre = self.linker.instantiate_pre(&module).map_err(|e| e.to_string())?;
+            Ok(Module { pre, exports })
+        }
+
+        pub fn call(&self, module: &Module, export: &str, input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+            let limits = StoreLimitsBuilder::new().memory_size(self.max_memory_bytes).instances(1).build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+
+            let instance = module.pre.instantiate(&mut store).map_err(|e| e.to_string())?;
+            let memory = instance.get_memory(&mut store, "memory").ok_or("the module exports no memory")?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(|e| e.to_string())?;
+            let hook = instance.get_typed_func::<(i32, i32), i64>(&mut store, export).map_err(|e| e.to_string())?;
+
+            let len = i32::try_from(input.len()).map_err(|_| "input too large")?;
+            let ptr = alloc.call(&mut store, len).map_err(|e| e.to_string())?;
+            memory.write(&mut store, ptr as u32 as usize, input).map_err(|e| e.to_string())?;
+            let packed = hook.call(&mut store, (ptr, len)).map_err(|e| e.to_string())?;
+            if packed == 0 {
+                return Ok(None);
+            }
+            let (out_ptr, out_len) = ((packed as u64 >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize);
+            let mut output = vec![0; out_len];
+            memory.read(&store, out_ptr, &mut output).map_err(|e| e.to_string())?;
+            Ok(Some(output))
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+mod runtime {
+    use super::WasmPluginsConfig;
+
+    const UNAVAILABLE: &str = "this build does not include the wasm-plugins feature";
+
+    pub struct Runtime;
+
+    pub struct Module;
+
+    impl Module {
+        pub fn exports(&self) -> &[String] {
+            &[]
+        }
+    }
+
+    impl Runtime {
+        pub fn new(_config: &WasmPluginsConfig) -> Result<Self, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+
+        pub fn compile(&self, _bytes: &[u8]) -> Result<Module, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+
+        pub fn call(&self, _module: &Module, _export: &str, _input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+            Err(UNAVAILABLE.to_string())
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// webhooks.rs
+// Outbound webhooks for room lifecycle events: a room was created, its first sender joined, its
+// last member left, or it was deleted; also zone events (zones.rs) and alerts raised by room
+// policy scripts (scripting.rs). Booking tools and home dashboards can react to a camera
+// session starting and ending without holding a signaling connection. Each endpoint has its own
+// queue and delivery task, so events reach it in order and a slow endpoint doesn't hold up the
+// others. With a backplane, an event is sent by the instance where it happened.
+
+use std::sync::Arc;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::event_bus::EventBus;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const ROOM_CREATED: &str = "room_created";
+pub const SENDER_JOINED: &str = "sender_joined";
+pub const ROOM_EMPTIED: &str = "room_emptied";
+pub const ROOM_DELETED: &str = "room_deleted";
+pub const ZONE_EVENT: &str = "zone_event";
+pub const ALERT: &str = "alert";
+
+const EVENTS: [&str; 6] = [ROOM_CREATED, SENDER_JOINED, ROOM_EMPTIED, ROOM_DELETED, ZONE_EVENT, ALERT];
+
+/// Events waiting for a slow or unreachable endpoint before new ones are dropped
+const QUEUE_CAPACITY: usize = 256;
+/// Attempts per event; a 5xx response or a connection error is retried
+const MAX_ATTEMPTS: u32 = 3;
+/// Pause before the first retry, doubled for each following one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Header carrying `sha256=<hex HMAC of the body>` when the endpoint has a secret
+pub const SIGNATURE_HEADER: &str = "X-Ws2infer-Signature";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events sent to this endpoint; all of them when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Signs each body with HMAC-SHA256 so the receiver can check it came from this server
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 { 5000 }
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err("url must start with http:// or https://".to_string());
+        }
+        if let Some(event) = self.events.iter().find(|event| !EVENTS.contains(&event.as_str())) {
+            return Err(format!("unknown event {} (expected one of {})", event, EVENTS.join(", ")));
+        }
+        if self.secret.as_deref() == Some("") {
+            return Err("secret must not be empty".to_string());
+        }
+        if self.timeout_ms == 0 {
+            return Err("timeout_ms must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|wanted| wanted == event)
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    config: WebhookConfig,
+    queue: mpsc::Sender<String>,
+}
+
+/// The endpoints of one server, and its event bus; clones share the delivery queues
+#[derive(Debug, Clone, Default)]
+pub struct Webhooks {
+    endpoints: Arc<Vec<Endpoint>>,
+    event_bus: Option<EventBus>,
+}
+
+impl Webhooks {
+    /// Starts one delivery task per endpoint
+    pub fn start(configs: &[WebhookConfig], event_bus: Option<EventBus>) -> Self {
+        let endpoints = configs.iter()
+            .map(|config| {
+                let (queue, events) = mpsc::channel(QUEUE_CAPACITY);
+                tokio::task::spawn(deliver(config.clone(), events));
+                info!("Sending room lifecycle webhooks to {}", config.url);
+                Endpoint { config: config.clone(), queue }
+            })
+            .collect();
+        Self { endpoints: Arc::new(endpoints), event_bus }
+    }
+
+    /// Queues an event for every endpoint that wants it; dropped with a warning when a queue is
+    /// full. The event also goes to the event bus when one is configured.
+    pub fn emit(&self, event: &str, room_id: &str, detail: Value) {
+        if let Some(event_bus) = &self.event_bus {
+            event_bus.publish_event(event, room_id, &detail);
+        }
+        if self.endpoints.is_empty() {
+            return;
+        }
+        let body = serde_json::json!({
+            "id": uuid::Uuid::new_v4().to_string(),
+            "event": event,
+            "room_id": room_id,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "data": detail,
+        })
+        .to_string();
+        for endpoint in self.endpoints.iter().filter(|endpoint| endpoint.config.wants(event)) {
+            if endpoint.queue.try_send(body.clone()).is_err() {
+                warn!("Webhook queue for {} is full; dropping {} for room {}", endpoint.config.url, event, room_id);
+            }
+        }
+    }
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the body
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+async fn deliver(config: WebhookConfig, mut events: mpsc::Receiver<String>) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_millis(config.timeout_ms)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Webhook client for {} could not be built: {}", config.url, e);
+            return;
+        }
+    };
+    while let Some(body) = events.recv().await {
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut request = client.post(&config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(secret) = &config.secret {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+            let retry = match request.send().await {
+                Ok(response) if response.status().is_server_error() => {
+                    warn!("Webhook {} answered {} (attempt {}/{})", config.url, response.status(), attempt, MAX_ATTEMPTS);
+                    true
+                }
+                Ok(response) => {
+                    if !response.status().is_success() {
+                        warn!("Webhook {} rejected an event with {}", config.url, response.status());
+                    }
+                    false
+                }
+                Err(e) => {
+                    warn!("Webhook {} failed: {} (attempt {}/{})", config.url, e, attempt, MAX_ATTEMPTS);
+                    true
+                }
+            };
+            if !retry || attempt == MAX_ATTEMPTS {
+                break;
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
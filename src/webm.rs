@@ -0,0 +1,176 @@
+// webm.rs
+// Minimal WebM (Matroska) muxer for a single VP8 video track, used by the recorder. The Segment
+// is written with an unknown size and without Cues, which browsers and ffmpeg play fine (seeking
+// aside); each Cluster is buffered in memory and written with its size once the next one starts.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Size marker for elements whose length isn't known when they are opened
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+/// Clusters are closed after this long even without a keyframe, well inside the i16 range of
+/// block timecodes
+const MAX_CLUSTER_MS: u64 = 5000;
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+/// EBML variable-length size: the shortest encoding whose all-ones value stays reserved
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    let length = (1..=8u32).find(|len| size < (1u64 << (7 * len)) - 1).unwrap_or(8);
+    let value = (1u64 << (7 * length)) | size;
+    out.extend_from_slice(&value.to_be_bytes()[8 - length as usize..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(out, id);
+    write_size(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+fn uint_element(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+/// Width and height from a VP8 keyframe header; None for interframes or truncated data
+pub fn vp8_keyframe_size(frame: &[u8]) -> Option<(u16, u16)> {
+    if frame.len() < 10 || frame[0] & 0x01 != 0 || frame[3..6] != [0x9D, 0x01, 0x2A] {
+        return None;
+    }
+    let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3FFF;
+    let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3FFF;
+    Some((width, height))
+}
+
+pub fn is_vp8_keyframe(frame: &[u8]) -> bool {
+    frame.first().is_some_and(|b| b & 0x01 == 0)
+}
+
+pub struct WebmWriter {
+    file: BufWriter<File>,
+    cluster: Vec<u8>,
+    cluster_start_ms: Option<u64>,
+    bytes_written: u64,
+}
+
+impl WebmWriter {
+    /// Creates the file and writes the header for one VP8 track of the given size
+    pub fn create(path: &Path, width: u16, height: u16) -> io::Result<Self> {
+        let mut header = Vec::new();
+
+        let mut ebml = Vec::new();
+        uint_element(&mut ebml, EBML_VERSION, 1);
+        uint_element(&mut ebml, EBML_READ_VERSION, 1);
+        uint_element(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut ebml, DOC_TYPE, b"webm");
+        uint_element(&mut ebml, DOC_TYPE_VERSION, 2);
+        uint_element(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut header, EBML, &ebml);
+
+        write_id(&mut header, SEGMENT);
+        header.extend_from_slice(&UNKNOWN_SIZE);
+
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMECODE_SCALE, 1_000_000);
+        element(&mut info, MUXING_APP, b"ws2infer");
+        element(&mut info, WRITING_APP, b"ws2infer");
+        element(&mut header, INFO, &info);
+
+        let mut video = Vec::new();
+        uint_element(&mut video, PIXEL_WIDTH, width as u64);
+        uint_element(&mut video, PIXEL_HEIGHT, height as u64);
+        let mut track = Vec::new();
+        uint_element(&mut track, TRACK_NUMBER, 1);
+        uint_element(&mut track, TRACK_UID, 1);
+        uint_element(&mut track, TRACK_TYPE, 1);
+        element(&mut track, CODEC_ID, b"V_VP8");
+        element(&mut track, VIDEO, &video);
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &track);
+        element(&mut header, TRACKS, &tracks);
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+        Ok(Self {
+            file,
+            cluster: Vec::new(),
+            cluster_start_ms: None,
+            bytes_written: header.len() as u64,
+        })
+    }
+
+    /// Appends one frame; timestamps are milliseconds from the start of this file and must not
+    /// go backwards
+    pub fn write_frame(&mut self, timestamp_ms: u64, keyframe: bool, frame: &[u8]) -> io::Result<()> {
+        let start = match self.cluster_start_ms {
+            Some(start) if !keyframe && timestamp_ms.saturating_sub(start) < MAX_CLUSTER_MS => start,
+            _ => {
+                self.flush_cluster()?;
+                self.cluster_start_ms = Some(timestamp_ms);
+                uint_element(&mut self.cluster, TIMECODE, timestamp_ms);
+                timestamp_ms
+            }
+        };
+
+        let relative = timestamp_ms.saturating_sub(start) as i16;
+        let mut block = Vec::with_capacity(frame.len() + 4);
+        block.push(0x81); // track number 1 as a vint
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(frame);
+        element(&mut self.cluster, SIMPLE_BLOCK, &block);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if self.cluster.is_empty() {
+            return Ok(());
+        }
+        let mut out = Vec::with_capacity(self.cluster.len() + 12);
+        element(&mut out, CLUSTER, &self.cluster);
+        self.file.write_all(&out)?;
+        self.bytes_written += out.len() as u64;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    /// Writes the last cluster and returns the file size
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.flush_cluster()?;
+        self.file.flush()?;
+        Ok(self.bytes_written)
+    }
+}
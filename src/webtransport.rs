@@ -0,0 +1,251 @@
+// webtransport.rs
+// Experimental WebTransport (HTTP/3 over QUIC) signaling listener, behind the `webtransport`
+// cargo feature. Browsers that support it open a session at https://host:port/wt/{room_id} and
+// then one bidirectional stream, which carries the WebSocket protocol's frames: each frame is a
+// 4-byte big-endian length followed by what a WebSocket message would hold (JSON, a compressed
+// JSON frame or a JPEG upload). Frames are fed to the same Session (session.rs) as a socket's,
+// so the room can't tell the transports apart. QUIC streams don't block one another on packet
+// loss, and the handshake shares the connection's round trips, so signaling reaches the room
+// sooner on lossy mobile links. The certificate is the HTTPS one.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::Clients;
+use crate::cluster::ClusterConfig;
+use crate::room::RoomManager;
+use crate::server_inference::ServerInference;
+use crate::state::SharedState;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebTransportConfig {
+    /// UDP address of the HTTP/3 listener, e.g. "0.0.0.0:4433"
+    pub addr: String,
+}
+
+impl WebTransportConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.addr.parse::<SocketAddr>()
+            .map(|_| ())
+            .map_err(|e| format!("addr '{}' is not a socket address: {}", self.addr, e))
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.addr.parse::<SocketAddr>().ok().map(|addr| addr.port())
+    }
+}
+
+/// What sessions need from the rest of the server
+#[derive(Clone)]
+#[cfg_attr(not(feature = "webtransport"), allow(dead_code))]
+pub struct Context {
+    pub state: SharedState,
+    pub room_manager: Arc<RwLock<RoomManager>>,
+    pub clients: Clients,
+    pub server_inference: Option<Arc<ServerInference>>,
+    pub cluster: Option<Arc<ClusterConfig>>,
+}
+
+#[cfg(feature = "webtransport")]
+pub use listener::run;
+
+#[cfg(feature = "webtransport")]
+mod listener {
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+    use bytes::Bytes;
+    use h3::ext::Protocol;
+    use h3::quic::BidiStream;
+    use h3_webtransport::server::{AcceptedBi, WebTransportSession};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio_rustls::rustls;
+    use tracing::{debug, error, info, warn};
+    use warp::ws::Message;
+
+    use super::{Context, WebTransportConfig};
+    use crate::session::Session;
+    use crate::signaling::SignalingMessage;
+    use crate::transport;
+
+    /// Largest frame accepted from a client, matching the decompressed WebSocket frame limit
+    const MAX_FRAME_BYTES: usize = 4 * 1024 * 1024;
+    /// Path prefix of session URLs; the room ID follows it
+    const PATH_PREFIX: &str = "/wt/";
+
+    type Connection = h3::server::Connection<h3_quinn::Connection, Bytes>;
+
+    /// Room ID of a session path (`/wt/{room_id}`)
+    fn room_id(path: &str) -> Option<&str> {
+        path.strip_prefix(PATH_PREFIX).filter(|id| !id.is_empty() && !id.contains('/'))
+    }
+
+    /// Accepts QUIC connections until the endpoint fails
+    pub async fn run(config: WebTransportConfig, cert_path: String, key_path: String, context: Context) -> anyhow::Result<()> {
+        let addr: SocketAddr = config.addr.parse()?;
+        let (certs, key) = transport::load_certificate(&cert_path, &key_path)?;
+        let mut tls = rustls::ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+
+        let endpoint = quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(tls)), addr)?;
+        info!("WebTransport listening on https://{}{}{{room_id}}", addr, PATH_PREFIX);
+
+        while let Some(connecting) = endpoint.accept().await {
+            let client_ip = connecting.remote_address().ip();
+            if !context.state.ip_filter.permits(client_ip) {
+                debug!("Refused WebTransport connection from {}", client_ip);
+                // Dropping the handshake closes the connection
+                drop(connecting);
+                continue;
+            }
+            let context = context.clone();
+            tokio::task::spawn(async move {
+                let result = match connecting.await {
+                    Ok(connection) => serve_connection(connection, client_ip, context).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    debug!("WebTransport connection from {} ended: {}", client_ip, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Waits for the CONNECT request that opens a WebTransport session; anything else gets 404
+    async fn serve_connection(connection: quinn::Connection, client_ip: IpAddr, context: Context) -> anyhow::Result<()> {
+        let mut h3_connection: Connection = h3::server::builder()
+            .enable_webtransport(true)
+            .enable_connect(true)
+            .max_webtransport_sessions(1)
+            .send_grease(true)
+            .build(h3_quinn::Connection::new(connection))
+            .await?;
+
+        while let Some((request, mut stream)) = h3_connection.accept().await? {
+            let is_webtransport = request.method() == http::Method::CONNECT
+                && request.extensions().get::<Protocol>() == Some(&Protocol::WEB_TRANSPORT);
+            match room_id(request.uri().path()).filter(|_| is_webtransport).map(str::to_string) {
+                Some(room_id) => {
+                    let user_agent = request.headers().get(http::header::USER_AGENT)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                    let session = WebTransportSession::accept(request, stream, h3_connection).await?;
+                    return serve_session(session, room_id, client_ip, user_agent, context).await;
+                }
+                None => {
+                    stream.send_response(http::Response::builder().status(http::StatusCode::NOT_FOUND).body(())?).await?;
+                    stream.finish().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "webtransport", skip_all, fields(room_id = %room_id, client_ip = ?client_ip, connection_id = tracing::field::Empty))]
+    async fn serve_session(
+        session: WebTransportSession<h3_quinn::Connection, Bytes>,
+        room_id: String,
+        client_ip: IpAddr,
+        user_agent: Option<String>,
+        context: Context,
+    ) -> anyhow::Result<()> {
+        let stream = match session.accept_bi().await? {
+            Some(AcceptedBi::BidiStream(_, stream)) => stream,
+            _ => return Ok(()),
+        };
+        let (send, recv) = stream.split();
+        tokio::pin!(send);
+        tokio::pin!(recv);
+
+        // In cluster mode, rooms owned by another instance are redirected there
+        if let Some(cluster) = context.cluster.as_ref().filter(|cluster| !cluster.is_local(&room_id)) {
+            info!("Redirecting WebTransport session to {}", cluster.room_url(&room_id));
+            let redirect = serde_json::to_vec(&SignalingMessage::new_redirect(cluster.room_url(&room_id)))?;
+            write_frame(&mut send, &redirect).await?;
+            send.shutdown().await?;
+            return Ok(());
+        }
+
+        info!("New WebTransport session for room: {}", room_id);
+        let mut signaling = Session::new(context.state, room_id, context.room_manager, context.clients, context.server_inference, Some(client_ip))
+            .with_user_agent(user_agent);
+        let client = signaling.client().clone();
+
+        // Queued messages go out in order on the stream; like a WebSocket send, a write that
+        // stalls for SEND_STALL_TIMEOUT ends the session
+        let forward = async {
+            while let Some(message) = client.recv().await {
+                if message.is_close() {
+                    break;
+                }
+                match tokio::time::timeout(crate::SEND_STALL_TIMEOUT, write_frame(&mut send, message.as_bytes())).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!("WebTransport send error: {}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        error!("WebTransport send stalled for {:?}; closing session", crate::SEND_STALL_TIMEOUT);
+                        break;
+                    }
+                }
+            }
+            client.shutdown();
+            let _ = send.shutdown().await;
+        };
+        let receive = async {
+            loop {
+                match read_frame(&mut recv).await {
+                    Ok(Some(frame)) => signaling.receive(&Message::binary(frame)).await,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("WebTransport receive error: {}", e);
+                        break;
+                    }
+                }
+            }
+        };
+        // Either direction ending ends the session, as with a WebSocket
+        tokio::select! {
+            _ = forward => {}
+            _ = receive => {}
+        }
+        signaling.end("WebTransport").await;
+        Ok(())
+    }
+
+    async fn write_frame(send: &mut (impl AsyncWrite + Unpin), payload: &[u8]) -> std::io::Result<()> {
+        send.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+        send.write_all(payload).await?;
+        send.flush().await
+    }
+
+    /// Next length-prefixed frame; None when the client finished the stream
+    async fn read_frame(recv: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Option<Vec<u8>>> {
+        let mut length = [0u8; 4];
+        match recv.read_exact(&mut length).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_FRAME_BYTES {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("frame of {} bytes exceeds {}", length, MAX_FRAME_BYTES)));
+        }
+        let mut frame = vec![0u8; length];
+        recv.read_exact(&mut frame).await?;
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(not(feature = "webtransport"))]
+pub async fn run(_config: WebTransportConfig, _cert_path: String, _key_path: String, _context: Context) -> anyhow::Result<()> {
+    anyhow::bail!("this build does not include the webtransport feature")
+}
@@ -0,0 +1,211 @@
+// zones.rs
+// Polygonal zones (regions of interest) per room, set with PUT /api/rooms/{id}/zones or a
+// SetZones message from an admin or controller. Every inference result is checked against
+// the room's zones: a detection is in a zone when the bottom centre of its bbox (where a person
+// stands) lies inside the polygon. A zone is occupied while at least one matching detection of a
+// source is inside it; `entered` and `left` are emitted when that changes and `dwell` once an
+// occupancy has lasted dwell_secs, all by capture time. Events go to the room as ZoneEvent
+// messages and to webhooks as `zone_event`.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::inference::InferencePayload;
+use crate::room;
+
+/// Zones one room may have at most
+const MAX_ZONES: usize = 32;
+/// Polygon corners one zone may have at most
+const MAX_POINTS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    /// Letters, digits, '-' and '_'; unique within the room
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Corners in order, [x, y] in pixels of the analysed frame like bboxes
+    pub polygon: Vec<[f64; 2]>,
+    /// Corners are fractions (0-1) of the frame instead; results without frame_size are skipped
+    #[serde(default)]
+    pub normalized: bool,
+    /// Classes counted in the zone; any when empty
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_score: Option<f64>,
+    /// A `dwell` event follows `entered` once the zone stays occupied this long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dwell_secs: Option<u64>,
+}
+
+impl Zone {
+    pub fn validate(&self) -> Result<(), String> {
+        if !room::is_valid_room_id(&self.id) {
+            return Err(format!("zone id '{}' may only contain letters, digits, '-' and '_'", self.id));
+        }
+        if self.polygon.len() < 3 || self.polygon.len() > MAX_POINTS {
+            return Err(format!("zone {} needs 3 to {} polygon points", self.id, MAX_POINTS));
+        }
+        if self.polygon.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(format!("zone {} has a non-finite polygon point", self.id));
+        }
+        if self.normalized && self.polygon.iter().flatten().any(|v| !(0.0..=1.0).contains(v)) {
+            return Err(format!("zone {} is normalized, so its points must be between 0 and 1", self.id));
+        }
+        if matches!(self.min_score, Some(score) if !(0.0..=1.0).contains(&score)) {
+            return Err(format!("zone {} min_score must be between 0 and 1", self.id));
+        }
+        if self.dwell_secs == Some(0) {
+            return Err(format!("zone {} dwell_secs must be greater than 0", self.id));
+        }
+        Ok(())
+    }
+
+    /// Bottom centre of `bbox`, in the zone's coordinates
+    fn anchor(&self, bbox: [f64; 4], frame_size: Option<[u32; 2]>) -> Option<[f64; 2]> {
+        let [x, y, width, height] = bbox;
+        let point = [x + width / 2.0, y + height];
+        match (self.normalized, frame_size) {
+            (false, _) => Some(point),
+            (true, Some([w, h])) => Some([point[0] / w as f64, point[1] / h as f64]),
+            (true, None) => None,
+        }
+    }
+
+    /// Even-odd rule
+    fn contains(&self, [px, py]: [f64; 2]) -> bool {
+        let mut inside = false;
+        let mut j = self.polygon.len() - 1;
+        for i in 0..self.polygon.len() {
+            let ([xi, yi], [xj, yj]) = (self.polygon[i], self.polygon[j]);
+            if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+/// Checks a SetZones or PUT body: the list and each zone
+pub fn validate_all(zones: &[Zone]) -> Result<(), String> {
+    if zones.len() > MAX_ZONES {
+        return Err(format!("at most {} zones", MAX_ZONES));
+    }
+    for (i, zone) in zones.iter().enumerate() {
+        zone.validate()?;
+        if zones[..i].iter().any(|other| other.id == zone.id) {
+            return Err(format!("duplicate zone id {}", zone.id));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneEventKind {
+    Entered,
+    Left,
+    Dwell,
+}
+
+/// `data` of a ZoneEvent message and of a `zone_event` webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneEvent {
+    pub zone_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub event: ZoneEventKind,
+    pub source_sender_id: String,
+    /// Matching detections in the zone; 0 for `left`
+    pub count: usize,
+    /// Their classes
+    pub classes: Vec<String>,
+    /// Capture time of the result that triggered the event (unix ms)
+    pub timestamp: i64,
+    /// How long the zone has been occupied, for `dwell` and `left`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupied_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+struct Occupancy {
+    since_ms: i64,
+    dwell_sent: bool,
+}
+
+/// A room's zones and which of them each source currently occupies
+#[derive(Debug, Clone, Default)]
+pub struct ZoneSet {
+    zones: Vec<Zone>,
+    /// (zone_id, source_id) -> occupancy
+    occupancy: HashMap<(String, String), Occupancy>,
+}
+
+impl ZoneSet {
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// Replaces the zones; every zone starts out unoccupied
+    pub fn set(&mut self, zones: Vec<Zone>) {
+        self.zones = zones;
+        self.occupancy.clear();
+    }
+
+    /// Events caused by a result of `source_id`, after calibration
+    pub fn evaluate(&mut self, source_id: &str, payload: &Value) -> Vec<ZoneEvent> {
+        if self.zones.is_empty() {
+            return Vec::new();
+        }
+        let Ok(payload) = serde_json::from_value::<InferencePayload>(payload.clone()) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+        for zone in &self.zones {
+            if zone.normalized && payload.frame_size.is_none() {
+                continue;
+            }
+            let classes: Vec<String> = payload.predictions.iter()
+                .filter(|detection| zone.classes.is_empty() || zone.classes.contains(&detection.class))
+                .filter(|detection| !matches!(zone.min_score, Some(min) if detection.score < min))
+                .filter(|detection| zone.anchor(detection.bbox, payload.frame_size).is_some_and(|point| zone.contains(point)))
+                .map(|detection| detection.class.clone())
+                .collect();
+            let event = |kind, occupied_ms: Option<i64>| ZoneEvent {
+                zone_id: zone.id.clone(),
+                name: zone.name.clone(),
+                event: kind,
+                source_sender_id: source_id.to_string(),
+                count: classes.len(),
+                classes: classes.clone(),
+                timestamp: payload.timestamp,
+                occupied_secs: occupied_ms.map(|ms| ms as f64 / 1000.0),
+            };
+            let key = (zone.id.clone(), source_id.to_string());
+            match (classes.is_empty(), self.occupancy.get_mut(&key)) {
+                (false, None) => {
+                    self.occupancy.insert(key, Occupancy { since_ms: payload.timestamp, dwell_sent: false });
+                    events.push(event(ZoneEventKind::Entered, None));
+                }
+                (false, Some(occupancy)) => {
+                    let occupied_ms = payload.timestamp - occupancy.since_ms;
+                    if let (Some(dwell), false) = (zone.dwell_secs, occupancy.dwell_sent) {
+                        if occupied_ms >= dwell as i64 * 1000 {
+                            occupancy.dwell_sent = true;
+                            events.push(event(ZoneEventKind::Dwell, Some(occupied_ms)));
+                        }
+                    }
+                }
+                (true, Some(_)) => {
+                    let occupancy = self.occupancy.remove(&key);
+                    events.push(event(ZoneEventKind::Left, occupancy.map(|o| payload.timestamp - o.since_ms)));
+                }
+                (true, None) => {}
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,110 @@
+// Anomaly detection is compiled into this test directly; it doesn't depend on the rest of the
+// server.
+#[path = "../src/anomaly.rs"]
+#[allow(dead_code)]
+mod anomaly;
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    use super::anomaly::{AnomalyConfig, AnomalyDetector, Baseline};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn people(count: usize) -> Value {
+        json!({"predictions": vec![json!({"class": "person"}); count]})
+    }
+
+    fn config() -> AnomalyConfig {
+        AnomalyConfig {
+            factor: 3.0,
+            min_expected: 0.5,
+            window_secs: 300,
+            min_window_results: 3,
+            history_days: 14,
+            min_history_samples: 4,
+            relearn_secs: 3600,
+        }
+    }
+
+    /// Four stored results at `hour` averaging two people each
+    fn baseline(hour: u32) -> Baseline {
+        let ts = Utc.with_ymd_and_hms(2026, 2, 1, hour, 0, 0).unwrap();
+        let history: Vec<(String, DateTime<Utc>, Value)> = [1, 3, 2, 2].iter()
+            .map(|count| ("lobby".to_string(), ts, people(*count)))
+            .collect();
+        Baseline::learn(&history)
+    }
+
+    fn observe(detector: &mut AnomalyDetector, count: usize, results: usize, start: DateTime<Utc>) -> Vec<Value> {
+        let mut events = Vec::new();
+        for i in 0..results {
+            events.extend(detector.observe("lobby", &people(count), start + Duration::seconds(i as i64)));
+        }
+        events
+    }
+
+    #[test]
+    fn test_spike_and_drop_against_baseline() {
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(12));
+        let events = observe(&mut detector, 7, 3, noon);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["kind"], "spike");
+        assert_eq!(events[0]["class"], "person");
+        assert!(close(events[0]["expected"].as_f64().unwrap(), 2.0));
+        assert!(close(events[0]["observed"].as_f64().unwrap(), 7.0));
+
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(12));
+        let events = observe(&mut detector, 0, 3, noon);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["kind"], "drop");
+    }
+
+    #[test]
+    fn test_within_factor_is_quiet() {
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(12));
+        assert!(observe(&mut detector, 5, 3, noon).is_empty());
+        assert!(observe(&mut detector, 1, 20, noon + Duration::seconds(400)).is_empty());
+    }
+
+    #[test]
+    fn test_needs_window_and_history() {
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(12));
+        assert!(observe(&mut detector, 7, 2, noon).is_empty(), "too few results in the window");
+
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(11));
+        assert!(observe(&mut detector, 7, 3, noon).is_empty(), "no history for this hour");
+    }
+
+    #[test]
+    fn test_fires_once_per_window() {
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let mut detector = AnomalyDetector::new(config());
+        detector.set_baseline(baseline(12));
+
+        assert_eq!(observe(&mut detector, 7, 3, noon).len(), 1);
+        assert!(observe(&mut detector, 7, 1, noon + Duration::seconds(10)).is_empty());
+        assert_eq!(observe(&mut detector, 7, 3, noon + Duration::seconds(303)).len(), 1);
+    }
+
+    #[test]
+    fn test_anomaly_config_validation() {
+        assert!(config().validate().is_ok());
+        assert!(AnomalyConfig { factor: 1.0, ..config() }.validate().is_err());
+        assert!(AnomalyConfig { window_secs: 0, ..config() }.validate().is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Score calibration is compiled into this test directly; it doesn't depend on the rest of the
+// server.
+#[path = "../src/calibration.rs"]
+#[allow(dead_code)]
+mod calibration;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde_json::json;
+
+    use super::calibration::{self, CalibrationCurve};
+
+    fn curve() -> CalibrationCurve {
+        CalibrationCurve(vec![(0.2, 0.0), (0.6, 0.5), (1.0, 1.0)])
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_curve_interpolates_and_clamps() {
+        let curve = curve();
+        assert!(close(curve.apply(0.0), 0.0));
+        assert!(close(curve.apply(0.2), 0.0));
+        assert!(close(curve.apply(0.4), 0.25));
+        assert!(close(curve.apply(0.6), 0.5));
+        assert!(close(curve.apply(0.8), 0.75));
+        assert!(close(curve.apply(1.0), 1.0));
+        assert!(close(CalibrationCurve(Vec::new()).apply(0.3), 0.3));
+    }
+
+    #[test]
+    fn test_curve_validation() {
+        assert!(curve().validate().is_ok());
+        assert!(CalibrationCurve(vec![(0.5, 0.5)]).validate().is_err());
+        assert!(CalibrationCurve(vec![(0.0, 0.0), (1.5, 1.0)]).validate().is_err());
+        assert!(CalibrationCurve(vec![(0.5, 0.0), (0.5, 1.0)]).validate().is_err());
+    }
+
+    #[test]
+    fn test_room_curve_overrides_global() {
+        let global = HashMap::from([("default".to_string(), curve()), ("lite".to_string(), curve())]);
+        let room = HashMap::from([("lite".to_string(), CalibrationCurve(vec![(0.0, 1.0), (1.0, 1.0)]))]);
+
+        let lite = calibration::curve_for(&json!({"model": "lite"}), &room, &global).unwrap();
+        assert!(close(lite.apply(0.0), 1.0));
+        assert_eq!(calibration::curve_for(&json!({}), &room, &global), Some(&curve()));
+        assert_eq!(calibration::curve_for(&json!({"model": "other"}), &room, &global), None);
+    }
+
+    #[test]
+    fn test_payload_scores_rewritten() {
+        let mut payload = json!({
+            "predictions": [{"class": "person", "score": 0.4}, {"class": "cat"}],
+            "detections": [{"score": 0.8}],
+        });
+        calibration::apply_to_payload(&mut payload, &curve());
+        assert!(close(payload["predictions"][0]["score"].as_f64().unwrap(), 0.25));
+        assert_eq!(payload["predictions"][1].get("score"), None);
+        assert!(close(payload["detections"][0]["score"].as_f64().unwrap(), 0.75));
+    }
+}
@@ -0,0 +1,112 @@
+// Splitting oversized signaling messages into chunks and joining them again.
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    use ws2infer_protocol::chunk::{self, Reassembler, MIN_MESSAGE_BYTES};
+    use ws2infer_protocol::{SignalingMessage, SignalingMessageType};
+
+    fn chunk(id: &str, index: u64, part: &str) -> SignalingMessage {
+        piece(SignalingMessageType::Chunk, json!({"id": id, "index": index, "part": part}))
+    }
+
+    fn chunk_end(id: &str, count: u64) -> SignalingMessage {
+        piece(SignalingMessageType::ChunkEnd, json!({"id": id, "count": count}))
+    }
+
+    fn piece(message_type: SignalingMessageType, data: serde_json::Value) -> SignalingMessage {
+        SignalingMessage {
+            message_type,
+            connection_id: None,
+            source_sender_id: None,
+            sender_id: None,
+            offer_id: None,
+            data: Some(data),
+            is_sender: None,
+            seq: None,
+            msg_id: None,
+        }
+    }
+
+    #[test]
+    fn test_split_respects_limit() {
+        let json = json!({"type": "offer", "data": {"sdp": "a=candidate \"x\"\n".repeat(500)}}).to_string();
+        let messages = chunk::split(&json, 2048, "c1", Some("peer".to_string()));
+
+        assert!(messages.len() > 2);
+        assert!(matches!(messages.last().unwrap().message_type, SignalingMessageType::ChunkEnd));
+        for message in &messages {
+            assert!(serde_json::to_string(message).unwrap().len() <= 2048);
+            assert_eq!(message.connection_id.as_deref(), Some("peer"));
+        }
+    }
+
+    #[test]
+    fn test_split_below_minimum_uses_minimum() {
+        let json = "x".repeat(4 * MIN_MESSAGE_BYTES);
+        let tiny = chunk::split(&json, 10, "c1", None);
+        let minimum = chunk::split(&json, MIN_MESSAGE_BYTES, "c1", None);
+        assert_eq!(tiny.len(), minimum.len());
+    }
+
+    #[test]
+    fn test_out_of_order_chunk_discards_message() {
+        let mut reassembler = Reassembler::new(1024);
+        assert_eq!(reassembler.push(&chunk("c1", 0, "ab")), Ok(None));
+        assert!(reassembler.push(&chunk("c1", 2, "cd")).is_err());
+        assert!(reassembler.push(&chunk_end("c1", 2)).is_err());
+    }
+
+    #[test]
+    fn test_count_mismatch_refused() {
+        let mut reassembler = Reassembler::new(1024);
+        reassembler.push(&chunk("c1", 0, "ab")).unwrap();
+        assert!(reassembler.push(&chunk_end("c1", 2)).is_err());
+    }
+
+    #[test]
+    fn test_oversized_message_refused() {
+        let mut reassembler = Reassembler::new(4);
+        reassembler.push(&chunk("c1", 0, "abc")).unwrap();
+        assert!(reassembler.push(&chunk("c1", 1, "de")).is_err());
+        assert!(reassembler.push(&chunk_end("c1", 2)).is_err());
+    }
+
+    #[test]
+    fn test_pending_messages_limited() {
+        let mut reassembler = Reassembler::new(1024);
+        for id in ["a", "b", "c", "d"] {
+            reassembler.push(&chunk(id, 0, "x")).unwrap();
+        }
+        assert!(reassembler.push(&chunk("e", 0, "x")).is_err());
+        // Messages already in flight may continue, and finishing one frees a slot
+        reassembler.push(&chunk("a", 1, "y")).unwrap();
+        assert_eq!(reassembler.push(&chunk_end("a", 2)), Ok(Some("xy".to_string())));
+        assert_eq!(reassembler.push(&chunk("e", 0, "x")), Ok(None));
+    }
+
+    #[test]
+    fn test_interleaved_messages() {
+        let mut reassembler = Reassembler::new(1024);
+        reassembler.push(&chunk("a", 0, "1")).unwrap();
+        reassembler.push(&chunk("b", 0, "x")).unwrap();
+        reassembler.push(&chunk("a", 1, "2")).unwrap();
+        assert_eq!(reassembler.push(&chunk_end("b", 1)), Ok(Some("x".to_string())));
+        assert_eq!(reassembler.push(&chunk_end("a", 2)), Ok(Some("12".to_string())));
+    }
+
+    proptest! {
+        #[test]
+        fn split_then_reassemble_roundtrips(text in "\\PC{0,4000}", limit in 0usize..4096) {
+            let json = json!({"type": "offer", "data": {"sdp": text}}).to_string();
+            let mut reassembler = Reassembler::new(json.len());
+            let mut joined = None;
+            for message in chunk::split(&json, limit, "c1", None) {
+                joined = reassembler.push(&message).unwrap();
+            }
+            prop_assert_eq!(joined, Some(json));
+        }
+    }
+}
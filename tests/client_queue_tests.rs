@@ -0,0 +1,85 @@
+// The outgoing queue is compiled into this test directly; it only depends on warp and the
+// protocol crate.
+#[path = "../src/client_queue.rs"]
+#[allow(dead_code)]
+mod client_queue;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use warp::ws::Message;
+
+    use super::client_queue::{ClientHandle, CLIENT_QUEUE_CAPACITY};
+
+    fn drain(handle: &ClientHandle) -> Vec<String> {
+        std::iter::from_fn(|| handle.try_recv())
+            .map(|message| message.to_str().unwrap_or("<close>").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_full_queue_drops_oldest_droppable() {
+        let handle = ClientHandle::new();
+        assert!(handle.send_chunks(vec![Message::text("offer")]));
+        for i in 0..CLIENT_QUEUE_CAPACITY - 1 {
+            assert!(handle.send_droppable(Message::text(format!("update {}", i))));
+        }
+        assert_eq!(handle.saturated_for(Instant::now()), None);
+
+        assert!(handle.send_chunks(vec![Message::text("answer")]));
+        assert_eq!(handle.depth(), CLIENT_QUEUE_CAPACITY);
+        assert_eq!(handle.dropped(), 1);
+        assert!(handle.saturated_for(Instant::now()).is_some());
+
+        let messages = drain(&handle);
+        assert_eq!(messages[0], "offer");
+        assert_eq!(messages[1], "update 1");
+        assert_eq!(messages.last().map(String::as_str), Some("answer"));
+        assert_eq!(handle.saturated_for(Instant::now()), None);
+    }
+
+    #[test]
+    fn test_full_queue_of_undroppable_refuses() {
+        let handle = ClientHandle::new();
+        for i in 0..CLIENT_QUEUE_CAPACITY {
+            assert!(handle.send_chunks(vec![Message::text(format!("offer {}", i))]));
+        }
+        assert!(!handle.send_chunks(vec![Message::text("late")]));
+        assert!(!handle.send_droppable(Message::text("update")));
+        assert_eq!(handle.depth(), CLIENT_QUEUE_CAPACITY);
+        assert_eq!(drain(&handle)[0], "offer 0");
+    }
+
+    #[test]
+    fn test_sequence_numbers_skip_discarded_messages() {
+        let handle = ClientHandle::new();
+        assert!(handle.send_sequenced(|seq| vec![Message::text(seq.to_string())]));
+        for _ in 1..CLIENT_QUEUE_CAPACITY {
+            handle.send_chunks(vec![Message::text("offer")]);
+        }
+        assert!(!handle.send_sequenced(|seq| vec![Message::text(seq.to_string())]));
+        drain(&handle);
+        assert!(handle.send_sequenced(|seq| vec![Message::text(seq.to_string())]));
+        assert_eq!(drain(&handle), vec!["3"]);
+    }
+
+    #[test]
+    fn test_close_replaces_queue_and_refuses_more() {
+        let handle = ClientHandle::new();
+        handle.send_chunks(vec![Message::text("offer")]);
+        handle.close();
+        assert!(!handle.send_chunks(vec![Message::text("answer")]));
+        assert_eq!(drain(&handle), vec!["<close>"]);
+    }
+
+    #[test]
+    fn test_duplicate_msg_ids() {
+        let handle = ClientHandle::new();
+        assert!(!handle.is_duplicate("m1"));
+        assert!(handle.is_duplicate("m1"));
+        for i in 0..128 {
+            handle.is_duplicate(&format!("other {}", i));
+        }
+        assert!(!handle.is_duplicate("m1"), "old msg_ids are forgotten");
+    }
+}
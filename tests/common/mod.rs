@@ -0,0 +1,148 @@
+// A Server on free local ports with its own data directory, for tests that talk to it over
+// HTTP and WebSocket.
+
+#![allow(dead_code)]
+
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+
+use cam2webrtc::{Config, Server};
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+use tempfile::TempDir;
+use ws2infer_protocol::{SignalingClient, SignalingMessage, SignalingMessageType};
+
+pub struct TestServer {
+    pub base_url: String,
+    pub ws_url: String,
+    /// UDP address of the TURN server
+    pub turn_addr: String,
+    // Removed when the test ends
+    _data_dir: TempDir,
+}
+
+fn free_tcp_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn free_udp_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Config::default with the top-level fields of `overrides` replaced, for settings whose types
+/// the crate doesn't export (auth, room_templates, ...)
+pub fn config(overrides: Value) -> Config {
+    let mut config = serde_json::to_value(Config::default()).unwrap();
+    for (key, value) in overrides.as_object().unwrap() {
+        config[key] = value.clone();
+    }
+    serde_json::from_value(config).unwrap()
+}
+
+/// Starts a server with `config` (its listener addresses and TLS are overwritten) and waits
+/// until it accepts connections
+pub async fn start(mut config: Config) -> TestServer {
+    let port = free_tcp_port();
+    config.signaling_addr = format!("127.0.0.1:{}", port);
+    config.stun_addr = format!("127.0.0.1:{}", free_udp_port());
+    config.turn_addr = format!("127.0.0.1:{}", free_udp_port());
+    config.tls_enabled = false;
+    let turn_addr = config.turn_addr.clone();
+    let data_dir = tempfile::tempdir().unwrap();
+    let server = Server::builder().with_config(config).with_data_dir(data_dir.path()).build();
+    // run()'s future isn't Send, so the server gets a runtime of its own
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        if let Err(e) = runtime.block_on(server.run()) {
+            panic!("test server failed: {}", e);
+        }
+    });
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    TestServer {
+        base_url: format!("http://127.0.0.1:{}", port),
+        ws_url: format!("ws://127.0.0.1:{}", port),
+        turn_addr,
+        _data_dir: data_dir,
+    }
+}
+
+impl TestServer {
+    /// POST /api/rooms; returns the reply with room_id, sender_token and control_token
+    pub async fn create_room(&self, body: Value) -> Value {
+        let reply = reqwest::Client::new()
+            .post(format!("{}/api/rooms", self.base_url))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert!(reply.status().is_success(), "create room: {}", reply.status());
+        reply.json().await.unwrap()
+    }
+
+    /// `method` on `path`, with `bearer` as the Authorization token; the reply body is Null
+    /// unless it is JSON
+    pub async fn request(&self, method: Method, path: &str, bearer: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = reqwest::Client::new().request(method, format!("{}{}", self.base_url, path));
+        if let Some(token) = bearer {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let reply = request.send().await.unwrap();
+        let status = reply.status();
+        (status, reply.json().await.unwrap_or(Value::Null))
+    }
+
+    pub async fn get(&self, path: &str, bearer: Option<&str>) -> (StatusCode, Value) {
+        self.request(Method::GET, path, bearer, None).await
+    }
+
+    /// Connects as `connection_id`, joins with `data` and waits for room_info
+    pub async fn join(&self, room_id: &str, connection_id: &str, is_sender: bool, data: Value) -> SignalingClient {
+        let mut client = SignalingClient::connect_as(&self.ws_url, room_id, connection_id.to_string()).await.unwrap();
+        client.join(is_sender, Some(data)).await.unwrap();
+        let joined = next_of(&mut client, |message| matches!(message.message_type, SignalingMessageType::RoomInfo | SignalingMessageType::Error)).await;
+        assert!(matches!(joined.message_type, SignalingMessageType::RoomInfo), "join of {} failed: {:?}", connection_id, joined.data);
+        client
+    }
+}
+
+/// Next message that matches, skipping others; panics after two seconds
+pub async fn next_of(client: &mut SignalingClient, wanted: impl Fn(&SignalingMessage) -> bool) -> SignalingMessage {
+    tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let message = client.next_message().await.expect("connection closed");
+            if wanted(&message) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no matching message within two seconds")
+}
+
+/// The error text of the next Error message
+pub async fn next_error(client: &mut SignalingClient) -> String {
+    let error = next_of(client, |message| matches!(message.message_type, SignalingMessageType::Error)).await;
+    error.data.as_ref().and_then(|data| data.get("error")).and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+/// Whether a matching message arrives within half a second
+pub async fn receives(client: &mut SignalingClient, wanted: impl Fn(&SignalingMessage) -> bool) -> bool {
+    tokio::time::timeout(Duration::from_millis(500), async {
+        while let Some(message) = client.next_message().await {
+            if wanted(&message) {
+                return true;
+            }
+        }
+        false
+    })
+    .await
+    .unwrap_or(false)
+}
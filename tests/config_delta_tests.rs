@@ -0,0 +1,56 @@
+// ETags and deltas of the client config payload served to polling clients.
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use cam2webrtc::config::{config_delta, config_etag, ConfigVersions};
+
+    #[test]
+    fn test_etag_follows_content() {
+        let config = json!({"ice_servers": [], "max_message_bytes": 65536});
+        let etag = config_etag(&config);
+
+        assert_eq!(etag.len(), 16);
+        assert!(etag.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(etag, config_etag(&config.clone()));
+        assert_ne!(etag, config_etag(&json!({"ice_servers": [], "max_message_bytes": 1024})));
+    }
+
+    #[test]
+    fn test_delta_lists_changed_added_and_removed_fields() {
+        let old = json!({"a": 1, "b": {"x": true}, "c": "gone"});
+        let new = json!({"a": 1, "b": {"x": false}, "d": [1, 2]});
+
+        assert_eq!(config_delta(&old, &new), json!({
+            "changed": {"b": {"x": false}, "d": [1, 2]},
+            "removed": ["c"],
+        }));
+    }
+
+    #[test]
+    fn test_delta_of_identical_configs_is_empty() {
+        let config = json!({"a": 1, "b": [1, 2]});
+        assert_eq!(config_delta(&config, &config), json!({"changed": {}, "removed": []}));
+    }
+
+    #[test]
+    fn test_delta_from_non_object_sends_everything() {
+        let new = json!({"a": 1});
+        assert_eq!(config_delta(&json!(null), &new), json!({"changed": {"a": 1}, "removed": []}));
+    }
+
+    #[test]
+    fn test_versions_keep_recent_payloads() {
+        let mut versions = ConfigVersions::default();
+        let first = versions.record(&json!({"n": 0}));
+        assert_eq!(versions.record(&json!({"n": 0})), first);
+        assert_eq!(versions.get(&first), Some(&json!({"n": 0})));
+
+        for n in 1..=16 {
+            versions.record(&json!({"n": n}));
+        }
+        assert_eq!(versions.get(&first), None, "the oldest payload is forgotten");
+        assert_eq!(versions.get(&config_etag(&json!({"n": 16}))), Some(&json!({"n": 16})));
+    }
+}
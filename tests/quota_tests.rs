@@ -0,0 +1,98 @@
+// The daily stored-results quota of rooms and tenants.
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use cam2webrtc::quota::{Quota, QuotaExceeded, QuotaLimits, QuotaScope, Quotas};
+    use cam2webrtc::tenant::TenantConfig;
+
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    fn tenant_with_rows(id: &str, max: u64) -> TenantConfig {
+        TenantConfig {
+            id: id.to_string(),
+            api_keys: Vec::new(),
+            admin_token: None,
+            quotas: Quotas { max_inference_rows_per_day: Some(max), ..Default::default() },
+        }
+    }
+
+    fn rows_per_room(max: u64) -> Quotas {
+        Quotas { max_inference_rows_per_day: Some(max), ..Default::default() }
+    }
+
+    #[test]
+    fn test_room_rows_refused_past_limit_and_reported_once() {
+        let mut limits = QuotaLimits::default();
+        limits.set_config(rows_per_room(2), &[]);
+
+        assert!(limits.take_inference_row("lobby", noon()).is_ok());
+        assert!(limits.take_inference_row("lobby", noon()).is_ok());
+
+        let refused = limits.take_inference_row("lobby", noon()).unwrap_err();
+        assert!(refused.first);
+        assert_eq!(refused.exceeded, QuotaExceeded {
+            quota: Quota::MaxInferenceRowsPerDay,
+            scope: QuotaScope::Room,
+            id: "lobby".to_string(),
+            limit: 2,
+        });
+        assert!(!limits.take_inference_row("lobby", noon()).unwrap_err().first);
+
+        // Other rooms count separately
+        assert!(limits.take_inference_row("hall", noon()).is_ok());
+    }
+
+    #[test]
+    fn test_rows_reset_at_utc_midnight() {
+        let mut limits = QuotaLimits::default();
+        limits.set_config(rows_per_room(1), &[]);
+        let late = Utc.with_ymd_and_hms(2026, 3, 1, 23, 59, 59).unwrap();
+
+        assert!(limits.take_inference_row("lobby", late).is_ok());
+        assert!(limits.take_inference_row("lobby", late).is_err());
+
+        let next_day = late + Duration::seconds(1);
+        assert!(limits.take_inference_row("lobby", next_day).is_ok());
+        let refused = limits.take_inference_row("lobby", next_day).unwrap_err();
+        assert!(refused.first, "the first refusal of a new day is reported again");
+    }
+
+    #[test]
+    fn test_tenant_rows_shared_by_its_rooms() {
+        let mut limits = QuotaLimits::default();
+        limits.set_config(Quotas::default(), &[tenant_with_rows("acme", 2)]);
+
+        assert!(limits.take_inference_row("acme:a", noon()).is_ok());
+        assert!(limits.take_inference_row("acme:b", noon()).is_ok());
+
+        let refused = limits.take_inference_row("acme:c", noon()).unwrap_err();
+        assert!(refused.first);
+        assert_eq!(refused.exceeded.scope, QuotaScope::Tenant);
+        assert_eq!(refused.exceeded.id, "acme");
+        assert!(!limits.take_inference_row("acme:a", noon()).unwrap_err().first);
+
+        // Rooms without a tenant and rooms of other tenants are not limited
+        assert!(limits.take_inference_row("lobby", noon()).is_ok());
+        assert!(limits.take_inference_row("other:a", noon()).is_ok());
+    }
+
+    #[test]
+    fn test_room_refusal_not_counted_against_tenant() {
+        let mut limits = QuotaLimits::default();
+        limits.set_config(rows_per_room(1), &[tenant_with_rows("acme", 2)]);
+
+        assert!(limits.take_inference_row("acme:a", noon()).is_ok());
+        let refused = limits.take_inference_row("acme:a", noon()).unwrap_err();
+        assert_eq!(refused.exceeded.scope, QuotaScope::Room);
+        assert_eq!(refused.exceeded.id, "acme:a");
+
+        assert!(limits.take_inference_row("acme:b", noon()).is_ok());
+        let refused = limits.take_inference_row("acme:c", noon()).unwrap_err();
+        assert_eq!(refused.exceeded.scope, QuotaScope::Tenant);
+        assert!(refused.first);
+    }
+}
@@ -0,0 +1,99 @@
+// The REST API for rooms, checked against a running server.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use cam2webrtc::Config;
+    use reqwest::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use ws2infer_protocol::{SignalingClient, SignalingMessageType};
+
+    use super::common::{self, TestServer};
+
+    /// A viewer joined as `connection_id`, with the room_info data it got
+    async fn join_viewer(server: &TestServer, room_id: &str, connection_id: &str) -> (SignalingClient, Value) {
+        let mut viewer = SignalingClient::connect_as(&server.ws_url, room_id, connection_id.to_string()).await.unwrap();
+        viewer.join(false, Some(json!({}))).await.unwrap();
+        let room_info = common::next_of(&mut viewer, |message| matches!(message.message_type, SignalingMessageType::RoomInfo)).await;
+        (viewer, room_info.data.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_put_room_issues_tokens_only_when_it_creates_the_room() {
+        let server = common::start(Config::default()).await;
+
+        let (status, created) = server.request(Method::PUT, "/api/rooms/lobby", None, None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(created["sender_token"].is_string());
+        assert!(created["control_token"].is_string());
+
+        let (status, existing) = server.request(Method::PUT, "/api/rooms/lobby", None, None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(existing.get("sender_token").is_none());
+        assert!(existing.get("control_token").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_refuse_with_the_same_error() {
+        let server = common::start(Config { admin_token: Some("admin-secret".to_string()), ..Config::default() }).await;
+        let room_id = server.create_room(json!({})).await["room_id"].as_str().unwrap().to_string();
+
+        for (method, path) in [
+            (Method::DELETE, format!("/api/rooms/{}", room_id)),
+            (Method::GET, format!("/api/rooms/{}/stats", room_id)),
+            (Method::GET, "/api/admin/recordings".to_string()),
+            (Method::GET, "/api/admin/audit".to_string()),
+        ] {
+            let (status, body) = server.request(method.clone(), &path, Some("wrong"), None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, path);
+            assert_eq!(body, json!({"error": "Admin token required"}), "{} {}", method, path);
+        }
+
+        let (status, _) = server.request(Method::DELETE, &format!("/api/rooms/{}", room_id), Some("admin-secret"), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_room_results_and_settings_need_the_admin_token() {
+        let server = common::start(Config { admin_token: Some("admin-secret".to_string()), ..Config::default() }).await;
+        let room_id = server.create_room(json!({})).await["room_id"].as_str().unwrap().to_string();
+
+        for route in ["inference/latest", "inference/stats", "analytics", "zones", "lines"] {
+            let path = format!("/api/rooms/{}/{}", room_id, route);
+            let (status, body) = server.get(&path, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", path);
+            assert_eq!(body, json!({"error": "Admin token or API key required"}), "{}", path);
+            assert_eq!(server.get(&path, Some("admin-secret")).await.0, StatusCode::OK, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_templates_set_room_defaults_that_the_request_overrides() {
+        let server = common::start(common::config(json!({
+            "room_templates": {
+                "classroom": {"max_viewers": 1, "model": "coco-ssd", "video_constraints": {"width": {"ideal": 640}}}
+            }
+        })))
+        .await;
+
+        let room_id = server.create_room(json!({"template": "classroom"})).await["room_id"].as_str().unwrap().to_string();
+        let (_viewer, room_info) = join_viewer(&server, &room_id, "viewer-1").await;
+        assert_eq!(room_info["template"], "classroom");
+        assert_eq!(room_info["model"], "coco-ssd");
+        assert_eq!(room_info["video_constraints"], json!({"width": {"ideal": 640}}));
+        let mut second = SignalingClient::connect_as(&server.ws_url, &room_id, "viewer-2".to_string()).await.unwrap();
+        second.join(false, Some(json!({}))).await.unwrap();
+        assert_eq!(common::next_error(&mut second).await, "Room is full");
+
+        let room_id = server.create_room(json!({"template": "classroom", "max_viewers": 2, "model": "yolo"})).await["room_id"].as_str().unwrap().to_string();
+        assert_eq!(server.get(&format!("/api/rooms/{}/capabilities", room_id), None).await.1["max_viewers"], 2);
+        let (_, room_info) = join_viewer(&server, &room_id, "viewer-3").await;
+        assert_eq!(room_info["model"], "yolo");
+        assert_eq!(room_info["video_constraints"], json!({"width": {"ideal": 640}}));
+
+        let (status, body) = server.request(Method::POST, "/api/rooms", None, Some(json!({"template": "lecture"}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({"error": "Unknown template"}));
+    }
+}
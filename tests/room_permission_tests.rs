@@ -0,0 +1,142 @@
+// Roles and permissions of room members, checked over real WebSocket connections.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{self, receives};
+    use cam2webrtc::{Config, SignalingMessage, SignalingMessageType};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_viewer_cannot_relay_control_as_a_controller() {
+        let server = common::start(Config::default()).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let _controller = server.join(room_id, "controller", false, json!({"role": "controller", "control_token": room["control_token"]})).await;
+        let mut viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let mut other = server.join(room_id, "other", false, json!({})).await;
+
+        // sender_id names the controller, but the session is bound to the viewer
+        let spoofed = SignalingMessage::new_relay("other".to_string(), "controller".to_string(), json!({"kind": "control", "command": "ptz"}));
+        viewer.send(&spoofed).unwrap();
+
+        assert_eq!(common::next_error(&mut viewer).await, "Control messages need the controller role");
+        assert!(!receives(&mut other, |message| matches!(message.message_type, SignalingMessageType::Relay)).await);
+    }
+
+    #[tokio::test]
+    async fn test_viewer_cannot_set_zones_as_a_controller() {
+        let server = common::start(Config::default()).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let controller = server.join(room_id, "controller", false, json!({"role": "controller", "control_token": room["control_token"]})).await;
+        let mut viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let zones = json!([{"id": "door", "polygon": [[0.0, 0.0], [50.0, 0.0], [50.0, 50.0]]}]);
+
+        viewer.send(&SignalingMessage::new_set_zones("controller".to_string(), zones.clone())).unwrap();
+        assert_eq!(common::next_error(&mut viewer).await, "Only admins and controllers may set zones");
+        assert_eq!(server.get(&format!("/api/rooms/{}/zones", room_id), None).await.1, json!([]));
+
+        controller.set_zones(zones).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(server.get(&format!("/api/rooms/{}/zones", room_id), None).await.1.as_array().map(Vec::len), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_standby_cannot_offer_as_the_sender() {
+        let server = common::start(Config::default()).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let token = &room["sender_token"];
+        let sender = server.join(room_id, "sender", true, json!({"sender_token": token})).await;
+        let mut standby = server.join(room_id, "standby", true, json!({"sender_token": token, "standby": true})).await;
+        let mut viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let sdp = json!({"type": "offer", "sdp": "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\n"});
+        let is_offer = |message: &SignalingMessage| matches!(message.message_type, SignalingMessageType::Offer);
+
+        standby.send(&SignalingMessage::new_offer("viewer".to_string(), "sender".to_string(), sdp.clone())).unwrap();
+        assert_eq!(common::next_error(&mut standby).await, "A standby sender can't offer until it is promoted");
+        assert!(!receives(&mut viewer, is_offer).await);
+
+        sender.offer("viewer", sdp).await.unwrap();
+        assert!(receives(&mut viewer, is_offer).await);
+    }
+
+    #[tokio::test]
+    async fn test_key_exchange_names_the_connection_that_sent_it() {
+        let server = common::start(Config::default()).await;
+        let room = server.create_room(json!({"e2ee": true})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let _sender = server.join(room_id, "sender", true, json!({"sender_token": room["sender_token"]})).await;
+        let viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let mut other = server.join(room_id, "other", false, json!({})).await;
+
+        viewer.send(&SignalingMessage::new_key_exchange("other".to_string(), "sender".to_string(), json!({"key": "AAAA"}))).unwrap();
+
+        let key = common::next_of(&mut other, |message| matches!(message.message_type, SignalingMessageType::KeyExchange)).await;
+        assert_eq!(key.sender_id.as_deref(), Some("viewer"));
+    }
+
+    #[tokio::test]
+    async fn test_layer_subscription_is_kept_for_the_connection_that_sent_it() {
+        let server = common::start(Config { admin_token: Some("admin-secret".to_string()), ..Config::default() }).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let mut sender = server.join(room_id, "sender", true, json!({"sender_token": room["sender_token"]})).await;
+        let viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let _other = server.join(room_id, "other", false, json!({})).await;
+
+        viewer.send(&SignalingMessage::new_subscribe_layer("sender".to_string(), "other".to_string(), "h")).unwrap();
+
+        let subscribe = common::next_of(&mut sender, |message| matches!(message.message_type, SignalingMessageType::SubscribeLayer)).await;
+        assert_eq!(subscribe.sender_id.as_deref(), Some("viewer"));
+        let (_, status) = server.get(&format!("/api/rooms/{}", room_id), Some("admin-secret")).await;
+        assert_eq!(status["layer_subscriptions"], json!([{"viewer": "viewer", "sender": "sender", "rid": "h"}]));
+    }
+
+    #[tokio::test]
+    async fn test_relay_rate_limit_applies_to_the_connection_that_sent_it() {
+        let mut config = Config::default();
+        config.relay.burst = 2;
+        config.relay.messages_per_sec = 0.1;
+        let server = common::start(config).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let mut viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let other = server.join(room_id, "other", false, json!({})).await;
+        let mut third = server.join(room_id, "third", false, json!({})).await;
+
+        // Claiming other members' IDs doesn't spread the messages over their buckets
+        for claimed in ["other", "third", "other"] {
+            viewer.send(&SignalingMessage::new_relay("third".to_string(), claimed.to_string(), json!({"text": "hi"}))).unwrap();
+        }
+        assert_eq!(common::next_error(&mut viewer).await, "Relay rate limit of 0.1 messages/s exceeded");
+
+        other.relay("third", json!({"text": "hi"})).await.unwrap();
+        let relayed = common::next_of(&mut third, |message| matches!(message.message_type, SignalingMessageType::Relay) && message.sender_id.as_deref() == Some("other")).await;
+        assert_eq!(relayed.data, Some(json!({"text": "hi"})));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_rate_limit_applies_to_the_connection_that_sent_it() {
+        let mut config = Config::default();
+        config.relay.burst = 2;
+        config.relay.messages_per_sec = 0.1;
+        let server = common::start(config).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let mut viewer = server.join(room_id, "viewer", false, json!({})).await;
+        let other = server.join(room_id, "other", false, json!({})).await;
+        let mut third = server.join(room_id, "third", false, json!({})).await;
+
+        for claimed in ["other", "third", "other"] {
+            viewer.send(&SignalingMessage::new_broadcast(claimed.to_string(), json!({"text": "hi"}))).unwrap();
+        }
+        assert_eq!(common::next_error(&mut viewer).await, "Broadcast rate limit of 0.1 messages/s exceeded");
+
+        other.broadcast(json!({"text": "hi"})).await.unwrap();
+        let broadcast = common::next_of(&mut third, |message| matches!(message.message_type, SignalingMessageType::Broadcast) && message.sender_id.as_deref() == Some("other")).await;
+        assert_eq!(broadcast.data, Some(json!({"text": "hi"})));
+    }
+}
@@ -0,0 +1,165 @@
+// The server is a binary crate, so the STUN codec is compiled into this test directly; it has no
+// dependencies on the rest of the server.
+#[path = "../src/stun_proto.rs"]
+#[allow(dead_code)]
+mod stun_proto;
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+    use proptest::prelude::*;
+
+    use super::stun_proto::{self, Attribute, DecodeError, Header, Message, MessageType};
+
+    fn socket_addr() -> impl Strategy<Value = SocketAddr> {
+        prop_oneof![
+            any::<[u8; 4]>().prop_map(IpAddr::from),
+            any::<[u8; 16]>().prop_map(IpAddr::from),
+        ]
+        .prop_flat_map(|ip| any::<u16>().prop_map(move |port| SocketAddr::new(ip, port)))
+    }
+
+    fn attribute() -> impl Strategy<Value = Attribute> {
+        prop_oneof![
+            socket_addr().prop_map(Attribute::MappedAddress),
+            socket_addr().prop_map(Attribute::XorMappedAddress),
+            socket_addr().prop_map(Attribute::XorRelayedAddress),
+            socket_addr().prop_map(Attribute::XorPeerAddress),
+            ".{0,64}".prop_map(Attribute::Username),
+            ".{0,64}".prop_map(Attribute::Realm),
+            ".{0,64}".prop_map(Attribute::Nonce),
+            any::<[u8; 20]>().prop_map(Attribute::MessageIntegrity),
+            (300u16..700, ".{0,32}").prop_map(|(code, reason)| Attribute::ErrorCode { code, reason }),
+            any::<u32>().prop_map(Attribute::Lifetime),
+            proptest::collection::vec(any::<u8>(), 0..256).prop_map(Attribute::Data),
+            // Types this module doesn't interpret
+            (0x8000u16..=0xffff, proptest::collection::vec(any::<u8>(), 0..64))
+                .prop_map(|(attr_type, value)| Attribute::Unknown { attr_type, value }),
+        ]
+    }
+
+    fn message() -> impl Strategy<Value = Message> {
+        let message_type = (0u16..0x4000).prop_map(MessageType::from_u16);
+        (message_type, any::<[u8; 16]>(), proptest::collection::vec(attribute(), 0..8))
+            .prop_map(|(message_type, transaction, attributes)| Message {
+                header: Header { message_type, transaction },
+                attributes,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn message_type_round_trips(value in 0u16..0x4000) {
+            prop_assert_eq!(MessageType::from_u16(value).to_u16(), value);
+        }
+
+        #[test]
+        fn message_round_trips(message in message()) {
+            let encoded = message.encode();
+            prop_assert_eq!(encoded.len() % 4, 0);
+            prop_assert_eq!(stun_proto::decode(&encoded), Ok(message));
+        }
+
+        #[test]
+        fn attribute_round_trips(attribute in attribute(), transaction in any::<[u8; 16]>()) {
+            let (attr_type, value) = attribute.encode(&transaction);
+            prop_assert_eq!(Attribute::decode(attr_type, &value, &transaction), Ok(attribute));
+        }
+
+        #[test]
+        fn decode_never_panics(packet in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = stun_proto::decode(&packet);
+        }
+
+        #[test]
+        fn truncated_messages_are_rejected(message in message(), cut in 1usize..64) {
+            let encoded = message.encode();
+            let cut = cut.min(encoded.len());
+            prop_assert!(stun_proto::decode(&encoded[..encoded.len() - cut]).is_err());
+        }
+
+        #[test]
+        fn appended_attribute_updates_length(message in message(), extra in attribute()) {
+            let mut encoded = message.encode();
+            stun_proto::append_attribute(&mut encoded, &extra);
+            let decoded = stun_proto::decode(&encoded).unwrap();
+            prop_assert_eq!(decoded.attributes.last(), Some(&extra));
+        }
+    }
+
+    #[test]
+    fn binding_request_from_rfc5769_decodes() {
+        // RFC 5769 §2.1 header with the SOFTWARE attribute only
+        let mut packet = vec![0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42];
+        packet.extend_from_slice(&[0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae]);
+        stun_proto::append_attribute(&mut packet, &Attribute::Unknown {
+            attr_type: 0x8022,
+            value: b"STUN test client".to_vec(),
+        });
+
+        let message = stun_proto::decode(&packet).unwrap();
+        assert_eq!(message.header.message_type, MessageType::BindingRequest);
+        assert_eq!(&message.header.transaction[..4], &stun_proto::MAGIC_COOKIE);
+        assert_eq!(message.attributes.len(), 1);
+    }
+
+    // RFC 5769 §2.1's transaction ID, used by its sample responses
+    const RFC5769_TRANSACTION: [u8; 16] = [
+        0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    #[test]
+    fn xor_mapped_address_matches_rfc5769_ipv4() {
+        // §2.2: 192.0.2.1 port 32853
+        let value = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let addr: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        assert_eq!(Attribute::decode(0x0020, &value, &RFC5769_TRANSACTION), Ok(Attribute::XorMappedAddress(addr)));
+        assert_eq!(Attribute::XorMappedAddress(addr).encode(&RFC5769_TRANSACTION), (0x0020, value.to_vec()));
+    }
+
+    #[test]
+    fn xor_mapped_address_matches_rfc5769_ipv6() {
+        // §2.3: 2001:db8:1234:5678:11:2233:4455:6677 port 32853, masked with the transaction ID too
+        let value = [
+            0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79,
+            0xbc, 0x25, 0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+        ];
+        let addr: SocketAddr = "[2001:db8:1234:5678:11:2233:4455:6677]:32853".parse().unwrap();
+        assert_eq!(Attribute::decode(0x0020, &value, &RFC5769_TRANSACTION), Ok(Attribute::XorMappedAddress(addr)));
+        assert_eq!(Attribute::XorMappedAddress(addr).encode(&RFC5769_TRANSACTION), (0x0020, value.to_vec()));
+    }
+
+    #[test]
+    fn xor_addresses_use_the_cookie_without_one_in_the_header() {
+        // RFC 3489 clients send a random 128-bit transaction ID; IPv4 is still masked with the cookie
+        let transaction = [0xff; 16];
+        let addr: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let (_, value) = Attribute::XorRelayedAddress(addr).encode(&transaction);
+        assert_eq!(value, vec![0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43]);
+    }
+
+    #[test]
+    fn header_checks() {
+        assert_eq!(stun_proto::decode(&[0; 19]), Err(DecodeError::TooShort));
+
+        let mut packet = Message {
+            header: Header { message_type: MessageType::BindingRequest, transaction: [0; 16] },
+            attributes: vec![Attribute::Lifetime(600)],
+        }
+        .encode();
+        packet.push(0);
+        assert_eq!(stun_proto::decode(&packet), Err(DecodeError::LengthMismatch));
+
+        packet.pop();
+        packet[0] |= 0x40; // ChannelData, not STUN
+        assert_eq!(stun_proto::decode(&packet), Err(DecodeError::NotStun));
+    }
+
+    #[test]
+    fn oversized_attribute_length_is_truncated() {
+        let mut packet = vec![0x00, 0x01, 0x00, 0x04];
+        packet.extend_from_slice(&[0; 16]);
+        packet.extend_from_slice(&[0x00, 0x13, 0xff, 0xff]); // DATA claiming 65535 bytes
+        assert_eq!(stun_proto::decode(&packet), Err(DecodeError::Truncated));
+    }
+}
@@ -0,0 +1,69 @@
+// Tenants through the REST API: which rooms an API key creates and which rooms a tenant's keys
+// and admin token reach, against a running server.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::common::{self, TestServer};
+
+    async fn start() -> TestServer {
+        common::start(common::config(json!({
+            "admin_token": "admin-secret",
+            "tenants": [
+                {"id": "acme", "api_keys": ["acme-key"], "admin_token": "acme-admin"},
+                {"id": "globex", "api_keys": ["globex-key"]}
+            ]
+        })))
+        .await
+    }
+
+    async fn create_room(server: &TestServer, bearer: Option<&str>, body: Value) -> (StatusCode, Value) {
+        server.request(Method::POST, "/api/rooms", bearer, Some(body)).await
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_create_rooms_in_their_tenant() {
+        let server = start().await;
+
+        let (status, room) = create_room(&server, Some("acme-key"), json!({"room_id": "lobby"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&room["room_id"], &room["created"]), (&json!("acme:lobby"), &json!(true)));
+
+        let (status, room) = create_room(&server, None, json!({"room_id": "lobby"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&room["room_id"], &room["created"]), (&json!("lobby"), &json!(true)));
+
+        let (status, body) = create_room(&server, Some("acme-key"), json!({"room_id": "globex:lobby"})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "room_id belongs to another tenant"}));
+
+        // A mistyped key must not fall back to a room outside every tenant
+        let (status, body) = create_room(&server, Some("acme-kye"), json!({"room_id": "lobby"})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, json!({"error": "Unknown API key"}));
+    }
+
+    #[tokio::test]
+    async fn test_tenant_credentials_reach_only_the_tenant_rooms() {
+        let server = start().await;
+        create_room(&server, Some("acme-key"), json!({"room_id": "lobby"})).await;
+        create_room(&server, Some("globex-key"), json!({"room_id": "yard"})).await;
+
+        let zones = "/api/rooms/acme:lobby/zones";
+        for bearer in ["acme-key", "acme-admin", "admin-secret"] {
+            assert_eq!(server.get(zones, Some(bearer)).await.0, StatusCode::OK, "{}", bearer);
+        }
+        assert_eq!(server.get(zones, Some("globex-key")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(server.get(zones, None).await.0, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = server.request(Method::DELETE, "/api/rooms/globex:yard", Some("acme-admin"), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"error": "Admin token required"}));
+        assert_eq!(server.get("/api/rooms/globex:yard/zones", Some("acme-admin")).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(server.request(Method::DELETE, "/api/rooms/acme:lobby", Some("acme-admin"), None).await.0, StatusCode::OK);
+    }
+}
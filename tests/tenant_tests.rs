@@ -0,0 +1,27 @@
+// Room keys across tenants.
+
+#[cfg(test)]
+mod tests {
+    use cam2webrtc::tenant;
+
+    #[test]
+    fn test_resolve_keeps_callers_in_their_tenant() {
+        assert_eq!(tenant::resolve(None, "lobby").as_deref(), Some("lobby"));
+        assert_eq!(tenant::resolve(Some("acme"), "lobby").as_deref(), Some("acme:lobby"));
+        assert_eq!(tenant::resolve(Some("acme"), "acme:lobby").as_deref(), Some("acme:lobby"));
+        assert_eq!(tenant::resolve(Some("acme"), "other:lobby"), None);
+        assert_eq!(tenant::resolve(None, "acme:lobby"), None);
+    }
+
+    #[test]
+    fn test_is_valid_room_key() {
+        assert!(tenant::is_valid_room_key("lobby"));
+        assert!(tenant::is_valid_room_key("acme:lobby-1_a"));
+        assert!(!tenant::is_valid_room_key(""));
+        assert!(!tenant::is_valid_room_key("acme:"));
+        assert!(!tenant::is_valid_room_key(":lobby"));
+        assert!(!tenant::is_valid_room_key("acme:other:lobby"));
+        assert!(!tenant::is_valid_room_key("acme:lob by"));
+        assert!(!tenant::is_valid_room_key(&"a".repeat(65)));
+    }
+}
@@ -0,0 +1,149 @@
+// TURN REST credentials and the TURN server's long-term credential check, against a running
+// server.
+
+mod common;
+
+// The STUN codec builds and reads the TURN messages, as in stun_proto_tests.rs
+#[path = "../src/stun_proto.rs"]
+#[allow(dead_code)]
+mod stun_proto;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use cam2webrtc::Config;
+    use hmac::{Hmac, Mac};
+    use md5::{Digest, Md5};
+    use reqwest::StatusCode;
+    use serde_json::{json, Value};
+    use sha1::Sha1;
+    use tokio::net::UdpSocket;
+
+    use super::common::{self, TestServer};
+    use super::stun_proto::{self, Attribute, Message, MessageType};
+
+    const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+
+    fn turn_config() -> Config {
+        Config {
+            turn_secret: Some("turn-secret".to_string()),
+            admin_token: Some("admin-secret".to_string()),
+            ..Config::default()
+        }
+    }
+
+    fn allocate_request(attributes: Vec<Attribute>) -> Message {
+        let mut transaction = [0u8; 16];
+        transaction[..4].copy_from_slice(&MAGIC_COOKIE);
+        transaction[4..].copy_from_slice(&uuid::Uuid::new_v4().as_bytes()[..12]);
+        Message { attributes, ..Message::new(MessageType::AllocateRequest, transaction) }
+    }
+
+    /// An Allocate request signed with `username` and `password` (RFC 5389 §15.4)
+    fn signed_allocate(username: &str, password: &str, realm: &str, nonce: &str) -> Vec<u8> {
+        let mut packet = allocate_request(vec![
+            Attribute::Username(username.to_string()),
+            Attribute::Realm(realm.to_string()),
+            Attribute::Nonce(nonce.to_string()),
+        ])
+        .encode();
+        let key = Md5::digest(format!("{}:{}:{}", username, realm, password).as_bytes());
+        // The length covers MESSAGE-INTEGRITY, which is 24 bytes with its header
+        let signed_len = (packet.len() - 20 + 24) as u16;
+        packet[2..4].copy_from_slice(&signed_len.to_be_bytes());
+        let mut mac = Hmac::<Sha1>::new_from_slice(&key).unwrap();
+        mac.update(&packet);
+        let mut integrity = [0u8; 20];
+        integrity.copy_from_slice(&mac.finalize().into_bytes());
+        stun_proto::append_attribute(&mut packet, &Attribute::MessageIntegrity(integrity));
+        packet
+    }
+
+    /// Sends `packet` to the TURN server until it answers; the server may still be binding
+    async fn exchange(server: &TestServer, packet: &[u8]) -> Message {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut buf = [0u8; 1500];
+        for _ in 0..20 {
+            socket.send_to(packet, &server.turn_addr).await.unwrap();
+            if let Ok(Ok(len)) = tokio::time::timeout(Duration::from_millis(250), socket.recv(&mut buf)).await {
+                return stun_proto::decode(&buf[..len]).unwrap();
+            }
+        }
+        panic!("no answer from the TURN server");
+    }
+
+    fn error_code(message: &Message) -> Option<u16> {
+        message.attributes.iter().find_map(|attribute| match attribute {
+            Attribute::ErrorCode { code, .. } => Some(*code),
+            _ => None,
+        })
+    }
+
+    /// The REALM and NONCE of a 401 challenge to an unsigned Allocate
+    async fn challenge(server: &TestServer) -> (String, String) {
+        let reply = exchange(server, &allocate_request(Vec::new()).encode()).await;
+        assert_eq!(reply.header.message_type, MessageType::AllocateErrorResponse);
+        assert_eq!(error_code(&reply), Some(401));
+        let realm = reply.attributes.iter().find_map(|attribute| match attribute {
+            Attribute::Realm(realm) => Some(realm.clone()),
+            _ => None,
+        });
+        let nonce = reply.attributes.iter().find_map(|attribute| match attribute {
+            Attribute::Nonce(nonce) => Some(nonce.clone()),
+            _ => None,
+        });
+        (realm.unwrap(), nonce.unwrap())
+    }
+
+    async fn credentials(server: &TestServer, room_id: &str, token: &str) -> Value {
+        let (status, credentials) = server.get(&format!("/api/turn-credentials?room_id={}", room_id), Some(token)).await;
+        assert_eq!(status, StatusCode::OK);
+        credentials
+    }
+
+    #[tokio::test]
+    async fn test_turn_credentials_need_a_token_the_server_issued() {
+        let server = common::start(turn_config()).await;
+        let room = server.create_room(json!({})).await;
+        let room_id = room["room_id"].as_str().unwrap();
+        let denied = json!({"error": "Room token, admin token or ID token required"});
+
+        for (path, bearer) in [
+            ("/api/turn-credentials".to_string(), None),
+            (format!("/api/turn-credentials?room_id={}", room_id), Some("wrong")),
+            // A room's token is only good for that room
+            ("/api/turn-credentials".to_string(), room["sender_token"].as_str()),
+        ] {
+            assert_eq!(server.get(&path, bearer).await, (StatusCode::UNAUTHORIZED, denied.clone()), "{} {:?}", path, bearer);
+        }
+
+        for token in [room["sender_token"].as_str().unwrap(), room["control_token"].as_str().unwrap(), "admin-secret"] {
+            let credentials = credentials(&server, room_id, token).await;
+            assert!(credentials["username"].is_string() && credentials["password"].is_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_turn_allocate_accepts_only_signed_credentials_and_issued_nonces() {
+        let server = common::start(turn_config()).await;
+        let room = server.create_room(json!({})).await;
+        let credentials = credentials(&server, room["room_id"].as_str().unwrap(), room["sender_token"].as_str().unwrap()).await;
+        let username = credentials["username"].as_str().unwrap();
+        let password = credentials["password"].as_str().unwrap();
+        let (realm, nonce) = challenge(&server).await;
+
+        let reply = exchange(&server, &signed_allocate(username, password, &realm, &nonce)).await;
+        assert_eq!(reply.header.message_type, MessageType::AllocateResponse);
+        assert!(reply.attributes.iter().any(|attribute| matches!(attribute, Attribute::XorRelayedAddress(_))));
+
+        let forged_nonce = format!("{:016x}{}", chrono::Utc::now().timestamp(), "0".repeat(16));
+        for packet in [
+            signed_allocate(username, "wrong-password", &realm, &nonce),
+            signed_allocate(username, password, &realm, &forged_nonce),
+        ] {
+            let reply = exchange(&server, &packet).await;
+            assert_eq!(reply.header.message_type, MessageType::AllocateErrorResponse);
+            assert_eq!(error_code(&reply), Some(401));
+        }
+    }
+}